@@ -0,0 +1,52 @@
+/*
+ * The C ABI of `nix-installer`, built with the `ffi` feature:
+ *
+ *     cargo rustc --lib --release --no-default-features --features ffi,embedded-nix --crate-type cdylib
+ *
+ * Everything crosses as NUL terminated UTF-8 JSON. Each function returns `0` on success, or the
+ * exit code of the failure's class (as `nix-installer` would exit with) with `*out` set to the
+ * error, like:
+ *
+ *     {"class":"network","exit_code":11,"message":"...","causes":["..."],"plan":{...}}
+ *
+ * When an install (or uninstall) fails, `plan` has the state of each action, pass it to
+ * `nix_installer_uninstall` to revert what was done. Every string given out as `*out` is freed
+ * with `nix_installer_string_free`. See the `ffi` module of the crate's documentation.
+ */
+
+#ifndef NIX_INSTALLER_H
+#define NIX_INSTALLER_H
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* Called with each progress event (as JSON, only valid during the call) and the `user_data` given */
+typedef void (*nix_installer_progress_callback)(const char *event, void *user_data);
+
+/* The planner `nix-installer` would pick for this system, with its default settings */
+int nix_installer_default_planner(char **out);
+
+/* Plan an install with `planner` (or the default planner, if it is `NULL`), giving the plan */
+int nix_installer_plan(const char *planner, char **out);
+
+/* Install `plan`, giving the receipt, `progress` may be `NULL` */
+int nix_installer_install(const char *plan,
+                          nix_installer_progress_callback progress,
+                          void *user_data,
+                          char **out);
+
+/* Uninstall what `receipt` (or a failed install's plan) records as done, `progress` may be `NULL` */
+int nix_installer_uninstall(const char *receipt,
+                            nix_installer_progress_callback progress,
+                            void *user_data,
+                            char **out);
+
+/* Free a string given out by one of the other functions, `NULL` is ignored */
+void nix_installer_string_free(char *string);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* NIX_INSTALLER_H */
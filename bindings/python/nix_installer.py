@@ -0,0 +1,88 @@
+"""Python bindings to the C ABI of `nix-installer` (see `bindings/nix_installer.h`)
+
+    import nix_installer
+
+    installer = nix_installer.Installer("./libnix_installer.so")
+    planner = installer.default_planner()
+    planner["Linux"]["settings"]["nix_build_user_count"] = 8
+    plan = installer.plan(planner)
+    try:
+        receipt = installer.install(plan, progress=print)
+    except nix_installer.InstallerError as err:
+        if err.plan is not None:
+            installer.uninstall(err.plan)
+        raise
+"""
+
+import ctypes
+import json
+
+PROGRESS_CALLBACK = ctypes.CFUNCTYPE(None, ctypes.c_char_p, ctypes.c_void_p)
+
+
+class InstallerError(Exception):
+    """A failure, with the class and exit code `nix-installer` would exit with"""
+
+    def __init__(self, failure):
+        super().__init__(failure.get("message", "nix-installer failed"))
+        self.error_class = failure.get("class")
+        self.exit_code = failure.get("exit_code")
+        self.causes = failure.get("causes", [])
+        # The state of each action of a failed install (or uninstall), to uninstall it
+        self.plan = failure.get("plan")
+
+
+class Installer:
+    def __init__(self, library_path):
+        self._lib = ctypes.CDLL(library_path)
+        out = ctypes.POINTER(ctypes.c_char_p)
+        self._lib.nix_installer_default_planner.argtypes = [out]
+        self._lib.nix_installer_plan.argtypes = [ctypes.c_char_p, out]
+        for function in (self._lib.nix_installer_install, self._lib.nix_installer_uninstall):
+            function.argtypes = [ctypes.c_char_p, PROGRESS_CALLBACK, ctypes.c_void_p, out]
+        # Not `c_char_p`, ctypes would copy it into a `bytes` and lose the pointer to free
+        self._lib.nix_installer_string_free.argtypes = [ctypes.c_void_p]
+
+    def default_planner(self):
+        """The planner `nix-installer` would pick for this system, with its default settings"""
+        return self._call(self._lib.nix_installer_default_planner)
+
+    def plan(self, planner=None):
+        """Plan an install with `planner` (or the default planner)"""
+        planner = None if planner is None else json.dumps(planner).encode()
+        return self._call(self._lib.nix_installer_plan, planner)
+
+    def install(self, plan, progress=None):
+        """Install `plan`, calling `progress` with each progress event, returning the receipt"""
+        return self._call(
+            self._lib.nix_installer_install,
+            json.dumps(plan).encode(),
+            self._progress(progress),
+            None,
+        )
+
+    def uninstall(self, receipt, progress=None):
+        """Uninstall what `receipt` (or a failed install's plan) records as done"""
+        self._call(
+            self._lib.nix_installer_uninstall,
+            json.dumps(receipt).encode(),
+            self._progress(progress),
+            None,
+        )
+
+    @staticmethod
+    def _progress(progress):
+        if progress is None:
+            return PROGRESS_CALLBACK()
+        return PROGRESS_CALLBACK(lambda event, _user_data: progress(json.loads(event)))
+
+    def _call(self, function, *args):
+        out = ctypes.c_void_p()
+        code = function(*args, ctypes.cast(ctypes.byref(out), ctypes.POINTER(ctypes.c_char_p)))
+        try:
+            value = json.loads(ctypes.string_at(out.value)) if out.value else None
+        finally:
+            self._lib.nix_installer_string_free(out)
+        if code != 0:
+            raise InstallerError(value or {})
+        return value
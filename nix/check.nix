@@ -56,6 +56,7 @@ in
     runtimeInputs = with pkgs; [ cargo clippy rustc ];
     text = ''
       cargo clippy
+      cargo clippy --all-features -- -D warnings
     '';
   });
 
@@ -6,9 +6,10 @@ use tokio::process::Command;
 use tracing::{span, Span};
 
 use crate::action::{ActionError, ActionErrorKind};
+use crate::audit::{self, AuditEvent};
 use crate::execute_command;
 
-use crate::action::{Action, ActionDescription, StatefulAction};
+use crate::action::{Action, ActionDescription, DryRunStep, StatefulAction};
 
 /**
 Create an operating system level user in the given group
@@ -147,6 +148,53 @@ impl AddUserToGroup {
 
         Ok(StatefulAction::uncompleted(this))
     }
+
+    /// The commands which add the user to the group on this host
+    fn commands(&self) -> Result<Vec<Command>, ActionErrorKind> {
+        let mut commands = match OperatingSystem::host() {
+            OperatingSystem::MacOSX {
+                major: _,
+                minor: _,
+                patch: _,
+            }
+            | OperatingSystem::Darwin => {
+                let mut dscl = Command::new("/usr/bin/dscl");
+                dscl.args([
+                    ".",
+                    "-append",
+                    &format!("/Groups/{}", self.groupname),
+                    "GroupMembership",
+                ])
+                .arg(&self.name);
+                let mut dseditgroup = Command::new("/usr/sbin/dseditgroup");
+                dseditgroup
+                    .args(["-o", "edit"])
+                    .arg("-a")
+                    .arg(&self.name)
+                    .arg("-t")
+                    .arg(&self.name)
+                    .arg(&self.groupname);
+                vec![dscl, dseditgroup]
+            },
+            _ => {
+                if which::which("gpasswd").is_ok() {
+                    let mut command = Command::new("gpasswd");
+                    command.args(["-a"]).args([&self.name, &self.groupname]);
+                    vec![command]
+                } else if which::which("addgroup").is_ok() {
+                    let mut command = Command::new("addgroup");
+                    command.args([&self.name, &self.groupname]);
+                    vec![command]
+                } else {
+                    return Err(ActionErrorKind::MissingAddUserToGroupCommand);
+                }
+            },
+        };
+        for command in commands.iter_mut() {
+            command.process_group(0).stdin(std::process::Stdio::null());
+        }
+        Ok(commands)
+    }
 }
 
 #[async_trait::async_trait]
@@ -184,73 +232,27 @@ impl Action for AddUserToGroup {
 
     #[tracing::instrument(level = "debug", skip_all)]
     async fn execute(&mut self) -> Result<(), ActionError> {
-        use target_lexicon::OperatingSystem;
-        match OperatingSystem::host() {
-            OperatingSystem::MacOSX {
-                major: _,
-                minor: _,
-                patch: _,
-            }
-            | OperatingSystem::Darwin => {
-                execute_command(
-                    Command::new("/usr/bin/dscl")
-                        .process_group(0)
-                        .args([
-                            ".",
-                            "-append",
-                            &format!("/Groups/{}", self.groupname),
-                            "GroupMembership",
-                        ])
-                        .arg(&self.name)
-                        .stdin(std::process::Stdio::null()),
-                )
-                .await
-                .map_err(Self::error)?;
-                execute_command(
-                    Command::new("/usr/sbin/dseditgroup")
-                        .process_group(0)
-                        .args(["-o", "edit"])
-                        .arg("-a")
-                        .arg(&self.name)
-                        .arg("-t")
-                        .arg(&self.name)
-                        .arg(&self.groupname)
-                        .stdin(std::process::Stdio::null()),
-                )
-                .await
-                .map_err(Self::error)?;
-            },
-            _ => {
-                if which::which("gpasswd").is_ok() {
-                    execute_command(
-                        Command::new("gpasswd")
-                            .process_group(0)
-                            .args(["-a"])
-                            .args([&self.name, &self.groupname])
-                            .stdin(std::process::Stdio::null()),
-                    )
-                    .await
-                    .map_err(Self::error)?;
-                } else if which::which("addgroup").is_ok() {
-                    execute_command(
-                        Command::new("addgroup")
-                            .process_group(0)
-                            .args([&self.name, &self.groupname])
-                            .stdin(std::process::Stdio::null()),
-                    )
-                    .await
-                    .map_err(Self::error)?;
-                } else {
-                    return Err(Self::error(Self::error(
-                        ActionErrorKind::MissingAddUserToGroupCommand,
-                    )));
-                }
-            },
+        for mut command in self.commands().map_err(Self::error)? {
+            execute_command(&mut command).await.map_err(Self::error)?;
         }
 
+        audit::record(AuditEvent::UserAddedToGroup {
+            user: self.name.clone(),
+            group: self.groupname.clone(),
+        });
         Ok(())
     }
 
+    fn dry_run(&self) -> Vec<DryRunStep> {
+        match self.commands() {
+            Ok(commands) => commands.iter().map(DryRunStep::command).collect(),
+            Err(err) => vec![DryRunStep::Other(format!(
+                "{} (could not be rendered: {err})",
+                self.tracing_synopsis()
+            ))],
+        }
+    }
+
     fn revert_description(&self) -> Vec<ActionDescription> {
         vec![ActionDescription::new(
             format!(
@@ -284,7 +286,7 @@ impl Action for AddUserToGroup {
                     Command::new("/usr/bin/dscl")
                         .process_group(0)
                         .args([".", "-delete", &format!("/Groups/{groupname}"), "users"])
-                        .arg(name)
+                        .arg(&*name)
                         .stdin(std::process::Stdio::null()),
                 )
                 .await
@@ -305,7 +307,7 @@ impl Action for AddUserToGroup {
                     execute_command(
                         Command::new("delgroup")
                             .process_group(0)
-                            .args([name, groupname])
+                            .args([&*name, &*groupname])
                             .stdin(std::process::Stdio::null()),
                     )
                     .await
@@ -318,6 +320,10 @@ impl Action for AddUserToGroup {
             },
         };
 
+        audit::record(AuditEvent::UserRemovedFromGroup {
+            user: name.clone(),
+            group: groupname.clone(),
+        });
         Ok(())
     }
 }
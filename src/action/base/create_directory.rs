@@ -8,8 +8,11 @@ use tokio::fs::{create_dir_all, remove_dir_all, remove_file};
 use tokio::process::Command;
 use tracing::{span, Span};
 
-use crate::action::{Action, ActionDescription, ActionErrorKind, ActionState};
+use crate::action::{
+    Action, ActionDescription, ActionErrorKind, ActionState, Artifact, DryRunStep,
+};
 use crate::action::{ActionError, StatefulAction};
+use crate::audit::{self, AuditEvent};
 use crate::execute_command;
 
 /** Create a directory at the given location, optionally with an owning user, group, and mode.
@@ -198,10 +201,42 @@ impl Action for CreateDirectory {
                 .map_err(|e| ActionErrorKind::SetPermissions(*mode, path.to_owned(), e))
                 .map_err(Self::error)?;
         }
+        audit::record(AuditEvent::DirectoryCreated { path: path.clone() });
 
         Ok(())
     }
 
+    fn dry_run(&self) -> Vec<DryRunStep> {
+        if self.is_mountpoint {
+            return vec![];
+        }
+        let mut steps = vec![DryRunStep::Other(format!(
+            "Create directory `{}` (and any missing parents)",
+            self.path.display()
+        ))];
+        steps.extend(DryRunStep::chown(
+            &self.path,
+            self.user.as_deref(),
+            self.group.as_deref(),
+        ));
+        if let Some(mode) = self.mode {
+            steps.push(DryRunStep::Other(format!(
+                "Set the mode of `{}` to `{mode:#o}`",
+                self.path.display()
+            )));
+        }
+        steps
+    }
+
+    fn artifacts(&self) -> Vec<Artifact> {
+        vec![Artifact::Directory(self.path.clone())]
+    }
+
+    fn holds_store(&self) -> bool {
+        // Like `/nix`, or the directory it is persisted in
+        self.force_prune_on_revert
+    }
+
     fn revert_description(&self) -> Vec<ActionDescription> {
         let Self {
             path,
@@ -232,6 +267,22 @@ impl Action for CreateDirectory {
         }
     }
 
+    fn revert_dry_run(&self) -> Vec<DryRunStep> {
+        let is_empty = self
+            .path
+            .read_dir()
+            .map(|mut entries| entries.next().is_none())
+            .unwrap_or(true);
+        match (self.is_mountpoint, is_empty, self.force_prune_on_revert) {
+            (true, _, true) => vec![DryRunStep::Other(format!(
+                "Remove everything in `{}`",
+                self.path.display()
+            ))],
+            (true, _, false) | (false, false, false) => vec![],
+            (false, true, _) | (false, false, true) => vec![DryRunStep::Remove(self.path.clone())],
+        }
+    }
+
     #[tracing::instrument(level = "debug", skip_all)]
     async fn revert(&mut self) -> Result<(), ActionError> {
         let Self {
@@ -266,22 +317,31 @@ impl Action for CreateDirectory {
                         remove_dir_all(child_path_path.clone())
                             .await
                             .map_err(|e| ActionErrorKind::Remove(path.clone(), e))
-                            .map_err(Self::error)?
+                            .map_err(Self::error)?;
+                        audit::record(AuditEvent::DirectoryRemoved {
+                            path: child_path_path,
+                        });
                     } else {
-                        remove_file(child_path_path)
+                        remove_file(&child_path_path)
                             .await
                             .map_err(|e| ActionErrorKind::Remove(path.clone(), e))
-                            .map_err(Self::error)?
+                            .map_err(Self::error)?;
+                        audit::record(AuditEvent::FileRemoved {
+                            path: child_path_path,
+                        });
                     }
                 }
             },
             (true, _, false) => {
                 tracing::debug!("Not cleaning mountpoint `{}`", path.display());
             },
-            (false, true, _) | (false, false, true) => remove_dir_all(path.clone())
-                .await
-                .map_err(|e| ActionErrorKind::Remove(path.clone(), e))
-                .map_err(Self::error)?,
+            (false, true, _) | (false, false, true) => {
+                remove_dir_all(path.clone())
+                    .await
+                    .map_err(|e| ActionErrorKind::Remove(path.clone(), e))
+                    .map_err(Self::error)?;
+                audit::record(AuditEvent::DirectoryRemoved { path: path.clone() });
+            },
             (false, false, false) => {
                 tracing::debug!("Not removing `{}`, the folder is not empty", path.display());
             },
@@ -10,8 +10,13 @@ use tokio::{
     io::{AsyncReadExt, AsyncWriteExt},
 };
 
-use crate::action::{
-    Action, ActionDescription, ActionError, ActionErrorKind, ActionTag, StatefulAction,
+use crate::{
+    action::{
+        Action, ActionDescription, ActionError, ActionErrorKind, ActionTag, Artifact, DryRunStep,
+        StatefulAction,
+    },
+    audit::{self, AuditEvent},
+    drift::{Drift, DriftedAction},
 };
 
 /** Create a file at the given location with the provided `buf`,
@@ -228,10 +233,47 @@ impl Action for CreateFile {
         chown(&self.path, uid, gid)
             .map_err(|e| ActionErrorKind::Chown(self.path.clone(), e))
             .map_err(Self::error)?;
+        audit::file_written(&self.path, self.buf.as_bytes());
 
         Ok(())
     }
 
+    fn dry_run(&self) -> Vec<DryRunStep> {
+        let mut steps = vec![DryRunStep::WriteFile {
+            path: self.path.clone(),
+            mode: self.mode,
+            contents: self.buf.clone(),
+        }];
+        steps.extend(DryRunStep::chown(
+            &self.path,
+            self.user.as_deref(),
+            self.group.as_deref(),
+        ));
+        steps
+    }
+
+    fn artifacts(&self) -> Vec<Artifact> {
+        vec![Artifact::File(self.path.clone())]
+    }
+
+    async fn drift(&self) -> Vec<DriftedAction> {
+        let drift = match tokio::fs::read_to_string(&self.path).await {
+            Ok(contents) if contents == self.buf => vec![],
+            Ok(_) => vec![Drift::Modified {
+                path: self.path.clone(),
+                detail: "its contents changed".into(),
+            }],
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => vec![Drift::Missing {
+                path: self.path.clone(),
+            }],
+            Err(err) => {
+                tracing::debug!(%err, "Could not read `{}`", self.path.display());
+                vec![]
+            },
+        };
+        DriftedAction::of(self.tracing_synopsis(), drift)
+    }
+
     fn revert_description(&self) -> Vec<ActionDescription> {
         let Self {
             path,
@@ -248,6 +290,10 @@ impl Action for CreateFile {
         )]
     }
 
+    fn revert_dry_run(&self) -> Vec<DryRunStep> {
+        vec![DryRunStep::Remove(self.path.clone())]
+    }
+
     #[tracing::instrument(level = "debug", skip_all)]
     async fn revert(&mut self) -> Result<(), ActionError> {
         let Self {
@@ -267,6 +313,7 @@ impl Action for CreateFile {
             .await
             .map_err(|e| ActionErrorKind::Remove(path.to_owned(), e))
             .map_err(Self::error)?;
+        audit::record(AuditEvent::FileRemoved { path: path.clone() });
 
         Ok(())
     }
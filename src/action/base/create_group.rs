@@ -4,9 +4,10 @@ use tokio::process::Command;
 use tracing::{span, Span};
 
 use crate::action::{ActionError, ActionErrorKind, ActionTag};
+use crate::audit::{self, AuditEvent};
 use crate::execute_command;
 
-use crate::action::{Action, ActionDescription, StatefulAction};
+use crate::action::{Action, ActionDescription, Artifact, DryRunStep, StatefulAction};
 
 /**
 Create an operating system level user group
@@ -56,6 +57,74 @@ impl CreateGroup {
         }
         Ok(StatefulAction::uncompleted(this))
     }
+
+    /// The command which creates the group on this host
+    fn command(&self) -> Result<Command, ActionErrorKind> {
+        let Self { name, gid } = self;
+
+        let mut command = match OperatingSystem::host() {
+            OperatingSystem::MacOSX {
+                major: _,
+                minor: _,
+                patch: _,
+            }
+            | OperatingSystem::Darwin => {
+                let mut command = Command::new("/usr/sbin/dseditgroup");
+                command.args([
+                    "-o",
+                    "create",
+                    "-r",
+                    "Nix build group for nix-daemon",
+                    "-i",
+                    &format!("{gid}"),
+                    name,
+                ]);
+                command
+            },
+            _ => {
+                let mut command = if which::which("groupadd").is_ok() {
+                    Command::new("groupadd")
+                } else if which::which("addgroup").is_ok() {
+                    Command::new("addgroup")
+                } else {
+                    return Err(ActionErrorKind::MissingGroupCreationCommand);
+                };
+                command.args(["-g", &gid.to_string(), "--system", name]);
+                command
+            },
+        };
+        command.process_group(0).stdin(std::process::Stdio::null());
+        Ok(command)
+    }
+
+    /// The command deleting the group
+    fn revert_command(&self) -> Result<Command, ActionErrorKind> {
+        let mut command = match OperatingSystem::host() {
+            OperatingSystem::MacOSX {
+                major: _,
+                minor: _,
+                patch: _,
+            }
+            | OperatingSystem::Darwin => {
+                let mut command = Command::new("/usr/bin/dscl");
+                command.args([".", "-delete", &format!("/Groups/{}", self.name)]);
+                command
+            },
+            _ => {
+                let mut command = if which::which("groupdel").is_ok() {
+                    Command::new("groupdel")
+                } else if which::which("delgroup").is_ok() {
+                    Command::new("delgroup")
+                } else {
+                    return Err(ActionErrorKind::MissingGroupDeletionCommand);
+                };
+                command.process_group(0).arg(&self.name);
+                command
+            },
+        };
+        command.stdin(std::process::Stdio::null());
+        Ok(command)
+    }
 }
 
 #[async_trait::async_trait]
@@ -88,59 +157,32 @@ impl Action for CreateGroup {
 
     #[tracing::instrument(level = "debug", skip_all)]
     async fn execute(&mut self) -> Result<(), ActionError> {
-        let Self { name, gid } = self;
+        execute_command(&mut self.command().map_err(Self::error)?)
+            .await
+            .map_err(Self::error)?;
+
+        audit::record(AuditEvent::GroupCreated {
+            name: self.name.clone(),
+            gid: self.gid,
+        });
+        Ok(())
+    }
 
-        use OperatingSystem;
-        match OperatingSystem::host() {
-            OperatingSystem::MacOSX {
-                major: _,
-                minor: _,
-                patch: _,
-            }
-            | OperatingSystem::Darwin => {
-                execute_command(
-                    Command::new("/usr/sbin/dseditgroup")
-                        .process_group(0)
-                        .args([
-                            "-o",
-                            "create",
-                            "-r",
-                            "Nix build group for nix-daemon",
-                            "-i",
-                            &format!("{gid}"),
-                            name,
-                        ])
-                        .stdin(std::process::Stdio::null()),
-                )
-                .await
-                .map_err(Self::error)?;
-            },
-            _ => {
-                if which::which("groupadd").is_ok() {
-                    execute_command(
-                        Command::new("groupadd")
-                            .process_group(0)
-                            .args(["-g", &gid.to_string(), "--system", name])
-                            .stdin(std::process::Stdio::null()),
-                    )
-                    .await
-                    .map_err(Self::error)?;
-                } else if which::which("addgroup").is_ok() {
-                    execute_command(
-                        Command::new("addgroup")
-                            .process_group(0)
-                            .args(["-g", &gid.to_string(), "--system", name])
-                            .stdin(std::process::Stdio::null()),
-                    )
-                    .await
-                    .map_err(Self::error)?;
-                } else {
-                    return Err(Self::error(ActionErrorKind::MissingGroupCreationCommand));
-                }
-            },
-        };
+    fn dry_run(&self) -> Vec<DryRunStep> {
+        match self.command() {
+            Ok(command) => vec![DryRunStep::command(&command)],
+            Err(err) => vec![DryRunStep::Other(format!(
+                "{} (could not be rendered: {err})",
+                self.tracing_synopsis()
+            ))],
+        }
+    }
 
-        Ok(())
+    fn artifacts(&self) -> Vec<Artifact> {
+        vec![Artifact::Group {
+            name: self.name.clone(),
+            gid: self.gid,
+        }]
     }
 
     fn revert_description(&self) -> Vec<ActionDescription> {
@@ -153,51 +195,23 @@ impl Action for CreateGroup {
         )]
     }
 
+    fn revert_dry_run(&self) -> Vec<DryRunStep> {
+        match self.revert_command() {
+            Ok(command) => vec![DryRunStep::command(&command)],
+            Err(err) => vec![DryRunStep::Other(format!(
+                "Delete group `{}` (could not be rendered: {err})",
+                self.name
+            ))],
+        }
+    }
+
     #[tracing::instrument(level = "debug", skip_all)]
     async fn revert(&mut self) -> Result<(), ActionError> {
-        let Self { name, gid: _ } = self;
-
-        use OperatingSystem;
-        match OperatingSystem::host() {
-            OperatingSystem::MacOSX {
-                major: _,
-                minor: _,
-                patch: _,
-            }
-            | OperatingSystem::Darwin => {
-                execute_command(
-                    Command::new("/usr/bin/dscl")
-                        .args([".", "-delete", &format!("/Groups/{name}")])
-                        .stdin(std::process::Stdio::null()),
-                )
-                .await
-                .map_err(Self::error)?;
-            },
-            _ => {
-                if which::which("groupdel").is_ok() {
-                    execute_command(
-                        Command::new("groupdel")
-                            .process_group(0)
-                            .arg(name)
-                            .stdin(std::process::Stdio::null()),
-                    )
-                    .await
-                    .map_err(Self::error)?;
-                } else if which::which("delgroup").is_ok() {
-                    execute_command(
-                        Command::new("delgroup")
-                            .process_group(0)
-                            .arg(name)
-                            .stdin(std::process::Stdio::null()),
-                    )
-                    .await
-                    .map_err(Self::error)?;
-                } else {
-                    return Err(Self::error(ActionErrorKind::MissingGroupDeletionCommand));
-                }
-            },
-        };
+        let mut command = self.revert_command().map_err(Self::error)?;
+        execute_command(&mut command).await.map_err(Self::error)?;
 
+        let Self { name, gid: _ } = self;
+        audit::record(AuditEvent::GroupDeleted { name: name.clone() });
         Ok(())
     }
 }
@@ -1,7 +1,12 @@
 use nix::unistd::{chown, Group, User};
 
-use crate::action::{
-    Action, ActionDescription, ActionError, ActionErrorKind, ActionTag, StatefulAction,
+use crate::{
+    action::{
+        Action, ActionDescription, ActionError, ActionErrorKind, ActionTag, Artifact, DryRunStep,
+        StatefulAction,
+    },
+    audit::{self, AuditEvent},
+    drift::{Drift, DriftedAction},
 };
 use rand::Rng;
 use std::{
@@ -309,10 +314,52 @@ impl Action for CreateOrInsertIntoFile {
             .await
             .map_err(|e| ActionErrorKind::Rename(path.to_owned(), temp_file_path.to_owned(), e))
             .map_err(Self::error)?;
+        audit::file_written_on_disk(path).await;
 
         Ok(())
     }
 
+    fn dry_run(&self) -> Vec<DryRunStep> {
+        let existing = std::fs::read_to_string(&self.path).unwrap_or_default();
+        let contents = match self.position {
+            Position::Beginning => format!("{}{existing}", self.buf),
+            Position::End => format!("{existing}{}", self.buf),
+        };
+        let mut steps = vec![DryRunStep::WriteFile {
+            path: self.path.clone(),
+            mode: self.mode,
+            contents,
+        }];
+        steps.extend(DryRunStep::chown(
+            &self.path,
+            self.user.as_deref(),
+            self.group.as_deref(),
+        ));
+        steps
+    }
+
+    fn artifacts(&self) -> Vec<Artifact> {
+        vec![Artifact::File(self.path.clone())]
+    }
+
+    async fn drift(&self) -> Vec<DriftedAction> {
+        let drift = match tokio::fs::read_to_string(&self.path).await {
+            Ok(contents) if contents.contains(&self.buf) => vec![],
+            Ok(_) => vec![Drift::Modified {
+                path: self.path.clone(),
+                detail: "it no longer has what was added to it".into(),
+            }],
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => vec![Drift::Missing {
+                path: self.path.clone(),
+            }],
+            Err(err) => {
+                tracing::debug!(%err, "Could not read `{}`", self.path.display());
+                vec![]
+            },
+        };
+        DriftedAction::of(self.tracing_synopsis(), drift)
+    }
+
     fn revert_description(&self) -> Vec<ActionDescription> {
         let Self {
             path,
@@ -331,6 +378,23 @@ impl Action for CreateOrInsertIntoFile {
         )]
     }
 
+    fn revert_dry_run(&self) -> Vec<DryRunStep> {
+        // The user already deleted it
+        let Ok(contents) = std::fs::read_to_string(&self.path) else {
+            return vec![];
+        };
+        let contents = remove_buf(contents, &self.buf);
+        if contents.is_empty() {
+            vec![DryRunStep::Remove(self.path.clone())]
+        } else {
+            vec![DryRunStep::WriteFile {
+                path: self.path.clone(),
+                mode: None,
+                contents,
+            }]
+        }
+    }
+
     #[tracing::instrument(level = "debug", skip_all)]
     async fn revert(&mut self) -> Result<(), ActionError> {
         let Self {
@@ -361,16 +425,14 @@ impl Action for CreateOrInsertIntoFile {
             .map_err(|e| ActionErrorKind::Read(path.to_owned(), e))
             .map_err(Self::error)?;
 
-        if let Some(start) = file_contents.rfind(buf.as_str()) {
-            let end = start + buf.len();
-            file_contents.replace_range(start..end, "")
-        }
+        let file_contents = remove_buf(file_contents, buf);
 
         if file_contents.is_empty() {
             remove_file(&path)
                 .await
                 .map_err(|e| ActionErrorKind::Remove(path.to_owned(), e))
                 .map_err(Self::error)?;
+            audit::record(AuditEvent::FileRemoved { path: path.clone() });
         } else {
             file.seek(SeekFrom::Start(0))
                 .await
@@ -388,11 +450,20 @@ impl Action for CreateOrInsertIntoFile {
                 .await
                 .map_err(|e| ActionErrorKind::Flush(path.to_owned(), e))
                 .map_err(Self::error)?;
+            audit::file_written(path, file_contents.as_bytes());
         }
         Ok(())
     }
 }
 
+/// `contents` without the last `buf` in it
+fn remove_buf(mut contents: String, buf: &str) -> String {
+    if let Some(start) = contents.rfind(buf) {
+        contents.replace_range(start..start + buf.len(), "");
+    }
+    contents
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -422,6 +493,41 @@ mod test {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn dry_runs_revert_without_changing_file() -> eyre::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let test_file = temp_dir
+            .path()
+            .join("dry_runs_revert_without_changing_file");
+        tokio::fs::write(&test_file, "Some other content").await?;
+
+        let mut action = CreateOrInsertIntoFile::plan(
+            test_file.clone(),
+            None,
+            None,
+            None,
+            "Test".into(),
+            Position::End,
+        )
+        .await?;
+        action.try_execute().await?;
+
+        assert_eq!(
+            action.revert_dry_run(),
+            vec![DryRunStep::WriteFile {
+                path: test_file.clone(),
+                mode: None,
+                contents: "Some other content".into(),
+            }]
+        );
+        assert_eq!(read_to_string(&test_file).await?, "Some other contentTest");
+
+        action.try_revert().await?;
+        assert!(action.revert_dry_run().is_empty());
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn edits_and_reverts_file() -> eyre::Result<()> {
         let temp_dir = tempfile::tempdir()?;
@@ -587,4 +693,34 @@ mod test {
 
         Ok(())
     }
+
+    #[tokio::test]
+    async fn diffs_existing_file() -> eyre::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let test_file = temp_dir.path().join("diffs_existing_file");
+        write(&test_file, "export PATH=/usr/bin\n").await?;
+
+        let action = CreateOrInsertIntoFile::plan(
+            &test_file,
+            None,
+            None,
+            None,
+            "# Nix\n".into(),
+            Position::Beginning,
+        )
+        .await?;
+
+        let diffs = action
+            .dry_run()
+            .iter()
+            .filter_map(DryRunStep::diff)
+            .collect::<Vec<_>>();
+        let [diff] = diffs.as_slice() else {
+            return Err(eyre!("Expected a single diff, got {diffs:?}"));
+        };
+        assert!(diff.contains("+# Nix\n"));
+        assert!(diff.contains(" export PATH=/usr/bin\n"));
+
+        Ok(())
+    }
 }
@@ -11,8 +11,13 @@ use tokio::{
 };
 use tracing::{span, Span};
 
-use crate::action::{
-    Action, ActionDescription, ActionError, ActionErrorKind, ActionTag, StatefulAction,
+use crate::{
+    action::{
+        Action, ActionDescription, ActionError, ActionErrorKind, ActionTag, Artifact, DryRunStep,
+        StatefulAction,
+    },
+    audit::{self, AuditEvent},
+    drift::{Drift, DriftedAction},
 };
 
 /// The `nix.conf` configuration names that are safe to merge.
@@ -173,105 +178,9 @@ impl CreateOrMergeNixConfig {
 
         Ok((merged_nix_config, existing_nix_config))
     }
-}
-
-#[async_trait::async_trait]
-#[typetag::serde(name = "create_or_merge_nix_config")]
-impl Action for CreateOrMergeNixConfig {
-    fn action_tag() -> ActionTag {
-        ActionTag("create_or_merge_nix_config")
-    }
-    fn tracing_synopsis(&self) -> String {
-        format!(
-            "Merge or create nix.conf file `{path}`",
-            path = self.path.display(),
-        )
-    }
-
-    fn tracing_span(&self) -> Span {
-        let span = span!(
-            tracing::Level::DEBUG,
-            "create_or_merge_nix_config",
-            path = tracing::field::display(self.path.display()),
-            mode = tracing::field::display(format!("{:#o}", NIX_CONF_MODE)),
-            pending_nix_config = tracing::field::Empty,
-        );
-
-        if tracing::enabled!(tracing::Level::TRACE) {
-            span.record(
-                "pending_nix_config",
-                self.pending_nix_config
-                    .settings()
-                    .iter()
-                    .map(|(k, v)| format!("{k}=\"{v}\""))
-                    .collect::<Vec<_>>()
-                    .join(","),
-            );
-        }
-        span
-    }
-
-    fn execute_description(&self) -> Vec<ActionDescription> {
-        vec![ActionDescription::new(
-            self.tracing_synopsis(),
-            vec![format!(
-                "Added settings: {settings}",
-                settings = self
-                    .pending_nix_config
-                    .settings()
-                    .iter()
-                    .map(|(k, v)| format!("{k}=\"{v}\""))
-                    .collect::<Vec<_>>()
-                    .join(", "),
-            )],
-        )]
-    }
-
-    #[tracing::instrument(level = "debug", skip_all)]
-    async fn execute(&mut self) -> Result<(), ActionError> {
-        let Self {
-            path,
-            pending_nix_config,
-        } = self;
-
-        if tracing::enabled!(tracing::Level::TRACE) {
-            let span = tracing::Span::current();
-            span.record(
-                "pending_nix_config",
-                pending_nix_config
-                    .settings()
-                    .iter()
-                    .map(|(k, v)| format!("{k}='{v}'"))
-                    .collect::<Vec<_>>()
-                    .join(" "),
-            );
-        }
-
-        // Create a temporary file in the same directory as the one
-        // that the final file goes in, so that we can rename it
-        // atomically
-        let parent_dir = path.parent().expect("File must be in a directory");
-        let mut temp_file_path = parent_dir.to_owned();
-        {
-            let mut rng = rand::thread_rng();
-            temp_file_path.push(format!("nix-installer-tmp.{}", rng.gen::<u32>()));
-        }
-        let mut temp_file = OpenOptions::new()
-            .create(true)
-            .truncate(true)
-            .write(true)
-            // If the file is created, ensure that it has harmless
-            // permissions regardless of whether the mode will be
-            // changed later (if we ever create setuid executables,
-            // they should only become setuid once they are owned by
-            // the appropriate user)
-            .mode(0o600)
-            .open(&temp_file_path)
-            .await
-            .map_err(|e| {
-                Self::error(ActionErrorKind::Open(temp_file_path.clone(), e))
-            })?;
 
+    /// The contents `path` will have once `pending_nix_config` is merged into it
+    fn render(pending_nix_config: &NixConfig, path: &Path) -> Result<String, ActionError> {
         let (mut merged_nix_config, mut existing_nix_config) = if path.exists() {
             let (merged_nix_config, existing_nix_config) =
                 Self::validate_existing_nix_config(pending_nix_config, path)?;
@@ -283,8 +192,7 @@ impl Action for CreateOrMergeNixConfig {
         let mut new_config = String::new();
 
         if let Some(existing_nix_config) = existing_nix_config.as_mut() {
-            let mut discovered_buf = tokio::fs::read_to_string(&path)
-                .await
+            let mut discovered_buf = std::fs::read_to_string(path)
                 .map_err(|e| Self::error(ActionErrorKind::Read(path.to_path_buf(), e)))?;
 
             // We append a newline to ensure that, in the case there are comments at the end of the
@@ -422,6 +330,109 @@ impl Action for CreateOrMergeNixConfig {
             new_config.push('\n');
         }
 
+        Ok(new_config)
+    }
+}
+
+#[async_trait::async_trait]
+#[typetag::serde(name = "create_or_merge_nix_config")]
+impl Action for CreateOrMergeNixConfig {
+    fn action_tag() -> ActionTag {
+        ActionTag("create_or_merge_nix_config")
+    }
+    fn tracing_synopsis(&self) -> String {
+        format!(
+            "Merge or create nix.conf file `{path}`",
+            path = self.path.display(),
+        )
+    }
+
+    fn tracing_span(&self) -> Span {
+        let span = span!(
+            tracing::Level::DEBUG,
+            "create_or_merge_nix_config",
+            path = tracing::field::display(self.path.display()),
+            mode = tracing::field::display(format!("{:#o}", NIX_CONF_MODE)),
+            pending_nix_config = tracing::field::Empty,
+        );
+
+        if tracing::enabled!(tracing::Level::TRACE) {
+            span.record(
+                "pending_nix_config",
+                self.pending_nix_config
+                    .settings()
+                    .iter()
+                    .map(|(k, v)| format!("{k}=\"{v}\""))
+                    .collect::<Vec<_>>()
+                    .join(","),
+            );
+        }
+        span
+    }
+
+    fn execute_description(&self) -> Vec<ActionDescription> {
+        vec![ActionDescription::new(
+            self.tracing_synopsis(),
+            vec![format!(
+                "Added settings: {settings}",
+                settings = self
+                    .pending_nix_config
+                    .settings()
+                    .iter()
+                    .map(|(k, v)| format!("{k}=\"{v}\""))
+                    .collect::<Vec<_>>()
+                    .join(", "),
+            )],
+        )]
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    async fn execute(&mut self) -> Result<(), ActionError> {
+        let Self {
+            path,
+            pending_nix_config,
+        } = self;
+
+        if tracing::enabled!(tracing::Level::TRACE) {
+            let span = tracing::Span::current();
+            span.record(
+                "pending_nix_config",
+                pending_nix_config
+                    .settings()
+                    .iter()
+                    .map(|(k, v)| format!("{k}='{v}'"))
+                    .collect::<Vec<_>>()
+                    .join(" "),
+            );
+        }
+
+        // Create a temporary file in the same directory as the one
+        // that the final file goes in, so that we can rename it
+        // atomically
+        let parent_dir = path.parent().expect("File must be in a directory");
+        let mut temp_file_path = parent_dir.to_owned();
+        {
+            let mut rng = rand::thread_rng();
+            temp_file_path.push(format!("nix-installer-tmp.{}", rng.gen::<u32>()));
+        }
+        let mut temp_file = OpenOptions::new()
+            .create(true)
+            .truncate(true)
+            .write(true)
+            // If the file is created, ensure that it has harmless
+            // permissions regardless of whether the mode will be
+            // changed later (if we ever create setuid executables,
+            // they should only become setuid once they are owned by
+            // the appropriate user)
+            .mode(0o600)
+            .open(&temp_file_path)
+            .await
+            .map_err(|e| {
+                Self::error(ActionErrorKind::Open(temp_file_path.clone(), e))
+            })?;
+
+        let new_config = Self::render(pending_nix_config, path)?;
+
         temp_file
             .write_all(new_config.as_bytes())
             .await
@@ -448,10 +459,56 @@ impl Action for CreateOrMergeNixConfig {
                     e,
                 ))
             })?;
+        audit::file_written(path, new_config.as_bytes());
 
         Ok(())
     }
 
+    fn dry_run(&self) -> Vec<DryRunStep> {
+        match Self::render(&self.pending_nix_config, &self.path) {
+            Ok(contents) => vec![DryRunStep::WriteFile {
+                path: self.path.clone(),
+                mode: Some(NIX_CONF_MODE),
+                contents,
+            }],
+            Err(err) => vec![DryRunStep::Other(format!(
+                "{} (could not be rendered: {err})",
+                self.tracing_synopsis()
+            ))],
+        }
+    }
+
+    fn artifacts(&self) -> Vec<Artifact> {
+        vec![Artifact::File(self.path.clone())]
+    }
+
+    async fn drift(&self) -> Vec<DriftedAction> {
+        let drift = if !self.path.exists() {
+            vec![Drift::Missing {
+                path: self.path.clone(),
+            }]
+        } else {
+            match NixConfig::parse_file(&self.path) {
+                // Values may since have been changed by `nix-installer reconfigure`, but each setting is still set
+                Ok(existing_nix_config) => self
+                    .pending_nix_config
+                    .settings()
+                    .keys()
+                    .filter(|name| !existing_nix_config.settings().contains_key(*name))
+                    .map(|name| Drift::Modified {
+                        path: self.path.clone(),
+                        detail: format!("`{name}` is no longer set"),
+                    })
+                    .collect(),
+                Err(err) => vec![Drift::Modified {
+                    path: self.path.clone(),
+                    detail: format!("it could not be parsed ({err})"),
+                }],
+            }
+        };
+        DriftedAction::of(self.tracing_synopsis(), drift)
+    }
+
     fn revert_description(&self) -> Vec<ActionDescription> {
         let Self {
             path,
@@ -464,6 +521,10 @@ impl Action for CreateOrMergeNixConfig {
         )]
     }
 
+    fn revert_dry_run(&self) -> Vec<DryRunStep> {
+        vec![DryRunStep::Remove(self.path.clone())]
+    }
+
     #[tracing::instrument(level = "debug", skip_all)]
     async fn revert(&mut self) -> Result<(), ActionError> {
         let Self {
@@ -474,6 +535,7 @@ impl Action for CreateOrMergeNixConfig {
         remove_file(&path)
             .await
             .map_err(|e| Self::error(ActionErrorKind::Remove(path.to_owned(), e)))?;
+        audit::record(AuditEvent::FileRemoved { path: path.clone() });
 
         Ok(())
     }
@@ -714,4 +776,33 @@ mod test {
 
         Ok(())
     }
+
+    #[tokio::test]
+    async fn dry_run_renders_merged_file() -> eyre::Result<()> {
+        let temp_dir = tempfile::TempDir::new()?;
+        let test_file = temp_dir.path().join("dry_run_renders_merged_file");
+        write(
+            &test_file,
+            "# Some comment\nexperimental-features = flakes # Inline\nwarn-dirty = false\n",
+        )
+        .await?;
+        tokio::fs::set_permissions(&test_file, PermissionsExt::from_mode(NIX_CONF_MODE)).await?;
+        let mut nix_config = NixConfig::new();
+        nix_config
+            .settings_mut()
+            .insert("experimental-features".into(), "nix-command".into());
+        let mut action = CreateOrMergeNixConfig::plan(&test_file, nix_config).await?;
+
+        let steps = action.dry_run();
+        let [DryRunStep::WriteFile { path, contents, .. }] = steps.as_slice() else {
+            return Err(eyre!("Expected a single file write, got {steps:?}"));
+        };
+        assert_eq!(path, &test_file);
+
+        action.try_execute().await?;
+
+        assert_eq!(contents, &std::fs::read_to_string(&test_file)?);
+
+        Ok(())
+    }
 }
@@ -0,0 +1,793 @@
+use std::{
+    os::unix::fs::{MetadataExt, PermissionsExt},
+    path::{Path, PathBuf},
+    process::Stdio,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use tokio::process::Command;
+use tracing::{span, Span};
+
+use crate::{
+    action::{
+        Action, ActionDescription, ActionError, ActionErrorKind, ActionTag, DryRunStep,
+        StatefulAction,
+    },
+    execute_command,
+};
+
+/// Where the btrfs snapshots are kept, in the subvolume they are of
+const BTRFS_SNAPSHOT_DIR: &str = ".nix-installer-snapshots";
+
+/// The volume macOS keeps `/etc` (as `/private/etc`) and `/Library` on, which `tmutil` snapshots
+const APFS_DATA_VOLUME: &str = "/System/Volumes/Data";
+
+/// The paths the install changes, which a rollback restores
+fn snapshotted_paths() -> Vec<PathBuf> {
+    if cfg!(target_os = "macos") {
+        // `/nix` is a volume of its own, created by the install
+        vec!["/etc".into(), "/Library/LaunchDaemons".into()]
+    } else {
+        vec!["/etc".into(), "/nix".into()]
+    }
+}
+
+/**
+Snapshot the filesystems (btrfs, ZFS, or APFS) with the paths the install changes, before it changes
+anything
+
+`nix-installer rollback-to-snapshot` restores what the install's actions placed in those paths from
+the snapshots, beyond what reverting each action can (like after a failed revert), and removes what
+they placed in those which didn't exist before. Reverting this action deletes the snapshots.
+ */
+#[derive(Debug, serde::Deserialize, serde::Serialize, Clone)]
+#[serde(tag = "action_name", rename = "create_snapshots")]
+pub struct CreateSnapshots {
+    snapshots: Vec<Snapshot>,
+    /// The paths which didn't exist before the install, a rollback removes them
+    absent: Vec<PathBuf>,
+}
+
+/// A snapshot of one filesystem
+#[derive(Debug, serde::Deserialize, serde::Serialize, Clone, PartialEq, Eq)]
+pub struct Snapshot {
+    pub filesystem: SnapshotFilesystem,
+    /// Where the btrfs subvolume, ZFS dataset, or APFS volume is mounted
+    pub mount_point: PathBuf,
+    /// The ZFS dataset (or the device of other filesystems)
+    pub source: String,
+    /// The paths on the filesystem a rollback restores
+    pub paths: Vec<PathBuf>,
+    /// The name of the snapshot once it is taken (for APFS, the date `tmutil` gave it)
+    pub name: Option<String>,
+}
+
+#[derive(Debug, serde::Deserialize, serde::Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SnapshotFilesystem {
+    Btrfs,
+    Zfs,
+    Apfs,
+}
+
+impl CreateSnapshots {
+    #[tracing::instrument(level = "debug", skip_all)]
+    pub async fn plan() -> Result<StatefulAction<Self>, ActionError> {
+        let mut snapshots: Vec<Snapshot> = vec![];
+        let mut absent = vec![];
+        for path in snapshotted_paths() {
+            if !path.exists() {
+                absent.push(path);
+                continue;
+            }
+            // On macOS, `/etc` is a link to `/private/etc`
+            let path = tokio::fs::canonicalize(&path)
+                .await
+                .map_err(|e| Self::error(ActionErrorKind::Canonicalize(path.clone(), e)))?;
+            let (filesystem, source, mount_point) =
+                filesystem_of(&path).await.map_err(Self::error)?;
+            match snapshots
+                .iter_mut()
+                .find(|snapshot| snapshot.mount_point == mount_point)
+            {
+                Some(snapshot) => snapshot.paths.push(path),
+                None => snapshots.push(Snapshot {
+                    filesystem,
+                    mount_point,
+                    source,
+                    paths: vec![path],
+                    name: None,
+                }),
+            }
+        }
+
+        Ok(StatefulAction::uncompleted(Self { snapshots, absent }))
+    }
+
+    /// The paths in `touched` (what the install's actions placed) a rollback restores: those in a
+    /// snapshotted path, or in one which didn't exist before the install
+    ///
+    /// Paths in another of them are left out, as restoring the outer one restores them too.
+    pub(crate) fn rollback_paths(&self, touched: &[PathBuf]) -> Vec<PathBuf> {
+        let roots = self
+            .snapshots
+            .iter()
+            .filter(|snapshot| snapshot.name.is_some())
+            .flat_map(|snapshot| &snapshot.paths)
+            .chain(&self.absent)
+            .collect::<Vec<_>>();
+        let mut paths = touched
+            .iter()
+            .map(|path| canonicalize_existing(path))
+            .filter(|path| {
+                roots
+                    .iter()
+                    .any(|root| path.starts_with(root) && path != *root)
+            })
+            .collect::<Vec<_>>();
+        // Sorted, an outer path comes before those in it
+        paths.sort();
+        paths.dedup();
+        let mut outer: Vec<PathBuf> = vec![];
+        for path in paths {
+            if !outer.iter().any(|other| path.starts_with(other)) {
+                outer.push(path);
+            }
+        }
+        outer
+    }
+
+    /// What [`CreateSnapshots::rollback`] does with `paths` (see [`CreateSnapshots::rollback_paths`])
+    pub(crate) fn describe_rollback(&self, paths: &[PathBuf]) -> Vec<String> {
+        let mut steps = vec![];
+        for snapshot in &self.snapshots {
+            let Some(name) = &snapshot.name else {
+                continue;
+            };
+            for path in snapshot.paths_in(paths) {
+                steps.push(format!(
+                    "Restore `{}` as it was in the {:?} snapshot `{name}` (removing it, if it wasn't there)",
+                    path.display(),
+                    snapshot.filesystem
+                ));
+            }
+        }
+        for absent in &self.absent {
+            for path in paths.iter().filter(|path| path.starts_with(absent)) {
+                steps.push(format!(
+                    "Remove `{}`, which didn't exist before the install",
+                    path.display()
+                ));
+            }
+            steps.push(format!(
+                "Remove `{}`, which didn't exist before the install, if nothing else is in it",
+                absent.display()
+            ));
+        }
+        steps
+    }
+
+    /// Restore `paths` (see [`CreateSnapshots::rollback_paths`]) from the snapshots, and remove
+    /// those in a path which didn't exist before the install (then that path, if it is empty)
+    ///
+    /// Nothing else in the snapshotted paths is changed. Returns the paths restored or removed.
+    pub(crate) async fn rollback(
+        &self,
+        paths: &[PathBuf],
+    ) -> Result<Vec<PathBuf>, ActionErrorKind> {
+        let mut restored = vec![];
+        for snapshot in &self.snapshots {
+            let Some(name) = &snapshot.name else {
+                // Never taken, the install failed before it
+                continue;
+            };
+            let paths = snapshot.paths_in(paths);
+            snapshot.restore(name, &paths).await?;
+            restored.extend(paths);
+        }
+        for absent in &self.absent {
+            for path in paths.iter().filter(|path| path.starts_with(absent)) {
+                if tokio::fs::symlink_metadata(path).await.is_ok() {
+                    remove(path)?;
+                    restored.push(path.clone());
+                }
+            }
+            if tokio::fs::remove_dir(absent).await.is_ok() {
+                restored.push(absent.clone());
+            }
+        }
+        Ok(restored)
+    }
+}
+
+/// `path`, with the part of it which exists canonicalized (on macOS, `/etc` is a link to `/private/etc`)
+fn canonicalize_existing(path: &Path) -> PathBuf {
+    for ancestor in path.ancestors() {
+        if let Ok(canonical) = std::fs::canonicalize(ancestor) {
+            let rest = path.strip_prefix(ancestor).unwrap_or(Path::new(""));
+            return if rest.as_os_str().is_empty() {
+                canonical
+            } else {
+                canonical.join(rest)
+            };
+        }
+    }
+    path.to_path_buf()
+}
+
+#[async_trait::async_trait]
+#[typetag::serde(name = "create_snapshots")]
+impl Action for CreateSnapshots {
+    fn action_tag() -> ActionTag {
+        ActionTag("create_snapshots")
+    }
+    fn tracing_synopsis(&self) -> String {
+        format!(
+            "Snapshot {}",
+            self.snapshots
+                .iter()
+                .flat_map(|snapshot| &snapshot.paths)
+                .map(|path| format!("`{}`", path.display()))
+                .collect::<Vec<_>>()
+                .join(", ")
+        )
+    }
+
+    fn tracing_span(&self) -> Span {
+        span!(tracing::Level::DEBUG, "create_snapshots",)
+    }
+
+    fn execute_description(&self) -> Vec<ActionDescription> {
+        let mut explanation = self
+            .snapshots
+            .iter()
+            .map(|snapshot| {
+                format!(
+                    "Take a {:?} snapshot of `{}`",
+                    snapshot.filesystem,
+                    snapshot.mount_point.display()
+                )
+            })
+            .collect::<Vec<_>>();
+        explanation.push(
+            "Restore them with `nix-installer rollback-to-snapshot` if the install can't be reverted"
+                .to_string(),
+        );
+        vec![ActionDescription::new(self.tracing_synopsis(), explanation)]
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    async fn execute(&mut self) -> Result<(), ActionError> {
+        let since_epoch = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let name = format!("nix-installer-{}", since_epoch.as_secs());
+        for snapshot in &mut self.snapshots {
+            if snapshot.name.is_none() {
+                snapshot.name = Some(snapshot.take(&name).await.map_err(Self::error)?);
+            }
+        }
+        Ok(())
+    }
+
+    fn dry_run(&self) -> Vec<DryRunStep> {
+        self.snapshots
+            .iter()
+            .map(|snapshot| DryRunStep::Command(snapshot.take_argv("nix-installer-<timestamp>")))
+            .collect()
+    }
+
+    fn revert_description(&self) -> Vec<ActionDescription> {
+        vec![ActionDescription::new(
+            format!(
+                "Delete the snapshots of {}",
+                self.snapshots
+                    .iter()
+                    .map(|snapshot| format!("`{}`", snapshot.mount_point.display()))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            vec![],
+        )]
+    }
+
+    fn revert_dry_run(&self) -> Vec<DryRunStep> {
+        self.snapshots
+            .iter()
+            .filter_map(|snapshot| {
+                let name = snapshot.name.as_deref()?;
+                Some(DryRunStep::Command(snapshot.delete_argv(name)))
+            })
+            .collect()
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    async fn revert(&mut self) -> Result<(), ActionError> {
+        let mut errors = vec![];
+        for snapshot in &mut self.snapshots {
+            let Some(name) = &snapshot.name else {
+                continue;
+            };
+            let argv = snapshot.delete_argv(name);
+            match execute_command(
+                Command::new(&argv[0])
+                    .process_group(0)
+                    .args(&argv[1..])
+                    .stdin(Stdio::null()),
+            )
+            .await
+            {
+                Ok(_) => snapshot.name = None,
+                Err(err) => errors.push(Self::error(err)),
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else if errors.len() == 1 {
+            Err(errors
+                .into_iter()
+                .next()
+                .expect("Expected 1 len Vec to have at least 1 item"))
+        } else {
+            Err(Self::error(ActionErrorKind::MultipleChildren(errors)))
+        }
+    }
+}
+
+impl Snapshot {
+    /// The command taking the snapshot
+    fn take_argv(&self, name: &str) -> Vec<String> {
+        match self.filesystem {
+            SnapshotFilesystem::Btrfs => vec![
+                "btrfs".into(),
+                "subvolume".into(),
+                "snapshot".into(),
+                "-r".into(),
+                self.mount_point.display().to_string(),
+                self.btrfs_snapshot(name).display().to_string(),
+            ],
+            SnapshotFilesystem::Zfs => vec![
+                "zfs".into(),
+                "snapshot".into(),
+                format!("{}@{name}", self.source),
+            ],
+            // `tmutil` names the snapshot by the date it was taken
+            SnapshotFilesystem::Apfs => vec!["tmutil".into(), "localsnapshot".into()],
+        }
+    }
+
+    /// The command deleting the snapshot
+    fn delete_argv(&self, name: &str) -> Vec<String> {
+        match self.filesystem {
+            SnapshotFilesystem::Btrfs => vec![
+                "btrfs".into(),
+                "subvolume".into(),
+                "delete".into(),
+                self.btrfs_snapshot(name).display().to_string(),
+            ],
+            SnapshotFilesystem::Zfs => vec![
+                "zfs".into(),
+                "destroy".into(),
+                format!("{}@{name}", self.source),
+            ],
+            SnapshotFilesystem::Apfs => vec![
+                "tmutil".into(),
+                "deletelocalsnapshots".into(),
+                name.to_string(),
+            ],
+        }
+    }
+
+    fn btrfs_snapshot(&self, name: &str) -> PathBuf {
+        self.mount_point.join(BTRFS_SNAPSHOT_DIR).join(name)
+    }
+
+    /// Take the snapshot, returning its name
+    async fn take(&self, name: &str) -> Result<String, ActionErrorKind> {
+        if self.filesystem == SnapshotFilesystem::Btrfs {
+            let dir = self.mount_point.join(BTRFS_SNAPSHOT_DIR);
+            tokio::fs::create_dir_all(&dir)
+                .await
+                .map_err(|e| ActionErrorKind::CreateDirectory(dir, e))?;
+        }
+        let argv = self.take_argv(name);
+        let output = execute_command(
+            Command::new(&argv[0])
+                .process_group(0)
+                .args(&argv[1..])
+                .stdin(Stdio::null()),
+        )
+        .await?;
+        match self.filesystem {
+            SnapshotFilesystem::Apfs => {
+                let stdout = String::from_utf8_lossy(&output.stdout);
+                tmutil_snapshot_date(&stdout)
+                    .map(ToString::to_string)
+                    .ok_or_else(|| CreateSnapshotsError::TmutilOutput(stdout.to_string()).into())
+            },
+            SnapshotFilesystem::Btrfs | SnapshotFilesystem::Zfs => Ok(name.to_string()),
+        }
+    }
+
+    /// Which of `paths` are in the paths this snapshot is of
+    fn paths_in(&self, paths: &[PathBuf]) -> Vec<PathBuf> {
+        paths
+            .iter()
+            .filter(|path| self.paths.iter().any(|root| path.starts_with(root)))
+            .cloned()
+            .collect()
+    }
+
+    /// Restore `paths` from the snapshot `name`
+    async fn restore(&self, name: &str, paths: &[PathBuf]) -> Result<(), ActionErrorKind> {
+        if paths.is_empty() {
+            return Ok(());
+        }
+        let (root, mounted) = match self.filesystem {
+            SnapshotFilesystem::Btrfs => (self.btrfs_snapshot(name), false),
+            SnapshotFilesystem::Zfs => (
+                self.mount_point.join(".zfs").join("snapshot").join(name),
+                false,
+            ),
+            SnapshotFilesystem::Apfs => {
+                let root = std::env::temp_dir().join(format!("nix-installer-snapshot-{name}"));
+                tokio::fs::create_dir_all(&root)
+                    .await
+                    .map_err(|e| ActionErrorKind::CreateDirectory(root.clone(), e))?;
+                execute_command(
+                    Command::new("mount_apfs")
+                        .process_group(0)
+                        .args(["-o", "rdonly", "-s"])
+                        .arg(format!("com.apple.TimeMachine.{name}.local"))
+                        .arg(&self.mount_point)
+                        .arg(&root)
+                        .stdin(Stdio::null()),
+                )
+                .await?;
+                (root, true)
+            },
+        };
+
+        let mut res = Ok(());
+        for path in paths {
+            // `/private/etc` is firmlinked into the data volume, at `private/etc`
+            let relative = match self.filesystem {
+                SnapshotFilesystem::Apfs => path.strip_prefix("/"),
+                SnapshotFilesystem::Btrfs | SnapshotFilesystem::Zfs => {
+                    path.strip_prefix(&self.mount_point)
+                },
+            }
+            .unwrap_or(path);
+            let from = root.join(relative);
+            let to = path.clone();
+            res = tokio::task::spawn_blocking(move || restore_tree(&from, &to))
+                .await
+                .map_err(|e| ActionErrorKind::Custom(Box::new(e)))
+                .and_then(|res| res);
+            if res.is_err() {
+                break;
+            }
+        }
+
+        if mounted {
+            execute_command(
+                Command::new("umount")
+                    .process_group(0)
+                    .arg(&root)
+                    .stdin(Stdio::null()),
+            )
+            .await?;
+            tokio::fs::remove_dir(&root).await.ok();
+        }
+        res
+    }
+}
+
+/// The filesystem kind, source, and mount point of the filesystem `path` is on
+async fn filesystem_of(
+    path: &Path,
+) -> Result<(SnapshotFilesystem, String, PathBuf), ActionErrorKind> {
+    if cfg!(target_os = "macos") {
+        return Ok((
+            SnapshotFilesystem::Apfs,
+            String::new(),
+            PathBuf::from(APFS_DATA_VOLUME),
+        ));
+    }
+
+    let output = execute_command(
+        Command::new("findmnt")
+            .process_group(0)
+            .args([
+                "--raw",
+                "--noheadings",
+                "--output",
+                "FSTYPE,SOURCE,TARGET",
+                "--target",
+            ])
+            .arg(path)
+            .stdin(Stdio::null()),
+    )
+    .await?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let Some((fstype, source, target)) = parse_findmnt(&stdout) else {
+        return Err(CreateSnapshotsError::FindmntOutput(stdout.to_string()).into());
+    };
+    let filesystem = match fstype.as_str() {
+        "btrfs" => SnapshotFilesystem::Btrfs,
+        "zfs" => SnapshotFilesystem::Zfs,
+        _ => return Err(CreateSnapshotsError::Unsupported(path.to_path_buf(), fstype).into()),
+    };
+    Ok((filesystem, source, target))
+}
+
+/// Parse the `FSTYPE,SOURCE,TARGET` of `findmnt --raw`, which escapes spaces as `\x20`
+fn parse_findmnt(output: &str) -> Option<(String, String, PathBuf)> {
+    let mut fields = output.lines().next()?.split(' ');
+    let mut field = || Some(fields.next()?.replace("\\x20", " "));
+    let (fstype, source, target) = (field()?, field()?, field()?);
+    Some((fstype, source, PathBuf::from(target)))
+}
+
+/// The date of the snapshot from `tmutil localsnapshot`'s `Created local snapshot with date: 2024-01-01-120000`
+fn tmutil_snapshot_date(output: &str) -> Option<&str> {
+    output
+        .lines()
+        .find_map(|line| line.split_once("with date:"))
+        .map(|(_, date)| date.trim())
+        .filter(|date| !date.is_empty())
+}
+
+/// Make `to` as `from` is: remove what isn't in `from` (or `to` itself, if `from` doesn't exist), and
+/// copy over what differs
+///
+/// Files with the same size, modification time, mode, and owner are taken as unchanged.
+fn restore_tree(from: &Path, to: &Path) -> Result<(), ActionErrorKind> {
+    let from_metadata = match std::fs::symlink_metadata(from) {
+        Ok(metadata) => metadata,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            if std::fs::symlink_metadata(to).is_ok() {
+                remove(to)?;
+            }
+            return Ok(());
+        },
+        Err(e) => return Err(ActionErrorKind::GettingMetadata(from.to_path_buf(), e)),
+    };
+    let to_metadata = std::fs::symlink_metadata(to).ok();
+    if let Some(to_metadata) = &to_metadata {
+        if unchanged(&from_metadata, to_metadata, from, to) {
+            if !from_metadata.is_dir() {
+                return Ok(());
+            }
+        } else if !(from_metadata.is_dir() && to_metadata.is_dir()) {
+            remove(to)?;
+        }
+    }
+
+    let file_type = from_metadata.file_type();
+    if file_type.is_dir() {
+        if !to.is_dir() {
+            std::fs::create_dir(to)
+                .map_err(|e| ActionErrorKind::CreateDirectory(to.to_path_buf(), e))?;
+        }
+        for entry in
+            std::fs::read_dir(to).map_err(|e| ActionErrorKind::ReadDir(to.to_path_buf(), e))?
+        {
+            let entry = entry.map_err(|e| ActionErrorKind::ReadDir(to.to_path_buf(), e))?;
+            if std::fs::symlink_metadata(from.join(entry.file_name())).is_err() {
+                remove(&entry.path())?;
+            }
+        }
+        for entry in
+            std::fs::read_dir(from).map_err(|e| ActionErrorKind::ReadDir(from.to_path_buf(), e))?
+        {
+            let entry = entry.map_err(|e| ActionErrorKind::ReadDir(from.to_path_buf(), e))?;
+            restore_tree(&entry.path(), &to.join(entry.file_name()))?;
+        }
+    } else if file_type.is_symlink() {
+        let target = std::fs::read_link(from)
+            .map_err(|e| ActionErrorKind::ReadSymlink(from.to_path_buf(), e))?;
+        std::os::unix::fs::symlink(&target, to)
+            .map_err(|e| ActionErrorKind::Symlink(target, to.to_path_buf(), e))?;
+    } else if file_type.is_file() {
+        std::fs::copy(from, to)
+            .map_err(|e| ActionErrorKind::Copy(from.to_path_buf(), to.to_path_buf(), e))?;
+        let file = std::fs::File::options()
+            .write(true)
+            .open(to)
+            .map_err(|e| ActionErrorKind::Open(to.to_path_buf(), e))?;
+        if let Ok(modified) = from_metadata.modified() {
+            file.set_modified(modified)
+                .map_err(|e| ActionErrorKind::Write(to.to_path_buf(), e))?;
+        }
+    } else {
+        // Sockets, FIFOs, and devices are made by what uses them
+        tracing::debug!("Not restoring `{}`, it isn't a file", to.display());
+        return Ok(());
+    }
+
+    std::os::unix::fs::lchown(to, Some(from_metadata.uid()), Some(from_metadata.gid())).map_err(
+        |e| {
+            let errno = nix::errno::Errno::from_raw(e.raw_os_error().unwrap_or_default());
+            ActionErrorKind::Chown(to.to_path_buf(), errno)
+        },
+    )?;
+    if !file_type.is_symlink() {
+        std::fs::set_permissions(to, std::fs::Permissions::from_mode(from_metadata.mode()))
+            .map_err(|e| {
+                ActionErrorKind::SetPermissions(from_metadata.mode(), to.to_path_buf(), e)
+            })?;
+    }
+    Ok(())
+}
+
+fn unchanged(
+    from_metadata: &std::fs::Metadata,
+    to_metadata: &std::fs::Metadata,
+    from: &Path,
+    to: &Path,
+) -> bool {
+    let same_type = from_metadata.file_type() == to_metadata.file_type();
+    let same_owner = from_metadata.mode() == to_metadata.mode()
+        && from_metadata.uid() == to_metadata.uid()
+        && from_metadata.gid() == to_metadata.gid();
+    if !same_type || !same_owner {
+        return false;
+    }
+    if from_metadata.is_dir() {
+        return true;
+    }
+    if from_metadata.is_symlink() {
+        return std::fs::read_link(from).ok() == std::fs::read_link(to).ok();
+    }
+    from_metadata.len() == to_metadata.len()
+        && from_metadata.modified().ok() == to_metadata.modified().ok()
+}
+
+fn remove(path: &Path) -> Result<(), ActionErrorKind> {
+    let res = match std::fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.is_dir() => std::fs::remove_dir_all(path),
+        _ => std::fs::remove_file(path),
+    };
+    res.map_err(|e| ActionErrorKind::Remove(path.to_path_buf(), e))
+}
+
+#[non_exhaustive]
+#[derive(Debug, thiserror::Error)]
+pub enum CreateSnapshotsError {
+    #[error("`{0}` is on a `{1}` filesystem, which can't be snapshotted (only btrfs, ZFS, and APFS can), install without `--snapshot`")]
+    Unsupported(PathBuf, String),
+    #[error("Unexpected output from `findmnt`: `{0}`")]
+    FindmntOutput(String),
+    #[error(
+        "Unexpected output from `tmutil localsnapshot`, without the date of the snapshot: `{0}`"
+    )]
+    TmutilOutput(String),
+}
+
+impl From<CreateSnapshotsError> for ActionErrorKind {
+    fn from(val: CreateSnapshotsError) -> Self {
+        ActionErrorKind::Custom(Box::new(val))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::PathBuf;
+
+    use super::{
+        parse_findmnt, restore_tree, tmutil_snapshot_date, CreateSnapshots, Snapshot,
+        SnapshotFilesystem,
+    };
+
+    #[test]
+    fn parses_findmnt() {
+        assert_eq!(
+            parse_findmnt("btrfs /dev/nvme0n1p2[/@] /\n"),
+            Some((
+                "btrfs".to_string(),
+                "/dev/nvme0n1p2[/@]".to_string(),
+                PathBuf::from("/")
+            ))
+        );
+        assert_eq!(
+            parse_findmnt("zfs rpool/ROOT/ubuntu /my\\x20root\n"),
+            Some((
+                "zfs".to_string(),
+                "rpool/ROOT/ubuntu".to_string(),
+                PathBuf::from("/my root")
+            ))
+        );
+        assert_eq!(parse_findmnt(""), None);
+    }
+
+    #[test]
+    fn parses_tmutil() {
+        assert_eq!(
+            tmutil_snapshot_date("NOTE: local snapshots are considered purgeable\nCreated local snapshot with date: 2024-01-01-120000\n"),
+            Some("2024-01-01-120000")
+        );
+        assert_eq!(tmutil_snapshot_date("Error\n"), None);
+    }
+
+    #[test]
+    fn restores_trees() -> eyre::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let snapshot = temp_dir.path().join("snapshot");
+        let live = temp_dir.path().join("live");
+        std::fs::create_dir_all(snapshot.join("profile.d"))?;
+        std::fs::write(snapshot.join("bashrc"), "# as it was\n")?;
+        std::fs::write(snapshot.join("profile.d").join("kept.sh"), "true\n")?;
+        std::os::unix::fs::symlink("bashrc", snapshot.join("link"))?;
+        std::fs::create_dir(&live)?;
+        restore_tree(&snapshot, &live)?;
+
+        // What the install changed, added, and removed
+        std::fs::write(live.join("bashrc"), "# as it was\n# Nix\n")?;
+        std::fs::create_dir(live.join("nix"))?;
+        std::fs::write(live.join("nix").join("nix.conf"), "")?;
+        std::fs::remove_file(live.join("profile.d").join("kept.sh"))?;
+        std::fs::remove_file(live.join("link"))?;
+        std::os::unix::fs::symlink("nix", live.join("link"))?;
+
+        restore_tree(&snapshot, &live)?;
+        assert_eq!(
+            std::fs::read_to_string(live.join("bashrc"))?,
+            "# as it was\n"
+        );
+        assert!(!live.join("nix").exists());
+        assert_eq!(
+            std::fs::read_to_string(live.join("profile.d").join("kept.sh"))?,
+            "true\n"
+        );
+        assert_eq!(
+            std::fs::read_link(live.join("link"))?,
+            PathBuf::from("bashrc")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn restores_only_touched_paths() {
+        let snapshots = CreateSnapshots {
+            snapshots: vec![Snapshot {
+                filesystem: SnapshotFilesystem::Btrfs,
+                mount_point: PathBuf::from("/"),
+                source: "/dev/sda1".into(),
+                paths: vec![PathBuf::from("/etc")],
+                name: Some("nix-installer-1".into()),
+            }],
+            absent: vec![PathBuf::from("/nix-installer-test-absent")],
+        };
+        let touched = [
+            "/etc/bashrc",
+            "/etc/nix",
+            "/etc/nix/nix.conf",
+            "/etc",
+            "/nix-installer-test-absent",
+            "/nix-installer-test-absent/store",
+            "/usr/local/bin/nix",
+        ]
+        .map(PathBuf::from);
+        assert_eq!(
+            snapshots.rollback_paths(&touched),
+            [
+                "/etc/bashrc",
+                "/etc/nix",
+                "/nix-installer-test-absent/store"
+            ]
+            .map(PathBuf::from)
+        );
+    }
+
+    #[test]
+    fn restoring_removes_what_the_snapshot_lacks() -> eyre::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let live = temp_dir.path().join("nix.conf");
+        std::fs::write(&live, "")?;
+        restore_tree(&temp_dir.path().join("snapshot").join("nix.conf"), &live)?;
+        assert!(!live.exists());
+        Ok(())
+    }
+}
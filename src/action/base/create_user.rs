@@ -4,9 +4,10 @@ use tokio::process::Command;
 use tracing::{span, Span};
 
 use crate::action::{ActionError, ActionErrorKind, ActionTag};
+use crate::audit::{self, AuditEvent};
 use crate::execute_command;
 
-use crate::action::{Action, ActionDescription, StatefulAction};
+use crate::action::{Action, ActionDescription, Artifact, DryRunStep, StatefulAction};
 
 /**
 Create an operating system level user in the given group
@@ -80,6 +81,97 @@ impl CreateUser {
 
         Ok(StatefulAction::uncompleted(this))
     }
+
+    /// The commands which create the user on this host
+    fn commands(&self) -> Result<Vec<Command>, ActionErrorKind> {
+        let Self {
+            name,
+            uid,
+            groupname,
+            gid,
+            comment,
+        } = self;
+
+        let mut commands = match OperatingSystem::host() {
+            OperatingSystem::MacOSX { .. } | OperatingSystem::Darwin => {
+                create_user_macos_commands(name, *uid, *gid)
+            },
+            _ => {
+                if which::which("useradd").is_ok() {
+                    let mut command = Command::new("useradd");
+                    command.args([
+                        "--home-dir",
+                        "/var/empty",
+                        "--comment",
+                        comment,
+                        "--gid",
+                        &gid.to_string(),
+                        "--groups",
+                        &gid.to_string(),
+                        "--no-user-group",
+                        "--system",
+                        "--shell",
+                        "/sbin/nologin",
+                        "--uid",
+                        &uid.to_string(),
+                        "--password",
+                        "!",
+                        name,
+                    ]);
+                    vec![command]
+                } else if which::which("adduser").is_ok() {
+                    let mut command = Command::new("adduser");
+                    command.args([
+                        "--home",
+                        "/var/empty",
+                        "-H", // Don't create a home.
+                        "--gecos",
+                        comment,
+                        "--ingroup",
+                        groupname,
+                        "--system",
+                        "--shell",
+                        "/sbin/nologin",
+                        "--uid",
+                        &uid.to_string(),
+                        "--disabled-password",
+                        name,
+                    ]);
+                    vec![command]
+                } else {
+                    return Err(ActionErrorKind::MissingUserCreationCommand);
+                }
+            },
+        };
+        for command in commands.iter_mut() {
+            command.process_group(0).stdin(std::process::Stdio::null());
+        }
+        Ok(commands)
+    }
+
+    /// The command deleting the user
+    fn revert_command(&self) -> Result<Command, ActionErrorKind> {
+        let mut command = match OperatingSystem::host() {
+            OperatingSystem::MacOSX { .. } | OperatingSystem::Darwin => {
+                let mut command = Command::new("/usr/bin/dscl");
+                command.args([".", "-delete", &format!("/Users/{}", self.name)]);
+                command
+            },
+            _ => {
+                let mut command = if which::which("userdel").is_ok() {
+                    Command::new("userdel")
+                } else if which::which("deluser").is_ok() {
+                    Command::new("deluser")
+                } else {
+                    return Err(ActionErrorKind::MissingUserDeletionCommand);
+                };
+                command.arg(&self.name);
+                command
+            },
+        };
+        command.process_group(0).stdin(std::process::Stdio::null());
+        Ok(command)
+    }
 }
 
 #[async_trait::async_trait]
@@ -117,81 +209,34 @@ impl Action for CreateUser {
 
     #[tracing::instrument(level = "debug", skip_all)]
     async fn execute(&mut self) -> Result<(), ActionError> {
-        let Self {
-            name,
-            uid,
-            groupname,
-            gid,
-            comment,
-        } = self;
-
-        match OperatingSystem::host() {
-            OperatingSystem::MacOSX { .. } | OperatingSystem::Darwin => {
-                create_user_macos(name, *uid, *gid)
-                    .await
-                    .map_err(Self::error)?;
-            },
-            _ => {
-                if which::which("useradd").is_ok() {
-                    execute_command(
-                        Command::new("useradd")
-                            .process_group(0)
-                            .args([
-                                "--home-dir",
-                                "/var/empty",
-                                "--comment",
-                                comment,
-                                "--gid",
-                                &gid.to_string(),
-                                "--groups",
-                                &gid.to_string(),
-                                "--no-user-group",
-                                "--system",
-                                "--shell",
-                                "/sbin/nologin",
-                                "--uid",
-                                &uid.to_string(),
-                                "--password",
-                                "!",
-                                name,
-                            ])
-                            .stdin(std::process::Stdio::null()),
-                    )
-                    .await
-                    .map_err(Self::error)?;
-                } else if which::which("adduser").is_ok() {
-                    execute_command(
-                        Command::new("adduser")
-                            .process_group(0)
-                            .args([
-                                "--home",
-                                "/var/empty",
-                                "-H", // Don't create a home.
-                                "--gecos",
-                                comment,
-                                "--ingroup",
-                                groupname,
-                                "--system",
-                                "--shell",
-                                "/sbin/nologin",
-                                "--uid",
-                                &uid.to_string(),
-                                "--disabled-password",
-                                name,
-                            ])
-                            .stdin(std::process::Stdio::null()),
-                    )
-                    .await
-                    .map_err(Self::error)?;
-                } else {
-                    return Err(Self::error(ActionErrorKind::MissingUserCreationCommand));
-                }
-            },
+        for mut command in self.commands().map_err(Self::error)? {
+            execute_command(&mut command).await.map_err(Self::error)?;
         }
 
+        audit::record(AuditEvent::UserCreated {
+            name: self.name.clone(),
+            uid: self.uid,
+        });
         Ok(())
     }
 
+    fn dry_run(&self) -> Vec<DryRunStep> {
+        match self.commands() {
+            Ok(commands) => commands.iter().map(DryRunStep::command).collect(),
+            Err(err) => vec![DryRunStep::Other(format!(
+                "{} (could not be rendered: {err})",
+                self.tracing_synopsis()
+            ))],
+        }
+    }
+
+    fn artifacts(&self) -> Vec<Artifact> {
+        vec![Artifact::User {
+            name: self.name.clone(),
+            uid: self.uid,
+        }]
+    }
+
     fn revert_description(&self) -> Vec<ActionDescription> {
         vec![ActionDescription::new(
             format!(
@@ -204,6 +249,16 @@ impl Action for CreateUser {
         )]
     }
 
+    fn revert_dry_run(&self) -> Vec<DryRunStep> {
+        match self.revert_command() {
+            Ok(command) => vec![DryRunStep::command(&command)],
+            Err(err) => vec![DryRunStep::Other(format!(
+                "Delete user `{}` (could not be rendered: {err})",
+                self.name
+            ))],
+        }
+    }
+
     #[tracing::instrument(level = "debug", skip_all)]
     async fn revert(&mut self) -> Result<(), ActionError> {
         match OperatingSystem::host() {
@@ -211,104 +266,37 @@ impl Action for CreateUser {
                 delete_user_macos(&self.name).await.map_err(Self::error)?;
             },
             _ => {
-                if which::which("userdel").is_ok() {
-                    execute_command(
-                        Command::new("userdel")
-                            .process_group(0)
-                            .arg(&self.name)
-                            .stdin(std::process::Stdio::null()),
-                    )
-                    .await
-                    .map_err(Self::error)?;
-                } else if which::which("deluser").is_ok() {
-                    execute_command(
-                        Command::new("deluser")
-                            .process_group(0)
-                            .arg(&self.name)
-                            .stdin(std::process::Stdio::null()),
-                    )
-                    .await
-                    .map_err(Self::error)?;
-                } else {
-                    return Err(Self::error(ActionErrorKind::MissingUserDeletionCommand));
-                }
+                let mut command = self.revert_command().map_err(Self::error)?;
+                execute_command(&mut command).await.map_err(Self::error)?;
             },
         };
 
+        audit::record(AuditEvent::UserDeleted {
+            name: self.name.clone(),
+        });
         Ok(())
     }
 }
 
-#[tracing::instrument(level = "debug", skip_all)]
-async fn create_user_macos(name: &str, uid: u32, gid: u32) -> Result<(), ActionErrorKind> {
-    execute_command(
-        Command::new("/usr/bin/dscl")
-            .process_group(0)
-            .args([".", "-create", &format!("/Users/{name}")])
-            .stdin(std::process::Stdio::null()),
-    )
-    .await?;
-    execute_command(
-        Command::new("/usr/bin/dscl")
-            .process_group(0)
-            .args([
-                ".",
-                "-create",
-                &format!("/Users/{name}"),
-                "UniqueID",
-                &format!("{uid}"),
-            ])
-            .stdin(std::process::Stdio::null()),
-    )
-    .await?;
-    execute_command(
-        Command::new("/usr/bin/dscl")
-            .process_group(0)
-            .args([
-                ".",
-                "-create",
-                &format!("/Users/{name}"),
-                "PrimaryGroupID",
-                &format!("{gid}"),
-            ])
-            .stdin(std::process::Stdio::null()),
-    )
-    .await?;
-    execute_command(
-        Command::new("/usr/bin/dscl")
-            .process_group(0)
-            .args([
-                ".",
-                "-create",
-                &format!("/Users/{name}"),
-                "NFSHomeDirectory",
-                "/var/empty",
-            ])
-            .stdin(std::process::Stdio::null()),
-    )
-    .await?;
-    execute_command(
-        Command::new("/usr/bin/dscl")
-            .process_group(0)
-            .args([
-                ".",
-                "-create",
-                &format!("/Users/{name}"),
-                "UserShell",
-                "/sbin/nologin",
-            ])
-            .stdin(std::process::Stdio::null()),
-    )
-    .await?;
-    execute_command(
-        Command::new("/usr/bin/dscl")
-            .process_group(0)
-            .args([".", "-create", &format!("/Users/{name}"), "IsHidden", "1"])
-            .stdin(std::process::Stdio::null()),
-    )
-    .await?;
-
-    Ok(())
+fn create_user_macos_commands(name: &str, uid: u32, gid: u32) -> Vec<Command> {
+    let user = format!("/Users/{name}");
+    let (uid, gid) = (uid.to_string(), gid.to_string());
+    let user = user.as_str();
+    [
+        vec![".", "-create", user],
+        vec![".", "-create", user, "UniqueID", uid.as_str()],
+        vec![".", "-create", user, "PrimaryGroupID", gid.as_str()],
+        vec![".", "-create", user, "NFSHomeDirectory", "/var/empty"],
+        vec![".", "-create", user, "UserShell", "/sbin/nologin"],
+        vec![".", "-create", user, "IsHidden", "1"],
+    ]
+    .into_iter()
+    .map(|args| {
+        let mut command = Command::new("/usr/bin/dscl");
+        command.args(args);
+        command
+    })
+    .collect()
 }
 
 #[tracing::instrument(level = "debug", skip_all)]
@@ -5,6 +5,7 @@ use tracing::{span, Span};
 
 use crate::action::base::create_user::delete_user_macos;
 use crate::action::{ActionError, ActionErrorKind, ActionTag};
+use crate::audit::{self, AuditEvent};
 use crate::execute_command;
 
 use crate::action::{Action, ActionDescription, StatefulAction};
@@ -103,6 +104,9 @@ impl Action for DeleteUser {
             },
         };
 
+        audit::record(AuditEvent::UserDeleted {
+            name: self.name.clone(),
+        });
         Ok(())
     }
 
@@ -1,13 +1,28 @@
-use std::path::PathBuf;
+use std::{
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
 
 use bytes::{Buf, Bytes};
-use reqwest::Url;
+use reqwest::{
+    dns::{Addrs, Name, Resolve, Resolving},
+    header::{HeaderValue, ETAG, IF_RANGE, RANGE},
+    StatusCode, Url,
+};
+use sha2::{Digest, Sha256};
 use tracing::{span, Span};
 
 use crate::{
-    action::{Action, ActionDescription, ActionError, ActionErrorKind, ActionTag, StatefulAction},
+    action::{
+        Action, ActionDescription, ActionError, ActionErrorKind, ActionTag, Artifact,
+        StatefulAction,
+    },
     parse_ssl_cert,
-    settings::UrlOrPath,
+    progress::{self, ProgressEvent, UnpackProgress},
+    settings::{IpVersion, UrlOrPath},
+    unprivileged,
 };
 
 /**
@@ -18,21 +33,68 @@ Fetch a URL to the given path
 pub struct FetchAndUnpackNix {
     url_or_path: Option<UrlOrPath>,
     dest: PathBuf,
-    proxy: Option<Url>,
+    #[serde(serialize_with = "crate::settings::serialize_url_without_password")]
+    pub(crate) proxy: Option<Url>,
     ssl_cert_file: Option<PathBuf>,
+    /// Fallback URLs, tried in order if `url_or_path` cannot be fetched
+    #[serde(default)]
+    mirrors: Vec<Url>,
+    #[serde(default)]
+    download_policy: DownloadPolicy,
+    /// Checks the tarball must pass before it is unpacked
+    ///
+    /// For local tarballs, the SHA-256 is always recorded during planning.
+    #[serde(default)]
+    verification: NixPackageVerification,
+}
+
+/// How a Nix package download copes with flaky networks
+#[derive(Debug, Default, serde::Deserialize, serde::Serialize, Clone, PartialEq, Eq)]
+pub struct DownloadPolicy {
+    /// How many times to retry (resuming where possible) an interrupted download
+    pub retries: u32,
+    /// How many seconds a connection or read may stall before it is considered failed
+    pub timeout: Option<u64>,
+    /// Which IP versions connections may use
+    #[serde(default)]
+    pub ip_version: IpVersion,
+}
+
+/// How a Nix package is verified before it is unpacked
+#[derive(Debug, Default, serde::Deserialize, serde::Serialize, Clone, PartialEq, Eq)]
+pub struct NixPackageVerification {
+    /// The expected SHA-256 of the tarball, hex encoded
+    pub sha256: Option<String>,
+    /// A detached [minisign](https://jedisct1.github.io/minisign/) signature of the tarball
+    pub signature: Option<UrlOrPath>,
+    /// The base64 encoded minisign public key the signature must be made with
+    pub public_key: Option<String>,
 }
 
 impl FetchAndUnpackNix {
     #[tracing::instrument(level = "debug", skip_all)]
     pub async fn plan(
         url_or_path: Option<UrlOrPath>,
+        mirrors: Vec<Url>,
         dest: PathBuf,
         proxy: Option<Url>,
         ssl_cert_file: Option<PathBuf>,
+        download_policy: DownloadPolicy,
+        mut verification: NixPackageVerification,
     ) -> Result<StatefulAction<Self>, ActionError> {
         // TODO(@hoverbear): Check URL exists?
         // TODO(@hoverbear): Check tempdir exists
 
+        if url_or_path.is_none() && crate::settings::NIX_TARBALL.is_none() {
+            return Err(Self::error(FetchUrlError::NoEmbeddedTarball));
+        }
+
+        let (url_or_path, mirrors) = match (url_or_path, mirrors.split_first()) {
+            // Without a primary URL, the first mirror takes its place
+            (None, Some((first, rest))) => (Some(UrlOrPath::Url(first.clone())), rest.to_vec()),
+            (url_or_path, _) => (url_or_path, mirrors),
+        };
+
         if let Some(UrlOrPath::Url(url)) = &url_or_path {
             match url.scheme() {
                 "https" | "http" | "file" => (),
@@ -40,9 +102,16 @@ impl FetchAndUnpackNix {
             }
         }
 
+        for mirror in &mirrors {
+            match mirror.scheme() {
+                "https" | "http" | "file" => (),
+                _ => return Err(Self::error(ActionErrorKind::UnknownUrlScheme)),
+            }
+        }
+
         if let Some(proxy) = &proxy {
             match proxy.scheme() {
-                "https" | "http" | "socks5" => (),
+                "https" | "http" | "socks5" | "socks5h" => (),
                 _ => return Err(Self::error(FetchUrlError::UnknownProxyScheme)),
             };
         }
@@ -51,16 +120,381 @@ impl FetchAndUnpackNix {
             parse_ssl_cert(ssl_cert_file).await.map_err(Self::error)?;
         }
 
+        if let Some(sha256) = &mut verification.sha256 {
+            *sha256 = sha256.to_ascii_lowercase();
+            if sha256.len() != 64 || !sha256.chars().all(|c| c.is_ascii_hexdigit()) {
+                return Err(Self::error(FetchUrlError::InvalidSha256(sha256.clone())));
+            }
+        }
+
+        match (&verification.signature, &verification.public_key) {
+            (Some(_), Some(public_key)) => {
+                minisign_verify::PublicKey::from_base64(public_key)
+                    .map_err(FetchUrlError::Minisign)
+                    .map_err(Self::error)?;
+            },
+            (None, None) => (),
+            (Some(_), None) | (None, Some(_)) => {
+                return Err(Self::error(FetchUrlError::SignatureRequiresPublicKey))
+            },
+        }
+
+        // Local tarballs are hashed now so the receipt records exactly what was installed
+        if let Some(path) = local_path(url_or_path.as_ref()) {
+            let actual = sha256_file(&path)
+                .await
+                .map_err(|e| ActionErrorKind::Read(path.clone(), e))
+                .map_err(Self::error)?;
+            match &verification.sha256 {
+                Some(expected) if *expected != actual => {
+                    return Err(Self::error(FetchUrlError::HashMismatch {
+                        expected: expected.clone(),
+                        actual,
+                    }))
+                },
+                _ => verification.sha256 = Some(actual),
+            }
+        }
+
         Ok(Self {
             url_or_path,
+            mirrors,
             dest,
             proxy,
             ssl_cert_file,
+            download_policy,
+            verification,
         }
         .into())
     }
 }
 
+impl FetchAndUnpackNix {
+    async fn client(&self) -> Result<reqwest::Client, ActionErrorKind> {
+        let mut buildable_client = reqwest::Client::builder();
+        if let Some(proxy) = &self.proxy {
+            // Credentials in the URL are used for `Proxy-Authorization`
+            let proxy = reqwest::Proxy::all(crate::settings::proxy_with_password(proxy))
+                .map_err(ActionErrorKind::Reqwest)?
+                .no_proxy(reqwest::NoProxy::from_env());
+            buildable_client = buildable_client.proxy(proxy)
+        }
+        if let Some(ssl_cert_file) = &self.ssl_cert_file {
+            let ssl_cert = parse_ssl_cert(ssl_cert_file).await?;
+            buildable_client = buildable_client.add_root_certificate(ssl_cert);
+        }
+        if let Some(download_timeout) = self.download_policy.timeout {
+            // Applied per-read rather than to the whole request so large, slow downloads aren't cut off
+            let download_timeout = Duration::from_secs(download_timeout);
+            buildable_client = buildable_client
+                .connect_timeout(download_timeout)
+                .read_timeout(download_timeout);
+        }
+        match self.download_policy.ip_version {
+            // The connector already races IPv6 and IPv4 addresses ("happy eyeballs"), with the connect
+            // timeout above bounding how long an unreachable family can stall things
+            IpVersion::Auto => (),
+            ip_version => {
+                buildable_client =
+                    buildable_client.dns_resolver(Arc::new(IpVersionResolver(ip_version)))
+            },
+        }
+        buildable_client.build().map_err(ActionErrorKind::Reqwest)
+    }
+
+    /// Fetch, verify, and unpack the Nix package in this process, returning its SHA-256
+    pub(crate) async fn fetch_and_unpack(&self) -> Result<String, ActionErrorKind> {
+        let bytes = match &self.url_or_path {
+            &None => crate::settings::NIX_TARBALL
+                .map(Bytes::from)
+                .ok_or(FetchUrlError::NoEmbeddedTarball)?,
+            Some(UrlOrPath::Url(url)) => {
+                let mut errors = vec![];
+                let mut fetched = None;
+                for candidate in std::iter::once(url).chain(self.mirrors.iter()) {
+                    match self.fetch_url(candidate).await {
+                        Ok(bytes) => {
+                            fetched = Some(bytes);
+                            break;
+                        },
+                        Err(err) => {
+                            tracing::warn!(url = %candidate, "Failed to fetch Nix, trying the next mirror: {err}");
+                            errors.push(err);
+                        },
+                    }
+                }
+                match fetched {
+                    Some(bytes) => bytes,
+                    None if errors.len() == 1 => {
+                        return Err(errors
+                            .into_iter()
+                            .next()
+                            .expect("Expected 1 len Vec to have at least 1 item"))
+                    },
+                    None => return Err(ActionErrorKind::Multiple(errors)),
+                }
+            },
+            Some(UrlOrPath::Path(path)) => {
+                let buf = tokio::fs::read(path)
+                    .await
+                    .map_err(|e| ActionErrorKind::Read(PathBuf::from(path), e))?;
+                Bytes::from(buf)
+            },
+        };
+
+        self.verify(&bytes).await?;
+        let sha256 = sha256_hex(&bytes);
+
+        // TODO(@Hoverbear): Pick directory
+        tracing::trace!("Unpacking tar.xz");
+        let dest_clone = self.dest.clone();
+
+        let total = bytes.len() as u64;
+        let decoder = xz2::read::XzDecoder::new(UnpackProgress::new(bytes.reader(), total));
+        let mut archive = tar::Archive::new(decoder);
+        archive.set_preserve_permissions(true);
+        archive.set_preserve_mtime(true);
+        archive.set_unpack_xattrs(true);
+        archive
+            .unpack(&dest_clone)
+            .map_err(FetchUrlError::Unarchive)?;
+
+        Ok(sha256)
+    }
+
+    /// Fetch, verify, and unpack the Nix package in `helper` run as [`UNPRIVILEGED_USER`](unprivileged::UNPRIVILEGED_USER), then move what it unpacked into place
+    ///
+    /// Local files may only be readable by root, so the helper is given copies of them (and of itself).
+    #[tracing::instrument(level = "debug", skip_all, fields(helper = %helper.display()))]
+    async fn fetch_and_unpack_unprivileged(
+        &self,
+        helper: &Path,
+    ) -> Result<String, ActionErrorKind> {
+        let user = unprivileged::user()?;
+        let scratch = unprivileged::scratch_dir(&self.dest, &user).await?;
+
+        let res = async {
+            let helper = unprivileged::stage(helper, &scratch, "nix-installer", &user).await?;
+            let mut request = self.clone();
+            request.dest = scratch.join("unpacked");
+            if let Some(path) = local_path(self.url_or_path.as_ref()) {
+                let staged = unprivileged::stage(&path, &scratch, "nix.tar.xz", &user).await?;
+                request.url_or_path = Some(UrlOrPath::Path(staged));
+            }
+            if let Some(UrlOrPath::Path(path)) = &self.verification.signature {
+                let staged =
+                    unprivileged::stage(path, &scratch, "nix.tar.xz.minisig", &user).await?;
+                request.verification.signature = Some(UrlOrPath::Path(staged));
+            }
+            if let Some(ssl_cert_file) = &self.ssl_cert_file {
+                let staged =
+                    unprivileged::stage(ssl_cert_file, &scratch, "ssl-cert", &user).await?;
+                request.ssl_cert_file = Some(staged);
+            }
+
+            let sha256 = unprivileged::run(&helper, &request, &scratch, &user).await?;
+            unprivileged::take_ownership(&scratch, &request.dest).await?;
+            unprivileged::place(&request.dest, &self.dest).await?;
+            Ok::<_, ActionErrorKind>(sha256)
+        }
+        .await;
+
+        if let Err(err) = tokio::fs::remove_dir_all(&scratch).await {
+            tracing::warn!(%err, "Could not remove `{}`", scratch.display());
+        }
+        res
+    }
+
+    /// Refuse a tarball which doesn't match the expected SHA-256 or signature
+    async fn verify(&self, bytes: &[u8]) -> Result<(), ActionErrorKind> {
+        let NixPackageVerification {
+            sha256,
+            signature,
+            public_key,
+        } = &self.verification;
+
+        if let Some(expected) = sha256 {
+            let actual = sha256_hex(bytes);
+            if *expected != actual {
+                return Err(FetchUrlError::HashMismatch {
+                    expected: expected.clone(),
+                    actual,
+                }
+                .into());
+            }
+        }
+
+        if let (Some(signature), Some(public_key)) = (signature, public_key) {
+            let signature = match signature {
+                UrlOrPath::Url(url) => self.fetch_url(url).await?.to_vec(),
+                UrlOrPath::Path(path) => tokio::fs::read(path)
+                    .await
+                    .map_err(|e| ActionErrorKind::Read(path.clone(), e))?,
+            };
+            let signature = minisign_verify::Signature::decode(&String::from_utf8(signature)?)
+                .map_err(FetchUrlError::Minisign)?;
+            minisign_verify::PublicKey::from_base64(public_key)
+                .and_then(|public_key| public_key.verify(bytes, &signature, false))
+                .map_err(FetchUrlError::Minisign)?;
+            tracing::debug!(
+                trusted_comment = signature.trusted_comment(),
+                "Verified Nix package signature"
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Fetch a single URL, checking a mirror is healthy (via a `HEAD` request) before downloading from it
+    #[tracing::instrument(level = "debug", skip_all, fields(url = %url))]
+    async fn fetch_url(&self, url: &Url) -> Result<Bytes, ActionErrorKind> {
+        match url.scheme() {
+            "https" | "http" => {
+                let client = self.client().await?;
+                let head = client
+                    .head(url.clone())
+                    .send()
+                    .await
+                    .and_then(|res| res.error_for_status())
+                    .map_err(ActionErrorKind::Reqwest)?;
+                let etag = head.headers().get(reqwest::header::ETAG).cloned();
+
+                let mut buf = Vec::new();
+                let mut attempt = 0;
+                loop {
+                    match download(&client, url, etag.as_ref(), &mut buf).await {
+                        Ok(()) => break,
+                        Err(err) if attempt < self.download_policy.retries => {
+                            attempt += 1;
+                            let backoff = Duration::from_secs(1 << attempt.min(6));
+                            tracing::warn!(
+                                "Download interrupted after {} bytes, retrying in {}s (attempt {attempt} of {}): {err}",
+                                buf.len(),
+                                backoff.as_secs(),
+                                self.download_policy.retries,
+                            );
+                            tokio::time::sleep(backoff).await;
+                        },
+                        Err(err) => return Err(err),
+                    }
+                }
+                Ok(Bytes::from(buf))
+            },
+            "file" => {
+                let buf = tokio::fs::read(url.path())
+                    .await
+                    .map_err(|e| ActionErrorKind::Read(PathBuf::from(url.path()), e))?;
+                Ok(Bytes::from(buf))
+            },
+            _ => Err(ActionErrorKind::UnknownUrlScheme),
+        }
+    }
+}
+
+/// Download `url` into `buf`, resuming from the end of `buf` with a range request if it isn't empty
+async fn download(
+    client: &reqwest::Client,
+    url: &Url,
+    etag: Option<&HeaderValue>,
+    buf: &mut Vec<u8>,
+) -> Result<(), ActionErrorKind> {
+    let mut req = client.get(url.clone());
+    if !buf.is_empty() {
+        req = req.header(RANGE, format!("bytes={}-", buf.len()));
+        if let Some(etag) = etag {
+            // The server will send the whole file if it changed since we started
+            req = req.header(IF_RANGE, etag.clone());
+        }
+    }
+    let mut res = req
+        .send()
+        .await
+        .and_then(|res| res.error_for_status())
+        .map_err(ActionErrorKind::Reqwest)?;
+
+    if res.status() != StatusCode::PARTIAL_CONTENT {
+        buf.clear();
+    }
+    // A mirror which changed the file between the requests is mid-sync
+    if let (Some(etag), Some(get_etag)) = (etag, res.headers().get(ETAG)) {
+        if etag != get_etag {
+            buf.clear();
+            return Err(FetchUrlError::EtagChanged(url.clone()).into());
+        }
+    }
+
+    // A partial response's length is only what remains
+    let total = res
+        .content_length()
+        .map(|remaining| remaining + buf.len() as u64);
+    while let Some(chunk) = res.chunk().await.map_err(ActionErrorKind::Reqwest)? {
+        buf.extend_from_slice(&chunk);
+        progress::report(ProgressEvent::Downloaded {
+            bytes: buf.len() as u64,
+            total,
+        });
+    }
+    Ok(())
+}
+
+/// Resolves hostnames with the system resolver, keeping only addresses of one IP version
+struct IpVersionResolver(IpVersion);
+
+impl Resolve for IpVersionResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let ip_version = self.0;
+        Box::pin(async move {
+            let host = name.as_str().to_string();
+            let addrs = tokio::net::lookup_host((host.as_str(), 0))
+                .await?
+                .filter(|addr| ip_version_allows(ip_version, addr))
+                .collect::<Vec<_>>();
+            if addrs.is_empty() {
+                return Err(FetchUrlError::NoAddresses { host, ip_version }.into());
+            }
+            let addrs: Addrs = Box::new(addrs.into_iter());
+            Ok(addrs)
+        })
+    }
+}
+
+fn ip_version_allows(ip_version: IpVersion, addr: &SocketAddr) -> bool {
+    match ip_version {
+        IpVersion::Auto => true,
+        IpVersion::V4 => addr.is_ipv4(),
+        IpVersion::V6 => addr.is_ipv6(),
+    }
+}
+
+fn local_path(url_or_path: Option<&UrlOrPath>) -> Option<PathBuf> {
+    match url_or_path {
+        Some(UrlOrPath::Path(path)) => Some(path.clone()),
+        Some(UrlOrPath::Url(url)) if url.scheme() == "file" => Some(PathBuf::from(url.path())),
+        _ => None,
+    }
+}
+
+pub(crate) fn sha256_hex(buf: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(buf))
+}
+
+/// Like [`sha256_hex`], of the file at `path`, read in chunks rather than all at once
+async fn sha256_file(path: &Path) -> std::io::Result<String> {
+    use tokio::io::AsyncReadExt;
+
+    let mut file = tokio::fs::File::open(path).await?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0; 64 * 1024];
+    loop {
+        let read = file.read(&mut buf).await?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buf[..read]);
+    }
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
 #[async_trait::async_trait]
 #[typetag::serde(name = "fetch_and_unpack_nix")]
 impl Action for FetchAndUnpackNix {
@@ -73,7 +507,7 @@ impl Action for FetchAndUnpackNix {
         } else {
             format!(
                 "Extract the bundled Nix (originally from {})",
-                crate::settings::NIX_TARBALL_PATH
+                crate::settings::NIX_TARBALL_PATH.unwrap_or("an unknown location")
             )
         }
     }
@@ -86,9 +520,17 @@ impl Action for FetchAndUnpackNix {
             proxy = tracing::field::Empty,
             ssl_cert_file = tracing::field::Empty,
             dest = tracing::field::display(self.dest.display()),
+            sha256 = self
+                .verification
+                .sha256
+                .as_ref()
+                .map(tracing::field::display),
         );
         if let Some(proxy) = &self.proxy {
-            span.record("proxy", tracing::field::display(&proxy));
+            span.record(
+                "proxy",
+                tracing::field::display(crate::settings::redact_url_password(proxy)),
+            );
         }
         if let Some(ssl_cert_file) = &self.ssl_cert_file {
             span.record(
@@ -100,81 +542,42 @@ impl Action for FetchAndUnpackNix {
     }
 
     fn execute_description(&self) -> Vec<ActionDescription> {
-        vec![ActionDescription::new(self.tracing_synopsis(), vec![])]
+        let mut explanation = vec![];
+        for mirror in &self.mirrors {
+            explanation.push(format!("Falling back to `{mirror}` if unreachable"));
+        }
+        if let Some(sha256) = &self.verification.sha256 {
+            explanation.push(format!(
+                "The tarball is expected to have a SHA-256 of `{sha256}`"
+            ));
+        }
+        vec![ActionDescription::new(self.tracing_synopsis(), explanation)]
     }
 
-    #[tracing::instrument(level = "debug", skip_all)]
-    async fn execute(&mut self) -> Result<(), ActionError> {
-        let bytes = match &self.url_or_path {
-            &None => Bytes::from(crate::settings::NIX_TARBALL),
-            Some(UrlOrPath::Url(url)) => {
-                let bytes = match url.scheme() {
-                    "https" | "http" => {
-                        let mut buildable_client = reqwest::Client::builder();
-                        if let Some(proxy) = &self.proxy {
-                            buildable_client = buildable_client.proxy(
-                                reqwest::Proxy::all(proxy.clone())
-                                    .map_err(ActionErrorKind::Reqwest)
-                                    .map_err(Self::error)?,
-                            )
-                        }
-                        if let Some(ssl_cert_file) = &self.ssl_cert_file {
-                            let ssl_cert =
-                                parse_ssl_cert(ssl_cert_file).await.map_err(Self::error)?;
-                            buildable_client = buildable_client.add_root_certificate(ssl_cert);
-                        }
-                        let client = buildable_client
-                            .build()
-                            .map_err(ActionErrorKind::Reqwest)
-                            .map_err(Self::error)?;
-                        let req = client
-                            .get(url.clone())
-                            .build()
-                            .map_err(ActionErrorKind::Reqwest)
-                            .map_err(Self::error)?;
-                        let res = client
-                            .execute(req)
-                            .await
-                            .map_err(ActionErrorKind::Reqwest)
-                            .map_err(Self::error)?;
-                        res.bytes()
-                            .await
-                            .map_err(ActionErrorKind::Reqwest)
-                            .map_err(Self::error)?
-                    },
-                    "file" => {
-                        let buf = tokio::fs::read(url.path())
-                            .await
-                            .map_err(|e| ActionErrorKind::Read(PathBuf::from(url.path()), e))
-                            .map_err(Self::error)?;
-                        Bytes::from(buf)
-                    },
-                    _ => return Err(Self::error(ActionErrorKind::UnknownUrlScheme)),
-                };
-                bytes
-            },
-            Some(UrlOrPath::Path(path)) => {
-                let buf = tokio::fs::read(path)
-                    .await
-                    .map_err(|e| ActionErrorKind::Read(PathBuf::from(path), e))
-                    .map_err(Self::error)?;
-                Bytes::from(buf)
-            },
+    fn artifacts(&self) -> Vec<Artifact> {
+        let source = match &self.url_or_path {
+            Some(UrlOrPath::Url(url)) => crate::settings::redact_url_password(url).to_string(),
+            Some(UrlOrPath::Path(path)) => path.display().to_string(),
+            None => format!(
+                "bundled, originally from {}",
+                crate::settings::NIX_TARBALL_PATH.unwrap_or("an unknown location")
+            ),
         };
+        vec![Artifact::NixPackage {
+            source,
+            sha256: self.verification.sha256.clone(),
+        }]
+    }
 
-        // TODO(@Hoverbear): Pick directory
-        tracing::trace!("Unpacking tar.xz");
-        let dest_clone = self.dest.clone();
-
-        let decoder = xz2::read::XzDecoder::new(bytes.reader());
-        let mut archive = tar::Archive::new(decoder);
-        archive.set_preserve_permissions(true);
-        archive.set_preserve_mtime(true);
-        archive.set_unpack_xattrs(true);
-        archive
-            .unpack(&dest_clone)
-            .map_err(FetchUrlError::Unarchive)
-            .map_err(Self::error)?;
+    #[tracing::instrument(level = "debug", skip_all)]
+    async fn execute(&mut self) -> Result<(), ActionError> {
+        let sha256 = match unprivileged::helper() {
+            Some(helper) => self.fetch_and_unpack_unprivileged(&helper).await,
+            None => self.fetch_and_unpack().await,
+        }
+        .map_err(Self::error)?;
+        // Kept in the receipt, so the manifest lists exactly what was unpacked
+        self.verification.sha256.get_or_insert(sha256);
 
         Ok(())
     }
@@ -194,8 +597,24 @@ impl Action for FetchAndUnpackNix {
 pub enum FetchUrlError {
     #[error("Unarchiving error")]
     Unarchive(#[source] std::io::Error),
-    #[error("Unknown proxy scheme, `https://`, `socks5://`, and `http://` supported")]
+    #[error(
+        "Unknown proxy scheme, `https://`, `socks5://`, `socks5h://`, and `http://` supported"
+    )]
     UnknownProxyScheme,
+    #[error("The Nix package has a SHA-256 of `{actual}`, but `{expected}` was expected; it may have changed since the plan was created")]
+    HashMismatch { expected: String, actual: String },
+    #[error("The ETag of `{0}` changed while downloading, the mirror may be mid-sync")]
+    EtagChanged(Url),
+    #[error("`{0}` is not a valid SHA-256, expected 64 hexadecimal characters")]
+    InvalidSha256(String),
+    #[error("Verifying a Nix package signature requires both a signature and a public key")]
+    SignatureRequiresPublicKey,
+    #[error("Verifying the Nix package signature")]
+    Minisign(#[source] minisign_verify::Error),
+    #[error("This `nix-installer` was built without an embedded Nix tarball, pass `--nix-package-url` or `--nix-package-file`")]
+    NoEmbeddedTarball,
+    #[error("`{host}` has no addresses usable with `--ip-version {ip_version}`")]
+    NoAddresses { host: String, ip_version: IpVersion },
 }
 
 impl From<FetchUrlError> for ActionErrorKind {
@@ -203,3 +622,253 @@ impl From<FetchUrlError> for ActionErrorKind {
         ActionErrorKind::Custom(Box::new(val))
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Write;
+
+    fn write_tarball(path: &std::path::Path, contents: &[u8]) -> std::io::Result<()> {
+        let file = std::fs::File::create(path)?;
+        let encoder = xz2::write::XzEncoder::new(file, 6);
+        let mut builder = tar::Builder::new(encoder);
+        let mut header = tar::Header::new_gnu();
+        header.set_size(contents.len() as u64);
+        header.set_mode(0o644);
+        header.set_cksum();
+        builder.append_data(&mut header, "nix-test/boop", contents)?;
+        builder.into_inner()?.finish()?.flush()
+    }
+
+    #[tokio::test]
+    async fn unpacks_local_file_with_recorded_hash() -> eyre::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let tarball = temp_dir.path().join("nix.tar.xz");
+        let dest = temp_dir.path().join("dest");
+        write_tarball(&tarball, b"bleat")?;
+
+        let mut action = FetchAndUnpackNix::plan(
+            Some(UrlOrPath::Path(tarball.clone())),
+            vec![],
+            dest.clone(),
+            None,
+            None,
+            Default::default(),
+            Default::default(),
+        )
+        .await?;
+        assert_eq!(
+            action.inner().verification.sha256.as_deref(),
+            Some(sha256_hex(&std::fs::read(&tarball)?).as_str())
+        );
+
+        action.try_execute().await?;
+        assert_eq!(std::fs::read(dest.join("nix-test/boop"))?, b"bleat");
+
+        Ok(())
+    }
+
+    #[test]
+    fn filters_addresses_by_ip_version() -> eyre::Result<()> {
+        let v4: SocketAddr = "127.0.0.1:0".parse()?;
+        let v6: SocketAddr = "[::1]:0".parse()?;
+        assert!(ip_version_allows(IpVersion::Auto, &v4));
+        assert!(ip_version_allows(IpVersion::Auto, &v6));
+        assert!(ip_version_allows(IpVersion::V4, &v4));
+        assert!(!ip_version_allows(IpVersion::V4, &v6));
+        assert!(!ip_version_allows(IpVersion::V6, &v4));
+        assert!(ip_version_allows(IpVersion::V6, &v6));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn falls_back_to_mirrors() -> eyre::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let tarball = temp_dir.path().join("nix.tar.xz");
+        let dest = temp_dir.path().join("dest");
+        write_tarball(&tarball, b"bleat")?;
+
+        let mut action = FetchAndUnpackNix::plan(
+            // Nothing listens on port 1, so this fails fast without network access
+            Some(UrlOrPath::Url(Url::parse("http://127.0.0.1:1/nix.tar.xz")?)),
+            vec![Url::from_file_path(&tarball).map_err(|()| eyre::eyre!("Bad path"))?],
+            dest.clone(),
+            None,
+            None,
+            Default::default(),
+            Default::default(),
+        )
+        .await?;
+
+        action.try_execute().await?;
+        assert_eq!(std::fs::read(dest.join("nix-test/boop"))?, b"bleat");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn resumes_interrupted_downloads() -> eyre::Result<()> {
+        use std::io::{BufRead, BufReader};
+
+        let temp_dir = tempfile::tempdir()?;
+        let tarball = temp_dir.path().join("nix.tar.xz");
+        let dest = temp_dir.path().join("dest");
+        write_tarball(&tarball, b"bleat")?;
+        let body = std::fs::read(&tarball)?;
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0")?;
+        let url = Url::parse(&format!("http://{}/nix.tar.xz", listener.local_addr()?))?;
+        let server = std::thread::spawn(move || -> std::io::Result<Vec<String>> {
+            let mut requests = vec![];
+            // A `HEAD`, a `GET` which is cut off halfway, then a ranged `GET` for the rest
+            for _ in 0..3 {
+                let (mut stream, _) = listener.accept()?;
+                let mut request = String::new();
+                let mut reader = BufReader::new(stream.try_clone()?);
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line)?;
+                    if line == "\r\n" || line.is_empty() {
+                        break;
+                    }
+                    request.push_str(&line);
+                }
+                let half = body.len() / 2;
+                if request.starts_with("HEAD") {
+                    write!(
+                        stream,
+                        "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                        body.len()
+                    )?;
+                } else if request.contains(&format!("bytes={half}-")) {
+                    write!(
+                        stream,
+                        "HTTP/1.1 206 Partial Content\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                        body.len() - half
+                    )?;
+                    stream.write_all(&body[half..])?;
+                } else {
+                    write!(
+                        stream,
+                        "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                        body.len()
+                    )?;
+                    stream.write_all(&body[..half])?;
+                }
+                requests.push(request);
+            }
+            Ok(requests)
+        });
+
+        let mut action = FetchAndUnpackNix::plan(
+            Some(UrlOrPath::Url(url)),
+            vec![],
+            dest.clone(),
+            None,
+            None,
+            DownloadPolicy {
+                retries: 1,
+                timeout: Some(5),
+                ..Default::default()
+            },
+            Default::default(),
+        )
+        .await?;
+
+        action.try_execute().await?;
+        assert_eq!(std::fs::read(dest.join("nix-test/boop"))?, b"bleat");
+
+        let requests = server
+            .join()
+            .map_err(|_| eyre::eyre!("Server thread panicked"))??;
+        assert!(requests[2].to_lowercase().contains("range: bytes="));
+
+        Ok(())
+    }
+
+    const TEST_PUBLIC_KEY: &str = "RWTsUAqSMlzNq5LfN/ody8ojlhkxRtNBrQFK3gFYNO6s0wh0EfMR1+Jy";
+    // A minisign signature of `bleat` made with the secret key for `TEST_PUBLIC_KEY`
+    const TEST_SIGNATURE: &str = "\
+untrusted comment: signature from minisign secret key
+RUTsUAqSMlzNq2/ovUqryJlAX3Z3lLFlgSz/3OhVsmgRXcsF7AS2ESC1/lTB/V9ub2VGUz4DUAOZ93oJV17j3CT2dCWsD4Urxgk=
+trusted comment: nix-installer test
+gtgOwbvvW0Smp2R7LXXVcFToURTVegDrm2AQoVUqZWDKd0cS36jUXXQJC1wgFFpSMWnDbYHqvsT2yIU+Hd7hBA==
+";
+
+    #[tokio::test]
+    async fn verifies_signatures() -> eyre::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let package = temp_dir.path().join("nix.tar.xz");
+        let signature = temp_dir.path().join("nix.tar.xz.minisig");
+        std::fs::write(&package, b"bleat")?;
+        std::fs::write(&signature, TEST_SIGNATURE)?;
+
+        let action = FetchAndUnpackNix::plan(
+            Some(UrlOrPath::Path(package)),
+            vec![],
+            temp_dir.path().join("dest"),
+            None,
+            None,
+            Default::default(),
+            NixPackageVerification {
+                sha256: None,
+                signature: Some(UrlOrPath::Path(signature)),
+                public_key: Some(TEST_PUBLIC_KEY.into()),
+            },
+        )
+        .await?;
+
+        action.inner().verify(b"bleat").await?;
+        assert!(action.inner().verify(b"boop").await.is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn refuses_mismatched_sha256() -> eyre::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let tarball = temp_dir.path().join("nix.tar.xz");
+        write_tarball(&tarball, b"bleat")?;
+
+        let res = FetchAndUnpackNix::plan(
+            Some(UrlOrPath::Path(tarball)),
+            vec![],
+            temp_dir.path().join("dest"),
+            None,
+            None,
+            Default::default(),
+            NixPackageVerification {
+                sha256: Some(sha256_hex(b"boop")),
+                ..Default::default()
+            },
+        )
+        .await;
+        assert!(res.is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn refuses_local_file_changed_after_planning() -> eyre::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let tarball = temp_dir.path().join("nix.tar.xz");
+        write_tarball(&tarball, b"bleat")?;
+
+        let mut action = FetchAndUnpackNix::plan(
+            Some(UrlOrPath::Path(tarball.clone())),
+            vec![],
+            temp_dir.path().join("dest"),
+            None,
+            None,
+            Default::default(),
+            Default::default(),
+        )
+        .await?;
+
+        write_tarball(&tarball, b"boop")?;
+
+        assert!(action.try_execute().await.is_err());
+
+        Ok(())
+    }
+}
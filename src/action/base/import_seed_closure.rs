@@ -0,0 +1,466 @@
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+    process::Stdio,
+};
+
+use tokio::{
+    io::{AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    process::Command,
+};
+use tracing::{span, Span};
+
+use crate::{
+    action::{
+        Action, ActionDescription, ActionError, ActionErrorKind, ActionTag, DryRunStep,
+        StatefulAction,
+    },
+    execute_command,
+    manifest::NIX_BIN,
+};
+
+const NIX_STORE_BIN: &str = "/nix/var/nix/profiles/default/bin/nix-store";
+
+/**
+Import a pre-built closure into the Nix store, without going through the Nix daemon
+
+The closure is either a binary cache directory (as made by `nix copy --to file://$DIR`), one of
+the `.nar.xz` files in such a binary cache (importing the store path it holds, and its closure),
+or a `nix-store --export` file which may be `.xz` compressed.
+
+Store paths from a binary cache must be signed by a key in `trusted-public-keys`, unless
+`no_check_sigs` is set. A `nix-store --export` file carries no signatures, so can only be imported
+with `no_check_sigs`.
+ */
+#[derive(Debug, serde::Deserialize, serde::Serialize, Clone)]
+#[serde(tag = "action_name", rename = "import_seed_closure")]
+pub struct ImportSeedClosure {
+    path: PathBuf,
+    #[serde(default)]
+    no_check_sigs: bool,
+}
+
+impl ImportSeedClosure {
+    #[tracing::instrument(level = "debug", skip_all)]
+    pub async fn plan(
+        path: PathBuf,
+        no_check_sigs: bool,
+    ) -> Result<StatefulAction<Self>, ActionError> {
+        // Fail early instead of after the store is set up
+        if let Seed::ExportFile = Seed::of(&path).await.map_err(Self::error)? {
+            if !no_check_sigs {
+                return Err(Self::error(ImportSeedClosureError::UnsignedExportFile(
+                    path,
+                )));
+            }
+        }
+
+        Ok(Self {
+            path,
+            no_check_sigs,
+        }
+        .into())
+    }
+}
+
+/// Where the store paths of a seed closure are read from
+#[derive(Debug, PartialEq, Eq)]
+enum Seed {
+    /// A binary cache directory, and the store paths to copy from it
+    BinaryCache {
+        cache: PathBuf,
+        store_paths: Vec<String>,
+    },
+    /// A `nix-store --export` file, which may be `.xz` compressed
+    ExportFile,
+}
+
+impl Seed {
+    async fn of(path: &Path) -> Result<Self, ActionErrorKind> {
+        let metadata = tokio::fs::metadata(path)
+            .await
+            .map_err(|e| ActionErrorKind::GettingMetadata(path.to_path_buf(), e))?;
+        if metadata.is_dir() {
+            let store_paths = binary_cache_store_paths(path, None).await?;
+            if store_paths.is_empty() {
+                return Err(ImportSeedClosureError::EmptyBinaryCache(path.to_path_buf()).into());
+            }
+            return Ok(Self::BinaryCache {
+                cache: path.to_path_buf(),
+                store_paths,
+            });
+        }
+
+        // A NAR in the `nar` directory of a binary cache, like `$DIR/nar/1xw...q.nar.xz`
+        let file_name = path.file_name().and_then(|name| name.to_str());
+        let is_nar = file_name.is_some_and(|name| {
+            name.ends_with(".nar")
+                || name
+                    .rsplit_once('.')
+                    .is_some_and(|(stem, _)| stem.ends_with(".nar"))
+        });
+        let cache = path
+            .parent()
+            .filter(|dir| dir.file_name().and_then(|name| name.to_str()) == Some("nar"))
+            .and_then(Path::parent);
+        match (is_nar, file_name, cache) {
+            (true, Some(file_name), Some(cache)) => {
+                let url = format!("nar/{file_name}");
+                let store_paths = binary_cache_store_paths(cache, Some(&url)).await?;
+                if store_paths.is_empty() {
+                    return Err(ImportSeedClosureError::UnknownNar(path.to_path_buf()).into());
+                }
+                Ok(Self::BinaryCache {
+                    cache: cache.to_path_buf(),
+                    store_paths,
+                })
+            },
+            (true, _, _) => Err(ImportSeedClosureError::UnknownNar(path.to_path_buf()).into()),
+            _ => Ok(Self::ExportFile),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+#[typetag::serde(name = "import_seed_closure")]
+impl Action for ImportSeedClosure {
+    fn action_tag() -> ActionTag {
+        ActionTag("import_seed_closure")
+    }
+    fn tracing_synopsis(&self) -> String {
+        format!("Import the seed closure `{}`", self.path.display())
+    }
+
+    fn tracing_span(&self) -> Span {
+        span!(
+            tracing::Level::DEBUG,
+            "import_seed_closure",
+            path = %self.path.display(),
+        )
+    }
+
+    fn execute_description(&self) -> Vec<ActionDescription> {
+        vec![ActionDescription::new(
+            self.tracing_synopsis(),
+            vec![
+                "Pre-built store paths are copied into `/nix/store` so they need not be fetched after install".to_string(),
+            ],
+        )]
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    async fn execute(&mut self) -> Result<(), ActionError> {
+        match Seed::of(&self.path).await.map_err(Self::error)? {
+            Seed::BinaryCache { cache, store_paths } => {
+                let mut from = std::ffi::OsString::from("file://");
+                from.push(&cache);
+                let mut command = Command::new(NIX_BIN);
+                command
+                    .process_group(0)
+                    .args(["--extra-experimental-features", "nix-command"])
+                    .arg("copy");
+                if self.no_check_sigs {
+                    command.arg("--no-check-sigs");
+                }
+                // The daemon is not running yet, so this writes to the store directly
+                execute_command(
+                    command
+                        .arg("--from")
+                        .arg(from)
+                        .args(store_paths)
+                        .stdin(Stdio::null()),
+                )
+                .await
+                .map_err(Self::error)?;
+            },
+            Seed::ExportFile => {
+                if !self.no_check_sigs {
+                    return Err(Self::error(ImportSeedClosureError::UnsignedExportFile(
+                        self.path.clone(),
+                    )));
+                }
+                import_export_file(&self.path).await.map_err(Self::error)?;
+            },
+        }
+
+        Ok(())
+    }
+
+    fn revert_description(&self) -> Vec<ActionDescription> {
+        vec![ActionDescription::new(
+            format!(
+                "Forget the seed closure `{}`, its store paths are removed along with `/nix/store`",
+                self.path.display()
+            ),
+            vec![],
+        )]
+    }
+
+    fn revert_dry_run(&self) -> Vec<DryRunStep> {
+        // The imported paths live in the store which is removed separately
+        vec![]
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    async fn revert(&mut self) -> Result<(), ActionError> {
+        // Nothing to do, the imported paths live in the store which is removed separately
+        Ok(())
+    }
+}
+
+/// The `StorePath` of every `.narinfo` in a binary cache directory, or only of those with the `URL` `url`
+async fn binary_cache_store_paths(
+    dir: &Path,
+    url: Option<&str>,
+) -> Result<Vec<String>, ActionErrorKind> {
+    let mut store_paths = Vec::new();
+    let mut entries = tokio::fs::read_dir(dir)
+        .await
+        .map_err(|e| ActionErrorKind::ReadDir(dir.to_path_buf(), e))?;
+    while let Some(entry) = entries
+        .next_entry()
+        .await
+        .map_err(|e| ActionErrorKind::ReadDir(dir.to_path_buf(), e))?
+    {
+        let path = entry.path();
+        if path.extension().and_then(|ext| ext.to_str()) != Some("narinfo") {
+            continue;
+        }
+        let narinfo = tokio::fs::read_to_string(&path)
+            .await
+            .map_err(|e| ActionErrorKind::Read(path.clone(), e))?;
+        if url.is_some() && narinfo_field(&narinfo, "URL") != url {
+            continue;
+        }
+        if let Some(store_path) = narinfo_field(&narinfo, "StorePath") {
+            store_paths.push(store_path.to_string());
+        }
+    }
+    store_paths.sort();
+    Ok(store_paths)
+}
+
+/// The value of `field` (like `StorePath`) in a `.narinfo`
+fn narinfo_field<'a>(narinfo: &'a str, field: &str) -> Option<&'a str> {
+    narinfo
+        .lines()
+        .find_map(|line| line.strip_prefix(field)?.strip_prefix(':'))
+        .map(str::trim)
+}
+
+/// Stream a `nix-store --export` file, decompressing it if needed, into `nix-store --import`
+async fn import_export_file(path: &Path) -> Result<(), ActionErrorKind> {
+    let mut command = Command::new(NIX_STORE_BIN);
+    command.process_group(0);
+    command.arg("--import");
+    command.stdin(Stdio::piped());
+    // The imported paths are printed, which could fill the pipe while we are still writing
+    command.stdout(Stdio::null());
+    command.stderr(Stdio::piped());
+    tracing::trace!(
+        "Executing `{:?}` with stdin from `{}`",
+        command.as_std(),
+        path.display()
+    );
+    let mut handle = command
+        .spawn()
+        .map_err(|e| ActionErrorKind::command(&command, e))?;
+
+    let Some(mut stdin) = handle.stdin.take() else {
+        return Err(ActionErrorKind::command(
+            &command,
+            std::io::Error::new(std::io::ErrorKind::BrokenPipe, "stdin was not piped"),
+        ));
+    };
+    write_export_file(path, &mut stdin).await?;
+    drop(stdin);
+
+    let output = handle
+        .wait_with_output()
+        .await
+        .map_err(|e| ActionErrorKind::command(&command, e))?;
+    if !output.status.success() {
+        return Err(ActionErrorKind::command_output(&command, output));
+    }
+
+    Ok(())
+}
+
+/// Write the `nix-store --export` file at `path` to `writer`, decompressing it if it is a `.xz`
+async fn write_export_file(
+    path: &Path,
+    writer: &mut (impl AsyncWrite + Unpin),
+) -> Result<(), ActionErrorKind> {
+    let mut file = tokio::fs::File::open(path)
+        .await
+        .map_err(|e| ActionErrorKind::Open(path.to_path_buf(), e))?;
+    let mut decoder = if path.extension().and_then(|ext| ext.to_str()) == Some("xz") {
+        Some(xz2::write::XzDecoder::new(Vec::new()))
+    } else {
+        None
+    };
+
+    let mut buf = vec![0; 64 * 1024];
+    loop {
+        let read = file
+            .read(&mut buf)
+            .await
+            .map_err(|e| ActionErrorKind::Read(path.to_path_buf(), e))?;
+        if read == 0 {
+            break;
+        }
+        let chunk = match &mut decoder {
+            Some(decoder) => {
+                decoder.get_mut().clear();
+                decoder
+                    .write_all(&buf[..read])
+                    .map_err(|e| ActionErrorKind::Read(path.to_path_buf(), e))?;
+                decoder.get_ref().as_slice()
+            },
+            None => &buf[..read],
+        };
+        writer
+            .write_all(chunk)
+            .await
+            .map_err(|e| ActionErrorKind::Write(PathBuf::from("/dev/stdin"), e))?;
+    }
+    if let Some(mut decoder) = decoder {
+        // What is left from the last chunk was already written
+        decoder.get_mut().clear();
+        let rest = decoder
+            .finish()
+            .map_err(|e| ActionErrorKind::Read(path.to_path_buf(), e))?;
+        writer
+            .write_all(&rest)
+            .await
+            .map_err(|e| ActionErrorKind::Write(PathBuf::from("/dev/stdin"), e))?;
+    }
+    writer
+        .flush()
+        .await
+        .map_err(|e| ActionErrorKind::Write(PathBuf::from("/dev/stdin"), e))?;
+    Ok(())
+}
+
+#[non_exhaustive]
+#[derive(Debug, thiserror::Error)]
+pub enum ImportSeedClosureError {
+    #[error("Binary cache directory `{0}` contains no `.narinfo` files to import")]
+    EmptyBinaryCache(PathBuf),
+    #[error("No `.narinfo` in the binary cache of `{0}` refers to it, seed closure NARs must be in the `nar` directory of a binary cache made by `nix copy --to file://`")]
+    UnknownNar(PathBuf),
+    #[error("`{0}` is a `nix-store --export` file, which has no signatures to check, import a signed binary cache instead or pass `--seed-closure-no-check-sigs`")]
+    UnsignedExportFile(PathBuf),
+}
+
+impl From<ImportSeedClosureError> for ActionErrorKind {
+    fn from(val: ImportSeedClosureError) -> Self {
+        ActionErrorKind::Custom(Box::new(val))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{binary_cache_store_paths, write_export_file, ImportSeedClosure, Seed};
+
+    #[tokio::test]
+    async fn finds_binary_cache_store_paths() -> eyre::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        tokio::fs::write(
+            temp_dir.path().join("bbbb.narinfo"),
+            "StorePath: /nix/store/bbbb-hello-2.12.1\nURL: nar/bbbb.nar.xz\n",
+        )
+        .await?;
+        tokio::fs::write(
+            temp_dir.path().join("aaaa.narinfo"),
+            "StorePath: /nix/store/aaaa-glibc-2.39\nURL: nar/aaaa.nar.xz\n",
+        )
+        .await?;
+        tokio::fs::write(
+            temp_dir.path().join("nix-cache-info"),
+            "StoreDir: /nix/store\n",
+        )
+        .await?;
+
+        assert_eq!(
+            binary_cache_store_paths(temp_dir.path(), None).await?,
+            vec!["/nix/store/aaaa-glibc-2.39", "/nix/store/bbbb-hello-2.12.1"]
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn refuses_empty_binary_cache() -> eyre::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        assert!(
+            ImportSeedClosure::plan(temp_dir.path().to_path_buf(), false)
+                .await
+                .is_err()
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn finds_the_store_path_of_a_nar() -> eyre::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        tokio::fs::create_dir(temp_dir.path().join("nar")).await?;
+        tokio::fs::write(
+            temp_dir.path().join("bbbb.narinfo"),
+            "StorePath: /nix/store/bbbb-hello-2.12.1\nURL: nar/1234.nar.xz\n",
+        )
+        .await?;
+        tokio::fs::write(
+            temp_dir.path().join("aaaa.narinfo"),
+            "StorePath: /nix/store/aaaa-glibc-2.39\nURL: nar/5678.nar.xz\n",
+        )
+        .await?;
+        let nar = temp_dir.path().join("nar/1234.nar.xz");
+        tokio::fs::write(&nar, "").await?;
+
+        assert_eq!(
+            Seed::of(&nar).await?,
+            Seed::BinaryCache {
+                cache: temp_dir.path().to_path_buf(),
+                store_paths: vec!["/nix/store/bbbb-hello-2.12.1".to_string()],
+            }
+        );
+
+        let stray = temp_dir.path().join("closure.nar.xz");
+        tokio::fs::write(&stray, "").await?;
+        assert!(Seed::of(&stray).await.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn only_imports_export_files_without_checking_sigs() -> eyre::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let export = temp_dir.path().join("closure.xz");
+        tokio::fs::write(&export, "").await?;
+
+        assert!(ImportSeedClosure::plan(export.clone(), false)
+            .await
+            .is_err());
+        assert!(ImportSeedClosure::plan(export, true).await.is_ok());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn decompresses_xz_export_files() -> eyre::Result<()> {
+        use std::io::Write;
+
+        let temp_dir = tempfile::tempdir()?;
+        let export = temp_dir.path().join("closure.xz");
+        // Spanning several chunks, so each is only written once
+        let contents = (0..200_000u32)
+            .flat_map(|index| index.to_le_bytes())
+            .collect::<Vec<_>>();
+        let mut encoder = xz2::write::XzEncoder::new(Vec::new(), 6);
+        encoder.write_all(&contents)?;
+        tokio::fs::write(&export, encoder.finish()?).await?;
+
+        let mut written = Vec::new();
+        write_export_file(&export, &mut written).await?;
+        assert!(written == contents, "The export file was not written as is");
+        Ok(())
+    }
+}
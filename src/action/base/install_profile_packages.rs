@@ -0,0 +1,240 @@
+use std::{path::PathBuf, process::Stdio, time::Duration};
+
+use tokio::process::Command;
+use tracing::{span, Span};
+
+use crate::{
+    action::{
+        Action, ActionDescription, ActionError, ActionErrorKind, ActionTag, DryRunStep,
+        StatefulAction,
+    },
+    execute_command,
+    manifest::{DEFAULT_PROFILE, NIX_BIN},
+};
+
+const NIX_ENV_BIN: &str = "/nix/var/nix/profiles/default/bin/nix-env";
+const DAEMON_WAIT_ATTEMPTS: usize = 30;
+
+/**
+Build packages (eg `nixpkgs#direnv`) and install them into the default profile
+ */
+#[derive(Debug, serde::Deserialize, serde::Serialize, Clone)]
+#[serde(tag = "action_name", rename = "install_profile_packages")]
+pub struct InstallProfilePackages {
+    packages: Vec<String>,
+    /// The store paths which were installed, recorded so they can be removed again
+    #[serde(default)]
+    installed: Vec<PathBuf>,
+}
+
+impl InstallProfilePackages {
+    #[tracing::instrument(level = "debug", skip_all)]
+    pub async fn plan(packages: Vec<String>) -> Result<StatefulAction<Self>, ActionError> {
+        Ok(Self {
+            packages,
+            installed: Vec::new(),
+        }
+        .into())
+    }
+}
+
+#[async_trait::async_trait]
+#[typetag::serde(name = "install_profile_packages")]
+impl Action for InstallProfilePackages {
+    fn action_tag() -> ActionTag {
+        ActionTag("install_profile_packages")
+    }
+    fn tracing_synopsis(&self) -> String {
+        format!(
+            "Install {} into the default profile",
+            self.packages
+                .iter()
+                .map(|package| format!("`{package}`"))
+                .collect::<Vec<_>>()
+                .join(", ")
+        )
+    }
+
+    fn tracing_span(&self) -> Span {
+        span!(
+            tracing::Level::DEBUG,
+            "install_profile_packages",
+            packages = self.packages.join(","),
+        )
+    }
+
+    fn execute_description(&self) -> Vec<ActionDescription> {
+        vec![ActionDescription::new(
+            self.tracing_synopsis(),
+            vec![format!(
+                "Packages are built (or substituted) by the Nix daemon, then installed into `{DEFAULT_PROFILE}`"
+            )],
+        )]
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    async fn execute(&mut self) -> Result<(), ActionError> {
+        wait_for_daemon().await.map_err(Self::error)?;
+
+        let output = execute_command(
+            Command::new(NIX_BIN)
+                .process_group(0)
+                .args(["--extra-experimental-features", "nix-command flakes"])
+                .args(["build", "--no-link", "--print-out-paths"])
+                .args(&self.packages)
+                .stdin(Stdio::null())
+                .env("HOME", root_home().map_err(Self::error)?),
+        )
+        .await
+        .map_err(Self::error)?;
+        let store_paths = String::from_utf8(output.stdout)
+            .map_err(|e| Self::error(ActionErrorKind::FromUtf8(e)))?
+            .lines()
+            .filter(|line| !line.is_empty())
+            .map(PathBuf::from)
+            .collect::<Vec<_>>();
+
+        execute_command(
+            Command::new(NIX_ENV_BIN)
+                .process_group(0)
+                .args(["--profile", DEFAULT_PROFILE])
+                .arg("-i")
+                .args(&store_paths)
+                .stdin(Stdio::null())
+                .env("HOME", root_home().map_err(Self::error)?),
+        )
+        .await
+        .map_err(Self::error)?;
+        self.installed = store_paths;
+
+        Ok(())
+    }
+
+    fn skippable(&self) -> bool {
+        true
+    }
+
+    fn revert_description(&self) -> Vec<ActionDescription> {
+        vec![ActionDescription::new(
+            format!(
+                "Remove {} from the default profile",
+                self.packages
+                    .iter()
+                    .map(|package| format!("`{package}`"))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            vec![],
+        )]
+    }
+
+    fn revert_dry_run(&self) -> Vec<DryRunStep> {
+        let names = self
+            .installed
+            .iter()
+            .filter_map(|store_path| profile_entry_name(store_path))
+            .collect::<Vec<_>>();
+        if names.is_empty() {
+            return vec![];
+        }
+        let mut argv = vec![
+            NIX_ENV_BIN.to_string(),
+            "--profile".to_string(),
+            DEFAULT_PROFILE.to_string(),
+            "-e".to_string(),
+        ];
+        argv.extend(names);
+        vec![DryRunStep::Command(argv)]
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    async fn revert(&mut self) -> Result<(), ActionError> {
+        let names = self
+            .installed
+            .iter()
+            .filter_map(|store_path| profile_entry_name(store_path))
+            .collect::<Vec<_>>();
+        if names.is_empty() {
+            return Ok(());
+        }
+
+        execute_command(
+            Command::new(NIX_ENV_BIN)
+                .process_group(0)
+                .args(["--profile", DEFAULT_PROFILE])
+                .arg("-e")
+                .args(&names)
+                .stdin(Stdio::null())
+                .env("HOME", root_home().map_err(Self::error)?),
+        )
+        .await
+        .map_err(Self::error)?;
+        self.installed.clear();
+
+        Ok(())
+    }
+}
+
+fn root_home() -> Result<PathBuf, InstallProfilePackagesError> {
+    dirs::home_dir().ok_or(InstallProfilePackagesError::NoRootHome)
+}
+
+/// Wait until the store (through the daemon, if one was started) accepts connections
+pub(crate) async fn wait_for_daemon() -> Result<(), ActionErrorKind> {
+    let mut command = Command::new(NIX_BIN);
+    command.process_group(0);
+    command.args(["--extra-experimental-features", "nix-command"]);
+    command.args(["store", "ping"]);
+    command.stdin(Stdio::null());
+
+    let mut attempt = 0;
+    loop {
+        attempt += 1;
+        match execute_command(&mut command).await {
+            Ok(_) => return Ok(()),
+            Err(err) if attempt >= DAEMON_WAIT_ATTEMPTS => return Err(err),
+            Err(err) => {
+                tracing::trace!(%err, attempt, "Nix daemon not ready yet");
+                tokio::time::sleep(Duration::from_secs(1)).await;
+            },
+        }
+    }
+}
+
+/// The name `nix-env` gives a store path in a profile, eg `direnv-2.34.0` for `/nix/store/$HASH-direnv-2.34.0`
+fn profile_entry_name(store_path: &std::path::Path) -> Option<String> {
+    let file_name = store_path.file_name()?.to_str()?;
+    let (_hash, name) = file_name.split_once('-')?;
+    Some(name.to_string())
+}
+
+#[non_exhaustive]
+#[derive(Debug, thiserror::Error)]
+pub enum InstallProfilePackagesError {
+    #[error("No root home found to run `nix` in")]
+    NoRootHome,
+}
+
+impl From<InstallProfilePackagesError> for ActionErrorKind {
+    fn from(val: InstallProfilePackagesError) -> Self {
+        ActionErrorKind::Custom(Box::new(val))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use super::profile_entry_name;
+
+    #[test]
+    fn names_profile_entries() {
+        assert_eq!(
+            profile_entry_name(Path::new(
+                "/nix/store/0c2kcxkvz0qfd5fnz2a1mhx9bdvw4gw9-direnv-2.34.0"
+            )),
+            Some("direnv-2.34.0".to_string())
+        );
+        assert_eq!(profile_entry_name(Path::new("/nix/store/")), None);
+    }
+}
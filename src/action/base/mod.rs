@@ -6,12 +6,19 @@ pub(crate) mod create_file;
 pub(crate) mod create_group;
 pub(crate) mod create_or_insert_into_file;
 pub(crate) mod create_or_merge_nix_config;
+pub(crate) mod create_snapshots;
 pub(crate) mod create_user;
 pub(crate) mod delete_user;
 pub(crate) mod fetch_and_unpack_nix;
+pub(crate) mod import_seed_closure;
+pub(crate) mod install_profile_packages;
 pub(crate) mod move_unpacked_nix;
 pub(crate) mod remove_directory;
+pub(crate) mod run_plugin;
+pub(crate) mod run_post_install_hook;
 pub(crate) mod setup_default_profile;
+pub(crate) mod unknown_action;
+pub(crate) mod update_nix_config;
 
 pub use add_user_to_group::AddUserToGroup;
 pub use create_directory::CreateDirectory;
@@ -19,9 +26,18 @@ pub use create_file::CreateFile;
 pub use create_group::CreateGroup;
 pub use create_or_insert_into_file::CreateOrInsertIntoFile;
 pub use create_or_merge_nix_config::CreateOrMergeNixConfig;
+pub use create_snapshots::{CreateSnapshots, CreateSnapshotsError, Snapshot, SnapshotFilesystem};
 pub use create_user::CreateUser;
 pub use delete_user::DeleteUser;
-pub use fetch_and_unpack_nix::{FetchAndUnpackNix, FetchUrlError};
+pub use fetch_and_unpack_nix::{
+    DownloadPolicy, FetchAndUnpackNix, FetchUrlError, NixPackageVerification,
+};
+pub use import_seed_closure::{ImportSeedClosure, ImportSeedClosureError};
+pub use install_profile_packages::{InstallProfilePackages, InstallProfilePackagesError};
 pub use move_unpacked_nix::{MoveUnpackedNix, MoveUnpackedNixError};
 pub use remove_directory::RemoveDirectory;
+pub use run_plugin::{RunPlugin, RunPluginError};
+pub use run_post_install_hook::{RunPostInstallHook, RunPostInstallHookError};
 pub use setup_default_profile::{SetupDefaultProfile, SetupDefaultProfileError};
+pub use unknown_action::{UnknownAction, UnknownActionError};
+pub use update_nix_config::UpdateNixConfig;
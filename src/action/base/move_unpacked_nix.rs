@@ -6,8 +6,10 @@ use std::{
 use tracing::{span, Span};
 use walkdir::WalkDir;
 
-use crate::action::{
-    Action, ActionDescription, ActionError, ActionErrorKind, ActionTag, StatefulAction,
+use crate::{
+    action::{Action, ActionDescription, ActionError, ActionErrorKind, ActionTag, StatefulAction},
+    audit::{self, AuditEvent},
+    settings::Distribution,
 };
 
 pub(crate) const DEST: &str = "/nix/";
@@ -19,13 +21,29 @@ Move an unpacked Nix at `src` to `/nix`
 #[serde(tag = "action_name", rename = "mount_unpacked_nix")]
 pub struct MoveUnpackedNix {
     unpacked_path: PathBuf,
+    #[serde(default)]
+    distribution: Distribution,
+    /// Leave store paths which already exist in place, rather than replacing them
+    ///
+    /// Used when the store is live (eg upgrading), since replacing a path removes it for a moment.
+    #[serde(default)]
+    keep_existing: bool,
 }
 
 impl MoveUnpackedNix {
     #[tracing::instrument(level = "debug", skip_all)]
-    pub async fn plan(unpacked_path: PathBuf) -> Result<StatefulAction<Self>, ActionError> {
+    pub async fn plan(
+        unpacked_path: PathBuf,
+        distribution: Distribution,
+        keep_existing: bool,
+    ) -> Result<StatefulAction<Self>, ActionError> {
         // Note: Do NOT try to check for the src/dest since the installer creates those
-        Ok(Self { unpacked_path }.into())
+        Ok(Self {
+            unpacked_path,
+            distribution,
+            keep_existing,
+        }
+        .into())
     }
 }
 
@@ -60,13 +78,21 @@ impl Action for MoveUnpackedNix {
 
     #[tracing::instrument(level = "debug", skip_all)]
     async fn execute(&mut self) -> Result<(), ActionError> {
-        let Self { unpacked_path } = self;
-
-        // This is the `nix-$VERSION` folder which unpacks from the tarball, not a nix derivation
-        let found_nix_paths = glob::glob(&format!("{}/nix-*", unpacked_path.display()))
-            .map_err(|e| Self::error(MoveUnpackedNixError::from(e)))?
-            .collect::<Result<Vec<_>, _>>()
-            .map_err(|e| Self::error(MoveUnpackedNixError::from(e)))?;
+        let Self {
+            unpacked_path,
+            distribution,
+            keep_existing,
+        } = self;
+
+        // This is the `nix-$VERSION` (or `lix-$VERSION`) folder which unpacks from the tarball, not a nix derivation
+        let found_nix_paths = glob::glob(&format!(
+            "{}/{}-*",
+            unpacked_path.display(),
+            distribution.package_name()
+        ))
+        .map_err(|e| Self::error(MoveUnpackedNixError::from(e)))?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| Self::error(MoveUnpackedNixError::from(e)))?;
         if found_nix_paths.len() != 1 {
             return Err(Self::error(ActionErrorKind::MalformedBinaryTarball));
         }
@@ -88,6 +114,9 @@ impl Action for MoveUnpackedNix {
                 .await
                 .map_err(|e| ActionErrorKind::CreateDirectory(dest_store.clone(), e))
                 .map_err(Self::error)?;
+            audit::record(AuditEvent::DirectoryCreated {
+                path: dest_store.clone(),
+            });
         }
 
         while let Some(entry) = src_store_listing
@@ -97,43 +126,64 @@ impl Action for MoveUnpackedNix {
             .map_err(Self::error)?
         {
             let entry_dest = dest_store.join(entry.file_name());
-            if entry_dest.exists() {
-                tracing::trace!(src = %entry.path().display(), dest = %entry_dest.display(), "Removing already existing package");
-                tokio::fs::remove_dir_all(&entry_dest)
-                    .await
-                    .map_err(|e| ActionErrorKind::Remove(entry_dest.clone(), e))
+            if entry_dest.exists() && *keep_existing {
+                tracing::trace!(dest = %entry_dest.display(), "Keeping already existing package");
+                // Only the back link is needed, so the unpacked copy is discarded
+                let src = entry.path();
+                let removed = if src.is_dir() {
+                    tokio::fs::remove_dir_all(&src).await
+                } else {
+                    tokio::fs::remove_file(&src).await
+                };
+                removed
+                    .map_err(|e| ActionErrorKind::Remove(src.clone(), e))
                     .map_err(Self::error)?;
-            }
-            tracing::trace!(src = %entry.path().display(), dest = %entry_dest.display(), "Renaming");
-            tokio::fs::rename(&entry.path(), &entry_dest)
-                .await
-                .map_err(|e| ActionErrorKind::Rename(entry.path(), entry_dest.to_owned(), e))
-                .map_err(Self::error)?;
-
-            for entry_item in WalkDir::new(&entry_dest)
-                .into_iter()
-                .filter_map(Result::ok)
-                .filter(|e| !e.file_type().is_symlink())
-            {
-                let path = entry_item.path();
-
-                let mut perms = path
-                    .metadata()
-                    .map_err(|e| ActionErrorKind::GetMetadata(path.to_owned(), e))
-                    .map_err(Self::error)?
-                    .permissions();
-                perms.set_readonly(true);
-
-                tokio::fs::set_permissions(path, perms.clone())
+            } else {
+                if entry_dest.exists() {
+                    tracing::trace!(src = %entry.path().display(), dest = %entry_dest.display(), "Removing already existing package");
+                    tokio::fs::remove_dir_all(&entry_dest)
+                        .await
+                        .map_err(|e| ActionErrorKind::Remove(entry_dest.clone(), e))
+                        .map_err(Self::error)?;
+                    audit::record(AuditEvent::DirectoryRemoved {
+                        path: entry_dest.clone(),
+                    });
+                }
+                tracing::trace!(src = %entry.path().display(), dest = %entry_dest.display(), "Renaming");
+                tokio::fs::rename(&entry.path(), &entry_dest)
                     .await
-                    .map_err(|e| {
-                        ActionErrorKind::SetPermissions(
-                            perms.mode(),
-                            entry_item.path().to_owned(),
-                            e,
-                        )
-                    })
+                    .map_err(|e| ActionErrorKind::Rename(entry.path(), entry_dest.to_owned(), e))
                     .map_err(Self::error)?;
+                audit::record(AuditEvent::Moved {
+                    from: entry.path(),
+                    to: entry_dest.clone(),
+                });
+
+                for entry_item in WalkDir::new(&entry_dest)
+                    .into_iter()
+                    .filter_map(Result::ok)
+                    .filter(|e| !e.file_type().is_symlink())
+                {
+                    let path = entry_item.path();
+
+                    let mut perms = path
+                        .metadata()
+                        .map_err(|e| ActionErrorKind::GetMetadata(path.to_owned(), e))
+                        .map_err(Self::error)?
+                        .permissions();
+                    perms.set_readonly(true);
+
+                    tokio::fs::set_permissions(path, perms.clone())
+                        .await
+                        .map_err(|e| {
+                            ActionErrorKind::SetPermissions(
+                                perms.mode(),
+                                entry_item.path().to_owned(),
+                                e,
+                            )
+                        })
+                        .map_err(Self::error)?;
+                }
             }
 
             // Leave a back link where we copied from since later we may need to know which packages we actually transferred
@@ -5,6 +5,7 @@ use tracing::{span, Span};
 
 use crate::action::{Action, ActionDescription, ActionErrorKind, ActionState};
 use crate::action::{ActionError, StatefulAction};
+use crate::audit::{self, AuditEvent};
 
 /** Remove a directory, does nothing on revert.
 */
@@ -59,6 +60,9 @@ impl Action for RemoveDirectory {
             remove_dir_all(&self.path)
                 .await
                 .map_err(|e| Self::error(ActionErrorKind::Remove(self.path.clone(), e)))?;
+            audit::record(AuditEvent::DirectoryRemoved {
+                path: self.path.clone(),
+            });
         } else {
             tracing::debug!("Directory `{}` not present, skipping", self.path.display(),);
         };
@@ -0,0 +1,227 @@
+use std::{
+    path::{Path, PathBuf},
+    process::Stdio,
+};
+
+use sha2::{Digest, Sha256};
+use tokio::process::Command;
+use tracing::{span, Span};
+
+use crate::{
+    action::{
+        Action, ActionDescription, ActionError, ActionErrorKind, ActionTag, DryRunStep,
+        StatefulAction,
+    },
+    execute_command,
+};
+
+/**
+Run a plugin, an external executable which adds a step of its own to the plan (eg enrolling an
+agent, or trusting an internal CA)
+
+The plugin is called with a single argument:
+
+* `plan`: print a JSON description of the step to stdout, like
+  `{"synopsis": "Enroll the host", "explanation": ["..."], "completed": false}`
+  (`explanation` and `completed` may be left out). Exiting non-zero fails the planning.
+* `execute`: take the step.
+* `revert`: undo the step.
+
+The plugin's SHA-256 is recorded when planned, and it won't be run if it changed since.
+ */
+#[derive(Debug, serde::Deserialize, serde::Serialize, Clone)]
+#[serde(tag = "action_name", rename = "run_plugin")]
+pub struct RunPlugin {
+    path: PathBuf,
+    sha256: String,
+    synopsis: String,
+    explanation: Vec<String>,
+}
+
+/// What a plugin prints when called with `plan`
+#[derive(Debug, serde::Deserialize, Clone, PartialEq, Eq)]
+struct PluginDescription {
+    synopsis: String,
+    #[serde(default)]
+    explanation: Vec<String>,
+    /// The step is already taken
+    #[serde(default)]
+    completed: bool,
+}
+
+impl RunPlugin {
+    #[tracing::instrument(level = "debug", skip_all)]
+    pub async fn plan(path: impl AsRef<Path>) -> Result<StatefulAction<Self>, ActionError> {
+        let path = path.as_ref().to_path_buf();
+        let sha256 = plugin_sha256(&path).await.map_err(Self::error)?;
+
+        let output = execute_command(
+            Command::new(&path)
+                .process_group(0)
+                .arg("plan")
+                .stdin(Stdio::null()),
+        )
+        .await
+        .map_err(Self::error)?;
+        let description: PluginDescription = serde_json::from_slice(&output.stdout)
+            .map_err(|e| Self::error(RunPluginError::Description(path.clone(), e)))?;
+
+        let this = Self {
+            path,
+            sha256,
+            synopsis: description.synopsis,
+            explanation: description.explanation,
+        };
+        if description.completed {
+            return Ok(StatefulAction::completed(this));
+        }
+        Ok(StatefulAction::uncompleted(this))
+    }
+
+    /// Run the plugin with `stage`, if it is still the plugin which was planned
+    async fn run(&self, stage: &str) -> Result<(), ActionErrorKind> {
+        let sha256 = plugin_sha256(&self.path).await?;
+        if sha256 != self.sha256 {
+            return Err(RunPluginError::Changed(self.path.clone()).into());
+        }
+
+        execute_command(
+            Command::new(&self.path)
+                .process_group(0)
+                .arg(stage)
+                .stdin(Stdio::null()),
+        )
+        .await?;
+        Ok(())
+    }
+}
+
+#[async_trait::async_trait]
+#[typetag::serde(name = "run_plugin")]
+impl Action for RunPlugin {
+    fn action_tag() -> ActionTag {
+        ActionTag("run_plugin")
+    }
+    fn tracing_synopsis(&self) -> String {
+        self.synopsis.clone()
+    }
+
+    fn tracing_span(&self) -> Span {
+        span!(
+            tracing::Level::DEBUG,
+            "run_plugin",
+            path = tracing::field::display(self.path.display()),
+        )
+    }
+
+    fn execute_description(&self) -> Vec<ActionDescription> {
+        let mut explanation = self.explanation.clone();
+        explanation.push(format!("Run by the plugin `{}`", self.path.display()));
+        vec![ActionDescription::new(self.tracing_synopsis(), explanation)]
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    async fn execute(&mut self) -> Result<(), ActionError> {
+        self.run("execute").await.map_err(Self::error)
+    }
+
+    fn dry_run(&self) -> Vec<DryRunStep> {
+        vec![DryRunStep::Command(vec![
+            self.path.display().to_string(),
+            "execute".to_string(),
+        ])]
+    }
+
+    fn revert_description(&self) -> Vec<ActionDescription> {
+        vec![ActionDescription::new(
+            format!("Undo: {}", self.synopsis),
+            vec![format!("Run by the plugin `{}`", self.path.display())],
+        )]
+    }
+
+    fn revert_dry_run(&self) -> Vec<DryRunStep> {
+        vec![DryRunStep::Command(vec![
+            self.path.display().to_string(),
+            "revert".to_string(),
+        ])]
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    async fn revert(&mut self) -> Result<(), ActionError> {
+        self.run("revert").await.map_err(Self::error)
+    }
+}
+
+pub(crate) async fn plugin_sha256(path: &Path) -> Result<String, ActionErrorKind> {
+    let contents = tokio::fs::read(path)
+        .await
+        .map_err(|e| ActionErrorKind::Read(path.to_path_buf(), e))?;
+    Ok(format!("{:x}", Sha256::digest(contents)))
+}
+
+#[non_exhaustive]
+#[derive(Debug, thiserror::Error)]
+pub enum RunPluginError {
+    #[error("The plugin `{0}` didn't describe its step as JSON when called with `plan`")]
+    Description(PathBuf, #[source] serde_json::Error),
+    #[error("The plugin `{0}` changed since it was planned, plan the install again to run it")]
+    Changed(PathBuf),
+}
+
+impl From<RunPluginError> for ActionErrorKind {
+    fn from(val: RunPluginError) -> Self {
+        ActionErrorKind::Custom(Box::new(val))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::os::unix::fs::PermissionsExt;
+
+    use super::RunPlugin;
+    use crate::action::{Action, ActionState};
+
+    async fn write_plugin(dir: &std::path::Path, script: &str) -> eyre::Result<std::path::PathBuf> {
+        let path = dir.join("plugin");
+        tokio::fs::write(&path, script).await?;
+        tokio::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).await?;
+        Ok(path)
+    }
+
+    #[tokio::test]
+    async fn runs_plugin_stages() -> eyre::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let log = temp_dir.path().join("log");
+        let plugin = write_plugin(
+            temp_dir.path(),
+            &format!(
+                "#!/bin/sh\nif [ \"$1\" = plan ]; then echo '{{\"synopsis\": \"Enroll\"}}'; else echo \"$1\" >> {}; fi\n",
+                log.display()
+            ),
+        )
+        .await?;
+
+        let mut action = RunPlugin::plan(&plugin).await?;
+        assert_eq!(action.state, ActionState::Uncompleted);
+        assert_eq!(action.action.tracing_synopsis(), "Enroll");
+        action.try_execute().await?;
+        action.try_revert().await?;
+        assert_eq!(tokio::fs::read_to_string(&log).await?, "execute\nrevert\n");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn refuses_changed_plugins() -> eyre::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let plugin = write_plugin(
+            temp_dir.path(),
+            "#!/bin/sh\n[ \"$1\" = plan ] && echo '{\"synopsis\": \"Enroll\"}'\nexit 0\n",
+        )
+        .await?;
+
+        let mut action = RunPlugin::plan(&plugin).await?;
+        write_plugin(temp_dir.path(), "#!/bin/sh\nexit 0\n").await?;
+        assert!(action.try_execute().await.is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,215 @@
+use std::{
+    path::{Path, PathBuf},
+    process::Stdio,
+};
+
+use tokio::process::Command;
+use tracing::{span, Span};
+
+use crate::{
+    action::{
+        base::{install_profile_packages::wait_for_daemon, run_plugin::plugin_sha256},
+        Action, ActionDescription, ActionError, ActionErrorKind, ActionTag, DryRunStep,
+        StatefulAction,
+    },
+    execute_command,
+    manifest::{DEFAULT_PROFILE, NIX_BIN},
+    plan::{JOURNAL_LOCATION, RECEIPT_LOCATION},
+    settings::PROXY_ENV,
+};
+
+const STORE_DIR: &str = "/nix/store";
+
+/**
+Run a post-install hook, an executable run once Nix is installed and the daemon answers (eg to
+configure a binary cache, or register the host), with the install described in its environment
+
+* `NIX_INSTALLER_NIX_VERSION`: the version of Nix installed, like `2.24.9`
+* `NIX_INSTALLER_STORE_DIR`: `/nix/store`
+* `NIX_INSTALLER_PROFILE`: the default profile, its `bin` is also first on `PATH`
+* `NIX_INSTALLER_RECEIPT`: where the receipt is written once the install finishes
+* `NIX_INSTALLER_JOURNAL`: where the plan (with the state of each action) is until then
+* `NIX_INSTALLER_VERSION`: the version of `nix-installer`
+
+The hook's SHA-256 is recorded when planned, and it won't be run if it changed since. Nothing is
+undone on uninstall.
+ */
+#[derive(Debug, serde::Deserialize, serde::Serialize, Clone)]
+#[serde(tag = "action_name", rename = "run_post_install_hook")]
+pub struct RunPostInstallHook {
+    path: PathBuf,
+    sha256: String,
+}
+
+impl RunPostInstallHook {
+    #[tracing::instrument(level = "debug", skip_all)]
+    pub async fn plan(path: impl AsRef<Path>) -> Result<StatefulAction<Self>, ActionError> {
+        let path = path.as_ref().to_path_buf();
+        let sha256 = plugin_sha256(&path).await.map_err(Self::error)?;
+        Ok(StatefulAction::uncompleted(Self { path, sha256 }))
+    }
+
+    /// Run the hook with `env`, if it is still the hook which was planned
+    async fn run(&self, env: &[(&str, String)]) -> Result<(), ActionErrorKind> {
+        let sha256 = plugin_sha256(&self.path).await?;
+        if sha256 != self.sha256 {
+            return Err(RunPostInstallHookError::Changed(self.path.clone()).into());
+        }
+
+        let output = execute_command(
+            Command::new(&self.path)
+                .process_group(0)
+                // The proxy may carry its password, which is no business of the hook
+                .env_remove(PROXY_ENV)
+                .envs(env.iter().map(|(key, value)| (*key, value)))
+                .stdin(Stdio::null()),
+        )
+        .await?;
+        for line in String::from_utf8_lossy(&output.stdout).lines() {
+            tracing::info!("{}: {line}", self.path.display());
+        }
+        Ok(())
+    }
+}
+
+#[async_trait::async_trait]
+#[typetag::serde(name = "run_post_install_hook")]
+impl Action for RunPostInstallHook {
+    fn action_tag() -> ActionTag {
+        ActionTag("run_post_install_hook")
+    }
+    fn tracing_synopsis(&self) -> String {
+        format!("Run the post-install hook `{}`", self.path.display())
+    }
+
+    fn tracing_span(&self) -> Span {
+        span!(
+            tracing::Level::DEBUG,
+            "run_post_install_hook",
+            path = tracing::field::display(self.path.display()),
+        )
+    }
+
+    fn execute_description(&self) -> Vec<ActionDescription> {
+        vec![ActionDescription::new(
+            self.tracing_synopsis(),
+            vec![
+                "Run once the Nix daemon answers, with the install described in `NIX_INSTALLER_*` environment variables".to_string(),
+                "It isn't undone on uninstall".to_string(),
+            ],
+        )]
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    async fn execute(&mut self) -> Result<(), ActionError> {
+        wait_for_daemon().await.map_err(Self::error)?;
+        let output = execute_command(
+            Command::new(NIX_BIN)
+                .process_group(0)
+                .arg("--version")
+                .stdin(Stdio::null()),
+        )
+        .await
+        .map_err(Self::error)?;
+        let nix_version = nix_version_of(&String::from_utf8_lossy(&output.stdout))
+            .unwrap_or_default()
+            .to_string();
+
+        self.run(&hook_env(nix_version)).await.map_err(Self::error)
+    }
+
+    fn dry_run(&self) -> Vec<DryRunStep> {
+        vec![DryRunStep::Command(vec![self.path.display().to_string()])]
+    }
+
+    fn revert_description(&self) -> Vec<ActionDescription> {
+        vec![]
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    async fn revert(&mut self) -> Result<(), ActionError> {
+        Ok(())
+    }
+}
+
+/// The environment describing the install to the hook
+fn hook_env(nix_version: String) -> Vec<(&'static str, String)> {
+    let path = match std::env::var("PATH") {
+        Ok(path) if !path.is_empty() => format!("{DEFAULT_PROFILE}/bin:{path}"),
+        _ => format!("{DEFAULT_PROFILE}/bin:/usr/bin:/bin:/usr/sbin:/sbin"),
+    };
+    vec![
+        ("NIX_INSTALLER_NIX_VERSION", nix_version),
+        ("NIX_INSTALLER_STORE_DIR", STORE_DIR.to_string()),
+        ("NIX_INSTALLER_PROFILE", DEFAULT_PROFILE.to_string()),
+        ("NIX_INSTALLER_RECEIPT", RECEIPT_LOCATION.to_string()),
+        ("NIX_INSTALLER_JOURNAL", JOURNAL_LOCATION.to_string()),
+        (
+            "NIX_INSTALLER_VERSION",
+            env!("CARGO_PKG_VERSION").to_string(),
+        ),
+        ("PATH", path),
+    ]
+}
+
+/// The version from `nix --version`, like `2.24.9` from `nix (Nix) 2.24.9`
+fn nix_version_of(output: &str) -> Option<&str> {
+    output.lines().next()?.split_whitespace().last()
+}
+
+#[non_exhaustive]
+#[derive(Debug, thiserror::Error)]
+pub enum RunPostInstallHookError {
+    #[error("The post-install hook `{0}` changed since it was planned, plan the install again to run it")]
+    Changed(PathBuf),
+}
+
+impl From<RunPostInstallHookError> for ActionErrorKind {
+    fn from(val: RunPostInstallHookError) -> Self {
+        ActionErrorKind::Custom(Box::new(val))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::os::unix::fs::PermissionsExt;
+
+    use super::{hook_env, nix_version_of, RunPostInstallHook};
+
+    #[test]
+    fn parses_nix_version() {
+        assert_eq!(nix_version_of("nix (Nix) 2.24.9\n"), Some("2.24.9"));
+        assert_eq!(
+            nix_version_of("nix (Determinate Nix 3.1.0) 2.27.1\n"),
+            Some("2.27.1")
+        );
+        assert_eq!(nix_version_of(""), None);
+    }
+
+    #[tokio::test]
+    async fn runs_hooks_with_install_env() -> eyre::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let out = temp_dir.path().join("out");
+        let hook = temp_dir.path().join("hook");
+        tokio::fs::write(
+            &hook,
+            format!(
+                "#!/bin/sh\necho \"$NIX_INSTALLER_NIX_VERSION $NIX_INSTALLER_RECEIPT\" > {}\n",
+                out.display()
+            ),
+        )
+        .await?;
+        tokio::fs::set_permissions(&hook, std::fs::Permissions::from_mode(0o755)).await?;
+
+        let action = RunPostInstallHook::plan(&hook).await?;
+        action.action.run(&hook_env("2.24.9".into())).await?;
+        assert_eq!(
+            tokio::fs::read_to_string(&out).await?,
+            "2.24.9 /nix/receipt.json\n"
+        );
+
+        tokio::fs::write(&hook, "#!/bin/sh\nexit 0\n").await?;
+        assert!(action.action.run(&hook_env("2.24.9".into())).await.is_err());
+        Ok(())
+    }
+}
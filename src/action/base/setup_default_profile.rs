@@ -3,6 +3,7 @@ use std::path::PathBuf;
 use crate::{
     action::{common::ConfigureNix, ActionError, ActionErrorKind, ActionTag, StatefulAction},
     execute_command, set_env,
+    settings::Distribution,
 };
 
 use tokio::{io::AsyncWriteExt, process::Command};
@@ -17,12 +18,21 @@ Setup the default Nix profile with `nss-cacert` and `nix` itself.
 #[serde(tag = "action_name", rename = "setup_default_profile")]
 pub struct SetupDefaultProfile {
     unpacked_path: PathBuf,
+    #[serde(default)]
+    distribution: Distribution,
 }
 
 impl SetupDefaultProfile {
     #[tracing::instrument(level = "debug", skip_all)]
-    pub async fn plan(unpacked_path: PathBuf) -> Result<StatefulAction<Self>, ActionError> {
-        Ok(Self { unpacked_path }.into())
+    pub async fn plan(
+        unpacked_path: PathBuf,
+        distribution: Distribution,
+    ) -> Result<StatefulAction<Self>, ActionError> {
+        Ok(Self {
+            unpacked_path,
+            distribution,
+        }
+        .into())
     }
 }
 
@@ -51,11 +61,15 @@ impl Action for SetupDefaultProfile {
     #[tracing::instrument(level = "debug", skip_all)]
     async fn execute(&mut self) -> Result<(), ActionError> {
         let (nix_pkg, nss_ca_cert_pkg) =
-            ConfigureNix::find_nix_and_ca_cert(&self.unpacked_path).await?;
-        let found_nix_paths = glob::glob(&format!("{}/nix-*", self.unpacked_path.display()))
-            .map_err(Self::error)?
-            .collect::<Result<Vec<_>, _>>()
-            .map_err(Self::error)?;
+            ConfigureNix::find_nix_and_ca_cert(&self.unpacked_path, self.distribution).await?;
+        let found_nix_paths = glob::glob(&format!(
+            "{}/{}-*",
+            self.unpacked_path.display(),
+            self.distribution.package_name()
+        ))
+        .map_err(Self::error)?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(Self::error)?;
         if found_nix_paths.len() != 1 {
             return Err(Self::error(ActionErrorKind::MalformedBinaryTarball));
         }
@@ -0,0 +1,183 @@
+use serde_json::Value;
+use tracing::{span, Span};
+
+use crate::action::{Action, ActionDescription, ActionError, ActionErrorKind, ActionTag};
+
+/** An action from a receipt which this `nix-installer` can't read, like one added by a newer version
+
+It can't be executed, and on revert the steps to undo it by hand are only reported (see
+[`InstallPlan::from_receipt_lossy`](crate::InstallPlan::from_receipt_lossy)).
+*/
+#[derive(Debug, serde::Deserialize, serde::Serialize, Clone)]
+#[serde(tag = "action_name", rename = "unknown_action")]
+pub struct UnknownAction {
+    /// The action as it was in the receipt
+    original: Value,
+    /// Why it couldn't be read
+    reason: String,
+}
+
+impl UnknownAction {
+    pub(crate) fn new(original: Value, reason: String) -> Self {
+        Self { original, reason }
+    }
+
+    /// The `action_name` it had in the receipt
+    pub fn name(&self) -> &str {
+        self.original
+            .get("action_name")
+            .and_then(Value::as_str)
+            .unwrap_or("unnamed")
+    }
+
+    /// The steps to undo it by hand, as best they can be told from the receipt
+    pub fn manual_steps(&self) -> Vec<String> {
+        let mut steps = vec![];
+        manual_steps(&self.original, &mut steps);
+        steps
+    }
+}
+
+/// Steps to undo `action`, and the completed actions within it
+fn manual_steps(action: &Value, steps: &mut Vec<String>) {
+    let Some(object) = action.as_object() else {
+        return;
+    };
+    let mut has_children = false;
+    for value in object.values() {
+        let children = match value {
+            Value::Array(values) => values.iter().collect::<Vec<_>>(),
+            value => vec![value],
+        };
+        for child in children {
+            let (Some(child_action), Some(state)) = (
+                child.get("action"),
+                child.get("state").and_then(Value::as_str),
+            ) else {
+                continue;
+            };
+            has_children = true;
+            if state == "Completed" {
+                manual_steps(child_action, steps);
+            }
+        }
+    }
+
+    let Some(action_name) = object.get("action_name").and_then(Value::as_str) else {
+        return;
+    };
+    let field = |name: &str| object.get(name).and_then(Value::as_str);
+    if let (Some(name), true) = (field("name"), action_name.contains("user")) {
+        steps.push(format!("Delete the user `{name}`"));
+    } else if let (Some(name), true) = (field("name"), action_name.contains("group")) {
+        steps.push(format!("Delete the group `{name}`"));
+    } else if let Some(path) = field("path")
+        .or_else(|| field("dest"))
+        .or_else(|| field("service_dest"))
+    {
+        steps.push(format!("Undo `{action_name}` at `{path}`"));
+    } else if !has_children {
+        steps.push(format!("Undo `{action_name}`"));
+    }
+}
+
+#[async_trait::async_trait]
+#[typetag::serde(name = "unknown_action")]
+impl Action for UnknownAction {
+    fn action_tag() -> ActionTag {
+        ActionTag("unknown_action")
+    }
+    fn tracing_synopsis(&self) -> String {
+        format!(
+            "Undo `{}` by hand, it was recorded by a different `nix-installer`",
+            self.name()
+        )
+    }
+
+    fn tracing_span(&self) -> Span {
+        span!(
+            tracing::Level::DEBUG,
+            "unknown_action",
+            name = self.name(),
+            reason = tracing::field::display(&self.reason),
+        )
+    }
+
+    fn execute_description(&self) -> Vec<ActionDescription> {
+        vec![]
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    async fn execute(&mut self) -> Result<(), ActionError> {
+        Err(Self::error(UnknownActionError::CannotExecute(
+            self.name().to_string(),
+        )))
+    }
+
+    fn revert_description(&self) -> Vec<ActionDescription> {
+        vec![ActionDescription::new(
+            self.tracing_synopsis(),
+            self.manual_steps(),
+        )]
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    async fn revert(&mut self) -> Result<(), ActionError> {
+        // The steps are reported once the rest of the uninstall is done
+        tracing::warn!(
+            reason = %self.reason,
+            "Could not undo `{}`, it was recorded by a different `nix-installer`",
+            self.name()
+        );
+        Ok(())
+    }
+}
+
+#[non_exhaustive]
+#[derive(Debug, thiserror::Error)]
+pub enum UnknownActionError {
+    #[error(
+        "`{0}` was recorded by a different `nix-installer`, and can't be executed by this one"
+    )]
+    CannotExecute(String),
+}
+
+impl From<UnknownActionError> for ActionErrorKind {
+    fn from(val: UnknownActionError) -> Self {
+        ActionErrorKind::Custom(Box::new(val))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::UnknownAction;
+
+    #[test]
+    fn lists_manual_steps_of_completed_actions() {
+        let action = UnknownAction::new(
+            serde_json::json!({
+                "action_name": "provision_frobnicator",
+                "create_user": {
+                    "action": { "action_name": "create_user", "name": "frob", "uid": 301 },
+                    "state": "Completed",
+                },
+                "create_files": [{
+                    "action": { "action_name": "create_file", "path": "/etc/frob.conf" },
+                    "state": "Completed",
+                }, {
+                    "action": { "action_name": "create_file", "path": "/etc/frob.d/extra.conf" },
+                    "state": "Uncompleted",
+                }],
+            }),
+            "unknown variant `provision_frobnicator`".into(),
+        );
+        assert_eq!(action.name(), "provision_frobnicator");
+        assert_eq!(
+            action.manual_steps(),
+            vec![
+                "Undo `create_file` at `/etc/frob.conf`".to_string(),
+                "Delete the user `frob`".to_string(),
+            ]
+        );
+    }
+}
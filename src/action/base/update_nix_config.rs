@@ -0,0 +1,278 @@
+use std::{
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+};
+
+use indexmap::IndexMap;
+use nix_config_parser::NixConfig;
+use tracing::{span, Span};
+
+use crate::{
+    action::{
+        base::create_or_merge_nix_config::CreateOrMergeNixConfigError, Action, ActionDescription,
+        ActionError, ActionErrorKind, ActionTag, DryRunStep, StatefulAction,
+    },
+    audit,
+};
+
+/**
+Set (replacing any existing values of) settings in an existing `nix.conf`
+
+Unlike [`CreateOrMergeNixConfig`](crate::action::base::CreateOrMergeNixConfig), existing values are
+overwritten, and restored on revert.
+*/
+#[derive(Debug, serde::Deserialize, serde::Serialize, Clone)]
+#[serde(tag = "action_name", rename = "update_nix_config")]
+pub struct UpdateNixConfig {
+    path: PathBuf,
+    settings: IndexMap<String, String>,
+    /// The values before updating, `None` if the setting was unset
+    previous: IndexMap<String, Option<String>>,
+}
+
+impl UpdateNixConfig {
+    #[tracing::instrument(level = "debug", skip_all)]
+    pub async fn plan(
+        path: impl AsRef<Path>,
+        settings: IndexMap<String, String>,
+    ) -> Result<StatefulAction<Self>, ActionError> {
+        let path = path.as_ref().to_path_buf();
+        let existing = NixConfig::parse_file(&path)
+            .map_err(CreateOrMergeNixConfigError::ParseNixConfig)
+            .map_err(Self::error)?;
+
+        // Only the settings which actually change are kept
+        let settings = settings
+            .into_iter()
+            .filter(|(name, value)| existing.settings().get(name) != Some(value))
+            .collect::<IndexMap<_, _>>();
+        let previous = settings
+            .keys()
+            .map(|name| (name.clone(), existing.settings().get(name).cloned()))
+            .collect();
+
+        let this = Self {
+            path,
+            settings,
+            previous,
+        };
+        if this.settings.is_empty() {
+            tracing::debug!(
+                "Setting Nix configurations in `{}` already complete",
+                this.path.display()
+            );
+            return Ok(StatefulAction::completed(this));
+        }
+        Ok(StatefulAction::uncompleted(this))
+    }
+
+    /// If there is nothing to change
+    pub fn is_empty(&self) -> bool {
+        self.settings.is_empty()
+    }
+
+    async fn write(&self, changes: &IndexMap<String, Option<String>>) -> Result<(), ActionError> {
+        let contents = tokio::fs::read_to_string(&self.path)
+            .await
+            .map_err(|e| Self::error(ActionErrorKind::Read(self.path.clone(), e)))?;
+        let mode = tokio::fs::metadata(&self.path)
+            .await
+            .map_err(|e| Self::error(ActionErrorKind::GettingMetadata(self.path.clone(), e)))?
+            .permissions()
+            .mode();
+
+        // Written beside the original then renamed over it, so the daemon never reads a partial file
+        let mut temp_file_path = self.path.clone();
+        temp_file_path.set_extension("nix-installer-tmp");
+        let contents = apply_changes(&contents, changes);
+        tokio::fs::write(&temp_file_path, &contents)
+            .await
+            .map_err(|e| Self::error(ActionErrorKind::Write(temp_file_path.clone(), e)))?;
+        tokio::fs::set_permissions(&temp_file_path, std::fs::Permissions::from_mode(mode))
+            .await
+            .map_err(|e| {
+                Self::error(ActionErrorKind::SetPermissions(
+                    mode,
+                    temp_file_path.clone(),
+                    e,
+                ))
+            })?;
+        tokio::fs::rename(&temp_file_path, &self.path)
+            .await
+            .map_err(|e| {
+                Self::error(ActionErrorKind::Rename(
+                    temp_file_path.clone(),
+                    self.path.clone(),
+                    e,
+                ))
+            })?;
+        audit::file_written(&self.path, contents.as_bytes());
+        Ok(())
+    }
+}
+
+#[async_trait::async_trait]
+#[typetag::serde(name = "update_nix_config")]
+impl Action for UpdateNixConfig {
+    fn action_tag() -> ActionTag {
+        ActionTag("update_nix_config")
+    }
+    fn tracing_synopsis(&self) -> String {
+        format!("Update nix.conf file `{}`", self.path.display())
+    }
+
+    fn tracing_span(&self) -> Span {
+        span!(
+            tracing::Level::DEBUG,
+            "update_nix_config",
+            path = tracing::field::display(self.path.display()),
+        )
+    }
+
+    fn execute_description(&self) -> Vec<ActionDescription> {
+        vec![ActionDescription::new(
+            self.tracing_synopsis(),
+            self.settings
+                .iter()
+                .map(|(name, value)| match self.previous.get(name) {
+                    Some(Some(previous)) => {
+                        format!("Change `{name}` from \"{previous}\" to \"{value}\"")
+                    },
+                    _ => format!("Set `{name}` to \"{value}\""),
+                })
+                .collect(),
+        )]
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    async fn execute(&mut self) -> Result<(), ActionError> {
+        let changes = self
+            .settings
+            .iter()
+            .map(|(name, value)| (name.clone(), Some(value.clone())))
+            .collect();
+        self.write(&changes).await
+    }
+
+    fn dry_run(&self) -> Vec<DryRunStep> {
+        let changes = self
+            .settings
+            .iter()
+            .map(|(name, value)| (name.clone(), Some(value.clone())))
+            .collect();
+        match std::fs::read_to_string(&self.path) {
+            Ok(contents) => vec![DryRunStep::WriteFile {
+                path: self.path.clone(),
+                mode: std::fs::metadata(&self.path)
+                    .ok()
+                    .map(|metadata| metadata.permissions().mode()),
+                contents: apply_changes(&contents, &changes),
+            }],
+            Err(err) => vec![DryRunStep::Other(format!(
+                "{} (could not be rendered: {err})",
+                self.tracing_synopsis()
+            ))],
+        }
+    }
+
+    fn revert_description(&self) -> Vec<ActionDescription> {
+        vec![ActionDescription::new(
+            format!(
+                "Restore the previous values of {} in `{}`",
+                self.settings
+                    .keys()
+                    .map(|name| format!("`{name}`"))
+                    .collect::<Vec<_>>()
+                    .join(", "),
+                self.path.display()
+            ),
+            vec![],
+        )]
+    }
+
+    fn revert_dry_run(&self) -> Vec<DryRunStep> {
+        match std::fs::read_to_string(&self.path) {
+            Ok(contents) => vec![DryRunStep::WriteFile {
+                path: self.path.clone(),
+                mode: std::fs::metadata(&self.path)
+                    .ok()
+                    .map(|metadata| metadata.permissions().mode()),
+                contents: apply_changes(&contents, &self.previous),
+            }],
+            // Already removed along with the rest of the Nix configuration
+            Err(_) => vec![],
+        }
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    async fn revert(&mut self) -> Result<(), ActionError> {
+        if !self.path.exists() {
+            // Already removed along with the rest of the Nix configuration
+            return Ok(());
+        }
+        self.write(&self.previous.clone()).await
+    }
+}
+
+/// Replace (or remove, for `None`) the lines setting each of `changes`, appending settings which weren't found
+///
+/// A comment at the end of a replaced line is kept.
+fn apply_changes(contents: &str, changes: &IndexMap<String, Option<String>>) -> String {
+    let mut remaining = changes.clone();
+    let mut buf = String::new();
+    for line in contents.lines() {
+        let (setting, comment) = match line.find('#') {
+            Some(index) => line.split_at(index),
+            None => (line, ""),
+        };
+        let name = setting.split_once('=').map(|(name, _)| name.trim());
+        match name.and_then(|name| remaining.swap_remove_entry(name)) {
+            Some((name, Some(value))) if comment.is_empty() => {
+                buf.push_str(&format!("{name} = {value}\n"))
+            },
+            Some((name, Some(value))) => buf.push_str(&format!("{name} = {value} {comment}\n")),
+            Some((_, None)) => (),
+            None => {
+                buf.push_str(line);
+                buf.push('\n');
+            },
+        }
+    }
+    for (name, value) in remaining {
+        if let Some(value) = value {
+            buf.push_str(&format!("{name} = {value}\n"));
+        }
+    }
+    buf
+}
+
+#[cfg(test)]
+mod test {
+    use indexmap::IndexMap;
+
+    use super::apply_changes;
+
+    #[test]
+    fn applies_changes() {
+        let contents = "\
+            # Generated by https://github.com/DeterminateSystems/nix-installer.\n\
+            build-users-group = nixbld\n\
+            trusted-users = root # Just root\n\
+            max-jobs = 4\n\
+        ";
+        let changes = IndexMap::from([
+            ("trusted-users".to_string(), Some("root @wheel".to_string())),
+            ("max-jobs".to_string(), None),
+            ("cores".to_string(), Some("2".to_string())),
+        ]);
+        assert_eq!(
+            apply_changes(contents, &changes),
+            "\
+            # Generated by https://github.com/DeterminateSystems/nix-installer.\n\
+            build-users-group = nixbld\n\
+            trusted-users = root @wheel # Just root\n\
+            cores = 2\n\
+            "
+        );
+    }
+}
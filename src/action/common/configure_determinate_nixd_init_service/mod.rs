@@ -1,5 +1,5 @@
 use std::collections::HashMap;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use serde::{Deserialize, Serialize};
 use tokio::io::AsyncWriteExt;
@@ -7,7 +7,10 @@ use tracing::{span, Span};
 
 use crate::action::common::configure_init_service::{SocketFile, UnitSrc};
 use crate::action::{common::ConfigureInitService, Action, ActionDescription};
-use crate::action::{ActionError, ActionErrorKind, ActionTag, StatefulAction};
+use crate::action::{
+    ActionError, ActionErrorKind, ActionTag, Artifact, DryRunStep, StatefulAction,
+};
+use crate::drift::DriftedAction;
 use crate::settings::InitSystem;
 
 // Linux
@@ -35,6 +38,8 @@ impl ConfigureDeterminateNixdInitService {
     pub async fn plan(
         init: InitSystem,
         start_daemon: bool,
+        ssl_cert_file: Option<PathBuf>,
+        daemon_env: Vec<(String, String)>,
     ) -> Result<StatefulAction<Self>, ActionError> {
         let service_dest: Option<PathBuf> = match init {
             InitSystem::Launchd => Some(DARWIN_NIXD_DAEMON_DEST.into()),
@@ -68,6 +73,8 @@ impl ConfigureDeterminateNixdInitService {
                     dest: "/etc/systemd/system/determinate-nixd.socket".into(),
                 },
             ],
+            ssl_cert_file,
+            daemon_env,
         )
         .await
         .map_err(Self::error)?;
@@ -151,6 +158,32 @@ impl Action for ConfigureDeterminateNixdInitService {
         Ok(())
     }
 
+    fn dry_run(&self) -> Vec<DryRunStep> {
+        let mut steps = vec![];
+        if self.init == InitSystem::Launchd {
+            steps.push(crate::action::macos::plist_dry_run(
+                Path::new(DARWIN_NIXD_DAEMON_DEST),
+                &generate_plist(),
+            ));
+        } else if self.init == InitSystem::Systemd {
+            steps.push(DryRunStep::WriteFile {
+                path: PathBuf::from(LINUX_NIXD_DAEMON_DEST),
+                mode: None,
+                contents: include_str!("./nix-daemon.determinate-nixd.service").to_string(),
+            });
+        }
+        steps.extend(self.configure_init_service.dry_run());
+        steps
+    }
+
+    fn artifacts(&self) -> Vec<Artifact> {
+        self.configure_init_service.artifacts()
+    }
+
+    async fn drift(&self) -> Vec<DriftedAction> {
+        self.configure_init_service.drift().await
+    }
+
     fn revert_description(&self) -> Vec<ActionDescription> {
         vec![ActionDescription::new(
             "Remove the Determinate Nix daemon".to_string(),
@@ -158,6 +191,20 @@ impl Action for ConfigureDeterminateNixdInitService {
         )]
     }
 
+    fn revert_dry_run(&self) -> Vec<DryRunStep> {
+        let mut steps = self.configure_init_service.revert_dry_run();
+        match self.init {
+            InitSystem::Launchd => {
+                steps.push(DryRunStep::Remove(PathBuf::from(DARWIN_NIXD_DAEMON_DEST)))
+            },
+            InitSystem::Systemd => {
+                steps.push(DryRunStep::Remove(PathBuf::from(LINUX_NIXD_DAEMON_DEST)))
+            },
+            InitSystem::None => (),
+        }
+        steps
+    }
+
     #[tracing::instrument(level = "debug", skip_all)]
     async fn revert(&mut self) -> Result<(), ActionError> {
         self.configure_init_service.try_revert().await?;
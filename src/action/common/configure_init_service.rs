@@ -1,18 +1,32 @@
 use std::path::Path;
 use std::path::PathBuf;
+use std::time::Duration;
 
 use tokio::process::Command;
 use tracing::{span, Span};
 
 use crate::action::macos::DARWIN_LAUNCHD_DOMAIN;
-use crate::action::{ActionError, ActionErrorKind, ActionTag, StatefulAction};
-use crate::execute_command;
+use crate::action::{
+    ActionError, ActionErrorKind, ActionTag, Artifact, DryRunStep, StatefulAction,
+};
+use crate::audit::{self, AuditEvent};
+use crate::drift::{Drift, DriftedAction};
+use crate::{execute_command, execute_command_with_retries};
 
 use crate::action::{Action, ActionDescription};
 use crate::settings::InitSystem;
 
 const TMPFILES_SRC: &str = "/nix/var/nix/profiles/default/lib/tmpfiles.d/nix-daemon.conf";
 const TMPFILES_DEST: &str = "/etc/tmpfiles.d/nix-daemon.conf";
+const SYSTEMD_DROPIN_DIR: &str = "/etc/systemd/system/nix-daemon.service.d";
+const SYSTEMD_SSL_CERT_FILE_DROPIN: &str =
+    "/etc/systemd/system/nix-daemon.service.d/nix-ssl-cert-file.conf";
+const SYSTEMD_DAEMON_ENV_DROPIN: &str =
+    "/etc/systemd/system/nix-daemon.service.d/nix-installer-env.conf";
+/// How many times a failed `launchctl` or `systemctl` command is retried
+const INIT_COMMAND_RETRIES: u32 = 5;
+/// The wait before the first retry, doubling after each
+const INIT_COMMAND_BACKOFF: Duration = Duration::from_millis(250);
 
 #[derive(Debug, serde::Deserialize, serde::Serialize, Clone)]
 pub struct SocketFile {
@@ -40,6 +54,11 @@ pub struct ConfigureInitService {
     service_name: Option<String>,
     service_dest: Option<PathBuf>,
     socket_files: Vec<SocketFile>,
+    /// Exported to the daemon as `NIX_SSL_CERT_FILE`
+    ssl_cert_file: Option<PathBuf>,
+    /// Exported to the daemon (`--daemon-env`)
+    #[serde(default)]
+    daemon_env: Vec<(String, String)>,
 }
 
 impl ConfigureInitService {
@@ -89,6 +108,7 @@ impl ConfigureInitService {
         Ok(())
     }
 
+    #[allow(clippy::too_many_arguments)]
     #[tracing::instrument(level = "debug", skip_all)]
     pub async fn plan(
         init: InitSystem,
@@ -97,6 +117,8 @@ impl ConfigureInitService {
         service_dest: Option<PathBuf>,
         service_name: Option<String>,
         socket_files: Vec<SocketFile>,
+        ssl_cert_file: Option<PathBuf>,
+        daemon_env: Vec<(String, String)>,
     ) -> Result<StatefulAction<Self>, ActionError> {
         match init {
             InitSystem::Launchd => {
@@ -146,9 +168,37 @@ impl ConfigureInitService {
             service_dest,
             service_name,
             socket_files,
+            ssl_cert_file,
+            daemon_env,
         }
         .into())
     }
+
+    /// The drop-ins for the systemd unit this writes, in `SYSTEMD_DROPIN_DIR`
+    fn dropins(&self) -> Vec<&'static str> {
+        let mut dropins = vec![];
+        if self.ssl_cert_file.is_some() {
+            dropins.push(SYSTEMD_SSL_CERT_FILE_DROPIN);
+        }
+        if !self.daemon_env.is_empty() {
+            dropins.push(SYSTEMD_DAEMON_ENV_DROPIN);
+        }
+        dropins
+    }
+
+    /// The variables set in the `EnvironmentVariables` of the launchd plist
+    fn launchd_environment(&self) -> Vec<(String, String)> {
+        self.ssl_cert_file
+            .iter()
+            .map(|ssl_cert_file| {
+                (
+                    "NIX_SSL_CERT_FILE".to_string(),
+                    ssl_cert_file.display().to_string(),
+                )
+            })
+            .chain(self.daemon_env.iter().cloned())
+            .collect()
+    }
 }
 
 #[async_trait::async_trait]
@@ -204,6 +254,18 @@ impl Action for ConfigureInitService {
                         },
                     }
                 }
+                if let Some(ssl_cert_file) = &self.ssl_cert_file {
+                    explanation.push(format!(
+                        "Create `{SYSTEMD_SSL_CERT_FILE_DROPIN}` setting `NIX_SSL_CERT_FILE={}`",
+                        ssl_cert_file.display()
+                    ));
+                }
+                if !self.daemon_env.is_empty() {
+                    explanation.push(format!(
+                        "Create `{SYSTEMD_DAEMON_ENV_DROPIN}` setting {}",
+                        describe_env(&self.daemon_env)
+                    ));
+                }
                 explanation.push("Run `systemctl daemon-reload`".to_string());
 
                 if self.start_daemon {
@@ -225,6 +287,13 @@ impl Action for ConfigureInitService {
                             .display(),
                     ));
                 }
+                let environment = self.launchd_environment();
+                if !environment.is_empty() {
+                    explanation.push(format!(
+                        "Set {} in the daemon's environment",
+                        describe_env(&environment)
+                    ));
+                }
 
                 if self.start_daemon {
                     explanation.push(format!(
@@ -244,6 +313,7 @@ impl Action for ConfigureInitService {
 
     #[tracing::instrument(level = "debug", skip_all)]
     async fn execute(&mut self) -> Result<(), ActionError> {
+        let launchd_environment = self.launchd_environment();
         let Self {
             init,
             start_daemon,
@@ -251,6 +321,8 @@ impl Action for ConfigureInitService {
             service_dest,
             service_name,
             socket_files,
+            ssl_cert_file,
+            daemon_env,
         } = self;
 
         match init {
@@ -273,6 +345,13 @@ impl Action for ConfigureInitService {
                                 e,
                             ))
                         })?;
+                    audit::file_written_on_disk(service_dest).await;
+                }
+
+                if !launchd_environment.is_empty() {
+                    set_launchd_environment(service_dest, &launchd_environment)
+                        .await
+                        .map_err(Self::error)?;
                 }
 
                 crate::action::macos::retry_bootstrap(domain, service, service_dest)
@@ -283,11 +362,11 @@ impl Action for ConfigureInitService {
                     .await
                     .map_err(Self::error)?;
                 if is_disabled {
-                    execute_command(
+                    execute_init_command(
                         Command::new("launchctl")
                             .process_group(0)
                             .arg("enable")
-                            .arg(&format!("{domain}/{service}"))
+                            .arg(format!("{domain}/{service}"))
                             .stdin(std::process::Stdio::null()),
                     )
                     .await
@@ -295,12 +374,12 @@ impl Action for ConfigureInitService {
                 }
 
                 if *start_daemon {
-                    execute_command(
+                    execute_init_command(
                         Command::new("launchctl")
                             .process_group(0)
                             .arg("kickstart")
                             .arg("-k")
-                            .arg(&format!("{domain}/{service}"))
+                            .arg(format!("{domain}/{service}"))
                             .stdin(std::process::Stdio::null()),
                     )
                     .await
@@ -313,7 +392,7 @@ impl Action for ConfigureInitService {
                     .expect("service_dest should be defined for systemd");
 
                 if *start_daemon {
-                    execute_command(
+                    execute_init_command(
                         Command::new("systemctl")
                             .process_group(0)
                             .arg("daemon-reload")
@@ -366,6 +445,10 @@ impl Action for ConfigureInitService {
                             )
                         })
                         .map_err(Self::error)?;
+                    audit::record(AuditEvent::SymlinkCreated {
+                        path: PathBuf::from(TMPFILES_DEST),
+                        target: PathBuf::from(TMPFILES_SRC),
+                    });
                 }
 
                 execute_command(
@@ -394,6 +477,9 @@ impl Action for ConfigureInitService {
                             .await
                             .map_err(|e| ActionErrorKind::Remove(service_dest.into(), e))
                             .map_err(Self::error)?;
+                        audit::record(AuditEvent::FileRemoved {
+                            path: service_dest.to_path_buf(),
+                        });
                     }
                     tracing::trace!(src = %service_src.display(), dest = %service_dest.display(), "Symlinking");
                     tokio::fs::symlink(service_src, service_dest)
@@ -406,6 +492,10 @@ impl Action for ConfigureInitService {
                             )
                         })
                         .map_err(Self::error)?;
+                    audit::record(AuditEvent::SymlinkCreated {
+                        path: service_dest.to_path_buf(),
+                        target: service_src.to_path_buf(),
+                    });
                 }
 
                 for SocketFile { src, dest, .. } in socket_files.iter() {
@@ -418,6 +508,9 @@ impl Action for ConfigureInitService {
                             .await
                             .map_err(|e| ActionErrorKind::Remove(dest.into(), e))
                             .map_err(Self::error)?;
+                        audit::record(AuditEvent::FileRemoved {
+                            path: dest.to_path_buf(),
+                        });
                     }
 
                     match src {
@@ -433,6 +526,10 @@ impl Action for ConfigureInitService {
                                     )
                                 })
                                 .map_err(Self::error)?;
+                            audit::record(AuditEvent::SymlinkCreated {
+                                path: dest.to_path_buf(),
+                                target: src.to_path_buf(),
+                            });
                         },
                         UnitSrc::Literal(content) => {
                             tracing::trace!(src = %content, dest = %dest.display(), "Writing");
@@ -441,12 +538,48 @@ impl Action for ConfigureInitService {
                                 .await
                                 .map_err(|e| ActionErrorKind::Write(dest.clone(), e))
                                 .map_err(Self::error)?;
+                            audit::file_written(dest, content.as_bytes());
                         },
                     }
                 }
 
+                if let Some(ssl_cert_file) = ssl_cert_file {
+                    tracing::trace!(path = SYSTEMD_SSL_CERT_FILE_DROPIN, "Writing");
+                    tokio::fs::create_dir_all(SYSTEMD_DROPIN_DIR)
+                        .await
+                        .map_err(|e| {
+                            ActionErrorKind::CreateDirectory(PathBuf::from(SYSTEMD_DROPIN_DIR), e)
+                        })
+                        .map_err(Self::error)?;
+                    let dropin = ssl_cert_file_dropin(ssl_cert_file);
+                    tokio::fs::write(SYSTEMD_SSL_CERT_FILE_DROPIN, &dropin)
+                        .await
+                        .map_err(|e| {
+                            ActionErrorKind::Write(PathBuf::from(SYSTEMD_SSL_CERT_FILE_DROPIN), e)
+                        })
+                        .map_err(Self::error)?;
+                    audit::file_written(Path::new(SYSTEMD_SSL_CERT_FILE_DROPIN), dropin.as_bytes());
+                }
+                if !daemon_env.is_empty() {
+                    tracing::trace!(path = SYSTEMD_DAEMON_ENV_DROPIN, "Writing");
+                    tokio::fs::create_dir_all(SYSTEMD_DROPIN_DIR)
+                        .await
+                        .map_err(|e| {
+                            ActionErrorKind::CreateDirectory(PathBuf::from(SYSTEMD_DROPIN_DIR), e)
+                        })
+                        .map_err(Self::error)?;
+                    let dropin = daemon_env_dropin(daemon_env);
+                    tokio::fs::write(SYSTEMD_DAEMON_ENV_DROPIN, &dropin)
+                        .await
+                        .map_err(|e| {
+                            ActionErrorKind::Write(PathBuf::from(SYSTEMD_DAEMON_ENV_DROPIN), e)
+                        })
+                        .map_err(Self::error)?;
+                    audit::file_written(Path::new(SYSTEMD_DAEMON_ENV_DROPIN), dropin.as_bytes());
+                }
+
                 if *start_daemon {
-                    execute_command(
+                    execute_init_command(
                         Command::new("systemctl")
                             .process_group(0)
                             .arg("daemon-reload")
@@ -486,6 +619,182 @@ impl Action for ConfigureInitService {
         Ok(())
     }
 
+    fn dry_run(&self) -> Vec<DryRunStep> {
+        let command =
+            |args: &[&str]| DryRunStep::Command(args.iter().map(|arg| arg.to_string()).collect());
+        let mut steps = vec![];
+        match self.init {
+            InitSystem::Systemd => {
+                let daemon_reload = command(&["systemctl", "daemon-reload"]);
+                if self.start_daemon {
+                    steps.push(daemon_reload.clone());
+                }
+                let units = self
+                    .socket_files
+                    .iter()
+                    .map(|SocketFile { name, .. }| format!("`{name}`"))
+                    .chain(std::iter::once("`nix-daemon.service`".to_string()))
+                    .collect::<Vec<_>>();
+                steps.push(DryRunStep::Other(format!(
+                    "Run `systemctl disable` (or `systemctl stop`) on {} if they are already enabled (or active)",
+                    units.join(", ")
+                )));
+                if !Path::new(TMPFILES_DEST).exists() {
+                    steps.push(DryRunStep::Other(format!(
+                        "Symlink `{TMPFILES_SRC}` to `{TMPFILES_DEST}`"
+                    )));
+                }
+                steps.push(command(&[
+                    "systemd-tmpfiles",
+                    "--create",
+                    "--prefix=/nix/var/nix",
+                ]));
+                if let (Some(service_src), Some(service_dest)) =
+                    (&self.service_src, &self.service_dest)
+                {
+                    steps.push(DryRunStep::Other(format!(
+                        "Symlink `{}` to `{}`",
+                        service_src.display(),
+                        service_dest.display()
+                    )));
+                }
+                for SocketFile { src, dest, .. } in &self.socket_files {
+                    match src {
+                        UnitSrc::Path(src) => steps.push(DryRunStep::Other(format!(
+                            "Symlink `{}` to `{}`",
+                            src.display(),
+                            dest.display()
+                        ))),
+                        UnitSrc::Literal(content) => steps.push(DryRunStep::WriteFile {
+                            path: dest.clone(),
+                            mode: None,
+                            contents: content.clone(),
+                        }),
+                    }
+                }
+                if let Some(ssl_cert_file) = &self.ssl_cert_file {
+                    steps.push(DryRunStep::WriteFile {
+                        path: PathBuf::from(SYSTEMD_SSL_CERT_FILE_DROPIN),
+                        mode: None,
+                        contents: ssl_cert_file_dropin(ssl_cert_file),
+                    });
+                }
+                if !self.daemon_env.is_empty() {
+                    steps.push(DryRunStep::WriteFile {
+                        path: PathBuf::from(SYSTEMD_DAEMON_ENV_DROPIN),
+                        mode: None,
+                        contents: daemon_env_dropin(&self.daemon_env),
+                    });
+                }
+                if self.start_daemon {
+                    steps.push(daemon_reload);
+                }
+                for SocketFile { name, src, .. } in &self.socket_files {
+                    let unit = match src {
+                        UnitSrc::Path(path) => path.display().to_string(),
+                        UnitSrc::Literal(_) => name.clone(),
+                    };
+                    let mut argv = vec!["systemctl".to_string(), "enable".to_string(), unit];
+                    if self.start_daemon {
+                        argv.push("--now".to_string());
+                    }
+                    steps.push(DryRunStep::Command(argv));
+                }
+            },
+            InitSystem::Launchd => {
+                let (Some(service_dest), Some(service)) = (&self.service_dest, &self.service_name)
+                else {
+                    return steps;
+                };
+                let service_dest = service_dest.display().to_string();
+                let target = format!("{DARWIN_LAUNCHD_DOMAIN}/{service}");
+                if let Some(service_src) = &self.service_src {
+                    steps.push(DryRunStep::Other(format!(
+                        "Copy `{}` to `{service_dest}`",
+                        service_src.display()
+                    )));
+                }
+                let environment = self.launchd_environment();
+                if !environment.is_empty() {
+                    steps.push(DryRunStep::Other(format!(
+                        "Set {} in the `EnvironmentVariables` of `{service_dest}`",
+                        describe_env(&environment)
+                    )));
+                }
+                steps.push(command(&[
+                    "launchctl",
+                    "bootstrap",
+                    DARWIN_LAUNCHD_DOMAIN,
+                    &service_dest,
+                ]));
+                steps.push(DryRunStep::Other(format!(
+                    "Run `launchctl enable {target}` if it is disabled"
+                )));
+                if self.start_daemon {
+                    steps.push(command(&["launchctl", "kickstart", "-k", &target]));
+                }
+            },
+            InitSystem::None => (),
+        }
+        steps
+    }
+
+    fn artifacts(&self) -> Vec<Artifact> {
+        let mut artifacts = vec![];
+        if let Some(service_dest) = &self.service_dest {
+            let name = self.service_name.clone().or_else(|| {
+                service_dest
+                    .file_name()
+                    .map(|name| name.to_string_lossy().into_owned())
+            });
+            if let Some(name) = name {
+                artifacts.push(Artifact::Service {
+                    name,
+                    path: service_dest.clone(),
+                });
+            }
+        }
+        if self.init == InitSystem::Systemd {
+            for SocketFile { name, dest, .. } in &self.socket_files {
+                artifacts.push(Artifact::Service {
+                    name: name.clone(),
+                    path: dest.clone(),
+                });
+            }
+            artifacts.push(Artifact::File(PathBuf::from(TMPFILES_DEST)));
+            if self.ssl_cert_file.is_some() {
+                artifacts.push(Artifact::File(PathBuf::from(SYSTEMD_SSL_CERT_FILE_DROPIN)));
+            }
+            if !self.daemon_env.is_empty() {
+                artifacts.push(Artifact::File(PathBuf::from(SYSTEMD_DAEMON_ENV_DROPIN)));
+            }
+        }
+        artifacts
+    }
+
+    async fn drift(&self) -> Vec<DriftedAction> {
+        let mut drift = crate::drift::artifact_drift(&self.artifacts()).await;
+        let mut units = vec![];
+        if let (Some(service_src), Some(service_dest)) = (&self.service_src, &self.service_dest) {
+            units.push((UnitSrc::Path(service_src.clone()), service_dest.clone()));
+        }
+        if self.init == InitSystem::Systemd {
+            for SocketFile { src, dest, .. } in &self.socket_files {
+                units.push((src.clone(), dest.clone()));
+            }
+            units.push((
+                UnitSrc::Path(PathBuf::from(TMPFILES_SRC)),
+                PathBuf::from(TMPFILES_DEST),
+            ));
+        }
+        for (src, dest) in units {
+            if let Some(detail) = unit_drift(&src, &dest).await {
+                drift.push(Drift::Modified { path: dest, detail });
+            }
+        }
+        DriftedAction::of(self.tracing_synopsis(), drift)
+    }
+
     fn revert_description(&self) -> Vec<ActionDescription> {
         match self.init {
             InitSystem::Systemd => {
@@ -502,6 +811,9 @@ impl Action for ConfigureInitService {
                         .expect("service_src should be defined for systemd")
                         .display()
                 ));
+                for dropin in self.dropins() {
+                    steps.push(format!("Remove `{dropin}`"));
+                }
                 steps.push("Run `systemd-tempfiles --remove --prefix=/nix/var/nix`".to_string());
                 steps.push("Run `systemctl daemon-reload`".to_string());
 
@@ -525,6 +837,55 @@ impl Action for ConfigureInitService {
         }
     }
 
+    fn revert_dry_run(&self) -> Vec<DryRunStep> {
+        let command =
+            |args: &[&str]| DryRunStep::Command(args.iter().map(|arg| arg.to_string()).collect());
+        let mut steps = vec![];
+        match self.init {
+            InitSystem::Launchd => {
+                if let Some(service_dest) = &self.service_dest {
+                    steps.push(DryRunStep::Command(vec![
+                        "launchctl".into(),
+                        "bootout".into(),
+                        DARWIN_LAUNCHD_DOMAIN.into(),
+                        service_dest.display().to_string(),
+                    ]));
+                }
+            },
+            InitSystem::Systemd => {
+                let units = self
+                    .socket_files
+                    .iter()
+                    .map(|SocketFile { name, .. }| format!("`{name}`"))
+                    .chain(std::iter::once("`nix-daemon.service`".to_string()))
+                    .collect::<Vec<_>>();
+                steps.push(DryRunStep::Other(format!(
+                    "Run `systemctl stop` (and `systemctl disable`) on {} if they are active (or enabled)",
+                    units.join(", ")
+                )));
+                steps.push(command(&[
+                    "systemd-tmpfiles",
+                    "--remove",
+                    "--prefix=/nix/var/nix",
+                ]));
+                for socket in &self.socket_files {
+                    if let UnitSrc::Literal(_) = socket.src {
+                        steps.push(DryRunStep::Remove(socket.dest.clone()));
+                    }
+                }
+                steps.push(DryRunStep::Remove(PathBuf::from(TMPFILES_DEST)));
+                for dropin in self.dropins() {
+                    if Path::new(dropin).exists() {
+                        steps.push(DryRunStep::Remove(PathBuf::from(dropin)));
+                    }
+                }
+                steps.push(command(&["systemctl", "daemon-reload"]));
+            },
+            InitSystem::None => (),
+        }
+        steps
+    }
+
     #[tracing::instrument(level = "debug", skip_all)]
     async fn revert(&mut self) -> Result<(), ActionError> {
         let mut errors = vec![];
@@ -575,7 +936,7 @@ impl Action for ConfigureInitService {
                     let socket_is_enabled = is_enabled(name).await.map_err(Self::error)?;
 
                     if socket_is_active {
-                        if let Err(err) = execute_command(
+                        if let Err(err) = execute_init_command(
                             Command::new("systemctl")
                                 .process_group(0)
                                 .args(["stop", name])
@@ -588,7 +949,7 @@ impl Action for ConfigureInitService {
                     }
 
                     if socket_is_enabled {
-                        if let Err(err) = execute_command(
+                        if let Err(err) = execute_init_command(
                             Command::new("systemctl")
                                 .process_group(0)
                                 .args(["disable", name])
@@ -607,7 +968,7 @@ impl Action for ConfigureInitService {
                     .map_err(Self::error)?;
 
                 if service_is_active {
-                    if let Err(err) = execute_command(
+                    if let Err(err) = execute_init_command(
                         Command::new("systemctl")
                             .process_group(0)
                             .args(["stop", "nix-daemon.service"])
@@ -620,7 +981,7 @@ impl Action for ConfigureInitService {
                 }
 
                 if service_is_enabled {
-                    if let Err(err) = execute_command(
+                    if let Err(err) = execute_init_command(
                         Command::new("systemctl")
                             .process_group(0)
                             .args(["disable", "nix-daemon.service"])
@@ -651,17 +1012,41 @@ impl Action for ConfigureInitService {
                             .await
                             .map_err(|e| ActionErrorKind::Remove(socket.dest.to_path_buf(), e))
                             .map_err(Self::error)?;
+                        audit::record(AuditEvent::FileRemoved {
+                            path: socket.dest.clone(),
+                        });
                     }
                 }
 
-                if let Err(err) = tokio::fs::remove_file(TMPFILES_DEST)
+                match tokio::fs::remove_file(TMPFILES_DEST)
                     .await
                     .map_err(|e| ActionErrorKind::Remove(PathBuf::from(TMPFILES_DEST), e))
                 {
-                    errors.push(err);
+                    Ok(()) => audit::record(AuditEvent::FileRemoved {
+                        path: PathBuf::from(TMPFILES_DEST),
+                    }),
+                    Err(err) => errors.push(err),
                 }
 
-                if let Err(err) = execute_command(
+                let dropins = self.dropins();
+                for dropin in &dropins {
+                    tracing::trace!(path = dropin, "Removing");
+                    match tokio::fs::remove_file(dropin).await {
+                        Ok(()) => audit::record(AuditEvent::FileRemoved {
+                            path: PathBuf::from(dropin),
+                        }),
+                        Err(e) if e.kind() == std::io::ErrorKind::NotFound => (),
+                        Err(e) => errors.push(ActionErrorKind::Remove(PathBuf::from(dropin), e)),
+                    }
+                }
+                // Only if nothing else, like the drop-ins of others, is left in it
+                if !dropins.is_empty() && tokio::fs::remove_dir(SYSTEMD_DROPIN_DIR).await.is_ok() {
+                    audit::record(AuditEvent::DirectoryRemoved {
+                        path: PathBuf::from(SYSTEMD_DROPIN_DIR),
+                    });
+                }
+
+                if let Err(err) = execute_init_command(
                     Command::new("systemctl")
                         .process_group(0)
                         .arg("daemon-reload")
@@ -692,28 +1077,132 @@ impl Action for ConfigureInitService {
     }
 }
 
+/// How the unit (or plist) at `dest` no longer matches `src`, if it still exists
+async fn unit_drift(src: &UnitSrc, dest: &Path) -> Option<String> {
+    match src {
+        UnitSrc::Path(src) => {
+            if let Ok(link) = tokio::fs::read_link(dest).await {
+                return (link != *src).then(|| {
+                    format!(
+                        "it links to `{}` instead of `{}`",
+                        link.display(),
+                        src.display()
+                    )
+                });
+            }
+            // On macOS it is a copy
+            match (tokio::fs::read(src).await, tokio::fs::read(dest).await) {
+                (Ok(src_contents), Ok(dest_contents)) => (src_contents != dest_contents)
+                    .then(|| format!("it differs from `{}`", src.display())),
+                _ => None,
+            }
+        },
+        UnitSrc::Literal(content) => match tokio::fs::read_to_string(dest).await {
+            Ok(dest_contents) => {
+                (*content != dest_contents).then(|| "its contents changed".to_string())
+            },
+            Err(_) => None,
+        },
+    }
+}
+
 #[non_exhaustive]
 #[derive(Debug, thiserror::Error)]
 pub enum ConfigureNixDaemonServiceError {
     #[error("No supported init system found")]
     InitNotSupported,
+    #[error("`{0}` is not a plist dictionary")]
+    PlistNotDictionary(PathBuf),
+}
+
+impl From<ConfigureNixDaemonServiceError> for ActionErrorKind {
+    fn from(val: ConfigureNixDaemonServiceError) -> Self {
+        ActionErrorKind::Custom(Box::new(val))
+    }
+}
+
+/// Add `environment` to the `EnvironmentVariables` of a launchd plist
+async fn set_launchd_environment(
+    plist_path: &Path,
+    environment: &[(String, String)],
+) -> Result<(), ActionErrorKind> {
+    let buf = tokio::fs::read(plist_path)
+        .await
+        .map_err(|e| ActionErrorKind::Read(plist_path.to_path_buf(), e))?;
+    let mut value = plist::Value::from_reader(std::io::Cursor::new(buf))?;
+    let dict = value
+        .as_dictionary_mut()
+        .ok_or_else(|| ConfigureNixDaemonServiceError::PlistNotDictionary(plist_path.into()))?;
+    if !dict.contains_key("EnvironmentVariables") {
+        dict.insert(
+            "EnvironmentVariables".to_string(),
+            plist::Value::Dictionary(plist::Dictionary::new()),
+        );
+    }
+    let environment_variables = dict
+        .get_mut("EnvironmentVariables")
+        .and_then(plist::Value::as_dictionary_mut)
+        .ok_or_else(|| ConfigureNixDaemonServiceError::PlistNotDictionary(plist_path.into()))?;
+    for (key, value) in environment {
+        environment_variables.insert(key.clone(), plist::Value::String(value.clone()));
+    }
+
+    let mut buf = Vec::new();
+    plist::to_writer_xml(&mut buf, &value)?;
+    tokio::fs::write(plist_path, &buf)
+        .await
+        .map_err(|e| ActionErrorKind::Write(plist_path.to_path_buf(), e))?;
+    audit::file_written(plist_path, &buf);
+
+    Ok(())
+}
+
+/// The systemd drop-in exporting `NIX_SSL_CERT_FILE` to the daemon
+fn ssl_cert_file_dropin(ssl_cert_file: &Path) -> String {
+    daemon_env_dropin(&[(
+        "NIX_SSL_CERT_FILE".into(),
+        ssl_cert_file.display().to_string(),
+    )])
+}
+
+/// The systemd drop-in exporting the `--daemon-env` variables to the daemon
+fn daemon_env_dropin(daemon_env: &[(String, String)]) -> String {
+    let mut dropin = String::from("[Service]\n");
+    for (key, value) in daemon_env {
+        // Quoted, with C-style escapes, and `%` would start a specifier
+        let value = value
+            .replace('\\', "\\\\")
+            .replace('"', "\\\"")
+            .replace('%', "%%");
+        dropin.push_str(&format!("Environment=\"{key}={value}\"\n"));
+    }
+    dropin
+}
+
+/// Like `` `http_proxy=http://proxy:3128`, `NO_PROXY=localhost` ``
+fn describe_env(environment: &[(String, String)]) -> String {
+    environment
+        .iter()
+        .map(|(key, value)| format!("`{key}={value}`"))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Run a `launchctl` or `systemctl` command, which can fail transiently right after units or plists change
+async fn execute_init_command(
+    command: &mut Command,
+) -> Result<std::process::Output, ActionErrorKind> {
+    execute_command_with_retries(command, INIT_COMMAND_RETRIES, INIT_COMMAND_BACKOFF).await
 }
 
 async fn stop(unit: &str) -> Result<(), ActionErrorKind> {
     let mut command = Command::new("systemctl");
     command.arg("stop");
     command.arg(unit);
-    let output = command
-        .output()
-        .await
-        .map_err(|e| ActionErrorKind::command(&command, e))?;
-    match output.status.success() {
-        true => {
-            tracing::trace!(%unit, "Stopped");
-            Ok(())
-        },
-        false => Err(ActionErrorKind::command_output(&command, output)),
-    }
+    command.stdin(std::process::Stdio::null());
+    execute_init_command(&mut command).await?;
+    tracing::trace!(%unit, "Stopped");
+    Ok(())
 }
 
 async fn enable(unit: &str, now: bool) -> Result<(), ActionErrorKind> {
@@ -723,17 +1212,10 @@ async fn enable(unit: &str, now: bool) -> Result<(), ActionErrorKind> {
     if now {
         command.arg("--now");
     }
-    let output = command
-        .output()
-        .await
-        .map_err(|e| ActionErrorKind::command(&command, e))?;
-    match output.status.success() {
-        true => {
-            tracing::trace!(unit = %unit, %now, "Enabled unit");
-            Ok(())
-        },
-        false => Err(ActionErrorKind::command_output(&command, output)),
-    }
+    command.stdin(std::process::Stdio::null());
+    execute_init_command(&mut command).await?;
+    tracing::trace!(unit = %unit, %now, "Enabled unit");
+    Ok(())
 }
 
 async fn disable(unit: &str, now: bool) -> Result<(), ActionErrorKind> {
@@ -743,17 +1225,10 @@ async fn disable(unit: &str, now: bool) -> Result<(), ActionErrorKind> {
     if now {
         command.arg("--now");
     }
-    let output = command
-        .output()
-        .await
-        .map_err(|e| ActionErrorKind::command(&command, e))?;
-    match output.status.success() {
-        true => {
-            tracing::trace!(%unit, %now, "Disabled unit");
-            Ok(())
-        },
-        false => Err(ActionErrorKind::command_output(&command, output)),
-    }
+    command.stdin(std::process::Stdio::null());
+    execute_init_command(&mut command).await?;
+    tracing::trace!(%unit, %now, "Disabled unit");
+    Ok(())
 }
 
 async fn is_active(unit: &str) -> Result<bool, ActionErrorKind> {
@@ -790,3 +1265,72 @@ async fn is_enabled(unit: &str) -> Result<bool, ActionErrorKind> {
         Ok(false)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn sets_launchd_environment() -> eyre::Result<()> {
+        let temp_dir = tempfile::TempDir::new()?;
+        let test_file = temp_dir.path().join("org.nixos.nix-daemon.plist");
+        let mut dict = plist::Dictionary::new();
+        dict.insert("Label".into(), "org.nixos.nix-daemon".into());
+        plist::to_file_xml(&test_file, &plist::Value::Dictionary(dict))?;
+
+        set_launchd_environment(
+            &test_file,
+            &[
+                (
+                    "NIX_SSL_CERT_FILE".into(),
+                    "/etc/nix/ssl-cert-file.pem".into(),
+                ),
+                ("https_proxy".into(), "http://proxy:3128".into()),
+            ],
+        )
+        .await?;
+
+        let value = plist::Value::from_file(&test_file)?;
+        let environment = value
+            .as_dictionary()
+            .and_then(|v| v.get("EnvironmentVariables"))
+            .and_then(plist::Value::as_dictionary)
+            .expect("EnvironmentVariables should be set");
+        assert_eq!(
+            environment
+                .get("NIX_SSL_CERT_FILE")
+                .and_then(plist::Value::as_string),
+            Some("/etc/nix/ssl-cert-file.pem")
+        );
+        assert_eq!(
+            environment
+                .get("https_proxy")
+                .and_then(plist::Value::as_string),
+            Some("http://proxy:3128")
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn escapes_daemon_env_dropin() {
+        assert_eq!(
+            daemon_env_dropin(&[
+                ("NO_PROXY".into(), "localhost,10.0.0.0/8".into()),
+                ("GREETING".into(), r#"say "100%""#.into()),
+            ]),
+            "[Service]\n\
+            Environment=\"NO_PROXY=localhost,10.0.0.0/8\"\n\
+            Environment=\"GREETING=say \\\"100%%\\\"\"\n"
+        );
+    }
+
+    #[test]
+    fn escapes_ssl_cert_file_dropin() {
+        assert_eq!(
+            ssl_cert_file_dropin(Path::new(r#"/etc/ssl/100% "my" certs\ca.pem"#)),
+            "[Service]\n\
+            Environment=\"NIX_SSL_CERT_FILE=/etc/ssl/100%% \\\"my\\\" certs\\\\ca.pem\"\n"
+        );
+    }
+}
@@ -2,12 +2,17 @@ use std::path::{Path, PathBuf};
 
 use crate::{
     action::{
-        base::SetupDefaultProfile,
-        common::{ConfigureShellProfile, PlaceNixConfiguration},
-        Action, ActionDescription, ActionError, ActionErrorKind, ActionTag, StatefulAction,
+        base::{ImportSeedClosure, SetupDefaultProfile},
+        common::{
+            configure_remote_builders::NIX_MACHINES, ConfigureRemoteBuilders,
+            ConfigureShellProfile, PlaceNixConfiguration,
+        },
+        Action, ActionDescription, ActionError, ActionErrorKind, ActionTag, Artifact, DryRunStep,
+        StatefulAction,
     },
+    drift::DriftedAction,
     planner::ShellProfileLocations,
-    settings::{CommonSettings, SCRATCH_DIR},
+    settings::{CommonSettings, Distribution, SCRATCH_DIR},
 };
 use glob::glob;
 
@@ -20,8 +25,12 @@ Configure Nix and start it
 #[serde(tag = "action_name", rename = "configure_nix")]
 pub struct ConfigureNix {
     setup_default_profile: StatefulAction<SetupDefaultProfile>,
+    #[serde(default)]
+    import_seed_closure: Option<StatefulAction<ImportSeedClosure>>,
     configure_shell_profile: Option<StatefulAction<ConfigureShellProfile>>,
     place_nix_configuration: StatefulAction<PlaceNixConfiguration>,
+    #[serde(default)]
+    configure_remote_builders: Option<StatefulAction<ConfigureRemoteBuilders>>,
 }
 
 impl ConfigureNix {
@@ -29,27 +38,75 @@ impl ConfigureNix {
     pub async fn plan(
         shell_profile_locations: ShellProfileLocations,
         settings: &CommonSettings,
-        extra_internal_conf: Option<nix_config_parser::NixConfig>,
+        mut extra_internal_conf: Option<nix_config_parser::NixConfig>,
     ) -> Result<StatefulAction<Self>, ActionError> {
-        let setup_default_profile = SetupDefaultProfile::plan(PathBuf::from(SCRATCH_DIR))
-            .await
-            .map_err(Self::error)?;
+        let setup_default_profile =
+            SetupDefaultProfile::plan(PathBuf::from(SCRATCH_DIR), settings.distribution())
+                .await
+                .map_err(Self::error)?;
 
-        let configure_shell_profile = if settings.modify_profile {
-            Some(
-                ConfigureShellProfile::plan(shell_profile_locations)
+        let import_seed_closure = match &settings.seed_closure {
+            Some(seed_closure) => Some(
+                ImportSeedClosure::plan(seed_closure.clone(), settings.seed_closure_no_check_sigs)
                     .await
                     .map_err(Self::error)?,
+            ),
+            None => None,
+        };
+
+        let configure_shell_profile = if settings.modify_profile {
+            Some(
+                ConfigureShellProfile::plan(
+                    shell_profile_locations
+                        .with_zsh_hook(settings.zsh_hook)
+                        .for_strategy(settings.profile_strategy)
+                        .with_homebrew_path_order(settings.homebrew_path_order),
+                )
+                .await
+                .map_err(Self::error)?,
             )
         } else {
             None
         };
+        if let Some(build_dir) = &settings.build_dir {
+            extra_internal_conf
+                .get_or_insert_with(nix_config_parser::NixConfig::new)
+                .settings_mut()
+                .insert("build-dir".into(), build_dir.display().to_string());
+        }
+        let configure_remote_builders = if settings.builders.is_empty() {
+            None
+        } else {
+            let nix_settings = extra_internal_conf
+                .get_or_insert_with(nix_config_parser::NixConfig::new)
+                .settings_mut();
+            nix_settings.insert("builders".into(), format!("@{NIX_MACHINES}"));
+            nix_settings.insert(
+                "builders-use-substitutes".into(),
+                settings.builders_use_substitutes.to_string(),
+            );
+            Some(
+                ConfigureRemoteBuilders::plan(
+                    settings.builders.clone(),
+                    settings.builder_ssh_key.clone(),
+                    settings.builder_known_hosts.clone(),
+                    settings.force,
+                )
+                .await
+                .map_err(Self::error)?,
+            )
+        };
         let place_nix_configuration = PlaceNixConfiguration::plan(
             settings.nix_build_group_name.clone(),
+            settings.distribution(),
             settings.proxy.clone(),
             settings.ssl_cert_file.clone(),
-            extra_internal_conf.clone(),
+            extra_internal_conf,
             settings.extra_conf.clone(),
+            settings.trusted_users.clone(),
+            settings.allowed_users.clone(),
+            settings.sandbox,
+            settings.extra_sandbox_paths.clone(),
             settings.force,
         )
         .await
@@ -58,16 +115,23 @@ impl ConfigureNix {
         Ok(Self {
             place_nix_configuration,
             setup_default_profile,
+            import_seed_closure,
             configure_shell_profile,
+            configure_remote_builders,
         }
         .into())
     }
 
     pub async fn find_nix_and_ca_cert(
         unpacked_path: &Path,
+        distribution: Distribution,
     ) -> Result<(PathBuf, PathBuf), ActionError> {
-        // Find a `nix` package
-        let nix_pkg_glob = format!("{}/nix-*/store/*-nix-*.*.*", unpacked_path.display());
+        // Find a `nix` (or `lix`) package
+        let package_name = distribution.package_name();
+        let nix_pkg_glob = format!(
+            "{}/{package_name}-*/store/*-{package_name}-*.*.*",
+            unpacked_path.display()
+        );
         let mut found_nix_pkg = None;
         for entry in glob(&nix_pkg_glob).map_err(Self::error)? {
             match entry {
@@ -93,8 +157,10 @@ impl ConfigureNix {
         };
 
         // Find an `nss-cacert` package
-        let nss_ca_cert_pkg_glob =
-            format!("{}/nix-*/store/*-nss-cacert-*.*", unpacked_path.display());
+        let nss_ca_cert_pkg_glob = format!(
+            "{}/{package_name}-*/store/*-nss-cacert-*.*",
+            unpacked_path.display()
+        );
         let mut found_nss_ca_cert_pkg = None;
         for entry in glob(&nss_ca_cert_pkg_glob).map_err(Self::error)? {
             match entry {
@@ -140,12 +206,20 @@ impl Action for ConfigureNix {
     fn execute_description(&self) -> Vec<ActionDescription> {
         let Self {
             setup_default_profile,
+            import_seed_closure,
             place_nix_configuration,
             configure_shell_profile,
+            configure_remote_builders,
         } = &self;
 
         let mut buf = setup_default_profile.describe_execute();
+        if let Some(import_seed_closure) = import_seed_closure {
+            buf.append(&mut import_seed_closure.describe_execute());
+        }
         buf.append(&mut place_nix_configuration.describe_execute());
+        if let Some(configure_remote_builders) = configure_remote_builders {
+            buf.append(&mut configure_remote_builders.describe_execute());
+        }
         if let Some(configure_shell_profile) = configure_shell_profile {
             buf.append(&mut configure_shell_profile.describe_execute());
         }
@@ -156,8 +230,10 @@ impl Action for ConfigureNix {
     async fn execute(&mut self) -> Result<(), ActionError> {
         let Self {
             setup_default_profile,
+            import_seed_closure,
             place_nix_configuration,
             configure_shell_profile,
+            configure_remote_builders,
         } = self;
 
         if let Some(configure_shell_profile) = configure_shell_profile {
@@ -210,26 +286,103 @@ impl Action for ConfigureNix {
             )?;
         };
 
+        // Needs `/etc/nix`, from placing the Nix configuration
+        if let Some(configure_remote_builders) = configure_remote_builders {
+            configure_remote_builders
+                .try_execute()
+                .await
+                .map_err(Self::error)?;
+        }
+
+        // Needs the `nix` from the default profile, and must finish before the daemon starts
+        if let Some(import_seed_closure) = import_seed_closure {
+            import_seed_closure
+                .try_execute()
+                .await
+                .map_err(Self::error)?;
+        }
+
         Ok(())
     }
 
+    fn dry_run(&self) -> Vec<DryRunStep> {
+        let mut steps = self.setup_default_profile.dry_run();
+        steps.extend(self.place_nix_configuration.dry_run());
+        if let Some(configure_remote_builders) = &self.configure_remote_builders {
+            steps.extend(configure_remote_builders.dry_run());
+        }
+        if let Some(configure_shell_profile) = &self.configure_shell_profile {
+            steps.extend(configure_shell_profile.dry_run());
+        }
+        if let Some(import_seed_closure) = &self.import_seed_closure {
+            steps.extend(import_seed_closure.dry_run());
+        }
+        steps
+    }
+
+    fn artifacts(&self) -> Vec<Artifact> {
+        let mut artifacts = self.place_nix_configuration.artifacts();
+        if let Some(configure_remote_builders) = &self.configure_remote_builders {
+            artifacts.extend(configure_remote_builders.artifacts());
+        }
+        if let Some(configure_shell_profile) = &self.configure_shell_profile {
+            artifacts.extend(configure_shell_profile.artifacts());
+        }
+        artifacts
+    }
+
+    async fn drift(&self) -> Vec<DriftedAction> {
+        let mut drifted = self.place_nix_configuration.drift().await;
+        if let Some(configure_remote_builders) = &self.configure_remote_builders {
+            drifted.extend(configure_remote_builders.drift().await);
+        }
+        if let Some(configure_shell_profile) = &self.configure_shell_profile {
+            drifted.extend(configure_shell_profile.drift().await);
+        }
+        drifted
+    }
+
     fn revert_description(&self) -> Vec<ActionDescription> {
         let Self {
             setup_default_profile,
+            import_seed_closure,
             place_nix_configuration,
             configure_shell_profile,
+            configure_remote_builders,
         } = &self;
 
         let mut buf = Vec::default();
         if let Some(configure_shell_profile) = configure_shell_profile {
             buf.append(&mut configure_shell_profile.describe_revert());
         }
+        if let Some(import_seed_closure) = import_seed_closure {
+            buf.append(&mut import_seed_closure.describe_revert());
+        }
+        if let Some(configure_remote_builders) = configure_remote_builders {
+            buf.append(&mut configure_remote_builders.describe_revert());
+        }
         buf.append(&mut place_nix_configuration.describe_revert());
         buf.append(&mut setup_default_profile.describe_revert());
 
         buf
     }
 
+    fn revert_dry_run(&self) -> Vec<DryRunStep> {
+        let mut steps = vec![];
+        if let Some(configure_shell_profile) = &self.configure_shell_profile {
+            steps.extend(configure_shell_profile.revert_dry_run());
+        }
+        if let Some(import_seed_closure) = &self.import_seed_closure {
+            steps.extend(import_seed_closure.revert_dry_run());
+        }
+        if let Some(configure_remote_builders) = &self.configure_remote_builders {
+            steps.extend(configure_remote_builders.revert_dry_run());
+        }
+        steps.extend(self.place_nix_configuration.revert_dry_run());
+        steps.extend(self.setup_default_profile.revert_dry_run());
+        steps
+    }
+
     #[tracing::instrument(level = "debug", skip_all)]
     async fn revert(&mut self) -> Result<(), ActionError> {
         let mut errors = vec![];
@@ -238,6 +391,16 @@ impl Action for ConfigureNix {
                 errors.push(err);
             }
         }
+        if let Some(import_seed_closure) = &mut self.import_seed_closure {
+            if let Err(err) = import_seed_closure.try_revert().await {
+                errors.push(err);
+            }
+        }
+        if let Some(configure_remote_builders) = &mut self.configure_remote_builders {
+            if let Err(err) = configure_remote_builders.try_revert().await {
+                errors.push(err);
+            }
+        }
         if let Err(err) = self.place_nix_configuration.try_revert().await {
             errors.push(err);
         }
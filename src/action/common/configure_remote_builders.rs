@@ -0,0 +1,353 @@
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+
+use tokio::io::AsyncWriteExt;
+use tracing::{span, Span};
+
+use crate::action::base::{
+    create_or_insert_into_file, CreateDirectory, CreateFile, CreateOrInsertIntoFile,
+};
+use crate::action::{
+    Action, ActionDescription, ActionError, ActionErrorKind, ActionTag, Artifact, DryRunStep,
+    StatefulAction,
+};
+use crate::drift::DriftedAction;
+
+/// The remote builders the Nix daemon may use, with `builders = @/etc/nix/machines`
+pub const NIX_MACHINES: &str = "/etc/nix/machines";
+/// Where `--builder-ssh-key` is copied, readable only by root (which the daemon runs as)
+pub const BUILDER_SSH_KEY: &str = "/etc/nix/builder-ssh-key";
+/// The system-wide `known_hosts`, which the daemon's `ssh` reads too
+pub const SSH_KNOWN_HOSTS: &str = "/etc/ssh/ssh_known_hosts";
+
+/**
+Configure the remote builders the Nix daemon distributes builds to
+
+Writes `/etc/nix/machines` and, if given, the SSH key the daemon reaches them with and their host keys.
+*/
+#[derive(Debug, serde::Deserialize, serde::Serialize, Clone)]
+#[serde(tag = "action_name", rename = "configure_remote_builders")]
+pub struct ConfigureRemoteBuilders {
+    create_machines: StatefulAction<CreateFile>,
+    /// The key copied to [`BUILDER_SSH_KEY`], only its path is kept (unlike a [`CreateFile`]) so it isn't in the receipt
+    ssh_key: Option<PathBuf>,
+    /// `/etc/ssh`, if it doesn't exist (like where no SSH client is installed yet)
+    create_ssh_directory: Option<StatefulAction<CreateDirectory>>,
+    insert_known_hosts: Option<StatefulAction<CreateOrInsertIntoFile>>,
+}
+
+impl ConfigureRemoteBuilders {
+    #[tracing::instrument(level = "debug", skip_all)]
+    pub async fn plan(
+        builders: Vec<String>,
+        ssh_key: Option<PathBuf>,
+        known_hosts: Option<PathBuf>,
+        force: bool,
+    ) -> Result<StatefulAction<Self>, ActionError> {
+        let ssh_key = match ssh_key {
+            Some(ssh_key) => Some(
+                tokio::fs::canonicalize(&ssh_key)
+                    .await
+                    .map_err(|e| ActionErrorKind::Canonicalize(ssh_key, e))
+                    .map_err(Self::error)?,
+            ),
+            None => None,
+        };
+        if ssh_key.is_some() && !force && Path::new(BUILDER_SSH_KEY).exists() {
+            return Err(Self::error(ActionErrorKind::FileExists(
+                BUILDER_SSH_KEY.into(),
+            )));
+        }
+        let create_machines = CreateFile::plan(
+            NIX_MACHINES,
+            None,
+            None,
+            0o0644,
+            machines(&builders, ssh_key.is_some()),
+            force,
+        )
+        .await
+        .map_err(Self::error)?;
+
+        let (create_ssh_directory, insert_known_hosts) = match &known_hosts {
+            Some(known_hosts) => {
+                let mut buf = tokio::fs::read_to_string(known_hosts)
+                    .await
+                    .map_err(|e| ActionErrorKind::Read(known_hosts.clone(), e))
+                    .map_err(Self::error)?;
+                if !buf.ends_with('\n') {
+                    buf.push('\n');
+                }
+                let create_ssh_directory = match Path::new(SSH_KNOWN_HOSTS).parent() {
+                    Some(parent) if !parent.exists() => Some(
+                        CreateDirectory::plan(parent, None, None, 0o0755, false)
+                            .await
+                            .map_err(Self::error)?,
+                    ),
+                    _ => None,
+                };
+                let insert_known_hosts = CreateOrInsertIntoFile::plan(
+                    SSH_KNOWN_HOSTS,
+                    None,
+                    None,
+                    0o0644,
+                    buf,
+                    create_or_insert_into_file::Position::End,
+                )
+                .await
+                .map_err(Self::error)?;
+                (create_ssh_directory, Some(insert_known_hosts))
+            },
+            None => (None, None),
+        };
+
+        Ok(Self {
+            create_machines,
+            ssh_key,
+            create_ssh_directory,
+            insert_known_hosts,
+        }
+        .into())
+    }
+}
+
+/// The `/etc/nix/machines` for `builders`, those without an SSH key of their own use [`BUILDER_SSH_KEY`] if `with_ssh_key`
+fn machines(builders: &[String], with_ssh_key: bool) -> String {
+    let mut buf =
+        "# Written by nix-installer, and removed by `nix-installer uninstall`.\n".to_string();
+    for builder in builders {
+        let mut fields = builder.split_whitespace().collect::<Vec<_>>();
+        if with_ssh_key {
+            // The systems (field 2) must be present (`-` for any) to give the key (field 3)
+            while fields.len() < 3 {
+                fields.push("-");
+            }
+            if fields[2] == "-" {
+                fields[2] = BUILDER_SSH_KEY;
+            }
+        }
+        buf += &fields.join(" ");
+        buf.push('\n');
+    }
+    buf
+}
+
+/// Copy `ssh_key` to [`BUILDER_SSH_KEY`], created readable only by root before anything is written to it
+async fn copy_ssh_key(ssh_key: &Path) -> Result<(), ActionErrorKind> {
+    let buf = tokio::fs::read(ssh_key)
+        .await
+        .map_err(|e| ActionErrorKind::Read(ssh_key.to_path_buf(), e))?;
+    let mut file = tokio::fs::OpenOptions::new()
+        .create(true)
+        .write(true)
+        .truncate(true)
+        .mode(0o0600)
+        .open(BUILDER_SSH_KEY)
+        .await
+        .map_err(|e| ActionErrorKind::Open(BUILDER_SSH_KEY.into(), e))?;
+    file.write_all(&buf)
+        .await
+        .map_err(|e| ActionErrorKind::Write(BUILDER_SSH_KEY.into(), e))?;
+    // `mode` only applies to new files, a `--force`d existing one may have been readable
+    tokio::fs::set_permissions(BUILDER_SSH_KEY, PermissionsExt::from_mode(0o0600))
+        .await
+        .map_err(|e| ActionErrorKind::SetPermissions(0o0600, BUILDER_SSH_KEY.into(), e))?;
+    Ok(())
+}
+
+#[async_trait::async_trait]
+#[typetag::serde(name = "configure_remote_builders")]
+impl Action for ConfigureRemoteBuilders {
+    fn action_tag() -> ActionTag {
+        ActionTag("configure_remote_builders")
+    }
+    fn tracing_synopsis(&self) -> String {
+        format!("Configure the remote builders in `{NIX_MACHINES}`")
+    }
+
+    fn tracing_span(&self) -> Span {
+        span!(tracing::Level::DEBUG, "configure_remote_builders",)
+    }
+
+    fn execute_description(&self) -> Vec<ActionDescription> {
+        let mut explanation = vec![
+            "The Nix daemon distributes builds to these machines, over SSH as root".to_string(),
+        ];
+        if self.ssh_key.is_some() {
+            explanation.push(format!(
+                "Copy the SSH key to reach them with to `{BUILDER_SSH_KEY}`"
+            ));
+        }
+        if self.insert_known_hosts.is_some() {
+            explanation.push(format!("Add their host keys to `{SSH_KNOWN_HOSTS}`"));
+        }
+        vec![ActionDescription::new(self.tracing_synopsis(), explanation)]
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    async fn execute(&mut self) -> Result<(), ActionError> {
+        if let Some(ssh_key) = &self.ssh_key {
+            copy_ssh_key(ssh_key).await.map_err(Self::error)?;
+        }
+        self.create_machines
+            .try_execute()
+            .await
+            .map_err(Self::error)?;
+        if let Some(create_ssh_directory) = &mut self.create_ssh_directory {
+            create_ssh_directory
+                .try_execute()
+                .await
+                .map_err(Self::error)?;
+        }
+        if let Some(insert_known_hosts) = &mut self.insert_known_hosts {
+            insert_known_hosts
+                .try_execute()
+                .await
+                .map_err(Self::error)?;
+        }
+
+        Ok(())
+    }
+
+    fn dry_run(&self) -> Vec<DryRunStep> {
+        let mut steps = vec![];
+        if let Some(ssh_key) = &self.ssh_key {
+            steps.push(DryRunStep::Command(vec![
+                "install".into(),
+                "-m".into(),
+                "0600".into(),
+                ssh_key.display().to_string(),
+                BUILDER_SSH_KEY.into(),
+            ]));
+        }
+        steps.extend(self.create_machines.dry_run());
+        if let Some(create_ssh_directory) = &self.create_ssh_directory {
+            steps.extend(create_ssh_directory.dry_run());
+        }
+        if let Some(insert_known_hosts) = &self.insert_known_hosts {
+            steps.extend(insert_known_hosts.dry_run());
+        }
+        steps
+    }
+
+    fn artifacts(&self) -> Vec<Artifact> {
+        let mut artifacts = vec![];
+        if self.ssh_key.is_some() {
+            artifacts.push(Artifact::File(BUILDER_SSH_KEY.into()));
+        }
+        artifacts.extend(self.create_machines.artifacts());
+        if let Some(create_ssh_directory) = &self.create_ssh_directory {
+            artifacts.extend(create_ssh_directory.artifacts());
+        }
+        if let Some(insert_known_hosts) = &self.insert_known_hosts {
+            artifacts.extend(insert_known_hosts.artifacts());
+        }
+        artifacts
+    }
+
+    async fn drift(&self) -> Vec<DriftedAction> {
+        let mut drifted = self.create_machines.drift().await;
+        if let Some(insert_known_hosts) = &self.insert_known_hosts {
+            drifted.extend(insert_known_hosts.drift().await);
+        }
+        drifted
+    }
+
+    fn revert_description(&self) -> Vec<ActionDescription> {
+        let mut explanation = vec![];
+        if self.ssh_key.is_some() {
+            explanation.push(format!("Remove the SSH key `{BUILDER_SSH_KEY}`"));
+        }
+        if self.insert_known_hosts.is_some() {
+            explanation.push(format!(
+                "Remove the builders' host keys from `{SSH_KNOWN_HOSTS}`"
+            ));
+        }
+        vec![ActionDescription::new(
+            format!("Remove the remote builders in `{NIX_MACHINES}`"),
+            explanation,
+        )]
+    }
+
+    fn revert_dry_run(&self) -> Vec<DryRunStep> {
+        let mut steps = vec![];
+        if let Some(insert_known_hosts) = &self.insert_known_hosts {
+            steps.extend(insert_known_hosts.revert_dry_run());
+        }
+        if let Some(create_ssh_directory) = &self.create_ssh_directory {
+            steps.extend(create_ssh_directory.revert_dry_run());
+        }
+        steps.extend(self.create_machines.revert_dry_run());
+        if self.ssh_key.is_some() {
+            steps.push(DryRunStep::Remove(BUILDER_SSH_KEY.into()));
+        }
+        steps
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    async fn revert(&mut self) -> Result<(), ActionError> {
+        let mut errors = vec![];
+        if let Some(insert_known_hosts) = &mut self.insert_known_hosts {
+            if let Err(err) = insert_known_hosts.try_revert().await {
+                errors.push(err);
+            }
+        }
+        if let Some(create_ssh_directory) = &mut self.create_ssh_directory {
+            if let Err(err) = create_ssh_directory.try_revert().await {
+                errors.push(err);
+            }
+        }
+        if let Err(err) = self.create_machines.try_revert().await {
+            errors.push(err);
+        }
+        if self.ssh_key.is_some() {
+            match tokio::fs::remove_file(BUILDER_SSH_KEY).await {
+                Ok(()) => (),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => (),
+                Err(e) => errors.push(Self::error(ActionErrorKind::Remove(
+                    BUILDER_SSH_KEY.into(),
+                    e,
+                ))),
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else if errors.len() == 1 {
+            Err(errors
+                .into_iter()
+                .next()
+                .expect("Expected 1 len Vec to have at least 1 item"))
+        } else {
+            Err(Self::error(ActionErrorKind::MultipleChildren(errors)))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{machines, BUILDER_SSH_KEY};
+
+    #[test]
+    fn fills_in_the_ssh_key() {
+        let builders = vec![
+            "ssh-ng://builder-1".to_string(),
+            "ssh-ng://builder-2 x86_64-linux - 8".to_string(),
+            "ssh://builder-3 aarch64-linux /root/.ssh/builder-3".to_string(),
+        ];
+        let with_key = machines(&builders, true);
+        let lines = with_key.lines().skip(1).collect::<Vec<_>>();
+        assert_eq!(
+            lines,
+            vec![
+                format!("ssh-ng://builder-1 - {BUILDER_SSH_KEY}"),
+                format!("ssh-ng://builder-2 x86_64-linux {BUILDER_SSH_KEY} 8"),
+                "ssh://builder-3 aarch64-linux /root/.ssh/builder-3".to_string(),
+            ]
+        );
+
+        let without_key = machines(&builders, false);
+        assert!(without_key.contains("\nssh-ng://builder-1\n"));
+        assert!(!without_key.contains(BUILDER_SSH_KEY));
+    }
+}
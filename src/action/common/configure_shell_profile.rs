@@ -1,16 +1,30 @@
 use crate::action::base::{create_or_insert_into_file, CreateDirectory, CreateOrInsertIntoFile};
 use crate::action::{
-    Action, ActionDescription, ActionError, ActionErrorKind, ActionTag, StatefulAction,
+    inherit_retrying, Action, ActionDescription, ActionError, ActionErrorKind, ActionTag, Artifact,
+    DryRunStep, StatefulAction,
 };
-use crate::planner::ShellProfileLocations;
+use crate::drift::DriftedAction;
+use crate::planner::{homebrew_prefix, ShellProfileLocations};
+use crate::settings::HomebrewPathOrder;
 
-use nix::unistd::User;
+use nix::unistd::{Uid, User};
+use std::os::unix::fs::MetadataExt;
 use std::path::{Path, PathBuf};
 use tokio::task::JoinSet;
 use tracing::{span, Instrument, Span};
 
 const PROFILE_NIX_FILE_SHELL: &str = "/nix/var/nix/profiles/default/etc/profile.d/nix-daemon.sh";
 const PROFILE_NIX_FILE_FISH: &str = "/nix/var/nix/profiles/default/etc/profile.d/nix-daemon.fish";
+const PROFILE_NIX_DEFAULT: &str = "/nix/var/nix/profiles/default";
+
+/// Where `nix-daemon.sh` looks for a CA bundle for `NIX_SSL_CERT_FILE`, in order, for the shells which can't source it
+const SSL_CERT_FILES: &[&str] = &[
+    "/etc/ssl/certs/ca-certificates.crt", // NixOS, Ubuntu, Debian, Gentoo, Arch
+    "/etc/ssl/ca-bundle.pem",             // openSUSE Tumbleweed
+    "/etc/ssl/certs/ca-bundle.crt",       // Old NixOS
+    "/etc/pki/tls/certs/ca-bundle.crt",   // Fedora, CentOS
+    "/nix/var/nix/profiles/default/etc/ssl/certs/ca-bundle.crt", // cacert in the Nix profile
+];
 
 /**
 Configure any detected shell profiles to include Nix support
@@ -31,12 +45,38 @@ impl ConfigureShellProfile {
         let mut create_or_insert_files = Vec::default();
         let mut create_directories = Vec::default();
 
+        let homebrew = match (homebrew_prefix(), locations.homebrew_path_order) {
+            (Some(prefix), Some(order)) => Some((prefix, order)),
+            (Some(prefix), None) => {
+                tracing::info!(
+                    "Homebrew is installed in `{}`, pass `--homebrew-path-order` to choose whether its commands or Nix's come first on `PATH`",
+                    prefix.display()
+                );
+                None
+            },
+            (None, Some(order)) => {
+                tracing::info!(
+                    "Homebrew isn't installed, `--homebrew-path-order {order}` takes effect if `nix-installer repair` is run after installing it"
+                );
+                None
+            },
+            (None, None) => None,
+        };
+        let (shell_homebrew_buf, fish_homebrew_buf) = match &homebrew {
+            Some((prefix, order)) => (
+                homebrew_path_shell(prefix, *order),
+                homebrew_path_fish(prefix, *order),
+            ),
+            None => (String::new(), String::new()),
+        };
+
         let shell_buf = format!(
             "\n\
             # Nix\n\
             if [ -e '{PROFILE_NIX_FILE_SHELL}' ]; then\n\
             {inde}. '{PROFILE_NIX_FILE_SHELL}'\n\
             fi\n\
+            {shell_homebrew_buf}\
             # End Nix\n
         \n",
             inde = "    ", // indent
@@ -77,6 +117,7 @@ impl ConfigureShellProfile {
             if test -e '{PROFILE_NIX_FILE_FISH}'\n\
             {inde}. '{PROFILE_NIX_FILE_FISH}'\n\
             end\n\
+            {fish_homebrew_buf}\
             # End Nix\n\
         \n",
             inde = "    ", // indent
@@ -145,6 +186,115 @@ impl ConfigureShellProfile {
             );
         }
 
+        // Nushell, Elvish, and Xonsh can't source `nix-daemon.sh`, so `PATH` and `NIX_SSL_CERT_FILE` are set directly
+        let cert_files = SSL_CERT_FILES
+            .iter()
+            .map(|cert_file| format!("'{cert_file}'"))
+            .collect::<Vec<_>>();
+        let nushell_buf = format!(
+            "\n\
+            # Nix\n\
+            if ('{PROFILE_NIX_DEFAULT}' | path exists) {{\n\
+            {inde}$env.PATH = ($env.PATH | split row (char esep) | prepend [$\"($nu.home-path)/.nix-profile/bin\" '{PROFILE_NIX_DEFAULT}/bin'] | uniq)\n\
+            {inde}for cert_file in [{nushell_cert_files}] {{\n\
+            {inde}{inde}if ('NIX_SSL_CERT_FILE' not-in $env) and ($cert_file | path exists) {{\n\
+            {inde}{inde}{inde}$env.NIX_SSL_CERT_FILE = $cert_file\n\
+            {inde}{inde}}}\n\
+            {inde}}}\n\
+            }}\n\
+            # End Nix\n\
+        \n",
+            inde = "    ", // indent
+            nushell_cert_files = cert_files.join(", "),
+        );
+        if which::which("nu").is_ok() {
+            for profile_target in &locations.nushell {
+                Self::plan_profile(
+                    profile_target,
+                    None,
+                    &nushell_buf,
+                    &mut create_directories,
+                    &mut create_or_insert_files,
+                )
+                .await?;
+            }
+        }
+
+        let elvish_buf = format!(
+            "\n\
+            # Nix\n\
+            use os\n\
+            if (os:exists '{PROFILE_NIX_DEFAULT}') {{\n\
+            {inde}var nix-paths = [~/.nix-profile/bin '{PROFILE_NIX_DEFAULT}/bin']\n\
+            {inde}set paths = [$@nix-paths (each {{|path| if (not (has-value $nix-paths $path)) {{ put $path }} }} $paths)]\n\
+            {inde}for cert-file [{elvish_cert_files}] {{\n\
+            {inde}{inde}if (and (not (has-env NIX_SSL_CERT_FILE)) (os:exists $cert-file)) {{\n\
+            {inde}{inde}{inde}set-env NIX_SSL_CERT_FILE $cert-file\n\
+            {inde}{inde}}}\n\
+            {inde}}}\n\
+            }}\n\
+            # End Nix\n\
+        \n",
+            inde = "    ", // indent
+            elvish_cert_files = cert_files.join(" "),
+        );
+        for home in crate::purge::homes().await {
+            // Each user's `rc.elv` should stay theirs
+            let Ok(metadata) = tokio::fs::metadata(&home).await else {
+                continue;
+            };
+            let Ok(Some(owner)) = User::from_uid(Uid::from_raw(metadata.uid())) else {
+                continue;
+            };
+            for rc in &locations.elvish {
+                let profile_target = home.join(rc);
+                // Only for users of Elvish
+                if !profile_target
+                    .parent()
+                    .is_some_and(|parent| parent.exists())
+                {
+                    continue;
+                }
+                Self::plan_profile(
+                    &profile_target,
+                    Some(owner.name.clone()),
+                    &elvish_buf,
+                    &mut create_directories,
+                    &mut create_or_insert_files,
+                )
+                .await?;
+            }
+        }
+
+        let xonsh_buf = format!(
+            "\n\
+            # Nix\n\
+            import os.path\n\
+            if os.path.exists('{PROFILE_NIX_DEFAULT}'):\n\
+            {inde}for __nix_path in ['{PROFILE_NIX_DEFAULT}/bin', $HOME + '/.nix-profile/bin']:\n\
+            {inde}{inde}if __nix_path not in $PATH:\n\
+            {inde}{inde}{inde}$PATH.insert(0, __nix_path)\n\
+            {inde}for __nix_cert_file in [{xonsh_cert_files}]:\n\
+            {inde}{inde}if 'NIX_SSL_CERT_FILE' not in ${{...}} and os.path.exists(__nix_cert_file):\n\
+            {inde}{inde}{inde}$NIX_SSL_CERT_FILE = __nix_cert_file\n\
+            # End Nix\n\
+        \n",
+            inde = "    ", // indent
+            xonsh_cert_files = cert_files.join(", "),
+        );
+        if which::which("xonsh").is_ok() {
+            for profile_target in &locations.xonsh {
+                Self::plan_profile(
+                    profile_target,
+                    None,
+                    &xonsh_buf,
+                    &mut create_directories,
+                    &mut create_or_insert_files,
+                )
+                .await?;
+            }
+        }
+
         // If the `$GITHUB_PATH` environment exists, we're almost certainly running on Github
         // Actions, and almost certainly wants the relevant `$PATH` additions added.
         if let Ok(github_path) = std::env::var("GITHUB_PATH") {
@@ -179,6 +329,44 @@ impl ConfigureShellProfile {
         }
         .into())
     }
+
+    /// Insert `buf` at the beginning of `profile_target`, creating the directories it is in (but none of theirs) if needed
+    async fn plan_profile(
+        profile_target: &Path,
+        user: Option<String>,
+        buf: &str,
+        create_directories: &mut Vec<StatefulAction<CreateDirectory>>,
+        create_or_insert_files: &mut Vec<StatefulAction<CreateOrInsertIntoFile>>,
+    ) -> Result<(), ActionError> {
+        // Some tools (eg `nix-darwin`) create symlinks to these files, don't write to them if that's the case.
+        if profile_target.is_symlink() {
+            return Ok(());
+        }
+        // Innermost first, so each is empty again (and removed) when reverted in order
+        for directory in profile_target.ancestors().skip(1) {
+            if directory.exists() {
+                break;
+            }
+            create_directories.push(
+                CreateDirectory::plan(directory, user.clone(), None, 0o755, false)
+                    .await
+                    .map_err(Self::error)?,
+            );
+        }
+        create_or_insert_files.push(
+            CreateOrInsertIntoFile::plan(
+                profile_target,
+                user,
+                None,
+                0o644,
+                buf.to_string(),
+                create_or_insert_into_file::Position::Beginning,
+            )
+            .await
+            .map_err(Self::error)?,
+        );
+        Ok(())
+    }
 }
 
 #[async_trait::async_trait]
@@ -216,14 +404,14 @@ impl Action for ConfigureShellProfile {
         {
             let span = tracing::Span::current().clone();
             let mut create_or_insert_into_file_clone = create_or_insert_into_file.clone();
-            let _abort_handle = set.spawn(async move {
+            let _abort_handle = set.spawn(inherit_retrying(async move {
                 create_or_insert_into_file_clone
                     .try_execute()
                     .instrument(span)
                     .await
                     .map_err(Self::error)?;
                 Result::<_, ActionError>::Ok((idx, create_or_insert_into_file_clone))
-            });
+            }));
         }
 
         while let Some(result) = set.join_next().await {
@@ -249,6 +437,39 @@ impl Action for ConfigureShellProfile {
         Ok(())
     }
 
+    fn dry_run(&self) -> Vec<DryRunStep> {
+        let mut steps = vec![];
+        for create_directory in &self.create_directories {
+            steps.extend(create_directory.dry_run());
+        }
+        for create_or_insert_into_file in &self.create_or_insert_into_files {
+            steps.extend(create_or_insert_into_file.dry_run());
+        }
+        steps
+    }
+
+    fn artifacts(&self) -> Vec<Artifact> {
+        let mut artifacts = vec![];
+        for create_directory in &self.create_directories {
+            artifacts.extend(create_directory.artifacts());
+        }
+        for create_or_insert_into_file in &self.create_or_insert_into_files {
+            artifacts.extend(create_or_insert_into_file.artifacts());
+        }
+        artifacts
+    }
+
+    async fn drift(&self) -> Vec<DriftedAction> {
+        let mut drifted = vec![];
+        for create_directory in &self.create_directories {
+            drifted.extend(create_directory.drift().await);
+        }
+        for create_or_insert_into_file in &self.create_or_insert_into_files {
+            drifted.extend(create_or_insert_into_file.drift().await);
+        }
+        drifted
+    }
+
     fn revert_description(&self) -> Vec<ActionDescription> {
         vec![ActionDescription::new(
             "Unconfigure the shell profiles".to_string(),
@@ -256,6 +477,17 @@ impl Action for ConfigureShellProfile {
         )]
     }
 
+    fn revert_dry_run(&self) -> Vec<DryRunStep> {
+        let mut steps = vec![];
+        for create_or_insert_into_file in &self.create_or_insert_into_files {
+            steps.extend(create_or_insert_into_file.revert_dry_run());
+        }
+        for create_directory in &self.create_directories {
+            steps.extend(create_directory.revert_dry_run());
+        }
+        steps
+    }
+
     #[tracing::instrument(level = "debug", skip_all)]
     async fn revert(&mut self) -> Result<(), ActionError> {
         let mut set = JoinSet::new();
@@ -265,10 +497,10 @@ impl Action for ConfigureShellProfile {
             self.create_or_insert_into_files.iter_mut().enumerate()
         {
             let mut create_or_insert_file_clone = create_or_insert_into_file.clone();
-            let _abort_handle = set.spawn(async move {
+            let _abort_handle = set.spawn(inherit_retrying(async move {
                 create_or_insert_file_clone.try_revert().await?;
                 Result::<_, _>::Ok((idx, create_or_insert_file_clone))
-            });
+            }));
         }
 
         while let Some(result) = set.join_next().await {
@@ -300,3 +532,97 @@ impl Action for ConfigureShellProfile {
         }
     }
 }
+
+/// The lines of the sh and zsh snippet which put the commands of `order` first on `PATH`, if Homebrew is (still) installed in `prefix`
+fn homebrew_path_shell(prefix: &Path, order: HomebrewPathOrder) -> String {
+    let prefix = prefix.display();
+    let path = match order {
+        HomebrewPathOrder::NixFirst => {
+            format!("$HOME/.nix-profile/bin:{PROFILE_NIX_DEFAULT}/bin")
+        },
+        HomebrewPathOrder::HomebrewFirst => format!("{prefix}/bin:{prefix}/sbin"),
+    };
+    format!(
+        "\
+        if [ -x '{prefix}/bin/brew' ]; then\n\
+        {inde}export PATH=\"{path}:$PATH\"\n\
+        fi\n\
+        ",
+        inde = "    ", // indent
+    )
+}
+
+/// The lines of the fish snippet which put the commands of `order` first on `PATH`, if Homebrew is (still) installed in `prefix`
+fn homebrew_path_fish(prefix: &Path, order: HomebrewPathOrder) -> String {
+    let prefix = prefix.display();
+    let paths = match order {
+        HomebrewPathOrder::NixFirst => {
+            format!("\"$HOME/.nix-profile/bin\" '{PROFILE_NIX_DEFAULT}/bin'")
+        },
+        HomebrewPathOrder::HomebrewFirst => format!("'{prefix}/bin' '{prefix}/sbin'"),
+    };
+    format!(
+        "\
+        if test -x '{prefix}/bin/brew'\n\
+        {inde}fish_add_path --global --move --path {paths}\n\
+        end\n\
+        ",
+        inde = "    ", // indent
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use super::{homebrew_path_shell, ConfigureShellProfile};
+    use crate::action::{Action, Artifact};
+    use crate::settings::HomebrewPathOrder;
+
+    #[tokio::test]
+    async fn creates_missing_directories_innermost_first() -> eyre::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let autoload = temp_dir.path().join("nushell/vendor/autoload");
+        let profile_target = autoload.join("nix.nu");
+
+        let mut create_directories = vec![];
+        let mut create_or_insert_files = vec![];
+        ConfigureShellProfile::plan_profile(
+            &profile_target,
+            None,
+            "# Nix\n# End Nix\n",
+            &mut create_directories,
+            &mut create_or_insert_files,
+        )
+        .await?;
+
+        let directories = create_directories
+            .iter()
+            .flat_map(|create_directory| create_directory.action.artifacts())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            directories,
+            vec![
+                Artifact::Directory(autoload.clone()),
+                Artifact::Directory(temp_dir.path().join("nushell/vendor")),
+                Artifact::Directory(temp_dir.path().join("nushell")),
+            ]
+        );
+        assert_eq!(create_or_insert_files.len(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn orders_homebrew_and_nix_on_path() {
+        let prefix = Path::new("/opt/homebrew");
+        let homebrew_first = homebrew_path_shell(prefix, HomebrewPathOrder::HomebrewFirst);
+        assert_eq!(
+            homebrew_first,
+            "if [ -x '/opt/homebrew/bin/brew' ]; then\n    export PATH=\"/opt/homebrew/bin:/opt/homebrew/sbin:$PATH\"\nfi\n"
+        );
+        let nix_first = homebrew_path_shell(prefix, HomebrewPathOrder::NixFirst);
+        assert!(nix_first.contains(
+            "export PATH=\"$HOME/.nix-profile/bin:/nix/var/nix/profiles/default/bin:$PATH\""
+        ));
+    }
+}
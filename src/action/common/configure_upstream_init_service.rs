@@ -2,7 +2,8 @@ use std::path::PathBuf;
 
 use tracing::{span, Span};
 
-use crate::action::{ActionError, ActionTag, StatefulAction};
+use crate::action::{ActionError, ActionTag, Artifact, DryRunStep, StatefulAction};
+use crate::drift::DriftedAction;
 
 use crate::action::common::configure_init_service::{SocketFile, UnitSrc};
 use crate::action::{common::ConfigureInitService, Action, ActionDescription};
@@ -32,6 +33,8 @@ impl ConfigureUpstreamInitService {
     pub async fn plan(
         init: InitSystem,
         start_daemon: bool,
+        ssl_cert_file: Option<PathBuf>,
+        daemon_env: Vec<(String, String)>,
     ) -> Result<StatefulAction<Self>, ActionError> {
         let service_src: Option<PathBuf> = match init {
             InitSystem::Launchd => Some(DARWIN_NIX_DAEMON_SOURCE.into()),
@@ -61,6 +64,8 @@ impl ConfigureUpstreamInitService {
                 ),
                 dest: "/etc/systemd/system/nix-daemon.socket".into(),
             }],
+            ssl_cert_file,
+            daemon_env,
         )
         .await
         .map_err(Self::error)?;
@@ -103,6 +108,18 @@ impl Action for ConfigureUpstreamInitService {
         Ok(())
     }
 
+    fn dry_run(&self) -> Vec<DryRunStep> {
+        self.configure_init_service.dry_run()
+    }
+
+    fn artifacts(&self) -> Vec<Artifact> {
+        self.configure_init_service.artifacts()
+    }
+
+    async fn drift(&self) -> Vec<DriftedAction> {
+        self.configure_init_service.drift().await
+    }
+
     fn revert_description(&self) -> Vec<ActionDescription> {
         vec![ActionDescription::new(
             "Remove upstream Nix daemon service".to_string(),
@@ -110,6 +127,10 @@ impl Action for ConfigureUpstreamInitService {
         )]
     }
 
+    fn revert_dry_run(&self) -> Vec<DryRunStep> {
+        self.configure_init_service.revert_dry_run()
+    }
+
     #[tracing::instrument(level = "debug", skip_all)]
     async fn revert(&mut self) -> Result<(), ActionError> {
         self.configure_init_service.try_revert().await?;
@@ -0,0 +1,223 @@
+use std::path::{Path, PathBuf};
+
+use tracing::{span, Span};
+
+use crate::action::base::{CreateDirectory, CreateFile};
+use crate::action::common::provision_determinate_nixd::DETERMINATE_NIXD_BINARY_PATH;
+use crate::action::{
+    Action, ActionDescription, ActionError, ActionTag, Artifact, DryRunStep, StatefulAction,
+};
+use crate::drift::DriftedAction;
+use crate::settings::Distribution;
+
+/// Where the start script is written
+pub const DAEMON_START_SCRIPT: &str = "/usr/local/bin/nix-daemon-start";
+
+const UPSTREAM_DAEMON: &str = "/nix/var/nix/profiles/default/bin/nix-daemon --daemon";
+
+/**
+Write a script which starts the Nix daemon, for `--init none` installs
+
+Containers and init systems `nix-installer` doesn't support can run it (as root) rather than
+reproducing what the systemd unit sets up: the environment, the open file limit, and logging.
+*/
+#[derive(Debug, serde::Deserialize, serde::Serialize, Clone)]
+#[serde(tag = "action_name", rename = "create_daemon_start_script")]
+pub struct CreateDaemonStartScript {
+    /// `/usr/local/bin`, if it doesn't exist (like in some container images)
+    create_directory: Option<StatefulAction<CreateDirectory>>,
+    create_file: StatefulAction<CreateFile>,
+}
+
+impl CreateDaemonStartScript {
+    #[tracing::instrument(level = "debug", skip_all)]
+    pub async fn plan(
+        distribution: Distribution,
+        ssl_cert_file: Option<PathBuf>,
+        daemon_env: Vec<(String, String)>,
+    ) -> Result<StatefulAction<Self>, ActionError> {
+        let create_directory = match Path::new(DAEMON_START_SCRIPT).parent() {
+            Some(parent) if !parent.exists() => Some(
+                CreateDirectory::plan(parent, None, None, 0o755, false)
+                    .await
+                    .map_err(Self::error)?,
+            ),
+            _ => None,
+        };
+        let create_file = CreateFile::plan(
+            DAEMON_START_SCRIPT,
+            None,
+            None,
+            0o744,
+            script(distribution, ssl_cert_file, &daemon_env),
+            false,
+        )
+        .await
+        .map_err(Self::error)?;
+
+        Ok(Self {
+            create_directory,
+            create_file,
+        }
+        .into())
+    }
+}
+
+/// The script, which runs the daemon in the foreground so a supervisor (or `&`) decides how it runs
+fn script(
+    distribution: Distribution,
+    ssl_cert_file: Option<PathBuf>,
+    daemon_env: &[(String, String)],
+) -> String {
+    let daemon = match distribution {
+        Distribution::Determinate => format!("{DETERMINATE_NIXD_BINARY_PATH} daemon"),
+        Distribution::Upstream | Distribution::Lix => UPSTREAM_DAEMON.to_string(),
+    };
+    let ssl_cert_file = match ssl_cert_file {
+        Some(ssl_cert_file) => format!(
+            "export NIX_SSL_CERT_FILE=\"${{NIX_SSL_CERT_FILE:-{}}}\"\n",
+            ssl_cert_file.display()
+        ),
+        None => String::new(),
+    };
+    let daemon_env = daemon_env
+        .iter()
+        .map(|(key, value)| format!("export {key}='{}'\n", value.replace('\'', r"'\''")))
+        .collect::<String>();
+    format!(
+        "#!/bin/sh\n\
+        # Start the Nix daemon, for systems without an init system nix-installer configures.\n\
+        # Written by nix-installer, and removed by `nix-installer uninstall`.\n\
+        #\n\
+        # Logs go to `/nix/var/log/nix/nix-daemon.log`, or to stderr with `NIX_DAEMON_LOG=-`.\n\
+        set -eu\n\
+        \n\
+        if [ \"$(id -u)\" -ne 0 ]; then\n\
+        {inde}echo \"nix-daemon-start: must be run as root\" >&2\n\
+        {inde}exit 1\n\
+        fi\n\
+        \n\
+        export PATH=\"/nix/var/nix/profiles/default/bin:$PATH\"\n\
+        {ssl_cert_file}\
+        {daemon_env}\
+        # Like the systemd unit's `LimitNOFILE`, as high as allowed if not\n\
+        ulimit -n 1048576 2>/dev/null || ulimit -n \"$(ulimit -H -n)\"\n\
+        \n\
+        log=\"${{NIX_DAEMON_LOG:-/nix/var/log/nix/nix-daemon.log}}\"\n\
+        if [ \"$log\" = \"-\" ]; then\n\
+        {inde}exec {daemon}\n\
+        fi\n\
+        mkdir -p \"$(dirname \"$log\")\"\n\
+        exec {daemon} >>\"$log\" 2>&1\n",
+        inde = "    ", // indent
+    )
+}
+
+#[async_trait::async_trait]
+#[typetag::serde(name = "create_daemon_start_script")]
+impl Action for CreateDaemonStartScript {
+    fn action_tag() -> ActionTag {
+        ActionTag("create_daemon_start_script")
+    }
+    fn tracing_synopsis(&self) -> String {
+        format!("Create the Nix daemon start script `{DAEMON_START_SCRIPT}`")
+    }
+
+    fn tracing_span(&self) -> Span {
+        span!(tracing::Level::DEBUG, "create_daemon_start_script",)
+    }
+
+    fn execute_description(&self) -> Vec<ActionDescription> {
+        vec![ActionDescription::new(
+            self.tracing_synopsis(),
+            vec![
+                "With `--init none` nothing starts the Nix daemon, run this script as root to start it"
+                    .to_string(),
+            ],
+        )]
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    async fn execute(&mut self) -> Result<(), ActionError> {
+        if let Some(create_directory) = &mut self.create_directory {
+            create_directory.try_execute().await.map_err(Self::error)?;
+        }
+        self.create_file.try_execute().await.map_err(Self::error)?;
+
+        Ok(())
+    }
+
+    fn dry_run(&self) -> Vec<DryRunStep> {
+        let mut steps = vec![];
+        if let Some(create_directory) = &self.create_directory {
+            steps.extend(create_directory.dry_run());
+        }
+        steps.extend(self.create_file.dry_run());
+        steps
+    }
+
+    fn artifacts(&self) -> Vec<Artifact> {
+        let mut artifacts = vec![];
+        if let Some(create_directory) = &self.create_directory {
+            artifacts.extend(create_directory.artifacts());
+        }
+        artifacts.extend(self.create_file.artifacts());
+        artifacts
+    }
+
+    async fn drift(&self) -> Vec<DriftedAction> {
+        self.create_file.drift().await
+    }
+
+    fn revert_description(&self) -> Vec<ActionDescription> {
+        vec![ActionDescription::new(
+            format!("Remove the Nix daemon start script `{DAEMON_START_SCRIPT}`"),
+            vec![],
+        )]
+    }
+
+    fn revert_dry_run(&self) -> Vec<DryRunStep> {
+        let mut steps = self.create_file.revert_dry_run();
+        if let Some(create_directory) = &self.create_directory {
+            steps.extend(create_directory.revert_dry_run());
+        }
+        steps
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    async fn revert(&mut self) -> Result<(), ActionError> {
+        self.create_file.try_revert().await?;
+        if let Some(create_directory) = &mut self.create_directory {
+            create_directory.try_revert().await?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::script;
+    use crate::settings::Distribution;
+
+    #[test]
+    fn starts_the_distributions_daemon() {
+        let upstream = script(
+            Distribution::Upstream,
+            Some("/etc/ssl/certs/ca-certificates.crt".into()),
+            &[("http_proxy".into(), "http://it's.example:3128".into())],
+        );
+        assert!(upstream.starts_with("#!/bin/sh\n"));
+        assert!(upstream.contains(
+            "exec /nix/var/nix/profiles/default/bin/nix-daemon --daemon >>\"$log\" 2>&1"
+        ));
+        assert!(upstream.contains(
+            "export NIX_SSL_CERT_FILE=\"${NIX_SSL_CERT_FILE:-/etc/ssl/certs/ca-certificates.crt}\""
+        ));
+        assert!(upstream.contains("export http_proxy='http://it'\\''s.example:3128'\n"));
+
+        let determinate = script(Distribution::Determinate, None, &[]);
+        assert!(determinate.contains("exec /usr/local/bin/determinate-nixd daemon\n"));
+        assert!(!determinate.contains("NIX_SSL_CERT_FILE"));
+    }
+}
@@ -2,7 +2,8 @@ use tracing::{span, Span};
 
 use crate::action::base::CreateDirectory;
 use crate::action::{
-    Action, ActionDescription, ActionError, ActionErrorKind, ActionTag, StatefulAction,
+    Action, ActionDescription, ActionError, ActionErrorKind, ActionTag, Artifact, DryRunStep,
+    StatefulAction,
 };
 
 const PATHS: &[&str] = &[
@@ -86,6 +87,20 @@ impl Action for CreateNixTree {
         Ok(())
     }
 
+    fn dry_run(&self) -> Vec<DryRunStep> {
+        self.create_directories
+            .iter()
+            .flat_map(|create_directory| create_directory.dry_run())
+            .collect()
+    }
+
+    fn artifacts(&self) -> Vec<Artifact> {
+        self.create_directories
+            .iter()
+            .flat_map(|create_directory| create_directory.artifacts())
+            .collect()
+    }
+
     fn revert_description(&self) -> Vec<ActionDescription> {
         vec![ActionDescription::new(
             "Remove the directory tree in `/nix`".to_string(),
@@ -106,6 +121,14 @@ impl Action for CreateNixTree {
         )]
     }
 
+    fn revert_dry_run(&self) -> Vec<DryRunStep> {
+        self.create_directories
+            .iter()
+            .rev()
+            .flat_map(|create_directory| create_directory.revert_dry_run())
+            .collect()
+    }
+
     #[tracing::instrument(level = "debug", skip_all)]
     async fn revert(&mut self) -> Result<(), ActionError> {
         let mut errors = vec![];
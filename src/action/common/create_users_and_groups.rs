@@ -1,7 +1,8 @@
 use crate::{
     action::{
-        base::{AddUserToGroup, CreateGroup, CreateUser},
-        Action, ActionDescription, ActionError, ActionErrorKind, ActionTag, StatefulAction,
+        base::{AddUserToGroup, CreateGroup, CreateSnapshots, CreateUser},
+        Action, ActionDependencies, ActionDescription, ActionError, ActionErrorKind, ActionTag,
+        Artifact, DryRunStep, StatefulAction,
     },
     settings::CommonSettings,
 };
@@ -211,6 +212,25 @@ impl Action for CreateUsersAndGroups {
         Ok(())
     }
 
+    fn dry_run(&self) -> Vec<DryRunStep> {
+        let mut steps = self.create_group.dry_run();
+        for create_user in &self.create_users {
+            steps.extend(create_user.dry_run());
+        }
+        for add_user_to_group in &self.add_users_to_groups {
+            steps.extend(add_user_to_group.dry_run());
+        }
+        steps
+    }
+
+    fn artifacts(&self) -> Vec<Artifact> {
+        let mut artifacts = self.create_group.artifacts();
+        for create_user in &self.create_users {
+            artifacts.extend(create_user.artifacts());
+        }
+        artifacts
+    }
+
     fn revert_description(&self) -> Vec<ActionDescription> {
         let Self {
             nix_build_user_count: _,
@@ -258,6 +278,16 @@ impl Action for CreateUsersAndGroups {
         }
     }
 
+    fn revert_dry_run(&self) -> Vec<DryRunStep> {
+        // Deleting the users removes them from the groups
+        let mut steps = vec![];
+        for create_user in &self.create_users {
+            steps.extend(create_user.revert_dry_run());
+        }
+        steps.extend(self.create_group.revert_dry_run());
+        steps
+    }
+
     #[tracing::instrument(level = "debug", skip_all)]
     async fn revert(&mut self) -> Result<(), ActionError> {
         let mut errors = vec![];
@@ -288,4 +318,10 @@ impl Action for CreateUsersAndGroups {
             Err(Self::error(ActionErrorKind::MultipleChildren(errors)))
         }
     }
+
+    fn depends_on(&self) -> ActionDependencies {
+        // Users and groups live outside of `/nix`, so can be created while Nix is provisioned (which is
+        // planned before this, so doesn't wait for it either), once the system was snapshotted
+        ActionDependencies::Tags(vec![CreateSnapshots::action_tag()])
+    }
 }
@@ -3,8 +3,10 @@
 pub(crate) mod configure_determinate_nixd_init_service;
 pub(crate) mod configure_init_service;
 pub(crate) mod configure_nix;
+pub(crate) mod configure_remote_builders;
 pub(crate) mod configure_shell_profile;
 pub(crate) mod configure_upstream_init_service;
+pub(crate) mod create_daemon_start_script;
 pub(crate) mod create_nix_tree;
 pub(crate) mod create_users_and_groups;
 pub(crate) mod delete_users;
@@ -12,11 +14,15 @@ pub(crate) mod place_nix_configuration;
 pub(crate) mod provision_determinate_nixd;
 pub(crate) mod provision_nix;
 
-pub use configure_determinate_nixd_init_service::ConfigureDeterminateNixdInitService;
+pub use configure_determinate_nixd_init_service::{
+    ConfigureDeterminateNixDaemonServiceError, ConfigureDeterminateNixdInitService,
+};
 pub use configure_init_service::{ConfigureInitService, ConfigureNixDaemonServiceError};
 pub use configure_nix::ConfigureNix;
+pub use configure_remote_builders::ConfigureRemoteBuilders;
 pub use configure_shell_profile::ConfigureShellProfile;
 pub use configure_upstream_init_service::ConfigureUpstreamInitService;
+pub use create_daemon_start_script::CreateDaemonStartScript;
 pub use create_nix_tree::CreateNixTree;
 pub use create_users_and_groups::CreateUsersAndGroups;
 pub use delete_users::DeleteUsersInGroup;
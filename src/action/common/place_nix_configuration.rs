@@ -2,17 +2,22 @@ use tracing::{span, Span};
 use url::Url;
 
 use crate::action::base::create_or_merge_nix_config::CreateOrMergeNixConfigError;
-use crate::action::base::{CreateDirectory, CreateOrMergeNixConfig};
+use crate::action::base::{CreateDirectory, CreateFile, CreateOrMergeNixConfig};
 use crate::action::{
-    Action, ActionDescription, ActionError, ActionErrorKind, ActionTag, StatefulAction,
+    Action, ActionDescription, ActionError, ActionErrorKind, ActionTag, Artifact, DryRunStep,
+    StatefulAction,
 };
+use crate::drift::DriftedAction;
 use crate::parse_ssl_cert;
-use crate::settings::UrlOrPathOrString;
+use crate::preflight::sandbox_unavailable;
+use crate::settings::{Distribution, SandboxMode, UrlOrPathOrString};
 use indexmap::map::Entry;
 use std::path::PathBuf;
 
 pub const NIX_CONF_FOLDER: &str = "/etc/nix";
-const NIX_CONF: &str = "/etc/nix/nix.conf";
+pub(crate) const NIX_CONF: &str = "/etc/nix/nix.conf";
+/// Where the `--ssl-cert-file` bundle is copied so it outlives the file the user pointed us at
+pub const NIX_SSL_CERT_FILE: &str = "/etc/nix/ssl-cert-file.pem";
 
 /**
 Place the `/etc/nix.conf` file
@@ -21,36 +26,86 @@ Place the `/etc/nix.conf` file
 #[serde(tag = "action_name", rename = "place_nix_configuration")]
 pub struct PlaceNixConfiguration {
     create_directory: StatefulAction<CreateDirectory>,
+    create_ssl_cert_file: Option<StatefulAction<CreateFile>>,
     create_or_merge_nix_config: StatefulAction<CreateOrMergeNixConfig>,
 }
 
 impl PlaceNixConfiguration {
+    #[allow(clippy::too_many_arguments)]
     #[tracing::instrument(level = "debug", skip_all)]
     pub async fn plan(
         nix_build_group_name: String,
+        distribution: Distribution,
         proxy: Option<Url>,
         ssl_cert_file: Option<PathBuf>,
-        extra_internal_conf: Option<nix_config_parser::NixConfig>,
+        mut extra_internal_conf: Option<nix_config_parser::NixConfig>,
         extra_conf: Vec<UrlOrPathOrString>,
+        trusted_users: Vec<String>,
+        allowed_users: Vec<String>,
+        sandbox: Option<SandboxMode>,
+        extra_sandbox_paths: Vec<String>,
         force: bool,
     ) -> Result<StatefulAction<Self>, ActionError> {
-        let nix_config = Self::setup_nix_config(
+        // Set before the rest, so it isn't detected
+        if let Some(sandbox) = sandbox {
+            check_sandbox(sandbox).await;
+            extra_internal_conf
+                .get_or_insert_with(nix_config_parser::NixConfig::new)
+                .settings_mut()
+                .insert("sandbox".to_string(), sandbox.to_string());
+        }
+        let mut nix_config = Self::setup_nix_config(
             nix_build_group_name,
+            distribution,
             proxy,
-            ssl_cert_file,
+            ssl_cert_file.clone(),
             extra_internal_conf,
             extra_conf,
         )
         .await?;
+        if !trusted_users.is_empty() {
+            merge_list(&mut nix_config, "trusted-users", &["root"], &trusted_users);
+        }
+        if !allowed_users.is_empty() {
+            merge_list(&mut nix_config, "allowed-users", &[], &allowed_users);
+        }
+        if !extra_sandbox_paths.is_empty() {
+            if sandbox == Some(SandboxMode::False) {
+                tracing::warn!(
+                    "`--extra-sandbox-path` has no effect with `--sandbox false`, builds aren't sandboxed"
+                );
+            }
+            merge_list(
+                &mut nix_config,
+                "extra-sandbox-paths",
+                &[],
+                &extra_sandbox_paths,
+            );
+        }
 
         let create_directory = CreateDirectory::plan(NIX_CONF_FOLDER, None, None, 0o0755, force)
             .await
             .map_err(Self::error)?;
+        let create_ssl_cert_file = match ssl_cert_file {
+            Some(ssl_cert_file) => {
+                let buf = tokio::fs::read_to_string(&ssl_cert_file)
+                    .await
+                    .map_err(|e| ActionErrorKind::Read(ssl_cert_file, e))
+                    .map_err(Self::error)?;
+                Some(
+                    CreateFile::plan(NIX_SSL_CERT_FILE, None, None, 0o0644, buf, force)
+                        .await
+                        .map_err(Self::error)?,
+                )
+            },
+            None => None,
+        };
         let create_or_merge_nix_config = CreateOrMergeNixConfig::plan(NIX_CONF, nix_config)
             .await
             .map_err(Self::error)?;
         Ok(Self {
             create_directory,
+            create_ssl_cert_file,
             create_or_merge_nix_config,
         }
         .into())
@@ -58,6 +113,7 @@ impl PlaceNixConfiguration {
 
     async fn setup_nix_config(
         nix_build_group_name: String,
+        distribution: Distribution,
         proxy: Option<Url>,
         ssl_cert_file: Option<PathBuf>,
         extra_internal_conf: Option<nix_config_parser::NixConfig>,
@@ -73,7 +129,8 @@ impl PlaceNixConfiguration {
                             buildable_client = buildable_client.proxy(
                                 reqwest::Proxy::all(proxy.clone())
                                     .map_err(ActionErrorKind::Reqwest)
-                                    .map_err(Self::error)?,
+                                    .map_err(Self::error)?
+                                    .no_proxy(reqwest::NoProxy::from_env()),
                             )
                         }
                         if let Some(ssl_cert_file) = &ssl_cert_file {
@@ -123,7 +180,7 @@ impl PlaceNixConfiguration {
         let settings = nix_config.settings_mut();
 
         if let Some(extra) = extra_internal_conf {
-            settings.extend(extra.into_settings().into_iter());
+            settings.extend(extra.into_settings());
         }
 
         settings.insert("build-users-group".to_string(), nix_build_group_name);
@@ -200,33 +257,83 @@ impl PlaceNixConfiguration {
             };
         }
 
+        // Unless configured, builds fail (rather than fall back) where the sandbox can't be created
+        if !settings.contains_key("sandbox") {
+            if let Some(reason) = sandbox_unavailable().await {
+                tracing::warn!(
+                    "Builds can't be sandboxed, {reason}, so `sandbox = false` is set in `{NIX_CONF}`. \
+                    Once user namespaces are enabled (like with `sysctl -w user.max_user_namespaces=15000`), \
+                    remove it and restart the Nix daemon (installing with `--sandbox true` skips this)."
+                );
+                settings.insert("sandbox".to_string(), "false".to_string());
+            }
+        }
+
         settings.insert(
             "bash-prompt-prefix".to_string(),
             "(nix:$name)\\040".to_string(),
         );
         settings.insert("max-jobs".to_string(), "auto".to_string());
-        if let Some(ssl_cert_file) = ssl_cert_file {
-            let ssl_cert_file_canonical = ssl_cert_file
-                .canonicalize()
-                .map_err(|e| Self::error(ActionErrorKind::Canonicalize(ssl_cert_file, e)))?;
-            settings.insert(
-                "ssl-cert-file".to_string(),
-                ssl_cert_file_canonical.display().to_string(),
-            );
+        if ssl_cert_file.is_some() {
+            settings.insert("ssl-cert-file".to_string(), NIX_SSL_CERT_FILE.to_string());
         }
         settings.insert(
             "extra-nix-path".to_string(),
             "nixpkgs=flake:nixpkgs".to_string(),
         );
-        settings.insert(
-            "upgrade-nix-store-path-url".to_string(),
-            "https://install.determinate.systems/nix-upgrade/stable/universal".to_string(),
-        );
+        if let Some(upgrade_nix_store_path_url) = distribution.upgrade_nix_store_path_url() {
+            settings.insert(
+                "upgrade-nix-store-path-url".to_string(),
+                upgrade_nix_store_path_url.to_string(),
+            );
+        }
 
         Ok(nix_config)
     }
 }
 
+/// Add `values` to the `key` list of `nix_config`, starting from `defaults` if it isn't set, without duplicates
+fn merge_list(
+    nix_config: &mut nix_config_parser::NixConfig,
+    key: &str,
+    defaults: &[&str],
+    values: &[String],
+) {
+    let slot = nix_config
+        .settings_mut()
+        .entry(key.to_string())
+        .or_insert_with(|| defaults.join(" "));
+    let mut merged = slot
+        .split_whitespace()
+        .map(str::to_string)
+        .collect::<Vec<_>>();
+    for value in values {
+        if !merged.contains(value) {
+            merged.push(value.clone());
+        }
+    }
+    *slot = merged.join(" ");
+}
+
+/// Warn where `sandbox` won't work as it might be expected to on this host
+async fn check_sandbox(sandbox: SandboxMode) {
+    if sandbox == SandboxMode::False {
+        return;
+    }
+    if cfg!(target_os = "macos") {
+        tracing::warn!(
+            "The macOS sandbox (`sandbox = {sandbox}`) is built on `sandbox-exec`, which Apple has deprecated. \
+            Builds which reach outside the store (including some of Nixpkgs' Darwin packages) fail in it, \
+            which is why Nix defaults to `sandbox = false` on macOS."
+        );
+    } else if let Some(reason) = sandbox_unavailable().await {
+        tracing::warn!(
+            "Builds can't be sandboxed, {reason}, so with `sandbox = {sandbox}` they will fail \
+            until user namespaces are enabled (like with `sysctl -w user.max_user_namespaces=15000`)."
+        );
+    }
+}
+
 #[async_trait::async_trait]
 #[typetag::serde(name = "place_nix_configuration")]
 impl Action for PlaceNixConfiguration {
@@ -244,6 +351,7 @@ impl Action for PlaceNixConfiguration {
     fn execute_description(&self) -> Vec<ActionDescription> {
         let Self {
             create_or_merge_nix_config,
+            create_ssl_cert_file,
             create_directory,
         } = self;
 
@@ -255,6 +363,12 @@ impl Action for PlaceNixConfiguration {
         if let Some(val) = create_directory.describe_execute().first() {
             explanation.push(val.description.clone())
         }
+        if let Some(val) = create_ssl_cert_file
+            .as_ref()
+            .and_then(|v| v.describe_execute().first().cloned())
+        {
+            explanation.push(val.description)
+        }
         for val in create_or_merge_nix_config.describe_execute().iter() {
             explanation.push(val.description.clone())
         }
@@ -268,6 +382,12 @@ impl Action for PlaceNixConfiguration {
             .try_execute()
             .await
             .map_err(Self::error)?;
+        if let Some(create_ssl_cert_file) = &mut self.create_ssl_cert_file {
+            create_ssl_cert_file
+                .try_execute()
+                .await
+                .map_err(Self::error)?;
+        }
         self.create_or_merge_nix_config
             .try_execute()
             .await
@@ -276,6 +396,33 @@ impl Action for PlaceNixConfiguration {
         Ok(())
     }
 
+    fn dry_run(&self) -> Vec<DryRunStep> {
+        let mut steps = self.create_directory.dry_run();
+        if let Some(create_ssl_cert_file) = &self.create_ssl_cert_file {
+            steps.extend(create_ssl_cert_file.dry_run());
+        }
+        steps.extend(self.create_or_merge_nix_config.dry_run());
+        steps
+    }
+
+    fn artifacts(&self) -> Vec<Artifact> {
+        let mut artifacts = self.create_directory.artifacts();
+        if let Some(create_ssl_cert_file) = &self.create_ssl_cert_file {
+            artifacts.extend(create_ssl_cert_file.artifacts());
+        }
+        artifacts.extend(self.create_or_merge_nix_config.artifacts());
+        artifacts
+    }
+
+    async fn drift(&self) -> Vec<DriftedAction> {
+        let mut drifted = self.create_directory.drift().await;
+        if let Some(create_ssl_cert_file) = &self.create_ssl_cert_file {
+            drifted.extend(create_ssl_cert_file.drift().await);
+        }
+        drifted.extend(self.create_or_merge_nix_config.drift().await);
+        drifted
+    }
+
     fn revert_description(&self) -> Vec<ActionDescription> {
         vec![ActionDescription::new(
             format!("Remove the Nix configuration in `{NIX_CONF}`"),
@@ -286,12 +433,26 @@ impl Action for PlaceNixConfiguration {
         )]
     }
 
+    fn revert_dry_run(&self) -> Vec<DryRunStep> {
+        let mut steps = self.create_or_merge_nix_config.revert_dry_run();
+        if let Some(create_ssl_cert_file) = &self.create_ssl_cert_file {
+            steps.extend(create_ssl_cert_file.revert_dry_run());
+        }
+        steps.extend(self.create_directory.revert_dry_run());
+        steps
+    }
+
     #[tracing::instrument(level = "debug", skip_all)]
     async fn revert(&mut self) -> Result<(), ActionError> {
         let mut errors = vec![];
         if let Err(err) = self.create_or_merge_nix_config.try_revert().await {
             errors.push(err);
         }
+        if let Some(create_ssl_cert_file) = &mut self.create_ssl_cert_file {
+            if let Err(err) = create_ssl_cert_file.try_revert().await {
+                errors.push(err);
+            }
+        }
         if let Err(err) = self.create_directory.try_revert().await {
             errors.push(err);
         }
@@ -317,6 +478,7 @@ mod tests {
     async fn extra_trusted_no_error() -> eyre::Result<()> {
         let nix_config = PlaceNixConfiguration::setup_nix_config(
             String::from("foo"),
+            Distribution::Upstream,
             None,
             None,
             None,
@@ -347,4 +509,58 @@ mod tests {
 
         Ok(())
     }
+
+    #[tokio::test]
+    async fn configured_sandbox_is_kept() -> eyre::Result<()> {
+        let nix_config = PlaceNixConfiguration::setup_nix_config(
+            String::from("foo"),
+            Distribution::Upstream,
+            None,
+            None,
+            None,
+            vec![UrlOrPathOrString::String(String::from("sandbox = relaxed"))],
+        )
+        .await?;
+
+        assert_eq!(
+            nix_config.settings().get("sandbox").map(String::as_str),
+            Some("relaxed")
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn merges_trusted_users() -> eyre::Result<()> {
+        let mut nix_config = PlaceNixConfiguration::setup_nix_config(
+            String::from("foo"),
+            Distribution::Upstream,
+            None,
+            None,
+            None,
+            vec![UrlOrPathOrString::String(String::from(
+                "trusted-users = root @wheel",
+            ))],
+        )
+        .await?;
+        merge_list(
+            &mut nix_config,
+            "trusted-users",
+            &["root"],
+            &["@wheel".into(), "runner".into()],
+        );
+        merge_list(&mut nix_config, "allowed-users", &[], &["runner".into()]);
+
+        let settings = nix_config.settings();
+        assert_eq!(
+            settings.get("trusted-users").map(String::as_str),
+            Some("root @wheel runner")
+        );
+        assert_eq!(
+            settings.get("allowed-users").map(String::as_str),
+            Some("runner")
+        );
+
+        Ok(())
+    }
 }
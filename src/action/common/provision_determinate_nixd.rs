@@ -5,10 +5,12 @@ use tokio::fs::{create_dir_all, remove_file};
 use tracing::{span, Span};
 
 use crate::action::{
-    Action, ActionDescription, ActionError, ActionErrorKind, ActionTag, StatefulAction,
+    base::CreateSnapshots, Action, ActionDependencies, ActionDescription, ActionError,
+    ActionErrorKind, ActionTag, Artifact, DryRunStep, StatefulAction,
 };
+use crate::audit::{self, AuditEvent};
 
-const DETERMINATE_NIXD_BINARY_PATH: &str = "/usr/local/bin/determinate-nixd";
+pub(crate) const DETERMINATE_NIXD_BINARY_PATH: &str = "/usr/local/bin/determinate-nixd";
 /**
 Provision the determinate-nixd binary
 */
@@ -57,6 +59,10 @@ impl Action for ProvisionDeterminateNixd {
         )]
     }
 
+    fn artifacts(&self) -> Vec<Artifact> {
+        vec![Artifact::File(self.binary_location.clone())]
+    }
+
     #[tracing::instrument(level = "debug", skip_all)]
     async fn execute(&mut self) -> Result<(), ActionError> {
         let bytes = crate::settings::DETERMINATE_NIXD_BINARY
@@ -80,6 +86,7 @@ impl Action for ProvisionDeterminateNixd {
             .await
             .map_err(|e| ActionErrorKind::Write(self.binary_location.clone(), e))
             .map_err(Self::error)?;
+        audit::file_written(&self.binary_location, bytes);
 
         tokio::fs::set_permissions(&self.binary_location, PermissionsExt::from_mode(0o555))
             .await
@@ -96,6 +103,10 @@ impl Action for ProvisionDeterminateNixd {
         )]
     }
 
+    fn revert_dry_run(&self) -> Vec<DryRunStep> {
+        vec![DryRunStep::Remove(self.binary_location.clone())]
+    }
+
     #[tracing::instrument(level = "debug", skip_all)]
     async fn revert(&mut self) -> Result<(), ActionError> {
         if self.binary_location.exists() {
@@ -103,8 +114,16 @@ impl Action for ProvisionDeterminateNixd {
                 .await
                 .map_err(|e| ActionErrorKind::Remove(self.binary_location.clone(), e))
                 .map_err(Self::error)?;
+            audit::record(AuditEvent::FileRemoved {
+                path: self.binary_location.clone(),
+            });
         }
 
         Ok(())
     }
+
+    fn depends_on(&self) -> ActionDependencies {
+        // The binary lives outside of `/nix`, so can be placed while Nix is provisioned, once the system was snapshotted
+        ActionDependencies::Tags(vec![CreateSnapshots::action_tag()])
+    }
 }
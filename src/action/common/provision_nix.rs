@@ -1,10 +1,11 @@
 use tracing::{span, Span};
 
-use super::CreateNixTree;
+use super::{CreateNixTree, ProvisionDeterminateNixd};
 use crate::{
     action::{
-        base::{FetchAndUnpackNix, MoveUnpackedNix},
-        Action, ActionDescription, ActionError, ActionErrorKind, ActionTag, StatefulAction,
+        base::{DownloadPolicy, FetchAndUnpackNix, MoveUnpackedNix, NixPackageVerification},
+        Action, ActionDependencies, ActionDescription, ActionError, ActionErrorKind, ActionTag,
+        Artifact, DryRunStep, StatefulAction,
     },
     settings::{CommonSettings, SCRATCH_DIR},
 };
@@ -24,18 +25,31 @@ pub struct ProvisionNix {
 impl ProvisionNix {
     #[tracing::instrument(level = "debug", skip_all)]
     pub async fn plan(settings: &CommonSettings) -> Result<StatefulAction<Self>, ActionError> {
+        let nix_package = settings.nix_package();
         let fetch_nix = FetchAndUnpackNix::plan(
-            settings.nix_package_url.clone(),
+            nix_package,
+            settings.nix_package_mirrors.clone(),
             PathBuf::from(SCRATCH_DIR),
             settings.proxy.clone(),
             settings.ssl_cert_file.clone(),
+            DownloadPolicy {
+                retries: settings.download_retries,
+                timeout: Some(settings.download_timeout),
+                ip_version: settings.ip_version,
+            },
+            NixPackageVerification {
+                sha256: settings.nix_package_sha256.clone(),
+                signature: settings.nix_package_signature.clone(),
+                public_key: settings.nix_package_public_key.clone(),
+            },
         )
         .await?;
 
         let create_nix_tree = CreateNixTree::plan().await.map_err(Self::error)?;
-        let move_unpacked_nix = MoveUnpackedNix::plan(PathBuf::from(SCRATCH_DIR))
-            .await
-            .map_err(Self::error)?;
+        let move_unpacked_nix =
+            MoveUnpackedNix::plan(PathBuf::from(SCRATCH_DIR), settings.distribution(), false)
+                .await
+                .map_err(Self::error)?;
         Ok(Self {
             fetch_nix,
             create_nix_tree,
@@ -101,6 +115,23 @@ impl Action for ProvisionNix {
         Ok(())
     }
 
+    fn dry_run(&self) -> Vec<DryRunStep> {
+        let mut steps = self.fetch_nix.dry_run();
+        steps.extend(self.create_nix_tree.dry_run());
+        steps.extend(self.move_unpacked_nix.dry_run());
+        steps
+    }
+
+    fn artifacts(&self) -> Vec<Artifact> {
+        let mut artifacts = self.fetch_nix.artifacts();
+        artifacts.extend(self.create_nix_tree.artifacts());
+        artifacts
+    }
+
+    fn holds_store(&self) -> bool {
+        true
+    }
+
     fn revert_description(&self) -> Vec<ActionDescription> {
         let Self {
             fetch_nix,
@@ -116,6 +147,13 @@ impl Action for ProvisionNix {
         buf
     }
 
+    fn revert_dry_run(&self) -> Vec<DryRunStep> {
+        let mut steps = self.fetch_nix.revert_dry_run();
+        steps.extend(self.create_nix_tree.revert_dry_run());
+        steps.extend(self.move_unpacked_nix.revert_dry_run());
+        steps
+    }
+
     #[tracing::instrument(level = "debug", skip_all)]
     async fn revert(&mut self) -> Result<(), ActionError> {
         let mut errors = vec![];
@@ -143,4 +181,9 @@ impl Action for ProvisionNix {
             Err(Self::error(ActionErrorKind::MultipleChildren(errors)))
         }
     }
+
+    fn depends_on(&self) -> ActionDependencies {
+        // What comes before prepares `/nix` (which Nix is fetched into), but `determinate-nixd` lives outside of it
+        ActionDependencies::AllExcept(vec![ProvisionDeterminateNixd::action_tag()])
+    }
 }
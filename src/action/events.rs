@@ -0,0 +1,275 @@
+/*! Typed events for each [`Action`] as it is executed (or reverted), and a gate to approve each first
+
+Unlike [`ProgressEvent`](crate::progress::ProgressEvent)s, which count the steps of a plan, these
+carry the [`ActionTag`] of every action run, including those inside others (like
+[`ConfigureNix`](crate::action::common::ConfigureNix)), for UIs and metrics:
+
+```rust
+use nix_installer::action::{subscribe_action_events, ActionEvent};
+
+let mut events = subscribe_action_events();
+// ... `InstallPlan::install` ...
+while let Ok(event) = events.try_recv() {
+    if let ActionEvent::ActionFailed { tag, error, .. } = event {
+        eprintln!("`{tag}` failed: {error}");
+    }
+}
+```
+
+With an approver set by [`set_action_approver`], each action waits for its [`ActionApproval`]
+before it runs, and fails if it is denied (or the approver goes away):
+
+```rust,no_run
+use nix_installer::action::{clear_action_approver, set_action_approver};
+
+let (tx, mut approvals) = tokio::sync::mpsc::unbounded_channel();
+set_action_approver(tx);
+tokio::spawn(async move {
+    while let Some(approval) = approvals.recv().await {
+        if approval.tag.0 == "create_user" {
+            approval.deny();
+        } else {
+            approval.approve();
+        }
+    }
+});
+// ... `InstallPlan::install` ...
+clear_action_approver();
+```
+*/
+
+use std::sync::RwLock;
+
+use tokio::sync::{
+    mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender},
+    oneshot,
+};
+use tracing::Span;
+
+use super::{policy::run_with_policy, Action, ActionError, ActionErrorKind, ActionTag};
+
+static SUBSCRIBERS: RwLock<Vec<UnboundedSender<ActionEvent>>> = RwLock::new(Vec::new());
+static APPROVER: RwLock<Option<UnboundedSender<ActionApproval>>> = RwLock::new(None);
+
+/// What happened to an [`Action`], with its [`ActionTag`] and `description` (its synopsis)
+#[non_exhaustive]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ActionEvent {
+    /// The action started executing
+    ActionStarted { tag: ActionTag, description: String },
+    /// The action finished executing
+    ActionCompleted { tag: ActionTag, description: String },
+    /// The action started being reverted
+    RevertStarted { tag: ActionTag, description: String },
+    /// The action finished being reverted
+    ActionReverted { tag: ActionTag, description: String },
+    /// The action failed to execute (or, if `revert`, to revert) after any retries
+    ActionFailed {
+        tag: ActionTag,
+        description: String,
+        revert: bool,
+        error: String,
+    },
+}
+
+impl ActionEvent {
+    /// The [`ActionTag`] of the action the event is about
+    pub fn tag(&self) -> ActionTag {
+        match self {
+            Self::ActionStarted { tag, .. }
+            | Self::ActionCompleted { tag, .. }
+            | Self::RevertStarted { tag, .. }
+            | Self::ActionReverted { tag, .. }
+            | Self::ActionFailed { tag, .. } => *tag,
+        }
+    }
+}
+
+/// A request to run an [`Action`], sent to the approver set with [`set_action_approver`]
+///
+/// The action waits until it is approved or denied, dropping it denies it.
+#[derive(Debug)]
+pub struct ActionApproval {
+    pub tag: ActionTag,
+    pub description: String,
+    /// If the action is to be reverted, rather than executed
+    pub revert: bool,
+    reply: oneshot::Sender<bool>,
+}
+
+impl ActionApproval {
+    pub fn approve(self) {
+        self.reply.send(true).ok();
+    }
+
+    pub fn deny(self) {
+        self.reply.send(false).ok();
+    }
+}
+
+/// Receive the [`ActionEvent`]s of every action executed (or reverted) after this
+///
+/// There may be any number of subscribers, each gets every event. Dropping the receiver
+/// unsubscribes.
+pub fn subscribe_action_events() -> UnboundedReceiver<ActionEvent> {
+    let (tx, rx) = unbounded_channel();
+    SUBSCRIBERS
+        .write()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .push(tx);
+    rx
+}
+
+/// Ask `sender` to approve every action before it is executed (or reverted) after this
+pub fn set_action_approver(sender: UnboundedSender<ActionApproval>) {
+    let mut current = APPROVER
+        .write()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    *current = Some(sender);
+}
+
+/// Run actions without asking for approval
+pub fn clear_action_approver() {
+    let mut current = APPROVER
+        .write()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    *current = None;
+}
+
+fn emit(event: ActionEvent) {
+    let mut subscribers = SUBSCRIBERS
+        .write()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    subscribers.retain(|subscriber| subscriber.send(event.clone()).is_ok());
+}
+
+/// Ask `approver` to approve an action, a closed channel (or dropped approval) denies it
+async fn request_approval(
+    approver: &UnboundedSender<ActionApproval>,
+    tag: ActionTag,
+    description: String,
+    revert: bool,
+) -> bool {
+    let (reply, approved) = oneshot::channel();
+    let approval = ActionApproval {
+        tag,
+        description,
+        revert,
+        reply,
+    };
+    if approver.send(approval).is_err() {
+        return false;
+    }
+    approved.await.unwrap_or(false)
+}
+
+/// [`run_with_policy`], once approved, emitting [`ActionEvent`]s around it
+pub(crate) async fn run_with_events<A>(
+    action: &mut A,
+    tag: ActionTag,
+    span: &Span,
+    revert: bool,
+) -> Result<(), ActionError>
+where
+    A: Action + ?Sized,
+{
+    let description = action.tracing_synopsis();
+    let approver = APPROVER
+        .read()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .clone();
+    if let Some(approver) = approver {
+        if !request_approval(&approver, tag, description.clone(), revert).await {
+            let err = ActionError::new(tag, ActionErrorKind::NotApproved);
+            emit(ActionEvent::ActionFailed {
+                tag,
+                description,
+                revert,
+                error: err.to_string(),
+            });
+            return Err(err);
+        }
+    }
+
+    emit(if revert {
+        ActionEvent::RevertStarted {
+            tag,
+            description: description.clone(),
+        }
+    } else {
+        ActionEvent::ActionStarted {
+            tag,
+            description: description.clone(),
+        }
+    });
+    let res = run_with_policy(action, tag, span, revert).await;
+    emit(match &res {
+        Ok(()) if revert => ActionEvent::ActionReverted { tag, description },
+        Ok(()) => ActionEvent::ActionCompleted { tag, description },
+        Err(err) => ActionEvent::ActionFailed {
+            tag,
+            description,
+            revert,
+            error: err.to_string(),
+        },
+    });
+    res
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        emit, request_approval, subscribe_action_events, ActionApproval, ActionEvent, ActionTag,
+    };
+
+    #[tokio::test]
+    async fn delivers_events_to_every_subscriber() -> eyre::Result<()> {
+        let tag = ActionTag("delivers_events_to_every_subscriber");
+        let mut first = subscribe_action_events();
+        let mut second = subscribe_action_events();
+        let event = ActionEvent::ActionStarted {
+            tag,
+            description: "Do something".to_string(),
+        };
+        emit(event.clone());
+
+        // Actions run by other tests are reported too
+        for receiver in [&mut first, &mut second] {
+            let mut received = None;
+            while let Ok(next) = receiver.try_recv() {
+                if next.tag() == tag {
+                    received = Some(next);
+                }
+            }
+            assert_eq!(received, Some(event.clone()));
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn denies_unless_approved() -> eyre::Result<()> {
+        let tag = ActionTag("denies_unless_approved");
+        let (tx, mut approvals) = tokio::sync::mpsc::unbounded_channel::<ActionApproval>();
+        let approver = tokio::spawn(async move {
+            let mut revert = Vec::new();
+            while let Some(approval) = approvals.recv().await {
+                revert.push(approval.revert);
+                match approval.revert {
+                    true => approval.approve(),
+                    false => drop(approval),
+                }
+            }
+            revert
+        });
+
+        assert!(request_approval(&tx, tag, "Do something".into(), true).await);
+        assert!(!request_approval(&tx, tag, "Do something".into(), false).await);
+        drop(tx);
+        assert_eq!(approver.await?, vec![true, false]);
+
+        let (tx, approvals) = tokio::sync::mpsc::unbounded_channel();
+        drop(approvals);
+        assert!(!request_approval(&tx, tag, "Do something".into(), false).await);
+        Ok(())
+    }
+}
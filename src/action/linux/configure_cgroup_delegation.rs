@@ -0,0 +1,151 @@
+use std::path::PathBuf;
+
+use tokio::fs::{create_dir_all, remove_file};
+use tracing::{span, Span};
+
+use crate::action::{
+    Action, ActionDescription, ActionError, ActionErrorKind, ActionTag, Artifact, DryRunStep,
+    StatefulAction,
+};
+use crate::audit::{self, AuditEvent};
+
+/// The systemd drop-in which delegates the daemon's cgroup to it
+pub const CGROUP_DELEGATION_DROPIN_PATH: &str =
+    "/etc/systemd/system/nix-daemon.service.d/cgroups.conf";
+
+/**
+Delegate the Nix daemon's cgroup to it, so it can put each build in a cgroup of its own
+
+The `nix.conf` half (`use-cgroups`, and the `cgroups` experimental feature) is set by
+[`PlaceNixConfiguration`](crate::action::common::PlaceNixConfiguration). Without `Delegate=yes`,
+systemd owns the cgroups below the daemon's, and moves the builds' processes back out of them.
+*/
+#[derive(Debug, serde::Deserialize, serde::Serialize, Clone)]
+#[serde(tag = "action_name", rename = "configure_cgroup_delegation")]
+pub struct ConfigureCgroupDelegation {
+    dropin_path: PathBuf,
+}
+
+impl ConfigureCgroupDelegation {
+    #[tracing::instrument(level = "debug", skip_all)]
+    pub async fn plan() -> Result<StatefulAction<Self>, ActionError> {
+        let this = Self {
+            dropin_path: CGROUP_DELEGATION_DROPIN_PATH.into(),
+        };
+
+        if tokio::fs::read_to_string(&this.dropin_path)
+            .await
+            .is_ok_and(|existing| existing == dropin())
+        {
+            return Ok(StatefulAction::completed(this));
+        }
+
+        Ok(StatefulAction::uncompleted(this))
+    }
+}
+
+#[async_trait::async_trait]
+#[typetag::serde(name = "configure_cgroup_delegation")]
+impl Action for ConfigureCgroupDelegation {
+    fn action_tag() -> ActionTag {
+        ActionTag("configure_cgroup_delegation")
+    }
+    fn tracing_synopsis(&self) -> String {
+        "Delegate the Nix daemon's cgroup to it, for builds in cgroups of their own".to_string()
+    }
+
+    fn tracing_span(&self) -> Span {
+        span!(
+            tracing::Level::DEBUG,
+            "configure_cgroup_delegation",
+            dropin_path = %self.dropin_path.display()
+        )
+    }
+
+    fn execute_description(&self) -> Vec<ActionDescription> {
+        vec![ActionDescription::new(
+            self.tracing_synopsis(),
+            vec![format!(
+                "Set `Delegate=yes` in `{}`",
+                self.dropin_path.display()
+            )],
+        )]
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    async fn execute(&mut self) -> Result<(), ActionError> {
+        if let Some(parent) = self.dropin_path.parent() {
+            create_dir_all(&parent)
+                .await
+                .map_err(|e| ActionErrorKind::CreateDirectory(parent.into(), e))
+                .map_err(Self::error)?;
+        }
+        let content = dropin();
+        tokio::fs::write(&self.dropin_path, &content)
+            .await
+            .map_err(|e| ActionErrorKind::Write(self.dropin_path.clone(), e))
+            .map_err(Self::error)?;
+        audit::file_written(&self.dropin_path, content.as_bytes());
+
+        Ok(())
+    }
+
+    fn dry_run(&self) -> Vec<DryRunStep> {
+        vec![DryRunStep::WriteFile {
+            path: self.dropin_path.clone(),
+            mode: None,
+            contents: dropin(),
+        }]
+    }
+
+    fn artifacts(&self) -> Vec<Artifact> {
+        vec![Artifact::File(self.dropin_path.clone())]
+    }
+
+    fn revert_description(&self) -> Vec<ActionDescription> {
+        vec![ActionDescription::new(
+            format!(
+                "Remove the Nix daemon's cgroup delegation `{}`",
+                self.dropin_path.display()
+            ),
+            vec![],
+        )]
+    }
+
+    fn revert_dry_run(&self) -> Vec<DryRunStep> {
+        if self.dropin_path.exists() {
+            vec![DryRunStep::Remove(self.dropin_path.clone())]
+        } else {
+            vec![]
+        }
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    async fn revert(&mut self) -> Result<(), ActionError> {
+        match remove_file(&self.dropin_path).await {
+            Ok(()) => audit::record(AuditEvent::FileRemoved {
+                path: self.dropin_path.clone(),
+            }),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => (),
+            Err(e) => {
+                return Err(Self::error(ActionErrorKind::Remove(
+                    self.dropin_path.clone(),
+                    e,
+                )))
+            },
+        }
+        // Only if the daemon's other drop-ins are gone too
+        if let Some(dir) = self.dropin_path.parent() {
+            if tokio::fs::remove_dir(dir).await.is_ok() {
+                audit::record(AuditEvent::DirectoryRemoved {
+                    path: dir.to_path_buf(),
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+fn dropin() -> String {
+    "[Service]\nDelegate=yes\n".to_string()
+}
@@ -1,11 +1,17 @@
+pub(crate) mod configure_cgroup_delegation;
 pub(crate) mod ensure_steamos_nix_directory;
+pub(crate) mod provision_apparmor;
 pub(crate) mod provision_selinux;
+pub(crate) mod restore_selinux_contexts;
 pub(crate) mod revert_clean_steamos_nix_offload;
 pub(crate) mod start_systemd_unit;
 pub(crate) mod systemctl_daemon_reload;
 
+pub use configure_cgroup_delegation::ConfigureCgroupDelegation;
 pub use ensure_steamos_nix_directory::EnsureSteamosNixDirectory;
+pub use provision_apparmor::{ApparmorMode, ProvisionApparmor};
 pub use provision_selinux::ProvisionSelinux;
+pub use restore_selinux_contexts::RestoreSelinuxContexts;
 pub use revert_clean_steamos_nix_offload::RevertCleanSteamosNixOffload;
 pub use start_systemd_unit::{StartSystemdUnit, StartSystemdUnitError};
 pub use systemctl_daemon_reload::SystemctlDaemonReload;
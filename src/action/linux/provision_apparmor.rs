@@ -0,0 +1,401 @@
+use std::path::{Path, PathBuf};
+
+use tokio::fs::{create_dir_all, remove_file};
+use tokio::process::Command;
+use tracing::{span, Span};
+
+use crate::action::{ActionError, ActionErrorKind, ActionTag, Artifact, DryRunStep};
+use crate::audit::{self, AuditEvent};
+use crate::execute_command;
+
+use crate::action::{Action, ActionDescription, StatefulAction};
+
+/// Where the profile is written
+pub const APPARMOR_PROFILE_PATH: &str = "/etc/apparmor.d/nix-daemon";
+/// The systemd drop-in which confines the daemon with the profile
+pub const APPARMOR_DROPIN_PATH: &str = "/etc/systemd/system/nix-daemon.service.d/apparmor.conf";
+const APPARMOR_PROFILE_NAME: &str = "nix-daemon";
+
+/// Which mode the Nix daemon's AppArmor profile is loaded in
+#[derive(Debug, serde::Deserialize, serde::Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum ApparmorMode {
+    /// Log what the profile would deny, without denying it
+    Complain,
+    /// Deny what the profile doesn't allow
+    Enforce,
+}
+
+impl std::fmt::Display for ApparmorMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ApparmorMode::Complain => write!(f, "complain"),
+            ApparmorMode::Enforce => write!(f, "enforce"),
+        }
+    }
+}
+
+/**
+Provision an AppArmor profile for the Nix daemon
+
+The profile has no attachment, the daemon is confined by `AppArmorProfile=` in a drop-in for its
+systemd unit. Attaching by path would confine every `nix` command as well, since `nix-daemon` is a
+link to `nix`.
+*/
+#[derive(Debug, serde::Deserialize, serde::Serialize, Clone)]
+#[serde(tag = "action_name", rename = "provision_apparmor")]
+pub struct ProvisionApparmor {
+    mode: ApparmorMode,
+    /// If `apparmor_parser` understands the `userns` rule of AppArmor 4.0, see [`parser_supports_userns`]
+    #[serde(default)]
+    userns: bool,
+    profile_path: PathBuf,
+    dropin_path: PathBuf,
+}
+
+impl ProvisionApparmor {
+    #[tracing::instrument(level = "debug", skip_all)]
+    pub async fn plan(mode: ApparmorMode) -> Result<StatefulAction<Self>, ActionError> {
+        let this = Self {
+            mode,
+            userns: parser_supports_userns().await,
+            profile_path: APPARMOR_PROFILE_PATH.into(),
+            dropin_path: APPARMOR_DROPIN_PATH.into(),
+        };
+
+        // Like `ProvisionSelinux`, the profile is (re)loaded even if it is in place.
+
+        Ok(StatefulAction::uncompleted(this))
+    }
+}
+
+#[async_trait::async_trait]
+#[typetag::serde(name = "provision_apparmor")]
+impl Action for ProvisionApparmor {
+    fn action_tag() -> ActionTag {
+        ActionTag("provision_apparmor")
+    }
+    fn tracing_synopsis(&self) -> String {
+        format!(
+            "Install an AppArmor profile for the Nix daemon, in {} mode",
+            self.mode
+        )
+    }
+
+    fn tracing_span(&self) -> Span {
+        span!(
+            tracing::Level::DEBUG,
+            "provision_apparmor",
+            mode = %self.mode,
+            profile_path = %self.profile_path.display()
+        )
+    }
+
+    fn execute_description(&self) -> Vec<ActionDescription> {
+        vec![ActionDescription::new(
+            self.tracing_synopsis(),
+            vec![
+                format!("Write the profile to `{}`", self.profile_path.display()),
+                format!(
+                    "Confine the Nix daemon with it in `{}`",
+                    self.dropin_path.display()
+                ),
+                format!(
+                    "Load it with `apparmor_parser --replace {}`",
+                    self.profile_path.display()
+                ),
+            ],
+        )]
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    async fn execute(&mut self) -> Result<(), ActionError> {
+        for (path, content) in [
+            (&self.profile_path, profile(self.mode, self.userns)),
+            (&self.dropin_path, dropin()),
+        ] {
+            if let Some(parent) = path.parent() {
+                create_dir_all(&parent)
+                    .await
+                    .map_err(|e| ActionErrorKind::CreateDirectory(parent.into(), e))
+                    .map_err(Self::error)?;
+            }
+            tokio::fs::write(path, &content)
+                .await
+                .map_err(|e| ActionErrorKind::Write(path.clone(), e))
+                .map_err(Self::error)?;
+            audit::file_written(path, content.as_bytes());
+        }
+
+        execute_command(
+            Command::new("apparmor_parser")
+                .arg("--replace")
+                .arg("--write-cache")
+                .arg(&self.profile_path),
+        )
+        .await
+        .map_err(Self::error)?;
+
+        Ok(())
+    }
+
+    fn dry_run(&self) -> Vec<DryRunStep> {
+        vec![
+            DryRunStep::WriteFile {
+                path: self.profile_path.clone(),
+                mode: None,
+                contents: profile(self.mode, self.userns),
+            },
+            DryRunStep::WriteFile {
+                path: self.dropin_path.clone(),
+                mode: None,
+                contents: dropin(),
+            },
+            DryRunStep::Command(vec![
+                "apparmor_parser".into(),
+                "--replace".into(),
+                "--write-cache".into(),
+                self.profile_path.display().to_string(),
+            ]),
+        ]
+    }
+
+    fn artifacts(&self) -> Vec<Artifact> {
+        vec![
+            Artifact::File(self.profile_path.clone()),
+            Artifact::File(self.dropin_path.clone()),
+        ]
+    }
+
+    fn revert_description(&self) -> Vec<ActionDescription> {
+        vec![ActionDescription::new(
+            "Remove the AppArmor profile for the Nix daemon".into(),
+            vec![],
+        )]
+    }
+
+    fn revert_dry_run(&self) -> Vec<DryRunStep> {
+        let mut steps = vec![];
+        if self.profile_path.exists() {
+            steps.push(DryRunStep::Command(vec![
+                "apparmor_parser".into(),
+                "--remove".into(),
+                self.profile_path.display().to_string(),
+            ]));
+            steps.push(DryRunStep::Remove(self.profile_path.clone()));
+        }
+        if self.dropin_path.exists() {
+            steps.push(DryRunStep::Remove(self.dropin_path.clone()));
+        }
+        steps
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    async fn revert(&mut self) -> Result<(), ActionError> {
+        if self.profile_path.exists() {
+            execute_command(
+                Command::new("apparmor_parser")
+                    .arg("--remove")
+                    .arg(&self.profile_path),
+            )
+            .await
+            .map_err(Self::error)?;
+        }
+        // The drop-in directory may be removed with the daemon's other drop-ins first
+        for path in [&self.profile_path, &self.dropin_path] {
+            remove_if_exists(path).await.map_err(Self::error)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// If `apparmor_parser` is AppArmor 4.0 or later, which is the first to parse `userns` rules
+///
+/// Earlier parsers (like Debian 12's, or Ubuntu 22.04's) refuse the whole profile with one.
+async fn parser_supports_userns() -> bool {
+    let output = match Command::new("apparmor_parser")
+        .arg("--version")
+        .stdin(std::process::Stdio::null())
+        .output()
+        .await
+    {
+        Ok(output) if output.status.success() => output,
+        _ => return false,
+    };
+    parser_major_version(&String::from_utf8_lossy(&output.stdout)).is_some_and(|major| major >= 4)
+}
+
+/// The major version from `apparmor_parser --version`, like `4` from `AppArmor parser version 4.0.1`
+fn parser_major_version(output: &str) -> Option<u32> {
+    output
+        .lines()
+        .next()?
+        .split_whitespace()
+        .last()?
+        .split('.')
+        .next()?
+        .parse()
+        .ok()
+}
+
+/// The profile, which allows what the daemon needs to run sandboxed builds and little else
+///
+/// Without `userns`, the profile is written for AppArmor 3.0, which doesn't mediate user namespaces.
+fn profile(mode: ApparmorMode, userns: bool) -> String {
+    let flags = match mode {
+        ApparmorMode::Complain => "attach_disconnected,mediate_deleted,complain",
+        ApparmorMode::Enforce => "attach_disconnected,mediate_deleted",
+    };
+    let (abi, userns) = if userns {
+        ("4.0", "  userns,\n")
+    } else {
+        ("3.0", "")
+    };
+    format!(
+        "# AppArmor profile for the Nix daemon, written by nix-installer.\n\
+        # It is applied by `AppArmorProfile={APPARMOR_PROFILE_NAME}` in `{APPARMOR_DROPIN_PATH}`.\n\
+        abi <abi/{abi}>,\n\
+        \n\
+        include <tunables/global>\n\
+        \n\
+        profile {APPARMOR_PROFILE_NAME} flags=({flags}) {{\n\
+        {inde}include <abstractions/base>\n\
+        {inde}include <abstractions/nameservice>\n\
+        {inde}include <abstractions/ssl_certs>\n\
+        \n\
+        {inde}# Builds run as build users, in namespaces, chroots, and mounts set up by the daemon\n\
+        {inde}capability chown,\n\
+        {inde}capability dac_override,\n\
+        {inde}capability dac_read_search,\n\
+        {inde}capability fowner,\n\
+        {inde}capability fsetid,\n\
+        {inde}capability kill,\n\
+        {inde}capability net_admin,\n\
+        {inde}capability setgid,\n\
+        {inde}capability setuid,\n\
+        {inde}capability sys_admin,\n\
+        {inde}capability sys_chroot,\n\
+        {inde}capability sys_resource,\n\
+        {userns}\
+        {inde}mount options=(rw, rprivate) -> /,\n\
+        {inde}mount options=(rw, bind) -> /nix/**,\n\
+        {inde}mount options=(rw, rbind) -> /nix/**,\n\
+        {inde}mount options=(ro, remount, bind) -> /nix/**,\n\
+        {inde}mount options=(ro, remount, rbind) -> /nix/**,\n\
+        {inde}mount fstype=proc -> /nix/**,\n\
+        {inde}mount fstype=tmpfs -> /nix/**,\n\
+        {inde}mount fstype=devpts -> /nix/**,\n\
+        {inde}mount fstype=mqueue -> /nix/**,\n\
+        {inde}umount /nix/**,\n\
+        {inde}pivot_root,\n\
+        {inde}network,\n\
+        {inde}signal,\n\
+        {inde}ptrace,\n\
+        {inde}unix,\n\
+        \n\
+        {inde}/nix/** rwlkmix,\n\
+        {inde}/build/** rwlkmix,\n\
+        {inde}/tmp/ rw,\n\
+        {inde}/tmp/** rwlkmix,\n\
+        {inde}/var/tmp/** rwlk,\n\
+        {inde}/etc/** r,\n\
+        {inde}@{{PROC}}/** r,\n\
+        {inde}@{{PROC}}/@{{pids}}/{{uid_map,gid_map,setgroups,oom_score_adj}} w,\n\
+        {inde}/sys/** r,\n\
+        {inde}/dev/** rwk,\n\
+        {inde}/run/** rwk,\n\
+        {inde}/root/.cache/nix/** rwlk,\n\
+        {inde}/root/.local/state/nix/** rwlk,\n\
+        \n\
+        {inde}# Builds without the sandbox use the host's shell and tools\n\
+        {inde}/{{usr/,}}{{s,}}bin/* rix,\n\
+        {inde}/usr/lib{{,exec}}/** rmix,\n\
+        \n\
+        {inde}deny /etc/shadow rw,\n\
+        {inde}deny /etc/gshadow rw,\n\
+        }}\n",
+        inde = "  ", // indent
+    )
+}
+
+fn dropin() -> String {
+    format!("[Service]\nAppArmorProfile={APPARMOR_PROFILE_NAME}\n")
+}
+
+async fn remove_if_exists(path: &Path) -> Result<(), ActionErrorKind> {
+    match remove_file(path).await {
+        Ok(()) => {
+            audit::record(AuditEvent::FileRemoved {
+                path: path.to_path_buf(),
+            });
+            Ok(())
+        },
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(ActionErrorKind::Remove(path.into(), e)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::process::Stdio;
+
+    use tokio::process::Command;
+
+    use super::{parser_major_version, parser_supports_userns, profile, ApparmorMode};
+
+    #[test]
+    fn only_uses_userns_with_apparmor_4() {
+        assert_eq!(
+            parser_major_version(
+                "AppArmor parser version 3.0.8\nCopyright (C) 1999-2008 Novell Inc.\n"
+            ),
+            Some(3)
+        );
+        assert_eq!(
+            parser_major_version("AppArmor parser version 4.0.1\n"),
+            Some(4)
+        );
+        assert_eq!(parser_major_version(""), None);
+
+        let apparmor_3 = profile(ApparmorMode::Enforce, false);
+        assert!(apparmor_3.contains("abi <abi/3.0>,\n"));
+        assert!(!apparmor_3.contains("userns"));
+        let apparmor_4 = profile(ApparmorMode::Complain, true);
+        assert!(apparmor_4.contains("abi <abi/4.0>,\n"));
+        assert!(apparmor_4.contains("  userns,\n"));
+    }
+
+    /// Parse the profile with the host's `apparmor_parser`, without loading it, where there is one
+    #[tokio::test]
+    async fn profile_parses() -> eyre::Result<()> {
+        if Command::new("apparmor_parser")
+            .arg("--version")
+            .stdout(Stdio::null())
+            .status()
+            .await
+            .is_err()
+        {
+            eprintln!("`apparmor_parser` isn't installed, not parsing the AppArmor profile");
+            return Ok(());
+        }
+        let userns = parser_supports_userns().await;
+        let temp_dir = tempfile::tempdir()?;
+        for mode in [ApparmorMode::Complain, ApparmorMode::Enforce] {
+            let path = temp_dir.path().join("nix-daemon");
+            tokio::fs::write(&path, profile(mode, userns)).await?;
+            let output = Command::new("apparmor_parser")
+                .args(["--skip-kernel-load", "--skip-cache", "--quiet"])
+                .arg(&path)
+                .output()
+                .await?;
+            assert!(
+                output.status.success(),
+                "The {mode} profile doesn't parse: {}",
+                String::from_utf8_lossy(&output.stderr)
+            );
+        }
+        Ok(())
+    }
+}
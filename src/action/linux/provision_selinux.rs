@@ -4,7 +4,8 @@ use tokio::fs::{create_dir_all, remove_file};
 use tokio::process::Command;
 use tracing::{span, Span};
 
-use crate::action::{ActionError, ActionErrorKind, ActionTag};
+use crate::action::{ActionError, ActionErrorKind, ActionTag, Artifact, DryRunStep};
+use crate::audit::{self, AuditEvent};
 use crate::execute_command;
 
 use crate::action::{Action, ActionDescription, StatefulAction};
@@ -13,6 +14,11 @@ pub const SELINUX_POLICY_PP_CONTENT: &[u8] = include_bytes!("selinux/nix.pp");
 pub const DETERMINATE_SELINUX_POLICY_PP_CONTENT: &[u8] =
     include_bytes!("selinux/determinate-nix.pp");
 
+/// What the policy labels which only exists once the daemon is started, see [`RestoreSelinuxContexts`](super::RestoreSelinuxContexts)
+pub const SELINUX_DAEMON_PATHS: &[&str] = &["/nix/var/nix/daemon-socket"];
+pub const DETERMINATE_SELINUX_DAEMON_PATHS: &[&str] =
+    &["/nix/var/nix/daemon-socket", "/nix/var/determinate"];
+
 /**
 Provision the selinux/nix.pp for SELinux compatibility
 */
@@ -87,6 +93,7 @@ impl Action for ProvisionSelinux {
             .await
             .map_err(|e| ActionErrorKind::Write(self.policy_path.clone(), e))
             .map_err(Self::error)?;
+        audit::file_written(&self.policy_path, &self.policy_content);
 
         execute_command(
             Command::new("semodule")
@@ -103,6 +110,26 @@ impl Action for ProvisionSelinux {
         Ok(())
     }
 
+    fn dry_run(&self) -> Vec<DryRunStep> {
+        vec![
+            DryRunStep::Other(format!(
+                "Write the {} byte SELinux policy to `{}`",
+                self.policy_content.len(),
+                self.policy_path.display()
+            )),
+            DryRunStep::Command(vec![
+                "semodule".into(),
+                "--install".into(),
+                self.policy_path.display().to_string(),
+            ]),
+            DryRunStep::Command(vec!["restorecon".into(), "-FR".into(), "/nix".into()]),
+        ]
+    }
+
+    fn artifacts(&self) -> Vec<Artifact> {
+        vec![Artifact::File(self.policy_path.clone())]
+    }
+
     fn revert_description(&self) -> Vec<ActionDescription> {
         vec![ActionDescription::new(
             "Remove the SELinux policy for Nix".into(),
@@ -110,6 +137,17 @@ impl Action for ProvisionSelinux {
         )]
     }
 
+    fn revert_dry_run(&self) -> Vec<DryRunStep> {
+        if !self.policy_path.exists() {
+            return vec![];
+        }
+        vec![
+            DryRunStep::Command(vec!["semodule".into(), "--remove".into(), "nix".into()]),
+            DryRunStep::Remove(self.policy_path.clone()),
+            DryRunStep::Command(vec!["restorecon".into(), "-FR".into(), "/nix".into()]),
+        ]
+    }
+
     #[tracing::instrument(level = "debug", skip_all)]
     async fn revert(&mut self) -> Result<(), ActionError> {
         if self.policy_path.exists() {
@@ -128,6 +166,9 @@ async fn remove_existing_policy(policy_path: &Path) -> Result<(), ActionErrorKin
     remove_file(&policy_path)
         .await
         .map_err(|e| ActionErrorKind::Remove(policy_path.into(), e))?;
+    audit::record(AuditEvent::FileRemoved {
+        path: policy_path.to_path_buf(),
+    });
 
     execute_command(Command::new("restorecon").args(["-FR", "/nix"])).await?;
 
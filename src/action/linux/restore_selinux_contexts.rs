@@ -0,0 +1,98 @@
+use std::path::PathBuf;
+
+use tokio::process::Command;
+use tracing::{span, Span};
+
+use crate::action::{
+    Action, ActionDescription, ActionError, ActionTag, DryRunStep, StatefulAction,
+};
+use crate::execute_command;
+
+/**
+Relabel paths created after the SELinux policy was installed, like the Nix daemon's socket
+
+[`ProvisionSelinux`](crate::action::linux::ProvisionSelinux) labels `/nix` as it is then, but the
+daemon's socket only exists once the daemon has been started. Created with the wrong context, the
+daemon (and clients) are denied access to it.
+*/
+#[derive(Debug, serde::Deserialize, serde::Serialize, Clone)]
+#[serde(tag = "action_name", rename = "restore_selinux_contexts")]
+pub struct RestoreSelinuxContexts {
+    paths: Vec<PathBuf>,
+}
+
+impl RestoreSelinuxContexts {
+    #[tracing::instrument(level = "debug", skip_all)]
+    pub async fn plan(paths: Vec<PathBuf>) -> Result<StatefulAction<Self>, ActionError> {
+        // Like `ProvisionSelinux`, this is never skipped, the paths may exist with the wrong context
+        Ok(StatefulAction::uncompleted(Self { paths }))
+    }
+}
+
+#[async_trait::async_trait]
+#[typetag::serde(name = "restore_selinux_contexts")]
+impl Action for RestoreSelinuxContexts {
+    fn action_tag() -> ActionTag {
+        ActionTag("restore_selinux_contexts")
+    }
+    fn tracing_synopsis(&self) -> String {
+        "Restore the SELinux contexts of the Nix daemon's files".to_string()
+    }
+
+    fn tracing_span(&self) -> Span {
+        span!(tracing::Level::DEBUG, "restore_selinux_contexts")
+    }
+
+    fn execute_description(&self) -> Vec<ActionDescription> {
+        vec![ActionDescription::new(
+            self.tracing_synopsis(),
+            self.paths
+                .iter()
+                .map(|path| format!("Run `restorecon -FR {}`", path.display()))
+                .collect(),
+        )]
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    async fn execute(&mut self) -> Result<(), ActionError> {
+        for path in &self.paths {
+            // Like the socket when the daemon wasn't started
+            if !path.exists() {
+                tracing::debug!("Not relabeling `{}`, it doesn't exist", path.display());
+                continue;
+            }
+            execute_command(Command::new("restorecon").arg("-FR").arg(path))
+                .await
+                .map_err(Self::error)?;
+        }
+
+        Ok(())
+    }
+
+    fn dry_run(&self) -> Vec<DryRunStep> {
+        self.paths
+            .iter()
+            .map(|path| {
+                DryRunStep::Command(vec![
+                    "restorecon".into(),
+                    "-FR".into(),
+                    path.display().to_string(),
+                ])
+            })
+            .collect()
+    }
+
+    fn revert_description(&self) -> Vec<ActionDescription> {
+        vec![]
+    }
+
+    fn revert_dry_run(&self) -> Vec<DryRunStep> {
+        vec![]
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    async fn revert(&mut self) -> Result<(), ActionError> {
+        // The contexts go with the files, which are removed with `/nix`
+        Ok(())
+    }
+}
@@ -1,7 +1,9 @@
 use tokio::process::Command;
 use tracing::{span, Span};
 
-use crate::action::{ActionError, ActionErrorKind, ActionState, ActionTag, StatefulAction};
+use crate::action::{
+    ActionError, ActionErrorKind, ActionState, ActionTag, DryRunStep, StatefulAction,
+};
 use crate::execute_command;
 
 use crate::action::{Action, ActionDescription};
@@ -46,6 +48,18 @@ impl StartSystemdUnit {
             state,
         })
     }
+
+    /// The command which starts (and maybe enables) the unit
+    fn command(&self) -> Command {
+        let mut command = Command::new("systemctl");
+        command.process_group(0);
+        match self.enable {
+            true => command.arg("enable").arg("--now"),
+            false => command.arg("start"),
+        };
+        command.arg(&self.unit).stdin(std::process::Stdio::null());
+        command
+    }
 }
 
 #[async_trait::async_trait]
@@ -72,39 +86,18 @@ impl Action for StartSystemdUnit {
 
     #[tracing::instrument(level = "debug", skip_all)]
     async fn execute(&mut self) -> Result<(), ActionError> {
-        let Self { unit, enable } = self;
-
-        match enable {
-            true => {
-                // TODO(@Hoverbear): Handle proxy vars
-                execute_command(
-                    Command::new("systemctl")
-                        .process_group(0)
-                        .arg("enable")
-                        .arg("--now")
-                        .arg(unit)
-                        .stdin(std::process::Stdio::null()),
-                )
-                .await
-                .map_err(Self::error)?;
-            },
-            false => {
-                // TODO(@Hoverbear): Handle proxy vars
-                execute_command(
-                    Command::new("systemctl")
-                        .process_group(0)
-                        .arg("start")
-                        .arg(unit)
-                        .stdin(std::process::Stdio::null()),
-                )
-                .await
-                .map_err(Self::error)?;
-            },
-        }
+        // TODO(@Hoverbear): Handle proxy vars
+        execute_command(&mut self.command())
+            .await
+            .map_err(Self::error)?;
 
         Ok(())
     }
 
+    fn dry_run(&self) -> Vec<DryRunStep> {
+        vec![DryRunStep::command(&self.command())]
+    }
+
     fn revert_description(&self) -> Vec<ActionDescription> {
         vec![ActionDescription::new(
             format!("Disable (and stop) the systemd unit `{}`", self.unit),
@@ -112,6 +105,23 @@ impl Action for StartSystemdUnit {
         )]
     }
 
+    fn revert_dry_run(&self) -> Vec<DryRunStep> {
+        let mut steps = vec![];
+        if self.enable {
+            steps.push(DryRunStep::Command(vec![
+                "systemctl".into(),
+                "disable".into(),
+                self.unit.clone(),
+            ]));
+        }
+        steps.push(DryRunStep::Command(vec![
+            "systemctl".into(),
+            "stop".into(),
+            self.unit.clone(),
+        ]));
+        steps
+    }
+
     #[tracing::instrument(level = "debug", skip_all)]
     async fn revert(&mut self) -> Result<(), ActionError> {
         let mut errors = vec![];
@@ -3,7 +3,7 @@ use std::path::Path;
 use tokio::process::Command;
 use tracing::{span, Span};
 
-use crate::action::{ActionError, ActionErrorKind, ActionTag};
+use crate::action::{ActionError, ActionErrorKind, ActionTag, DryRunStep};
 use crate::execute_command;
 
 use crate::action::{Action, ActionDescription, StatefulAction};
@@ -61,10 +61,21 @@ impl Action for SystemctlDaemonReload {
         Ok(())
     }
 
+    fn dry_run(&self) -> Vec<DryRunStep> {
+        vec![DryRunStep::Command(vec![
+            "systemctl".into(),
+            "daemon-reload".into(),
+        ])]
+    }
+
     fn revert_description(&self) -> Vec<ActionDescription> {
         vec![ActionDescription::new(self.tracing_synopsis(), vec![])]
     }
 
+    fn revert_dry_run(&self) -> Vec<DryRunStep> {
+        self.dry_run()
+    }
+
     #[tracing::instrument(level = "debug", skip_all)]
     async fn revert(&mut self) -> Result<(), ActionError> {
         execute_command(
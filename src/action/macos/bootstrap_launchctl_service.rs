@@ -3,7 +3,7 @@ use std::path::PathBuf;
 use tokio::process::Command;
 use tracing::{span, Span};
 
-use crate::action::{ActionError, ActionErrorKind, ActionTag, StatefulAction};
+use crate::action::{ActionError, ActionErrorKind, ActionTag, DryRunStep, StatefulAction};
 use crate::execute_command;
 
 use crate::action::{Action, ActionDescription};
@@ -110,7 +110,7 @@ impl Action for BootstrapLaunchctlService {
                 Command::new("launchctl")
                     .process_group(0)
                     .arg("enable")
-                    .arg(&format!("{DARWIN_LAUNCHD_DOMAIN}/{service}"))
+                    .arg(format!("{DARWIN_LAUNCHD_DOMAIN}/{service}"))
                     .stdin(std::process::Stdio::null()),
             )
             .await
@@ -126,6 +126,26 @@ impl Action for BootstrapLaunchctlService {
         Ok(())
     }
 
+    fn dry_run(&self) -> Vec<DryRunStep> {
+        let mut steps = vec![];
+        if self.is_disabled {
+            steps.push(DryRunStep::Command(vec![
+                "launchctl".into(),
+                "enable".into(),
+                format!("{DARWIN_LAUNCHD_DOMAIN}/{}", self.service),
+            ]));
+        }
+        if !self.is_present {
+            steps.push(DryRunStep::Command(vec![
+                "launchctl".into(),
+                "bootstrap".into(),
+                DARWIN_LAUNCHD_DOMAIN.into(),
+                self.path.display().to_string(),
+            ]));
+        }
+        steps
+    }
+
     fn revert_description(&self) -> Vec<ActionDescription> {
         vec![ActionDescription::new(
             format!(
@@ -137,6 +157,15 @@ impl Action for BootstrapLaunchctlService {
         )]
     }
 
+    fn revert_dry_run(&self) -> Vec<DryRunStep> {
+        vec![DryRunStep::Command(vec![
+            "launchctl".into(),
+            "bootout".into(),
+            DARWIN_LAUNCHD_DOMAIN.into(),
+            self.path.display().to_string(),
+        ])]
+    }
+
     #[tracing::instrument(level = "debug", skip_all)]
     async fn revert(&mut self) -> Result<(), ActionError> {
         crate::action::macos::retry_bootout(DARWIN_LAUNCHD_DOMAIN, &self.service, &self.path)
@@ -1,5 +1,8 @@
 use crate::action::base::{create_or_insert_into_file, CreateOrInsertIntoFile};
-use crate::action::{Action, ActionDescription, ActionError, ActionTag, StatefulAction};
+use crate::action::{
+    Action, ActionDescription, ActionError, ActionTag, Artifact, DryRunStep, StatefulAction,
+};
+use crate::drift::DriftedAction;
 
 use std::path::Path;
 use tracing::{span, Instrument, Span};
@@ -81,6 +84,20 @@ impl Action for ConfigureRemoteBuilding {
         )]
     }
 
+    fn artifacts(&self) -> Vec<Artifact> {
+        self.create_or_insert_into_file
+            .as_ref()
+            .map(|create_or_insert_into_file| create_or_insert_into_file.artifacts())
+            .unwrap_or_default()
+    }
+
+    async fn drift(&self) -> Vec<DriftedAction> {
+        match &self.create_or_insert_into_file {
+            Some(create_or_insert_into_file) => create_or_insert_into_file.drift().await,
+            None => vec![],
+        }
+    }
+
     #[tracing::instrument(level = "debug", skip_all)]
     async fn execute(&mut self) -> Result<(), ActionError> {
         let span = tracing::Span::current().clone();
@@ -95,6 +112,10 @@ impl Action for ConfigureRemoteBuilding {
         Ok(())
     }
 
+    fn skippable(&self) -> bool {
+        true
+    }
+
     fn revert_description(&self) -> Vec<ActionDescription> {
         vec![ActionDescription::new(
             "Remove the Nix configuration from zsh's non-login shells".to_string(),
@@ -102,6 +123,13 @@ impl Action for ConfigureRemoteBuilding {
         )]
     }
 
+    fn revert_dry_run(&self) -> Vec<DryRunStep> {
+        self.create_or_insert_into_file
+            .as_ref()
+            .map(|create_or_insert_into_file| create_or_insert_into_file.revert_dry_run())
+            .unwrap_or_default()
+    }
+
     #[tracing::instrument(level = "debug", skip_all)]
     async fn revert(&mut self) -> Result<(), ActionError> {
         if let Some(create_or_insert_into_file) = &mut self.create_or_insert_into_file {
@@ -4,7 +4,7 @@ use std::path::{Path, PathBuf};
 use tokio::process::Command;
 use tracing::{span, Span};
 
-use crate::action::{ActionError, ActionTag, StatefulAction};
+use crate::action::{ActionError, ActionTag, DryRunStep, StatefulAction};
 use crate::execute_command;
 
 use crate::action::{Action, ActionDescription};
@@ -111,6 +111,23 @@ impl Action for CreateApfsVolume {
         Ok(())
     }
 
+    fn dry_run(&self) -> Vec<DryRunStep> {
+        vec![DryRunStep::Command(vec![
+            "/usr/sbin/diskutil".into(),
+            "apfs".into(),
+            "addVolume".into(),
+            self.disk.display().to_string(),
+            if !self.case_sensitive {
+                "APFS"
+            } else {
+                "Case-sensitive APFS"
+            }
+            .into(),
+            self.name.clone(),
+            "-nomount".into(),
+        ])]
+    }
+
     fn revert_description(&self) -> Vec<ActionDescription> {
         vec![ActionDescription::new(
             format!(
@@ -122,6 +139,21 @@ impl Action for CreateApfsVolume {
         )]
     }
 
+    fn revert_dry_run(&self) -> Vec<DryRunStep> {
+        vec![
+            DryRunStep::Other(format!(
+                "Run `diskutil unmount force {}` if it is mounted",
+                self.name
+            )),
+            DryRunStep::Command(vec![
+                "/usr/sbin/diskutil".into(),
+                "apfs".into(),
+                "deleteVolume".into(),
+                self.name.clone(),
+            ]),
+        ]
+    }
+
     #[tracing::instrument(level = "debug", skip_all)]
     async fn revert(&mut self) -> Result<(), ActionError> {
         let currently_mounted = {
@@ -17,8 +17,10 @@ use crate::action::{
         CreateApfsVolume, CreateSyntheticObjects, EnableOwnership, EncryptApfsVolume,
         UnmountApfsVolume,
     },
-    Action, ActionDescription, ActionError, ActionErrorKind, ActionTag, StatefulAction,
+    Action, ActionDescription, ActionError, ActionErrorKind, ActionTag, Artifact, DryRunStep,
+    StatefulAction,
 };
+use crate::drift::DriftedAction;
 
 pub const VOLUME_MOUNT_SERVICE_NAME: &str = "systems.determinate.nix-store";
 pub const VOLUME_MOUNT_SERVICE_DEST: &str =
@@ -265,6 +267,59 @@ impl Action for CreateDeterminateNixVolume {
         Ok(())
     }
 
+    fn dry_run(&self) -> Vec<DryRunStep> {
+        let mut steps = self.create_directory.dry_run();
+        steps.extend(self.create_or_append_synthetic_conf.dry_run());
+        steps.extend(self.create_synthetic_objects.dry_run());
+        steps.extend(self.unmount_volume.dry_run());
+        steps.extend(self.create_volume.dry_run());
+        steps.extend(self.create_fstab_entry.dry_run());
+        steps.extend(self.encrypt_volume.dry_run());
+        steps.push(DryRunStep::Command(vec![
+            "/usr/local/bin/determinate-nixd".into(),
+            "init".into(),
+            "--stop-after".into(),
+            "mount".into(),
+        ]));
+        steps.extend(self.setup_volume_daemon.dry_run());
+        steps.extend(self.bootstrap_volume.dry_run());
+        steps.extend(self.kickstart_launchctl_service.dry_run());
+        steps.extend(self.enable_ownership.dry_run());
+        steps
+    }
+
+    fn artifacts(&self) -> Vec<Artifact> {
+        let mut artifacts = self.create_directory.artifacts();
+        artifacts.extend(self.create_or_append_synthetic_conf.artifacts());
+        artifacts.extend(self.create_fstab_entry.artifacts());
+        artifacts.extend(self.setup_volume_daemon.artifacts());
+        artifacts.push(Artifact::Volume {
+            name: self.name.clone(),
+            mount_point: PathBuf::from("/nix"),
+        });
+        artifacts
+    }
+
+    async fn drift(&self) -> Vec<DriftedAction> {
+        let volume = Artifact::Volume {
+            name: self.name.clone(),
+            mount_point: PathBuf::from("/nix"),
+        };
+        let mut drifted = DriftedAction::of(
+            self.create_volume.tracing_synopsis(),
+            crate::drift::artifact_drift(&[volume]).await,
+        );
+        drifted.extend(self.create_directory.drift().await);
+        drifted.extend(self.create_or_append_synthetic_conf.drift().await);
+        drifted.extend(self.create_fstab_entry.drift().await);
+        drifted.extend(self.setup_volume_daemon.drift().await);
+        drifted
+    }
+
+    fn holds_store(&self) -> bool {
+        true
+    }
+
     fn revert_description(&self) -> Vec<ActionDescription> {
         let explanation = vec![
             self.create_directory.tracing_synopsis(),
@@ -290,6 +345,21 @@ impl Action for CreateDeterminateNixVolume {
         )]
     }
 
+    fn revert_dry_run(&self) -> Vec<DryRunStep> {
+        let mut steps = self.enable_ownership.revert_dry_run();
+        steps.extend(self.kickstart_launchctl_service.revert_dry_run());
+        steps.extend(self.bootstrap_volume.revert_dry_run());
+        steps.extend(self.setup_volume_daemon.revert_dry_run());
+        steps.extend(self.encrypt_volume.revert_dry_run());
+        steps.extend(self.create_fstab_entry.revert_dry_run());
+        steps.extend(self.unmount_volume.revert_dry_run());
+        steps.extend(self.create_volume.revert_dry_run());
+        steps.extend(self.create_or_append_synthetic_conf.revert_dry_run());
+        steps.extend(self.create_synthetic_objects.revert_dry_run());
+        steps.extend(self.create_directory.revert_dry_run());
+        steps
+    }
+
     #[tracing::instrument(level = "debug", skip_all)]
     async fn revert(&mut self) -> Result<(), ActionError> {
         let mut errors = vec![];
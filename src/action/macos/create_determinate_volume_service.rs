@@ -9,10 +9,12 @@ use tokio::{
 };
 
 use crate::action::{
-    Action, ActionDescription, ActionError, ActionErrorKind, ActionTag, StatefulAction,
+    Action, ActionDescription, ActionError, ActionErrorKind, ActionTag, Artifact, DryRunStep,
+    StatefulAction,
 };
+use crate::audit::{self, AuditEvent};
 
-use super::DARWIN_LAUNCHD_DOMAIN;
+use super::{bootout_dry_run, plist_dry_run, DARWIN_LAUNCHD_DOMAIN};
 
 /** Create a plist for a `launchctl` service to mount the volume
  */
@@ -72,7 +74,6 @@ impl CreateDeterminateVolumeService {
 
             let expected_plist =
                 generate_mount_plist(&this.mount_service_label, use_ec2_instance_store)
-                    .await
                     .map_err(Self::error)?;
             if discovered_plist != expected_plist {
                 tracing::trace!(
@@ -145,7 +146,6 @@ impl Action for CreateDeterminateVolumeService {
         }
 
         let generated_plist = generate_mount_plist(mount_service_label, *use_ec2_instance_store)
-            .await
             .map_err(Self::error)?;
 
         let mut options = OpenOptions::new();
@@ -161,10 +161,33 @@ impl Action for CreateDeterminateVolumeService {
         file.write_all(&buf)
             .await
             .map_err(|e| Self::error(ActionErrorKind::Write(path.to_owned(), e)))?;
+        audit::file_written(path, &buf);
 
         Ok(())
     }
 
+    fn dry_run(&self) -> Vec<DryRunStep> {
+        let mut steps = vec![];
+        if self.needs_bootout {
+            steps.push(bootout_dry_run(DARWIN_LAUNCHD_DOMAIN, &self.path));
+        }
+        match generate_mount_plist(&self.mount_service_label, self.use_ec2_instance_store) {
+            Ok(plist) => steps.push(plist_dry_run(&self.path, &plist)),
+            Err(err) => steps.push(DryRunStep::Other(format!(
+                "{} (could not be rendered: {err})",
+                self.tracing_synopsis()
+            ))),
+        }
+        steps
+    }
+
+    fn artifacts(&self) -> Vec<Artifact> {
+        vec![Artifact::Service {
+            name: self.mount_service_label.clone(),
+            path: self.path.clone(),
+        }]
+    }
+
     fn revert_description(&self) -> Vec<ActionDescription> {
         vec![ActionDescription::new(
             format!("Delete file `{}`", self.path.display()),
@@ -172,18 +195,25 @@ impl Action for CreateDeterminateVolumeService {
         )]
     }
 
+    fn revert_dry_run(&self) -> Vec<DryRunStep> {
+        vec![DryRunStep::Remove(self.path.clone())]
+    }
+
     #[tracing::instrument(level = "debug", skip_all)]
     async fn revert(&mut self) -> Result<(), ActionError> {
         remove_file(&self.path)
             .await
             .map_err(|e| Self::error(ActionErrorKind::Remove(self.path.to_owned(), e)))?;
+        audit::record(AuditEvent::FileRemoved {
+            path: self.path.clone(),
+        });
 
         Ok(())
     }
 }
 
 /// This function must be able to operate at both plan and execute time.
-async fn generate_mount_plist(
+fn generate_mount_plist(
     mount_service_label: &str,
     use_ec2_instance_store: bool,
 ) -> Result<LaunchctlMountPlist, ActionErrorKind> {
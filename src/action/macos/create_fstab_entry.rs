@@ -1,9 +1,9 @@
-use uuid::Uuid;
-
 use super::{get_uuid_for_label, CreateApfsVolume};
 use crate::action::{
-    Action, ActionDescription, ActionError, ActionErrorKind, ActionState, ActionTag, StatefulAction,
+    Action, ActionDescription, ActionError, ActionErrorKind, ActionState, ActionTag, Artifact,
+    DryRunStep, StatefulAction,
 };
+use crate::audit;
 use std::{io::SeekFrom, path::Path};
 use tokio::{
     fs::OpenOptions,
@@ -151,57 +151,8 @@ impl Action for CreateFstabEntry {
             .await
             .map_err(|e| Self::error(ActionErrorKind::Read(fstab_path.to_owned(), e)))?;
 
-        let updated_buf = match existing_entry {
-            ExistingFstabEntry::NixInstallerEntry => {
-                // Update the entry
-                let mut current_fstab_lines = fstab_buf
-                    .lines()
-                    .map(|v| v.to_owned())
-                    .collect::<Vec<String>>();
-                let mut updated_line = false;
-                let mut saw_prelude = false;
-                let prelude = fstab_prelude_comment(apfs_volume_label);
-                for line in current_fstab_lines.iter_mut() {
-                    if line == &prelude {
-                        saw_prelude = true;
-                        continue;
-                    }
-                    if saw_prelude && line.split(&[' ', '\t']).nth(1) == Some("/nix") {
-                        *line = fstab_entry(&uuid);
-                        updated_line = true;
-                        break;
-                    }
-                }
-                if !(saw_prelude && updated_line) {
-                    return Err(Self::error(
-                        CreateFstabEntryError::ExistingNixInstallerEntryDisappeared,
-                    ));
-                }
-                current_fstab_lines.join("\n")
-            },
-            ExistingFstabEntry::Foreign => {
-                // Overwrite the existing entry with our own
-                let mut current_fstab_lines = fstab_buf
-                    .lines()
-                    .map(|v| v.to_owned())
-                    .collect::<Vec<String>>();
-                let mut updated_line = false;
-                for line in current_fstab_lines.iter_mut() {
-                    if line.split(&[' ', '\t']).nth(2) == Some("/nix") {
-                        *line = fstab_lines(&uuid, apfs_volume_label);
-                        updated_line = true;
-                        break;
-                    }
-                }
-                if !updated_line {
-                    return Err(Self::error(
-                        CreateFstabEntryError::ExistingForeignEntryDisappeared,
-                    ));
-                }
-                current_fstab_lines.join("\n")
-            },
-            ExistingFstabEntry::None => fstab_buf + "\n" + &fstab_lines(&uuid, apfs_volume_label),
-        };
+        let updated_buf = updated_fstab(fstab_buf, *existing_entry, &uuid, apfs_volume_label)
+            .map_err(Self::error)?;
 
         fstab
             .seek(SeekFrom::Start(0))
@@ -215,10 +166,37 @@ impl Action for CreateFstabEntry {
             .write_all(updated_buf.as_bytes())
             .await
             .map_err(|e| Self::error(ActionErrorKind::Write(fstab_path.to_owned(), e)))?;
+        audit::file_written(fstab_path, updated_buf.as_bytes());
 
         Ok(())
     }
 
+    fn dry_run(&self) -> Vec<DryRunStep> {
+        let fstab_buf = std::fs::read_to_string(FSTAB_PATH).unwrap_or_default();
+        // The volume's UUID is only known once it is created
+        let uuid = format!("<UUID of `{}`>", self.apfs_volume_label);
+        match updated_fstab(
+            fstab_buf,
+            self.existing_entry,
+            &uuid,
+            &self.apfs_volume_label,
+        ) {
+            Ok(contents) => vec![DryRunStep::WriteFile {
+                path: FSTAB_PATH.into(),
+                mode: None,
+                contents,
+            }],
+            Err(err) => vec![DryRunStep::Other(format!(
+                "{} (could not be rendered: {err})",
+                self.tracing_synopsis()
+            ))],
+        }
+    }
+
+    fn artifacts(&self) -> Vec<Artifact> {
+        vec![Artifact::File(FSTAB_PATH.into())]
+    }
+
     fn revert_description(&self) -> Vec<ActionDescription> {
         let Self {
             apfs_volume_label,
@@ -233,6 +211,13 @@ impl Action for CreateFstabEntry {
         )]
     }
 
+    fn revert_dry_run(&self) -> Vec<DryRunStep> {
+        vec![DryRunStep::Other(format!(
+            "Remove the entry of the `{}` volume from `{FSTAB_PATH}`",
+            self.apfs_volume_label
+        ))]
+    }
+
     #[tracing::instrument(level = "debug", skip_all)]
     async fn revert(&mut self) -> Result<(), ActionError> {
         let fstab_path = Path::new(FSTAB_PATH);
@@ -273,6 +258,7 @@ impl Action for CreateFstabEntry {
             file.flush()
                 .await
                 .map_err(|e| Self::error(ActionErrorKind::Flush(fstab_path.to_owned(), e)))?;
+            audit::file_written(fstab_path, file_contents.as_bytes());
         } else {
             return Err(Self::error(CreateFstabEntryError::CannotDetermineFstabLine));
         }
@@ -281,7 +267,64 @@ impl Action for CreateFstabEntry {
     }
 }
 
-fn fstab_lines(uuid: &Uuid, apfs_volume_label: &str) -> String {
+/// `fstab_buf` with the entry for the volume (with `uuid`) added, or updated
+fn updated_fstab(
+    fstab_buf: String,
+    existing_entry: ExistingFstabEntry,
+    uuid: &dyn std::fmt::Display,
+    apfs_volume_label: &str,
+) -> Result<String, CreateFstabEntryError> {
+    let updated_buf = match existing_entry {
+        ExistingFstabEntry::NixInstallerEntry => {
+            // Update the entry
+            let mut current_fstab_lines = fstab_buf
+                .lines()
+                .map(|v| v.to_owned())
+                .collect::<Vec<String>>();
+            let mut updated_line = false;
+            let mut saw_prelude = false;
+            let prelude = fstab_prelude_comment(apfs_volume_label);
+            for line in current_fstab_lines.iter_mut() {
+                if line == &prelude {
+                    saw_prelude = true;
+                    continue;
+                }
+                if saw_prelude && line.split(&[' ', '\t']).nth(1) == Some("/nix") {
+                    *line = fstab_entry(uuid);
+                    updated_line = true;
+                    break;
+                }
+            }
+            if !(saw_prelude && updated_line) {
+                return Err(CreateFstabEntryError::ExistingNixInstallerEntryDisappeared);
+            }
+            current_fstab_lines.join("\n")
+        },
+        ExistingFstabEntry::Foreign => {
+            // Overwrite the existing entry with our own
+            let mut current_fstab_lines = fstab_buf
+                .lines()
+                .map(|v| v.to_owned())
+                .collect::<Vec<String>>();
+            let mut updated_line = false;
+            for line in current_fstab_lines.iter_mut() {
+                if line.split(&[' ', '\t']).nth(2) == Some("/nix") {
+                    *line = fstab_lines(uuid, apfs_volume_label);
+                    updated_line = true;
+                    break;
+                }
+            }
+            if !updated_line {
+                return Err(CreateFstabEntryError::ExistingForeignEntryDisappeared);
+            }
+            current_fstab_lines.join("\n")
+        },
+        ExistingFstabEntry::None => fstab_buf + "\n" + &fstab_lines(uuid, apfs_volume_label),
+    };
+    Ok(updated_buf)
+}
+
+fn fstab_lines(uuid: &dyn std::fmt::Display, apfs_volume_label: &str) -> String {
     let prelude_comment = fstab_prelude_comment(apfs_volume_label);
     let fstab_entry = fstab_entry(uuid);
     prelude_comment + "\n" + &fstab_entry
@@ -291,7 +334,7 @@ fn fstab_prelude_comment(apfs_volume_label: &str) -> String {
     format!("# nix-installer created volume labelled `{apfs_volume_label}`")
 }
 
-fn fstab_entry(uuid: &Uuid) -> String {
+fn fstab_entry(uuid: &dyn std::fmt::Display) -> String {
     format!("UUID={uuid} /nix apfs rw,noauto,nobrowse,suid,owners")
 }
 
@@ -9,10 +9,12 @@ use tokio::{
 };
 
 use crate::action::{
-    Action, ActionDescription, ActionError, ActionErrorKind, ActionTag, StatefulAction,
+    Action, ActionDescription, ActionError, ActionErrorKind, ActionTag, Artifact, DryRunStep,
+    StatefulAction,
 };
+use crate::audit::{self, AuditEvent};
 
-use super::DARWIN_LAUNCHD_DOMAIN;
+use super::{bootout_dry_run, plist_dry_run, DARWIN_LAUNCHD_DOMAIN};
 
 /** Create a plist for a `launchctl` service to re-add Nix to the zshrc after upgrades.
  */
@@ -62,9 +64,7 @@ impl CreateNixHookService {
         if this.path.exists() {
             let discovered_plist: LaunchctlHookPlist =
                 plist::from_file(&this.path).map_err(Self::error)?;
-            let expected_plist = generate_plist(&this.service_label)
-                .await
-                .map_err(Self::error)?;
+            let expected_plist = generate_plist(&this.service_label).map_err(Self::error)?;
             if discovered_plist != expected_plist {
                 tracing::trace!(
                     ?discovered_plist,
@@ -132,7 +132,7 @@ impl Action for CreateNixHookService {
                 .map_err(Self::error)?;
         }
 
-        let generated_plist = generate_plist(service_label).await.map_err(Self::error)?;
+        let generated_plist = generate_plist(service_label).map_err(Self::error)?;
 
         let mut options = OpenOptions::new();
         options.create(true).write(true).read(true);
@@ -147,10 +147,33 @@ impl Action for CreateNixHookService {
         file.write_all(&buf)
             .await
             .map_err(|e| Self::error(ActionErrorKind::Write(path.to_owned(), e)))?;
+        audit::file_written(path, &buf);
 
         Ok(())
     }
 
+    fn dry_run(&self) -> Vec<DryRunStep> {
+        let mut steps = vec![];
+        if self.needs_bootout {
+            steps.push(bootout_dry_run(DARWIN_LAUNCHD_DOMAIN, &self.path));
+        }
+        match generate_plist(&self.service_label) {
+            Ok(plist) => steps.push(plist_dry_run(&self.path, &plist)),
+            Err(err) => steps.push(DryRunStep::Other(format!(
+                "{} (could not be rendered: {err})",
+                self.tracing_synopsis()
+            ))),
+        }
+        steps
+    }
+
+    fn artifacts(&self) -> Vec<Artifact> {
+        vec![Artifact::Service {
+            name: self.service_label.clone(),
+            path: self.path.clone(),
+        }]
+    }
+
     fn revert_description(&self) -> Vec<ActionDescription> {
         vec![ActionDescription::new(
             format!("Delete file `{}`", self.path.display()),
@@ -158,18 +181,25 @@ impl Action for CreateNixHookService {
         )]
     }
 
+    fn revert_dry_run(&self) -> Vec<DryRunStep> {
+        vec![DryRunStep::Remove(self.path.clone())]
+    }
+
     #[tracing::instrument(level = "debug", skip_all)]
     async fn revert(&mut self) -> Result<(), ActionError> {
         remove_file(&self.path)
             .await
             .map_err(|e| Self::error(ActionErrorKind::Remove(self.path.to_owned(), e)))?;
+        audit::record(AuditEvent::FileRemoved {
+            path: self.path.clone(),
+        });
 
         Ok(())
     }
 }
 
 /// This function must be able to operate at both plan and execute time.
-async fn generate_plist(service_label: &str) -> Result<LaunchctlHookPlist, ActionErrorKind> {
+fn generate_plist(service_label: &str) -> Result<LaunchctlHookPlist, ActionErrorKind> {
     let plist = LaunchctlHookPlist {
         keep_alive: KeepAliveOpts {
             successful_exit: false,
@@ -4,8 +4,10 @@ use crate::action::{
         BootstrapLaunchctlService, CreateApfsVolume, CreateSyntheticObjects, EnableOwnership,
         EncryptApfsVolume, UnmountApfsVolume,
     },
-    Action, ActionDescription, ActionError, ActionErrorKind, ActionTag, StatefulAction,
+    Action, ActionDescription, ActionError, ActionErrorKind, ActionTag, Artifact, DryRunStep,
+    StatefulAction,
 };
+use crate::drift::DriftedAction;
 use std::{
     path::{Path, PathBuf},
     time::Duration,
@@ -237,6 +239,52 @@ impl Action for CreateNixVolume {
         Ok(())
     }
 
+    fn dry_run(&self) -> Vec<DryRunStep> {
+        let mut steps = self.create_or_append_synthetic_conf.dry_run();
+        steps.extend(self.create_synthetic_objects.dry_run());
+        steps.extend(self.unmount_volume.dry_run());
+        steps.extend(self.create_volume.dry_run());
+        steps.extend(self.create_fstab_entry.dry_run());
+        if let Some(encrypt_volume) = &self.encrypt_volume {
+            steps.extend(encrypt_volume.dry_run());
+        }
+        steps.extend(self.setup_volume_daemon.dry_run());
+        steps.extend(self.bootstrap_volume.dry_run());
+        steps.extend(self.kickstart_launchctl_service.dry_run());
+        steps.extend(self.enable_ownership.dry_run());
+        steps
+    }
+
+    fn artifacts(&self) -> Vec<Artifact> {
+        let mut artifacts = self.create_or_append_synthetic_conf.artifacts();
+        artifacts.extend(self.create_fstab_entry.artifacts());
+        artifacts.extend(self.setup_volume_daemon.artifacts());
+        artifacts.push(Artifact::Volume {
+            name: self.name.clone(),
+            mount_point: PathBuf::from("/nix"),
+        });
+        artifacts
+    }
+
+    async fn drift(&self) -> Vec<DriftedAction> {
+        let volume = Artifact::Volume {
+            name: self.name.clone(),
+            mount_point: PathBuf::from("/nix"),
+        };
+        let mut drifted = DriftedAction::of(
+            self.create_volume.tracing_synopsis(),
+            crate::drift::artifact_drift(&[volume]).await,
+        );
+        drifted.extend(self.create_or_append_synthetic_conf.drift().await);
+        drifted.extend(self.create_fstab_entry.drift().await);
+        drifted.extend(self.setup_volume_daemon.drift().await);
+        drifted
+    }
+
+    fn holds_store(&self) -> bool {
+        true
+    }
+
     fn revert_description(&self) -> Vec<ActionDescription> {
         let mut explanation = vec![
             self.create_or_append_synthetic_conf.tracing_synopsis(),
@@ -262,6 +310,22 @@ impl Action for CreateNixVolume {
         )]
     }
 
+    fn revert_dry_run(&self) -> Vec<DryRunStep> {
+        let mut steps = self.enable_ownership.revert_dry_run();
+        steps.extend(self.kickstart_launchctl_service.revert_dry_run());
+        steps.extend(self.bootstrap_volume.revert_dry_run());
+        steps.extend(self.setup_volume_daemon.revert_dry_run());
+        if let Some(encrypt_volume) = &self.encrypt_volume {
+            steps.extend(encrypt_volume.revert_dry_run());
+        }
+        steps.extend(self.create_fstab_entry.revert_dry_run());
+        steps.extend(self.unmount_volume.revert_dry_run());
+        steps.extend(self.create_volume.revert_dry_run());
+        steps.extend(self.create_or_append_synthetic_conf.revert_dry_run());
+        steps.extend(self.create_synthetic_objects.revert_dry_run());
+        steps
+    }
+
     #[tracing::instrument(level = "debug", skip_all)]
     async fn revert(&mut self) -> Result<(), ActionError> {
         let mut errors = vec![];
@@ -3,7 +3,9 @@ use tracing::{span, Span};
 
 use crate::execute_command;
 
-use crate::action::{Action, ActionDescription, ActionError, ActionTag, StatefulAction};
+use crate::action::{
+    Action, ActionDescription, ActionError, ActionTag, DryRunStep, StatefulAction,
+};
 
 /// Create the synthetic objects defined in `/etc/synthetic.conf`
 #[derive(Debug, serde::Deserialize, serde::Serialize, Clone)]
@@ -60,6 +62,18 @@ impl Action for CreateSyntheticObjects {
         Ok(())
     }
 
+    fn dry_run(&self) -> Vec<DryRunStep> {
+        ["-t", "-B"]
+            .into_iter()
+            .map(|flag| {
+                DryRunStep::Command(vec![
+                    "/System/Library/Filesystems/apfs.fs/Contents/Resources/apfs.util".into(),
+                    flag.into(),
+                ])
+            })
+            .collect()
+    }
+
     fn revert_description(&self) -> Vec<ActionDescription> {
         vec![ActionDescription::new(
             "Refresh the objects defined in `/etc/synthetic.conf`".to_string(),
@@ -67,6 +81,14 @@ impl Action for CreateSyntheticObjects {
         )]
     }
 
+    fn revert_dry_run(&self) -> Vec<DryRunStep> {
+        const APFS_UTIL: &str = "/System/Library/Filesystems/apfs.fs/Contents/Resources/apfs.util";
+        vec![
+            DryRunStep::Command(vec![APFS_UTIL.into(), "-t".into()]),
+            DryRunStep::Command(vec![APFS_UTIL.into(), "-B".into()]),
+        ]
+    }
+
     #[tracing::instrument(level = "debug", skip_all)]
     async fn revert(&mut self) -> Result<(), ActionError> {
         // Yup we literally call both and ignore the error! Reasoning: https://github.com/NixOS/nix/blob/95331cb9c99151cbd790ceb6ddaf49fc1c0da4b3/scripts/create-darwin-volume.sh#L261
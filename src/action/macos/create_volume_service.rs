@@ -10,8 +10,10 @@ use tokio::{
 
 use crate::action::{
     macos::DARWIN_LAUNCHD_DOMAIN, Action, ActionDescription, ActionError, ActionErrorKind,
-    ActionTag, StatefulAction,
+    ActionTag, Artifact, DryRunStep, StatefulAction,
 };
+use crate::audit::{self, AuditEvent};
+use crate::drift::{Drift, DriftedAction};
 
 use super::get_uuid_for_label;
 
@@ -174,6 +176,41 @@ impl Action for CreateVolumeService {
         vec![ActionDescription::new(self.tracing_synopsis(), vec![])]
     }
 
+    fn artifacts(&self) -> Vec<Artifact> {
+        vec![Artifact::Service {
+            name: self.mount_service_label.clone(),
+            path: self.path.clone(),
+        }]
+    }
+
+    async fn drift(&self) -> Vec<DriftedAction> {
+        let mut drift = crate::drift::artifact_drift(&self.artifacts()).await;
+        if drift.is_empty() {
+            if let Ok(Some(uuid)) = get_uuid_for_label(&self.apfs_volume_label).await {
+                let discovered_plist = plist::from_file::<_, LaunchctlMountPlist>(&self.path);
+                let expected_plist = generate_mount_plist(
+                    &self.mount_service_label,
+                    &self.apfs_volume_label,
+                    uuid,
+                    &self.mount_point,
+                    self.encrypt,
+                )
+                .await;
+                if let (Ok(discovered_plist), Ok(expected_plist)) =
+                    (discovered_plist, expected_plist)
+                {
+                    if discovered_plist != expected_plist {
+                        drift.push(Drift::Modified {
+                            path: self.path.clone(),
+                            detail: "it no longer mounts the Nix volume as it did".into(),
+                        });
+                    }
+                }
+            }
+        }
+        DriftedAction::of(self.tracing_synopsis(), drift)
+    }
+
     #[tracing::instrument(level = "debug", skip_all)]
     async fn execute(&mut self) -> Result<(), ActionError> {
         let Self {
@@ -225,6 +262,7 @@ impl Action for CreateVolumeService {
         file.write_all(&buf)
             .await
             .map_err(|e| Self::error(ActionErrorKind::Write(path.to_owned(), e)))?;
+        audit::file_written(path, &buf);
 
         Ok(())
     }
@@ -236,11 +274,18 @@ impl Action for CreateVolumeService {
         )]
     }
 
+    fn revert_dry_run(&self) -> Vec<DryRunStep> {
+        vec![DryRunStep::Remove(self.path.clone())]
+    }
+
     #[tracing::instrument(level = "debug", skip_all)]
     async fn revert(&mut self) -> Result<(), ActionError> {
         remove_file(&self.path)
             .await
             .map_err(|e| Self::error(ActionErrorKind::Remove(self.path.to_owned(), e)))?;
+        audit::record(AuditEvent::FileRemoved {
+            path: self.path.clone(),
+        });
 
         Ok(())
     }
@@ -7,7 +7,7 @@ use tracing::{span, Span};
 use crate::action::{ActionError, ActionErrorKind, ActionTag, StatefulAction};
 use crate::execute_command;
 
-use crate::action::{Action, ActionDescription};
+use crate::action::{Action, ActionDescription, DryRunStep};
 use crate::os::darwin::DiskUtilInfoOutput;
 
 /**
@@ -89,6 +89,10 @@ impl Action for EnableOwnership {
         vec![]
     }
 
+    fn revert_dry_run(&self) -> Vec<DryRunStep> {
+        vec![]
+    }
+
     #[tracing::instrument(level = "debug", skip_all)]
     async fn revert(&mut self) -> Result<(), ActionError> {
         // noop
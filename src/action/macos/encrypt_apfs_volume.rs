@@ -1,9 +1,9 @@
 use crate::{
     action::{
         macos::NIX_VOLUME_MOUNTD_DEST, Action, ActionDescription, ActionError, ActionErrorKind,
-        ActionState, ActionTag, StatefulAction,
+        ActionState, ActionTag, DryRunStep, StatefulAction,
     },
-    execute_command,
+    execute_command, execute_command_with_input,
     os::darwin::DiskUtilApfsListOutput,
 };
 use rand::Rng;
@@ -28,6 +28,11 @@ pub struct EncryptApfsVolume {
 }
 
 impl EncryptApfsVolume {
+    /// The name of the encrypted volume
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
     #[tracing::instrument(level = "debug", skip_all)]
     pub async fn plan(
         determinate_nix: bool,
@@ -44,7 +49,7 @@ impl EncryptApfsVolume {
         command.arg("-s");
         command.arg("Nix Store");
         command.arg("-l");
-        command.arg(&format!("{} encryption password", disk.display()));
+        command.arg(format!("{} encryption password", disk.display()));
         command.arg("-D");
         command.arg("Encrypted volume password");
         command.process_group(0);
@@ -112,6 +117,148 @@ impl EncryptApfsVolume {
     }
 }
 
+impl EncryptApfsVolume {
+    /// `security add-generic-password`, adding `password` to the System keychain (or, if `update`, replacing it)
+    fn add_password_command(&self, password: &str, update: bool) -> Command {
+        let disk_str = self.disk.to_str().expect("Could not turn disk into string"); /* Should not reasonably ever fail */
+        let mut cmd = Command::new("/usr/bin/security");
+        cmd.process_group(0).args([
+            "add-generic-password",
+            "-a",
+            self.name.as_str(),
+            "-s",
+            "Nix Store",
+            "-l",
+            format!("{} encryption password", disk_str).as_str(),
+            "-D",
+            "Encrypted volume password",
+            "-j",
+            format!("Added automatically by the Nix installer for use by {NIX_VOLUME_MOUNTD_DEST}")
+                .as_str(),
+            "-w",
+            password,
+            "-T",
+            "/System/Library/CoreServices/APFSUserAgent",
+            "-T",
+            "/System/Library/CoreServices/CSUserAgent",
+            "-T",
+            "/usr/bin/security",
+        ]);
+
+        if self.determinate_nix {
+            cmd.args(["-T", "/usr/local/bin/determinate-nixd"]);
+        }
+        if update {
+            cmd.arg("-U");
+        }
+
+        cmd.arg("/Library/Keychains/System.keychain");
+        cmd
+    }
+
+    /// Change the volume's passphrase to a new random one, in the System keychain too, then check the
+    /// volume unlocks with the keychain's passphrase (as its mount service does at boot)
+    ///
+    /// If the keychain can't be updated, or the check fails, the previous passphrase is restored.
+    #[tracing::instrument(level = "debug", skip_all, fields(
+        disk = %self.disk.display(),
+    ))]
+    pub async fn rotate_passphrase(&self) -> Result<(), ActionError> {
+        let old = keychain_password(&self.name).await.map_err(Self::error)?;
+        let new = generate_password();
+
+        change_passphrase(&self.name, &old, &new)
+            .await
+            .map_err(Self::error)?;
+        let updated = async {
+            execute_command(&mut self.add_password_command(&new, true)).await?;
+            verify_unlock(&self.name).await
+        }
+        .await;
+        if let Err(err) = updated {
+            tracing::warn!(
+                "Restoring the previous passphrase of volume `{}`, after: {err}",
+                self.name
+            );
+            change_passphrase(&self.name, &new, &old)
+                .await
+                .map_err(Self::error)?;
+            execute_command(&mut self.add_password_command(&old, true))
+                .await
+                .map_err(Self::error)?;
+            return Err(Self::error(err));
+        }
+
+        Ok(())
+    }
+}
+
+/// A random 32 character passphrase
+fn generate_password() -> String {
+    const CHARSET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ\
+                        abcdefghijklmnopqrstuvwxyz\
+                            0123456789)(*&^%$#@!~";
+    const PASSWORD_LEN: usize = 32;
+    let mut rng = rand::thread_rng();
+
+    (0..PASSWORD_LEN)
+        .map(|_| {
+            let idx = rng.gen_range(0..CHARSET.len());
+            CHARSET[idx] as char
+        })
+        .collect()
+}
+
+/// The volume's passphrase in the System keychain, as its mount service reads it at boot
+async fn keychain_password(name: &str) -> Result<String, ActionErrorKind> {
+    let output = execute_command(
+        Command::new("/usr/bin/security")
+            .process_group(0)
+            .args(["find-generic-password", "-a", name])
+            .args(["-s", "Nix Store", "-w"])
+            .stdin(Stdio::null()),
+    )
+    .await?;
+    Ok(String::from_utf8(output.stdout)?
+        .trim_end_matches('\n')
+        .to_string())
+}
+
+/// Change the volume's passphrase, passing both passphrases on stdin (old first) rather than as arguments
+async fn change_passphrase(name: &str, old: &str, new: &str) -> Result<(), ActionErrorKind> {
+    execute_command_with_input(
+        Command::new("/usr/sbin/diskutil").process_group(0).args([
+            "apfs",
+            "changePassphrase",
+            name,
+            "-user",
+            "disk",
+            "-oldStdinpassphrase",
+            "-newStdinpassphrase",
+        ]),
+        format!("{old}\n{new}\n").as_bytes(),
+    )
+    .await?;
+    Ok(())
+}
+
+/// Check the keychain's passphrase unlocks the volume, the way its mount service reads it at boot
+async fn verify_unlock(name: &str) -> Result<(), ActionErrorKind> {
+    let password = keychain_password(name).await?;
+    execute_command_with_input(
+        Command::new("/usr/sbin/diskutil").process_group(0).args([
+            "apfs",
+            "unlockVolume",
+            name,
+            "-verify",
+            "-stdinpassphrase",
+        ]),
+        format!("{password}\n").as_bytes(),
+    )
+    .await?;
+    Ok(())
+}
+
 #[async_trait::async_trait]
 #[typetag::serde(name = "encrypt_volume")]
 impl Action for EncryptApfsVolume {
@@ -142,23 +289,7 @@ impl Action for EncryptApfsVolume {
         disk = %self.disk.display(),
     ))]
     async fn execute(&mut self) -> Result<(), ActionError> {
-        // Generate a random password.
-        let password: String = {
-            const CHARSET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ\
-                                abcdefghijklmnopqrstuvwxyz\
-                                    0123456789)(*&^%$#@!~";
-            const PASSWORD_LEN: usize = 32;
-            let mut rng = rand::thread_rng();
-
-            (0..PASSWORD_LEN)
-                .map(|_| {
-                    let idx = rng.gen_range(0..CHARSET.len());
-                    CHARSET[idx] as char
-                })
-                .collect()
-        };
-
-        let disk_str = &self.disk.to_str().expect("Could not turn disk into string"); /* Should not reasonably ever fail */
+        let password = generate_password();
 
         execute_command(
             Command::new("/usr/sbin/diskutil")
@@ -169,49 +300,22 @@ impl Action for EncryptApfsVolume {
         .map_err(Self::error)?;
 
         // Add the password to the user keychain so they can unlock it later.
-        let mut cmd = Command::new("/usr/bin/security");
-        cmd.process_group(0).args([
-            "add-generic-password",
-            "-a",
-            self.name.as_str(),
-            "-s",
-            "Nix Store",
-            "-l",
-            format!("{} encryption password", disk_str).as_str(),
-            "-D",
-            "Encrypted volume password",
-            "-j",
-            format!("Added automatically by the Nix installer for use by {NIX_VOLUME_MOUNTD_DEST}")
-                .as_str(),
-            "-w",
-            password.as_str(),
-            "-T",
-            "/System/Library/CoreServices/APFSUserAgent",
-            "-T",
-            "/System/Library/CoreServices/CSUserAgent",
-            "-T",
-            "/usr/bin/security",
-        ]);
-
-        if self.determinate_nix {
-            cmd.args(["-T", "/usr/local/bin/determinate-nixd"]);
-        }
-
-        cmd.arg("/Library/Keychains/System.keychain");
-
-        // Add the password to the user keychain so they can unlock it later.
-        execute_command(&mut cmd).await.map_err(Self::error)?;
+        execute_command(&mut self.add_password_command(&password, false))
+            .await
+            .map_err(Self::error)?;
 
         // Encrypt the mounted volume
-        execute_command(Command::new("/usr/sbin/diskutil").process_group(0).args([
-            "apfs",
-            "encryptVolume",
-            self.name.as_str(),
-            "-user",
-            "disk",
-            "-passphrase",
-            password.as_str(),
-        ]))
+        execute_command_with_input(
+            Command::new("/usr/sbin/diskutil").process_group(0).args([
+                "apfs",
+                "encryptVolume",
+                self.name.as_str(),
+                "-user",
+                "disk",
+                "-stdinpassphrase",
+            ]),
+            format!("{password}\n").as_bytes(),
+        )
         .await
         .map_err(Self::error)?;
 
@@ -238,6 +342,23 @@ impl Action for EncryptApfsVolume {
         )]
     }
 
+    fn revert_dry_run(&self) -> Vec<DryRunStep> {
+        vec![DryRunStep::Command(vec![
+            "/usr/bin/security".into(),
+            "delete-generic-password".into(),
+            "-a".into(),
+            self.name.clone(),
+            "-s".into(),
+            self.name.clone(),
+            "-l".into(),
+            format!("{} encryption password", self.disk.display()),
+            "-D".into(),
+            "Encrypted volume password".into(),
+            "-j".into(),
+            format!("Added automatically by the Nix installer for use by {NIX_VOLUME_MOUNTD_DEST}"),
+        ])]
+    }
+
     #[tracing::instrument(level = "debug", skip_all, fields(
         disk = %self.disk.display(),
     ))]
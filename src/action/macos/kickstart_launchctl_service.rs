@@ -1,10 +1,11 @@
 use std::process::Output;
-use std::time::Duration;
 
 use tokio::process::Command;
 use tracing::{span, Span};
 
-use crate::action::{ActionError, ActionErrorKind, ActionTag, StatefulAction};
+use crate::action::macos::{LAUNCHCTL_BACKOFF, LAUNCHCTL_RETRIES};
+use crate::action::{ActionError, ActionErrorKind, ActionTag, DryRunStep, StatefulAction};
+use crate::execute_command_with_retries;
 
 use crate::action::{Action, ActionDescription};
 
@@ -99,39 +100,30 @@ impl Action for KickstartLaunchctlService {
     async fn execute(&mut self) -> Result<(), ActionError> {
         let Self { domain, service } = self;
 
-        let mut retry_tokens: usize = 10;
-        loop {
-            let mut command = Command::new("launchctl");
-            command.process_group(0);
-            command.args(["kickstart", "-k"]);
-            command.arg(format!("{domain}/{service}"));
-            command.stdin(std::process::Stdio::null());
-            command.stderr(std::process::Stdio::null());
-            command.stdout(std::process::Stdio::null());
-            tracing::trace!(%retry_tokens, command = ?command.as_std(), "Waiting for kickstart to succeed");
-
-            let output = command
-                .output()
-                .await
-                .map_err(|e| ActionErrorKind::command(&command, e))
-                .map_err(Self::error)?;
-
-            if output.status.success() {
-                break;
-            } else if retry_tokens == 0 {
-                return Err(Self::error(ActionErrorKind::command_output(
-                    &command, output,
-                )))?;
-            } else {
-                retry_tokens = retry_tokens.saturating_sub(1);
-            }
-
-            tokio::time::sleep(Duration::from_millis(500)).await;
-        }
+        let mut command = Command::new("launchctl");
+        command.process_group(0);
+        command.args(["kickstart", "-k"]);
+        command.arg(format!("{domain}/{service}"));
+        command.stdin(std::process::Stdio::null());
+        command.stderr(std::process::Stdio::piped());
+        command.stdout(std::process::Stdio::null());
+        tracing::trace!(command = ?command.as_std(), "Waiting for kickstart to succeed");
+        execute_command_with_retries(&mut command, LAUNCHCTL_RETRIES, LAUNCHCTL_BACKOFF)
+            .await
+            .map_err(Self::error)?;
 
         Ok(())
     }
 
+    fn dry_run(&self) -> Vec<DryRunStep> {
+        vec![DryRunStep::Command(vec![
+            "launchctl".into(),
+            "kickstart".into(),
+            "-k".into(),
+            format!("{}/{}", self.domain, self.service),
+        ])]
+    }
+
     fn revert_description(&self) -> Vec<ActionDescription> {
         vec![ActionDescription::new(
             format!("Run `launchctl stop {}`", self.service),
@@ -139,6 +131,14 @@ impl Action for KickstartLaunchctlService {
         )]
     }
 
+    fn revert_dry_run(&self) -> Vec<DryRunStep> {
+        vec![DryRunStep::Command(vec![
+            "launchctl".into(),
+            "stop".into(),
+            format!("{}/{}", self.domain, self.service),
+        ])]
+    }
+
     #[tracing::instrument(level = "debug", skip_all)]
     async fn revert(&mut self) -> Result<(), ActionError> {
         // MacOs doesn't offer an "ensure-stopped" like they do with Kickstart
@@ -40,11 +40,15 @@ use tokio::process::Command;
 pub use unmount_apfs_volume::UnmountApfsVolume;
 use uuid::Uuid;
 
-use crate::execute_command;
+use crate::{execute_command, execute_command_with_retries};
 
-use super::ActionErrorKind;
+use super::{ActionErrorKind, DryRunStep};
 
 pub const DARWIN_LAUNCHD_DOMAIN: &str = "system";
+/// How many times `launchctl bootstrap`/`bootout`/`kickstart` is retried, they often fail right after a plist changes
+pub(crate) const LAUNCHCTL_RETRIES: u32 = 10;
+/// The wait before the first retry, doubling after each
+pub(crate) const LAUNCHCTL_BACKOFF: Duration = Duration::from_millis(500);
 
 async fn get_uuid_for_label(apfs_volume_label: &str) -> Result<Option<Uuid>, ActionErrorKind> {
     let mut command = Command::new("/usr/sbin/diskutil");
@@ -165,33 +169,16 @@ pub(crate) async fn retry_bootstrap(
         return Ok(());
     }
 
-    let mut retry_tokens: usize = 10;
-    loop {
-        let mut command = Command::new("launchctl");
-        command.process_group(0);
-        command.arg("bootstrap");
-        command.arg(domain);
-        command.arg(service_path);
-        command.stdin(std::process::Stdio::null());
-        command.stderr(std::process::Stdio::null());
-        command.stdout(std::process::Stdio::null());
-        tracing::trace!(%retry_tokens, command = ?command.as_std(), "Waiting for bootstrap to succeed");
-
-        let output = command
-            .output()
-            .await
-            .map_err(|e| ActionErrorKind::command(&command, e))?;
-
-        if output.status.success() {
-            break;
-        } else if retry_tokens == 0 {
-            return Err(ActionErrorKind::command_output(&command, output))?;
-        } else {
-            retry_tokens = retry_tokens.saturating_sub(1);
-        }
-
-        tokio::time::sleep(Duration::from_millis(500)).await;
-    }
+    let mut command = Command::new("launchctl");
+    command.process_group(0);
+    command.arg("bootstrap");
+    command.arg(domain);
+    command.arg(service_path);
+    command.stdin(std::process::Stdio::null());
+    command.stderr(std::process::Stdio::piped());
+    command.stdout(std::process::Stdio::null());
+    tracing::trace!(command = ?command.as_std(), "Waiting for bootstrap to succeed");
+    execute_command_with_retries(&mut command, LAUNCHCTL_RETRIES, LAUNCHCTL_BACKOFF).await?;
 
     Ok(())
 }
@@ -221,33 +208,42 @@ pub(crate) async fn retry_bootout(
         return Ok(());
     }
 
-    let mut retry_tokens: usize = 10;
-    loop {
-        let mut command = Command::new("launchctl");
-        command.process_group(0);
-        command.arg("bootout");
-        command.arg(domain);
-        command.arg(service_path);
-        command.stdin(std::process::Stdio::null());
-        command.stderr(std::process::Stdio::null());
-        command.stdout(std::process::Stdio::null());
-        tracing::trace!(%retry_tokens, command = ?command.as_std(), "Waiting for bootout to succeed");
+    let mut command = Command::new("launchctl");
+    command.process_group(0);
+    command.arg("bootout");
+    command.arg(domain);
+    command.arg(service_path);
+    command.stdin(std::process::Stdio::null());
+    command.stderr(std::process::Stdio::piped());
+    command.stdout(std::process::Stdio::null());
+    tracing::trace!(command = ?command.as_std(), "Waiting for bootout to succeed");
+    execute_command_with_retries(&mut command, LAUNCHCTL_RETRIES, LAUNCHCTL_BACKOFF).await?;
 
-        let output = command
-            .output()
-            .await
-            .map_err(|e| ActionErrorKind::command(&command, e))?;
+    Ok(())
+}
 
-        if output.status.success() {
-            break;
-        } else if retry_tokens == 0 {
-            return Err(ActionErrorKind::command_output(&command, output))?;
-        } else {
-            retry_tokens = retry_tokens.saturating_sub(1);
-        }
+/// The `launchctl bootout` run by [`retry_bootout`], for [`Action::dry_run`](crate::action::Action::dry_run)
+pub(crate) fn bootout_dry_run(domain: &str, service_path: &Path) -> DryRunStep {
+    DryRunStep::Command(vec![
+        "launchctl".into(),
+        "bootout".into(),
+        domain.into(),
+        service_path.display().to_string(),
+    ])
+}
 
-        tokio::time::sleep(Duration::from_millis(500)).await;
+/// Writing `plist` to `path` as XML, for [`Action::dry_run`](crate::action::Action::dry_run)
+pub(crate) fn plist_dry_run(path: &Path, plist: &impl serde::Serialize) -> DryRunStep {
+    let mut buf = Vec::new();
+    match plist::to_writer_xml(&mut buf, plist) {
+        Ok(()) => DryRunStep::WriteFile {
+            path: path.to_path_buf(),
+            mode: None,
+            contents: String::from_utf8_lossy(&buf).into_owned(),
+        },
+        Err(err) => DryRunStep::Other(format!(
+            "Write `{}` (could not be rendered: {err})",
+            path.display()
+        )),
     }
-
-    Ok(())
 }
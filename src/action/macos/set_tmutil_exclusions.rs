@@ -3,7 +3,7 @@ use std::path::PathBuf;
 use tracing::{span, Span};
 
 use crate::action::{
-    Action, ActionDescription, ActionError, ActionErrorKind, ActionTag, StatefulAction,
+    Action, ActionDescription, ActionError, ActionErrorKind, ActionTag, DryRunStep, StatefulAction,
 };
 
 use super::SetTmutilExclusion;
@@ -102,6 +102,17 @@ impl Action for SetTmutilExclusions {
         Ok(())
     }
 
+    fn dry_run(&self) -> Vec<DryRunStep> {
+        self.set_tmutil_exclusions
+            .iter()
+            .flat_map(|set_tmutil_exclusion| set_tmutil_exclusion.dry_run())
+            .collect()
+    }
+
+    fn skippable(&self) -> bool {
+        true
+    }
+
     fn revert_description(&self) -> Vec<ActionDescription> {
         vec![ActionDescription::new(
             "Remove time machine exclusions".to_string(),
@@ -109,6 +120,14 @@ impl Action for SetTmutilExclusions {
         )]
     }
 
+    fn revert_dry_run(&self) -> Vec<DryRunStep> {
+        self.set_tmutil_exclusions
+            .iter()
+            .rev()
+            .flat_map(|set_tmutil_exclusion| set_tmutil_exclusion.revert_dry_run())
+            .collect()
+    }
+
     #[tracing::instrument(level = "debug", skip_all)]
     async fn revert(&mut self) -> Result<(), ActionError> {
         let mut errors = vec![];
@@ -7,7 +7,7 @@ use tracing::{span, Span};
 use crate::action::{ActionError, ActionTag, StatefulAction};
 use crate::execute_command;
 
-use crate::action::{Action, ActionDescription};
+use crate::action::{Action, ActionDescription, DryRunStep};
 use crate::os::darwin::DiskUtilInfoOutput;
 
 /**
@@ -94,6 +94,13 @@ impl Action for UnmountApfsVolume {
         vec![ActionDescription::new(self.tracing_synopsis(), vec![])]
     }
 
+    fn revert_dry_run(&self) -> Vec<DryRunStep> {
+        vec![DryRunStep::Other(format!(
+            "Run `diskutil unmount force {}` if it is mounted",
+            self.name
+        ))]
+    }
+
     #[tracing::instrument(level = "debug", skip_all)]
     async fn revert(&mut self) -> Result<(), ActionError> {
         let currently_mounted = {
@@ -202,20 +202,34 @@ match plan.install(None).await {
 # }
 ```
 
+Or appended to a builtin planner's plan with [`InstallPlan::push_action`](crate::InstallPlan::push_action).
+Steps which aren't Rust can be added with [`RunPlugin`](base::RunPlugin) (`--plugin` on the CLI),
+which runs an external executable for each of planning, executing, and reverting.
+
 */
 
 pub mod base;
 pub mod common;
+mod events;
 pub mod linux;
 pub mod macos;
+mod policy;
 mod stateful;
 
+pub use events::{
+    clear_action_approver, set_action_approver, subscribe_action_events, ActionApproval,
+    ActionEvent,
+};
+pub(crate) use policy::{inherit_retrying, retrying};
+pub use policy::{parse_tagged, set_action_policies, ActionPolicies, ActionPolicy};
 pub use stateful::{ActionState, StatefulAction};
 use std::{error::Error, process::Output};
 use tokio::task::JoinError;
 use tracing::Span;
 
-use crate::{error::HasExpectedErrors, settings::UrlOrPathError, CertificateError};
+use crate::{
+    drift::DriftedAction, error::HasExpectedErrors, settings::UrlOrPathError, CertificateError,
+};
 
 /// An action which can be reverted or completed, with an action state
 ///
@@ -262,6 +276,60 @@ pub trait Action: Send + Sync + std::fmt::Debug + dyn_clone::DynClone {
     ///
     /// This is called by [`InstallPlan::uninstall`](crate::InstallPlan::uninstall) through [`StatefulAction::try_revert`] which handles tracing as well as if the action needs to revert based on its `action_state`.
     async fn revert(&mut self) -> Result<(), ActionError>;
+    /// The earlier actions of a plan which must complete before this one starts
+    ///
+    /// This is used by [`InstallPlan::install`](crate::InstallPlan::install) to run independent actions concurrently. By default an action waits for every earlier action.
+    fn depends_on(&self) -> ActionDependencies {
+        ActionDependencies::All
+    }
+    /// If the install still works when this (top-level) action is left out
+    ///
+    /// Used by the `--review` TUI to offer skipping it. By default actions can't be skipped.
+    fn skippable(&self) -> bool {
+        false
+    }
+    /// If reverting this (top-level) action removes the Nix store
+    ///
+    /// Used by `nix-installer uninstall --keep-store` to leave it in place. By default actions don't.
+    fn holds_store(&self) -> bool {
+        false
+    }
+    /// The exact commands this action would run, and files it would write, during execution
+    ///
+    /// If this action calls sub-[`Action`]s, care should be taken to use [`StatefulAction::dry_run`] on those actions, not [`dry_run`][Action::dry_run].
+    ///
+    /// This is called by [`InstallPlan::describe_dry_run`](crate::InstallPlan::describe_dry_run) through [`StatefulAction::dry_run`]. By default only the [`tracing_synopsis`][Action::tracing_synopsis] is given.
+    fn dry_run(&self) -> Vec<DryRunStep> {
+        vec![DryRunStep::Other(self.tracing_synopsis())]
+    }
+    /// The exact commands this action would run, and files it would remove or write, during revert
+    ///
+    /// If this action calls sub-[`Action`]s, care should be taken to use [`StatefulAction::revert_dry_run`] on those actions, in the order they are reverted, not [`revert_dry_run`][Action::revert_dry_run].
+    ///
+    /// This is called by [`InstallPlan::describe_revert_dry_run`](crate::InstallPlan::describe_revert_dry_run) through [`StatefulAction::revert_dry_run`]. By default only the [`revert_description`][Action::revert_description] is given.
+    fn revert_dry_run(&self) -> Vec<DryRunStep> {
+        self.revert_description()
+            .into_iter()
+            .map(|description| DryRunStep::Other(description.description))
+            .collect()
+    }
+    /// What this action placed on the system, for the install's [`Manifest`](crate::manifest::Manifest)
+    ///
+    /// If this action calls sub-[`Action`]s, care should be taken to use [`StatefulAction::artifacts`] on those actions, not [`artifacts`][Action::artifacts].
+    ///
+    /// This is called by [`InstallPlan::manifest`](crate::InstallPlan::manifest) through [`StatefulAction::artifacts`], which skips actions that haven't completed. By default nothing is listed.
+    fn artifacts(&self) -> Vec<Artifact> {
+        vec![]
+    }
+    /// How the system no longer matches what this action did, for `nix-installer verify`
+    ///
+    /// If this action calls sub-[`Action`]s, care should be taken to use [`StatefulAction::drift`] on those actions, not [`drift`][Action::drift].
+    ///
+    /// This is called by [`drift::detect`](crate::drift::detect) through [`StatefulAction::drift`], which skips actions that haven't completed. By default the [`artifacts`][Action::artifacts] are checked to still be there, actions which know what they wrote also check it is unchanged.
+    async fn drift(&self) -> Vec<DriftedAction> {
+        let drift = crate::drift::artifact_drift(&self.artifacts()).await;
+        DriftedAction::of(self.tracing_synopsis(), drift)
+    }
 
     fn stateful(self) -> StatefulAction<Self>
     where
@@ -303,7 +371,157 @@ impl ActionDescription {
     }
 }
 
+/**
+A step an [`Action`] would take, rendered exactly for review (see [`Action::dry_run`])
+*/
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DryRunStep {
+    /// Run a command, as its program and arguments
+    Command(Vec<String>),
+    /// Write a file with these contents
+    WriteFile {
+        path: std::path::PathBuf,
+        mode: Option<u32>,
+        contents: String,
+    },
+    /// Remove a file or (with what is in it) a directory
+    Remove(std::path::PathBuf),
+    /// A step which is not rendered in detail, like a download
+    Other(String),
+}
+
+impl DryRunStep {
+    pub fn command(command: &tokio::process::Command) -> Self {
+        let command = command.as_std();
+        Self::Command(
+            std::iter::once(command.get_program())
+                .chain(command.get_args())
+                .map(|arg| arg.to_string_lossy().into_owned())
+                .collect(),
+        )
+    }
+
+    /// Changing the owner of `path`, if there is a `user` or `group` to change it to
+    pub fn chown(path: &std::path::Path, user: Option<&str>, group: Option<&str>) -> Option<Self> {
+        let owner = match (user, group) {
+            (Some(user), Some(group)) => format!("{user}:{group}"),
+            (Some(user), None) => user.to_string(),
+            (None, Some(group)) => format!(":{group}"),
+            (None, None) => return None,
+        };
+        Some(Self::Other(format!(
+            "Change the owner of `{}` to `{owner}`",
+            path.display()
+        )))
+    }
+
+    /// A unified diff of the changes to an existing file, if this writes one with other contents
+    pub fn diff(&self) -> Option<String> {
+        let DryRunStep::WriteFile { path, contents, .. } = self else {
+            return None;
+        };
+        let existing = std::fs::read_to_string(path).ok()?;
+        if &existing == contents {
+            return None;
+        }
+        let path = path.display().to_string();
+        Some(
+            similar::TextDiff::from_lines(&existing, contents)
+                .unified_diff()
+                .header(&path, &path)
+                .to_string(),
+        )
+    }
+}
+
+impl std::fmt::Display for DryRunStep {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DryRunStep::Command(argv) => {
+                let argv = argv.iter().map(|arg| shell_quote(arg)).collect::<Vec<_>>();
+                write!(f, "$ {}", argv.join(" "))
+            },
+            DryRunStep::WriteFile {
+                path,
+                mode,
+                contents,
+            } => {
+                let path = path.display();
+                match mode {
+                    Some(mode) => writeln!(f, "# Write `{path}` (mode {mode:#o}):")?,
+                    None => writeln!(f, "# Write `{path}`:")?,
+                }
+                write!(f, "{contents}")?;
+                if !contents.is_empty() && !contents.ends_with('\n') {
+                    writeln!(f)?;
+                }
+                write!(f, "# End of `{path}`")
+            },
+            DryRunStep::Remove(path) => write!(f, "# Remove `{}`", path.display()),
+            DryRunStep::Other(description) => write!(f, "# {description}"),
+        }
+    }
+}
+
+/**
+Something an [`Action`] placed on the system (see [`Action::artifacts`])
+*/
+#[non_exhaustive]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Artifact {
+    /// The Nix package, unpacked from `source`, whose tarball had the SHA-256 `sha256`
+    NixPackage {
+        source: String,
+        sha256: Option<String>,
+    },
+    File(std::path::PathBuf),
+    Directory(std::path::PathBuf),
+    /// An init service (a systemd unit, or a launchd job) named `name`, defined at `path`
+    Service {
+        name: String,
+        path: std::path::PathBuf,
+    },
+    User {
+        name: String,
+        uid: u32,
+    },
+    Group {
+        name: String,
+        gid: u32,
+    },
+    /// A volume named `name`, mounted at `mount_point`
+    Volume {
+        name: String,
+        mount_point: std::path::PathBuf,
+    },
+}
+
+/// Quote an argument the way a POSIX shell would need it
+pub(crate) fn shell_quote(arg: &str) -> String {
+    let is_plain = !arg.is_empty()
+        && arg
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_./=:@%+,".contains(c));
+    if is_plain {
+        arg.to_string()
+    } else {
+        format!("'{}'", arg.replace('\'', r#"'\''"#))
+    }
+}
+
+/// Which earlier actions of a plan an [`Action`] depends on, see [`Action::depends_on`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ActionDependencies {
+    /// Every earlier action
+    All,
+    /// Every earlier action but those with these tags, so actions added before this one later are still waited for
+    AllExcept(Vec<ActionTag>),
+    /// Only the earlier actions with these tags
+    Tags(Vec<ActionTag>),
+}
+
 /// A 'tag' name an action has that corresponds to the one we serialize in [`typetag]`
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
 pub struct ActionTag(pub &'static str);
 
 impl std::fmt::Display for ActionTag {
@@ -540,6 +758,10 @@ pub enum ActionErrorKind {
         #[from]
         JoinError,
     ),
+    #[error("Timed out after {0:?}, the timeout can be changed with `--action-timeout`")]
+    TimedOut(std::time::Duration),
+    #[error("Not approved by the action approver")]
+    NotApproved,
     #[error("String from UTF-8 error")]
     FromUtf8(
         #[source]
@@ -595,7 +817,7 @@ impl ActionErrorKind {
         Self::Command {
             #[cfg(feature = "diagnostics")]
             program: command.as_std().get_program().to_string_lossy().into(),
-            command: format!("{:?}", command.as_std()),
+            command: crate::command_string(command),
             error,
         }
     }
@@ -603,7 +825,7 @@ impl ActionErrorKind {
         Self::CommandOutput {
             #[cfg(feature = "diagnostics")]
             program: command.as_std().get_program().to_string_lossy().into(),
-            command: format!("{:?}", command.as_std()),
+            command: crate::command_string(command),
             output,
         }
     }
@@ -615,7 +837,7 @@ impl HasExpectedErrors for ActionErrorKind {
             Self::PathUserMismatch(_, _, _)
             | Self::PathGroupMismatch(_, _, _)
             | Self::PathModeMismatch(_, _, _) => Some(Box::new(self)),
-            Self::SystemdMissing => Some(Box::new(self)),
+            Self::SystemdMissing | Self::NotApproved => Some(Box::new(self)),
             _ => None,
         }
     }
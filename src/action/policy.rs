@@ -0,0 +1,231 @@
+/*! Timeouts and retries applied to every [`Action`] by [`StatefulAction`](crate::action::StatefulAction)
+
+By default actions may take as long as they need and are never retried. A default policy, and
+overrides for specific [`ActionTag`]s, can be set with [`set_action_policies`]:
+
+```rust
+use std::time::Duration;
+use nix_installer::action::{set_action_policies, ActionPolicies, ActionPolicy};
+
+let mut policies = ActionPolicies::default();
+policies.overrides.insert(
+    "create_user".to_string(),
+    ActionPolicy {
+        timeout: Some(Duration::from_secs(60)),
+        retries: 2,
+    },
+);
+set_action_policies(policies);
+```
+
+Note the default policy applies to every action, including those made of other actions (like
+[`ConfigureNix`](crate::action::common::ConfigureNix)), so its timeout must allow for all of their steps.
+Retries happen at one layer only: what an action which is retried runs (its sub-actions, and the
+commands it retries itself) is only tried once per attempt, so the retries don't multiply.
+*/
+
+use std::{collections::HashMap, future::Future, sync::RwLock, time::Duration};
+
+use tracing::{Instrument, Span};
+
+use super::{Action, ActionError, ActionErrorKind, ActionTag};
+
+static ACTION_POLICIES: RwLock<Option<ActionPolicies>> = RwLock::new(None);
+
+tokio::task_local! {
+    /// Set while an action which is retried runs
+    static RETRYING: bool;
+}
+
+/// The longest wait between retries
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// How long an action may take, and how often it is retried if it fails
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ActionPolicy {
+    /// How long each attempt may take before it fails, `None` waits forever
+    pub timeout: Option<Duration>,
+    /// How many times to retry after a failure (or timeout)
+    pub retries: u32,
+}
+
+impl ActionPolicy {
+    /// How long to wait before the given retry, doubling each time
+    fn backoff(&self, retry: u32) -> Duration {
+        Duration::from_secs(1)
+            .saturating_mul(2u32.saturating_pow(retry.saturating_sub(1)))
+            .min(MAX_BACKOFF)
+    }
+}
+
+/// The [`ActionPolicy`] of every action, with overrides by [`ActionTag`]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ActionPolicies {
+    pub default: ActionPolicy,
+    /// Policies for the actions with these tags (eg `create_user`), used instead of the default
+    pub overrides: HashMap<String, ActionPolicy>,
+}
+
+impl ActionPolicies {
+    pub fn get(&self, tag: &str) -> ActionPolicy {
+        self.overrides.get(tag).copied().unwrap_or(self.default)
+    }
+}
+
+/// Set the policies used by every action executed or reverted after this
+pub fn set_action_policies(policies: ActionPolicies) {
+    let mut current = ACTION_POLICIES
+        .write()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    *current = Some(policies);
+}
+
+fn action_policy(tag: &str) -> ActionPolicy {
+    ACTION_POLICIES
+        .read()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .as_ref()
+        .map(|policies| policies.get(tag))
+        .unwrap_or_default()
+}
+
+/// If an action which is retried is running, so what it runs shouldn't retry too
+pub(crate) fn retrying() -> bool {
+    RETRYING.try_with(|retrying| *retrying).unwrap_or(false)
+}
+
+/// Run `future` (like a sub-action spawned on a [`JoinSet`](tokio::task::JoinSet)) knowing if the action which spawned it is retried
+pub(crate) fn inherit_retrying<F: Future>(future: F) -> impl Future<Output = F::Output> {
+    RETRYING.scope(retrying(), future)
+}
+
+/// Execute (or revert) `action` under the policy for `tag`, retrying it only if what runs it isn't retried already
+pub(crate) async fn run_with_policy<A>(
+    action: &mut A,
+    tag: ActionTag,
+    span: &Span,
+    revert: bool,
+) -> Result<(), ActionError>
+where
+    A: Action + ?Sized,
+{
+    let policy = action_policy(tag.0);
+    let retrying = retrying();
+    let retries = if retrying { 0 } else { policy.retries };
+    let mut retry = 0;
+    loop {
+        let attempt = RETRYING.scope(
+            retrying || retries > 0,
+            if revert {
+                action.revert()
+            } else {
+                action.execute()
+            }
+            .instrument(span.clone()),
+        );
+        let res = match policy.timeout {
+            Some(timeout) => tokio::time::timeout(timeout, attempt)
+                .await
+                .unwrap_or_else(|_| Err(ActionError::new(tag, ActionErrorKind::TimedOut(timeout)))),
+            None => attempt.await,
+        };
+        match res {
+            Err(err) if retry < retries => {
+                retry += 1;
+                let backoff = policy.backoff(retry);
+                tracing::warn!(
+                    parent: span,
+                    "{err}, retrying ({retry}/{retries}) in {backoff:?}: {synopsis}",
+                    synopsis = action.tracing_synopsis(),
+                );
+                tokio::time::sleep(backoff).await;
+            },
+            res => return res,
+        }
+    }
+}
+
+/// Parse a `[TAG=]VALUE` override, a value without a tag is the default
+pub fn parse_tagged<T>(input: &str) -> Result<(Option<String>, T), String>
+where
+    T: std::str::FromStr,
+    T::Err: std::fmt::Display,
+{
+    let (tag, value) = match input.split_once('=') {
+        Some((tag, value)) => (Some(tag.trim().to_string()), value),
+        None => (None, input),
+    };
+    let value = value
+        .trim()
+        .parse()
+        .map_err(|e| format!("`{input}` is not `[TAG=]VALUE`: {e}"))?;
+    Ok((tag, value))
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use tokio::process::Command;
+
+    use super::{parse_tagged, ActionPolicies, ActionPolicy, RETRYING};
+
+    #[test]
+    fn parses_tagged_values() {
+        assert_eq!(parse_tagged::<u32>("3"), Ok((None, 3)));
+        assert_eq!(
+            parse_tagged::<u32>("create_user=5"),
+            Ok((Some("create_user".to_string()), 5))
+        );
+        assert!(parse_tagged::<u32>("create_user=soon").is_err());
+    }
+
+    #[test]
+    fn overrides_by_tag() {
+        let mut policies = ActionPolicies {
+            default: ActionPolicy {
+                timeout: Some(Duration::from_secs(600)),
+                retries: 0,
+            },
+            ..Default::default()
+        };
+        let create_user = ActionPolicy {
+            timeout: Some(Duration::from_secs(60)),
+            retries: 2,
+        };
+        policies
+            .overrides
+            .insert("create_user".to_string(), create_user);
+        assert_eq!(policies.get("create_user"), create_user);
+        assert_eq!(policies.get("create_group"), policies.default);
+    }
+
+    #[test]
+    fn backs_off_exponentially() {
+        let policy = ActionPolicy::default();
+        assert_eq!(policy.backoff(1), Duration::from_secs(1));
+        assert_eq!(policy.backoff(3), Duration::from_secs(4));
+        assert_eq!(policy.backoff(20), Duration::from_secs(30));
+    }
+
+    #[tokio::test]
+    async fn retries_at_one_layer() -> eyre::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let attempts = temp_dir.path().join("attempts");
+        let mut command = Command::new("sh");
+        command.arg("-c").arg(format!(
+            "echo attempt >> {attempts}; exit 1",
+            attempts = attempts.display()
+        ));
+        // Within an action which is retried, the command isn't retried too
+        let res = RETRYING
+            .scope(
+                true,
+                crate::execute_command_with_retries(&mut command, 2, Duration::from_millis(1)),
+            )
+            .await;
+        assert!(res.is_err());
+        assert_eq!(std::fs::read_to_string(&attempts)?.lines().count(), 1);
+        Ok(())
+    }
+}
@@ -1,7 +1,11 @@
 use serde::{Deserialize, Serialize};
-use tracing::{Instrument, Span};
+use tracing::Span;
 
-use super::{Action, ActionDescription, ActionError, ActionTag};
+use super::{
+    events::run_with_events, Action, ActionDescription, ActionError, ActionTag, Artifact,
+    DryRunStep,
+};
+use crate::drift::DriftedAction;
 
 /// A wrapper around an [`Action`](crate::action::Action) which tracks the [`ActionState`] and
 /// handles some tracing output
@@ -43,6 +47,38 @@ impl StatefulAction<Box<dyn Action>> {
             _ => self.action.execute_description(),
         }
     }
+    /// The exact steps this action would take during execution
+    pub fn dry_run(&self) -> Vec<DryRunStep> {
+        match self.state {
+            ActionState::Completed | ActionState::Skipped => {
+                vec![]
+            },
+            _ => self.action.dry_run(),
+        }
+    }
+    /// The exact steps this action would take during revert
+    pub fn revert_dry_run(&self) -> Vec<DryRunStep> {
+        match self.state {
+            ActionState::Uncompleted | ActionState::Skipped => {
+                vec![]
+            },
+            _ => self.action.revert_dry_run(),
+        }
+    }
+    /// What this action placed on the system, if it has completed
+    pub fn artifacts(&self) -> Vec<Artifact> {
+        match self.state {
+            ActionState::Completed => self.action.artifacts(),
+            _ => vec![],
+        }
+    }
+    /// How the system no longer matches what this action did, if it has completed
+    pub async fn drift(&self) -> Vec<DriftedAction> {
+        match self.state {
+            ActionState::Completed => self.action.drift().await,
+            _ => vec![],
+        }
+    }
     /// A description of what this action would do during revert
     pub fn describe_revert(&self) -> Vec<ActionDescription> {
         match self.state {
@@ -52,6 +88,30 @@ impl StatefulAction<Box<dyn Action>> {
             _ => self.action.revert_description(),
         }
     }
+    /// If this action has yet to run, and the install works without it
+    pub fn skippable(&self) -> bool {
+        self.state == ActionState::Uncompleted && self.action.skippable()
+    }
+    /// Leave this action out of the install (and uninstall), if it is [`skippable`][StatefulAction::skippable]
+    pub fn skip(&mut self) -> bool {
+        if self.skippable() {
+            self.state = ActionState::Skipped;
+            true
+        } else {
+            false
+        }
+    }
+    /// Leave this action out of the uninstall, if reverting it would remove the Nix store
+    pub fn keep_store(&mut self) -> bool {
+        if matches!(self.state, ActionState::Completed | ActionState::Progress)
+            && self.action.holds_store()
+        {
+            self.state = ActionState::Skipped;
+            true
+        } else {
+            false
+        }
+    }
     /// Perform any execution steps
     ///
     /// You should prefer this ([`try_execute`][StatefulAction::try_execute]) over [`execute`][Action::execute] as it handles [`ActionState`] and does tracing
@@ -72,7 +132,8 @@ impl StatefulAction<Box<dyn Action>> {
             _ => {
                 self.state = ActionState::Progress;
                 tracing::debug!("Executing: {}", self.action.tracing_synopsis());
-                self.action.execute().await?;
+                let tag = ActionTag(self.action.typetag_name());
+                run_with_events(&mut *self.action, tag, &Span::current(), false).await?;
                 self.state = ActionState::Completed;
                 tracing::debug!("Completed: {}", self.action.tracing_synopsis());
                 Ok(())
@@ -99,7 +160,8 @@ impl StatefulAction<Box<dyn Action>> {
             _ => {
                 self.state = ActionState::Progress;
                 tracing::debug!("Reverting: {}", self.action.tracing_synopsis());
-                self.action.revert().await?;
+                let tag = ActionTag(self.action.typetag_name());
+                run_with_events(&mut *self.action, tag, &Span::current(), true).await?;
                 tracing::debug!("Reverted: {}", self.action.tracing_synopsis());
                 self.state = ActionState::Uncompleted;
                 Ok(())
@@ -127,6 +189,12 @@ where
         self.action.tracing_span()
     }
 
+    /// The action's [`tracing_span`](Action::tracing_span), inside an `action` span with its [`ActionTag`] as `tag`
+    fn tagged_span(&self) -> Span {
+        tracing::debug_span!("action", tag = %A::action_tag())
+            .in_scope(|| self.action.tracing_span())
+    }
+
     pub fn inner(&self) -> &A {
         &self.action
     }
@@ -147,6 +215,34 @@ where
         }
         self.action.execute_description()
     }
+    /// The exact steps this action would take during execution
+    pub fn dry_run(&self) -> Vec<DryRunStep> {
+        if self.state == ActionState::Completed {
+            return vec![];
+        }
+        self.action.dry_run()
+    }
+    /// The exact steps this action would take during revert
+    pub fn revert_dry_run(&self) -> Vec<DryRunStep> {
+        if self.state == ActionState::Uncompleted {
+            return vec![];
+        }
+        self.action.revert_dry_run()
+    }
+    /// What this action placed on the system, if it has completed
+    pub fn artifacts(&self) -> Vec<Artifact> {
+        if self.state != ActionState::Completed {
+            return vec![];
+        }
+        self.action.artifacts()
+    }
+    /// How the system no longer matches what this action did, if it has completed
+    pub async fn drift(&self) -> Vec<DriftedAction> {
+        if self.state != ActionState::Completed {
+            return vec![];
+        }
+        self.action.drift().await
+    }
     /// A description of what this action would do during revert
     pub fn describe_revert(&self) -> Vec<ActionDescription> {
         if self.state == ActionState::Uncompleted {
@@ -158,7 +254,7 @@ where
     ///
     /// You should prefer this ([`try_execute`][StatefulAction::try_execute]) over [`execute`][Action::execute] as it handles [`ActionState`] and does tracing
     pub async fn try_execute(&mut self) -> Result<(), ActionError> {
-        let span = self.action.tracing_span();
+        let span = self.tagged_span();
         match self.state {
             ActionState::Completed => {
                 tracing::trace!(
@@ -179,7 +275,10 @@ where
                     "Executing: {}",
                     self.action.tracing_synopsis()
                 );
-                self.action.execute().instrument(span.clone()).await?;
+                let res = run_with_events(&mut self.action, A::action_tag(), &span, false).await;
+                #[cfg(feature = "otel")]
+                record_outcome(&span, A::action_tag(), &res);
+                res?;
                 self.state = ActionState::Completed;
                 tracing::debug!(
                     parent: &span,
@@ -194,7 +293,7 @@ where
     ///
     /// You should prefer this ([`try_revert`][StatefulAction::try_revert]) over [`revert`][Action::revert] as it handles [`ActionState`] and does tracing
     pub async fn try_revert(&mut self) -> Result<(), ActionError> {
-        let span = self.action.tracing_span();
+        let span = self.tagged_span();
         match self.state {
             ActionState::Uncompleted => {
                 tracing::trace!(
@@ -215,7 +314,10 @@ where
                     "Reverting: {}",
                     self.action.tracing_synopsis()
                 );
-                self.action.revert().instrument(span.clone()).await?;
+                let res = run_with_events(&mut self.action, A::action_tag(), &span, true).await;
+                #[cfg(feature = "otel")]
+                record_outcome(&span, A::action_tag(), &res);
+                res?;
                 tracing::debug!(
                     parent: &span,
                     "Reverted: {}",
@@ -278,3 +380,14 @@ pub enum ActionState {
     */
     Skipped,
 }
+
+/// Attribute the span of an action to its tag, and mark it failed if it did, for `--otlp-endpoint` traces
+#[cfg(feature = "otel")]
+fn record_outcome(span: &Span, action_tag: ActionTag, res: &Result<(), ActionError>) {
+    use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+    span.set_attribute("nix_installer.action", action_tag.0);
+    if let Err(err) = res {
+        span.set_status(opentelemetry::trace::Status::error(err.to_string()));
+    }
+}
@@ -0,0 +1,146 @@
+/*! Adopting a Nix install which has no receipt, see `nix-installer adopt`
+
+Nix installed some other way (like the upstream install script, or a `nix-installer` receipt which
+was deleted) can't be uninstalled, repaired, or upgraded by `nix-installer`, since there is no
+receipt to say what was done. [`detect_settings`] reads the settings of such an install from the
+system: the build group, the build users (their prefix, count, and first UID), and the
+[`Distribution`]. An install is planned with them by [`InstallPlan::adopt`](crate::InstallPlan::adopt),
+each action finding what of it is already in place, and written as the receipt.
+
+The receipt is best-effort: what the other installer did differently (like a shell profile it set up
+elsewhere) isn't recorded, and is left behind by an uninstall.
+*/
+
+use std::path::Path;
+
+use nix::unistd::{Group, User};
+use tokio::process::Command;
+
+use crate::{
+    action::common::provision_determinate_nixd::DETERMINATE_NIXD_BINARY_PATH,
+    manifest::NIX_BIN,
+    settings::{CommonSettings, Distribution},
+};
+
+/// Build user prefixes other installers use, tried after the configured one
+const BUILD_USER_PREFIXES: &[&str] = &["_nixbld", "nixbld"];
+
+/// The settings of the Nix install already on the system, starting from `settings`
+#[tracing::instrument(level = "debug", skip_all)]
+pub async fn detect_settings(mut settings: CommonSettings) -> Result<CommonSettings, AdoptError> {
+    if !Path::new("/nix/store").exists() {
+        return Err(AdoptError::NoNix);
+    }
+
+    let group = Group::from_name(&settings.nix_build_group_name)
+        .map_err(|e| AdoptError::GettingGroup(settings.nix_build_group_name.clone(), e))?
+        .ok_or_else(|| AdoptError::NoBuildGroup(settings.nix_build_group_name.clone()))?;
+    settings.nix_build_group_id = group.gid.as_raw();
+
+    let mut prefixes = vec![settings.nix_build_user_prefix.clone()];
+    prefixes.extend(
+        BUILD_USER_PREFIXES
+            .iter()
+            .map(|prefix| prefix.to_string())
+            .filter(|prefix| *prefix != settings.nix_build_user_prefix),
+    );
+    let mut found = None;
+    for prefix in &prefixes {
+        let (count, first_uid) = build_users(prefix)?;
+        if count > 0 {
+            found = Some((prefix.clone(), count, first_uid));
+            break;
+        }
+    }
+    let Some((prefix, count, first_uid)) = found else {
+        return Err(AdoptError::NoBuildUsers(prefixes));
+    };
+    tracing::debug!(prefix, count, first_uid, "Found the build users");
+    settings.nix_build_user_prefix = prefix;
+    settings.nix_build_user_count = count;
+    settings.nix_build_user_id_base = first_uid.saturating_sub(1);
+
+    settings.determinate_nix = false;
+    settings.distribution = detect_distribution().await;
+    Ok(settings)
+}
+
+/// How many build users named `prefix` and a number there are, counting up from `1`, and the UID of the first
+fn build_users(prefix: &str) -> Result<(u32, u32), AdoptError> {
+    let mut count = 0;
+    let mut first_uid = 0;
+    loop {
+        let name = format!("{prefix}{}", count + 1);
+        match User::from_name(&name).map_err(|e| AdoptError::GettingUser(name.clone(), e))? {
+            Some(user) => {
+                if count == 0 {
+                    first_uid = user.uid.as_raw();
+                }
+                count += 1;
+            },
+            None => return Ok((count, first_uid)),
+        }
+    }
+}
+
+/// The [`Distribution`] of the Nix in the default profile
+pub async fn detect_distribution() -> Distribution {
+    if Path::new(DETERMINATE_NIXD_BINARY_PATH).exists() {
+        return Distribution::Determinate;
+    }
+    match Command::new(NIX_BIN)
+        .arg("--version")
+        .stdin(std::process::Stdio::null())
+        .output()
+        .await
+    {
+        Ok(output) => distribution_of(&String::from_utf8_lossy(&output.stdout)).unwrap_or_default(),
+        Err(err) => {
+            tracing::debug!(%err, "Could not run `nix --version`, assuming upstream Nix");
+            Distribution::Upstream
+        },
+    }
+}
+
+/// The distribution `nix --version` printed `version`, if it is one
+pub(crate) fn distribution_of(version: &str) -> Option<Distribution> {
+    [Distribution::Lix, Distribution::Upstream]
+        .into_iter()
+        .find(|distribution| version.contains(distribution.version_marker()))
+}
+
+#[non_exhaustive]
+#[derive(Debug, thiserror::Error)]
+pub enum AdoptError {
+    #[error("There is no `/nix/store`, so no Nix to adopt, install it with `nix-installer install` instead")]
+    NoNix,
+    #[error("The receipt `{0}` already exists, this Nix is already managed by `nix-installer`")]
+    ReceiptExists(std::path::PathBuf),
+    #[error("The Nix build group `{0}` doesn't exist, pass the name of the existing one as `--nix-build-group-name`")]
+    NoBuildGroup(String),
+    #[error("No Nix build users were found (named {}, followed by `1`), pass the prefix of the existing ones as `--nix-build-user-prefix`", .0.iter().map(|prefix| format!("`{prefix}`")).collect::<Vec<_>>().join(" or "))]
+    NoBuildUsers(Vec<String>),
+    #[error("Getting the group `{0}`")]
+    GettingGroup(String, #[source] nix::errno::Errno),
+    #[error("Getting the user `{0}`")]
+    GettingUser(String, #[source] nix::errno::Errno),
+}
+
+#[cfg(test)]
+mod test {
+    use super::distribution_of;
+    use crate::settings::Distribution;
+
+    #[test]
+    fn tells_distributions_apart() {
+        assert_eq!(
+            distribution_of("nix (Nix) 2.24.10\n"),
+            Some(Distribution::Upstream)
+        );
+        assert_eq!(
+            distribution_of("nix (Lix, like Nix) 2.91.1\n"),
+            Some(Distribution::Lix)
+        );
+        assert_eq!(distribution_of(""), None);
+    }
+}
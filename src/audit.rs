@@ -0,0 +1,236 @@
+/*! An append-only audit log of every change an install (or uninstall) makes to the system
+
+Nothing is recorded until a log is opened with [`set_audit_log`], after which each file written or
+removed, directory created or removed, user or group created or deleted, and command run is
+appended to it as a line of JSON:
+
+```json
+{"timestamp":"2024-06-01T12:00:00.000Z","event":"file_written","path":"/etc/nix/nix.conf","sha256":"..."}
+{"timestamp":"2024-06-01T12:00:01.000Z","event":"command_executed","command":"\"useradd\" ...","status":0}
+```
+
+The log is only ever appended to, never truncated. Each record can also be sent to the system's
+audit trail (`auditctl -m` if auditd is running, otherwise `logger`, which on macOS is the unified log).
+*/
+
+use std::{
+    fs::{File, OpenOptions},
+    io::Write,
+    os::unix::fs::OpenOptionsExt,
+    path::{Path, PathBuf},
+    process::Stdio,
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use sha2::{Digest, Sha256};
+
+static AUDIT_LOG: Mutex<Option<AuditLog>> = Mutex::new(None);
+
+/// Where records are written, see [`set_audit_log`]
+#[derive(Debug)]
+struct AuditLog {
+    file: File,
+    system_log: bool,
+}
+
+/// A change to the system, recorded in the audit log
+#[non_exhaustive]
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum AuditEvent {
+    /// A file was created or changed, and now has the contents with the SHA-256 `sha256`
+    FileWritten {
+        path: PathBuf,
+        sha256: String,
+    },
+    FileRemoved {
+        path: PathBuf,
+    },
+    SymlinkCreated {
+        path: PathBuf,
+        target: PathBuf,
+    },
+    DirectoryCreated {
+        path: PathBuf,
+    },
+    DirectoryRemoved {
+        path: PathBuf,
+    },
+    /// A file or directory (like a Nix store path) was moved into place
+    Moved {
+        from: PathBuf,
+        to: PathBuf,
+    },
+    UserCreated {
+        name: String,
+        uid: u32,
+    },
+    UserDeleted {
+        name: String,
+    },
+    GroupCreated {
+        name: String,
+        gid: u32,
+    },
+    GroupDeleted {
+        name: String,
+    },
+    UserAddedToGroup {
+        user: String,
+        group: String,
+    },
+    UserRemovedFromGroup {
+        user: String,
+        group: String,
+    },
+    /// A command was run (as root), and exited with `status` (or was killed by a signal)
+    CommandExecuted {
+        command: String,
+        status: Option<i32>,
+    },
+}
+
+/// Append a record of each change made after this to the file at `path`, and to the system's audit trail if `system_log`
+pub fn set_audit_log(path: &Path, system_log: bool) -> std::io::Result<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .mode(0o600)
+        .open(path)?;
+    let mut current = AUDIT_LOG
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    *current = Some(AuditLog { file, system_log });
+    Ok(())
+}
+
+fn is_enabled() -> bool {
+    AUDIT_LOG
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .is_some()
+}
+
+pub(crate) fn record(event: AuditEvent) {
+    let mut current = AUDIT_LOG
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    let Some(log) = current.as_mut() else {
+        return;
+    };
+    let mut record = match serde_json::to_value(&event) {
+        Ok(serde_json::Value::Object(record)) => record,
+        Ok(_) | Err(_) => {
+            tracing::warn!(?event, "Could not serialize an audit record");
+            return;
+        },
+    };
+    record.insert("timestamp".into(), timestamp(SystemTime::now()).into());
+    let line = serde_json::Value::Object(record).to_string();
+
+    // A change which can't be recorded is still made, the install isn't failed halfway over it
+    if let Err(err) = writeln!(log.file, "{line}").and_then(|()| log.file.flush()) {
+        tracing::warn!(%err, "Could not write to the audit log");
+    }
+    if log.system_log {
+        system_log(&line);
+    }
+}
+
+/// Record that `path` was written with `contents`
+pub(crate) fn file_written(path: &Path, contents: &[u8]) {
+    if !is_enabled() {
+        return;
+    }
+    record(AuditEvent::FileWritten {
+        path: path.to_path_buf(),
+        sha256: format!("{:x}", Sha256::digest(contents)),
+    });
+}
+
+/// Record that `path` was written, hashing what is now on disk
+pub(crate) async fn file_written_on_disk(path: &Path) {
+    if !is_enabled() {
+        return;
+    }
+    match tokio::fs::read(path).await {
+        Ok(contents) => file_written(path, &contents),
+        Err(err) => {
+            tracing::warn!(%err, path = %path.display(), "Could not hash a written file for the audit log");
+            record(AuditEvent::FileWritten {
+                path: path.to_path_buf(),
+                sha256: String::new(),
+            });
+        },
+    }
+}
+
+fn system_log(line: &str) {
+    let auditd = Path::new("/var/run/auditd.pid").exists() && which::which("auditctl").is_ok();
+    let mut command = if auditd {
+        let mut command = std::process::Command::new("auditctl");
+        command.arg("-m").arg(format!("nix-installer {line}"));
+        command
+    } else {
+        let mut command = std::process::Command::new("logger");
+        command.args(["-p", "auth.notice", "-t", "nix-installer-audit", line]);
+        command
+    };
+    let res = command
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status();
+    match res {
+        Ok(status) if status.success() => (),
+        Ok(status) => tracing::warn!(%status, "Could not send an audit record to the system log"),
+        Err(err) => tracing::warn!(%err, "Could not send an audit record to the system log"),
+    }
+}
+
+/// `time` as RFC 3339 in UTC, like `2024-06-01T12:00:00.000Z`
+fn timestamp(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since_epoch.as_secs();
+    let (days, secs_of_day) = (secs / 86400, secs % 86400);
+
+    // Days since the epoch to a civil date, from http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = days as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}.{:03}Z",
+        secs_of_day / 3600,
+        secs_of_day % 3600 / 60,
+        secs_of_day % 60,
+        since_epoch.subsec_millis(),
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use super::timestamp;
+
+    #[test]
+    fn timestamps_are_rfc3339() {
+        assert_eq!(timestamp(UNIX_EPOCH), "1970-01-01T00:00:00.000Z");
+        assert_eq!(
+            timestamp(UNIX_EPOCH + Duration::from_millis(1_709_210_096_789)),
+            "2024-02-29T12:34:56.789Z"
+        );
+    }
+}
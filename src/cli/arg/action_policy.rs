@@ -0,0 +1,87 @@
+use std::time::Duration;
+
+use crate::action::{parse_tagged, set_action_policies, ActionPolicies};
+
+#[derive(clap::Args, Debug, Default)]
+pub struct ActionPolicyArgs {
+    /// Seconds an action may take before it fails, as `SECONDS` for every action or `TAG=SECONDS` for actions with that tag (eg `create_user=60`)
+    #[clap(long = "action-timeout", env = "NIX_INSTALLER_ACTION_TIMEOUTS", value_delimiter = ',', value_parser = parse_tagged::<u64>, global = true)]
+    pub action_timeouts: Vec<(Option<String>, u64)>,
+    /// Times a failed action is retried, as `COUNT` for every action or `TAG=COUNT` for actions with that tag (eg `create_user=2`)
+    #[clap(long = "action-retries", env = "NIX_INSTALLER_ACTION_RETRIES", value_delimiter = ',', value_parser = parse_tagged::<u32>, global = true)]
+    pub action_retries: Vec<(Option<String>, u32)>,
+}
+
+impl ActionPolicyArgs {
+    pub fn policies(&self) -> ActionPolicies {
+        let mut policies = ActionPolicies::default();
+        // The default is set first, so overrides start from it
+        for (_, seconds) in self.action_timeouts.iter().filter(|(tag, _)| tag.is_none()) {
+            policies.default.timeout = Some(Duration::from_secs(*seconds));
+        }
+        for (_, retries) in self.action_retries.iter().filter(|(tag, _)| tag.is_none()) {
+            policies.default.retries = *retries;
+        }
+        for (tag, seconds) in self.action_timeouts.iter().filter_map(tagged) {
+            policies
+                .overrides
+                .entry(tag)
+                .or_insert(policies.default)
+                .timeout = Some(Duration::from_secs(seconds));
+        }
+        for (tag, retries) in self.action_retries.iter().filter_map(tagged) {
+            policies
+                .overrides
+                .entry(tag)
+                .or_insert(policies.default)
+                .retries = retries;
+        }
+        policies
+    }
+
+    pub fn setup(&self) {
+        set_action_policies(self.policies());
+    }
+}
+
+fn tagged<T: Copy>((tag, value): &(Option<String>, T)) -> Option<(String, T)> {
+    Some((tag.clone()?, *value))
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::ActionPolicyArgs;
+    use crate::action::ActionPolicy;
+
+    #[test]
+    fn overrides_start_from_default() {
+        let args = ActionPolicyArgs {
+            action_timeouts: vec![(Some("create_user".into()), 60), (None, 600)],
+            action_retries: vec![(None, 1), (Some("create_group".into()), 3)],
+        };
+        let policies = args.policies();
+        assert_eq!(
+            policies.default,
+            ActionPolicy {
+                timeout: Some(Duration::from_secs(600)),
+                retries: 1
+            }
+        );
+        assert_eq!(
+            policies.get("create_user"),
+            ActionPolicy {
+                timeout: Some(Duration::from_secs(60)),
+                retries: 1
+            }
+        );
+        assert_eq!(
+            policies.get("create_group"),
+            ActionPolicy {
+                timeout: Some(Duration::from_secs(600)),
+                retries: 3
+            }
+        );
+    }
+}
@@ -0,0 +1,32 @@
+use std::path::PathBuf;
+
+use eyre::WrapErr;
+
+use crate::audit::set_audit_log;
+
+#[derive(clap::Args, Debug, Default)]
+pub struct AuditArgs {
+    /// Append a JSON line recording each file, directory, user, and group changed, and command run, to this file
+    #[clap(long, env = "NIX_INSTALLER_AUDIT_LOG", global = true)]
+    pub audit_log: Option<PathBuf>,
+    /// Also send each `--audit-log` record to the system's audit trail (auditd if running, otherwise syslog or the macOS unified log)
+    #[clap(
+        long,
+        env = "NIX_INSTALLER_AUDIT_SYSTEM_LOG",
+        action(clap::ArgAction::SetTrue),
+        default_value = "false",
+        requires = "audit_log",
+        global = true
+    )]
+    pub audit_system_log: bool,
+}
+
+impl AuditArgs {
+    pub fn setup(&self) -> eyre::Result<()> {
+        if let Some(audit_log) = &self.audit_log {
+            set_audit_log(audit_log, self.audit_system_log)
+                .wrap_err_with(|| format!("Opening audit log `{}`", audit_log.display()))?;
+        }
+        Ok(())
+    }
+}
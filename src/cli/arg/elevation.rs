@@ -0,0 +1,39 @@
+use crate::cli::set_elevation;
+
+#[derive(Clone, Copy, Default, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum Elevation {
+    /// Run the whole of `nix-installer` as `root` with `sudo`
+    #[default]
+    Sudo,
+    /// Ask polkit (with `pkexec`) to run only the privileged phases as `root`, planning and prompting as the user
+    Pkexec,
+}
+
+impl std::fmt::Display for Elevation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let elevation = match self {
+            Elevation::Sudo => "sudo",
+            Elevation::Pkexec => "pkexec",
+        };
+        write!(f, "{}", elevation)
+    }
+}
+
+#[derive(clap::Args, Debug, Default)]
+pub struct ElevationArgs {
+    /// How to become `root` when not run as it (options are `sudo` and, on Linux, `pkexec`, which runs only the privileged phase of `install` as `root`)
+    #[clap(long, env = "NIX_INSTALLER_ELEVATE_WITH", default_value_t = Default::default(), global = true)]
+    pub elevate_with: Elevation,
+}
+
+impl ElevationArgs {
+    pub fn setup(&self) -> eyre::Result<()> {
+        if self.elevate_with == Elevation::Pkexec && cfg!(target_os = "macos") {
+            return Err(eyre::eyre!(
+                "`--elevate-with pkexec` is only available on Linux, as macOS doesn't have polkit"
+            ));
+        }
+        set_elevation(self.elevate_with);
+        Ok(())
+    }
+}
@@ -1,11 +1,16 @@
 use eyre::WrapErr;
 use std::error::Error;
 use std::io::IsTerminal;
+use std::path::PathBuf;
+use std::sync::Mutex;
 use tracing_error::ErrorLayer;
 use tracing_subscriber::{
-    filter::Directive, layer::SubscriberExt, util::SubscriberInitExt, EnvFilter,
+    filter::Directive, fmt::writer::BoxMakeWriter, layer::SubscriberExt, util::SubscriberInitExt,
+    EnvFilter, Layer,
 };
 
+use crate::cli::log_file::{RecentLogs, RotatingFile};
+
 #[derive(Clone, Default, Debug, clap::ValueEnum)]
 pub enum Logger {
     #[default]
@@ -32,7 +37,7 @@ pub struct Instrumentation {
     /// Enable debug logs, -vv for trace
     #[clap(short = 'v', env = "NIX_INSTALLER_VERBOSITY", long, action = clap::ArgAction::Count, global = true)]
     pub verbose: u8,
-    /// Which logger to use (options are `compact`, `full`, `pretty`, and `json`)
+    /// Which logger to use (options are `compact`, `full`, `pretty`, and `json`, which prints a JSON object per line)
     #[clap(long, env = "NIX_INSTALLER_LOGGER", default_value_t = Default::default(), global = true)]
     pub logger: Logger,
     /// Tracing directives delimited by comma
@@ -40,6 +45,29 @@ pub struct Instrumentation {
     /// See https://docs.rs/tracing-subscriber/latest/tracing_subscriber/filter/struct.EnvFilter.html#directives
     #[clap(long = "log-directive", global = true, env = "NIX_INSTALLER_LOG_DIRECTIVES", value_delimiter = ',', num_args = 0..)]
     pub log_directives: Vec<Directive>,
+    /// Also write debug logs (trace with -vv) to this file, whatever the console shows (otherwise the most recent are kept for crash reports)
+    #[clap(long, env = "NIX_INSTALLER_LOG_FILE", global = true)]
+    pub log_file: Option<PathBuf>,
+    /// How many MiB the `--log-file` may grow to before it is moved aside to `$LOG_FILE.1`
+    #[clap(
+        long,
+        env = "NIX_INSTALLER_LOG_FILE_MAX_SIZE",
+        default_value_t = 10,
+        global = true
+    )]
+    pub log_file_max_size: u64,
+    /// How many moved aside `--log-file`s to keep
+    #[clap(
+        long,
+        env = "NIX_INSTALLER_LOG_FILE_KEEP",
+        default_value_t = 3,
+        global = true
+    )]
+    pub log_file_keep: usize,
+    /// Export traces of the run, with a span per action, to this OTLP/HTTP collector (like `http://localhost:4318`)
+    #[cfg(feature = "otel")]
+    #[clap(long, env = "NIX_INSTALLER_OTLP_ENDPOINT", global = true)]
+    pub otlp_endpoint: Option<url::Url>,
 }
 
 impl Instrumentation {
@@ -53,27 +81,33 @@ impl Instrumentation {
     }
 
     pub fn setup(&self) -> eyre::Result<()> {
-        let filter_layer = self.filter_layer()?;
+        // The log file (or the logs kept for crash reports) and traces see more than the console, so each has its own filter
+        let log_file_layer = self.log_file_layer()?;
+        #[cfg(feature = "otel")]
+        let otel_layer = self.otel_layer()?;
+        #[cfg(not(feature = "otel"))]
+        let otel_layer: Option<tracing_subscriber::layer::Identity> = None;
 
         let registry = tracing_subscriber::registry()
-            .with(filter_layer)
-            .with(ErrorLayer::default());
+            .with(ErrorLayer::default().with_filter(self.filter_layer()?))
+            .with(log_file_layer)
+            .with(otel_layer);
 
         match self.logger {
             Logger::Compact => {
-                let fmt_layer = self.fmt_layer_compact();
+                let fmt_layer = self.fmt_layer_compact().with_filter(self.filter_layer()?);
                 registry.with(fmt_layer).try_init()?
             },
             Logger::Full => {
-                let fmt_layer = self.fmt_layer_full();
+                let fmt_layer = self.fmt_layer_full().with_filter(self.filter_layer()?);
                 registry.with(fmt_layer).try_init()?
             },
             Logger::Pretty => {
-                let fmt_layer = self.fmt_layer_pretty();
+                let fmt_layer = self.fmt_layer_pretty().with_filter(self.filter_layer()?);
                 registry.with(fmt_layer).try_init()?
             },
             Logger::Json => {
-                let fmt_layer = self.fmt_layer_json();
+                let fmt_layer = self.fmt_layer_json().with_filter(self.filter_layer()?);
                 registry.with(fmt_layer).try_init()?
             },
         }
@@ -81,6 +115,50 @@ impl Instrumentation {
         Ok(())
     }
 
+    pub fn log_file_layer<S>(&self) -> eyre::Result<impl Layer<S>>
+    where
+        S: tracing::Subscriber + for<'span> tracing_subscriber::registry::LookupSpan<'span>,
+    {
+        let writer = match &self.log_file {
+            Some(log_file) => {
+                let file = RotatingFile::open(
+                    log_file,
+                    self.log_file_max_size.saturating_mul(1024 * 1024),
+                    self.log_file_keep,
+                )
+                .wrap_err_with(|| format!("Opening log file `{}`", log_file.display()))?;
+                BoxMakeWriter::new(Mutex::new(file))
+            },
+            None => BoxMakeWriter::new(|| RecentLogs),
+        };
+        let level = if self.verbose > 1 { "trace" } else { "debug" };
+        let filter = EnvFilter::try_new(format!(
+            "{}={level}",
+            env!("CARGO_PKG_NAME").replace('-', "_")
+        ))?;
+        Ok(tracing_subscriber::fmt::Layer::new()
+            .with_ansi(false)
+            .with_writer(writer)
+            .with_filter(filter))
+    }
+
+    #[cfg(feature = "otel")]
+    pub fn otel_layer<S>(&self) -> eyre::Result<Option<impl Layer<S>>>
+    where
+        S: tracing::Subscriber + for<'span> tracing_subscriber::registry::LookupSpan<'span>,
+    {
+        let Some(endpoint) = &self.otlp_endpoint else {
+            return Ok(None);
+        };
+        // Actions trace their spans at debug
+        let level = if self.verbose > 1 { "trace" } else { "debug" };
+        let filter = EnvFilter::try_new(format!(
+            "{}={level}",
+            env!("CARGO_PKG_NAME").replace('-', "_")
+        ))?;
+        Ok(Some(crate::cli::otel::layer(endpoint)?.with_filter(filter)))
+    }
+
     pub fn fmt_layer_full<S>(&self) -> impl tracing_subscriber::layer::Layer<S>
     where
         S: tracing::Subscriber + for<'span> tracing_subscriber::registry::LookupSpan<'span>,
@@ -104,10 +182,14 @@ impl Instrumentation {
     where
         S: tracing::Subscriber + for<'span> tracing_subscriber::registry::LookupSpan<'span>,
     {
+        // One object per event, with its fields at the top and the spans (like `action`, with its `tag`) it is in
         tracing_subscriber::fmt::Layer::new()
-            .with_ansi(std::io::stderr().is_terminal())
+            .with_ansi(false)
             .with_writer(std::io::stderr)
             .json()
+            .flatten_event(true)
+            .with_current_span(true)
+            .with_span_list(true)
     }
 
     pub fn fmt_layer_compact<S>(&self) -> impl tracing_subscriber::layer::Layer<S>
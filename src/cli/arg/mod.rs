@@ -1,2 +1,16 @@
+mod action_policy;
+mod audit;
+mod elevation;
 mod instrumentation;
+mod output;
+mod privilege_separation;
+mod receipt_signing;
+mod self_test;
+pub(crate) use action_policy::ActionPolicyArgs;
+pub(crate) use audit::AuditArgs;
+pub(crate) use elevation::{Elevation, ElevationArgs};
 pub(crate) use instrumentation::Instrumentation;
+pub(crate) use output::{Output, OutputFormat};
+pub(crate) use privilege_separation::PrivilegeSeparationArgs;
+pub(crate) use receipt_signing::ReceiptSigningArgs;
+pub(crate) use self_test::SelfTestArgs;
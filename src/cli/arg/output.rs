@@ -0,0 +1,33 @@
+use crate::cli::output::set_format;
+
+#[derive(Clone, Copy, Default, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum OutputFormat {
+    /// Text and prompts for people
+    #[default]
+    Human,
+    /// A JSON object per line on stdout, ending with a `result` document
+    Json,
+}
+
+impl std::fmt::Display for OutputFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let format = match self {
+            OutputFormat::Human => "human",
+            OutputFormat::Json => "json",
+        };
+        write!(f, "{}", format)
+    }
+}
+
+#[derive(clap::Args, Debug, Default)]
+pub struct Output {
+    /// How to print what happened (options are `human` and `json`), `json` needs `--no-confirm` for commands which ask
+    #[clap(long, env = "NIX_INSTALLER_FORMAT", default_value_t = Default::default(), global = true)]
+    pub format: OutputFormat,
+}
+
+impl Output {
+    pub fn setup(&self) {
+        set_format(self.format);
+    }
+}
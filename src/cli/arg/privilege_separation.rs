@@ -0,0 +1,28 @@
+use eyre::WrapErr;
+
+use crate::unprivileged::set_unprivileged_helper;
+
+#[derive(clap::Args, Debug, Default)]
+pub struct PrivilegeSeparationArgs {
+    /// Download and unpack the Nix package as root, instead of as the `nobody` user
+    #[clap(
+        long,
+        env = "NIX_INSTALLER_NO_PRIVILEGE_SEPARATION",
+        action(clap::ArgAction::SetTrue),
+        default_value = "false",
+        global = true
+    )]
+    pub no_privilege_separation: bool,
+}
+
+impl PrivilegeSeparationArgs {
+    pub fn setup(&self) -> eyre::Result<()> {
+        let helper = if self.no_privilege_separation {
+            None
+        } else {
+            Some(std::env::current_exe().wrap_err("Finding the path of `nix-installer`")?)
+        };
+        set_unprivileged_helper(helper);
+        Ok(())
+    }
+}
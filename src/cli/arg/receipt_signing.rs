@@ -0,0 +1,44 @@
+use std::path::PathBuf;
+
+use eyre::WrapErr;
+
+use crate::receipt_signature::{read_key, set_receipt_signing, SigningKey};
+
+#[derive(clap::Args, Debug, Default)]
+pub struct ReceiptSigningArgs {
+    /// Sign the receipt with a key generated for this machine (kept in `/var/lib/nix-installer/receipt.key`), so uninstalls refuse a tampered receipt
+    #[clap(
+        long,
+        env = "NIX_INSTALLER_SIGN_RECEIPT",
+        action(clap::ArgAction::SetTrue),
+        default_value = "false",
+        global = true
+    )]
+    pub sign_receipt: bool,
+    /// Sign (and check) the receipt with the key in this file, instead of one kept on this machine
+    #[clap(long, env = "NIX_INSTALLER_RECEIPT_KEY", global = true)]
+    pub receipt_key: Option<PathBuf>,
+    /// Trust the receipt even if it doesn't match its signature
+    #[clap(
+        long,
+        env = "NIX_INSTALLER_SKIP_RECEIPT_VERIFICATION",
+        action(clap::ArgAction::SetTrue),
+        default_value = "false",
+        global = true
+    )]
+    pub skip_receipt_verification: bool,
+}
+
+impl ReceiptSigningArgs {
+    pub fn setup(&self) -> eyre::Result<()> {
+        let key = match &self.receipt_key {
+            Some(receipt_key) => Some(read_key(receipt_key).wrap_err_with(|| {
+                format!("Reading the receipt key `{}`", receipt_key.display())
+            })?),
+            None if self.sign_receipt => Some(SigningKey::Machine),
+            None => None,
+        };
+        set_receipt_signing(key, self.skip_receipt_verification);
+        Ok(())
+    }
+}
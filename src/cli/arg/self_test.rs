@@ -0,0 +1,32 @@
+use crate::self_test::{set_self_test_shells, Shell};
+
+#[derive(clap::Args, Debug, Default)]
+pub struct SelfTestArgs {
+    /// Only self-test these shells (delimited by comma), failing if one isn't installed, instead of each one found
+    #[clap(
+        long,
+        env = "NIX_INSTALLER_SELF_TEST_SHELLS",
+        value_delimiter = ',',
+        num_args = 1..,
+        global = true
+    )]
+    pub self_test_shells: Option<Vec<Shell>>,
+    /// Don't self-test these shells (delimited by comma), even if they are installed
+    #[clap(
+        long,
+        env = "NIX_INSTALLER_SKIP_SELF_TEST_SHELLS",
+        value_delimiter = ',',
+        num_args = 1..,
+        global = true
+    )]
+    pub skip_self_test_shells: Vec<Shell>,
+}
+
+impl SelfTestArgs {
+    pub fn setup(&self) {
+        set_self_test_shells(
+            self.self_test_shells.clone(),
+            self.skip_self_test_shells.clone(),
+        );
+    }
+}
@@ -95,5 +95,7 @@ pub(crate) fn read_line() -> eyre::Result<String> {
 
 pub(crate) async fn clean_exit_with_message(message: impl AsRef<str>) -> ! {
     eprintln!("{}", message.as_ref());
+    #[cfg(feature = "otel")]
+    crate::cli::otel::shutdown().await;
     std::process::exit(0)
 }
@@ -0,0 +1,154 @@
+//! The `--log-file` which full debug logs are written to, whatever the console shows
+//!
+//! Without one, the most recent debug logs are kept in memory for crash reports instead.
+
+use std::{
+    collections::VecDeque,
+    fs::{File, OpenOptions},
+    io::Write,
+    os::unix::fs::OpenOptionsExt,
+    path::{Path, PathBuf},
+    sync::{Mutex, OnceLock},
+};
+
+static LOG_FILE: OnceLock<PathBuf> = OnceLock::new();
+static RECENT_LOGS: Mutex<VecDeque<u8>> = Mutex::new(VecDeque::new());
+/// How many bytes of logs are kept in memory, older ones are dropped
+const RECENT_LOGS_SIZE: usize = 8 * 1024 * 1024;
+
+/// The `--log-file` being written, if any, so errors can point at it
+pub(crate) fn log_file() -> Option<&'static Path> {
+    LOG_FILE.get().map(PathBuf::as_path)
+}
+
+/// A file which is moved aside (to `$PATH.1`, `$PATH.2`, ...) once it grows past `max_size` bytes
+#[derive(Debug)]
+pub(crate) struct RotatingFile {
+    path: PathBuf,
+    max_size: u64,
+    /// How many moved aside files are kept
+    keep: usize,
+    file: File,
+    size: u64,
+}
+
+impl RotatingFile {
+    pub(crate) fn open(path: &Path, max_size: u64, keep: usize) -> std::io::Result<Self> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        // The debug logs have the plan, and the commands run, in them
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .mode(0o600)
+            .open(path)?;
+        let size = file.metadata()?.len();
+        LOG_FILE.get_or_init(|| path.to_path_buf());
+        Ok(Self {
+            path: path.to_path_buf(),
+            max_size,
+            keep,
+            file,
+            size,
+        })
+    }
+
+    fn rotated(&self, generation: usize) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{generation}"));
+        PathBuf::from(path)
+    }
+
+    fn rotate(&mut self) -> std::io::Result<()> {
+        self.file.flush()?;
+        if self.keep > 0 {
+            for generation in (1..self.keep).rev() {
+                let from = self.rotated(generation);
+                if from.exists() {
+                    std::fs::rename(&from, self.rotated(generation + 1))?;
+                }
+            }
+            std::fs::rename(&self.path, self.rotated(1))?;
+        }
+        self.file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        // A line longer than the limit still goes in a file of its own
+        if self.size > 0 && self.size + buf.len() as u64 > self.max_size {
+            self.rotate()?;
+        }
+        let written = self.file.write(buf)?;
+        self.size += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.file.flush()
+    }
+}
+
+/// The debug logs kept in memory, when there is no `--log-file`
+pub(crate) fn recent_logs() -> Vec<u8> {
+    let logs = RECENT_LOGS
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    logs.iter().copied().collect()
+}
+
+/// Writes to the logs kept in memory, see [`recent_logs`]
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct RecentLogs;
+
+impl Write for RecentLogs {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let mut logs = RECENT_LOGS
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        logs.extend(buf);
+        let excess = logs.len().saturating_sub(RECENT_LOGS_SIZE);
+        logs.drain(..excess);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{io::Write, os::unix::fs::PermissionsExt};
+
+    use super::RotatingFile;
+
+    #[test]
+    fn rotates_by_size() -> std::io::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let path = temp_dir.path().join("nix-installer.log");
+        let mut file = RotatingFile::open(&path, 10, 2)?;
+        for line in ["first\n", "second\n", "third\n", "fourth\n"] {
+            file.write_all(line.as_bytes())?;
+        }
+        file.flush()?;
+
+        assert_eq!(std::fs::read_to_string(&path)?, "fourth\n");
+        assert_eq!(std::fs::read_to_string(file.rotated(1))?, "third\n");
+        assert_eq!(std::fs::read_to_string(file.rotated(2))?, "second\n");
+        assert!(!file.rotated(3).exists());
+        for path in [path.clone(), file.rotated(1), file.rotated(2)] {
+            assert_eq!(std::fs::metadata(path)?.permissions().mode() & 0o777, 0o600);
+        }
+        Ok(())
+    }
+}
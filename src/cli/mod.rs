@@ -3,16 +3,26 @@
 */
 
 pub(crate) mod arg;
+mod crash_report;
 mod interaction;
+mod log_file;
+#[cfg(feature = "otel")]
+mod otel;
+pub(crate) mod output;
+mod progress;
+#[cfg(feature = "tui")]
+mod review;
 pub(crate) mod subcommand;
 
 use clap::Parser;
+use color_eyre::Section;
 use eyre::WrapErr;
 use owo_colors::OwoColorize;
-use std::{ffi::CString, process::ExitCode};
+use std::{ffi::CString, process::ExitCode, sync::OnceLock};
 use tokio::sync::broadcast::{Receiver, Sender};
 
-use self::subcommand::NixInstallerSubcommand;
+use self::{arg::Elevation, subcommand::NixInstallerSubcommand};
+use crate::{error::HasExpectedErrors, lock::InstallerLock, ErrorClass, NixInstallerError};
 
 #[async_trait::async_trait]
 pub trait CommandExecute {
@@ -30,6 +40,27 @@ pub struct NixInstallerCli {
     #[clap(flatten)]
     pub instrumentation: arg::Instrumentation,
 
+    #[clap(flatten)]
+    pub action_policy: arg::ActionPolicyArgs,
+
+    #[clap(flatten)]
+    pub output: arg::Output,
+
+    #[clap(flatten)]
+    pub audit: arg::AuditArgs,
+
+    #[clap(flatten)]
+    pub receipt_signing: arg::ReceiptSigningArgs,
+
+    #[clap(flatten)]
+    pub privilege_separation: arg::PrivilegeSeparationArgs,
+
+    #[clap(flatten)]
+    pub elevation: arg::ElevationArgs,
+
+    #[clap(flatten)]
+    pub self_test: arg::SelfTestArgs,
+
     #[clap(subcommand)]
     pub subcommand: NixInstallerSubcommand,
 }
@@ -38,21 +69,126 @@ pub struct NixInstallerCli {
 impl CommandExecute for NixInstallerCli {
     #[tracing::instrument(level = "trace", skip_all)]
     async fn execute(self) -> eyre::Result<ExitCode> {
+        let res = self.run().await;
+        // Spans of a failed run matter most, so flush them however it ended
+        #[cfg(feature = "otel")]
+        otel::shutdown().await;
+        res
+    }
+}
+
+impl NixInstallerCli {
+    async fn run(self) -> eyre::Result<ExitCode> {
         let Self {
             instrumentation: _,
+            action_policy,
+            output,
+            audit,
+            receipt_signing,
+            privilege_separation,
+            elevation,
+            self_test,
             subcommand,
         } = self;
 
-        match subcommand {
+        action_policy.setup();
+        output.setup();
+        audit.setup()?;
+        receipt_signing.setup()?;
+        privilege_separation.setup()?;
+        elevation.setup()?;
+        self_test.setup();
+
+        let command = subcommand.name();
+        let res = match subcommand {
             NixInstallerSubcommand::Plan(plan) => plan.execute().await,
+            NixInstallerSubcommand::Apply(apply) => apply.execute().await,
+            NixInstallerSubcommand::Resume(resume) => resume.execute().await,
+            NixInstallerSubcommand::RollbackToSnapshot(rollback_to_snapshot) => {
+                rollback_to_snapshot.execute().await
+            },
+            NixInstallerSubcommand::RotateVolumeKey(rotate_volume_key) => {
+                rotate_volume_key.execute().await
+            },
+            NixInstallerSubcommand::Doctor(doctor) => doctor.execute().await,
+            NixInstallerSubcommand::Check(check) => check.execute().await,
+            NixInstallerSubcommand::Upgrade(upgrade) => upgrade.execute().await,
+            NixInstallerSubcommand::Reconfigure(reconfigure) => reconfigure.execute().await,
+            NixInstallerSubcommand::Verify(verify) => verify.execute().await,
+            NixInstallerSubcommand::Explain(explain) => explain.execute().await,
+            NixInstallerSubcommand::Adopt(adopt) => adopt.execute().await,
+            NixInstallerSubcommand::Migrate(migrate) => migrate.execute().await,
+            NixInstallerSubcommand::MigrateSingleUser(migrate_single_user) => {
+                migrate_single_user.execute().await
+            },
             NixInstallerSubcommand::SelfTest(self_test) => self_test.execute().await,
             NixInstallerSubcommand::Install(install) => install.execute().await,
             NixInstallerSubcommand::Repair(restore_shell) => restore_shell.execute().await,
             NixInstallerSubcommand::Uninstall(revert) => revert.execute().await,
+            NixInstallerSubcommand::Remote(remote) => remote.execute().await,
+            NixInstallerSubcommand::ListVersions(list_versions) => list_versions.execute().await,
+            NixInstallerSubcommand::FetchUnprivileged(fetch_unprivileged) => {
+                fetch_unprivileged.execute().await
+            },
+        };
+
+        // Classified failures exit with their own code, see `ErrorClass`
+        let res =
+            match res {
+                Err(report) => {
+                    let root: &(dyn std::error::Error + 'static) = report.as_ref();
+                    let class = report
+                        .chain()
+                        .find_map(|err| err.downcast_ref::<NixInstallerError>())
+                        .map(NixInstallerError::class)
+                        .unwrap_or_else(|| ErrorClass::of(root));
+                    if class == ErrorClass::Other && !output::is_json() {
+                        match log_file::log_file() {
+                            Some(log_file) => Err(report
+                                .note(format!("Full debug logs are in `{}`", log_file.display()))),
+                            None => Err(report),
+                        }
+                    } else {
+                        eprintln!("Error: {report:?}");
+                        Ok(output::failure(class, &report, output::causes(root)))
+                    }
+                },
+                ok => ok,
+            };
+        if let (Ok(exit_code), Some(log_file)) = (&res, log_file::log_file()) {
+            if *exit_code != ExitCode::SUCCESS {
+                eprintln!("Full debug logs are in `{}`", log_file.display());
+            }
         }
+
+        // Commands without anything more to say still end with a result
+        if output::is_json() && !output::result_emitted() {
+            match &res {
+                Ok(exit_code) => {
+                    output::result(command, *exit_code == ExitCode::SUCCESS, Default::default())
+                },
+                Err(err) => output::result(
+                    command,
+                    false,
+                    serde_json::json!({ "error": format!("{err:#}") }),
+                ),
+            }
+        }
+        res
     }
 }
 
+static ELEVATION: OnceLock<Elevation> = OnceLock::new();
+
+pub(crate) fn set_elevation(elevation: Elevation) {
+    ELEVATION.get_or_init(|| elevation);
+}
+
+/// How to become `root`, see `--elevate-with`
+pub(crate) fn elevation() -> Elevation {
+    ELEVATION.get().copied().unwrap_or_default()
+}
+
 pub(crate) async fn signal_channel() -> eyre::Result<(Sender<()>, Receiver<()>)> {
     let (sender, receiver) = tokio::sync::broadcast::channel(100);
 
@@ -90,49 +226,33 @@ pub fn is_root() -> bool {
 
 pub fn ensure_root() -> eyre::Result<()> {
     if !is_root() {
+        let elevation = elevation();
         eprintln!(
             "{}",
-            "`nix-installer` needs to run as `root`, attempting to escalate now via `sudo`..."
+            format!("`nix-installer` needs to run as `root`, attempting to escalate now via `{elevation}`...")
                 .yellow()
                 .dimmed()
         );
-        let sudo_cstring = CString::new("sudo").wrap_err("Making C string of `sudo`")?;
-        let set_home_cstring =
-            CString::new("--set-home").wrap_err("Making C string of `--set-home`")?;
-
-        let args = std::env::args();
         let mut arg_vec_cstring = vec![];
-        arg_vec_cstring.push(sudo_cstring.clone());
-        arg_vec_cstring.push(set_home_cstring);
-
-        let mut env_list = vec![];
-        for (key, value) in std::env::vars() {
-            let preserve = match key.as_str() {
-                // Rust logging/backtrace bits we use
-                "RUST_LOG" | "RUST_BACKTRACE" => true,
-                // CI
-                "GITHUB_PATH" => true,
-                // Used for detecting what command to suggest for sourcing Nix
-                "SHELL" => true,
-                // Proxy settings (automatically picked up by Reqwest)
-                "HTTP_PROXY" | "http_proxy" | "HTTPS_PROXY" | "https_proxy" => true,
-                // Our own environments
-                key if key.starts_with("NIX_INSTALLER") => true,
-                _ => false,
-            };
-            if preserve {
-                env_list.push(format!("{key}={value}"));
-            }
-        }
-
-        #[cfg(feature = "diagnostics")]
-        if is_ci::cached() {
-            // Normally `sudo` would erase those envs, so we detect and pass that along specifically to avoid having to pass around
-            // a bunch of environment variables
-            env_list.push("NIX_INSTALLER_CI=1".to_string());
-        }
+        let program = match elevation {
+            Elevation::Sudo => {
+                let sudo_cstring = CString::new("sudo").wrap_err("Making C string of `sudo`")?;
+                arg_vec_cstring.push(sudo_cstring.clone());
+                arg_vec_cstring
+                    .push(CString::new("--set-home").wrap_err("Making C string of `--set-home`")?);
+                sudo_cstring
+            },
+            Elevation::Pkexec => {
+                let pkexec_cstring =
+                    CString::new("pkexec").wrap_err("Making C string of `pkexec`")?;
+                arg_vec_cstring.push(pkexec_cstring.clone());
+                pkexec_cstring
+            },
+        };
 
-        if !env_list.is_empty() {
+        let env_list = preserved_env();
+        // `pkexec` clears the environment, and only runs programs by their absolute path
+        if !env_list.is_empty() || elevation == Elevation::Pkexec {
             arg_vec_cstring
                 .push(CString::new("env").wrap_err("Building a `env` argument for `sudo`")?);
             for env in env_list {
@@ -143,13 +263,118 @@ pub fn ensure_root() -> eyre::Result<()> {
             }
         }
 
-        for arg in args {
+        for arg in elevated_args()? {
             arg_vec_cstring.push(CString::new(arg).wrap_err("Making arg into C string")?);
         }
 
-        tracing::trace!("Execvp'ing `{sudo_cstring:?}` with args `{arg_vec_cstring:?}`");
-        nix::unistd::execvp(&sudo_cstring, &arg_vec_cstring)
-            .wrap_err("Executing `nix-installer` as `root` via `sudo`")?;
+        tracing::trace!("Execvp'ing `{program:?}` with args `{arg_vec_cstring:?}`");
+        nix::unistd::execvp(&program, &arg_vec_cstring)
+            .wrap_err_with(|| format!("Executing `nix-installer` as `root` via `{elevation}`"))?;
     }
     Ok(())
 }
+
+/// The environment variables (as `KEY=VALUE`) an elevated `nix-installer` is run with
+fn preserved_env() -> Vec<String> {
+    let mut env_list = vec![];
+    for (key, value) in std::env::vars() {
+        let preserve = match key.as_str() {
+            // Rust logging/backtrace bits we use
+            "RUST_LOG" | "RUST_BACKTRACE" => true,
+            // CI
+            "GITHUB_PATH" => true,
+            // Used for detecting what command to suggest for sourcing Nix
+            "SHELL" => true,
+            // Proxy settings (automatically picked up by Reqwest)
+            "HTTP_PROXY" | "http_proxy" | "HTTPS_PROXY" | "https_proxy" | "ALL_PROXY"
+            | "all_proxy" | "NO_PROXY" | "no_proxy" => true,
+            // Our own environments
+            key if key.starts_with("NIX_INSTALLER") => true,
+            _ => false,
+        };
+        if preserve {
+            env_list.push(format!("{key}={value}"));
+        }
+    }
+
+    #[cfg(feature = "diagnostics")]
+    if is_ci::cached() {
+        // Normally `sudo` would erase those envs, so we detect and pass that along specifically to avoid having to pass around
+        // a bunch of environment variables
+        env_list.push("NIX_INSTALLER_CI=1".to_string());
+    }
+
+    env_list
+}
+
+/// The arguments `nix-installer` was run with, starting with its path (absolute, for `pkexec`)
+fn elevated_args() -> eyre::Result<Vec<String>> {
+    let mut args = std::env::args();
+    let arg0 = args.next().unwrap_or_default();
+    let exe = match elevation() {
+        Elevation::Sudo => arg0,
+        Elevation::Pkexec => std::env::current_exe()
+            .wrap_err("Finding the path of `nix-installer`")?
+            .display()
+            .to_string(),
+    };
+    Ok(std::iter::once(exe).chain(args).collect())
+}
+
+/// If privileged phases are run in an elevated `nix-installer`, rather than the whole process, see [`run_elevated`]
+pub(crate) fn elevate_per_phase() -> bool {
+    elevation() == Elevation::Pkexec && !is_root()
+}
+
+/// Run this `nix-installer` again as `root` with `pkexec`, with `env` added to its environment, returning its exit code
+///
+/// The unprivileged process does what doesn't need root (like planning, and prompting), then hands the
+/// rest to this one, which is told what was already done by `env`.
+pub(crate) async fn run_elevated(env: &[(&str, &str)]) -> eyre::Result<ExitCode> {
+    let mut command = tokio::process::Command::new("pkexec");
+    command.arg("env").args(preserved_env());
+    for (key, value) in env {
+        command.arg(format!("{key}={value}"));
+    }
+    command.args(elevated_args()?);
+    tracing::debug!("Running the privileged phase with `{command:?}`");
+    let status = command
+        .status()
+        .await
+        .wrap_err("Running `nix-installer` as `root` via `pkexec`")?;
+    // The elevated `nix-installer` printed its own result
+    output::set_result_emitted();
+    match status.code() {
+        // `pkexec` couldn't authenticate, or wasn't authorized
+        Some(code @ (126 | 127)) => {
+            eprintln!(
+                "{}",
+                "Authenticating with polkit to run `nix-installer` as `root` failed, or was dismissed".red()
+            );
+            Ok(output::failure(
+                ErrorClass::Permission,
+                format!("`pkexec` exited with {code}"),
+                vec![],
+            ))
+        },
+        Some(code) => Ok(ExitCode::from(u8::try_from(code).unwrap_or(1))),
+        None => Ok(ExitCode::FAILURE),
+    }
+}
+
+/// Take the [`InstallerLock`], so no other `nix-installer` changes the system until it is dropped
+///
+/// If another `nix-installer` holds it, this says so and gives the exit code to fail with instead.
+pub(crate) fn ensure_lock() -> eyre::Result<Result<InstallerLock, ExitCode>> {
+    match InstallerLock::acquire() {
+        Ok(lock) => Ok(Ok(lock)),
+        Err(err) => {
+            let err = NixInstallerError::from(err);
+            if let Some(expected) = err.expected() {
+                eprintln!("{}", expected.red());
+                return Ok(Err(output::error_failure(&err)));
+            }
+            Err(err)?
+        },
+    }
+}
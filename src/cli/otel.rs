@@ -0,0 +1,54 @@
+//! Exporting the tracing spans of a run (one per action) to an OTLP collector, for `--otlp-endpoint`
+
+use std::sync::OnceLock;
+
+use eyre::WrapErr;
+use opentelemetry::{trace::TracerProvider as _, KeyValue};
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::{runtime, trace::TracerProvider, Resource};
+use tracing_subscriber::Layer;
+use url::Url;
+
+static TRACER_PROVIDER: OnceLock<TracerProvider> = OnceLock::new();
+
+/// A layer sending every span it sees to the OTLP/HTTP collector at `endpoint`
+///
+/// An `endpoint` without a path has the usual `/v1/traces` added.
+pub(crate) fn layer<S>(endpoint: &Url) -> eyre::Result<impl Layer<S>>
+where
+    S: tracing::Subscriber + for<'span> tracing_subscriber::registry::LookupSpan<'span>,
+{
+    let mut endpoint = endpoint.clone();
+    if endpoint.path() == "/" {
+        endpoint.set_path("/v1/traces");
+    }
+    let exporter = opentelemetry_otlp::SpanExporter::builder()
+        .with_http()
+        .with_endpoint(endpoint.as_str())
+        .build()
+        .wrap_err_with(|| format!("Creating an OTLP exporter for `{endpoint}`"))?;
+    let provider = TracerProvider::builder()
+        .with_batch_exporter(exporter, runtime::Tokio)
+        .with_resource(Resource::new(vec![
+            KeyValue::new("service.name", env!("CARGO_PKG_NAME")),
+            KeyValue::new("service.version", env!("CARGO_PKG_VERSION")),
+        ]))
+        .build();
+    let tracer = provider.tracer(env!("CARGO_PKG_NAME"));
+    TRACER_PROVIDER.get_or_init(|| provider);
+    Ok(tracing_opentelemetry::layer().with_tracer(tracer))
+}
+
+/// Send the spans which are still batched, before exiting
+pub(crate) async fn shutdown() {
+    let Some(provider) = TRACER_PROVIDER.get() else {
+        return;
+    };
+    // Flushing blocks until the collector answers
+    let provider = provider.clone();
+    match tokio::task::spawn_blocking(move || provider.shutdown()).await {
+        Ok(Ok(())) => (),
+        Ok(Err(err)) => tracing::warn!(%err, "Could not export all traces"),
+        Err(err) => tracing::warn!(%err, "Could not export all traces"),
+    }
+}
@@ -0,0 +1,124 @@
+/*! Machine readable output for `--format json`
+
+Each event is printed to stdout as a JSON object on its own line, with an `event` field saying
+what it is. Every command ends with a single `result` event:
+
+```json
+{"event":"result","command":"install","success":true}
+```
+
+Failures also print an `error` event to stderr, with the [`ErrorClass`] which decided the exit code:
+
+```json
+{"event":"error","class":"network","exit_code":11,"message":"Error executing action","causes":["..."]}
+```
+*/
+
+use std::{
+    error::Error,
+    fmt::Display,
+    process::ExitCode,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        OnceLock,
+    },
+};
+
+use serde::Serialize;
+
+use super::{arg::OutputFormat, log_file::log_file};
+use crate::{ErrorClass, NixInstallerError};
+
+static FORMAT: OnceLock<OutputFormat> = OnceLock::new();
+static RESULT_EMITTED: AtomicBool = AtomicBool::new(false);
+static FAILURE_CLASS: OnceLock<ErrorClass> = OnceLock::new();
+
+pub(crate) fn set_format(format: OutputFormat) {
+    FORMAT.get_or_init(|| format);
+}
+
+/// If `--format json` was given, so only JSON may be printed to stdout
+pub(crate) fn is_json() -> bool {
+    FORMAT.get() == Some(&OutputFormat::Json)
+}
+
+/// Print `event` as a line of JSON
+pub(crate) fn event(event: &impl Serialize) {
+    match serde_json::to_string(event) {
+        Ok(line) => println!("{line}"),
+        Err(err) => tracing::warn!(%err, "Could not serialize an output event"),
+    }
+}
+
+/// Print the `result` event of `command`, with the fields of `data` (if it is an object) added to it
+pub(crate) fn result(command: &str, success: bool, data: serde_json::Value) {
+    let mut result = serde_json::json!({
+        "event": "result",
+        "command": command,
+        "success": success,
+    });
+    if let (Some(result), serde_json::Value::Object(data)) = (result.as_object_mut(), data) {
+        result.extend(data);
+    }
+    if let (false, Some(class), Some(result)) =
+        (success, FAILURE_CLASS.get(), result.as_object_mut())
+    {
+        result.insert("class".into(), serde_json::json!(class));
+        result.insert("exit_code".into(), class.exit_code().into());
+    }
+    event(&result);
+    RESULT_EMITTED.store(true, Ordering::SeqCst);
+}
+
+/// If the command already printed its `result`
+pub(crate) fn result_emitted() -> bool {
+    RESULT_EMITTED.load(Ordering::SeqCst)
+}
+
+/// Don't end with a `result`, because another process (like an elevated `nix-installer`) printed it
+pub(crate) fn set_result_emitted() {
+    RESULT_EMITTED.store(true, Ordering::SeqCst);
+}
+
+/// Refuse to prompt when the output is JSON, as no one is reading it to answer
+pub(crate) fn ensure_no_prompt(no_confirm: bool) -> eyre::Result<()> {
+    if is_json() && !no_confirm {
+        return Err(eyre::eyre!(
+            "`--format json` needs `--no-confirm`, as there is no one to answer prompts"
+        ));
+    }
+    Ok(())
+}
+
+/// The exit code of a failure of `class`, printing an `error` event to stderr for `--format json`
+pub(crate) fn failure(class: ErrorClass, message: impl Display, causes: Vec<String>) -> ExitCode {
+    FAILURE_CLASS.get_or_init(|| class);
+    if is_json() {
+        let error = serde_json::json!({
+            "event": "error",
+            "class": class,
+            "exit_code": class.exit_code(),
+            "message": message.to_string(),
+            "causes": causes,
+            "log_file": log_file().map(|path| path.display().to_string()),
+        });
+        eprintln!("{error}");
+    }
+    ExitCode::from(class.exit_code())
+}
+
+/// The exit code of `err`, see [`failure`]
+pub(crate) fn error_failure(err: &NixInstallerError) -> ExitCode {
+    failure(err.class(), err, causes(err))
+}
+
+/// The messages of the sources of `err`
+pub(crate) fn causes(err: &(dyn Error + 'static)) -> Vec<String> {
+    let mut causes = vec![];
+    let mut next = err.source();
+    while let Some(err) = next {
+        causes.push(err.to_string());
+        next = err.source();
+    }
+    causes
+}
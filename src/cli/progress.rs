@@ -0,0 +1,103 @@
+//! Progress bars (or JSON lines, for `--format json`) rendering the [`ProgressEvent`]s of an install
+
+use std::time::Duration;
+
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use tokio::{
+    sync::mpsc::{unbounded_channel, UnboundedReceiver},
+    task::JoinHandle,
+};
+
+use crate::{
+    cli::output,
+    progress::{clear_progress_sender, set_progress_sender, ProgressEvent},
+};
+
+const ACTIONS_TEMPLATE: &str = "{spinner} [{bar:30}] {pos}/{len} ({elapsed}, ETA {eta}) {wide_msg}";
+const DOWNLOAD_TEMPLATE: &str =
+    "  Downloading Nix [{bar:30}] {bytes}/{total_bytes} ({binary_bytes_per_sec}, ETA {eta})";
+const UNPACK_TEMPLATE: &str = "  Unpacking Nix   [{bar:30}] {percent}% (ETA {eta})";
+
+/// Draws the progress of an install (on stderr, if it is a terminal) until [`finish`](ProgressReport::finish)ed
+///
+/// With `--format json` each event is printed to stdout instead.
+pub(crate) struct ProgressReport {
+    handle: JoinHandle<()>,
+}
+
+impl ProgressReport {
+    /// Start drawing the progress of a plan of `actions` actions
+    pub(crate) fn start(actions: usize) -> Self {
+        let (tx, rx) = unbounded_channel();
+        set_progress_sender(tx);
+        let handle = if output::is_json() {
+            tokio::spawn(render_json(rx))
+        } else {
+            tokio::spawn(render(rx, actions as u64))
+        };
+        Self { handle }
+    }
+
+    /// Remove the progress bars, so the outcome of the install can be printed
+    pub(crate) async fn finish(self) {
+        clear_progress_sender();
+        if let Err(err) = self.handle.await {
+            tracing::debug!(%err, "Drawing progress failed");
+        }
+    }
+}
+
+fn bar(multi: &MultiProgress, template: &str, len: u64) -> ProgressBar {
+    let style = ProgressStyle::with_template(template)
+        .unwrap_or_else(|_| ProgressStyle::default_bar())
+        .progress_chars("=> ");
+    multi.add(ProgressBar::new(len).with_style(style))
+}
+
+async fn render(mut rx: UnboundedReceiver<ProgressEvent>, actions: u64) {
+    let multi = MultiProgress::new();
+    let actions = bar(&multi, ACTIONS_TEMPLATE, actions);
+    actions.enable_steady_tick(Duration::from_millis(100));
+    let mut download: Option<ProgressBar> = None;
+    let mut unpack: Option<ProgressBar> = None;
+
+    while let Some(event) = rx.recv().await {
+        match event {
+            ProgressEvent::ActionStarted { synopsis }
+            | ProgressEvent::RevertStarted { synopsis } => actions.set_message(synopsis),
+            ProgressEvent::ActionCompleted {
+                completed: done,
+                total,
+            }
+            | ProgressEvent::RevertCompleted {
+                reverted: done,
+                total,
+            } => {
+                actions.set_length(total as u64);
+                actions.set_position(done as u64);
+            },
+            ProgressEvent::Downloaded { bytes, total } => {
+                let download = download.get_or_insert_with(|| bar(&multi, DOWNLOAD_TEMPLATE, 0));
+                if let Some(total) = total {
+                    download.set_length(total);
+                }
+                download.set_position(bytes);
+            },
+            ProgressEvent::Unpacked { bytes, total } => {
+                let unpack = unpack.get_or_insert_with(|| bar(&multi, UNPACK_TEMPLATE, total));
+                unpack.set_position(bytes);
+            },
+        }
+    }
+
+    for bar in download.iter().chain(unpack.iter()) {
+        bar.finish_and_clear();
+    }
+    actions.finish_and_clear();
+}
+
+async fn render_json(mut rx: UnboundedReceiver<ProgressEvent>) {
+    while let Some(event) = rx.recv().await {
+        output::event(&event);
+    }
+}
@@ -0,0 +1,278 @@
+//! An interactive review of an [`InstallPlan`], see `nix-installer install --review`
+
+use std::collections::BTreeSet;
+
+use ratatui::{
+    crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers},
+    layout::{Constraint, Layout},
+    style::{Color, Style, Stylize},
+    text::Line,
+    widgets::{Block, List, ListItem, ListState, Paragraph},
+    DefaultTerminal, Frame,
+};
+
+use crate::InstallPlan;
+
+const HELP: &str = "↑/↓ move  ⏎ expand  s skip  a approve  q abort";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ReviewOutcome {
+    Approve,
+    Abort,
+}
+
+/// A top-level action of the plan
+#[derive(Debug, Clone)]
+struct ReviewItem {
+    /// Where it is in [`InstallPlan::actions`]
+    index: usize,
+    synopsis: String,
+    /// The descriptions of what it does, and diffs of the files it changes
+    details: Vec<Line<'static>>,
+    skippable: bool,
+}
+
+#[derive(Debug)]
+struct Review {
+    items: Vec<ReviewItem>,
+    selected: usize,
+    expanded: BTreeSet<usize>,
+    skipped: BTreeSet<usize>,
+    status: Option<String>,
+}
+
+impl Review {
+    fn new(plan: &InstallPlan) -> Self {
+        let items = plan
+            .actions
+            .iter()
+            .enumerate()
+            .filter_map(|(index, action)| {
+                let descriptions = action.describe_execute();
+                if descriptions.is_empty() {
+                    // Already completed, or skipped
+                    return None;
+                }
+                let mut details = vec![];
+                for description in descriptions {
+                    details.push(Line::from(format!("    {}", description.description)));
+                    for explanation in description.explanation {
+                        details.push(Line::from(format!("      - {explanation}")).dim());
+                    }
+                }
+                for diff in action.dry_run().iter().filter_map(|step| step.diff()) {
+                    details.extend(diff.lines().map(diff_line));
+                }
+                Some(ReviewItem {
+                    index,
+                    synopsis: action.tracing_synopsis(),
+                    details,
+                    skippable: action.skippable(),
+                })
+            })
+            .collect();
+        Self::with_items(items)
+    }
+
+    fn with_items(items: Vec<ReviewItem>) -> Self {
+        Self {
+            items,
+            selected: 0,
+            expanded: BTreeSet::new(),
+            skipped: BTreeSet::new(),
+            status: None,
+        }
+    }
+
+    fn handle_key(&mut self, key: KeyEvent) -> Option<ReviewOutcome> {
+        self.status = None;
+        match key.code {
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                return Some(ReviewOutcome::Abort)
+            },
+            KeyCode::Up | KeyCode::Char('k') => self.selected = self.selected.saturating_sub(1),
+            KeyCode::Down | KeyCode::Char('j') => {
+                self.selected = (self.selected + 1).min(self.items.len().saturating_sub(1))
+            },
+            KeyCode::Enter | KeyCode::Char(' ') if !self.expanded.remove(&self.selected) => {
+                self.expanded.insert(self.selected);
+            },
+            KeyCode::Right | KeyCode::Char('l') => {
+                self.expanded.insert(self.selected);
+            },
+            KeyCode::Left | KeyCode::Char('h') => {
+                self.expanded.remove(&self.selected);
+            },
+            KeyCode::Char('s') => self.toggle_skip(),
+            KeyCode::Char('a') | KeyCode::Char('y') => return Some(ReviewOutcome::Approve),
+            KeyCode::Char('q') | KeyCode::Char('n') | KeyCode::Esc => {
+                return Some(ReviewOutcome::Abort)
+            },
+            _ => (),
+        }
+        None
+    }
+
+    fn toggle_skip(&mut self) {
+        let Some(item) = self.items.get(self.selected) else {
+            return;
+        };
+        if !item.skippable {
+            self.status = Some(format!(
+                "`{}` is needed by the install, it can't be skipped",
+                item.synopsis
+            ));
+        } else if !self.skipped.remove(&self.selected) {
+            self.skipped.insert(self.selected);
+        }
+    }
+
+    fn draw(&self, frame: &mut Frame, list_state: &mut ListState) {
+        let [tree_area, status_area] =
+            Layout::vertical([Constraint::Min(1), Constraint::Length(1)]).areas(frame.area());
+
+        let items = self.items.iter().enumerate().map(|(index, item)| {
+            let expanded = self.expanded.contains(&index);
+            let marker = if expanded { "▾" } else { "▸" };
+            let mut synopsis = Line::from(format!("{marker} {}", item.synopsis));
+            if self.skipped.contains(&index) {
+                synopsis = synopsis.crossed_out().dim();
+            } else if item.skippable {
+                synopsis.push_span(" (optional)".dim());
+            }
+            let mut lines = vec![synopsis];
+            if expanded {
+                lines.extend(item.details.iter().cloned());
+            }
+            ListItem::new(lines)
+        });
+        let tree = List::new(items)
+            .block(Block::bordered().title(" Nix install plan "))
+            .highlight_style(Style::new().bold().reversed());
+        frame.render_stateful_widget(tree, tree_area, list_state);
+
+        let status = match &self.status {
+            Some(status) => Line::from(status.as_str()).yellow(),
+            None => Line::from(HELP).dim(),
+        };
+        frame.render_widget(Paragraph::new(status), status_area);
+    }
+
+    fn run(&mut self, terminal: &mut DefaultTerminal) -> std::io::Result<ReviewOutcome> {
+        let mut list_state = ListState::default();
+        loop {
+            list_state.select(Some(self.selected));
+            terminal.draw(|frame| self.draw(frame, &mut list_state))?;
+            if let Event::Key(key) = event::read()? {
+                if key.kind != KeyEventKind::Press {
+                    continue;
+                }
+                if let Some(outcome) = self.handle_key(key) {
+                    return Ok(outcome);
+                }
+            }
+        }
+    }
+}
+
+fn diff_line(line: &str) -> Line<'static> {
+    let styled = Line::from(format!("    {line}"));
+    if line.starts_with("+++") || line.starts_with("---") {
+        styled.bold()
+    } else if line.starts_with('+') {
+        styled.fg(Color::Green)
+    } else if line.starts_with('-') {
+        styled.fg(Color::Red)
+    } else if line.starts_with("@@") {
+        styled.fg(Color::Cyan)
+    } else {
+        styled
+    }
+}
+
+/// Show the plan as a tree of actions, each expandable to its description and diffs
+///
+/// On approval the actions the user chose to skip are marked [`Skipped`](crate::action::ActionState::Skipped).
+pub(crate) async fn review(plan: &mut InstallPlan) -> eyre::Result<ReviewOutcome> {
+    let mut review = Review::new(plan);
+    let (review, outcome) = tokio::task::spawn_blocking(move || {
+        let outcome = ratatui::try_init().and_then(|mut terminal| {
+            let outcome = review.run(&mut terminal);
+            ratatui::restore();
+            outcome
+        });
+        (review, outcome)
+    })
+    .await?;
+    let outcome = outcome?;
+
+    if outcome == ReviewOutcome::Approve {
+        for item in review
+            .skipped
+            .iter()
+            .filter_map(|skipped| review.items.get(*skipped))
+        {
+            if let Some(action) = plan.actions.get_mut(item.index) {
+                if action.skip() {
+                    tracing::info!("Skipping: {}", action.tracing_synopsis());
+                }
+            }
+        }
+    }
+    Ok(outcome)
+}
+
+#[cfg(test)]
+mod test {
+    use ratatui::crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+
+    use super::{Review, ReviewItem, ReviewOutcome};
+
+    fn item(index: usize, synopsis: &str, skippable: bool) -> ReviewItem {
+        ReviewItem {
+            index,
+            synopsis: synopsis.to_string(),
+            details: vec![],
+            skippable,
+        }
+    }
+
+    #[test]
+    fn skips_only_skippable_actions() {
+        let mut review = Review::with_items(vec![
+            item(0, "Create directory `/nix`", false),
+            item(1, "Configure remote building", true),
+        ]);
+
+        assert_eq!(review.handle_key(KeyCode::Char('s').into()), None);
+        assert!(review.skipped.is_empty());
+        assert!(review.status.is_some());
+
+        review.handle_key(KeyCode::Down.into());
+        review.handle_key(KeyCode::Down.into());
+        assert_eq!(review.selected, 1);
+        review.handle_key(KeyCode::Char('s').into());
+        assert!(review.skipped.contains(&1));
+        review.handle_key(KeyCode::Char('s').into());
+        assert!(review.skipped.is_empty());
+    }
+
+    #[test]
+    fn approves_or_aborts() {
+        let mut review = Review::with_items(vec![item(0, "Create directory `/nix`", false)]);
+        review.handle_key(KeyCode::Enter.into());
+        assert!(review.expanded.contains(&0));
+        assert_eq!(
+            review.handle_key(KeyCode::Char('a').into()),
+            Some(ReviewOutcome::Approve)
+        );
+        assert_eq!(
+            review.handle_key(KeyCode::Esc.into()),
+            Some(ReviewOutcome::Abort)
+        );
+        assert_eq!(
+            review.handle_key(KeyEvent::new(KeyCode::Char('c'), KeyModifiers::CONTROL)),
+            Some(ReviewOutcome::Abort)
+        );
+    }
+}
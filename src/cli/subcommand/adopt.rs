@@ -0,0 +1,152 @@
+use std::{path::Path, process::ExitCode};
+
+use clap::{ArgAction, Parser};
+use color_eyre::eyre::eyre;
+use owo_colors::OwoColorize;
+
+use super::install::copy_self_to_nix_dir;
+use crate::{
+    adopt::{detect_settings, AdoptError},
+    cli::{
+        ensure_lock, ensure_root,
+        interaction::{self, PromptChoice},
+        output, CommandExecute,
+    },
+    error::HasExpectedErrors,
+    plan::RECEIPT_LOCATION,
+    settings::CommonSettings,
+    BuiltinPlanner, InstallPlan, NixInstallerError,
+};
+
+/**
+Adopt a Nix install which has no receipt, so it can be uninstalled, repaired, and upgraded
+
+The build group, build users, and distribution of the existing install are found on the system, and
+a receipt is written as if `nix-installer` had installed it. Nothing else is changed. The receipt is
+best-effort, anything the other installer did which `nix-installer` wouldn't have isn't recorded.
+*/
+#[derive(Debug, Parser)]
+pub struct Adopt {
+    /// Adopt without requiring explicit user confirmation
+    #[clap(
+        long,
+        env = "NIX_INSTALLER_NO_CONFIRM",
+        action(ArgAction::SetTrue),
+        default_value = "false"
+    )]
+    pub no_confirm: bool,
+
+    /// Provide an explanation of what an uninstall of the adopted Nix would do
+    #[clap(
+        long,
+        env = "NIX_INSTALLER_EXPLAIN",
+        action(ArgAction::SetTrue),
+        default_value = "false"
+    )]
+    pub explain: bool,
+
+    /// The settings to start from, the build users and distribution are found on the system
+    #[clap(flatten)]
+    pub settings: CommonSettings,
+}
+
+#[async_trait::async_trait]
+impl CommandExecute for Adopt {
+    #[tracing::instrument(level = "debug", skip_all, fields())]
+    async fn execute(self) -> eyre::Result<ExitCode> {
+        let Self {
+            no_confirm,
+            explain,
+            settings,
+        } = self;
+
+        ensure_root()?;
+        let _lock = match ensure_lock()? {
+            Ok(lock) => lock,
+            Err(exit_code) => return Ok(exit_code),
+        };
+
+        let plan = match adopt(settings).await {
+            Ok(plan) => plan,
+            Err(err) => {
+                if let Some(expected) = err.expected() {
+                    eprintln!("{}", expected.red());
+                    return Ok(output::error_failure(&err));
+                }
+                return Err(err)?;
+            },
+        };
+
+        output::ensure_no_prompt(no_confirm)?;
+
+        if !no_confirm {
+            let mut currently_explaining = explain;
+            loop {
+                let mut question = plan
+                    .describe_uninstall(currently_explaining)
+                    .await
+                    .map_err(|e| eyre!(e))?;
+                question.push_str(&format!(
+                    "\nThe existing Nix will be recorded in `{RECEIPT_LOCATION}`, the above is what uninstalling it would undo.\n"
+                ));
+                match interaction::prompt(question, PromptChoice::Yes, currently_explaining).await?
+                {
+                    PromptChoice::Yes => break,
+                    PromptChoice::Explain => currently_explaining = true,
+                    PromptChoice::No => {
+                        interaction::clean_exit_with_message("Okay, didn't do anything! Bye!").await
+                    },
+                }
+            }
+        }
+
+        plan.write_receipt().await?;
+        if let Err(err) = copy_self_to_nix_dir().await {
+            tracing::warn!("Could not copy `nix-installer` to `/nix/nix-installer`: {err}");
+        }
+
+        if output::is_json() {
+            output::result(
+                "adopt",
+                true,
+                serde_json::json!({
+                    "receipt": RECEIPT_LOCATION,
+                    "planner": plan.planner.typetag_name(),
+                    "settings": plan.planner.settings()?,
+                }),
+            );
+        } else {
+            println!(
+                "{}\n\nIt can now be managed with `/nix/nix-installer uninstall`, `repair`, and `upgrade`.",
+                "The existing Nix was adopted!".green().bold()
+            );
+        }
+
+        Ok(ExitCode::SUCCESS)
+    }
+}
+
+async fn adopt(settings: CommonSettings) -> Result<InstallPlan, NixInstallerError> {
+    ensure_no_receipt()?;
+    let settings = detect_settings(settings).await?;
+    plan_adoption(settings).await
+}
+
+pub(super) fn ensure_no_receipt() -> Result<(), NixInstallerError> {
+    if Path::new(RECEIPT_LOCATION).exists() {
+        return Err(AdoptError::ReceiptExists(RECEIPT_LOCATION.into()).into());
+    }
+    Ok(())
+}
+
+/// Plan an install with the [detected](detect_settings) `settings`, of the Nix already on the system
+pub(super) async fn plan_adoption(
+    settings: CommonSettings,
+) -> Result<InstallPlan, NixInstallerError> {
+    match BuiltinPlanner::from_common_settings(settings).await? {
+        BuiltinPlanner::Linux(planner) => InstallPlan::adopt(planner).await,
+        BuiltinPlanner::SteamDeck(planner) => InstallPlan::adopt(planner).await,
+        BuiltinPlanner::Ostree(planner) => InstallPlan::adopt(planner).await,
+        BuiltinPlanner::Macos(planner) => InstallPlan::adopt(planner).await,
+    }
+}
@@ -0,0 +1,110 @@
+use std::{path::PathBuf, process::ExitCode};
+
+use clap::{builder::TypedValueParser, ArgAction, Parser};
+
+use super::{install::OnCancel, Install};
+use crate::{cli::CommandExecute, plan::DEFAULT_JOBS, settings::CommonSettings};
+
+/**
+Execute a plan made by `nix-installer plan` exactly as written
+
+The plan is refused if it was made on a different kind of host (architecture, OS, or OS version).
+*/
+#[derive(Debug, Parser)]
+pub struct Apply {
+    /// Run installation without requiring explicit user confirmation
+    #[clap(
+        long,
+        env = "NIX_INSTALLER_NO_CONFIRM",
+        action(ArgAction::SetTrue),
+        default_value = "false"
+    )]
+    pub no_confirm: bool,
+
+    /// Provide an explanation of the changes the installation process will make to your system
+    #[clap(
+        long,
+        env = "NIX_INSTALLER_EXPLAIN",
+        action(ArgAction::SetTrue),
+        default_value = "false"
+    )]
+    pub explain: bool,
+
+    /// Print the exact commands the plan would run, and files it would write, then exit without changing anything
+    #[clap(
+        long,
+        env = "NIX_INSTALLER_DRY_RUN",
+        action(ArgAction::SetTrue),
+        default_value = "false"
+    )]
+    pub dry_run: bool,
+
+    /// Show a unified diff of each existing file the plan will change
+    #[clap(
+        long,
+        env = "NIX_INSTALLER_SHOW_DIFFS",
+        action(ArgAction::SetTrue),
+        default_value = "false"
+    )]
+    pub show_diffs: bool,
+
+    /// How many independent actions to run at once
+    #[clap(
+        long,
+        env = "NIX_INSTALLER_JOBS",
+        default_value_t = DEFAULT_JOBS,
+        value_parser = clap::value_parser!(u16).range(1..).map(usize::from)
+    )]
+    pub jobs: usize,
+
+    /// Run the actions one at a time, in the order they were planned (the same as `--jobs 1`)
+    #[clap(
+        long,
+        env = "NIX_INSTALLER_SERIAL",
+        action(ArgAction::SetTrue),
+        default_value = "false",
+        conflicts_with = "jobs"
+    )]
+    pub serial: bool,
+
+    /// The plan to execute (in JSON format)
+    pub plan: PathBuf,
+}
+
+#[async_trait::async_trait]
+impl CommandExecute for Apply {
+    #[tracing::instrument(level = "debug", skip_all, fields())]
+    async fn execute(self) -> eyre::Result<ExitCode> {
+        let Self {
+            no_confirm,
+            explain,
+            dry_run,
+            show_diffs,
+            jobs,
+            serial,
+            plan,
+        } = self;
+
+        // The settings are unused, everything comes from the plan
+        Install {
+            no_confirm,
+            settings: CommonSettings::default().await?,
+            explain,
+            dry_run,
+            show_diffs,
+            #[cfg(feature = "tui")]
+            review: false,
+            progress: true,
+            jobs,
+            serial,
+            crash_report: None,
+            skip_preflight: false,
+            check: false,
+            on_cancel: OnCancel::default(),
+            plan: Some(plan),
+            planner: None,
+        }
+        .execute()
+        .await
+    }
+}
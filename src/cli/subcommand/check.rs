@@ -0,0 +1,69 @@
+use std::process::ExitCode;
+
+use clap::Parser;
+use owo_colors::OwoColorize;
+
+use crate::{
+    cli::{output, CommandExecute},
+    preflight::preflight,
+    settings::CommonSettings,
+    ErrorClass,
+};
+
+/**
+Check that this host can take an install of Nix, without changing anything
+
+Runs the preflight checks `nix-installer install` runs before it changes anything (disk space,
+kernel features, open file limits, an existing `/nix`, the clock, and the network), and reports
+every problem found at once.
+*/
+#[derive(Debug, Parser)]
+pub struct Check {
+    #[clap(flatten)]
+    pub settings: CommonSettings,
+}
+
+#[async_trait::async_trait]
+impl CommandExecute for Check {
+    #[tracing::instrument(level = "debug", skip_all, fields())]
+    async fn execute(self) -> eyre::Result<ExitCode> {
+        let Self { settings } = self;
+
+        let problems = preflight(&settings).await;
+        // Before the `result`, so it has the failure's class
+        let exit_code = if problems.is_empty() {
+            ExitCode::SUCCESS
+        } else {
+            output::failure(
+                ErrorClass::Preflight,
+                format!("{} preflight check(s) failed", problems.len()),
+                problems
+                    .iter()
+                    .map(|problem| problem.problem.clone())
+                    .collect(),
+            )
+        };
+
+        if output::is_json() {
+            output::result(
+                "check",
+                problems.is_empty(),
+                serde_json::json!({ "problems": problems }),
+            );
+        } else if problems.is_empty() {
+            println!("{}", "No problems found.".green().bold());
+        } else {
+            report(&problems);
+        }
+
+        Ok(exit_code)
+    }
+}
+
+/// Print each problem with its fix, to stderr so it doesn't mix with `--format json` output
+pub(crate) fn report(problems: &[crate::preflight::Problem]) {
+    for problem in problems {
+        eprintln!("{}: {}", problem.check.bold(), problem.problem.red());
+        eprintln!("  Fix: {}", problem.fix);
+    }
+}
@@ -0,0 +1,71 @@
+use std::process::ExitCode;
+
+use clap::{ArgAction, Parser};
+use owo_colors::OwoColorize;
+
+use crate::{
+    cli::{ensure_root, CommandExecute},
+    doctor::diagnose,
+};
+
+/**
+Check an existing Nix install for common problems, and explain how to fix them
+
+Nothing is changed unless `--fix` is passed, which only applies fixes that remove leftovers.
+*/
+#[derive(Debug, Parser)]
+pub struct Doctor {
+    /// Apply the fixes which are safe to make without review
+    #[clap(
+        long,
+        env = "NIX_INSTALLER_DOCTOR_FIX",
+        action(ArgAction::SetTrue),
+        default_value = "false"
+    )]
+    pub fix: bool,
+}
+
+#[async_trait::async_trait]
+impl CommandExecute for Doctor {
+    #[tracing::instrument(level = "debug", skip_all, fields())]
+    async fn execute(self) -> eyre::Result<ExitCode> {
+        let Self { fix } = self;
+
+        let findings = diagnose().await;
+        if findings.is_empty() {
+            println!("{}", "No problems found.".green().bold());
+            return Ok(ExitCode::SUCCESS);
+        }
+
+        if fix && findings.iter().any(|finding| finding.has_safe_fix()) {
+            ensure_root()?;
+        }
+
+        let mut unresolved = 0;
+        for finding in &findings {
+            println!("{}: {}", finding.check.bold(), finding.problem.red());
+            if fix && finding.has_safe_fix() {
+                match finding.apply_safe_fix().await {
+                    Ok(()) => {
+                        println!("  {}", format!("Fixed: {}", finding.fix).green());
+                        continue;
+                    },
+                    Err(err) => println!("  {}", format!("Fixing failed: {err}").red()),
+                }
+            }
+            unresolved += 1;
+            let maybe_safe = if !fix && finding.has_safe_fix() {
+                " (`--fix` can do this)"
+            } else {
+                ""
+            };
+            println!("  Fix: {}{maybe_safe}", finding.fix);
+        }
+
+        if unresolved == 0 {
+            Ok(ExitCode::SUCCESS)
+        } else {
+            Ok(ExitCode::FAILURE)
+        }
+    }
+}
@@ -0,0 +1,75 @@
+use std::{path::PathBuf, process::ExitCode};
+
+use clap::{ArgAction, Parser};
+use eyre::WrapErr;
+
+use super::uninstall::read_receipt;
+use crate::{
+    cli::{output, CommandExecute},
+    plan::RECEIPT_LOCATION,
+    InstallPlan,
+};
+
+/**
+Describe what the install recorded in a receipt did, and what uninstalling it would do
+
+The same descriptions `nix-installer install` and `nix-installer uninstall` prompt with, for
+auditing a machine without reading the receipt's JSON. Nothing is changed.
+*/
+#[derive(Debug, Parser)]
+pub struct Explain {
+    /// Include the explanation of each action
+    #[clap(
+        long,
+        env = "NIX_INSTALLER_EXPLAIN",
+        action(ArgAction::SetTrue),
+        default_value = "false"
+    )]
+    pub explain: bool,
+
+    /// The receipt (or journal, of an install which didn't finish) to describe
+    #[clap(default_value = RECEIPT_LOCATION)]
+    pub receipt: PathBuf,
+}
+
+#[async_trait::async_trait]
+impl CommandExecute for Explain {
+    #[tracing::instrument(level = "debug", skip_all, fields())]
+    async fn execute(self) -> eyre::Result<ExitCode> {
+        let Self { explain, receipt } = self;
+
+        let (receipt, receipt_string) = read_receipt(receipt).await?;
+        let plan = InstallPlan::from_receipt_lossy(&receipt_string)
+            .wrap_err_with(|| format!("Unable to parse the receipt `{}`", receipt.display()))?;
+
+        if output::is_json() {
+            let actions = plan
+                .actions
+                .iter()
+                .map(|action| {
+                    serde_json::json!({
+                        "action": action.inner_typetag_name(),
+                        "state": action.state,
+                        "execute": action.action.execute_description(),
+                        "revert": action.describe_revert(),
+                    })
+                })
+                .collect::<Vec<_>>();
+            output::result(
+                "explain",
+                true,
+                serde_json::json!({
+                    "receipt": receipt,
+                    "version": plan.version.to_string(),
+                    "planner": plan.planner.typetag_name(),
+                    "settings": plan.planner.configured_settings().await?,
+                    "actions": actions,
+                }),
+            );
+            return Ok(ExitCode::SUCCESS);
+        }
+
+        print!("{}", plan.describe_receipt(explain).await?);
+        Ok(ExitCode::SUCCESS)
+    }
+}
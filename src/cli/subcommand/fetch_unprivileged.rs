@@ -0,0 +1,37 @@
+use std::process::ExitCode;
+
+use clap::Parser;
+use eyre::WrapErr;
+use tokio::io::AsyncReadExt;
+
+use crate::{cli::CommandExecute, unprivileged::serve};
+
+/**
+Fetch and unpack a Nix package for a `nix-installer` running as root
+
+Run by `nix-installer` itself as an unprivileged user, see `--no-privilege-separation`.
+*/
+#[derive(Debug, Parser)]
+#[clap(hide = true)]
+pub struct FetchUnprivileged {}
+
+#[async_trait::async_trait]
+impl CommandExecute for FetchUnprivileged {
+    #[tracing::instrument(level = "debug", skip_all, fields())]
+    async fn execute(self) -> eyre::Result<ExitCode> {
+        let mut request = String::new();
+        tokio::io::stdin()
+            .read_to_string(&mut request)
+            .await
+            .wrap_err("Reading the request from stdin")?;
+
+        // The error was already sent to the `nix-installer` which asked
+        match serve(&request).await {
+            Ok(()) => Ok(ExitCode::SUCCESS),
+            Err(err) => {
+                tracing::debug!(%err, "Serving the request failed");
+                Ok(ExitCode::FAILURE)
+            },
+        }
+    }
+}
@@ -1,28 +1,36 @@
 use std::{
+    collections::HashMap,
     os::unix::prelude::PermissionsExt,
     path::{Path, PathBuf},
     process::ExitCode,
 };
 
+use super::check::report as report_problems;
 use crate::{
-    action::ActionState,
+    action::{base::CreateSnapshots, Action, ActionState},
     cli::{
-        ensure_root,
+        crash_report, elevate_per_phase, ensure_lock, ensure_root,
         interaction::{self, PromptChoice},
-        signal_channel, CommandExecute,
+        is_root, output,
+        progress::ProgressReport,
+        run_elevated, signal_channel, CommandExecute,
     },
     error::HasExpectedErrors,
-    plan::RECEIPT_LOCATION,
+    manifest::MANIFEST_LOCATION,
+    plan::{DEFAULT_JOBS, JOURNAL_LOCATION, RECEIPT_LOCATION},
     planner::Planner,
-    settings::CommonSettings,
-    BuiltinPlanner, InstallPlan, NixInstallerError,
+    preflight::preflight,
+    settings::{self, CommonSettings},
+    user_state::{self, USER_STATE_LOCATION},
+    BuiltinPlanner, ErrorClass, InstallPlan, NixInstallerError,
 };
-use clap::{ArgAction, Parser};
+use clap::{builder::TypedValueParser, ArgAction, Parser};
 use color_eyre::{
     eyre::{eyre, WrapErr},
     Section,
 };
 use owo_colors::OwoColorize;
+use tokio::io::AsyncWriteExt;
 
 const EXISTING_INCOMPATIBLE_PLAN_GUIDANCE: &str = "\
     If you are trying to upgrade Nix, try running `sudo -i nix upgrade-nix` instead.\n\
@@ -63,6 +71,104 @@ pub struct Install {
     )]
     pub explain: bool,
 
+    /// Print the exact commands the install would run, and files it would write, then exit without changing anything
+    #[clap(
+        long,
+        env = "NIX_INSTALLER_DRY_RUN",
+        action(ArgAction::SetTrue),
+        default_value = "false",
+        global = true
+    )]
+    pub dry_run: bool,
+
+    /// Show a unified diff of each existing file the install will change (like `/etc/nix/nix.conf` or shell profiles)
+    #[clap(
+        long,
+        env = "NIX_INSTALLER_SHOW_DIFFS",
+        action(ArgAction::SetTrue),
+        default_value = "false",
+        global = true
+    )]
+    pub show_diffs: bool,
+
+    /// Review the plan in a terminal UI, expanding each action's description and diffs, before approving (or skipping optional actions) or aborting
+    #[cfg(feature = "tui")]
+    #[clap(
+        long,
+        env = "NIX_INSTALLER_REVIEW",
+        action(ArgAction::SetTrue),
+        default_value = "false",
+        conflicts_with_all = ["no_confirm", "dry_run"],
+        global = true
+    )]
+    pub review: bool,
+
+    /// Draw progress bars of the install (and of downloading and unpacking Nix) when run in a terminal
+    #[clap(
+        long = "no-progress",
+        env = "NIX_INSTALLER_PROGRESS",
+        action(ArgAction::SetFalse),
+        default_value = "true",
+        global = true
+    )]
+    pub progress: bool,
+
+    /// How many independent actions to run at once
+    #[clap(
+        long,
+        env = "NIX_INSTALLER_JOBS",
+        default_value_t = DEFAULT_JOBS,
+        value_parser = clap::value_parser!(u16).range(1..).map(usize::from),
+        global = true
+    )]
+    pub jobs: usize,
+
+    /// Run the actions one at a time, in the order they were planned (the same as `--jobs 1`)
+    #[clap(
+        long,
+        env = "NIX_INSTALLER_SERIAL",
+        action(ArgAction::SetTrue),
+        default_value = "false",
+        conflicts_with = "jobs",
+        global = true
+    )]
+    pub serial: bool,
+
+    /// Write a crash report (a tarball of the plan, receipt, logs, and system facts, with secrets redacted) here if the install fails, instead of offering to
+    #[clap(long, env = "NIX_INSTALLER_CRASH_REPORT", global = true)]
+    pub crash_report: Option<PathBuf>,
+
+    /// Don't stop on the problems found by the preflight checks (see `nix-installer check`) before changing anything
+    #[clap(
+        long,
+        env = "NIX_INSTALLER_SKIP_PREFLIGHT",
+        action(ArgAction::SetTrue),
+        default_value = "false",
+        global = true
+    )]
+    pub skip_preflight: bool,
+
+    /// Change nothing, exit `0` if the install (per its receipt) already matches these settings, or `2` listing the changes an install would make
+    #[clap(
+        long,
+        env = "NIX_INSTALLER_CHECK",
+        action(ArgAction::SetTrue),
+        default_value = "false",
+        conflicts_with_all = ["dry_run", "explain"],
+        global = true
+    )]
+    pub check: bool,
+
+    /// What to do when interrupted (by `SIGINT` or `SIGTERM`) once the install has started: `revert` what was done, or `keep` it to finish with `nix-installer resume`
+    #[clap(
+        long,
+        env = "NIX_INSTALLER_ON_CANCEL",
+        value_enum,
+        default_value_t = OnCancel::Revert,
+        global = true
+    )]
+    pub on_cancel: OnCancel,
+
     /// A path to a non-default installer plan
     #[clap(env = "NIX_INSTALLER_PLAN")]
     pub plan: Option<PathBuf>,
@@ -81,9 +187,46 @@ impl CommandExecute for Install {
             planner,
             settings,
             explain,
+            dry_run,
+            show_diffs,
+            #[cfg(feature = "tui")]
+            review,
+            progress,
+            jobs,
+            serial,
+            crash_report,
+            skip_preflight,
+            check,
+            on_cancel,
         } = self;
 
-        ensure_root()?;
+        // Read only, so it needs neither `root` nor the lock
+        if check {
+            return check_changes(planner, plan, settings).await;
+        }
+
+        // Plans don't keep the proxy's password, so it is kept aside for executing this one
+        if let Some(proxy) = &settings.proxy {
+            settings::provide_proxy_password(proxy);
+        }
+
+        // With `--elevate-with pkexec`, only executing the plan is done as root, by another `nix-installer`
+        let per_phase = elevate_per_phase();
+        let (planner, plan, no_confirm) = match elevated_plan() {
+            Some(elevated_plan) => (None, Some(elevated_plan), true),
+            None => (planner, plan, no_confirm),
+        };
+        if !per_phase {
+            ensure_root()?;
+        }
+        let _lock = if per_phase {
+            None
+        } else {
+            match ensure_lock()? {
+                Ok(lock) => Some(lock),
+                Err(exit_code) => return Ok(exit_code),
+            }
+        };
 
         let existing_receipt: Option<InstallPlan> = match Path::new(RECEIPT_LOCATION).exists() {
             true => {
@@ -92,7 +235,7 @@ impl CommandExecute for Install {
                     .await
                     .wrap_err("Reading plan")?;
                 Some(
-                    serde_json::from_str(&install_plan_string).wrap_err_with(|| {
+                    InstallPlan::from_receipt(&install_plan_string).wrap_err_with(|| {
                         format!("Unable to parse existing receipt `{RECEIPT_LOCATION}`, it may be from an incompatible version of `nix-installer`. Try running `/nix/nix-installer uninstall`, then installing again.")
                     })?,
                 )
@@ -100,6 +243,15 @@ impl CommandExecute for Install {
             false => None,
         };
 
+        if existing_receipt.is_none() && plan.is_none() && Path::new(JOURNAL_LOCATION).exists() {
+            eprintln!("{}", format!("Found an interrupted install in `{JOURNAL_LOCATION}`, finish it with `nix-installer resume`, or delete `{JOURNAL_LOCATION}` to start over").red());
+            return Ok(output::failure(
+                ErrorClass::ConflictingInstall,
+                format!("Found an interrupted install in `{JOURNAL_LOCATION}`"),
+                vec![],
+            ));
+        }
+
         let uninstall_command = match Path::new("/nix/nix-installer").exists() {
             true => "/nix/nix-installer uninstall".into(),
             false => format!("curl --proto '=https' --tlsv1.2 -sSf -L https://install.determinate.systems/nix/tag/v{} | sh -s -- uninstall", env!("CARGO_PKG_VERSION")),
@@ -121,15 +273,15 @@ impl CommandExecute for Install {
                                     {EXISTING_INCOMPATIBLE_PLAN_GUIDANCE}\n\
                                 ").red()
                             );
-                            return Ok(ExitCode::FAILURE)
+                            return Ok(conflicting_receipt())
                         }
                         if existing_receipt.planner.typetag_name() != chosen_planner.typetag_name() {
                             eprintln!("{}", format!("Found existing plan in `{RECEIPT_LOCATION}` which used a different planner, try uninstalling the existing install with `{uninstall_command}`").red());
-                            return Ok(ExitCode::FAILURE)
+                            return Ok(conflicting_receipt())
                         }
                         if existing_receipt.planner.settings().map_err(|e| eyre!(e))? != chosen_planner.settings().map_err(|e| eyre!(e))? {
                             eprintln!("{}", format!("Found existing plan in `{RECEIPT_LOCATION}` which used different planner settings, try uninstalling the existing install with `{uninstall_command}`").red());
-                            return Ok(ExitCode::FAILURE)
+                            return Ok(conflicting_receipt())
                         }
                         eprintln!("{}", format!("Found existing plan in `{RECEIPT_LOCATION}`, with the same settings, already completed. Try uninstalling (`{uninstall_command}`) and reinstalling if Nix isn't working").red());
                         return Ok(ExitCode::SUCCESS)
@@ -141,7 +293,7 @@ impl CommandExecute for Install {
                             Err(err) => {
                                 if let Some(expected) = err.expected() {
                                     eprintln!("{}", expected.red());
-                                    return Ok(ExitCode::FAILURE);
+                                    return Ok(output::error_failure(&err));
                                 }
                                 return Err(err)?;
                             }
@@ -153,7 +305,12 @@ impl CommandExecute for Install {
                 let install_plan_string = tokio::fs::read_to_string(&plan_path)
                 .await
                 .wrap_err("Reading plan")?;
-                serde_json::from_str(&install_plan_string)?
+                let install_plan = InstallPlan::from_receipt(&install_plan_string)?;
+                if let Err(err) = install_plan.check_host() {
+                    eprintln!("{}", err.red());
+                    return Ok(output::failure(ErrorClass::Preflight, err, vec![]));
+                }
+                install_plan
             },
             (None, None) => {
                 let builtin_planner = BuiltinPlanner::from_common_settings(settings.clone())
@@ -172,15 +329,15 @@ impl CommandExecute for Install {
                                     {EXISTING_INCOMPATIBLE_PLAN_GUIDANCE}\n\
                                 ").red()
                             );
-                            return Ok(ExitCode::FAILURE)
+                            return Ok(conflicting_receipt())
                         }
                         if existing_receipt.planner.typetag_name() != builtin_planner.typetag_name() {
                             eprintln!("{}", format!("Found existing plan in `{RECEIPT_LOCATION}` which used a different planner, try uninstalling the existing install with `{uninstall_command}`").red());
-                            return Ok(ExitCode::FAILURE)
+                            return Ok(conflicting_receipt())
                         }
                         if existing_receipt.planner.settings().map_err(|e| eyre!(e))? != builtin_planner.settings().map_err(|e| eyre!(e))? {
                             eprintln!("{}", format!("Found existing plan in `{RECEIPT_LOCATION}` which used different planner settings, try uninstalling the existing install with `{uninstall_command}`").red());
-                            return Ok(ExitCode::FAILURE)
+                            return Ok(conflicting_receipt())
                         }
                         if existing_receipt.actions.iter().all(|v| v.state == ActionState::Completed) {
                             eprintln!("{}", format!("Found existing plan in `{RECEIPT_LOCATION}`, with the same settings, already completed. Try uninstalling (`{uninstall_command}`) and reinstalling if Nix isn't working").yellow());
@@ -195,7 +352,7 @@ impl CommandExecute for Install {
                             Err(err) => {
                                 if let Some(expected) = err.expected() {
                                     eprintln!("{}", expected.red());
-                                    return Ok(ExitCode::FAILURE);
+                                    return Ok(output::error_failure(&err));
                                 }
                                 return Err(err)?;
                             }
@@ -209,23 +366,102 @@ impl CommandExecute for Install {
         if let Err(err) = install_plan.pre_install_check().await {
             if let Some(expected) = err.expected() {
                 eprintln!("{}", expected.red());
-                return Ok(ExitCode::FAILURE);
+                return Ok(output::error_failure(&err));
             }
             Err(err)?
         }
 
-        if !no_confirm {
+        let diffs = if show_diffs {
+            install_plan.describe_diffs()
+        } else {
+            String::new()
+        };
+
+        if dry_run {
+            if output::is_json() {
+                let actions = install_plan
+                    .actions
+                    .iter()
+                    .map(|action| {
+                        serde_json::json!({
+                            "synopsis": action.tracing_synopsis(),
+                            "steps": action
+                                .dry_run()
+                                .iter()
+                                .map(ToString::to_string)
+                                .collect::<Vec<_>>(),
+                        })
+                    })
+                    .collect::<Vec<_>>();
+                output::result(
+                    "install",
+                    true,
+                    serde_json::json!({ "dry_run": actions, "diffs": show_diffs.then_some(&diffs) }),
+                );
+                return Ok(ExitCode::SUCCESS);
+            }
+            println!("{}", install_plan.describe_dry_run());
+            if !diffs.is_empty() {
+                println!("{diffs}");
+            }
+            return Ok(ExitCode::SUCCESS);
+        }
+
+        // Only a fresh install, resuming one finds its own partial `/nix`
+        let fresh = install_plan
+            .actions
+            .iter()
+            .all(|action| action.state == ActionState::Uncompleted);
+        if fresh && !skip_preflight {
+            let problems = preflight(&settings).await;
+            if !problems.is_empty() {
+                report_problems(&problems);
+                eprintln!(
+                    "{}",
+                    "Fix the problems above, or pass `--skip-preflight` to install anyway".red()
+                );
+                return Ok(output::failure(
+                    ErrorClass::Preflight,
+                    format!("{} preflight check(s) failed", problems.len()),
+                    problems
+                        .iter()
+                        .map(|problem| problem.problem.clone())
+                        .collect(),
+                ));
+            }
+        }
+
+        output::ensure_no_prompt(no_confirm)?;
+
+        if no_confirm && !diffs.is_empty() && !output::is_json() {
+            println!("{diffs}");
+        }
+
+        #[cfg(feature = "tui")]
+        let reviewed = if review {
+            if crate::cli::review::review(&mut install_plan).await?
+                == crate::cli::review::ReviewOutcome::Abort
+            {
+                interaction::clean_exit_with_message("Okay, didn't do anything! Bye!").await
+            }
+            true
+        } else {
+            false
+        };
+        #[cfg(not(feature = "tui"))]
+        let reviewed = false;
+
+        if !no_confirm && !reviewed {
             let mut currently_explaining = explain;
             loop {
-                match interaction::prompt(
-                    install_plan
-                        .describe_install(currently_explaining)
-                        .await
-                        .map_err(|e| eyre!(e))?,
-                    PromptChoice::Yes,
-                    currently_explaining,
-                )
-                .await?
+                let mut question = install_plan
+                    .describe_install(currently_explaining)
+                    .await
+                    .map_err(|e| eyre!(e))?;
+                if !diffs.is_empty() {
+                    question.push_str(&format!("\n{diffs}\n"));
+                }
+                match interaction::prompt(question, PromptChoice::Yes, currently_explaining).await?
                 {
                     PromptChoice::Yes => break,
                     PromptChoice::Explain => currently_explaining = true,
@@ -236,14 +472,32 @@ impl CommandExecute for Install {
             }
         }
 
+        if per_phase {
+            return run_elevated_install(&install_plan).await;
+        }
+
         let (tx, rx1) = signal_channel().await?;
 
-        match install_plan.install(rx1).await {
+        let jobs = if serial { 1 } else { jobs };
+        let progress_report = (progress || output::is_json())
+            .then(|| ProgressReport::start(install_plan.actions.len()));
+        let res = install_plan.install_with_jobs(rx1, jobs).await;
+        if let Some(progress_report) = progress_report {
+            progress_report.finish().await;
+        }
+        match res {
+            Err(NixInstallerError::Cancelled) => {
+                return cancelled(&mut install_plan, on_cancel, &tx).await;
+            },
             Err(err) => {
                 // Attempt to copy self to the store if possible, but since the install failed, this might not work, that's ok.
                 copy_self_to_nix_dir().await.ok();
+                crash_report::offer(&install_plan, &err, crash_report.as_deref(), no_confirm)
+                    .await?;
 
                 if !no_confirm {
+                    let failure_message = err.to_string();
+                    let failure_causes = output::causes(&err);
                     let mut was_expected = false;
                     if let Some(expected) = err.expected() {
                         was_expected = true;
@@ -286,12 +540,15 @@ impl CommandExecute for Install {
                             for err in errs {
                                 report = report.error(err);
                             }
+                            if took_snapshots(&install_plan) {
+                                report = report.suggestion("Restore what the install changed from the snapshots taken before it with `nix-installer rollback-to-snapshot`");
+                            }
                             return Err(report)?;
                         },
                         Err(err) => {
                             if let Some(expected) = err.expected() {
                                 eprintln!("{}", expected.red());
-                                return Ok(ExitCode::FAILURE);
+                                return Ok(output::error_failure(&err));
                             }
                             return Err(err)?;
                         },
@@ -303,12 +560,17 @@ impl CommandExecute for Install {
                                 message =
                                     "Partial Nix install was uninstalled successfully!".bold(),
                             );
+                            return Ok(output::failure(
+                                ErrorClass::RolledBack,
+                                failure_message,
+                                failure_causes,
+                            ));
                         },
                     }
                 } else {
                     if let Some(expected) = err.expected() {
                         eprintln!("{}", expected.red());
-                        return Ok(ExitCode::FAILURE);
+                        return Ok(output::error_failure(&err));
                     }
 
                     let error = eyre!(err).wrap_err("Install failure");
@@ -319,6 +581,28 @@ impl CommandExecute for Install {
                 copy_self_to_nix_dir()
                     .await
                     .wrap_err("Copying `nix-installer` to `/nix/nix-installer`")?;
+                match user_state::restore().await {
+                    Ok(Some(restored)) => tracing::info!(
+                        "Restored the users' profiles kept by the last uninstall ({} links)",
+                        restored.links
+                    ),
+                    Ok(None) => (),
+                    Err(err) => tracing::warn!(
+                        "Could not restore the users' profiles kept in `{USER_STATE_LOCATION}`: {:?}",
+                        err
+                    ),
+                }
+                if output::is_json() {
+                    output::result(
+                        "install",
+                        true,
+                        serde_json::json!({
+                            "diffs": show_diffs.then_some(&diffs),
+                            "manifest": MANIFEST_LOCATION,
+                        }),
+                    );
+                    return Ok(ExitCode::SUCCESS);
+                }
                 println!(
                     "\
                     {success}\n\
@@ -339,10 +623,266 @@ impl CommandExecute for Install {
     }
 }
 
+/// If the snapshots of `install_plan` (see `--snapshot`) were taken, for `nix-installer rollback-to-snapshot`
+fn took_snapshots(install_plan: &InstallPlan) -> bool {
+    install_plan.actions.iter().any(|action| {
+        action.inner_typetag_name() == CreateSnapshots::action_tag().0
+            && action.state == ActionState::Completed
+    })
+}
+
+/// What to do with a partial install once it is interrupted, see `--on-cancel`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum OnCancel {
+    /// Revert the actions which were completed
+    #[default]
+    Revert,
+    /// Keep the actions which were completed, and the journal, to finish with `nix-installer resume`
+    Keep,
+}
+
+/// The install was interrupted, the actions which were running have finished, and the journal records what was done
+async fn cancelled(
+    install_plan: &mut InstallPlan,
+    on_cancel: OnCancel,
+    signals: &tokio::sync::broadcast::Sender<()>,
+) -> eyre::Result<ExitCode> {
+    let completed = install_plan
+        .actions
+        .iter()
+        .filter(|action| action.state == ActionState::Completed)
+        .count();
+    let total = install_plan.actions.len();
+
+    if on_cancel == OnCancel::Keep {
+        eprintln!(
+            "{}",
+            format!("Cancelled after {completed} of {total} steps, finish the install with `nix-installer resume`, or revert it with `nix-installer uninstall {JOURNAL_LOCATION}`").red()
+        );
+        return Ok(output::failure(
+            ErrorClass::Cancelled,
+            format!("Cancelled by user, the partial install is kept in `{JOURNAL_LOCATION}`"),
+            vec![],
+        ));
+    }
+
+    eprintln!(
+        "{}",
+        format!("Cancelled after {completed} of {total} steps, reverting them...").red()
+    );
+    // Interrupting again stops reverting, leaving the receipt to finish it with `nix-installer uninstall`
+    match install_plan.uninstall(signals.subscribe()).await {
+        Ok(()) => {
+            eprintln!(
+                "{}",
+                "Cancelled, the partial Nix install was reverted".bold()
+            );
+            Ok(output::failure(
+                ErrorClass::Cancelled,
+                "Cancelled by user, the partial install was reverted",
+                vec![],
+            ))
+        },
+        Err(NixInstallerError::Cancelled) => {
+            copy_self_to_nix_dir().await.ok();
+            eprintln!(
+                "{}",
+                format!("Reverting was cancelled, finish it with `/nix/nix-installer uninstall {RECEIPT_LOCATION}`").red()
+            );
+            Ok(output::failure(
+                ErrorClass::Cancelled,
+                format!("Cancelled by user while reverting, the partial install is recorded in `{RECEIPT_LOCATION}`"),
+                vec![],
+            ))
+        },
+        Err(err) => Err(eyre!(err).wrap_err("Reverting the cancelled install")),
+    }
+}
+
+/// The environment variable telling an elevated `nix-installer` which plan was already made and confirmed
+const ELEVATED_PLAN_ENV: &str = "NIX_INSTALLER_ELEVATED_PLAN";
+
+/// The plan made by the unprivileged `nix-installer` which ran this one with `pkexec`, see [`run_elevated_install`]
+fn elevated_plan() -> Option<PathBuf> {
+    std::env::var_os(ELEVATED_PLAN_ENV)
+        .filter(|_| is_root())
+        .map(PathBuf::from)
+}
+
+/// Write the confirmed `install_plan` where only this user can read it, and have `nix-installer` execute it as `root`
+async fn run_elevated_install(install_plan: &InstallPlan) -> eyre::Result<ExitCode> {
+    let plan_path =
+        std::env::temp_dir().join(format!("nix-installer-plan-{}.json", std::process::id()));
+    let json = serde_json::to_string_pretty(install_plan)?;
+    let mut file = tokio::fs::OpenOptions::new()
+        .create_new(true)
+        .write(true)
+        .mode(0o600)
+        .open(&plan_path)
+        .await
+        .wrap_err_with(|| format!("Creating `{}`", plan_path.display()))?;
+    file.write_all(format!("{json}\n").as_bytes())
+        .await
+        .wrap_err_with(|| format!("Writing `{}`", plan_path.display()))?;
+    drop(file);
+
+    let res = run_elevated(&[(ELEVATED_PLAN_ENV, &plan_path.display().to_string())]).await;
+    if let Err(err) = tokio::fs::remove_file(&plan_path).await {
+        tracing::warn!(%err, "Could not remove `{}`", plan_path.display());
+    }
+    res
+}
+
+/// The exit code of `--check` when an install would change something, like Ansible's `changed`
+const CHANGES_PENDING: u8 = 2;
+
+/// `--check`: report what an install would change, without changing anything
+async fn check_changes(
+    planner: Option<BuiltinPlanner>,
+    plan: Option<PathBuf>,
+    settings: CommonSettings,
+) -> eyre::Result<ExitCode> {
+    let desired: Box<dyn Planner> = match (planner, plan) {
+        (Some(planner), None) => planner.boxed(),
+        (None, Some(plan_path)) => {
+            let install_plan_string = tokio::fs::read_to_string(&plan_path)
+                .await
+                .wrap_err("Reading plan")?;
+            InstallPlan::from_receipt(&install_plan_string)?.planner
+        },
+        (None, None) => BuiltinPlanner::from_common_settings(settings)
+            .await
+            .map_err(|e| eyre!(e))?
+            .boxed(),
+        (Some(_), Some(_)) => return Err(eyre!("`--plan` conflicts with passing a planner, a planner creates plans, so passing an existing plan doesn't make sense")),
+    };
+
+    let mut changes = vec![];
+    match tokio::fs::read_to_string(RECEIPT_LOCATION).await {
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+            changes.push("Install Nix".to_string());
+        },
+        Err(err) => Err(err).wrap_err_with(|| format!("Reading `{RECEIPT_LOCATION}`"))?,
+        Ok(receipt_string) => {
+            let receipt = InstallPlan::from_receipt(&receipt_string).wrap_err_with(|| {
+                format!("Unable to parse existing receipt `{RECEIPT_LOCATION}`, it may be from an incompatible version of `nix-installer`")
+            })?;
+            if let Err(err) = receipt.check_compatible() {
+                changes.push(format!("Reinstall Nix, the install is incompatible: {err}"));
+            } else if receipt.planner.typetag_name() != desired.typetag_name() {
+                changes.push(format!(
+                    "Reinstall Nix with the `{}` planner, it was installed with `{}`",
+                    desired.typetag_name(),
+                    receipt.planner.typetag_name()
+                ));
+            } else {
+                changes.extend(settings_changes(
+                    &receipt.planner.settings().map_err(|e| eyre!(e))?,
+                    &desired.settings().map_err(|e| eyre!(e))?,
+                ));
+                if receipt
+                    .actions
+                    .iter()
+                    .any(|action| action.state != ActionState::Completed)
+                {
+                    changes.push("Finish the incomplete install".to_string());
+                }
+                for drifted in crate::drift::detect(&receipt).await {
+                    for drift in drifted.drift {
+                        changes.push(format!("{}: {drift}", drifted.action));
+                    }
+                }
+            }
+        },
+    }
+
+    if output::is_json() {
+        output::result(
+            "install",
+            true,
+            serde_json::json!({ "check": { "changed": !changes.is_empty(), "changes": changes } }),
+        );
+    } else if changes.is_empty() {
+        println!(
+            "{}",
+            "No changes, the install matches these settings."
+                .green()
+                .bold()
+        );
+    } else {
+        println!("{}", "Changes pending:".yellow().bold());
+        for change in &changes {
+            println!("* {change}");
+        }
+    }
+
+    if changes.is_empty() {
+        Ok(ExitCode::SUCCESS)
+    } else {
+        Ok(ExitCode::from(CHANGES_PENDING))
+    }
+}
+
+/// The settings which differ between the `receipt`'s planner and the `desired` one
+fn settings_changes(
+    receipt: &HashMap<String, serde_json::Value>,
+    desired: &HashMap<String, serde_json::Value>,
+) -> Vec<String> {
+    let mut keys = receipt.keys().chain(desired.keys()).collect::<Vec<_>>();
+    keys.sort();
+    keys.dedup();
+    keys.into_iter()
+        .filter_map(|key| {
+            let (installed, wanted) = (
+                receipt.get(key).unwrap_or(&serde_json::Value::Null),
+                desired.get(key).unwrap_or(&serde_json::Value::Null),
+            );
+            (installed != wanted)
+                .then(|| format!("Change `{key}` from `{installed}` to `{wanted}`"))
+        })
+        .collect()
+}
+
+/// The exit code of an install refused because of the existing install in the receipt
+fn conflicting_receipt() -> ExitCode {
+    output::failure(
+        ErrorClass::ConflictingInstall,
+        format!("Found an existing install in `{RECEIPT_LOCATION}` which conflicts with this one"),
+        vec![],
+    )
+}
+
 #[tracing::instrument(level = "debug")]
-async fn copy_self_to_nix_dir() -> Result<(), std::io::Error> {
+pub(crate) async fn copy_self_to_nix_dir() -> Result<(), std::io::Error> {
     let path = std::env::current_exe()?;
     tokio::fs::copy(path, "/nix/nix-installer").await?;
     tokio::fs::set_permissions("/nix/nix-installer", PermissionsExt::from_mode(0o0755)).await?;
     Ok(())
 }
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use super::settings_changes;
+
+    #[test]
+    fn lists_changed_settings() {
+        let receipt = HashMap::from([
+            ("modify_profile".to_string(), serde_json::json!(true)),
+            ("nix_build_user_count".to_string(), serde_json::json!(32)),
+        ]);
+        let mut desired = receipt.clone();
+        assert!(settings_changes(&receipt, &desired).is_empty());
+
+        desired.insert("nix_build_user_count".into(), serde_json::json!(8));
+        desired.insert("plugins".into(), serde_json::json!([]));
+        assert_eq!(
+            settings_changes(&receipt, &desired),
+            vec![
+                "Change `nix_build_user_count` from `32` to `8`".to_string(),
+                "Change `plugins` from `null` to `[]`".to_string(),
+            ]
+        );
+    }
+}
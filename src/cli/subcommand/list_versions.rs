@@ -0,0 +1,51 @@
+use std::{path::PathBuf, process::ExitCode};
+
+use clap::Parser;
+use eyre::WrapErr;
+use url::Url;
+
+use crate::{cli::CommandExecute, releases::list_versions, settings::Distribution};
+
+/**
+List the Nix versions which can be installed with `--nix-version`
+
+Versions are fetched from the releases of the chosen distribution, oldest first.
+*/
+#[derive(Debug, Parser)]
+pub struct ListVersions {
+    /// Which distribution of Nix to list the releases of
+    #[clap(
+        long,
+        env = "NIX_INSTALLER_DISTRIBUTION",
+        default_value_t = Distribution::default()
+    )]
+    pub distribution: Distribution,
+    /// The proxy to use (if any); valid proxy bases are `https://$URL`, `http://$URL`, `socks5://$URL`, and `socks5h://$URL`
+    #[clap(long, env = "NIX_INSTALLER_PROXY")]
+    pub proxy: Option<Url>,
+    /// An SSL cert to use (if any)
+    #[clap(long, env = "NIX_INSTALLER_SSL_CERT_FILE")]
+    pub ssl_cert_file: Option<PathBuf>,
+}
+
+#[async_trait::async_trait]
+impl CommandExecute for ListVersions {
+    #[tracing::instrument(level = "debug", skip_all, fields())]
+    async fn execute(self) -> eyre::Result<ExitCode> {
+        let Self {
+            distribution,
+            proxy,
+            ssl_cert_file,
+        } = self;
+
+        let versions = list_versions(distribution, proxy.as_ref(), ssl_cert_file.as_deref())
+            .await
+            .wrap_err_with(|| format!("Listing {distribution} releases"))?;
+
+        for version in versions {
+            println!("{version}");
+        }
+
+        Ok(ExitCode::SUCCESS)
+    }
+}
@@ -0,0 +1,226 @@
+use std::process::ExitCode;
+
+use clap::{ArgAction, Parser};
+use eyre::WrapErr;
+use owo_colors::OwoColorize;
+
+use super::{
+    adopt::{ensure_no_receipt, plan_adoption},
+    install::copy_self_to_nix_dir,
+};
+use crate::{
+    action::{
+        base::{FetchAndUnpackNix, MoveUnpackedNix, SetupDefaultProfile},
+        common::{ConfigureShellProfile, ConfigureUpstreamInitService},
+        Action, ActionTag,
+    },
+    adopt::detect_settings,
+    cli::{
+        ensure_lock, ensure_root,
+        interaction::{self, PromptChoice},
+        output, CommandExecute,
+    },
+    error::HasExpectedErrors,
+    packaged_nix::PackagedNix,
+    plan::RECEIPT_LOCATION,
+    planner::ShellProfileLocations,
+    settings::CommonSettings,
+    upstream_install::{UpstreamInstall, UpstreamInstallError},
+    NixInstallerError,
+};
+
+/**
+Migrate Nix installed by the upstream install script, or a distribution's package, to be managed by `nix-installer`
+
+The shell profiles the script changed are restored from their `.backup-before-nix` backups, and its
+Nix daemon services are replaced with those `nix-installer` places. A packaged Nix (like Debian's
+`nix-bin`) has its daemon stopped and disabled, and Nix is placed in the default profile, keeping
+the store. Then the install is adopted (see `nix-installer adopt`), so it can be uninstalled,
+repaired, and upgraded.
+*/
+#[derive(Debug, Parser)]
+pub struct Migrate {
+    /// Migrate without requiring explicit user confirmation
+    #[clap(
+        long,
+        env = "NIX_INSTALLER_NO_CONFIRM",
+        action(ArgAction::SetTrue),
+        default_value = "false"
+    )]
+    pub no_confirm: bool,
+
+    /// The settings to start from, the build users are found on the system
+    #[clap(flatten)]
+    pub settings: CommonSettings,
+}
+
+#[async_trait::async_trait]
+impl CommandExecute for Migrate {
+    #[tracing::instrument(level = "debug", skip_all, fields())]
+    async fn execute(self) -> eyre::Result<ExitCode> {
+        let Self {
+            no_confirm,
+            settings,
+        } = self;
+
+        ensure_root()?;
+        let _lock = match ensure_lock()? {
+            Ok(lock) => lock,
+            Err(exit_code) => return Ok(exit_code),
+        };
+
+        let (migrated, settings) = match detect(settings).await {
+            Ok(detected) => detected,
+            Err(err) => {
+                if let Some(expected) = err.expected() {
+                    eprintln!("{}", expected.red());
+                    return Ok(output::error_failure(&err));
+                }
+                return Err(err)?;
+            },
+        };
+
+        output::ensure_no_prompt(no_confirm)?;
+
+        if !no_confirm {
+            let mut question = format!(
+                "Nix installed by {} will be migrated:\n\n",
+                migrated.origin()
+            );
+            for step in migrated.describe_normalize() {
+                question.push_str(&format!("* {step}\n"));
+            }
+            if settings.modify_profile {
+                question.push_str("* Configure the shell profiles\n");
+            }
+            question.push_str(&format!(
+                "* Configure the Nix daemon service\n* Record the install in `{RECEIPT_LOCATION}`\n"
+            ));
+            loop {
+                match interaction::prompt(&question, PromptChoice::Yes, false).await? {
+                    PromptChoice::Yes => break,
+                    PromptChoice::Explain => (),
+                    PromptChoice::No => {
+                        interaction::clean_exit_with_message("Okay, didn't do anything! Bye!").await
+                    },
+                }
+            }
+        }
+
+        migrated.normalize().await?;
+
+        // Before planning, so the adopted plan finds the shell profiles in place
+        if settings.modify_profile {
+            let mut configure_shell_profile = ConfigureShellProfile::plan(
+                ShellProfileLocations::default()
+                    .with_zsh_hook(settings.zsh_hook)
+                    .for_strategy(settings.profile_strategy)
+                    .with_homebrew_path_order(settings.homebrew_path_order),
+            )
+            .await?;
+            configure_shell_profile.try_execute().await?;
+        }
+
+        let mut plan = plan_adoption(settings)
+            .await
+            .wrap_err_with(|| migrated.partly_migrated())?;
+        plan.execute_tagged(&migrated.tags())
+            .await
+            .wrap_err_with(|| migrated.partly_migrated())?;
+
+        plan.write_receipt().await?;
+        if let Err(err) = copy_self_to_nix_dir().await {
+            tracing::warn!("Could not copy `nix-installer` to `/nix/nix-installer`: {err}");
+        }
+
+        if output::is_json() {
+            output::result(
+                "migrate",
+                true,
+                serde_json::json!({ "receipt": RECEIPT_LOCATION }),
+            );
+        } else {
+            println!(
+                "{}\n\nIt can now be managed with `/nix/nix-installer uninstall`, `repair`, and `upgrade`.",
+                format!("Nix was migrated from {}!", migrated.origin())
+                    .green()
+                    .bold()
+            );
+        }
+
+        Ok(ExitCode::SUCCESS)
+    }
+}
+
+/// The install `migrate` takes over
+enum Migrated {
+    Upstream(UpstreamInstall),
+    Packaged(PackagedNix),
+}
+
+impl Migrated {
+    /// What installed the Nix, for the user
+    fn origin(&self) -> String {
+        match self {
+            Self::Upstream(_) => "the upstream install script".to_string(),
+            Self::Packaged(packaged) => format!("the `{}` package", packaged.package),
+        }
+    }
+
+    /// What to do when the migration fails after [`normalize`](Self::normalize)
+    fn partly_migrated(&self) -> String {
+        match self {
+            Self::Upstream(_) => "The upstream install was partly migrated (its shell profiles and Nix daemon services were replaced), fix the error then run `nix-installer adopt`".to_string(),
+            Self::Packaged(packaged) => format!("The Nix of the `{}` package was partly migrated (its daemon was disabled), fix the error then run `nix-installer migrate` again", packaged.package),
+        }
+    }
+
+    fn describe_normalize(&self) -> Vec<String> {
+        match self {
+            Self::Upstream(upstream) => upstream.describe_normalize(),
+            Self::Packaged(packaged) => packaged.describe_normalize(),
+        }
+    }
+
+    async fn normalize(&self) -> Result<(), NixInstallerError> {
+        match self {
+            Self::Upstream(upstream) => upstream.normalize().await?,
+            Self::Packaged(packaged) => packaged.normalize().await?,
+        }
+        Ok(())
+    }
+
+    /// The actions of the adopted plan to execute, for what the install lacks
+    fn tags(&self) -> Vec<ActionTag> {
+        match self {
+            Self::Upstream(_) => vec![ConfigureUpstreamInitService::action_tag()],
+            // A packaged Nix is in `/usr/bin`, not in the default profile which the daemon's units run it from
+            Self::Packaged(_) => vec![
+                FetchAndUnpackNix::action_tag(),
+                MoveUnpackedNix::action_tag(),
+                SetupDefaultProfile::action_tag(),
+                ConfigureUpstreamInitService::action_tag(),
+            ],
+        }
+    }
+}
+
+async fn detect(settings: CommonSettings) -> Result<(Migrated, CommonSettings), NixInstallerError> {
+    ensure_no_receipt()?;
+    let migrated = match UpstreamInstall::detect().await? {
+        Some(upstream) => Migrated::Upstream(upstream),
+        None => {
+            let packaged = PackagedNix::detect()
+                .await
+                .ok_or(UpstreamInstallError::NotFound)?;
+            packaged.check_takeover().await?;
+            Migrated::Packaged(packaged)
+        },
+    };
+    let mut settings = detect_settings(settings).await?;
+    if let Migrated::Packaged(packaged) = &migrated {
+        // `detect_settings` asks the default profile's `nix`, which a packaged Nix isn't in
+        settings.distribution = packaged.distribution().await;
+    }
+    Ok((migrated, settings))
+}
@@ -0,0 +1,175 @@
+use std::process::ExitCode;
+
+use clap::{ArgAction, Parser};
+use eyre::WrapErr;
+use owo_colors::OwoColorize;
+
+use super::{
+    adopt::{ensure_no_receipt, plan_adoption},
+    install::copy_self_to_nix_dir,
+};
+use crate::{
+    action::{
+        common::{
+            ConfigureShellProfile, ConfigureUpstreamInitService, CreateUsersAndGroups,
+            PlaceNixConfiguration,
+        },
+        Action,
+    },
+    adopt::detect_distribution,
+    cli::{
+        ensure_lock, ensure_root,
+        interaction::{self, PromptChoice},
+        output, CommandExecute,
+    },
+    error::HasExpectedErrors,
+    plan::RECEIPT_LOCATION,
+    settings::CommonSettings,
+    single_user::{SingleUserError, SingleUserInstall},
+    NixInstallerError,
+};
+
+/**
+Convert a single-user Nix install (`/nix` owned by a user, Nix in their `~/.nix-profile`) to a
+multi-user one with a daemon, managed by `nix-installer`
+
+`/nix` is given to root, build users are created, and the Nix daemon, `nix.conf`, and shell profiles
+are set up as `nix-installer install` would. The user keeps their profile and GC roots.
+*/
+#[derive(Debug, Parser)]
+pub struct MigrateSingleUser {
+    /// Convert without requiring explicit user confirmation
+    #[clap(
+        long,
+        env = "NIX_INSTALLER_NO_CONFIRM",
+        action(ArgAction::SetTrue),
+        default_value = "false"
+    )]
+    pub no_confirm: bool,
+
+    /// The settings of the multi-user install, like the build users to create
+    #[clap(flatten)]
+    pub settings: CommonSettings,
+}
+
+#[async_trait::async_trait]
+impl CommandExecute for MigrateSingleUser {
+    #[tracing::instrument(level = "debug", skip_all, fields())]
+    async fn execute(self) -> eyre::Result<ExitCode> {
+        let Self {
+            no_confirm,
+            mut settings,
+        } = self;
+
+        ensure_root()?;
+        let _lock = match ensure_lock()? {
+            Ok(lock) => lock,
+            Err(exit_code) => return Ok(exit_code),
+        };
+
+        let single_user = match detect().await {
+            Ok(single_user) => single_user,
+            Err(err) => {
+                if let Some(expected) = err.expected() {
+                    eprintln!("{}", expected.red());
+                    return Ok(output::error_failure(&err));
+                }
+                return Err(err)?;
+            },
+        };
+
+        output::ensure_no_prompt(no_confirm)?;
+
+        if !no_confirm {
+            let mut question = format!(
+                "The single-user Nix of `{}` will be converted to multi-user:\n\n",
+                single_user.user
+            );
+            for step in single_user.describe(&settings.nix_build_group_name) {
+                question.push_str(&format!("* {step}\n"));
+            }
+            question.push_str(&format!(
+                "* Create the build group `{}` and {} build users\n* Configure `nix.conf`\n",
+                settings.nix_build_group_name, settings.nix_build_user_count
+            ));
+            if settings.modify_profile {
+                question.push_str("* Configure the shell profiles\n");
+            }
+            question.push_str(&format!(
+                "* Configure the Nix daemon service\n* Record the install in `{RECEIPT_LOCATION}`\n"
+            ));
+            loop {
+                match interaction::prompt(&question, PromptChoice::Yes, false).await? {
+                    PromptChoice::Yes => break,
+                    PromptChoice::Explain => (),
+                    PromptChoice::No => {
+                        interaction::clean_exit_with_message("Okay, didn't do anything! Bye!").await
+                    },
+                }
+            }
+        }
+
+        single_user.reown().await.map_err(NixInstallerError::from)?;
+        single_user
+            .setup_default_profile()
+            .await
+            .map_err(NixInstallerError::from)?;
+
+        settings.determinate_nix = false;
+        settings.distribution = detect_distribution().await;
+        let mut plan = plan_adoption(settings.clone()).await.wrap_err(
+            "`/nix` was given to root and the default profile set up, fix the error then run `nix-installer migrate-single-user` again",
+        )?;
+
+        plan.execute_tagged(&[CreateUsersAndGroups::action_tag()])
+            .await?;
+        single_user
+            .share_store(&settings.nix_build_group_name)
+            .await
+            .map_err(NixInstallerError::from)?;
+
+        let mut tags = vec![
+            PlaceNixConfiguration::action_tag(),
+            ConfigureUpstreamInitService::action_tag(),
+        ];
+        if settings.modify_profile {
+            tags.push(ConfigureShellProfile::action_tag());
+        }
+        plan.execute_tagged(&tags).await?;
+        single_user
+            .unhook_shell_profiles()
+            .await
+            .map_err(NixInstallerError::from)?;
+
+        plan.write_receipt().await?;
+        if let Err(err) = copy_self_to_nix_dir().await {
+            tracing::warn!("Could not copy `nix-installer` to `/nix/nix-installer`: {err}");
+        }
+
+        if output::is_json() {
+            output::result(
+                "migrate-single-user",
+                true,
+                serde_json::json!({
+                    "receipt": RECEIPT_LOCATION,
+                    "user": single_user.user,
+                }),
+            );
+        } else {
+            println!(
+                "{}\n\nOpen a new terminal to use it. It can now be managed with `/nix/nix-installer uninstall`, `repair`, and `upgrade`.",
+                "Nix was converted to a multi-user install!".green().bold()
+            );
+        }
+
+        Ok(ExitCode::SUCCESS)
+    }
+}
+
+async fn detect() -> Result<SingleUserInstall, NixInstallerError> {
+    ensure_no_receipt()?;
+    let single_user = SingleUserInstall::detect()
+        .await?
+        .ok_or(SingleUserError::NotFound)?;
+    Ok(single_user)
+}
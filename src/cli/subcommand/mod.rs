@@ -8,6 +8,38 @@ mod uninstall;
 use uninstall::Uninstall;
 mod self_test;
 use self_test::SelfTest;
+mod list_versions;
+use list_versions::ListVersions;
+mod apply;
+use apply::Apply;
+mod doctor;
+use doctor::Doctor;
+mod check;
+use check::Check;
+mod upgrade;
+use upgrade::Upgrade;
+mod resume;
+use resume::Resume;
+mod rollback_to_snapshot;
+use rollback_to_snapshot::RollbackToSnapshot;
+mod rotate_volume_key;
+use rotate_volume_key::RotateVolumeKey;
+mod reconfigure;
+use reconfigure::Reconfigure;
+mod verify;
+use verify::Verify;
+mod explain;
+use explain::Explain;
+mod adopt;
+use adopt::Adopt;
+mod migrate;
+use migrate::Migrate;
+mod migrate_single_user;
+use migrate_single_user::MigrateSingleUser;
+mod remote;
+use remote::Remote;
+mod fetch_unprivileged;
+use fetch_unprivileged::FetchUnprivileged;
 
 #[allow(clippy::large_enum_variant)]
 #[derive(Debug, clap::Subcommand)]
@@ -17,4 +49,49 @@ pub enum NixInstallerSubcommand {
     Uninstall(Uninstall),
     SelfTest(SelfTest),
     Plan(Plan),
+    Apply(Apply),
+    Resume(Resume),
+    RollbackToSnapshot(RollbackToSnapshot),
+    RotateVolumeKey(RotateVolumeKey),
+    Doctor(Doctor),
+    Check(Check),
+    Upgrade(Upgrade),
+    Reconfigure(Reconfigure),
+    Verify(Verify),
+    Explain(Explain),
+    Adopt(Adopt),
+    Migrate(Migrate),
+    MigrateSingleUser(MigrateSingleUser),
+    Remote(Remote),
+    ListVersions(ListVersions),
+    FetchUnprivileged(FetchUnprivileged),
+}
+
+impl NixInstallerSubcommand {
+    /// The name of the subcommand, as typed
+    pub fn name(&self) -> &'static str {
+        match self {
+            NixInstallerSubcommand::Install(_) => "install",
+            NixInstallerSubcommand::Repair(_) => "repair",
+            NixInstallerSubcommand::Uninstall(_) => "uninstall",
+            NixInstallerSubcommand::SelfTest(_) => "self-test",
+            NixInstallerSubcommand::Plan(_) => "plan",
+            NixInstallerSubcommand::Apply(_) => "apply",
+            NixInstallerSubcommand::Resume(_) => "resume",
+            NixInstallerSubcommand::RollbackToSnapshot(_) => "rollback-to-snapshot",
+            NixInstallerSubcommand::RotateVolumeKey(_) => "rotate-volume-key",
+            NixInstallerSubcommand::Doctor(_) => "doctor",
+            NixInstallerSubcommand::Check(_) => "check",
+            NixInstallerSubcommand::Upgrade(_) => "upgrade",
+            NixInstallerSubcommand::Reconfigure(_) => "reconfigure",
+            NixInstallerSubcommand::Verify(_) => "verify",
+            NixInstallerSubcommand::Explain(_) => "explain",
+            NixInstallerSubcommand::Adopt(_) => "adopt",
+            NixInstallerSubcommand::Migrate(_) => "migrate",
+            NixInstallerSubcommand::MigrateSingleUser(_) => "migrate-single-user",
+            NixInstallerSubcommand::Remote(_) => "remote",
+            NixInstallerSubcommand::ListVersions(_) => "list-versions",
+            NixInstallerSubcommand::FetchUnprivileged(_) => "fetch-unprivileged",
+        }
+    }
 }
@@ -1,36 +1,123 @@
-use std::{path::PathBuf, process::ExitCode};
+use std::{
+    path::{Path, PathBuf},
+    process::ExitCode,
+};
 
-use crate::{cli::ensure_root, error::HasExpectedErrors, BuiltinPlanner};
-use clap::Parser;
+use crate::{
+    cli::{ensure_root, output},
+    error::HasExpectedErrors,
+    plan_diff, BuiltinPlanner, InstallPlan,
+};
+use clap::{ArgAction, Parser};
 
 use eyre::WrapErr;
 use owo_colors::OwoColorize;
 
 use crate::cli::CommandExecute;
 
+const STDOUT: &str = "/dev/stdout";
+
 /**
 Emit a JSON install plan that can be manually edited before execution
 
 Primarily intended for development, debugging, and handling install cases.
+The plan can be reviewed, then executed on any matching host with `nix-installer apply`.
+Two plans (or receipts) can be compared with `nix-installer plan diff`.
 */
 #[derive(Debug, Parser)]
 pub struct Plan {
     #[clap(subcommand)]
-    pub planner: Option<BuiltinPlanner>,
+    pub planner: Option<PlanCommand>,
     /// Where to write the generated plan (in JSON format)
+    ///
+    /// With `--format json` and no `--out-file`, the plan is the `plan` of the `result` instead.
     #[clap(
         long = "out-file",
         env = "NIX_INSTALLER_PLAN_OUT_FILE",
-        default_value = "/dev/stdout"
+        default_value = STDOUT
     )]
     pub output: PathBuf,
+    /// Show a unified diff of each existing file the plan will change (on stderr, so the plan can still go to stdout)
+    #[clap(
+        long,
+        env = "NIX_INSTALLER_SHOW_DIFFS",
+        action(ArgAction::SetTrue),
+        default_value = "false"
+    )]
+    pub show_diffs: bool,
+}
+
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug, clap::Subcommand)]
+pub enum PlanCommand {
+    Diff(Diff),
+    #[clap(flatten)]
+    Planner(BuiltinPlanner),
+}
+
+/**
+Show which actions were added, removed, or changed between two plans (or receipts)
+
+Without `NEW`, `OLD` is compared with a plan made now, by this version of `nix-installer`, with the
+same planner and settings. Like the receipt of an install against what an upgraded installer would
+do, before rolling it out.
+*/
+#[derive(Debug, Parser)]
+pub struct Diff {
+    /// A plan (from `nix-installer plan`) or receipt
+    pub old: PathBuf,
+    /// A plan (or receipt) to compare `OLD` to, instead of a fresh one
+    pub new: Option<PathBuf>,
+}
+
+#[async_trait::async_trait]
+impl CommandExecute for Diff {
+    #[tracing::instrument(level = "debug", skip_all, fields())]
+    async fn execute(self) -> eyre::Result<ExitCode> {
+        let Self { old, new } = self;
+
+        let old = read_plan(&old).await?;
+        let new = match new {
+            Some(new) => read_plan(&new).await?,
+            None => {
+                ensure_root()?;
+                old.replan().await.wrap_err("Planning again")?
+            },
+        };
+        let diff = plan_diff::diff(&old, &new)?;
+
+        if output::is_json() {
+            output::result("plan-diff", true, serde_json::to_value(&diff)?);
+        } else {
+            print!("{diff}");
+        }
+        Ok(ExitCode::SUCCESS)
+    }
+}
+
+async fn read_plan(path: &Path) -> eyre::Result<InstallPlan> {
+    let plan_string = tokio::fs::read_to_string(path)
+        .await
+        .wrap_err_with(|| format!("Reading `{}`", path.display()))?;
+    InstallPlan::from_receipt(&plan_string)
+        .wrap_err_with(|| format!("Unable to parse `{}`", path.display()))
 }
 
 #[async_trait::async_trait]
 impl CommandExecute for Plan {
     #[tracing::instrument(level = "debug", skip_all, fields())]
     async fn execute(self) -> eyre::Result<ExitCode> {
-        let Self { planner, output } = self;
+        let Self {
+            planner,
+            output: output_path,
+            show_diffs,
+        } = self;
+
+        let planner = match planner {
+            Some(PlanCommand::Diff(diff)) => return diff.execute().await,
+            Some(PlanCommand::Planner(planner)) => Some(planner),
+            None => None,
+        };
 
         ensure_root()?;
 
@@ -46,14 +133,43 @@ impl CommandExecute for Plan {
             Err(err) => {
                 if let Some(expected) = err.expected() {
                     eprintln!("{}", expected.red());
-                    return Ok(ExitCode::FAILURE);
+                    return Ok(output::error_failure(&err));
                 }
                 return Err(err)?;
             },
         };
 
+        if show_diffs && !output::is_json() {
+            let diffs = install_plan.describe_diffs();
+            if !diffs.is_empty() {
+                eprintln!("{diffs}");
+            }
+        }
+
+        if output::is_json() {
+            let diffs = show_diffs.then(|| install_plan.describe_diffs());
+            if output_path == Path::new(STDOUT) {
+                output::result(
+                    "plan",
+                    true,
+                    serde_json::json!({ "plan": serde_json::to_value(&install_plan)?, "diffs": diffs }),
+                );
+                return Ok(ExitCode::SUCCESS);
+            }
+            let json = serde_json::to_string_pretty(&install_plan)?;
+            tokio::fs::write(&output_path, format!("{json}\n"))
+                .await
+                .wrap_err("Writing plan")?;
+            output::result(
+                "plan",
+                true,
+                serde_json::json!({ "out_file": output_path, "diffs": diffs }),
+            );
+            return Ok(ExitCode::SUCCESS);
+        }
+
         let json = serde_json::to_string_pretty(&install_plan)?;
-        tokio::fs::write(output, format!("{json}\n"))
+        tokio::fs::write(output_path, format!("{json}\n"))
             .await
             .wrap_err("Writing plan")?;
 
@@ -0,0 +1,275 @@
+use std::{path::PathBuf, process::ExitCode, time::SystemTime};
+
+use clap::{ArgAction, Parser};
+use eyre::WrapErr;
+use indexmap::IndexMap;
+use nix_config_parser::NixConfig;
+use owo_colors::OwoColorize;
+
+use super::upgrade::{receipt_init, restart_daemon};
+use crate::{
+    action::{
+        base::UpdateNixConfig, common::place_nix_configuration::NIX_CONF, Action, StatefulAction,
+    },
+    cli::{
+        ensure_lock, ensure_root,
+        interaction::{self, PromptChoice},
+        CommandExecute,
+    },
+    plan::RECEIPT_LOCATION,
+    settings::UrlOrPathOrString,
+    InstallPlan,
+};
+
+/**
+Change the Nix settings of an existing install
+
+Only `nix.conf` is changed, then the Nix daemon is restarted. The change is recorded in the
+install receipt, and reverted by `nix-installer uninstall`.
+*/
+#[derive(Debug, Parser)]
+pub struct Reconfigure {
+    /// Run without requiring explicit user confirmation
+    #[clap(
+        long,
+        env = "NIX_INSTALLER_NO_CONFIRM",
+        action(ArgAction::SetTrue),
+        default_value = "false"
+    )]
+    pub no_confirm: bool,
+
+    /// Replace the `substituters` with these
+    #[clap(
+        long = "substituter",
+        env = "NIX_INSTALLER_SUBSTITUTERS",
+        value_delimiter = ',',
+        action(ArgAction::Append)
+    )]
+    pub substituters: Vec<url::Url>,
+
+    /// Replace the `trusted-public-keys` with these
+    #[clap(
+        long = "trusted-public-key",
+        env = "NIX_INSTALLER_TRUSTED_PUBLIC_KEYS",
+        value_delimiter = ',',
+        action(ArgAction::Append)
+    )]
+    pub trusted_public_keys: Vec<String>,
+
+    /// Replace the `trusted-users` with these
+    #[clap(
+        long = "trusted-user",
+        env = "NIX_INSTALLER_TRUSTED_USERS",
+        value_delimiter = ',',
+        action(ArgAction::Append)
+    )]
+    pub trusted_users: Vec<String>,
+
+    /// The `max-jobs` the Nix daemon builds with (a number, or `auto`)
+    #[clap(long, env = "NIX_INSTALLER_MAX_JOBS")]
+    pub max_jobs: Option<String>,
+
+    /// The `cores` each build may use (`0` uses all of them)
+    #[clap(long, env = "NIX_INSTALLER_CORES")]
+    pub cores: Option<u32>,
+
+    /// Extra configuration lines for `/etc/nix/nix.conf`, replacing existing values of the same settings
+    #[clap(long, env = "NIX_INSTALLER_EXTRA_CONF", action(ArgAction::Append))]
+    pub extra_conf: Vec<String>,
+}
+
+impl Reconfigure {
+    /// The settings to set in `nix.conf`
+    fn nix_config_settings(&self) -> eyre::Result<IndexMap<String, String>> {
+        let mut settings = IndexMap::new();
+        if !self.substituters.is_empty() {
+            settings.insert(
+                "substituters".to_string(),
+                self.substituters
+                    .iter()
+                    .map(|url| url.to_string())
+                    .collect::<Vec<_>>()
+                    .join(" "),
+            );
+        }
+        if !self.trusted_public_keys.is_empty() {
+            settings.insert(
+                "trusted-public-keys".to_string(),
+                self.trusted_public_keys.join(" "),
+            );
+        }
+        if !self.trusted_users.is_empty() {
+            settings.insert("trusted-users".to_string(), self.trusted_users.join(" "));
+        }
+        if let Some(max_jobs) = &self.max_jobs {
+            settings.insert("max-jobs".to_string(), max_jobs.clone());
+        }
+        if let Some(cores) = self.cores {
+            settings.insert("cores".to_string(), cores.to_string());
+        }
+        for extra_conf in &self.extra_conf {
+            let parsed = NixConfig::parse_string(extra_conf.clone(), None)
+                .wrap_err_with(|| format!("Parsing `--extra-conf` `{extra_conf}`"))?;
+            settings.extend(parsed.settings().clone());
+        }
+        Ok(settings)
+    }
+}
+
+#[async_trait::async_trait]
+impl CommandExecute for Reconfigure {
+    #[tracing::instrument(level = "debug", skip_all, fields())]
+    async fn execute(self) -> eyre::Result<ExitCode> {
+        let settings = self.nix_config_settings()?;
+        if settings.is_empty() {
+            eprintln!(
+                "{}",
+                "No settings were given, see `nix-installer reconfigure --help`".red()
+            );
+            return Ok(ExitCode::FAILURE);
+        }
+
+        ensure_root()?;
+        let _lock = match ensure_lock()? {
+            Ok(lock) => lock,
+            Err(exit_code) => return Ok(exit_code),
+        };
+
+        let receipt_string = tokio::fs::read_to_string(RECEIPT_LOCATION)
+            .await
+            .wrap_err_with(|| {
+                format!("Reading `{RECEIPT_LOCATION}`, only Nix installed by `nix-installer` can be reconfigured")
+            })?;
+        let receipt = InstallPlan::from_receipt(&receipt_string).wrap_err_with(|| {
+            format!("Unable to parse existing receipt `{RECEIPT_LOCATION}`, it may be from an incompatible version of `nix-installer`")
+        })?;
+
+        let mut update_nix_config = UpdateNixConfig::plan(NIX_CONF, settings).await?;
+        if update_nix_config.inner().is_empty() {
+            println!("{}", "Nothing to change.".green().bold());
+            return Ok(ExitCode::SUCCESS);
+        }
+
+        if !self.no_confirm {
+            let mut question = String::new();
+            for description in update_nix_config.describe_execute() {
+                question.push_str(&format!("* {}\n", description.description));
+                for explanation in description.explanation {
+                    question.push_str(&format!("  - {explanation}\n"));
+                }
+            }
+            question.push_str("\nThe Nix daemon will be restarted.\n");
+            loop {
+                match interaction::prompt(&question, PromptChoice::Yes, false).await? {
+                    PromptChoice::Yes => break,
+                    PromptChoice::Explain => (),
+                    PromptChoice::No => {
+                        interaction::clean_exit_with_message("Okay, didn't do anything! Bye!").await
+                    },
+                }
+            }
+        }
+
+        update_nix_config.try_execute().await?;
+
+        restart_daemon(receipt_init(&receipt)).await?;
+
+        let timestamp_millis = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)?
+            .as_millis();
+        let mut old_receipt = PathBuf::from(RECEIPT_LOCATION);
+        old_receipt.set_extension(format!("pre-reconfigure.{timestamp_millis}.json"));
+        tokio::fs::copy(RECEIPT_LOCATION, &old_receipt).await?;
+        tracing::info!(
+            "Backed up pre-reconfigure receipt to {}",
+            old_receipt.display()
+        );
+
+        let updated_receipt = record_reconfigure(&receipt_string, update_nix_config.boxed())?;
+        updated_receipt.write_receipt().await?;
+        tracing::info!("Wrote updated receipt");
+
+        println!("{}", "Nix was reconfigured successfully!".green().bold());
+
+        Ok(ExitCode::SUCCESS)
+    }
+}
+
+/// Append the executed action to the receipt, and its settings to the planner's `extra_conf`
+fn record_reconfigure(
+    receipt: &str,
+    action: StatefulAction<Box<dyn Action>>,
+) -> eyre::Result<InstallPlan> {
+    let mut receipt: serde_json::Value = serde_json::from_str(receipt)?;
+    crate::migration::migrate(&mut receipt)?;
+    let action = serde_json::to_value(&action)?;
+
+    if let Some(settings) = receipt
+        .get_mut("planner")
+        .and_then(|planner| planner.get_mut("settings"))
+        .and_then(|settings| settings.as_object_mut())
+    {
+        let lines = action["action"]["settings"]
+            .as_object()
+            .into_iter()
+            .flatten()
+            .filter_map(|(name, value)| {
+                let line = format!("{name} = {}", value.as_str()?);
+                serde_json::to_value(UrlOrPathOrString::String(line)).ok()
+            });
+        let extra_conf = settings
+            .entry("extra_conf")
+            .or_insert_with(|| serde_json::Value::Array(vec![]));
+        if let Some(extra_conf) = extra_conf.as_array_mut() {
+            extra_conf.extend(lines);
+        }
+    }
+    if let Some(actions) = receipt
+        .get_mut("actions")
+        .and_then(|actions| actions.as_array_mut())
+    {
+        actions.push(action);
+    }
+    Ok(serde_json::from_value(receipt)?)
+}
+
+#[cfg(test)]
+mod test {
+    use indexmap::IndexMap;
+
+    use super::record_reconfigure;
+    use crate::{action::base::UpdateNixConfig, settings::UrlOrPathOrString};
+
+    #[tokio::test]
+    async fn records_reconfigure_in_receipt() -> eyre::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let nix_conf = temp_dir.path().join("nix.conf");
+        tokio::fs::write(&nix_conf, "trusted-users = root\n").await?;
+
+        let mut action = UpdateNixConfig::plan(
+            &nix_conf,
+            IndexMap::from([("trusted-users".to_string(), "root @wheel".to_string())]),
+        )
+        .await?;
+        action.try_execute().await?;
+
+        let receipt = include_str!("../../../tests/fixtures/linux/linux.json");
+        let reconfigured = record_reconfigure(receipt, action.boxed())?;
+
+        let original: serde_json::Value = serde_json::from_str(receipt)?;
+        assert_eq!(
+            Some(reconfigured.actions.len()),
+            original["actions"]
+                .as_array()
+                .map(|actions| actions.len() + 1)
+        );
+        let extra_conf = reconfigured.planner.settings()?["extra_conf"].clone();
+        assert_eq!(
+            extra_conf.as_array().and_then(|lines| lines.last()),
+            Some(&serde_json::to_value(UrlOrPathOrString::String(
+                "trusted-users = root @wheel".into()
+            ))?)
+        );
+        Ok(())
+    }
+}
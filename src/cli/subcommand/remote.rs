@@ -0,0 +1,394 @@
+use std::{
+    path::PathBuf,
+    process::{ExitCode, Stdio},
+    sync::Arc,
+};
+
+use clap::{ArgAction, Parser};
+use eyre::{eyre, WrapErr};
+use owo_colors::OwoColorize;
+use tokio::{
+    io::{AsyncBufReadExt, BufReader},
+    process::Command,
+    sync::Semaphore,
+    task::JoinSet,
+};
+
+use crate::{
+    action::shell_quote,
+    cli::{output, CommandExecute},
+    plan::RECEIPT_LOCATION,
+    progress::ProgressEvent,
+    settings::SYSTEM,
+    ErrorClass,
+};
+
+/**
+Install Nix on other machines over SSH
+
+Copies a `nix-installer` binary to each `--host`, runs `nix-installer install --no-confirm` there
+(with any arguments given after `--`), streams its progress back, and keeps a copy of each host's
+receipt in `--receipt-dir`. The hosts must be reachable with `ssh` without a prompt, as `root` or a
+user with passwordless `sudo`.
+*/
+#[derive(Debug, Parser)]
+pub struct Remote {
+    /// A host to install on, as given to `ssh` (eg `user@machine`)
+    #[clap(
+        long = "host",
+        required = true,
+        action = ArgAction::Append,
+        value_delimiter = ',',
+        env = "NIX_INSTALLER_REMOTE_HOSTS"
+    )]
+    pub hosts: Vec<String>,
+
+    /// The `nix-installer` binary to copy, needed if the hosts aren't the same platform as this one
+    #[clap(long, env = "NIX_INSTALLER_REMOTE_BINARY")]
+    pub binary: Option<PathBuf>,
+
+    /// Where to keep the hosts' receipts, each as `<host>.json`
+    #[clap(
+        long,
+        default_value = "nix-installer-receipts",
+        env = "NIX_INSTALLER_REMOTE_RECEIPT_DIR"
+    )]
+    pub receipt_dir: PathBuf,
+
+    /// An option for `ssh` (eg `StrictHostKeyChecking=accept-new`)
+    #[clap(long = "ssh-option", action = ArgAction::Append)]
+    pub ssh_options: Vec<String>,
+
+    /// How many hosts to install on at once
+    #[clap(long, default_value_t = 4, env = "NIX_INSTALLER_REMOTE_PARALLEL")]
+    pub parallel: usize,
+
+    /// Arguments for `nix-installer install` on the hosts
+    #[clap(last = true)]
+    pub install_args: Vec<String>,
+}
+
+#[async_trait::async_trait]
+impl CommandExecute for Remote {
+    #[tracing::instrument(level = "debug", skip_all, fields())]
+    async fn execute(self) -> eyre::Result<ExitCode> {
+        let Self {
+            hosts,
+            binary,
+            receipt_dir,
+            ssh_options,
+            parallel,
+            install_args,
+        } = self;
+
+        let binary_given = binary.is_some();
+        let local_binary = match binary {
+            Some(binary) => binary,
+            None => std::env::current_exe().wrap_err("Finding this `nix-installer` to copy")?,
+        };
+        tokio::fs::create_dir_all(&receipt_dir)
+            .await
+            .wrap_err_with(|| format!("Creating `{}`", receipt_dir.display()))?;
+
+        let target = Arc::new(Target {
+            local_binary,
+            binary_given,
+            receipt_dir,
+            ssh_options,
+            install_args,
+        });
+        let permits = Arc::new(Semaphore::new(parallel.max(1)));
+        let mut set = JoinSet::new();
+        for (index, host) in hosts.iter().enumerate() {
+            let target = target.clone();
+            let permits = permits.clone();
+            let host = host.clone();
+            set.spawn(async move {
+                let _permit = permits.acquire_owned().await;
+                let outcome = target.install_on(&host).await;
+                (index, host, outcome)
+            });
+        }
+
+        let mut outcomes = vec![];
+        while let Some(joined) = set.join_next().await {
+            outcomes.push(joined.wrap_err("Installing on a host")?);
+        }
+        outcomes.sort_by_key(|(index, _, _)| *index);
+
+        let failed = outcomes
+            .iter()
+            .filter(|(_, _, outcome)| outcome.is_err())
+            .count();
+        // Before the `result`, so it has the failure's class
+        let exit_code = if failed == 0 {
+            ExitCode::SUCCESS
+        } else {
+            output::failure(
+                ErrorClass::Other,
+                format!(
+                    "Installing on {failed} of {} host(s) failed",
+                    outcomes.len()
+                ),
+                outcomes
+                    .iter()
+                    .filter_map(|(_, host, outcome)| {
+                        outcome.as_ref().err().map(|err| format!("{host}: {err:#}"))
+                    })
+                    .collect(),
+            )
+        };
+
+        if output::is_json() {
+            let hosts = outcomes
+                .iter()
+                .map(|(_, host, outcome)| match outcome {
+                    Ok(receipt) => serde_json::json!({
+                        "host": host,
+                        "success": true,
+                        "receipt": receipt,
+                    }),
+                    Err(err) => serde_json::json!({
+                        "host": host,
+                        "success": false,
+                        "error": format!("{err:#}"),
+                    }),
+                })
+                .collect::<Vec<_>>();
+            output::result("remote", failed == 0, serde_json::json!({ "hosts": hosts }));
+        } else {
+            for (_, host, outcome) in &outcomes {
+                match outcome {
+                    Ok(receipt) => println!(
+                        "{} {host}, receipt kept in `{}`",
+                        "Installed".green().bold(),
+                        receipt.display()
+                    ),
+                    Err(err) => println!("{} {host}: {err:#}", "Failed".red().bold()),
+                }
+            }
+        }
+
+        Ok(exit_code)
+    }
+}
+
+/// What is installed, and how, on each host
+struct Target {
+    local_binary: PathBuf,
+    /// `--binary` was given, so it is copied whatever platform the host is
+    binary_given: bool,
+    receipt_dir: PathBuf,
+    ssh_options: Vec<String>,
+    install_args: Vec<String>,
+}
+
+impl Target {
+    /// Install on `host`, returning where its receipt was kept
+    async fn install_on(&self, host: &str) -> eyre::Result<PathBuf> {
+        let uname = self.ssh(host, "uname -sm").await?;
+        let system = system_of(&uname).ok_or_else(|| {
+            eyre!(
+                "`{}` isn't a platform Nix can be installed on",
+                uname.trim()
+            )
+        })?;
+        if system != SYSTEM && !self.binary_given {
+            return Err(eyre!(
+                "The host is `{system}`, this `nix-installer` is for `{SYSTEM}`, give a `--binary` for `{system}`"
+            ));
+        }
+
+        let remote_binary = self.ssh(host, "mktemp /tmp/nix-installer.XXXXXX").await?;
+        let remote_binary = shell_quote(remote_binary.trim());
+        let outcome = self.copy_and_install(host, &remote_binary).await;
+        // The install's outcome matters more than the clean up's
+        if let Err(err) = self.ssh(host, &format!("rm -f {remote_binary}")).await {
+            tracing::warn!(%host, "Could not remove `{remote_binary}`: {err:#}");
+        }
+        outcome
+    }
+
+    async fn copy_and_install(&self, host: &str, remote_binary: &str) -> eyre::Result<PathBuf> {
+        let binary = std::fs::File::open(&self.local_binary)
+            .wrap_err_with(|| format!("Opening `{}`", self.local_binary.display()))?;
+        let status = self
+            .ssh_command(
+                host,
+                &format!("cat > {remote_binary} && chmod 0755 {remote_binary}"),
+            )
+            .stdin(binary)
+            .stdout(Stdio::null())
+            .status()
+            .await
+            .wrap_err("Running `ssh`")?;
+        if !status.success() {
+            return Err(eyre!("Copying `nix-installer` failed ({status})"));
+        }
+
+        let mut install = vec![
+            remote_binary.to_string(),
+            "install".to_string(),
+            "--no-confirm".to_string(),
+            "--format".to_string(),
+            "json".to_string(),
+        ];
+        install.extend(self.install_args.iter().map(|arg| shell_quote(arg)));
+        self.stream_install(host, &as_root(&install.join(" ")))
+            .await?;
+
+        let receipt = self
+            .ssh(host, &as_root(&format!("cat {RECEIPT_LOCATION}")))
+            .await
+            .wrap_err("Reading the receipt")?;
+        let receipt_path = self
+            .receipt_dir
+            .join(format!("{}.json", file_name_of(host)));
+        tokio::fs::write(&receipt_path, receipt)
+            .await
+            .wrap_err_with(|| format!("Writing `{}`", receipt_path.display()))?;
+        Ok(receipt_path)
+    }
+
+    /// Run the install, reporting its progress as `--format json` events come in
+    async fn stream_install(&self, host: &str, command: &str) -> eyre::Result<()> {
+        let mut child = self
+            .ssh_command(host, command)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .wrap_err("Running `ssh`")?;
+        let (Some(stdout), Some(stderr)) = (child.stdout.take(), child.stderr.take()) else {
+            return Err(eyre!("The output of `ssh` couldn't be read"));
+        };
+
+        let events = async {
+            let mut lines = BufReader::new(stdout).lines();
+            let mut succeeded = false;
+            while let Ok(Some(line)) = lines.next_line().await {
+                succeeded |= report_line(host, &line);
+            }
+            succeeded
+        };
+        // `error` events, and the logs, come on stderr
+        let logs = async {
+            let mut lines = BufReader::new(stderr).lines();
+            let mut last = None;
+            while let Ok(Some(line)) = lines.next_line().await {
+                tracing::debug!(%host, "{line}");
+                last = Some(line);
+            }
+            last
+        };
+        let (succeeded, last_log) = tokio::join!(events, logs);
+        let status = child.wait().await.wrap_err("Waiting for `ssh`")?;
+
+        if status.success() && succeeded {
+            Ok(())
+        } else {
+            Err(eyre!(
+                "`nix-installer install` failed ({status}){}",
+                last_log.map(|log| format!(": {log}")).unwrap_or_default()
+            ))
+        }
+    }
+
+    async fn ssh(&self, host: &str, command: &str) -> eyre::Result<String> {
+        let output = self
+            .ssh_command(host, command)
+            .stdin(Stdio::null())
+            .output()
+            .await
+            .wrap_err("Running `ssh`")?;
+        if !output.status.success() {
+            return Err(eyre!(
+                "`{command}` failed ({}): {}",
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+        String::from_utf8(output.stdout).wrap_err_with(|| format!("The output of `{command}`"))
+    }
+
+    fn ssh_command(&self, host: &str, command: &str) -> Command {
+        let mut ssh = Command::new("ssh");
+        ssh.process_group(0);
+        // Fail rather than prompt for a password no one is there to type
+        ssh.args(["-o", "BatchMode=yes"]);
+        for option in &self.ssh_options {
+            ssh.args(["-o", option]);
+        }
+        ssh.arg("--").arg(host).arg(command);
+        ssh
+    }
+}
+
+/// Report a line of the remote install's `--format json` output, returning if it was a successful `result`
+fn report_line(host: &str, line: &str) -> bool {
+    let Ok(mut event) = serde_json::from_str::<serde_json::Value>(line) else {
+        return false;
+    };
+    if event.get("event").and_then(|event| event.as_str()) == Some("result") {
+        return event.get("success").and_then(|success| success.as_bool()) == Some(true);
+    }
+    if output::is_json() {
+        if let Some(event) = event.as_object_mut() {
+            event.insert("host".into(), host.into());
+        }
+        output::event(&event);
+    } else if let Ok(ProgressEvent::ActionStarted { synopsis }) = serde_json::from_value(event) {
+        eprintln!("{}: {synopsis}", host.bold());
+    }
+    false
+}
+
+/// Run `command` as root, with `sudo` unless the SSH user already is
+fn as_root(command: &str) -> String {
+    format!("if [ \"$(id -u)\" -eq 0 ]; then {command}; else sudo -n {command}; fi")
+}
+
+/// The Nix system (eg `x86_64-linux`) of the output of `uname -sm`
+fn system_of(uname: &str) -> Option<&'static str> {
+    let mut fields = uname.split_whitespace();
+    match (fields.next()?, fields.next()?) {
+        ("Linux", "x86_64") => Some("x86_64-linux"),
+        ("Linux", "aarch64" | "arm64") => Some("aarch64-linux"),
+        ("Darwin", "x86_64") => Some("x86_64-darwin"),
+        ("Darwin", "arm64" | "aarch64") => Some("aarch64-darwin"),
+        _ => None,
+    }
+}
+
+/// `host` as a file name, so a `/` in it can't put the receipt elsewhere
+fn file_name_of(host: &str) -> String {
+    host.replace(['/', '\\'], "_")
+}
+
+#[cfg(test)]
+mod test {
+    use super::{as_root, file_name_of, system_of};
+
+    #[test]
+    fn finds_systems() {
+        assert_eq!(system_of("Linux x86_64\n"), Some("x86_64-linux"));
+        assert_eq!(system_of("Linux aarch64"), Some("aarch64-linux"));
+        assert_eq!(system_of("Darwin arm64"), Some("aarch64-darwin"));
+        assert_eq!(system_of("FreeBSD amd64"), None);
+        assert_eq!(system_of(""), None);
+    }
+
+    #[test]
+    fn runs_as_root() {
+        assert_eq!(
+            as_root("/tmp/nix-installer.abc install"),
+            "if [ \"$(id -u)\" -eq 0 ]; then /tmp/nix-installer.abc install; else sudo -n /tmp/nix-installer.abc install; fi"
+        );
+    }
+
+    #[test]
+    fn names_receipts() {
+        assert_eq!(file_name_of("root@machine"), "root@machine");
+        assert_eq!(file_name_of("../machine"), ".._machine");
+    }
+}
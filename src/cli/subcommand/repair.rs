@@ -8,14 +8,20 @@ use serde::{Deserialize, Serialize};
 use target_lexicon::OperatingSystem;
 use tokio::process::Command;
 
+use super::uninstall::read_receipt;
+use super::upgrade::{receipt_init, restart_daemon};
 use crate::action::base::{AddUserToGroup, CreateGroup, CreateUser};
-use crate::action::common::{ConfigureShellProfile, CreateUsersAndGroups};
-use crate::action::{Action, ActionState, StatefulAction};
+use crate::action::common::{
+    ConfigureDeterminateNixdInitService, ConfigureInitService, ConfigureShellProfile,
+    ConfigureUpstreamInitService, CreateUsersAndGroups, PlaceNixConfiguration,
+};
+use crate::action::{Action, ActionState, ActionTag, StatefulAction};
 use crate::cli::interaction::PromptChoice;
-use crate::cli::{ensure_root, CommandExecute};
+use crate::cli::{ensure_lock, ensure_root, CommandExecute};
 use crate::plan::RECEIPT_LOCATION;
 use crate::planner::{PlannerError, ShellProfileLocations};
-use crate::{execute_command, InstallPlan};
+use crate::settings::{HomebrewPathOrder, ProfileStrategy, ZshHook};
+use crate::{execute_command, receipt_signature, InstallPlan, NixInstallerError};
 
 /// The base UID that we temporarily move build users to while migrating macOS to the new range.
 const TEMP_USER_ID_BASE: u32 = 31000;
@@ -23,7 +29,8 @@ const TEMP_USER_ID_BASE: u32 = 31000;
 /**
 Various actions to repair Nix installations.
 
-The default is to repair shell hooks.
+The default is to repair shell hooks. `init-service`, `mount`, and `nix-conf` plan the install in
+the receipt again, and execute only the actions of that part of it which aren't in place.
 */
 #[derive(Debug, Parser)]
 #[command(args_conflicts_with_subcommands = true)]
@@ -44,7 +51,14 @@ pub struct Repair {
 #[derive(Clone, Debug, Subcommand, serde::Deserialize, serde::Serialize)]
 pub enum RepairKind {
     /// Update the shell profiles to make Nix usable after system upgrades.
+    #[command(visible_alias = "shell-profiles")]
     Hooks,
+    /// Restore the Nix daemon's service (its systemd units, or launchd plist) as it was installed.
+    InitService,
+    /// Restore the mounting of the Nix store (the macOS Nix volume, or the Steam Deck's `/nix`).
+    Mount,
+    /// Restore `/etc/nix/nix.conf` as it was installed, then restart the Nix daemon.
+    NixConf,
     /// Recover from the macOS 15 Sequoia update taking over _nixbld users.
     ///
     /// Default functionality is to only attempt the fix if _nixbld users are missing.
@@ -115,6 +129,26 @@ impl Repair {
     }
 }
 
+impl RepairKind {
+    /// The actions of the receipt's plan the repair executes again, for those which re-plan it
+    fn action_tags(&self) -> Vec<ActionTag> {
+        match self {
+            RepairKind::InitService => vec![
+                ConfigureInitService::action_tag(),
+                ConfigureUpstreamInitService::action_tag(),
+                ConfigureDeterminateNixdInitService::action_tag(),
+            ],
+            RepairKind::Mount => vec![
+                crate::action::macos::CreateNixVolume::action_tag(),
+                crate::action::macos::CreateDeterminateNixVolume::action_tag(),
+                crate::action::linux::EnsureSteamosNixDirectory::action_tag(),
+            ],
+            RepairKind::NixConf => vec![PlaceNixConfiguration::action_tag()],
+            RepairKind::Hooks | RepairKind::Sequoia { .. } => vec![],
+        }
+    }
+}
+
 #[async_trait::async_trait]
 impl CommandExecute for Repair {
     #[tracing::instrument(level = "trace", skip_all)]
@@ -122,6 +156,10 @@ impl CommandExecute for Repair {
         let command = self.command();
 
         ensure_root()?;
+        let _lock = match ensure_lock()? {
+            Ok(lock) => lock,
+            Err(exit_code) => return Ok(exit_code),
+        };
 
         let mut repair_actions = Vec::new();
         let (prompt_before_repairing, brief_repair_summary) = match command {
@@ -129,6 +167,18 @@ impl CommandExecute for Repair {
                 false,
                 String::from("Will ensure the Nix shell profiles are still being sourced"),
             ),
+            RepairKind::InitService => (
+                false,
+                String::from("Will ensure the Nix daemon's service is as it was installed"),
+            ),
+            RepairKind::Mount => (
+                false,
+                String::from("Will ensure the Nix store is mounted as it was installed"),
+            ),
+            RepairKind::NixConf => (
+                false,
+                String::from("Will ensure `/etc/nix/nix.conf` is as it was installed"),
+            ),
             RepairKind::Sequoia {
                 ref nix_build_user_prefix,
                 nix_build_user_count,
@@ -185,10 +235,11 @@ impl CommandExecute for Repair {
         // TODO(cole-h): if we add another repair command, make this whole thing more generic
         let updated_receipt = match command.clone() {
             RepairKind::Hooks => {
-                let reconfigure = ConfigureShellProfile::plan(ShellProfileLocations::default())
-                    .await
-                    .map_err(PlannerError::Action)?
-                    .boxed();
+                let reconfigure =
+                    ConfigureShellProfile::plan(receipt_shell_profile_locations().await?)
+                        .await
+                        .map_err(PlannerError::Action)?
+                        .boxed();
                 repair_actions.push(reconfigure);
 
                 match OperatingSystem::host() {
@@ -206,6 +257,28 @@ impl CommandExecute for Repair {
 
                 None
             },
+            RepairKind::InitService | RepairKind::Mount | RepairKind::NixConf => {
+                let (receipt_path, receipt_string) =
+                    read_receipt(RECEIPT_LOCATION.into()).await.wrap_err(
+                        "Only Nix installed by `nix-installer` can be repaired this way, try `nix-installer doctor`",
+                    )?;
+                // The receipt decides what is executed, a tampered one isn't acted on
+                receipt_signature::verify(&receipt_path, receipt_string.as_bytes())
+                    .await
+                    .map_err(NixInstallerError::from)?;
+                let receipt = InstallPlan::from_receipt(&receipt_string)?;
+
+                let repaired = receipt.repair_tagged(&command.action_tags()).await?;
+                if repaired.is_empty() {
+                    tracing::info!("Nothing to do! It is all in place!");
+                    return Ok(ExitCode::SUCCESS);
+                }
+                if matches!(command, RepairKind::NixConf) {
+                    restart_daemon(receipt_init(&receipt)).await?;
+                }
+
+                None
+            },
             RepairKind::Sequoia {
                 nix_build_user_prefix,
                 nix_build_user_count,
@@ -449,14 +522,21 @@ where
 }
 
 #[tracing::instrument]
-async fn get_existing_receipt() -> Option<InstallPlan> {
+async fn get_existing_receipt() -> eyre::Result<Option<InstallPlan>> {
     match std::path::Path::new(RECEIPT_LOCATION).exists() {
         true => {
             tracing::debug!("Reading existing receipt");
             let install_plan_string = tokio::fs::read_to_string(RECEIPT_LOCATION).await.ok();
 
-            match install_plan_string {
-                Some(s) => match serde_json::from_str::<InstallPlan>(s.as_str()) {
+            if let Some(s) = &install_plan_string {
+                // The receipt decides which users are moved, a tampered one isn't acted on
+                receipt_signature::verify(std::path::Path::new(RECEIPT_LOCATION), s.as_bytes())
+                    .await
+                    .map_err(NixInstallerError::from)?;
+            }
+
+            Ok(match install_plan_string {
+                Some(s) => match InstallPlan::from_receipt(&s) {
                     Ok(plan) => {
                         tracing::debug!(plan_version = %plan.version, "Able to parse receipt");
                         Some(plan)
@@ -468,12 +548,36 @@ async fn get_existing_receipt() -> Option<InstallPlan> {
                     },
                 },
                 _ => None,
-            }
+            })
         },
-        false => None,
+        false => Ok(None),
     }
 }
 
+/// The shell profiles to repair, following the `--zsh-hook`, `--profile-strategy`, and `--homebrew-path-order` recorded in the receipt (if there is one)
+async fn receipt_shell_profile_locations() -> eyre::Result<ShellProfileLocations> {
+    let settings = get_existing_receipt()
+        .await?
+        .and_then(|plan| plan.planner.settings().ok())
+        .unwrap_or_default();
+    let zsh_hook = settings
+        .get("zsh_hook")
+        .and_then(|zsh_hook| serde_json::from_value::<ZshHook>(zsh_hook.clone()).ok())
+        .unwrap_or_default();
+    let strategy = settings
+        .get("profile_strategy")
+        .and_then(|strategy| serde_json::from_value::<ProfileStrategy>(strategy.clone()).ok())
+        .unwrap_or_default();
+    let homebrew_path_order = settings
+        .get("homebrew_path_order")
+        .and_then(|order| serde_json::from_value::<Option<HomebrewPathOrder>>(order.clone()).ok())
+        .flatten();
+    Ok(ShellProfileLocations::default()
+        .with_zsh_hook(zsh_hook)
+        .for_strategy(strategy)
+        .with_homebrew_path_order(homebrew_path_order))
+}
+
 #[tracing::instrument(skip_all)]
 fn find_users_and_groups(
     existing_receipt: Option<InstallPlan>,
@@ -538,7 +642,7 @@ async fn maybe_users_and_groups_from_receipt(
     nix_build_user_count: u32,
     nix_build_group_name: &str,
 ) -> eyre::Result<UsersAndGroupsMeta> {
-    let existing_receipt = get_existing_receipt().await;
+    let existing_receipt = get_existing_receipt().await?;
     let maybe_create_users_and_groups_idx_action = find_users_and_groups(existing_receipt)?;
 
     match maybe_create_users_and_groups_idx_action {
@@ -0,0 +1,109 @@
+use std::{
+    path::{Path, PathBuf},
+    process::ExitCode,
+};
+
+use clap::{builder::TypedValueParser, ArgAction, Parser};
+use owo_colors::OwoColorize;
+
+use super::{install::OnCancel, Install};
+use crate::{
+    cli::CommandExecute,
+    plan::{self, DEFAULT_JOBS, JOURNAL_LOCATION, RECEIPT_LOCATION},
+    settings::CommonSettings,
+};
+
+/**
+Resume an install which was interrupted (by a crash, power loss, or a dropped SSH connection)
+
+The install continues from the last action recorded as completed in the journal.
+*/
+#[derive(Debug, Parser)]
+pub struct Resume {
+    /// Run installation without requiring explicit user confirmation
+    #[clap(
+        long,
+        env = "NIX_INSTALLER_NO_CONFIRM",
+        action(ArgAction::SetTrue),
+        default_value = "false"
+    )]
+    pub no_confirm: bool,
+
+    /// Provide an explanation of the changes the installation process will make to your system
+    #[clap(
+        long,
+        env = "NIX_INSTALLER_EXPLAIN",
+        action(ArgAction::SetTrue),
+        default_value = "false"
+    )]
+    pub explain: bool,
+
+    /// How many independent actions to run at once
+    #[clap(
+        long,
+        env = "NIX_INSTALLER_JOBS",
+        default_value_t = DEFAULT_JOBS,
+        value_parser = clap::value_parser!(u16).range(1..).map(usize::from)
+    )]
+    pub jobs: usize,
+
+    /// Run the actions one at a time, in the order they were planned (the same as `--jobs 1`)
+    #[clap(
+        long,
+        env = "NIX_INSTALLER_SERIAL",
+        action(ArgAction::SetTrue),
+        default_value = "false",
+        conflicts_with = "jobs"
+    )]
+    pub serial: bool,
+}
+
+#[async_trait::async_trait]
+impl CommandExecute for Resume {
+    #[tracing::instrument(level = "debug", skip_all, fields())]
+    async fn execute(self) -> eyre::Result<ExitCode> {
+        let Self {
+            no_confirm,
+            explain,
+            jobs,
+            serial,
+        } = self;
+
+        // The journal is only removed once the receipt is written, so is never older than it
+        let plan = if let Some(journal) = plan::journal()? {
+            journal
+        } else if Path::new(RECEIPT_LOCATION).exists() {
+            PathBuf::from(RECEIPT_LOCATION)
+        } else {
+            eprintln!(
+                "{}",
+                format!("No interrupted install was found in `{JOURNAL_LOCATION}` or `{RECEIPT_LOCATION}`").red()
+            );
+            return Ok(ExitCode::FAILURE);
+        };
+        tracing::info!("Resuming install from `{}`", plan.display());
+
+        // The settings are unused, everything comes from the journal
+        Install {
+            no_confirm,
+            settings: CommonSettings::default().await?,
+            explain,
+            dry_run: false,
+            show_diffs: false,
+            #[cfg(feature = "tui")]
+            review: false,
+            progress: true,
+            jobs,
+            serial,
+            crash_report: None,
+            skip_preflight: false,
+            check: false,
+            // Interrupting a resumed install keeps it to resume again, rather than reverting what was resumed
+            on_cancel: OnCancel::Keep,
+            plan: Some(plan),
+            planner: None,
+        }
+        .execute()
+        .await
+    }
+}
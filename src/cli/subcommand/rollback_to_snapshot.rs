@@ -0,0 +1,238 @@
+use std::{
+    path::{Path, PathBuf},
+    process::{ExitCode, Stdio},
+};
+
+use clap::{ArgAction, Parser};
+use eyre::{eyre, WrapErr};
+use owo_colors::OwoColorize;
+use tokio::process::Command;
+
+use super::uninstall::read_receipt;
+use crate::{
+    action::{base::CreateSnapshots, Action, ActionState, Artifact},
+    cli::{
+        ensure_lock, ensure_root,
+        interaction::{self, PromptChoice},
+        output, CommandExecute,
+    },
+    error::HasExpectedErrors,
+    plan::{self, JOURNAL_LOCATION, RECEIPT_LOCATION},
+    receipt_signature, InstallPlan, NixInstallerError,
+};
+
+/**
+Restore what an install changed from the snapshots taken before it (with `install --snapshot`)
+
+For when the install failed, and couldn't be reverted. What the install's actions placed in the
+snapshotted paths (like `/etc/bashrc`) is made as it was when the snapshots were taken, undoing any
+other changes made to it since, and what they placed in paths which didn't exist before (like `/nix`
+on Linux) is removed. Nothing else is changed, and the snapshots are kept.
+*/
+#[derive(Debug, Parser)]
+pub struct RollbackToSnapshot {
+    #[clap(
+        long,
+        env = "NIX_INSTALLER_NO_CONFIRM",
+        action(ArgAction::SetTrue),
+        default_value = "false"
+    )]
+    pub no_confirm: bool,
+
+    /// The receipt (or journal, of an install which didn't finish) recording the snapshots
+    #[clap(default_value = RECEIPT_LOCATION)]
+    pub receipt: PathBuf,
+}
+
+#[async_trait::async_trait]
+impl CommandExecute for RollbackToSnapshot {
+    #[tracing::instrument(level = "debug", skip_all, fields())]
+    async fn execute(self) -> eyre::Result<ExitCode> {
+        let Self {
+            no_confirm,
+            mut receipt,
+        } = self;
+
+        ensure_root()?;
+        let _lock = match ensure_lock()? {
+            Ok(lock) => lock,
+            Err(exit_code) => return Ok(exit_code),
+        };
+
+        // An install which was cancelled (or crashed) only has a journal, which isn't signed, but
+        // is only trusted if root wrote it
+        if receipt == Path::new(RECEIPT_LOCATION) && !receipt.exists() {
+            if let Some(journal) = plan::journal()? {
+                receipt = journal;
+            }
+        } else if receipt == Path::new(JOURNAL_LOCATION) {
+            plan::journal()?;
+        }
+        let is_journal = receipt == Path::new(JOURNAL_LOCATION);
+        let (receipt, receipt_string) = read_receipt(receipt).await?;
+
+        // The receipt decides what is rolled back, a tampered one isn't acted on
+        if !is_journal {
+            if let Err(err) = receipt_signature::verify(&receipt, receipt_string.as_bytes()).await {
+                let err = NixInstallerError::from(err);
+                if let Some(expected) = err.expected() {
+                    eprintln!("{}", expected.red());
+                    return Ok(output::error_failure(&err));
+                }
+                Err(err)?
+            }
+        }
+        let plan = InstallPlan::from_receipt_lossy(&receipt_string)
+            .wrap_err_with(|| format!("Unable to parse the receipt `{}`", receipt.display()))?;
+
+        let Some(snapshots) = plan
+            .actions
+            .iter()
+            .find(|action| action.inner_typetag_name() == CreateSnapshots::action_tag().0)
+        else {
+            return Err(eyre!(
+                "`{}` records no snapshots, the install was done without `--snapshot`",
+                receipt.display()
+            ));
+        };
+        if snapshots.state != ActionState::Completed {
+            return Err(eyre!(
+                "`{}` records that the snapshots weren't taken, the install failed before changing anything",
+                receipt.display()
+            ));
+        }
+        let delete_commands = snapshots
+            .revert_dry_run()
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>();
+        let snapshots: CreateSnapshots = serde_json::from_value(
+            serde_json::to_value(snapshots)?
+                .get_mut("action")
+                .map(serde_json::Value::take)
+                .unwrap_or_default(),
+        )?;
+        // Only what the install placed, in the actions it started
+        let touched = plan
+            .actions
+            .iter()
+            .filter(|action| {
+                !matches!(
+                    action.state,
+                    ActionState::Uncompleted | ActionState::Skipped
+                )
+            })
+            .flat_map(|action| action.action.artifacts())
+            .filter_map(|artifact| match artifact {
+                Artifact::File(path)
+                | Artifact::Directory(path)
+                | Artifact::Service { path, .. } => Some(path),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        let paths = snapshots.rollback_paths(&touched);
+
+        if !no_confirm {
+            let question = format!(
+                "\
+                Roll back to the snapshots recorded in `{}`?\n\
+                \n\
+                {}\n\
+                \n\
+                Any changes made to these paths since the snapshots were taken are lost, nothing else is changed.\
+                ",
+                receipt.display(),
+                snapshots
+                    .describe_rollback(&paths)
+                    .iter()
+                    .map(|step| format!("* {step}"))
+                    .collect::<Vec<_>>()
+                    .join("\n"),
+            );
+            loop {
+                match interaction::prompt(&question, PromptChoice::Yes, false).await? {
+                    PromptChoice::Yes => break,
+                    PromptChoice::Explain => (),
+                    PromptChoice::No => {
+                        interaction::clean_exit_with_message("Okay, didn't do anything! Bye!").await
+                    },
+                }
+            }
+        }
+
+        stop_daemon().await;
+        let restored = snapshots
+            .rollback(&paths)
+            .await
+            .map_err(|err| eyre!(err))
+            .wrap_err("Rolling back to the snapshots")?;
+        if cfg!(target_os = "linux") {
+            // The units the install added to `/etc` are gone
+            run_ignoring_failure(Command::new("systemctl").arg("daemon-reload")).await;
+        }
+        if let Err(err) = tokio::fs::remove_file(JOURNAL_LOCATION).await {
+            if err.kind() != std::io::ErrorKind::NotFound {
+                tracing::warn!(%err, "Could not remove `{JOURNAL_LOCATION}`");
+            }
+        }
+
+        if output::is_json() {
+            output::result(
+                "rollback-to-snapshot",
+                true,
+                serde_json::json!({ "restored": restored }),
+            );
+            return Ok(ExitCode::SUCCESS);
+        }
+        println!(
+            "{}",
+            "Rolled back to the snapshots taken before the install"
+                .green()
+                .bold()
+        );
+        for path in &restored {
+            println!("  - `{}`", path.display());
+        }
+        if cfg!(target_os = "macos") {
+            println!(
+                "Restart to unmount `/nix`, then delete the `Nix Store` volume with `diskutil apfs deleteVolume \"Nix Store\"`"
+            );
+        }
+        println!("The snapshots were kept, delete them with:");
+        for command in delete_commands {
+            println!("{command}");
+        }
+
+        Ok(ExitCode::SUCCESS)
+    }
+}
+
+/// Stop the Nix daemon (if it's running) before what it runs from is removed
+async fn stop_daemon() {
+    if cfg!(target_os = "macos") {
+        for service in [
+            "system/org.nixos.nix-daemon",
+            "system/systems.determinate.nix-daemon",
+        ] {
+            run_ignoring_failure(Command::new("launchctl").args(["bootout", service])).await;
+        }
+    } else {
+        run_ignoring_failure(Command::new("systemctl").args([
+            "stop",
+            "nix-daemon.socket",
+            "nix-daemon.service",
+        ]))
+        .await;
+    }
+}
+
+async fn run_ignoring_failure(command: &mut Command) {
+    let status = command
+        .process_group(0)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .await;
+    tracing::debug!(?status, "Ran `{:?}`", command.as_std());
+}
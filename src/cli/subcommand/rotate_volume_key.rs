@@ -0,0 +1,174 @@
+use std::{path::PathBuf, process::ExitCode};
+
+use clap::{ArgAction, Parser};
+use eyre::{eyre, WrapErr};
+use owo_colors::OwoColorize;
+
+use super::uninstall::read_receipt;
+use crate::{
+    action::macos::EncryptApfsVolume,
+    cli::{
+        ensure_lock, ensure_root,
+        interaction::{self, PromptChoice},
+        output, CommandExecute,
+    },
+    plan::RECEIPT_LOCATION,
+    InstallPlan,
+};
+
+/**
+Rotate the passphrase of the encrypted Nix Store volume (macOS)
+
+A new random passphrase is set on the volume and in the System keychain (where the volume's mount
+service reads it at boot), then checked to unlock the volume. If either fails, the previous
+passphrase is restored.
+*/
+#[derive(Debug, Parser)]
+pub struct RotateVolumeKey {
+    #[clap(
+        long,
+        env = "NIX_INSTALLER_NO_CONFIRM",
+        action(ArgAction::SetTrue),
+        default_value = "false"
+    )]
+    pub no_confirm: bool,
+
+    /// The receipt of the install which encrypted the volume
+    #[clap(default_value = RECEIPT_LOCATION)]
+    pub receipt: PathBuf,
+}
+
+#[async_trait::async_trait]
+impl CommandExecute for RotateVolumeKey {
+    #[tracing::instrument(level = "debug", skip_all, fields())]
+    async fn execute(self) -> eyre::Result<ExitCode> {
+        let Self {
+            no_confirm,
+            receipt,
+        } = self;
+
+        if !cfg!(target_os = "macos") {
+            return Err(eyre!(
+                "Only macOS installs have an encrypted Nix Store volume"
+            ));
+        }
+        ensure_root()?;
+        let _lock = match ensure_lock()? {
+            Ok(lock) => lock,
+            Err(exit_code) => return Ok(exit_code),
+        };
+
+        let (receipt, receipt_string) = read_receipt(receipt).await?;
+        let plan = InstallPlan::from_receipt_lossy(&receipt_string)
+            .wrap_err_with(|| format!("Unable to parse the receipt `{}`", receipt.display()))?;
+        let Some(encrypt_volume) = find_encrypted_volume(&serde_json::to_value(&plan.actions)?)
+        else {
+            return Err(eyre!(
+                "`{}` records no encrypted volume, the Nix Store volume isn't encrypted (like on a Mac with a T2 or Apple silicon, whose disks are encrypted already)",
+                receipt.display()
+            ));
+        };
+
+        if !no_confirm {
+            let question = format!(
+                "\
+                Rotate the passphrase of the `{}` volume?\n\
+                \n\
+                A new passphrase is set on the volume and in the System keychain, and checked to unlock it.\
+                ",
+                encrypt_volume.name()
+            );
+            loop {
+                match interaction::prompt(&question, PromptChoice::Yes, false).await? {
+                    PromptChoice::Yes => break,
+                    PromptChoice::Explain => (),
+                    PromptChoice::No => {
+                        interaction::clean_exit_with_message("Okay, didn't do anything! Bye!").await
+                    },
+                }
+            }
+        }
+
+        encrypt_volume
+            .rotate_passphrase()
+            .await
+            .map_err(|err| eyre!(err))
+            .wrap_err("Rotating the volume's passphrase")?;
+
+        if output::is_json() {
+            output::result(
+                "rotate-volume-key",
+                true,
+                serde_json::json!({ "volume": encrypt_volume.name() }),
+            );
+            return Ok(ExitCode::SUCCESS);
+        }
+        println!(
+            "{}",
+            format!(
+                "Rotated the passphrase of the `{}` volume, it unlocks with the System keychain's",
+                encrypt_volume.name()
+            )
+            .green()
+            .bold()
+        );
+
+        Ok(ExitCode::SUCCESS)
+    }
+}
+
+/// The completed [`EncryptApfsVolume`] within the (serialized) actions, if any
+fn find_encrypted_volume(value: &serde_json::Value) -> Option<EncryptApfsVolume> {
+    match value {
+        serde_json::Value::Array(values) => values.iter().find_map(find_encrypted_volume),
+        serde_json::Value::Object(object) => {
+            let action = object.get("action");
+            let is_encrypt_volume = action
+                .and_then(|action| action.get("action_name"))
+                .and_then(serde_json::Value::as_str)
+                == Some("encrypt_volume");
+            let completed =
+                object.get("state").and_then(serde_json::Value::as_str) == Some("Completed");
+            if is_encrypt_volume {
+                return completed
+                    .then(|| serde_json::from_value(action?.clone()).ok())
+                    .flatten();
+            }
+            object.values().find_map(find_encrypted_volume)
+        },
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::find_encrypted_volume;
+
+    #[test]
+    fn finds_the_nested_encrypted_volume() {
+        let actions = serde_json::json!([
+            { "action": { "action_name": "create_directory", "path": "/nix" }, "state": "Completed" },
+            {
+                "action": {
+                    "action_name": "create_apfs_volume",
+                    "encrypt_volume": {
+                        "action": {
+                            "action_name": "encrypt_volume",
+                            "determinate_nix": false,
+                            "disk": "disk3",
+                            "name": "Nix Store",
+                        },
+                        "state": "Completed",
+                    },
+                },
+                "state": "Completed",
+            },
+        ]);
+        let found = find_encrypted_volume(&actions).map(|volume| volume.name().to_string());
+        assert_eq!(found.as_deref(), Some("Nix Store"));
+
+        let mut uncompleted = actions.clone();
+        uncompleted[1]["action"]["encrypt_volume"]["state"] = "Uncompleted".into();
+        assert!(find_encrypted_volume(&uncompleted).is_none());
+    }
+}
@@ -1,28 +1,94 @@
 use std::process::ExitCode;
 
-use clap::Parser;
+use clap::{ArgAction, Parser};
 
-use crate::{cli::CommandExecute, NixInstallerError};
+use super::upgrade::receipt_init;
+use crate::{
+    cli::{output, CommandExecute},
+    plan::RECEIPT_LOCATION,
+    self_test::{self_test, self_test_subsystems},
+    InstallPlan, NixInstallerError,
+};
 
-/// Run a self test of Nix to ensure that an install is working
+/**
+Run a self test of Nix to ensure that an install is working
+
+Besides building with Nix in each shell, this checks the daemon answers `nix store ping`, a trivial
+sandboxed build, a trivial flake evaluation, and that each configured substituter is reachable.
+*/
 #[derive(Debug, Parser)]
-pub struct SelfTest {}
+pub struct SelfTest {
+    /// Also kill the Nix daemon, and check its service brings it back (builds running in it fail)
+    #[clap(
+        long,
+        env = "NIX_INSTALLER_CHECK_DAEMON_RESTART",
+        action(ArgAction::SetTrue),
+        default_value = "false"
+    )]
+    pub check_daemon_restart: bool,
+}
 
 #[async_trait::async_trait]
 impl CommandExecute for SelfTest {
     #[tracing::instrument(level = "debug", skip_all, fields())]
     async fn execute(self) -> eyre::Result<ExitCode> {
-        crate::self_test::self_test()
-            .await
-            .map_err(NixInstallerError::SelfTest)?;
+        let Self {
+            check_daemon_restart,
+        } = self;
+
+        // The receipt records which distribution was installed, and which init system runs the daemon
+        let receipt = match tokio::fs::read_to_string(RECEIPT_LOCATION).await {
+            Ok(receipt) => InstallPlan::from_receipt(&receipt).ok(),
+            Err(_) => None,
+        };
+        let distribution = receipt
+            .as_ref()
+            .map(InstallPlan::distribution)
+            .unwrap_or_default();
+        let init = check_daemon_restart.then(|| match &receipt {
+            Some(receipt) => receipt_init(receipt),
+            None if cfg!(target_os = "macos") => crate::settings::InitSystem::Launchd,
+            None => crate::settings::InitSystem::Systemd,
+        });
+
+        let mut failures = vec![];
+        if let Err(errs) = self_test(distribution).await {
+            failures.extend(errs);
+        }
+        if let Err(errs) = self_test_subsystems(distribution, init).await {
+            failures.extend(errs);
+        }
+        if !failures.is_empty() {
+            if output::is_json() {
+                let failures = failures
+                    .iter()
+                    .map(|failure| {
+                        serde_json::json!({
+                            "subsystem": failure.subsystem(),
+                            "message": failure.to_string(),
+                        })
+                    })
+                    .collect::<Vec<_>>();
+                output::result(
+                    "self-test",
+                    false,
+                    serde_json::json!({ "failures": failures }),
+                );
+            }
+            return Err(NixInstallerError::SelfTest(failures))?;
+        }
 
+        let shells = crate::self_test::Shell::discover()
+            .iter()
+            .map(|v| v.executable())
+            .collect::<Vec<_>>();
         tracing::info!(
-            shells = ?crate::self_test::Shell::discover()
-                .iter()
-                .map(|v| v.executable())
-                .collect::<Vec<_>>(),
-            "Successfully tested Nix install in all discovered shells."
+            ?shells,
+            "Successfully tested Nix install in all discovered shells, and the daemon, sandbox, flakes, and substituters."
         );
+        if output::is_json() {
+            output::result("self-test", true, serde_json::json!({ "shells": shells }));
+        }
         Ok(ExitCode::SUCCESS)
     }
 }
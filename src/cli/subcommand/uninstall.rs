@@ -4,14 +4,26 @@ use std::{
     process::ExitCode,
 };
 
+use super::adopt::{ensure_no_receipt, plan_adoption};
 use crate::{
-    cli::{ensure_root, interaction::PromptChoice, signal_channel},
+    action::ActionDescription,
+    adopt::detect_settings,
+    cli::{
+        crash_report, ensure_lock, ensure_root, interaction::PromptChoice, output,
+        progress::ProgressReport, signal_channel,
+    },
     error::HasExpectedErrors,
-    plan::{current_version, RECEIPT_LOCATION},
+    plan::{current_version, RECEIPT_BACKUP_LOCATION, RECEIPT_LOCATION},
+    purge, receipt_signature,
+    settings::CommonSettings,
+    user_state::{self, USER_STATE_LOCATION},
     InstallPlan, NixInstallerError,
 };
 use clap::{ArgAction, Parser};
-use color_eyre::eyre::{eyre, WrapErr};
+use color_eyre::{
+    eyre::{eyre, WrapErr},
+    Section,
+};
 use owo_colors::OwoColorize;
 use rand::Rng;
 
@@ -38,6 +50,62 @@ pub struct Uninstall {
     )]
     pub explain: bool,
 
+    /// Print the exact commands the uninstall would run, and files it would remove or write, then exit without changing anything
+    #[clap(
+        long,
+        env = "NIX_INSTALLER_DRY_RUN",
+        action(ArgAction::SetTrue),
+        default_value = "false",
+        global = true
+    )]
+    pub dry_run: bool,
+
+    /// Write a crash report (a tarball of the plan, receipt, logs, and system facts, with secrets redacted) here if the uninstall fails, instead of offering to
+    #[clap(long, env = "NIX_INSTALLER_CRASH_REPORT", global = true)]
+    pub crash_report: Option<PathBuf>,
+
+    /// Remove the services, users, and configuration, but leave `/nix` in place
+    #[clap(
+        long,
+        env = "NIX_INSTALLER_KEEP_STORE",
+        action(ArgAction::SetTrue),
+        default_value = "false",
+        conflicts_with = "purge",
+        global = true
+    )]
+    pub keep_store: bool,
+
+    /// Also remove what Nix left in users' homes: their `~/.nix-profile`, channels, and caches (like `~/.cache/nix`)
+    #[clap(
+        long,
+        env = "NIX_INSTALLER_PURGE",
+        action(ArgAction::SetTrue),
+        default_value = "false",
+        global = true
+    )]
+    pub purge: bool,
+
+    /// Keep the users' profiles (and their channels and GC roots), so the next install puts them back
+    #[clap(
+        long,
+        env = "NIX_INSTALLER_KEEP_USER_PROFILES",
+        action(ArgAction::SetTrue),
+        default_value = "false",
+        conflicts_with = "purge",
+        global = true
+    )]
+    pub keep_user_profiles: bool,
+
+    /// Without a receipt, undo what `nix-installer` would have installed which is found on the system (like the daemon, build users, and shell profiles)
+    #[clap(
+        long,
+        env = "NIX_INSTALLER_FORCE_NO_RECEIPT",
+        action(ArgAction::SetTrue),
+        default_value = "false",
+        global = true
+    )]
+    pub force_no_receipt: bool,
+
     #[clap(default_value = RECEIPT_LOCATION)]
     pub receipt: PathBuf,
 }
@@ -50,9 +118,19 @@ impl CommandExecute for Uninstall {
             no_confirm,
             receipt,
             explain,
+            dry_run,
+            crash_report,
+            keep_store,
+            purge,
+            keep_user_profiles,
+            force_no_receipt,
         } = self;
 
         ensure_root()?;
+        let _lock = match ensure_lock()? {
+            Ok(lock) => lock,
+            Err(exit_code) => return Ok(exit_code),
+        };
 
         if let Ok(current_dir) = std::env::current_dir() {
             let mut components = current_dir.components();
@@ -108,34 +186,56 @@ impl CommandExecute for Uninstall {
             }
         }
 
-        let install_receipt_string = tokio::fs::read_to_string(receipt)
-            .await
-            .wrap_err("Reading receipt")?;
+        let mut plan = if force_no_receipt {
+            match plan_cleanup().await {
+                Ok(plan) => plan,
+                Err(err) => {
+                    if let Some(expected) = err.expected() {
+                        eprintln!("{}", expected.red());
+                        return Ok(output::error_failure(&err));
+                    }
+                    return Err(err)?;
+                },
+            }
+        } else {
+            let (receipt, install_receipt_string) = read_receipt(receipt).await?;
 
-        let mut plan: InstallPlan = match serde_json::from_str(&install_receipt_string) {
-            Ok(plan) => plan,
-            Err(plan_err) => {
-                #[derive(serde::Deserialize)]
-                struct MinimalPlan {
-                    version: semver::Version,
-                }
-                let minimal_plan: Result<MinimalPlan, _> =
-                    serde_json::from_str(&install_receipt_string);
-                match minimal_plan {
-                    Ok(minimal_plan) => {
-                        return Err(plan_err).wrap_err_with(|| {
-                            let plan_version = minimal_plan.version;
-                            let current_version = current_version().map(|v| v.to_string()).unwrap_or_else(|_| env!("CARGO_PKG_VERSION").to_string());
-                            format!(
-                            "\
-                            Unable to parse plan, this plan was created by `nix-installer` version `{plan_version}`, this is `nix-installer` version `{current_version}`\n\
-                            To uninstall, either run  `/nix/nix-installer uninstall` or `curl --proto '=https' --tlsv1.2 -sSf -L https://install.determinate.systems/nix/tag/v{plan_version} | sh -s -- uninstall`\
-                            ").red().to_string()
-                        });
-                    },
-                    Err(_minimal_plan_err) => return Err(plan_err)?,
+            if let Err(err) =
+                receipt_signature::verify(&receipt, install_receipt_string.as_bytes()).await
+            {
+                let err = NixInstallerError::from(err);
+                if let Some(expected) = err.expected() {
+                    eprintln!("{}", expected.red());
+                    return Ok(output::error_failure(&err));
                 }
-            },
+                Err(err)?
+            }
+
+            match InstallPlan::from_receipt_lossy(&install_receipt_string) {
+                Ok(plan) => plan,
+                Err(plan_err) => {
+                    #[derive(serde::Deserialize)]
+                    struct MinimalPlan {
+                        version: semver::Version,
+                    }
+                    let minimal_plan: Result<MinimalPlan, _> =
+                        serde_json::from_str(&install_receipt_string);
+                    match minimal_plan {
+                        Ok(minimal_plan) => {
+                            return Err(plan_err).wrap_err_with(|| {
+                                let plan_version = minimal_plan.version;
+                                let current_version = current_version().map(|v| v.to_string()).unwrap_or_else(|_| env!("CARGO_PKG_VERSION").to_string());
+                                format!(
+                                "\
+                                Unable to parse plan, this plan was created by `nix-installer` version `{plan_version}`, this is `nix-installer` version `{current_version}`\n\
+                                To uninstall, either run  `/nix/nix-installer uninstall` or `curl --proto '=https' --tlsv1.2 -sSf -L https://install.determinate.systems/nix/tag/v{plan_version} | sh -s -- uninstall`\
+                                ").red().to_string()
+                            });
+                        },
+                        Err(_minimal_plan_err) => return Err(plan_err)?,
+                    }
+                },
+            }
         };
 
         if let Err(e) = plan.check_compatible() {
@@ -151,28 +251,104 @@ impl CommandExecute for Uninstall {
                     \n\
                 ").red()
             );
-            return Ok(ExitCode::FAILURE);
+            return Ok(output::error_failure(&e));
         }
 
         if let Err(err) = plan.pre_uninstall_check().await {
             if let Some(expected) = err.expected() {
                 eprintln!("{}", expected.red());
-                return Ok(ExitCode::FAILURE);
+                return Ok(output::error_failure(&err));
             }
             Err(err)?
         }
 
+        if keep_store {
+            plan.keep_store();
+        }
+
+        if dry_run {
+            let purged = if purge {
+                purge::purgeable().await
+            } else {
+                vec![]
+            };
+            if output::is_json() {
+                let actions = plan
+                    .actions
+                    .iter()
+                    .rev()
+                    .map(|action| {
+                        serde_json::json!({
+                            "synopsis": action.tracing_synopsis(),
+                            "steps": action
+                                .revert_dry_run()
+                                .iter()
+                                .map(ToString::to_string)
+                                .collect::<Vec<_>>(),
+                        })
+                    })
+                    .collect::<Vec<_>>();
+                output::result(
+                    "uninstall",
+                    true,
+                    serde_json::json!({
+                        "dry_run": actions,
+                        "keep_user_profiles": keep_user_profiles,
+                        "purge": purged,
+                    }),
+                );
+                return Ok(ExitCode::SUCCESS);
+            }
+            println!("{}", plan.describe_revert_dry_run());
+            if keep_user_profiles {
+                println!("* Keep the users' profiles\n# Copy the links in `/nix/var/nix/profiles/per-user` and `/nix/var/nix/gcroots/per-user` to `{USER_STATE_LOCATION}`\n");
+            }
+            if !purged.is_empty() {
+                println!(
+                    "* Purge the home of root and every user\n{}\n",
+                    purged
+                        .iter()
+                        .map(|path| format!("# Remove `{}`", path.display()))
+                        .collect::<Vec<_>>()
+                        .join("\n")
+                );
+            }
+            return Ok(ExitCode::SUCCESS);
+        }
+
+        output::ensure_no_prompt(no_confirm)?;
+
+        // Without a receipt, what was found is always shown before it is undone
+        if no_confirm && force_no_receipt && !output::is_json() {
+            eprintln!(
+                "{}",
+                plan.describe_uninstall(explain)
+                    .await
+                    .map_err(|e| eyre!(e))?
+            );
+        }
+
         if !no_confirm {
             let mut currently_explaining = explain;
             loop {
-                match interaction::prompt(
-                    plan.describe_uninstall(currently_explaining)
-                        .await
-                        .map_err(|e| eyre!(e))?,
-                    PromptChoice::Yes,
-                    currently_explaining,
-                )
-                .await?
+                let mut question = plan
+                    .describe_uninstall(currently_explaining)
+                    .await
+                    .map_err(|e| eyre!(e))?;
+                if keep_store {
+                    question.push_str("\n`/nix` will be kept.\n");
+                }
+                if purge {
+                    question.push_str(&format!(
+                        "\nThese will also be removed from the home of root and every user: {}\n",
+                        purge::USER_STATE
+                            .iter()
+                            .map(|state| format!("`~/{state}`"))
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                    ));
+                }
+                match interaction::prompt(question, PromptChoice::Yes, currently_explaining).await?
                 {
                     PromptChoice::Yes => break,
                     PromptChoice::Explain => currently_explaining = true,
@@ -183,9 +359,29 @@ impl CommandExecute for Uninstall {
             }
         }
 
+        // Collected before uninstalling, which marks every action uncompleted
+        let manual_steps = plan.manual_steps();
+
+        let saved_user_state = if keep_user_profiles {
+            Some(
+                user_state::save()
+                    .await
+                    .wrap_err("Saving the users' profiles")?,
+            )
+        } else {
+            None
+        };
+
         let (_tx, rx) = signal_channel().await?;
 
+        let progress_report = output::is_json().then(|| ProgressReport::start(plan.actions.len()));
         let res = plan.uninstall(rx).await;
+        if let Some(progress_report) = progress_report {
+            progress_report.finish().await;
+        }
+        if let Err(err) = &res {
+            crash_report::offer(&plan, err, crash_report.as_deref(), no_confirm).await?;
+        }
         match res {
             Err(err @ NixInstallerError::ActionRevert(_)) => {
                 tracing::error!("Uninstallation complete, some errors encountered");
@@ -193,21 +389,176 @@ impl CommandExecute for Uninstall {
             },
             Err(err) => {
                 if let Some(expected) = err.expected() {
-                    println!("{}", expected.red());
-                    return Ok(ExitCode::FAILURE);
+                    eprintln!("{}", expected.red());
+                    return Ok(output::error_failure(&err));
                 }
                 return Err(err)?;
             },
             _ => (),
         }
 
+        let purged = if purge { purge::purge().await } else { vec![] };
+
+        if output::is_json() {
+            output::result(
+                "uninstall",
+                true,
+                serde_json::json!({
+                    "manual_steps": manual_steps,
+                    "kept_store": keep_store,
+                    "purged": purged,
+                    "saved_user_state": saved_user_state,
+                }),
+            );
+            return Ok(ExitCode::SUCCESS);
+        }
         println!(
             "\
             {success}\n\
             ",
             success = "Nix was uninstalled successfully!".green().bold(),
         );
+        if keep_store {
+            println!("`/nix` was left in place.");
+        }
+        if let Some(saved_user_state) = &saved_user_state {
+            println!(
+                "The users' profiles ({} links to {} store paths) were kept in `{USER_STATE_LOCATION}`, the next `nix-installer install` puts them back.",
+                saved_user_state.links,
+                saved_user_state.store_paths.len()
+            );
+        }
+        for path in &purged {
+            println!("Removed `{}`", path.display());
+        }
+        if !manual_steps.is_empty() {
+            eprintln!(
+                "{}",
+                "The receipt recorded actions this `nix-installer` can't undo, undo them by hand:"
+                    .yellow()
+            );
+            for ActionDescription {
+                description,
+                explanation,
+            } in &manual_steps
+            {
+                eprintln!("* {description}");
+                for step in explanation {
+                    eprintln!("  - {step}");
+                }
+            }
+        }
 
         Ok(ExitCode::SUCCESS)
     }
 }
+
+/// Plan undoing what `nix-installer` would have installed, for `--force-no-receipt`
+///
+/// The install is [adopted](InstallPlan::adopt) with the settings found on the system (or the
+/// defaults, if they can't be), so only what is found in place is undone.
+async fn plan_cleanup() -> Result<InstallPlan, NixInstallerError> {
+    ensure_no_receipt()?;
+    let settings = CommonSettings::default().await?;
+    let settings = match detect_settings(settings.clone()).await {
+        Ok(settings) => settings,
+        Err(err) => {
+            tracing::warn!("{err}, cleaning up with the default settings");
+            settings
+        },
+    };
+    plan_adoption(settings).await
+}
+
+/// Read `receipt`, or if it is the default receipt and it is gone (or damaged), the copy kept outside of `/nix`
+pub(crate) async fn read_receipt(receipt: PathBuf) -> eyre::Result<(PathBuf, String)> {
+    read_receipt_at(
+        receipt,
+        Path::new(RECEIPT_LOCATION),
+        Path::new(RECEIPT_BACKUP_LOCATION),
+    )
+    .await
+}
+
+async fn read_receipt_at(
+    receipt: PathBuf,
+    default: &Path,
+    backup: &Path,
+) -> eyre::Result<(PathBuf, String)> {
+    let read = tokio::fs::read_to_string(&receipt).await;
+    if receipt != default {
+        return Ok((receipt, read.wrap_err("Reading receipt")?));
+    }
+    let problem = match &read {
+        Ok(contents) => match serde_json::from_str::<serde_json::Value>(contents) {
+            Ok(_) => None,
+            Err(err) => Some(format!("could not be parsed ({err})")),
+        },
+        Err(err) => Some(format!("could not be read ({err})")),
+    };
+    match problem {
+        Some(problem) if backup.exists() => {
+            eprintln!(
+                "{}",
+                format!(
+                    "The receipt `{}` {problem}, using the copy at `{}`",
+                    default.display(),
+                    backup.display()
+                )
+                .yellow()
+            );
+            let contents = tokio::fs::read_to_string(backup)
+                .await
+                .wrap_err_with(|| format!("Reading receipt `{}`", backup.display()))?;
+            Ok((backup.to_path_buf(), contents))
+        },
+        None => Ok((receipt, read.wrap_err("Reading receipt")?)),
+        Some(_) => Ok((
+            receipt,
+            read.wrap_err("Reading receipt")
+                .suggestion("If the receipt was lost, `nix-installer uninstall --force-no-receipt` undoes what is found of the install")?,
+        )),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{receipt_signature, InstallPlan};
+
+    use super::read_receipt_at;
+
+    #[tokio::test]
+    async fn uninstalls_from_the_copy_once_nix_is_gone() -> eyre::Result<()> {
+        let nix = tempfile::tempdir()?;
+        let state_dir = tempfile::tempdir()?;
+        let receipt = nix.path().join("receipt.json");
+        let backup = state_dir.path().join("receipt.json");
+        let machine_key = state_dir.path().join("receipt.key");
+        let signed_marker = state_dir.path().join("receipt.signed");
+
+        let key = b"a key which is long enough";
+        let contents = include_str!("../../../tests/fixtures/linux/linux.json");
+        std::fs::write(&machine_key, key)?;
+        std::fs::write(&signed_marker, "")?;
+        std::fs::write(&backup, contents)?;
+        std::fs::write(
+            receipt_signature::signature_path(&backup),
+            receipt_signature::signature(key, contents.as_bytes()),
+        )?;
+        std::fs::write(&receipt, contents)?;
+        std::fs::write(nix.path().join("receipt.key"), key)?;
+        // `/nix` was wiped, along with the receipt (and the key, where it used to be kept)
+        std::fs::remove_file(&receipt)?;
+        std::fs::remove_file(nix.path().join("receipt.key"))?;
+
+        let (read_from, read) = read_receipt_at(receipt.clone(), &receipt, &backup).await?;
+        assert_eq!(read_from, backup);
+        assert_eq!(
+            receipt_signature::verify_at(&read_from, read.as_bytes(), &machine_key, &signed_marker)
+                .await?,
+            receipt_signature::Verification::Verified
+        );
+        InstallPlan::from_receipt_lossy(&read)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,288 @@
+use std::{path::PathBuf, process::ExitCode, time::SystemTime};
+
+use clap::{ArgAction, Parser};
+use eyre::WrapErr;
+use owo_colors::OwoColorize;
+use tokio::process::Command;
+
+use crate::{
+    action::{
+        base::{
+            DownloadPolicy, FetchAndUnpackNix, MoveUnpackedNix, NixPackageVerification,
+            RemoveDirectory, SetupDefaultProfile,
+        },
+        Action, StatefulAction,
+    },
+    cli::{
+        ensure_lock, ensure_root,
+        interaction::{self, PromptChoice},
+        CommandExecute,
+    },
+    execute_command,
+    plan::RECEIPT_LOCATION,
+    settings::{
+        default_download_retries, default_download_timeout, parse_nix_version, Distribution,
+        InitSystem, UrlOrPath, SCRATCH_DIR,
+    },
+    InstallPlan,
+};
+
+/**
+Upgrade the Nix recorded in the install receipt to another release
+
+Only the Nix package and the default profile are changed, then the Nix daemon is restarted.
+Users, volumes, and shell configuration are left as they are.
+*/
+#[derive(Debug, Parser)]
+pub struct Upgrade {
+    /// Run the upgrade without requiring explicit user confirmation
+    #[clap(
+        long,
+        env = "NIX_INSTALLER_NO_CONFIRM",
+        action(ArgAction::SetTrue),
+        default_value = "false"
+    )]
+    pub no_confirm: bool,
+
+    /// The Nix version (eg `2.24.9`) to upgrade to, see `nix-installer list-versions`
+    ///
+    /// Without this (or `--nix-package-url`), the Nix this `nix-installer` would install is used.
+    #[clap(
+        long,
+        env = "NIX_INSTALLER_NIX_VERSION",
+        value_parser = parse_nix_version,
+        conflicts_with = "nix_package_url"
+    )]
+    pub nix_version: Option<String>,
+
+    /// The Nix package URL (or path) to upgrade to
+    #[clap(long, env = "NIX_INSTALLER_NIX_PACKAGE_URL", value_parser = clap::value_parser!(UrlOrPath))]
+    pub nix_package_url: Option<UrlOrPath>,
+
+    /// The proxy to use (if any); valid proxy bases are `https://$URL`, `http://$URL`, `socks5://$URL`, and `socks5h://$URL`
+    #[clap(long, env = "NIX_INSTALLER_PROXY")]
+    pub proxy: Option<url::Url>,
+
+    /// An SSL cert to use (if any) for fetching Nix
+    #[clap(long, env = "NIX_INSTALLER_SSL_CERT_FILE")]
+    pub ssl_cert_file: Option<PathBuf>,
+}
+
+#[async_trait::async_trait]
+impl CommandExecute for Upgrade {
+    #[tracing::instrument(level = "debug", skip_all, fields())]
+    async fn execute(self) -> eyre::Result<ExitCode> {
+        let Self {
+            no_confirm,
+            nix_version,
+            nix_package_url,
+            proxy,
+            ssl_cert_file,
+        } = self;
+
+        ensure_root()?;
+        let _lock = match ensure_lock()? {
+            Ok(lock) => lock,
+            Err(exit_code) => return Ok(exit_code),
+        };
+
+        let receipt_string = tokio::fs::read_to_string(RECEIPT_LOCATION)
+            .await
+            .wrap_err_with(|| {
+                format!("Reading `{RECEIPT_LOCATION}`, only Nix installed by `nix-installer` can be upgraded")
+            })?;
+        let receipt = InstallPlan::from_receipt(&receipt_string).wrap_err_with(|| {
+            format!("Unable to parse existing receipt `{RECEIPT_LOCATION}`, it may be from an incompatible version of `nix-installer`")
+        })?;
+
+        let distribution = receipt.distribution();
+        if distribution == Distribution::Determinate {
+            eprintln!(
+                "{}",
+                "Determinate Nix is upgraded by `determinate-nixd`, run `sudo determinate-nixd upgrade` instead".red()
+            );
+            return Ok(ExitCode::FAILURE);
+        }
+
+        let nix_package = match (nix_package_url, &nix_version) {
+            (Some(nix_package_url), _) => Some(nix_package_url),
+            (None, Some(nix_version)) => {
+                Some(UrlOrPath::Url(distribution.nix_package_url(nix_version)))
+            },
+            (None, None) => distribution.default_nix_package_url(),
+        };
+
+        if !no_confirm {
+            let question = format!(
+                "\
+                Upgrade {distribution} Nix to {package}\n\
+                \n\
+                The default profile will be switched to the new Nix, and the Nix daemon restarted.\n\
+                ",
+                package = match &nix_package {
+                    Some(nix_package) => format!("`{nix_package}`"),
+                    None => "the version bundled with this `nix-installer`".into(),
+                },
+            );
+            loop {
+                match interaction::prompt(&question, PromptChoice::Yes, true).await? {
+                    PromptChoice::Yes => break,
+                    PromptChoice::Explain => (),
+                    PromptChoice::No => {
+                        interaction::clean_exit_with_message("Okay, didn't do anything! Bye!").await
+                    },
+                }
+            }
+        }
+
+        let mut upgrade_actions: Vec<StatefulAction<Box<dyn Action>>> = vec![
+            FetchAndUnpackNix::plan(
+                nix_package.clone(),
+                vec![],
+                PathBuf::from(SCRATCH_DIR),
+                proxy,
+                ssl_cert_file,
+                DownloadPolicy {
+                    retries: default_download_retries(),
+                    timeout: Some(default_download_timeout()),
+                    ..Default::default()
+                },
+                NixPackageVerification::default(),
+            )
+            .await?
+            .boxed(),
+            // The store is live, so paths already in it must stay where they are
+            MoveUnpackedNix::plan(PathBuf::from(SCRATCH_DIR), distribution, true)
+                .await?
+                .boxed(),
+            SetupDefaultProfile::plan(PathBuf::from(SCRATCH_DIR), distribution)
+                .await?
+                .boxed(),
+        ];
+        for action in upgrade_actions.iter_mut() {
+            action.try_execute().await?;
+        }
+
+        restart_daemon(receipt_init(&receipt)).await?;
+
+        let mut remove_scratch_dir = RemoveDirectory::plan(SCRATCH_DIR).await?;
+        if let Err(err) = remove_scratch_dir.try_execute().await {
+            tracing::warn!(%err, "Could not remove `{SCRATCH_DIR}`");
+        }
+
+        let timestamp_millis = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)?
+            .as_millis();
+        let mut old_receipt = PathBuf::from(RECEIPT_LOCATION);
+        old_receipt.set_extension(format!("pre-upgrade.{timestamp_millis}.json"));
+        tokio::fs::copy(RECEIPT_LOCATION, &old_receipt).await?;
+        tracing::info!("Backed up pre-upgrade receipt to {}", old_receipt.display());
+
+        let updated_receipt = record_upgrade(&receipt_string, nix_package.as_ref(), nix_version)?;
+        updated_receipt.write_receipt().await?;
+        tracing::info!("Wrote updated receipt");
+
+        println!("{}", "Nix was upgraded successfully!".green().bold());
+
+        Ok(ExitCode::SUCCESS)
+    }
+}
+
+/// The init system the receipt configured, planners which don't record one always use launchd (macOS) or systemd
+pub(super) fn receipt_init(receipt: &InstallPlan) -> InitSystem {
+    receipt
+        .planner
+        .settings()
+        .ok()
+        .and_then(|settings| settings.get("init").cloned())
+        .and_then(|init| serde_json::from_value(init).ok())
+        .unwrap_or(if cfg!(target_os = "macos") {
+            InitSystem::Launchd
+        } else {
+            InitSystem::Systemd
+        })
+}
+
+#[tracing::instrument(level = "debug")]
+pub(super) async fn restart_daemon(init: InitSystem) -> eyre::Result<()> {
+    match init {
+        InitSystem::Systemd => {
+            // Socket activated installs may not have the service running, which is fine
+            execute_command(
+                Command::new("systemctl")
+                    .process_group(0)
+                    .args(["try-restart", "nix-daemon.service"])
+                    .stdin(std::process::Stdio::null()),
+            )
+            .await
+            .wrap_err("Restarting the Nix daemon")?;
+        },
+        InitSystem::Launchd => {
+            execute_command(
+                Command::new("launchctl")
+                    .process_group(0)
+                    .args(["kickstart", "-k", "system/org.nixos.nix-daemon"])
+                    .stdin(std::process::Stdio::null()),
+            )
+            .await
+            .wrap_err("Restarting the Nix daemon")?;
+        },
+        InitSystem::None => (),
+    }
+    Ok(())
+}
+
+/// Record the Nix package in the receipt's planner settings, as if it had been installed with it
+fn record_upgrade(
+    receipt: &str,
+    nix_package: Option<&UrlOrPath>,
+    nix_version: Option<String>,
+) -> eyre::Result<InstallPlan> {
+    let mut receipt: serde_json::Value = serde_json::from_str(receipt)?;
+    crate::migration::migrate(&mut receipt)?;
+    if let Some(settings) = receipt
+        .get_mut("planner")
+        .and_then(|planner| planner.get_mut("settings"))
+        .and_then(|settings| settings.as_object_mut())
+    {
+        settings.insert("nix_package_url".into(), serde_json::to_value(nix_package)?);
+        settings.insert("nix_package_file".into(), serde_json::Value::Null);
+        settings.insert("nix_version".into(), serde_json::to_value(nix_version)?);
+    }
+    Ok(serde_json::from_value(receipt)?)
+}
+
+#[cfg(test)]
+mod test {
+    use super::record_upgrade;
+    use crate::settings::UrlOrPath;
+
+    #[test]
+    fn records_upgrade_in_receipt() -> eyre::Result<()> {
+        let receipt = include_str!("../../../tests/fixtures/linux/linux.json");
+        let url = url::Url::parse(
+            "https://releases.nixos.org/nix/nix-2.24.9/nix-2.24.9-x86_64-linux.tar.xz",
+        )?;
+        let upgraded = record_upgrade(
+            receipt,
+            Some(&UrlOrPath::Url(url.clone())),
+            Some("2.24.9".into()),
+        )?;
+        let settings = upgraded.planner.settings()?;
+        assert_eq!(
+            settings.get("nix_package_url"),
+            Some(&serde_json::to_value(Some(UrlOrPath::Url(url)))?)
+        );
+        assert_eq!(
+            settings.get("nix_version"),
+            Some(&serde_json::Value::from("2.24.9"))
+        );
+        // Only the settings change, the recorded actions are kept as they were
+        let original: serde_json::Value = serde_json::from_str(receipt)?;
+        assert_eq!(
+            Some(upgraded.actions.len()),
+            original["actions"].as_array().map(Vec::len)
+        );
+        Ok(())
+    }
+}
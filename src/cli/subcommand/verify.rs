@@ -0,0 +1,90 @@
+use std::{path::PathBuf, process::ExitCode};
+
+use clap::Parser;
+use eyre::WrapErr;
+use owo_colors::OwoColorize;
+
+use super::uninstall::read_receipt;
+use crate::{
+    cli::{output, CommandExecute},
+    drift::detect,
+    plan::RECEIPT_LOCATION,
+    InstallPlan,
+};
+
+/**
+Compare the install receipt against the system, and report the actions it has drifted from
+
+Like a removed build user, a modified `nix.conf` or service, a shell profile which no longer sets up
+Nix, or an unmounted Nix volume. Nothing is changed, each drifted action is listed with the
+`nix-installer repair` which fixes it, where there is one.
+*/
+#[derive(Debug, Parser)]
+pub struct Verify {
+    #[clap(default_value = RECEIPT_LOCATION)]
+    pub receipt: PathBuf,
+}
+
+#[async_trait::async_trait]
+impl CommandExecute for Verify {
+    #[tracing::instrument(level = "debug", skip_all, fields())]
+    async fn execute(self) -> eyre::Result<ExitCode> {
+        let Self { receipt } = self;
+
+        let (receipt, receipt_string) = read_receipt(receipt).await?;
+        let plan = InstallPlan::from_receipt_lossy(&receipt_string)
+            .wrap_err_with(|| format!("Unable to parse the receipt `{}`", receipt.display()))?;
+
+        let drifted = detect(&plan).await;
+        if output::is_json() {
+            let report = drifted
+                .iter()
+                .map(|drifted| {
+                    serde_json::json!({
+                        "action": drifted.action,
+                        "drift": drifted.drift,
+                        "repairs": drifted.repairs(),
+                    })
+                })
+                .collect::<Vec<_>>();
+            output::result(
+                "verify",
+                drifted.is_empty(),
+                serde_json::json!({ "drifted": report }),
+            );
+        } else if drifted.is_empty() {
+            println!(
+                "{}",
+                "No drift found, the system matches the receipt."
+                    .green()
+                    .bold()
+            );
+        } else {
+            for drifted in &drifted {
+                println!("{}", drifted.action.bold());
+                for drift in &drifted.drift {
+                    println!("  - {}", drift.to_string().red());
+                }
+                match drifted.repairs().as_slice() {
+                    [] => println!(
+                        "  Fix: no `nix-installer repair` covers this, uninstall with `/nix/nix-installer uninstall` and install again"
+                    ),
+                    repairs => println!(
+                        "  Fix: {}",
+                        repairs
+                            .iter()
+                            .map(|repair| format!("`{repair}`"))
+                            .collect::<Vec<_>>()
+                            .join(", then ")
+                    ),
+                }
+            }
+        }
+
+        if drifted.is_empty() {
+            Ok(ExitCode::SUCCESS)
+        } else {
+            Ok(ExitCode::FAILURE)
+        }
+    }
+}
@@ -3,9 +3,16 @@
 When enabled with the `diagnostics` feature (default) this module provides automated install success/failure reporting to an endpoint.
 
 That endpoint can be a URL such as `https://our.project.org/nix-installer/diagnostics` or `file:///home/$USER/diagnostic.json` which receives a [`DiagnosticReport`] in JSON format.
+
+For air-gapped machines, reports can instead be added to a local file ([`DiagnosticData::report_file`]), a JSON array of every [`DiagnosticReport`], to be forwarded by other means. Nothing is sent over the network then.
+
+A self-hosted endpoint may require a client certificate ([`DiagnosticData::client_cert`]), and parts of the report can be left out with [`DiagnosticData::redact`].
 */
 
-use std::{path::PathBuf, time::Duration};
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
 
 use os_release::OsRelease;
 use reqwest::Url;
@@ -31,6 +38,26 @@ pub enum DiagnosticAction {
     Uninstall,
 }
 
+/// Parts of the [`DiagnosticReport`] to leave out, for endpoints which shouldn't identify machines
+#[derive(Debug, serde::Deserialize, serde::Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum DiagnosticRedaction {
+    /// Replace the hostname wherever it appears (like in the attribution) with `<hostname>`
+    Hostname,
+    /// Only report the architecture and OS of the triple, like `x86_64-linux`
+    Triple,
+}
+
+impl std::fmt::Display for DiagnosticRedaction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DiagnosticRedaction::Hostname => write!(f, "hostname"),
+            DiagnosticRedaction::Triple => write!(f, "triple"),
+        }
+    }
+}
+
 /// A report sent to an endpoint
 #[derive(Debug, serde::Deserialize, serde::Serialize, Clone)]
 pub struct DiagnosticReport {
@@ -61,6 +88,14 @@ pub struct DiagnosticData {
     is_ci: bool,
     endpoint: Option<Url>,
     ssl_cert_file: Option<PathBuf>,
+    /// A PEM file with the certificate (and private key) to authenticate to the endpoint with
+    #[serde(default)]
+    client_cert: Option<PathBuf>,
+    #[serde(default)]
+    redactions: Vec<DiagnosticRedaction>,
+    /// A file reports are added to, instead of being sent to the `endpoint`
+    #[serde(default)]
+    report_file: Option<PathBuf>,
     /// Generally this includes the [`strum::IntoStaticStr`] representation of the error, we take special care not to include parameters of the error (which may include secrets)
     failure_chain: Option<Vec<String>>,
 }
@@ -94,10 +129,35 @@ impl DiagnosticData {
             triple: target_lexicon::HOST.to_string(),
             is_ci,
             ssl_cert_file: ssl_cert_file.and_then(|v| v.canonicalize().ok()),
+            client_cert: None,
+            redactions: Vec::new(),
+            report_file: None,
             failure_chain: None,
         })
     }
 
+    /// Authenticate to the endpoint with the certificate and private key in the PEM file `client_cert`
+    pub fn client_cert(mut self, client_cert: Option<PathBuf>) -> Self {
+        self.client_cert = client_cert.and_then(|v| v.canonicalize().ok());
+        self
+    }
+
+    /// Add reports to the JSON array in `report_file`, instead of sending them to the endpoint
+    pub fn report_file(mut self, report_file: Option<PathBuf>) -> Self {
+        // The install may run from a different directory than the plan was made in
+        self.report_file = report_file.map(|path| match std::env::current_dir() {
+            Ok(cwd) if path.is_relative() => cwd.join(path),
+            _ => path,
+        });
+        self
+    }
+
+    /// Leave these parts out of reports
+    pub fn redact(mut self, redactions: Vec<DiagnosticRedaction>) -> Self {
+        self.redactions = redactions;
+        self
+    }
+
     pub fn failure(mut self, err: &NixInstallerError) -> Self {
         let mut failure_chain = vec![];
         let diagnostic = err.diagnostic();
@@ -145,9 +205,12 @@ impl DiagnosticData {
             is_ci,
             endpoint: _,
             ssl_cert_file: _,
+            client_cert: _,
+            redactions,
+            report_file: _,
             failure_chain,
         } = self;
-        DiagnosticReport {
+        let report = DiagnosticReport {
             attribution: attribution.clone(),
             version: version.clone(),
             planner: planner.clone(),
@@ -159,7 +222,18 @@ impl DiagnosticData {
             action,
             status,
             failure_chain: failure_chain.clone(),
-        }
+        };
+
+        let hostname = redactions
+            .contains(&DiagnosticRedaction::Hostname)
+            .then(|| nix::unistd::gethostname().ok())
+            .flatten()
+            .map(|hostname| hostname.to_string_lossy().into_owned());
+        redact(
+            report,
+            hostname.as_deref(),
+            redactions.contains(&DiagnosticRedaction::Triple),
+        )
     }
 
     #[tracing::instrument(level = "debug", skip_all)]
@@ -168,7 +242,15 @@ impl DiagnosticData {
         action: DiagnosticAction,
         status: DiagnosticStatus,
     ) -> Result<(), DiagnosticError> {
-        let serialized = serde_json::to_string_pretty(&self.report(action, status))?;
+        let report = self.report(action, status);
+        if let Some(report_file) = &self.report_file {
+            tracing::debug!("Adding diagnostic to `{}`", report_file.display());
+            if let Err(err) = append_report(report_file, &report).await {
+                tracing::warn!(%err, "Failed to add diagnostic to `{}`, continuing", report_file.display())
+            }
+            return Ok(());
+        }
+        let serialized = serde_json::to_string_pretty(&report)?;
 
         let endpoint = match self.endpoint {
             Some(endpoint) => endpoint,
@@ -185,6 +267,13 @@ impl DiagnosticData {
                         buildable_client = buildable_client.add_root_certificate(ssl_cert);
                     }
                 }
+                if let Some(client_cert) = &self.client_cert {
+                    let pem = tokio::fs::read(client_cert)
+                        .await
+                        .map_err(|e| DiagnosticError::ClientCert(client_cert.clone(), e))?;
+                    buildable_client =
+                        buildable_client.identity(reqwest::Identity::from_pem(&pem)?);
+                }
                 let client = buildable_client.build().map_err(DiagnosticError::Reqwest)?;
 
                 let res = client
@@ -242,6 +331,48 @@ pub enum DiagnosticError {
     ),
     #[error(transparent)]
     Certificate(#[from] CertificateError),
+    #[error("Reading the diagnostic client certificate `{0}`")]
+    ClientCert(std::path::PathBuf, #[source] std::io::Error),
+}
+
+/// Add `report` to the JSON array in `path`, atomically through a temporary file beside it
+async fn append_report(path: &Path, report: &DiagnosticReport) -> Result<(), DiagnosticError> {
+    let mut reports: Vec<serde_json::Value> = match tokio::fs::read(path).await {
+        Ok(existing) if !existing.is_empty() => serde_json::from_slice(&existing)?,
+        Ok(_) => vec![],
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => vec![],
+        Err(e) => return Err(DiagnosticError::Write(path.to_path_buf(), e)),
+    };
+    reports.push(serde_json::to_value(report)?);
+
+    let mut path_tmp = path.to_path_buf();
+    path_tmp.set_extension("tmp");
+    let contents = format!("{}\n", serde_json::to_string_pretty(&reports)?);
+    tokio::fs::write(&path_tmp, contents)
+        .await
+        .map_err(|e| DiagnosticError::Write(path_tmp.clone(), e))?;
+    tokio::fs::rename(&path_tmp, path)
+        .await
+        .map_err(|e| DiagnosticError::Write(path.to_path_buf(), e))
+}
+
+/// Leave the `hostname` (if any), and the details of the triple (if `triple`), out of `report`
+fn redact(mut report: DiagnosticReport, hostname: Option<&str>, triple: bool) -> DiagnosticReport {
+    if let Some(hostname) = hostname.filter(|hostname| !hostname.is_empty()) {
+        let strip = |value: &mut String| *value = value.replace(hostname, "<hostname>");
+        report.attribution.iter_mut().for_each(&strip);
+        strip(&mut report.os_name);
+        strip(&mut report.os_version);
+        report.configured_settings.iter_mut().for_each(&strip);
+        report.failure_chain.iter_mut().flatten().for_each(&strip);
+    }
+    if triple {
+        report.triple = match report.triple.parse::<target_lexicon::Triple>() {
+            Ok(parsed) => format!("{}-{}", parsed.architecture, parsed.operating_system),
+            Err(_) => "unknown".into(),
+        };
+    }
+    report
 }
 
 pub trait ErrorDiagnostic {
@@ -275,3 +406,57 @@ pub fn diagnostic_endpoint_validator(input: &str) -> Result<String, DiagnosticEr
     let _ = diagnostic_endpoint_parser(input)?;
     Ok(input.to_string())
 }
+
+#[cfg(test)]
+mod test {
+    use super::{append_report, redact, DiagnosticAction, DiagnosticData, DiagnosticStatus};
+
+    #[test]
+    fn redacts_hostname_and_triple() {
+        let data = DiagnosticData {
+            attribution: Some("ci-builder-7 nightly".into()),
+            triple: "x86_64-unknown-linux-gnu".into(),
+            failure_chain: Some(vec!["ActionError".into()]),
+            ..Default::default()
+        };
+        let report = data.report(DiagnosticAction::Install, DiagnosticStatus::Failure);
+
+        let untouched = redact(report.clone(), None, false);
+        assert_eq!(untouched.attribution, report.attribution);
+        assert_eq!(untouched.triple, "x86_64-unknown-linux-gnu");
+
+        let redacted = redact(report, Some("ci-builder-7"), true);
+        assert_eq!(redacted.attribution.as_deref(), Some("<hostname> nightly"));
+        assert_eq!(redacted.failure_chain, Some(vec!["ActionError".into()]));
+        assert_eq!(redacted.triple, "x86_64-linux");
+    }
+
+    #[tokio::test]
+    async fn appends_to_report_files() -> eyre::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let report_file = temp_dir.path().join("diagnostics.json");
+        let data = DiagnosticData::default().report_file(Some(report_file.clone()));
+        for status in [DiagnosticStatus::Failure, DiagnosticStatus::Success] {
+            append_report(
+                &report_file,
+                &data.report(DiagnosticAction::Install, status),
+            )
+            .await?;
+        }
+
+        let reports: Vec<serde_json::Value> =
+            serde_json::from_slice(&tokio::fs::read(&report_file).await?)?;
+        assert_eq!(reports.len(), 2);
+        assert_eq!(reports[0]["status"], "Failure");
+        assert_eq!(reports[1]["status"], "Success");
+
+        tokio::fs::write(&report_file, "not json").await?;
+        assert!(append_report(
+            &report_file,
+            &data.report(DiagnosticAction::Install, DiagnosticStatus::Success)
+        )
+        .await
+        .is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,317 @@
+//! Non-mutating checks of an existing Nix install, see `nix-installer doctor`
+
+use std::path::{Path, PathBuf};
+
+use crate::{
+    action::{ActionState, StatefulAction},
+    manifest::NIX_BIN,
+    plan::{JOURNAL_LOCATION, RECEIPT_LOCATION},
+    InstallPlan,
+};
+
+const NIX_STORE: &str = "/nix/store";
+const DAEMON_SOCKET: &str = "/nix/var/nix/daemon-socket/socket";
+const SYNTHETIC_CONF: &str = "/etc/synthetic.conf";
+const BACKUP_SUFFIX: &str = ".backup-before-nix";
+const BACKUP_DIRS: &[&str] = &["/etc", "/etc/zsh"];
+
+#[non_exhaustive]
+#[derive(thiserror::Error, Debug)]
+pub enum DoctorError {
+    #[error("Removing `{0}`")]
+    Remove(PathBuf, #[source] std::io::Error),
+    #[error("Reading `{0}`")]
+    Read(PathBuf, #[source] std::io::Error),
+    #[error("Writing `{0}`")]
+    Write(PathBuf, #[source] std::io::Error),
+}
+
+/// The checks run by [`diagnose`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Check {
+    PathOrdering,
+    DaemonSocket,
+    EtcBackups,
+    SyntheticConf,
+    Receipt,
+}
+
+impl std::fmt::Display for Check {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Check::PathOrdering => "PATH ordering",
+            Check::DaemonSocket => "Nix daemon socket",
+            Check::EtcBackups => "`/etc` backups",
+            Check::SyntheticConf => "`/etc/synthetic.conf`",
+            Check::Receipt => "Install receipt",
+        };
+        write!(f, "{name}")
+    }
+}
+
+/// A fix which only removes things Nix left behind, so can be applied without asking
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum SafeFix {
+    RemoveBrokenSymlink(PathBuf),
+    RemoveSyntheticConfEntry,
+}
+
+/// A problem found by [`diagnose`], with how to fix it
+#[derive(Debug, Clone)]
+pub struct Finding {
+    pub check: Check,
+    pub problem: String,
+    pub fix: String,
+    safe_fix: Option<SafeFix>,
+}
+
+impl Finding {
+    /// If `nix-installer doctor --fix` can fix this itself
+    pub fn has_safe_fix(&self) -> bool {
+        self.safe_fix.is_some()
+    }
+
+    #[tracing::instrument(level = "debug", skip_all, fields(check = %self.check))]
+    pub async fn apply_safe_fix(&self) -> Result<(), DoctorError> {
+        match &self.safe_fix {
+            Some(SafeFix::RemoveBrokenSymlink(path)) => tokio::fs::remove_file(path)
+                .await
+                .map_err(|e| DoctorError::Remove(path.clone(), e)),
+            Some(SafeFix::RemoveSyntheticConfEntry) => {
+                let path = PathBuf::from(SYNTHETIC_CONF);
+                let contents = tokio::fs::read_to_string(&path)
+                    .await
+                    .map_err(|e| DoctorError::Read(path.clone(), e))?;
+                let contents = remove_synthetic_conf_nix_entries(&contents);
+                if contents.trim().is_empty() {
+                    tokio::fs::remove_file(&path)
+                        .await
+                        .map_err(|e| DoctorError::Remove(path.clone(), e))
+                } else {
+                    tokio::fs::write(&path, contents)
+                        .await
+                        .map_err(|e| DoctorError::Write(path.clone(), e))
+                }
+            },
+            None => Ok(()),
+        }
+    }
+}
+
+/// Run every check, returning the problems found
+#[tracing::instrument(level = "debug", skip_all)]
+pub async fn diagnose() -> Vec<Finding> {
+    let mut findings = vec![];
+    findings.extend(check_path_ordering());
+    findings.extend(check_daemon_socket().await);
+    findings.extend(check_etc_backups().await);
+    findings.extend(check_synthetic_conf().await);
+    findings.extend(check_receipt().await);
+    findings
+}
+
+fn check_path_ordering() -> Option<Finding> {
+    if !Path::new(NIX_BIN).exists() {
+        return None;
+    }
+    let path = std::env::var_os("PATH").unwrap_or_default();
+    let first_nix = std::env::split_paths(&path)
+        .map(|dir| dir.join("nix"))
+        .find(|nix| nix.is_file());
+
+    match first_nix {
+        None => Some(Finding {
+            check: Check::PathOrdering,
+            problem: "`nix` is installed but not on `PATH`".into(),
+            fix: "Open a new shell, or source `/nix/var/nix/profiles/default/etc/profile.d/nix-daemon.sh`; if that doesn't help run `nix-installer repair`".into(),
+            safe_fix: None,
+        }),
+        Some(nix) if !is_nix_managed(&nix) => Some(Finding {
+            check: Check::PathOrdering,
+            problem: format!(
+                "`{}` comes before the installed `nix` on `PATH`",
+                nix.display()
+            ),
+            fix: format!(
+                "Remove `{}`, or move `/nix/var/nix/profiles/default/bin` ahead of `{}` on `PATH`",
+                nix.display(),
+                nix.parent().unwrap_or(Path::new("/")).display()
+            ),
+            safe_fix: None,
+        }),
+        Some(_) => None,
+    }
+}
+
+/// If a `nix` comes from a Nix profile rather than some other package manager
+fn is_nix_managed(nix: &Path) -> bool {
+    nix.starts_with("/nix")
+        || nix.starts_with("/run/current-system")
+        || nix.starts_with("/etc/profiles/per-user")
+        || nix
+            .components()
+            .any(|component| component.as_os_str() == ".nix-profile")
+}
+
+async fn check_daemon_socket() -> Option<Finding> {
+    if !Path::new(NIX_STORE).exists() {
+        return None;
+    }
+    let restart = if cfg!(target_os = "macos") {
+        "sudo launchctl kickstart -k system/org.nixos.nix-daemon"
+    } else {
+        "sudo systemctl restart nix-daemon.socket"
+    };
+    if !Path::new(DAEMON_SOCKET).exists() {
+        return Some(Finding {
+            check: Check::DaemonSocket,
+            problem: format!("`{DAEMON_SOCKET}` does not exist, the Nix daemon is not running"),
+            fix: format!(
+                "Start the Nix daemon with `{restart}` (not needed for `--init none` installs)"
+            ),
+            safe_fix: None,
+        });
+    }
+    match tokio::net::UnixStream::connect(DAEMON_SOCKET).await {
+        Ok(_) => None,
+        Err(err) => Some(Finding {
+            check: Check::DaemonSocket,
+            problem: format!("Connecting to `{DAEMON_SOCKET}` failed: {err}"),
+            fix: format!("Restart the Nix daemon with `{restart}`"),
+            safe_fix: None,
+        }),
+    }
+}
+
+async fn check_etc_backups() -> Vec<Finding> {
+    let mut findings = vec![];
+    for dir in BACKUP_DIRS {
+        let Ok(mut entries) = tokio::fs::read_dir(dir).await else {
+            continue;
+        };
+        while let Ok(Some(entry)) = entries.next_entry().await {
+            let path = entry.path();
+            let is_backup = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| name.ends_with(BACKUP_SUFFIX));
+            if !is_backup {
+                continue;
+            }
+            let is_symlink = tokio::fs::symlink_metadata(&path)
+                .await
+                .is_ok_and(|metadata| metadata.file_type().is_symlink());
+            // `metadata` follows the link, so fails if the target is gone
+            if is_symlink && tokio::fs::metadata(&path).await.is_err() {
+                findings.push(Finding {
+                    check: Check::EtcBackups,
+                    problem: format!("`{}` is a broken symlink", path.display()),
+                    fix: format!("Remove `{}`, it has nothing to restore", path.display()),
+                    safe_fix: Some(SafeFix::RemoveBrokenSymlink(path)),
+                });
+            }
+        }
+    }
+    findings
+}
+
+async fn check_synthetic_conf() -> Option<Finding> {
+    let contents = tokio::fs::read_to_string(SYNTHETIC_CONF).await.ok()?;
+    let has_nix_entry = contents != remove_synthetic_conf_nix_entries(&contents);
+    if has_nix_entry && !Path::new(NIX_STORE).exists() {
+        Some(Finding {
+            check: Check::SyntheticConf,
+            problem: format!("`{SYNTHETIC_CONF}` creates `/nix`, but Nix is not installed"),
+            fix: format!("Remove the `nix` line from `{SYNTHETIC_CONF}` and reboot"),
+            safe_fix: Some(SafeFix::RemoveSyntheticConfEntry),
+        })
+    } else {
+        None
+    }
+}
+
+/// Drop the lines of a `synthetic.conf` which create `/nix`
+fn remove_synthetic_conf_nix_entries(contents: &str) -> String {
+    contents
+        .split_inclusive('\n')
+        .filter(|line| line.split_whitespace().next() != Some("nix"))
+        .collect()
+}
+
+async fn check_receipt() -> Option<Finding> {
+    // An install interrupted before writing the receipt only has a journal
+    let location = [JOURNAL_LOCATION, RECEIPT_LOCATION]
+        .into_iter()
+        .find(|location| Path::new(location).exists())?;
+    let receipt = tokio::fs::read_to_string(location).await.ok()?;
+    let plan = match InstallPlan::from_receipt(&receipt) {
+        Ok(plan) => plan,
+        Err(err) => {
+            return Some(Finding {
+                check: Check::Receipt,
+                problem: format!("`{location}` could not be read: {err}"),
+                fix: "It was likely written by an incompatible `nix-installer`, use `/nix/nix-installer` to uninstall".into(),
+                safe_fix: None,
+            })
+        },
+    };
+    let incomplete = incomplete_actions(&plan.actions);
+    if incomplete.is_empty() {
+        None
+    } else {
+        Some(Finding {
+            check: Check::Receipt,
+            problem: format!(
+                "`{location}` records a partially applied install, {} action(s) did not complete: {}",
+                incomplete.len(),
+                incomplete.join(", ")
+            ),
+            fix: "Resume the install with `nix-installer resume`, or remove it with `/nix/nix-installer uninstall`".into(),
+            safe_fix: None,
+        })
+    }
+}
+
+fn incomplete_actions(actions: &[StatefulAction<Box<dyn crate::action::Action>>]) -> Vec<String> {
+    actions
+        .iter()
+        .filter(|action| {
+            matches!(
+                action.state,
+                ActionState::Uncompleted | ActionState::Progress
+            )
+        })
+        .map(|action| format!("`{}`", action.inner_typetag_name()))
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use super::{is_nix_managed, remove_synthetic_conf_nix_entries};
+
+    #[test]
+    fn removes_synthetic_conf_nix_entries() {
+        assert_eq!(
+            remove_synthetic_conf_nix_entries("nix\nrun\tprivate/var/run\nnix\n"),
+            "run\tprivate/var/run\n"
+        );
+        assert_eq!(remove_synthetic_conf_nix_entries("nix\n"), "");
+        assert_eq!(
+            remove_synthetic_conf_nix_entries("nixos\tUsers/nixos\n"),
+            "nixos\tUsers/nixos\n"
+        );
+    }
+
+    #[test]
+    fn recognizes_nix_managed_paths() {
+        assert!(is_nix_managed(Path::new(
+            "/nix/var/nix/profiles/default/bin/nix"
+        )));
+        assert!(is_nix_managed(Path::new("/home/ana/.nix-profile/bin/nix")));
+        assert!(is_nix_managed(Path::new("/run/current-system/sw/bin/nix")));
+        assert!(!is_nix_managed(Path::new("/usr/bin/nix")));
+        assert!(!is_nix_managed(Path::new("/opt/homebrew/bin/nix")));
+    }
+}
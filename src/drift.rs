@@ -0,0 +1,313 @@
+/*! Comparing what an install's receipt says it did against the system as it is now, see `nix-installer verify`
+
+Each completed action reports how the system has drifted from what it did (see
+[`Action::drift`](crate::action::Action::drift)): files, directories, services, users, or groups
+which were removed or changed, a Nix volume which isn't mounted, and so on. The drifted actions
+are listed with the `nix-installer repair` which fixes them, where there is one.
+*/
+
+use std::{
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    action::{common::place_nix_configuration::NIX_CONF, Artifact},
+    manifest::NIX_BIN,
+    planner::ShellProfileLocations,
+    InstallPlan,
+};
+
+/// A way the system no longer matches what an action did
+#[non_exhaustive]
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+#[serde(tag = "drift", rename_all = "snake_case")]
+pub enum Drift {
+    /// A file or directory is gone
+    Missing {
+        path: PathBuf,
+    },
+    /// A file was changed, `detail` says how
+    Modified {
+        path: PathBuf,
+        detail: String,
+    },
+    ServiceMissing {
+        name: String,
+        path: PathBuf,
+    },
+    UserMissing {
+        name: String,
+    },
+    UserChanged {
+        name: String,
+        uid: u32,
+        found: u32,
+    },
+    GroupMissing {
+        name: String,
+    },
+    GroupChanged {
+        name: String,
+        gid: u32,
+        found: u32,
+    },
+    VolumeUnmounted {
+        name: String,
+        mount_point: PathBuf,
+    },
+}
+
+impl std::fmt::Display for Drift {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Drift::Missing { path } => write!(f, "`{}` is missing", path.display()),
+            Drift::Modified { path, detail } => {
+                write!(f, "`{}` was modified, {detail}", path.display())
+            },
+            Drift::ServiceMissing { name, path } => {
+                write!(f, "The service `{name}` (`{}`) is missing", path.display())
+            },
+            Drift::UserMissing { name } => write!(f, "The user `{name}` is missing"),
+            Drift::UserChanged { name, uid, found } => {
+                write!(f, "The user `{name}` has UID {found} instead of {uid}")
+            },
+            Drift::GroupMissing { name } => write!(f, "The group `{name}` is missing"),
+            Drift::GroupChanged { name, gid, found } => {
+                write!(f, "The group `{name}` has GID {found} instead of {gid}")
+            },
+            Drift::VolumeUnmounted { name, mount_point } => write!(
+                f,
+                "The volume `{name}` is not mounted at `{}`",
+                mount_point.display()
+            ),
+        }
+    }
+}
+
+impl Drift {
+    /// The `nix-installer repair` which fixes this, if there is one
+    pub fn repair(&self) -> Option<&'static str> {
+        match self {
+            Drift::Missing { path } | Drift::Modified { path, .. } if is_shell_profile(path) => {
+                Some("nix-installer repair hooks")
+            },
+            Drift::Missing { path } | Drift::Modified { path, .. }
+                if path == Path::new(NIX_CONF) =>
+            {
+                Some("nix-installer repair nix-conf")
+            },
+            Drift::ServiceMissing { .. } => Some("nix-installer repair init-service"),
+            Drift::VolumeUnmounted { .. } => Some("nix-installer repair mount"),
+            Drift::UserMissing { .. }
+            | Drift::UserChanged { .. }
+            | Drift::GroupMissing { .. }
+            | Drift::GroupChanged { .. }
+                if cfg!(target_os = "macos") =>
+            {
+                Some("nix-installer repair sequoia")
+            },
+            _ => None,
+        }
+    }
+}
+
+/// An action the system has drifted from, see [`Action::drift`](crate::action::Action::drift)
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct DriftedAction {
+    /// The action's [`tracing_synopsis`](crate::action::Action::tracing_synopsis)
+    pub action: String,
+    pub drift: Vec<Drift>,
+}
+
+impl DriftedAction {
+    /// The action `synopsis` if it has drifted at all
+    pub fn of(synopsis: String, drift: Vec<Drift>) -> Vec<Self> {
+        if drift.is_empty() {
+            vec![]
+        } else {
+            vec![Self {
+                action: synopsis,
+                drift,
+            }]
+        }
+    }
+
+    /// The `nix-installer repair`s which fix this action's drift
+    pub fn repairs(&self) -> Vec<&'static str> {
+        let mut repairs = vec![];
+        for repair in self.drift.iter().filter_map(Drift::repair) {
+            if !repairs.contains(&repair) {
+                repairs.push(repair);
+            }
+        }
+        repairs
+    }
+}
+
+/// How the system has drifted from each completed action of `plan`
+#[tracing::instrument(level = "debug", skip_all)]
+pub async fn detect(plan: &InstallPlan) -> Vec<DriftedAction> {
+    let mut drifted = vec![];
+    for action in &plan.actions {
+        drifted.extend(action.drift().await);
+    }
+    drifted
+}
+
+/// Whether each of `artifacts` is still there, as it was made
+pub(crate) async fn artifact_drift(artifacts: &[Artifact]) -> Vec<Drift> {
+    let mut drift = vec![];
+    for artifact in artifacts {
+        match artifact {
+            Artifact::NixPackage { .. } => {
+                if !exists(Path::new(NIX_BIN)).await {
+                    drift.push(Drift::Missing {
+                        path: PathBuf::from(NIX_BIN),
+                    });
+                }
+            },
+            Artifact::File(path) | Artifact::Directory(path) => {
+                if !exists(path).await {
+                    drift.push(Drift::Missing { path: path.clone() });
+                }
+            },
+            Artifact::Service { name, path } => {
+                if !exists(path).await {
+                    drift.push(Drift::ServiceMissing {
+                        name: name.clone(),
+                        path: path.clone(),
+                    });
+                }
+            },
+            Artifact::User { name, uid } => match nix::unistd::User::from_name(name) {
+                Ok(None) => drift.push(Drift::UserMissing { name: name.clone() }),
+                Ok(Some(user)) if user.uid.as_raw() != *uid => drift.push(Drift::UserChanged {
+                    name: name.clone(),
+                    uid: *uid,
+                    found: user.uid.as_raw(),
+                }),
+                Ok(Some(_)) => (),
+                Err(err) => tracing::debug!(%err, "Could not look up the user `{name}`"),
+            },
+            Artifact::Group { name, gid } => match nix::unistd::Group::from_name(name) {
+                Ok(None) => drift.push(Drift::GroupMissing { name: name.clone() }),
+                Ok(Some(group)) if group.gid.as_raw() != *gid => drift.push(Drift::GroupChanged {
+                    name: name.clone(),
+                    gid: *gid,
+                    found: group.gid.as_raw(),
+                }),
+                Ok(Some(_)) => (),
+                Err(err) => tracing::debug!(%err, "Could not look up the group `{name}`"),
+            },
+            Artifact::Volume { name, mount_point } => {
+                if !is_mounted(mount_point).await {
+                    drift.push(Drift::VolumeUnmounted {
+                        name: name.clone(),
+                        mount_point: mount_point.clone(),
+                    });
+                }
+            },
+        }
+    }
+    drift
+}
+
+async fn exists(path: &Path) -> bool {
+    tokio::fs::symlink_metadata(path).await.is_ok()
+}
+
+/// A mountpoint is on a different device than its parent
+async fn is_mounted(mount_point: &Path) -> bool {
+    let Some(parent) = mount_point.parent() else {
+        return true;
+    };
+    match (
+        tokio::fs::metadata(mount_point).await,
+        tokio::fs::metadata(parent).await,
+    ) {
+        (Ok(mount_point), Ok(parent)) => mount_point.dev() != parent.dev(),
+        _ => false,
+    }
+}
+
+fn is_shell_profile(path: &Path) -> bool {
+    let locations = ShellProfileLocations::default();
+    locations
+        .bash
+        .iter()
+        .chain(locations.zsh.iter())
+        .chain(locations.nushell.iter())
+        .chain(locations.xonsh.iter())
+        .any(|profile| profile == path)
+        || path.ends_with(&locations.fish.confd_suffix)
+        || path.ends_with(&locations.fish.vendor_confd_suffix)
+        || (["/etc", "/etc/zsh"]
+            .iter()
+            .any(|dir| path.parent() == Some(Path::new(dir)))
+            && ["zshenv", "zprofile", "zshrc"]
+                .iter()
+                .any(|file| path.file_name().is_some_and(|name| name == *file)))
+        || locations.elvish.iter().any(|rc| path.ends_with(rc))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{artifact_drift, Drift, DriftedAction};
+    use crate::action::Artifact;
+
+    #[tokio::test]
+    async fn finds_missing_artifacts() -> eyre::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let kept = temp_dir.path().join("kept");
+        std::fs::write(&kept, "boop")?;
+        let removed = temp_dir.path().join("removed");
+
+        let drift = artifact_drift(&[
+            Artifact::File(kept),
+            Artifact::File(removed.clone()),
+            Artifact::Directory(temp_dir.path().to_path_buf()),
+            Artifact::User {
+                name: "nix-installer-no-such-user".into(),
+                uid: 30001,
+            },
+        ])
+        .await;
+        assert_eq!(
+            drift,
+            vec![
+                Drift::Missing { path: removed },
+                Drift::UserMissing {
+                    name: "nix-installer-no-such-user".into()
+                },
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn suggests_repairs() {
+        let drifted = DriftedAction {
+            action: "Configure the shell profiles".into(),
+            drift: vec![
+                Drift::Modified {
+                    path: "/etc/zshrc".into(),
+                    detail: "it no longer has what was added to it".into(),
+                },
+                Drift::Missing {
+                    path: "/etc/fish/conf.d/nix.fish".into(),
+                },
+                Drift::Missing {
+                    path: "/etc/nix/nix.conf".into(),
+                },
+            ],
+        };
+        assert_eq!(
+            drifted.repairs(),
+            vec![
+                "nix-installer repair hooks",
+                "nix-installer repair nix-conf"
+            ]
+        );
+    }
+}
@@ -3,8 +3,18 @@ use std::{error::Error, path::PathBuf};
 use semver::Version;
 
 use crate::{
-    action::ActionError, planner::PlannerError, self_test::SelfTestError,
+    action::{ActionError, ActionErrorKind},
+    adopt::AdoptError,
+    lock::LockError,
+    migration::MigrationError,
+    packaged_nix::PackagedNixError,
+    plan::HostFingerprint,
+    planner::PlannerError,
+    receipt_signature::ReceiptSignatureError,
+    self_test::SelfTestError,
     settings::InstallSettingsError,
+    single_user::SingleUserError,
+    upstream_install::UpstreamInstallError,
 };
 
 /// An error occurring during a call defined in this crate
@@ -16,10 +26,11 @@ pub enum NixInstallerError {
     Action(#[source] ActionError),
     /// An error originating from a [`self_test`](crate::self_test)
     #[error("Self test error, install may be only partially functional\n{}", .0.iter().map(|err| {
+        let subsystem = err.subsystem();
         if let Some(source) = err.source() {
-            format!("{err}\n{source}\n")
+            format!("[{subsystem}] {err}\n{source}\n")
         } else {
-            format!("{err}\n")
+            format!("[{subsystem}] {err}\n")
         }
     }).collect::<Vec<_>>().join("\n"))]
     SelfTest(Vec<SelfTestError>),
@@ -35,6 +46,9 @@ pub enum NixInstallerError {
     /// An error while writing the [`InstallPlan`](crate::InstallPlan)
     #[error("Recording install receipt")]
     RecordingReceipt(PathBuf, #[source] std::io::Error),
+    /// State kept outside of `/nix`, like the journal, could have been written by someone other than root
+    #[error("`{0}` is not owned by root, or others can write to it, so it is not trusted, remove it to start over")]
+    UntrustedState(PathBuf),
     /// An error while writing copying the binary into the `/nix` folder
     #[error("Copying `nix-installer` binary into `/nix`")]
     CopyingSelf(
@@ -91,6 +105,177 @@ pub enum NixInstallerError {
     /// This version of `nix-installer` is not compatible with this plan's version
     #[error("`nix-installer` version `{}` is not compatible with this plan's version `{}`", .binary, .plan)]
     IncompatibleVersion { binary: Version, plan: Version },
+    /// An action running concurrently with others panicked or was aborted
+    #[error("Joining spawned async task")]
+    Join(
+        #[source]
+        #[from]
+        tokio::task::JoinError,
+    ),
+    /// The receipt could not be upgraded to the current schema
+    #[error("Upgrading the receipt to the current schema")]
+    ReceiptMigration(
+        #[from]
+        #[source]
+        MigrationError,
+    ),
+    /// The receipt's signature could not be written, or didn't match it
+    #[error("Checking the receipt signature")]
+    ReceiptSignature(
+        #[from]
+        #[source]
+        ReceiptSignatureError,
+    ),
+    /// The Nix already on the system could not be adopted
+    #[error("Adopting the existing Nix install")]
+    Adopt(
+        #[from]
+        #[source]
+        AdoptError,
+    ),
+    /// The layout of the upstream install script could not be migrated
+    #[error("Migrating from the upstream install script")]
+    UpstreamInstall(
+        #[from]
+        #[source]
+        UpstreamInstallError,
+    ),
+    /// The Nix installed by a package manager could not be taken over
+    #[error("Taking over the packaged Nix")]
+    PackagedNix(
+        #[from]
+        #[source]
+        PackagedNixError,
+    ),
+    /// The single-user install could not be converted to multi-user
+    #[error("Converting the single-user install to multi-user")]
+    SingleUser(
+        #[from]
+        #[source]
+        SingleUserError,
+    ),
+    /// Another `nix-installer` holds the [`InstallerLock`](crate::lock::InstallerLock), or it could not be taken
+    #[error("Taking the `nix-installer` lock")]
+    Lock(
+        #[from]
+        #[source]
+        LockError,
+    ),
+    /// The plan was made on a different kind of host than it is being applied to
+    #[error("This plan was made for `{plan}`, but this host is `{host}`, create a new plan on a matching host")]
+    HostMismatch {
+        plan: Box<HostFingerprint>,
+        host: Box<HostFingerprint>,
+    },
+}
+
+/// A class of failure, each with its own exit code so wrappers can tell them apart
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorClass {
+    /// A failure which isn't one of the others
+    Other,
+    /// A check before anything was changed refused to continue (eg an unsupported system)
+    Preflight,
+    /// Fetching something over the network failed
+    Network,
+    /// Permission to change something was denied
+    Permission,
+    /// Nix, or a different install by `nix-installer`, is already on the system
+    ConflictingInstall,
+    /// The user cancelled
+    Cancelled,
+    /// The install failed, and the changes it made were reverted
+    RolledBack,
+}
+
+impl ErrorClass {
+    /// The process exit code for failures of this class
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            ErrorClass::Other => 1,
+            ErrorClass::Preflight => 10,
+            ErrorClass::Network => 11,
+            ErrorClass::Permission => 12,
+            ErrorClass::ConflictingInstall => 13,
+            ErrorClass::Cancelled => 14,
+            ErrorClass::RolledBack => 15,
+        }
+    }
+
+    /// Classify an error by the network or permission errors among its sources
+    pub fn of(err: &(dyn Error + 'static)) -> Self {
+        let mut next = Some(err);
+        while let Some(err) = next {
+            if err.downcast_ref::<reqwest::Error>().is_some() {
+                return ErrorClass::Network;
+            }
+            if let Some(io_error) = err.downcast_ref::<std::io::Error>() {
+                if io_error.kind() == std::io::ErrorKind::PermissionDenied {
+                    return ErrorClass::Permission;
+                }
+            }
+            if let Some(errno) = err.downcast_ref::<nix::errno::Errno>() {
+                if matches!(errno, nix::errno::Errno::EACCES | nix::errno::Errno::EPERM) {
+                    return ErrorClass::Permission;
+                }
+            }
+            // Several errors (like every mirror of the Nix package failing) are classified if they all agree
+            let children: Vec<&(dyn Error + 'static)> = match err.downcast_ref::<ActionErrorKind>()
+            {
+                Some(ActionErrorKind::Multiple(kinds)) => kinds
+                    .iter()
+                    .map(|kind| kind as &(dyn Error + 'static))
+                    .collect(),
+                Some(ActionErrorKind::MultipleChildren(errs)) => errs
+                    .iter()
+                    .map(|err| err as &(dyn Error + 'static))
+                    .collect(),
+                _ => vec![],
+            };
+            if let Some((first, rest)) = children.split_first() {
+                let class = Self::of(*first);
+                return if rest.iter().all(|child| Self::of(*child) == class) {
+                    class
+                } else {
+                    ErrorClass::Other
+                };
+            }
+            next = err.source();
+        }
+        ErrorClass::Other
+    }
+}
+
+impl NixInstallerError {
+    /// What kind of failure this is, see [`ErrorClass`]
+    pub fn class(&self) -> ErrorClass {
+        match self {
+            NixInstallerError::Cancelled => ErrorClass::Cancelled,
+            NixInstallerError::Planner(
+                PlannerError::NixExists | PlannerError::NixOs | PlannerError::PackagedNix { .. },
+            )
+            | NixInstallerError::IncompatibleVersion { .. } => ErrorClass::ConflictingInstall,
+            _ => match ErrorClass::of(self) {
+                ErrorClass::Other => match self {
+                    NixInstallerError::Planner(_)
+                    | NixInstallerError::InstallSettings(_)
+                    | NixInstallerError::Adopt(_)
+                    | NixInstallerError::UpstreamInstall(UpstreamInstallError::NotFound)
+                    | NixInstallerError::PackagedNix(PackagedNixError::OtherStore { .. })
+                    | NixInstallerError::SingleUser(
+                        SingleUserError::NotFound
+                        | SingleUserError::NoUser(_)
+                        | SingleUserError::NoNix(_),
+                    )
+                    | NixInstallerError::Lock(LockError::Held { .. })
+                    | NixInstallerError::HostMismatch { .. } => ErrorClass::Preflight,
+                    _ => ErrorClass::Other,
+                },
+                class => class,
+            },
+        }
+    }
 }
 
 pub(crate) trait HasExpectedErrors: std::error::Error + Sized + Send + Sync {
@@ -104,6 +289,7 @@ impl HasExpectedErrors for NixInstallerError {
             NixInstallerError::ActionRevert(_) => None,
             this @ NixInstallerError::SelfTest(_) => Some(Box::new(this)),
             NixInstallerError::RecordingReceipt(_, _) => None,
+            this @ NixInstallerError::UntrustedState(_) => Some(Box::new(this)),
             NixInstallerError::CopyingSelf(_) => None,
             NixInstallerError::SerializingReceipt(_) => None,
             this @ NixInstallerError::Cancelled => Some(Box::new(this)),
@@ -115,6 +301,42 @@ impl HasExpectedErrors for NixInstallerError {
             this @ NixInstallerError::IncompatibleVersion { binary: _, plan: _ } => {
                 Some(Box::new(this))
             },
+            NixInstallerError::Join(_) => None,
+            NixInstallerError::ReceiptMigration(err @ MigrationError::TooNew { .. }) => {
+                Some(Box::new(err))
+            },
+            NixInstallerError::ReceiptMigration(_) => None,
+            NixInstallerError::ReceiptSignature(
+                err @ (ReceiptSignatureError::Mismatch(_)
+                | ReceiptSignatureError::Missing(_)
+                | ReceiptSignatureError::Expected(_)
+                | ReceiptSignatureError::NoKey(_)),
+            ) => Some(Box::new(err)),
+            NixInstallerError::ReceiptSignature(_) => None,
+            NixInstallerError::Adopt(
+                err @ (AdoptError::NoNix
+                | AdoptError::ReceiptExists(_)
+                | AdoptError::NoBuildGroup(_)
+                | AdoptError::NoBuildUsers(_)),
+            ) => Some(Box::new(err)),
+            NixInstallerError::Adopt(_) => None,
+            NixInstallerError::UpstreamInstall(err @ UpstreamInstallError::NotFound) => {
+                Some(Box::new(err))
+            },
+            NixInstallerError::UpstreamInstall(_) => None,
+            NixInstallerError::PackagedNix(err @ PackagedNixError::OtherStore { .. }) => {
+                Some(Box::new(err))
+            },
+            NixInstallerError::PackagedNix(_) => None,
+            NixInstallerError::SingleUser(
+                err @ (SingleUserError::NotFound
+                | SingleUserError::NoUser(_)
+                | SingleUserError::NoNix(_)),
+            ) => Some(Box::new(err)),
+            NixInstallerError::SingleUser(_) => None,
+            NixInstallerError::Lock(err @ LockError::Held { .. }) => Some(Box::new(err)),
+            NixInstallerError::Lock(_) => None,
+            this @ NixInstallerError::HostMismatch { plan: _, host: _ } => Some(Box::new(this)),
             #[cfg(feature = "diagnostics")]
             NixInstallerError::Diagnostic(_) => None,
         }
@@ -148,3 +370,42 @@ impl crate::diagnostics::ErrorDiagnostic for NixInstallerError {
         )
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::{ErrorClass, NixInstallerError};
+    use crate::{
+        action::{ActionError, ActionErrorKind, ActionTag},
+        planner::PlannerError,
+    };
+
+    #[test]
+    fn classifies_errors() {
+        assert_eq!(NixInstallerError::Cancelled.class(), ErrorClass::Cancelled);
+        assert_eq!(
+            NixInstallerError::Planner(PlannerError::NixExists).class(),
+            ErrorClass::ConflictingInstall
+        );
+        assert_eq!(
+            NixInstallerError::Planner(PlannerError::PackagedNix {
+                package: "nix-bin".into(),
+                remove: "apt remove nix-bin".into(),
+            })
+            .class(),
+            ErrorClass::ConflictingInstall
+        );
+        assert_eq!(
+            NixInstallerError::Planner(PlannerError::Wsl1).class(),
+            ErrorClass::Preflight
+        );
+        let denied = NixInstallerError::Action(ActionError::new(
+            ActionTag("create_directory"),
+            ActionErrorKind::CreateDirectory(
+                "/nix".into(),
+                std::io::Error::from(std::io::ErrorKind::PermissionDenied),
+            ),
+        ));
+        assert_eq!(denied.class(), ErrorClass::Permission);
+        assert_eq!(ErrorClass::Permission.exit_code(), 12);
+    }
+}
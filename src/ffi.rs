@@ -0,0 +1,283 @@
+/*! A C ABI over planning, installing, and uninstalling, so other languages can embed the installer
+
+Built as a shared library with the `ffi` feature:
+
+```bash
+cargo rustc --lib --release --no-default-features --features ffi,embedded-nix --crate-type cdylib
+```
+
+The functions are declared in `bindings/nix_installer.h`, and `bindings/python/nix_installer.py`
+wraps them for Python. Everything crosses as NUL terminated UTF-8 JSON:
+
+* A planner is a [`BuiltinPlanner`], like `{"Linux": {"settings": {...}, "init": {...}, ...}}`.
+  [`nix_installer_default_planner`] gives the one `nix-installer` would pick, to change and pass
+  back.
+* A plan (or receipt) is an [`InstallPlan`].
+* A progress event is a [`ProgressEvent`](crate::progress::ProgressEvent).
+
+Each function returns `0` on success, or the [`ErrorClass::exit_code`] of the failure with `*out`
+set to the error:
+
+```json
+{"class":"network","exit_code":11,"message":"Error executing action","causes":["..."],"plan":{...}}
+```
+
+When an install (or uninstall) fails, `plan` has the state of each action, pass it to
+[`nix_installer_uninstall`] to revert what was done. Every string given out is freed with
+[`nix_installer_string_free`].
+*/
+
+use std::{
+    ffi::{c_char, c_int, c_void, CStr, CString},
+    future::Future,
+    panic::AssertUnwindSafe,
+};
+
+use crate::{
+    progress::{clear_progress_sender, set_progress_callback},
+    BuiltinPlanner, ErrorClass, InstallPlan, NixInstallerError,
+};
+
+/// Called with each progress event (as JSON, only valid during the call) and the `user_data` given
+pub type ProgressCallback = extern "C" fn(event: *const c_char, user_data: *mut c_void);
+
+/// The error given out as `*out`
+#[derive(Debug, serde::Serialize)]
+struct Failure {
+    class: ErrorClass,
+    exit_code: u8,
+    message: String,
+    causes: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    plan: Option<serde_json::Value>,
+}
+
+impl Failure {
+    fn other(message: impl std::fmt::Display) -> Self {
+        Self {
+            class: ErrorClass::Other,
+            exit_code: ErrorClass::Other.exit_code(),
+            message: message.to_string(),
+            causes: vec![],
+            plan: None,
+        }
+    }
+
+    fn of(err: &NixInstallerError, plan: Option<&InstallPlan>) -> Self {
+        let class = err.class();
+        let mut causes = vec![];
+        let mut source = std::error::Error::source(err);
+        while let Some(err) = source {
+            causes.push(err.to_string());
+            source = err.source();
+        }
+        Self {
+            class,
+            exit_code: class.exit_code(),
+            message: err.to_string(),
+            causes,
+            plan: plan.and_then(|plan| serde_json::to_value(plan).ok()),
+        }
+    }
+}
+
+/// The planner `nix-installer` would pick for this system, with its default settings
+///
+/// # Safety
+///
+/// `out` must be valid to write a pointer to.
+#[no_mangle]
+pub unsafe extern "C" fn nix_installer_default_planner(out: *mut *mut c_char) -> c_int {
+    call(out, || {
+        block_on(async {
+            let planner = BuiltinPlanner::default()
+                .await
+                .map_err(|err| Failure::of(&err.into(), None))?;
+            serde_json::to_string(&planner).map_err(Failure::other)
+        })?
+    })
+}
+
+/// Plan an install with `planner` (or the default planner, if it is `NULL`), giving the plan
+///
+/// # Safety
+///
+/// `planner` must be `NULL` or a NUL terminated string, and `out` must be valid to write a pointer to.
+#[no_mangle]
+pub unsafe extern "C" fn nix_installer_plan(
+    planner: *const c_char,
+    out: *mut *mut c_char,
+) -> c_int {
+    call(out, || {
+        let planner = match planner.is_null() {
+            true => None,
+            false => Some(parse::<BuiltinPlanner>(planner)?),
+        };
+        block_on(async {
+            let plan = match planner {
+                Some(planner) => planner.plan().await,
+                None => InstallPlan::default().await,
+            }
+            .map_err(|err| Failure::of(&err, None))?;
+            serde_json::to_string(&plan).map_err(Failure::other)
+        })?
+    })
+}
+
+/// Install `plan`, giving the receipt
+///
+/// # Safety
+///
+/// `plan` must be a NUL terminated string, `progress` (if given) must be safe to call with
+/// `user_data` from any thread until this returns, and `out` must be valid to write a pointer to.
+#[no_mangle]
+pub unsafe extern "C" fn nix_installer_install(
+    plan: *const c_char,
+    progress: Option<ProgressCallback>,
+    user_data: *mut c_void,
+    out: *mut *mut c_char,
+) -> c_int {
+    call(out, || {
+        let mut plan = parse::<InstallPlan>(plan)?;
+        with_progress(progress, user_data, || {
+            block_on(async {
+                if let Err(err) = plan.install(None).await {
+                    return Err(Failure::of(&err, Some(&plan)));
+                }
+                serde_json::to_string(&plan).map_err(Failure::other)
+            })?
+        })
+    })
+}
+
+/// Uninstall what `receipt` (or a failed install's plan) records as done
+///
+/// # Safety
+///
+/// As [`nix_installer_install`].
+#[no_mangle]
+pub unsafe extern "C" fn nix_installer_uninstall(
+    receipt: *const c_char,
+    progress: Option<ProgressCallback>,
+    user_data: *mut c_void,
+    out: *mut *mut c_char,
+) -> c_int {
+    call(out, || {
+        let mut plan = parse::<InstallPlan>(receipt)?;
+        with_progress(progress, user_data, || {
+            block_on(async {
+                if let Err(err) = plan.uninstall(None).await {
+                    return Err(Failure::of(&err, Some(&plan)));
+                }
+                Ok("null".to_string())
+            })?
+        })
+    })
+}
+
+/// Free a string given out by one of the other functions, `NULL` is ignored
+///
+/// # Safety
+///
+/// `string` must have been given out by `nix-installer`, and not already freed.
+#[no_mangle]
+pub unsafe extern "C" fn nix_installer_string_free(string: *mut c_char) {
+    if !string.is_null() {
+        drop(CString::from_raw(string));
+    }
+}
+
+/// Run `f`, giving out its output (or failure) as `*out`, without letting a panic cross the ABI
+unsafe fn call(out: *mut *mut c_char, f: impl FnOnce() -> Result<String, Failure>) -> c_int {
+    let (code, json) = match std::panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(json)) => (0, json),
+        Ok(Err(failure)) => (failure.exit_code, failure_json(&failure)),
+        Err(_) => {
+            let failure = Failure::other("`nix-installer` panicked");
+            (failure.exit_code, failure_json(&failure))
+        },
+    };
+    if !out.is_null() {
+        // JSON can't have a NUL in it, it would be escaped
+        *out = CString::new(json).unwrap_or_default().into_raw();
+    }
+    c_int::from(code)
+}
+
+fn failure_json(failure: &Failure) -> String {
+    serde_json::to_string(failure).unwrap_or_else(|_| "{}".to_string())
+}
+
+unsafe fn parse<T: serde::de::DeserializeOwned>(json: *const c_char) -> Result<T, Failure> {
+    if json.is_null() {
+        return Err(Failure::other("Expected JSON, got `NULL`"));
+    }
+    let json = CStr::from_ptr(json)
+        .to_str()
+        .map_err(|err| Failure::other(format!("The JSON isn't UTF-8: {err}")))?;
+    serde_json::from_str(json).map_err(Failure::other)
+}
+
+fn block_on<F: Future>(future: F) -> Result<F::Output, Failure> {
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .map_err(|err| Failure::other(format!("Starting the async runtime: {err}")))?;
+    Ok(runtime.block_on(future))
+}
+
+/// Report progress to `progress` (if given) while `f` runs
+fn with_progress<T>(
+    progress: Option<ProgressCallback>,
+    user_data: *mut c_void,
+    f: impl FnOnce() -> T,
+) -> T {
+    let Some(progress) = progress else {
+        return f();
+    };
+    // The caller promised `user_data` may be used from any thread
+    let user_data = user_data as usize;
+    set_progress_callback(move |event| {
+        let event = serde_json::to_string(&event)
+            .ok()
+            .and_then(|event| CString::new(event).ok());
+        if let Some(event) = event {
+            progress(event.as_ptr(), user_data as *mut c_void);
+        }
+    });
+    let output = f();
+    clear_progress_sender();
+    output
+}
+
+#[cfg(test)]
+mod test {
+    use std::ffi::{c_char, CStr};
+
+    use super::{nix_installer_install, nix_installer_plan, nix_installer_string_free};
+
+    /// Take the string given out as `out`
+    unsafe fn take(out: *mut c_char) -> eyre::Result<serde_json::Value> {
+        let json = serde_json::from_str(CStr::from_ptr(out).to_str()?)?;
+        nix_installer_string_free(out);
+        Ok(json)
+    }
+
+    #[test]
+    fn reports_bad_input() -> eyre::Result<()> {
+        let mut out = std::ptr::null_mut();
+        let code = unsafe { nix_installer_plan(c"not json".as_ptr(), &mut out) };
+        assert_eq!(code, 1);
+        let failure = unsafe { take(out)? };
+        assert_eq!(failure["class"], "other");
+        assert_eq!(failure["exit_code"], 1);
+
+        let code = unsafe {
+            nix_installer_install(std::ptr::null(), None, std::ptr::null_mut(), &mut out)
+        };
+        assert_eq!(code, 1);
+        let failure = unsafe { take(out)? };
+        assert_eq!(failure["message"], "Expected JSON, got `NULL`");
+        Ok(())
+    }
+}
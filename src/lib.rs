@@ -67,24 +67,100 @@ match plan.install(None).await {
 # }
 ```
 
+## Embedding
+
+Provisioning daemons and GUI wrappers can drive an install without the CLI: the default `cli`
+feature can be disabled, and nothing outside the `cli` module prompts, reads stdin, or assumes a
+TTY. Settings are changed with the setters of [`CommonSettings`](settings::CommonSettings) (and
+[`InitSettings`](settings::InitSettings)), and progress is reported through
+[`progress::set_progress_callback`] (or [`progress::set_progress_sender`]):
+
+```rust,no_run
+# #[cfg(target_os = "linux")]
+# async fn embedded_install() -> Result<(), nix_installer::NixInstallerError> {
+use nix_installer::{
+    planner::Planner,
+    progress::{set_progress_callback, ProgressEvent},
+    InstallPlan,
+};
+
+let mut planner = nix_installer::planner::linux::Linux::default().await?;
+planner
+    .settings
+    .modify_profile(false)
+    .nix_build_user_count(8);
+planner.init.start_daemon(true);
+
+set_progress_callback(|event| {
+    if let ProgressEvent::ActionCompleted { completed, total } = event {
+        eprintln!("{completed}/{total}");
+    }
+});
+
+let mut plan = InstallPlan::plan(planner).await?;
+if let Err(e) = plan.install(None).await {
+    plan.uninstall(None).await?;
+    return Err(e);
+}
+# Ok(())
+# }
+```
+
+### Stability
+
+The embedding surface follows semver: a breaking change to it only comes with a new major version.
+It is:
+
+* [`InstallPlan`], and its receipts (see [`InstallPlan::from_receipt`]).
+* The [`Planner`](planner::Planner) and [`Action`] traits, [`StatefulAction`](action::StatefulAction),
+  [`BuiltinPlanner`](planner::BuiltinPlanner), and the builtin planners' settings.
+* [`CommonSettings`](settings::CommonSettings) and [`InitSettings`](settings::InitSettings), their
+  setters, and `default()`s.
+* The [`progress`] module, and [`ActionEvent`](action::ActionEvent)s with the functions to subscribe
+  to (and approve) them.
+* [`NixInstallerError`] and [`ErrorClass`]. Error enums are `#[non_exhaustive]`, so new variants
+  are not breaking.
+
+Everything else, including the `cli` module and the builtin [`Action`]s' fields, may change in
+a minor release.
 */
 
 pub mod action;
+pub mod adopt;
+pub mod audit;
 #[cfg(feature = "cli")]
 pub mod cli;
 #[cfg(feature = "diagnostics")]
 pub mod diagnostics;
+pub mod doctor;
+pub mod drift;
 mod error;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod lock;
+pub mod manifest;
+pub mod migration;
 mod os;
+pub mod packaged_nix;
 mod plan;
+pub mod plan_diff;
 pub mod planner;
+pub mod preflight;
+pub mod progress;
+pub mod purge;
+pub mod receipt_signature;
+pub mod releases;
 pub mod self_test;
 pub mod settings;
+pub mod single_user;
+pub mod unprivileged;
+pub mod upstream_install;
+pub mod user_state;
 
-use std::{ffi::OsStr, path::Path, process::Output};
+use std::{ffi::OsStr, path::Path, process::Output, time::Duration};
 
-pub use error::NixInstallerError;
-pub use plan::InstallPlan;
+pub use error::{ErrorClass, NixInstallerError};
+pub use plan::{HostFingerprint, InstallPlan};
 use planner::BuiltinPlanner;
 
 use reqwest::Certificate;
@@ -92,13 +168,55 @@ use tokio::process::Command;
 
 use crate::action::{Action, ActionErrorKind};
 
-#[tracing::instrument(level = "debug", skip_all, fields(command = %format!("{:?}", command.as_std())))]
+#[tracing::instrument(level = "debug", skip_all, fields(command = %command_string(command)))]
 async fn execute_command(command: &mut Command) -> Result<Output, ActionErrorKind> {
     tracing::trace!("Executing");
+    // An action which timed out doesn't leave the command running
     let output = command
+        .kill_on_drop(true)
         .output()
         .await
         .map_err(|e| ActionErrorKind::command(command, e))?;
+    command_result(command, output)
+}
+
+/// Like [`execute_command`], but writes `input` to the command's stdin instead of passing it as an argument
+///
+/// Used for secrets (like passphrases), which would otherwise be visible in the process list and the audit log.
+#[tracing::instrument(level = "debug", skip_all, fields(command = %command_string(command)))]
+async fn execute_command_with_input(
+    command: &mut Command,
+    input: &[u8],
+) -> Result<Output, ActionErrorKind> {
+    use tokio::io::AsyncWriteExt;
+
+    tracing::trace!("Executing");
+    let mut child = command
+        .kill_on_drop(true)
+        .stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .spawn()
+        .map_err(|e| ActionErrorKind::command(command, e))?;
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    stdin
+        .write_all(input)
+        .await
+        .map_err(|e| ActionErrorKind::command(command, e))?;
+    drop(stdin);
+    let output = child
+        .wait_with_output()
+        .await
+        .map_err(|e| ActionErrorKind::command(command, e))?;
+    command_result(command, output)
+}
+
+/// Record that `command` ran, and turn its `output` into an error if it failed
+fn command_result(command: &Command, output: Output) -> Result<Output, ActionErrorKind> {
+    audit::record(audit::AuditEvent::CommandExecuted {
+        command: command_string(command),
+        status: output.status.code(),
+    });
     match output.status.success() {
         true => {
             tracing::trace!(
@@ -112,6 +230,55 @@ async fn execute_command(command: &mut Command) -> Result<Output, ActionErrorKin
     }
 }
 
+/// Arguments which are followed by a secret, like the password of `security add-generic-password -w <password>`
+const SECRET_ARGS: &[&str] = &["-w", "-passphrase", "-oldPassphrase", "-newPassphrase"];
+
+/// `command` as it is logged and recorded, with the arguments following any of [`SECRET_ARGS`] redacted
+pub(crate) fn command_string(command: &Command) -> String {
+    let command = command.as_std();
+    let mut redact_next = false;
+    std::iter::once(command.get_program())
+        .chain(command.get_args())
+        .map(|arg| {
+            let rendered = if redact_next {
+                "\"REDACTED\"".to_string()
+            } else {
+                format!("{arg:?}")
+            };
+            redact_next = arg.to_str().is_some_and(|arg| SECRET_ARGS.contains(&arg));
+            rendered
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// The longest wait between retries of [`execute_command_with_retries`]
+const MAX_COMMAND_BACKOFF: Duration = Duration::from_secs(5);
+
+/// Like [`execute_command`], but retries a failure up to `retries` times, doubling the wait (starting from `backoff`) after each
+///
+/// Within an action which is retried already, the command is only run once.
+#[tracing::instrument(level = "debug", skip_all, fields(command = %command_string(command)))]
+async fn execute_command_with_retries(
+    command: &mut Command,
+    retries: u32,
+    mut backoff: Duration,
+) -> Result<Output, ActionErrorKind> {
+    let retries = if action::retrying() { 0 } else { retries };
+    let mut retry = 0;
+    loop {
+        match execute_command(command).await {
+            Err(err) if retry < retries => {
+                retry += 1;
+                tracing::debug!(%err, retry, retries, ?backoff, "Command failed, retrying");
+                tokio::time::sleep(backoff).await;
+                backoff = (backoff * 2).min(MAX_COMMAND_BACKOFF);
+            },
+            res => return res,
+        }
+    }
+}
+
 #[tracing::instrument(level = "debug", skip_all, fields(
     k = %k.as_ref().to_string_lossy(),
     v = %v.as_ref().to_string_lossy(),
@@ -145,3 +312,69 @@ pub enum CertificateError {
     #[error("Unknown certificate format, `der` and `pem` supported")]
     UnknownCertFormat,
 }
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use tokio::process::Command;
+
+    use super::{command_string, execute_command, execute_command_with_retries};
+
+    #[tokio::test]
+    async fn retries_failed_commands() -> eyre::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let marker = temp_dir.path().join("marker");
+        // Fails the first time, succeeds after
+        let mut command = Command::new("sh");
+        command.arg("-c").arg(format!(
+            "test -e {marker} || {{ touch {marker}; exit 1; }}",
+            marker = marker.display()
+        ));
+        execute_command_with_retries(&mut command, 1, Duration::from_millis(1)).await?;
+
+        let mut command = Command::new("false");
+        assert!(
+            execute_command_with_retries(&mut command, 2, Duration::from_millis(1))
+                .await
+                .is_err()
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn kills_commands_which_time_out() -> eyre::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let marker = temp_dir.path().join("marker");
+        let mut command = Command::new("sh");
+        command.arg("-c").arg(format!(
+            "sleep 0.5; touch {marker}",
+            marker = marker.display()
+        ));
+        assert!(
+            tokio::time::timeout(Duration::from_millis(50), execute_command(&mut command))
+                .await
+                .is_err()
+        );
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        assert!(!marker.exists());
+        Ok(())
+    }
+
+    #[test]
+    fn redacts_secret_arguments() {
+        let mut command = Command::new("/usr/bin/security");
+        command.args([
+            "add-generic-password",
+            "-a",
+            "Nix Store",
+            "-w",
+            "hunter2",
+            "-U",
+        ]);
+        assert_eq!(
+            command_string(&command),
+            r#""/usr/bin/security" "add-generic-password" "-a" "Nix Store" "-w" "REDACTED" "-U""#
+        );
+    }
+}
@@ -0,0 +1,106 @@
+/*! Keeping two `nix-installer`s from changing the system at once
+
+Configuration management tools may start `nix-installer install` (or `uninstall`, or `repair`) a
+second time while the first is still running. Their actions would interleave, and each would record
+a receipt which doesn't match what is on the system. Commands which change the system hold an
+[`InstallerLock`] from before they read the receipt until they exit, and refuse to start if another
+`nix-installer` holds it.
+*/
+
+use std::{
+    fs::File,
+    io::{Read, Seek, Write},
+    path::{Path, PathBuf},
+};
+
+use nix::{
+    errno::Errno,
+    fcntl::{Flock, FlockArg},
+};
+
+/// Where the lock is taken, this file is never removed
+pub const LOCK_LOCATION: &str = "/var/run/nix-installer.lock";
+
+/// An exclusive `flock` on [`LOCK_LOCATION`], released when dropped (or when the process exits)
+#[derive(Debug)]
+pub struct InstallerLock {
+    _lock: Flock<File>,
+}
+
+impl InstallerLock {
+    /// Take the lock at [`LOCK_LOCATION`]
+    pub fn acquire() -> Result<Self, LockError> {
+        Self::acquire_at(LOCK_LOCATION)
+    }
+
+    /// Take the lock at `path`, without waiting if another process holds it
+    #[tracing::instrument(level = "debug", skip_all, fields(path = %path.as_ref().display()))]
+    pub fn acquire_at(path: impl AsRef<Path>) -> Result<Self, LockError> {
+        let path = path.as_ref();
+        // Not truncated on open, the holder's PID in it is read if the lock is held
+        let file = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)
+            .map_err(|e| LockError::Open(path.into(), e))?;
+        let mut lock = match Flock::lock(file, FlockArg::LockExclusiveNonblock) {
+            Ok(lock) => lock,
+            Err((mut file, Errno::EWOULDBLOCK)) => {
+                let mut holder = String::new();
+                let pid = file
+                    .read_to_string(&mut holder)
+                    .ok()
+                    .and_then(|_| holder.trim().parse().ok());
+                return Err(LockError::Held {
+                    path: path.into(),
+                    pid,
+                });
+            },
+            Err((_, e)) => return Err(LockError::Lock(path.into(), e)),
+        };
+
+        let pid = std::process::id();
+        lock.set_len(0)
+            .and_then(|()| lock.rewind())
+            .and_then(|()| writeln!(lock, "{pid}"))
+            .map_err(|e| LockError::Write(path.into(), e))?;
+        tracing::debug!("Took the lock at `{}`", path.display());
+        Ok(Self { _lock: lock })
+    }
+}
+
+#[non_exhaustive]
+#[derive(thiserror::Error, Debug, strum::IntoStaticStr)]
+pub enum LockError {
+    #[error("Another `nix-installer`{} is changing the system, wait for it to finish before trying again (it holds the lock at `{}`)", .pid.map(|pid| format!(" (PID {pid})")).unwrap_or_default(), .path.display())]
+    Held { path: PathBuf, pid: Option<u32> },
+    #[error("Opening `{0}`")]
+    Open(PathBuf, #[source] std::io::Error),
+    #[error("Locking `{0}`")]
+    Lock(PathBuf, #[source] Errno),
+    #[error("Writing the PID to `{0}`")]
+    Write(PathBuf, #[source] std::io::Error),
+}
+
+#[cfg(test)]
+mod test {
+    use super::{InstallerLock, LockError};
+
+    #[test]
+    fn refuses_a_second_holder() -> eyre::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let path = temp_dir.path().join("nix-installer.lock");
+
+        let lock = InstallerLock::acquire_at(&path)?;
+        match InstallerLock::acquire_at(&path) {
+            Err(LockError::Held { pid, .. }) => assert_eq!(pid, Some(std::process::id())),
+            res => panic!("Expected the lock to be held, got {res:?}"),
+        }
+
+        drop(lock);
+        InstallerLock::acquire_at(&path)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,260 @@
+/*! A manifest of everything an install placed on the system, for reviewing what `nix-installer` did
+
+After a successful install it is written to [`MANIFEST_LOCATION`]:
+
+```json
+{
+  "nix_installer_version": "0.27.0",
+  "planner": "linux",
+  "nix": {
+    "version": "nix (Nix) 2.24.10",
+    "source": "https://releases.nixos.org/nix/nix-2.24.10/nix-2.24.10-x86_64-linux.tar.xz",
+    "sha256": "..."
+  },
+  "files": [{ "path": "/etc/nix/nix.conf", "sha256": "..." }],
+  "directories": ["/nix/var"],
+  "services": [{ "name": "nix-daemon.service", "path": "/etc/systemd/system/nix-daemon.service" }],
+  "users": [{ "name": "nixbld1", "uid": 30001 }],
+  "groups": [{ "name": "nixbld", "gid": 30000 }],
+  "volumes": []
+}
+```
+
+Files are hashed as they are when the manifest is made, symlinks are listed with their `target` instead.
+*/
+
+use std::path::{Path, PathBuf};
+
+use sha2::{Digest, Sha256};
+use tokio::process::Command;
+
+use crate::{action::Artifact, InstallPlan};
+
+pub const MANIFEST_LOCATION: &str = "/nix/manifest.json";
+/// The `nix` of the default profile, for anything which runs Nix once it is installed
+pub(crate) const NIX_BIN: &str = "/nix/var/nix/profiles/default/bin/nix";
+/// The default profile, which Nix itself (and `--extra-profile-package`s) are installed into
+pub(crate) const DEFAULT_PROFILE: &str = "/nix/var/nix/profiles/default";
+
+/// Everything an [`InstallPlan`] placed on the system, see [`InstallPlan::manifest`]
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct Manifest {
+    pub nix_installer_version: String,
+    pub planner: String,
+    pub nix: Option<NixPackage>,
+    pub files: Vec<ManifestFile>,
+    pub directories: Vec<PathBuf>,
+    pub services: Vec<ManifestService>,
+    pub users: Vec<ManifestUser>,
+    pub groups: Vec<ManifestGroup>,
+    /// On macOS, the Nix volume
+    pub volumes: Vec<ManifestVolume>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct NixPackage {
+    /// What `nix --version` reports, if it could be run
+    pub version: Option<String>,
+    pub source: String,
+    /// The SHA-256 of the tarball Nix was unpacked from, hex encoded
+    pub sha256: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct ManifestFile {
+    pub path: PathBuf,
+    /// The SHA-256 of its contents, hex encoded, unless it is a symlink or couldn't be read
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub target: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct ManifestService {
+    pub name: String,
+    pub path: PathBuf,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct ManifestUser {
+    pub name: String,
+    pub uid: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct ManifestGroup {
+    pub name: String,
+    pub gid: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct ManifestVolume {
+    pub name: String,
+    pub mount_point: PathBuf,
+}
+
+impl Manifest {
+    /// The manifest of the completed actions of `plan`
+    #[tracing::instrument(level = "debug", skip_all)]
+    pub async fn of(plan: &InstallPlan) -> Self {
+        let artifacts = plan
+            .actions
+            .iter()
+            .flat_map(|action| action.artifacts())
+            .collect::<Vec<_>>();
+        let mut manifest = Self::from_artifacts(plan.planner.typetag_name(), artifacts).await;
+        if let Some(nix) = &mut manifest.nix {
+            nix.version = nix_version().await;
+        }
+        manifest
+    }
+
+    async fn from_artifacts(planner: &str, artifacts: Vec<Artifact>) -> Self {
+        let mut manifest = Self {
+            nix_installer_version: env!("CARGO_PKG_VERSION").to_string(),
+            planner: planner.to_string(),
+            nix: None,
+            files: vec![],
+            directories: vec![],
+            services: vec![],
+            users: vec![],
+            groups: vec![],
+            volumes: vec![],
+        };
+        // Several actions may touch the same file (like a shell profile), each is listed once
+        for artifact in artifacts {
+            match artifact {
+                Artifact::NixPackage { source, sha256 } => {
+                    manifest.nix = Some(NixPackage {
+                        version: None,
+                        source,
+                        sha256,
+                    })
+                },
+                Artifact::File(path) => {
+                    if !manifest.files.iter().any(|file| file.path == path) {
+                        manifest.files.push(describe_file(path).await);
+                    }
+                },
+                Artifact::Directory(path) => {
+                    if !manifest.directories.contains(&path) {
+                        manifest.directories.push(path);
+                    }
+                },
+                Artifact::Service { name, path } => {
+                    if !manifest.services.iter().any(|service| service.name == name) {
+                        manifest.services.push(ManifestService { name, path });
+                    }
+                },
+                Artifact::User { name, uid } => manifest.users.push(ManifestUser { name, uid }),
+                Artifact::Group { name, gid } => manifest.groups.push(ManifestGroup { name, gid }),
+                Artifact::Volume { name, mount_point } => {
+                    manifest.volumes.push(ManifestVolume { name, mount_point })
+                },
+            }
+        }
+        manifest
+    }
+
+    /// Write the manifest to `path`, readable by anyone since it holds nothing secret
+    pub async fn write_to(&self, path: impl AsRef<Path>) -> std::io::Result<()> {
+        let path = path.as_ref();
+        let contents = format!("{}\n", serde_json::to_string_pretty(self)?);
+        tokio::fs::write(path, &contents).await?;
+        crate::audit::file_written(path, contents.as_bytes());
+        Ok(())
+    }
+}
+
+async fn describe_file(path: PathBuf) -> ManifestFile {
+    if let Ok(target) = tokio::fs::read_link(&path).await {
+        return ManifestFile {
+            path,
+            sha256: None,
+            target: Some(target),
+        };
+    }
+    let sha256 = match tokio::fs::read(&path).await {
+        Ok(contents) => Some(format!("{:x}", Sha256::digest(contents))),
+        Err(err) => {
+            tracing::debug!(%err, path = %path.display(), "Could not hash a file for the manifest");
+            None
+        },
+    };
+    ManifestFile {
+        path,
+        sha256,
+        target: None,
+    }
+}
+
+async fn nix_version() -> Option<String> {
+    let output = Command::new(NIX_BIN)
+        .arg("--version")
+        .stdin(std::process::Stdio::null())
+        .output()
+        .await
+        .ok()?;
+    output
+        .status
+        .success()
+        .then(|| String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Manifest, ManifestFile, ManifestUser};
+    use crate::action::Artifact;
+
+    #[tokio::test]
+    async fn lists_each_artifact_once() -> eyre::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let profile = temp_dir.path().join("profile");
+        std::fs::write(&profile, "boop")?;
+        let link = temp_dir.path().join("link");
+        std::os::unix::fs::symlink(&profile, &link)?;
+
+        let manifest = Manifest::from_artifacts(
+            "linux",
+            vec![
+                Artifact::File(profile.clone()),
+                Artifact::User {
+                    name: "nixbld1".into(),
+                    uid: 30001,
+                },
+                Artifact::File(profile.clone()),
+                Artifact::File(link.clone()),
+            ],
+        )
+        .await;
+
+        assert_eq!(
+            manifest.files,
+            vec![
+                ManifestFile {
+                    path: profile.clone(),
+                    sha256: Some(
+                        "016c756f0e615ef70ca05eb499a74d31c2ddec7244760d59d792583c0410b36d"
+                            .to_string()
+                    ),
+                    target: None,
+                },
+                ManifestFile {
+                    path: link,
+                    sha256: None,
+                    target: Some(profile),
+                },
+            ]
+        );
+        assert_eq!(
+            manifest.users,
+            vec![ManifestUser {
+                name: "nixbld1".into(),
+                uid: 30001,
+            }]
+        );
+        assert!(manifest.nix.is_none());
+        Ok(())
+    }
+}
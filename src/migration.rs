@@ -0,0 +1,235 @@
+/*! Upgrading receipts written by older `nix-installer`s to the current schema
+
+Each receipt records the [`SCHEMA_VERSION`] it was written with (receipts from before it was
+recorded are version `0`). When a receipt is read with [`InstallPlan::from_receipt`](crate::InstallPlan::from_receipt),
+each migration from its version up to the current one is applied to it, in order, before it is parsed.
+
+When a change to an [`Action`](crate::action::Action) (or [`Planner`](crate::planner::Planner)) can't be
+read from older receipts with a `#[serde(default)]`, bump [`SCHEMA_VERSION`] by adding a migration to
+`MIGRATIONS`, and a fixture receipt in the old shape to `tests/fixtures`.
+*/
+
+use serde_json::{Map, Value};
+
+/// A migration from one schema version to the next, changing the receipt in place
+type Migration = fn(&mut Map<String, Value>) -> Result<(), MigrationError>;
+
+/// The migrations from each schema version to the next, the first migrates version `0` to `1`
+const MIGRATIONS: &[Migration] = &[v0_to_v1];
+
+/// The version of the receipt schema this `nix-installer` writes
+pub const SCHEMA_VERSION: u32 = MIGRATIONS.len() as u32;
+
+/// Names an action may be recorded under, and the name this `nix-installer` reads it as
+///
+/// A few actions are recorded under a different name than their [`ActionTag`](crate::action::ActionTag),
+/// receipts which record them under their tag are read the same.
+const ACTION_ALIASES: &[(&str, &str)] = &[
+    ("move_unpacked_nix", "mount_unpacked_nix"),
+    ("unmount_apfs_volume", "unmount_volume"),
+    ("encrypt_apfs_volume", "encrypt_volume"),
+    ("create_nix_volume", "create_apfs_volume"),
+];
+
+#[non_exhaustive]
+#[derive(Debug, thiserror::Error)]
+pub enum MigrationError {
+    #[error("The receipt is not a JSON object")]
+    NotAnObject,
+    #[error("`schema_version` in the receipt is not a number")]
+    InvalidSchemaVersion,
+    #[error("The receipt has schema version {found}, but this `nix-installer` only understands up to version {SCHEMA_VERSION}, use the `nix-installer` which wrote it (usually `/nix/nix-installer`)")]
+    TooNew { found: u64 },
+    #[error("Upgrading the receipt from schema version {from}: {reason}")]
+    Failed { from: u32, reason: String },
+}
+
+/// Upgrade `receipt` to the current [`SCHEMA_VERSION`], returning the version it had
+pub fn migrate(receipt: &mut Value) -> Result<u32, MigrationError> {
+    let receipt = receipt.as_object_mut().ok_or(MigrationError::NotAnObject)?;
+    let found = match receipt.get("schema_version") {
+        None => 0,
+        Some(version) => version
+            .as_u64()
+            .ok_or(MigrationError::InvalidSchemaVersion)?,
+    };
+    if found > u64::from(SCHEMA_VERSION) {
+        return Err(MigrationError::TooNew { found });
+    }
+    let found = found as u32;
+
+    for (from, migration) in MIGRATIONS.iter().enumerate().skip(found as usize) {
+        tracing::debug!(from, to = from + 1, "Upgrading the receipt schema");
+        migration(receipt)?;
+    }
+    if let Some(actions) = receipt.get_mut("actions") {
+        rename_aliased_actions(actions);
+    }
+    receipt.insert("schema_version".into(), SCHEMA_VERSION.into());
+    Ok(found)
+}
+
+/// Rename every action (at any depth) recorded under one of the [`ACTION_ALIASES`]
+pub(crate) fn rename_aliased_actions(actions: &mut Value) {
+    match actions {
+        Value::Object(object) => {
+            let alias = object
+                .get("action_name")
+                .and_then(Value::as_str)
+                .and_then(|name| ACTION_ALIASES.iter().find(|(alias, _)| *alias == name));
+            if let Some((alias, name)) = alias {
+                tracing::debug!("Reading `{alias}` as `{name}`");
+                object.insert("action_name".into(), (*name).into());
+            }
+            object.values_mut().for_each(rename_aliased_actions);
+        },
+        Value::Array(values) => values.iter_mut().for_each(rename_aliased_actions),
+        _ => (),
+    }
+}
+
+/// Call `f` on every action (at any depth) named `action_name`
+fn for_each_action(
+    value: &mut Value,
+    action_name: &str,
+    f: &mut impl FnMut(&mut Map<String, Value>),
+) {
+    match value {
+        Value::Object(object) => {
+            if object.get("action_name").and_then(Value::as_str) == Some(action_name) {
+                f(object);
+            }
+            for value in object.values_mut() {
+                for_each_action(value, action_name, f);
+            }
+        },
+        Value::Array(values) => {
+            for value in values {
+                for_each_action(value, action_name, f);
+            }
+        },
+        _ => (),
+    }
+}
+
+/// Receipts from before the schema was versioned
+///
+/// The oldest of them don't record whether a `create_directory` is a mountpoint (or pruned on
+/// revert), or the `comment` of a `create_user`.
+fn v0_to_v1(receipt: &mut Map<String, Value>) -> Result<(), MigrationError> {
+    let Some(actions) = receipt.get_mut("actions") else {
+        return Err(MigrationError::Failed {
+            from: 0,
+            reason: "it has no `actions`".into(),
+        });
+    };
+
+    for_each_action(actions, "create_directory", &mut |action| {
+        action.entry("is_mountpoint").or_insert(false.into());
+        action
+            .entry("force_prune_on_revert")
+            .or_insert(false.into());
+    });
+    for_each_action(actions, "create_user", &mut |action| {
+        if action.contains_key("comment") {
+            return;
+        }
+        let comment = match action.get("name").and_then(Value::as_str) {
+            Some(name) => format!(
+                "Nix build user {}",
+                name.trim_start_matches(|c: char| !c.is_ascii_digit())
+            ),
+            None => "Nix build user".to_string(),
+        };
+        action.insert("comment".into(), comment.into());
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{migrate, MigrationError, SCHEMA_VERSION};
+
+    #[test]
+    fn fills_in_fields_older_receipts_lack() -> Result<(), MigrationError> {
+        let mut receipt = serde_json::json!({
+            "version": "0.4.0",
+            "actions": [{
+                "action": {
+                    "action_name": "create_users_and_group",
+                    "create_users": [{
+                        "action": {
+                            "action_name": "create_user",
+                            "name": "nixbld3",
+                            "uid": 30003,
+                            "groupname": "nixbld",
+                            "gid": 30000,
+                        },
+                        "state": "Completed",
+                    }],
+                },
+                "state": "Completed",
+            }, {
+                "action": { "action_name": "create_directory", "path": "/nix", "user": null, "group": null, "mode": 493 },
+                "state": "Completed",
+            }],
+        });
+        assert_eq!(migrate(&mut receipt)?, 0);
+
+        assert_eq!(receipt["schema_version"], SCHEMA_VERSION);
+        assert_eq!(
+            receipt["actions"][0]["action"]["create_users"][0]["action"]["comment"],
+            "Nix build user 3"
+        );
+        assert_eq!(receipt["actions"][1]["action"]["is_mountpoint"], false);
+        assert_eq!(
+            receipt["actions"][1]["action"]["force_prune_on_revert"],
+            false
+        );
+
+        // Migrating again changes nothing
+        let migrated = receipt.clone();
+        assert_eq!(migrate(&mut receipt)?, SCHEMA_VERSION);
+        assert_eq!(receipt, migrated);
+        Ok(())
+    }
+
+    #[test]
+    fn reads_actions_recorded_under_their_tag() -> Result<(), MigrationError> {
+        let mut receipt = serde_json::json!({
+            "schema_version": SCHEMA_VERSION,
+            "actions": [{
+                "action": {
+                    "action_name": "provision_nix",
+                    "move_unpacked_nix": {
+                        "action": { "action_name": "move_unpacked_nix", "unpacked_path": "/nix/temp-install-dir" },
+                        "state": "Completed",
+                    },
+                },
+                "state": "Completed",
+            }],
+        });
+        migrate(&mut receipt)?;
+        assert_eq!(
+            receipt["actions"][0]["action"]["move_unpacked_nix"]["action"]["action_name"],
+            "mount_unpacked_nix"
+        );
+        assert_eq!(
+            receipt["actions"][0]["action"]["action_name"],
+            "provision_nix"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn refuses_newer_receipts() {
+        let mut receipt = serde_json::json!({
+            "schema_version": SCHEMA_VERSION + 1,
+            "actions": [],
+        });
+        assert!(matches!(
+            migrate(&mut receipt),
+            Err(MigrationError::TooNew { .. })
+        ));
+    }
+}
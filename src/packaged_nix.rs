@@ -0,0 +1,308 @@
+/*! Taking over Nix installed by the distribution's package manager (like Debian's `nix-bin`, or Arch's `nix`), see `nix-installer migrate`
+
+A packaged Nix keeps its commands in `/usr/bin` and its daemon's systemd units in
+`/usr/lib/systemd/system`, with the store and its database in `/nix` as usual. Installing over it
+fails partway, so [`PackagedNix::detect`] finds it (and the package it is from) before an install
+is planned. `nix-installer install` refuses it, naming the package, and `nix-installer migrate`
+takes it over: [`PackagedNix::normalize`] stops and disables the packaged daemon, then Nix is
+placed in the default profile, its daemon's units are placed in `/etc/systemd/system` (where they
+take precedence over the package's), and the rest of the install, like the store, is
+[adopted](crate::adopt).
+
+The package itself is left installed, its commands are behind the default profile's on `PATH`.
+*/
+
+use std::path::{Path, PathBuf};
+
+use tokio::process::Command;
+use which::which;
+
+use crate::{
+    action::ActionErrorKind, adopt::distribution_of, execute_command, settings::Distribution,
+};
+
+/// The commands a packaged Nix installs, the first one found is asked after
+const BINARIES: &[&str] = &["/usr/bin/nix", "/usr/bin/nix-daemon"];
+/// Where packages place the daemon's systemd units
+const UNITS: &[&str] = &[
+    "/usr/lib/systemd/system/nix-daemon.service",
+    "/usr/lib/systemd/system/nix-daemon.socket",
+    "/lib/systemd/system/nix-daemon.service",
+    "/lib/systemd/system/nix-daemon.socket",
+];
+/// The store `nix-installer` installs to, and adopts
+const STORE_DIR: &str = "/nix/store";
+
+/// A package manager which may have installed Nix
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PackageManager {
+    /// Debian, Ubuntu, and their derivatives
+    Dpkg,
+    /// Arch, and its derivatives
+    Pacman,
+    /// Fedora, openSUSE, and their derivatives
+    Rpm,
+}
+
+impl PackageManager {
+    const ALL: &'static [PackageManager] = &[Self::Dpkg, Self::Pacman, Self::Rpm];
+
+    /// What asks this package manager which package has a path
+    fn executable(&self) -> &'static str {
+        match self {
+            Self::Dpkg => "dpkg-query",
+            Self::Pacman => "pacman",
+            Self::Rpm => "rpm",
+        }
+    }
+
+    /// The command which prints the package owning `path`
+    fn owner_command(&self, path: &Path) -> Command {
+        let mut command = Command::new(self.executable());
+        match self {
+            Self::Dpkg => command.arg("--search"),
+            Self::Pacman => command.arg("-Qqo"),
+            Self::Rpm => command.args(["--query", "--file", "--queryformat", "%{NAME}\n"]),
+        };
+        command.arg(path).stdin(std::process::Stdio::null());
+        command
+    }
+
+    /// The package [`owner_command`](Self::owner_command) printed as `stdout`, if any
+    fn parse_owner(&self, stdout: &str) -> Option<String> {
+        let line = stdout.lines().find(|line| !line.trim().is_empty())?;
+        let package = match self {
+            // `nix-bin: /usr/bin/nix`, or `nix-bin, nix-setup-systemd: /usr/bin/nix` if several have the path
+            Self::Dpkg => line.split_once(": ")?.0.split(',').next()?,
+            Self::Pacman | Self::Rpm => line,
+        };
+        let package = package.trim();
+        (!package.is_empty()).then(|| package.to_string())
+    }
+
+    /// How `package` is removed with this package manager
+    pub fn remove_command(&self, package: &str) -> String {
+        match self {
+            Self::Dpkg => format!("apt remove {package}"),
+            Self::Pacman => format!("pacman -R {package}"),
+            Self::Rpm => format!("dnf remove {package}"),
+        }
+    }
+}
+
+impl std::fmt::Display for PackageManager {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Dpkg => write!(f, "dpkg"),
+            Self::Pacman => write!(f, "pacman"),
+            Self::Rpm => write!(f, "rpm"),
+        }
+    }
+}
+
+/// Nix installed by a package manager, see [`PackagedNix::detect`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PackagedNix {
+    /// The package Nix is from, like `nix-bin`
+    pub package: String,
+    pub manager: PackageManager,
+    /// The packaged `nix` (or `nix-daemon`)
+    pub binary: PathBuf,
+    /// The packaged units of the daemon
+    pub units: Vec<PathBuf>,
+}
+
+impl PackagedNix {
+    /// The packaged Nix on this system, if there is one
+    ///
+    /// Nix is packaged if a package manager says it owns `/usr/bin/nix` (or `/usr/bin/nix-daemon`).
+    #[tracing::instrument(level = "debug")]
+    pub async fn detect() -> Option<Self> {
+        let binary = BINARIES
+            .iter()
+            .map(PathBuf::from)
+            .find(|binary| binary.exists())?;
+
+        let mut found = None;
+        for manager in PackageManager::ALL {
+            if which(manager.executable()).is_err() {
+                continue;
+            }
+            let output = match manager.owner_command(&binary).output().await {
+                Ok(output) if output.status.success() => output,
+                Ok(_) => continue,
+                Err(err) => {
+                    tracing::debug!(%err, "Could not ask `{manager}` which package has `{}`", binary.display());
+                    continue;
+                },
+            };
+            if let Some(package) = manager.parse_owner(&String::from_utf8_lossy(&output.stdout)) {
+                found = Some((*manager, package));
+                break;
+            }
+        }
+        let Some((manager, package)) = found else {
+            tracing::debug!(
+                "No package has `{}`, Nix wasn't installed by a package manager",
+                binary.display()
+            );
+            return None;
+        };
+
+        // On merged-`/usr` systems `/lib` is `/usr/lib`, so the units are found twice
+        let mut units: Vec<PathBuf> = vec![];
+        for unit in UNITS.iter().map(PathBuf::from).filter(|unit| unit.exists()) {
+            let canonical = unit.canonicalize().unwrap_or_else(|_| unit.clone());
+            if !units
+                .iter()
+                .any(|found| found.canonicalize().ok().as_ref() == Some(&canonical))
+            {
+                units.push(unit);
+            }
+        }
+
+        Some(Self {
+            package,
+            manager,
+            binary,
+            units,
+        })
+    }
+
+    /// The store the packaged Nix uses, according to its `nix-instantiate`
+    async fn store_dir(&self) -> PathBuf {
+        let nix_instantiate = self.binary.with_file_name("nix-instantiate");
+        match Command::new(&nix_instantiate)
+            .args(["--eval", "--expr", "builtins.storeDir"])
+            .stdin(std::process::Stdio::null())
+            .output()
+            .await
+        {
+            Ok(output) if output.status.success() => PathBuf::from(
+                String::from_utf8_lossy(&output.stdout)
+                    .trim()
+                    .trim_matches('"'),
+            ),
+            _ => {
+                tracing::debug!(
+                    "Could not ask `{}` for the store, assuming `{STORE_DIR}`",
+                    nix_instantiate.display()
+                );
+                PathBuf::from(STORE_DIR)
+            },
+        }
+    }
+
+    /// The [`Distribution`] of the packaged Nix, like Lix for Arch's `lix`
+    pub async fn distribution(&self) -> Distribution {
+        let nix = self.binary.with_file_name("nix");
+        match Command::new(&nix)
+            .arg("--version")
+            .stdin(std::process::Stdio::null())
+            .output()
+            .await
+        {
+            Ok(output) => {
+                distribution_of(&String::from_utf8_lossy(&output.stdout)).unwrap_or_default()
+            },
+            Err(err) => {
+                tracing::debug!(%err, "Could not run `{} --version`, assuming upstream Nix", nix.display());
+                Distribution::Upstream
+            },
+        }
+    }
+
+    /// Check the packaged Nix can be taken over, its store must be the one `nix-installer` adopts
+    pub async fn check_takeover(&self) -> Result<(), PackagedNixError> {
+        let store = self.store_dir().await;
+        if store != Path::new(STORE_DIR) {
+            return Err(PackagedNixError::OtherStore {
+                package: self.package.clone(),
+                store,
+            });
+        }
+        Ok(())
+    }
+
+    /// What [`normalize`](Self::normalize) will do, for the user to review
+    pub fn describe_normalize(&self) -> Vec<String> {
+        let mut step = format!(
+            "Stop and disable the Nix daemon of the `{}` package",
+            self.package
+        );
+        if !self.units.is_empty() {
+            step.push_str(&format!(
+                " ({})",
+                self.units
+                    .iter()
+                    .map(|unit| format!("`{}`", unit.display()))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ));
+        }
+        vec![
+            step,
+            "Place Nix in `/nix/var/nix/profiles/default`, keeping the existing store".to_string(),
+        ]
+    }
+
+    /// Stop and disable the packaged daemon, so the one `nix-installer` places can replace it
+    #[tracing::instrument(level = "debug", skip_all)]
+    pub async fn normalize(&self) -> Result<(), PackagedNixError> {
+        if self.units.is_empty() {
+            return Ok(());
+        }
+        execute_command(
+            Command::new("systemctl")
+                .args([
+                    "disable",
+                    "--now",
+                    "nix-daemon.socket",
+                    "nix-daemon.service",
+                ])
+                .stdin(std::process::Stdio::null()),
+        )
+        .await
+        .map_err(|e| PackagedNixError::Disable(self.package.clone(), Box::new(e)))?;
+        Ok(())
+    }
+}
+
+#[non_exhaustive]
+#[derive(Debug, thiserror::Error)]
+pub enum PackagedNixError {
+    #[error("The `{package}` package keeps its store in `{}`, not `/nix/store`, so it can't be taken over, remove the package to install Nix afresh", .store.display())]
+    OtherStore { package: String, store: PathBuf },
+    #[error("Stopping and disabling the Nix daemon of the `{0}` package")]
+    Disable(String, #[source] Box<ActionErrorKind>),
+}
+
+#[cfg(test)]
+mod test {
+    use super::PackageManager;
+
+    #[test]
+    fn parses_package_owners() {
+        assert_eq!(
+            PackageManager::Dpkg
+                .parse_owner("nix-bin: /usr/bin/nix\n")
+                .as_deref(),
+            Some("nix-bin")
+        );
+        assert_eq!(
+            PackageManager::Dpkg
+                .parse_owner("nix-bin, nix-setup-systemd: /usr/bin/nix\n")
+                .as_deref(),
+            Some("nix-bin")
+        );
+        assert_eq!(PackageManager::Dpkg.parse_owner("\n"), None);
+        assert_eq!(
+            PackageManager::Pacman.parse_owner("lix\n").as_deref(),
+            Some("lix")
+        );
+        assert_eq!(
+            PackageManager::Rpm.parse_owner("nix\n").as_deref(),
+            Some("nix")
+        );
+    }
+}
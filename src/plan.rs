@@ -1,15 +1,38 @@
-use std::{path::PathBuf, str::FromStr};
+use std::{
+    os::unix::fs::{MetadataExt, PermissionsExt},
+    path::{Path, PathBuf},
+    str::FromStr,
+};
 
 use crate::{
-    action::{Action, ActionDescription, StatefulAction},
+    action::{
+        base::UnknownAction, Action, ActionDependencies, ActionDescription, ActionState, ActionTag,
+        StatefulAction,
+    },
+    audit::{self, AuditEvent},
+    manifest::{Manifest, MANIFEST_LOCATION},
+    migration::{self, MigrationError, SCHEMA_VERSION},
     planner::{BuiltinPlanner, Planner},
+    progress::{self, ProgressEvent},
+    receipt_signature,
+    settings::{Distribution, SYSTEM},
     NixInstallerError,
 };
 use owo_colors::OwoColorize;
 use semver::{Version, VersionReq};
-use tokio::sync::broadcast::Receiver;
+use tokio::{io::AsyncWriteExt, sync::broadcast::Receiver};
 
 pub const RECEIPT_LOCATION: &str = "/nix/receipt.json";
+/// Where `nix-installer` keeps state outside of `/nix`, only root may read or write in it
+pub const STATE_DIR: &str = "/var/lib/nix-installer";
+/// A copy of the receipt kept outside of `/nix`, so Nix can still be uninstalled if `/nix` (or its
+/// volume) was wiped without uninstalling
+pub const RECEIPT_BACKUP_LOCATION: &str = "/var/lib/nix-installer/receipt.json";
+/// Where the plan is recorded after each action while installing, `/nix` may not exist yet (or may be
+/// an unmounted volume) so it is kept outside of it, somewhere which survives a reboot
+pub const JOURNAL_LOCATION: &str = "/var/lib/nix-installer/journal.json";
+/// How many independent actions [`InstallPlan::install`] runs at once
+pub const DEFAULT_JOBS: usize = 4;
 
 /**
 A set of [`Action`]s, along with some metadata, which can be carried out to drive an install or
@@ -19,10 +42,18 @@ revert
 pub struct InstallPlan {
     pub(crate) version: Version,
 
+    /// The shape of the receipt, see [`migration`](crate::migration)
+    #[serde(default)]
+    pub(crate) schema_version: u32,
+
     pub(crate) actions: Vec<StatefulAction<Box<dyn Action>>>,
 
     pub(crate) planner: Box<dyn Planner>,
 
+    /// The host the plan was made on, plans from before it was recorded can be applied anywhere
+    #[serde(default)]
+    pub(crate) host: Option<HostFingerprint>,
+
     #[cfg(feature = "diagnostics")]
     pub(crate) diagnostic_data: Option<crate::diagnostics::DiagnosticData>,
 }
@@ -41,6 +72,8 @@ impl InstallPlan {
             planner,
             actions,
             version: current_version()?,
+            schema_version: SCHEMA_VERSION,
+            host: Some(HostFingerprint::current()),
             #[cfg(feature = "diagnostics")]
             diagnostic_data,
         })
@@ -63,11 +96,215 @@ impl InstallPlan {
             planner: planner.boxed(),
             actions,
             version: current_version()?,
+            schema_version: SCHEMA_VERSION,
+            host: Some(HostFingerprint::current()),
+            #[cfg(feature = "diagnostics")]
+            diagnostic_data,
+        })
+    }
+
+    /// Plan again with the same planner (and settings), as this version of `nix-installer` would,
+    /// to compare with this plan (see [`plan_diff`](crate::plan_diff))
+    ///
+    /// Not `pre_install_check`ed, this plan may be the receipt of the Nix on the system.
+    pub async fn replan(&self) -> Result<Self, NixInstallerError> {
+        self.planner.platform_check().await?;
+
+        #[cfg(feature = "diagnostics")]
+        let diagnostic_data = Some(self.planner.diagnostic_data().await?);
+
+        let actions = self.planner.plan().await?;
+        Ok(Self {
+            planner: self.planner.clone(),
+            actions,
+            version: current_version()?,
+            schema_version: SCHEMA_VERSION,
+            host: Some(HostFingerprint::current()),
+            #[cfg(feature = "diagnostics")]
+            diagnostic_data,
+        })
+    }
+
+    /// Plan an install of the Nix already on the system, recorded as done, see [`adopt`](crate::adopt)
+    ///
+    /// Each action finds what of it is already in place (as it does when an install is resumed),
+    /// then each action which has any of its actions in place is marked completed too, so an
+    /// uninstall reverts what was found and nothing else.
+    pub async fn adopt<P>(planner: P) -> Result<Self, NixInstallerError>
+    where
+        P: Planner + 'static,
+    {
+        planner.platform_check().await?;
+
+        #[cfg(feature = "diagnostics")]
+        let diagnostic_data = Some(planner.diagnostic_data().await?);
+
+        // Not `pre_install_check`, it refuses a system which already has Nix
+        let actions = planner.plan().await?;
+        let mut actions = serde_json::to_value(&actions)?;
+        mark_found_completed(&mut actions);
+        let actions = serde_json::from_value(actions)?;
+        Ok(Self {
+            planner: planner.boxed(),
+            actions,
+            version: current_version()?,
+            schema_version: SCHEMA_VERSION,
+            host: Some(HostFingerprint::current()),
             #[cfg(feature = "diagnostics")]
             diagnostic_data,
         })
     }
 
+    /// Execute the actions (at any depth) with one of `tags` which aren't completed, like those of
+    /// an [adopted](Self::adopt) plan which weren't found in place
+    ///
+    /// The actions they are within are marked completed after, as with [`adopt`](Self::adopt).
+    pub(crate) async fn execute_tagged(
+        &mut self,
+        tags: &[ActionTag],
+    ) -> Result<(), NixInstallerError> {
+        let mut actions = serde_json::to_value(&self.actions)?;
+        let mut pointers = vec![];
+        find_tagged(&actions, tags, String::new(), &mut pointers);
+        for pointer in pointers {
+            let Some(value) = actions.pointer_mut(&pointer) else {
+                continue;
+            };
+            let mut action: StatefulAction<Box<dyn Action>> = serde_json::from_value(value.take())?;
+            tracing::info!("Step: {}", action.tracing_synopsis());
+            let res = action.try_execute().await;
+            *value = serde_json::to_value(&action)?;
+            res.map_err(NixInstallerError::Action)?;
+        }
+        mark_found_completed(&mut actions);
+        self.actions = serde_json::from_value(actions)?;
+        Ok(())
+    }
+
+    /// Plan the install again and execute its actions (at any depth) with one of `tags` which aren't
+    /// in place, see `nix-installer repair`
+    ///
+    /// Returns the synopses of the actions executed. The receipt is left as it is, it already records
+    /// the repaired actions as done.
+    pub(crate) async fn repair_tagged(
+        &self,
+        tags: &[ActionTag],
+    ) -> Result<Vec<String>, NixInstallerError> {
+        let actions = self.planner.plan().await?;
+        let mut actions = serde_json::to_value(&actions)?;
+        let mut pointers = vec![];
+        find_tagged(&actions, tags, String::new(), &mut pointers);
+        let mut repaired = vec![];
+        for pointer in pointers {
+            let Some(value) = actions.pointer_mut(&pointer) else {
+                continue;
+            };
+            let mut action: StatefulAction<Box<dyn Action>> = serde_json::from_value(value.take())?;
+            tracing::info!("Step: {}", action.tracing_synopsis());
+            action
+                .try_execute()
+                .await
+                .map_err(NixInstallerError::Action)?;
+            repaired.push(action.tracing_synopsis());
+        }
+        Ok(repaired)
+    }
+
+    /// Parse a receipt (or journal), upgrading one written by an older `nix-installer` to the current schema
+    ///
+    /// See [`migration`](crate::migration).
+    pub fn from_receipt(receipt: &str) -> Result<Self, NixInstallerError> {
+        let mut value: serde_json::Value =
+            serde_json::from_str(receipt).map_err(NixInstallerError::SerializingReceipt)?;
+        let schema_version = migration::migrate(&mut value)?;
+        if schema_version != SCHEMA_VERSION {
+            tracing::debug!(
+                from = schema_version,
+                to = SCHEMA_VERSION,
+                "Upgraded the receipt schema"
+            );
+        }
+        serde_json::from_value(value).map_err(NixInstallerError::SerializingReceipt)
+    }
+
+    /// Like [`from_receipt`](Self::from_receipt), for uninstalling a receipt written by an older or newer `nix-installer`
+    ///
+    /// Actions this `nix-installer` can't read become [`UnknownAction`]s, which are only undone by
+    /// hand, see [`manual_steps`](Self::manual_steps).
+    pub fn from_receipt_lossy(receipt: &str) -> Result<Self, NixInstallerError> {
+        let mut value: serde_json::Value =
+            serde_json::from_str(receipt).map_err(NixInstallerError::SerializingReceipt)?;
+        match migration::migrate(&mut value) {
+            Ok(_) => (),
+            Err(MigrationError::TooNew { found }) => {
+                tracing::warn!("The receipt has schema version {found}, newer than this `nix-installer` understands, undoing what it can");
+                if let Some(actions) = value.get_mut("actions") {
+                    migration::rename_aliased_actions(actions);
+                }
+                value["schema_version"] = SCHEMA_VERSION.into();
+            },
+            Err(err) => return Err(err.into()),
+        }
+
+        if let Some(actions) = value
+            .get_mut("actions")
+            .and_then(serde_json::Value::as_array_mut)
+        {
+            for action in actions {
+                let Err(err) =
+                    serde_json::from_value::<StatefulAction<Box<dyn Action>>>(action.clone())
+                else {
+                    continue;
+                };
+                let original = action.get("action").cloned().unwrap_or(action.clone());
+                let state = action
+                    .get("state")
+                    .cloned()
+                    .unwrap_or_else(|| "Completed".into());
+                let unknown = UnknownAction::new(original, err.to_string());
+                tracing::warn!(
+                    "Could not read `{}` from the receipt: {err}",
+                    unknown.name()
+                );
+                *action = serde_json::json!({
+                    "action": serde_json::to_value(&unknown)
+                        .map_err(NixInstallerError::SerializingReceipt)?,
+                    "state": state,
+                });
+            }
+        }
+        serde_json::from_value(value).map_err(NixInstallerError::SerializingReceipt)
+    }
+
+    /// Add an action to the end of the plan, eg a step of a downstream crate's own (cache auth, an
+    /// internal CA, ...) after a [`BuiltinPlanner`]'s plan
+    ///
+    /// It is executed, reverted, and recorded in the receipt like the planner's actions, so its type
+    /// must be registered with `#[typetag::serde]` (see [`Action`]). `nix-installer`s without the type
+    /// read it back as an [`UnknownAction`].
+    pub fn push_action(&mut self, action: StatefulAction<Box<dyn Action>>) {
+        self.actions.push(action);
+    }
+
+    /// The steps to undo, by hand, the actions of the plan this `nix-installer` couldn't read
+    pub fn manual_steps(&self) -> Vec<ActionDescription> {
+        self.actions
+            .iter()
+            .filter(|action| action.inner_typetag_name() == UnknownAction::action_tag().0)
+            .flat_map(|action| action.describe_revert())
+            .collect()
+    }
+
+    /// The [`Distribution`] the plan installs, plans from before it was recorded installed upstream Nix
+    pub fn distribution(&self) -> Distribution {
+        self.planner
+            .settings()
+            .ok()
+            .and_then(|settings| settings.get("distribution").cloned())
+            .and_then(|distribution| serde_json::from_value(distribution).ok())
+            .unwrap_or_default()
+    }
+
     pub async fn pre_uninstall_check(&self) -> Result<(), NixInstallerError> {
         self.planner.platform_check().await?;
         self.planner.pre_uninstall_check().await?;
@@ -154,69 +391,202 @@ impl InstallPlan {
         Ok(buf)
     }
 
+    /// Unified diffs of the changes the planned actions would make to existing files
+    pub fn describe_diffs(&self) -> String {
+        let diffs = self
+            .actions
+            .iter()
+            .flat_map(|action| action.dry_run())
+            .filter_map(|step| step.diff())
+            .collect::<Vec<_>>();
+        if diffs.is_empty() {
+            return String::new();
+        }
+        format!("Changes to existing files:\n\n{}", diffs.join("\n"))
+    }
+
+    /// The exact commands each planned action would run, and the files it would write
+    pub fn describe_dry_run(&self) -> String {
+        let mut buf = format!(
+            "Nix install plan (v{version}) dry run\nPlanner: {planner}\n",
+            version = self.version,
+            planner = self.planner.typetag_name(),
+        );
+        for action in &self.actions {
+            let steps = action.dry_run();
+            if steps.is_empty() {
+                continue;
+            }
+            buf.push_str(&format!("\n* {}\n", action.tracing_synopsis()));
+            for step in steps {
+                buf.push_str(&format!("{step}\n"));
+            }
+        }
+        buf
+    }
+
+    /// The exact commands each completed action would run, and the files it would remove or write, when uninstalling
+    pub fn describe_revert_dry_run(&self) -> String {
+        let mut buf = format!(
+            "Nix uninstall plan (v{version}) dry run\nPlanner: {planner}\n",
+            version = self.version,
+            planner = self.planner.typetag_name(),
+        );
+        for action in self.actions.iter().rev() {
+            let steps = action.revert_dry_run();
+            if steps.is_empty() {
+                continue;
+            }
+            buf.push_str(&format!("\n* {}\n", action.tracing_synopsis()));
+            for step in steps {
+                buf.push_str(&format!("{step}\n"));
+            }
+        }
+        buf
+    }
+
+    /// Everything the completed actions placed on the system, like files, services, users, and the Nix package
+    pub async fn manifest(&self) -> Manifest {
+        Manifest::of(self).await
+    }
+
     #[tracing::instrument(level = "debug", skip_all)]
     pub async fn install(
         &mut self,
         cancel_channel: impl Into<Option<Receiver<()>>>,
+    ) -> Result<(), NixInstallerError> {
+        self.install_with_jobs(cancel_channel, DEFAULT_JOBS).await
+    }
+
+    /// Install, running up to `jobs` actions which don't depend on each other (see [`Action::depends_on`]) at once
+    ///
+    /// With one job, the actions are run in the order they were planned.
+    ///
+    /// Once `cancel_channel` receives, the actions already running are finished, no more are started, and
+    /// [`NixInstallerError::Cancelled`] is returned. No receipt is written, the journal in [`JOURNAL_LOCATION`]
+    /// records what was done, so the install can be resumed, or reverted with [`InstallPlan::uninstall`].
+    #[tracing::instrument(level = "debug", skip(self, cancel_channel))]
+    pub async fn install_with_jobs(
+        &mut self,
+        cancel_channel: impl Into<Option<Receiver<()>>>,
+        jobs: usize,
     ) -> Result<(), NixInstallerError> {
         self.check_compatible()?;
         self.pre_install_check().await?;
 
-        let Self { actions, .. } = self;
+        self.write_journal().await;
+
         let mut cancel_channel = cancel_channel.into();
+        let dependencies = self.dependencies();
+        let mut started = vec![false; self.actions.len()];
+        let mut finished = vec![false; self.actions.len()];
+        let mut running = tokio::task::JoinSet::new();
+        let mut cancelled = false;
+        let mut failure = None;
 
-        // This is **deliberately sequential**.
-        // Actions which are parallelizable are represented by "group actions" like CreateUsers
-        // The plan itself represents the concept of the sequence of stages.
-        for action in actions {
+        loop {
             if let Some(ref mut cancel_channel) = cancel_channel {
                 if cancel_channel.try_recv()
                     != Err(tokio::sync::broadcast::error::TryRecvError::Empty)
                 {
-                    if let Err(err) = self.write_receipt().await {
-                        tracing::error!("Error saving receipt: {:?}", err);
+                    cancelled = true;
+                }
+            }
+
+            // Once something fails or the user cancels, wait for what's running but start nothing new
+            if !cancelled && failure.is_none() {
+                for (index, action) in self.actions.iter().enumerate() {
+                    if running.len() >= jobs.max(1) {
+                        break;
+                    }
+                    if started[index] || !dependencies[index].iter().all(|dep| finished[*dep]) {
+                        continue;
                     }
+                    started[index] = true;
+                    tracing::info!("Step: {}", action.tracing_synopsis());
+                    progress::report(ProgressEvent::ActionStarted {
+                        synopsis: action.tracing_synopsis(),
+                    });
+                    let mut action = action.clone();
+                    running.spawn(async move {
+                        let res = action.try_execute().await;
+                        (index, action, res)
+                    });
+                }
+            }
 
-                    #[cfg(feature = "diagnostics")]
-                    if let Some(diagnostic_data) = &self.diagnostic_data {
-                        diagnostic_data
-                            .clone()
-                            .send(
-                                crate::diagnostics::DiagnosticAction::Install,
-                                crate::diagnostics::DiagnosticStatus::Cancelled,
-                            )
-                            .await?;
+            let Some(joined) = running.join_next().await else {
+                break;
+            };
+            match joined {
+                Ok((index, action, res)) => {
+                    self.actions[index] = action;
+                    // Recorded whether or not the action succeeded, so a crash at any point can be resumed
+                    self.write_journal().await;
+                    match res {
+                        Ok(()) => {
+                            finished[index] = true;
+                            progress::report(ProgressEvent::ActionCompleted {
+                                completed: finished.iter().filter(|finished| **finished).count(),
+                                total: finished.len(),
+                            });
+                        },
+                        Err(err) => {
+                            failure.get_or_insert(NixInstallerError::Action(err));
+                        },
                     }
+                },
+                Err(err) => {
+                    failure.get_or_insert(NixInstallerError::Join(err));
+                },
+            }
+        }
 
-                    return Err(NixInstallerError::Cancelled);
-                }
+        if let Some(err) = failure {
+            if let Err(err) = self.write_receipt().await {
+                tracing::error!("Error saving receipt: {:?}", err);
+            }
+            #[cfg(feature = "diagnostics")]
+            if let Some(diagnostic_data) = &self.diagnostic_data {
+                diagnostic_data
+                    .clone()
+                    .failure(&err)
+                    .send(
+                        crate::diagnostics::DiagnosticAction::Install,
+                        crate::diagnostics::DiagnosticStatus::Failure,
+                    )
+                    .await?;
             }
 
-            tracing::info!("Step: {}", action.tracing_synopsis());
-            if let Err(err) = action.try_execute().await {
-                if let Err(err) = self.write_receipt().await {
-                    tracing::error!("Error saving receipt: {:?}", err);
-                }
-                let err = NixInstallerError::Action(err);
-                #[cfg(feature = "diagnostics")]
-                if let Some(diagnostic_data) = &self.diagnostic_data {
-                    diagnostic_data
-                        .clone()
-                        .failure(&err)
-                        .send(
-                            crate::diagnostics::DiagnosticAction::Install,
-                            crate::diagnostics::DiagnosticStatus::Failure,
-                        )
-                        .await?;
-                }
+            return Err(err);
+        }
 
-                return Err(err);
+        // Only the journal is kept, so the install can be resumed (or reverted), but isn't mistaken for a finished one
+        if cancelled {
+            #[cfg(feature = "diagnostics")]
+            if let Some(diagnostic_data) = &self.diagnostic_data {
+                diagnostic_data
+                    .clone()
+                    .send(
+                        crate::diagnostics::DiagnosticAction::Install,
+                        crate::diagnostics::DiagnosticStatus::Cancelled,
+                    )
+                    .await?;
             }
+
+            return Err(NixInstallerError::Cancelled);
         }
 
         self.write_receipt().await?;
+        remove_journal().await;
+        if let Err(err) = self.manifest().await.write_to(MANIFEST_LOCATION).await {
+            tracing::warn!(
+                "Error writing the manifest `{MANIFEST_LOCATION}`: {:?}",
+                err
+            );
+        }
 
-        if let Err(err) = crate::self_test::self_test()
+        if let Err(err) = crate::self_test::self_test(self.distribution())
             .await
             .map_err(NixInstallerError::SelfTest)
         {
@@ -249,6 +619,15 @@ impl InstallPlan {
         Ok(())
     }
 
+    /// Leave the Nix store out of the uninstall, so only the services, users, and configuration are removed
+    pub fn keep_store(&mut self) {
+        for action in self.actions.iter_mut() {
+            if action.keep_store() {
+                tracing::debug!("Keeping: {}", action.tracing_synopsis());
+            }
+        }
+    }
+
     #[tracing::instrument(level = "debug", skip_all)]
     pub async fn describe_uninstall(&self, explain: bool) -> Result<String, NixInstallerError> {
         let Self {
@@ -325,6 +704,104 @@ impl InstallPlan {
         Ok(buf)
     }
 
+    /// What an install recorded in this receipt (or journal) did, and what uninstalling it would do
+    ///
+    /// Unlike [`describe_install`](Self::describe_install), which leaves out the actions already
+    /// done, every action is described, with whether it was done.
+    pub async fn describe_receipt(&self, explain: bool) -> Result<String, NixInstallerError> {
+        let Self {
+            version,
+            planner,
+            actions,
+            ..
+        } = self;
+
+        let mut plan_settings = planner
+            .configured_settings()
+            .await?
+            .into_iter()
+            .map(|(k, v)| format!("* {k}: {v}", k = k.bold()))
+            .collect::<Vec<_>>();
+        // Stabilize output order
+        plan_settings.sort();
+
+        let describe = |descriptions: Vec<ActionDescription>, note: &str| {
+            descriptions
+                .into_iter()
+                .map(|desc| {
+                    let ActionDescription {
+                        description,
+                        explanation,
+                    } = desc;
+
+                    let mut buf = format!("* {description}{note}");
+                    if explain {
+                        for line in explanation {
+                            buf.push_str(&format!("\n  {line}"));
+                        }
+                    }
+                    buf
+                })
+                .collect::<Vec<_>>()
+        };
+        let done = actions
+            .iter()
+            .flat_map(|action| {
+                let note = match action.state {
+                    ActionState::Completed => "",
+                    ActionState::Skipped => " (skipped)",
+                    ActionState::Progress => " (started, not finished)",
+                    ActionState::Uncompleted => " (not done)",
+                };
+                describe(action.action.execute_description(), note)
+            })
+            .collect::<Vec<_>>();
+        let undo = actions
+            .iter()
+            .rev()
+            .flat_map(|action| describe(action.describe_revert(), ""))
+            .collect::<Vec<_>>();
+
+        let buf = format!(
+            "\
+            Nix install receipt (v{version})\n\
+            Planner: {planner}{maybe_default_setting_note}\n\
+            \n\
+            {maybe_plan_settings}\
+            Actions:\n\
+            {done}\n\
+            \n\
+            Uninstalling would:\n\
+            {undo}\n\
+        ",
+            planner = planner.typetag_name(),
+            maybe_default_setting_note = if plan_settings.is_empty() {
+                String::from(" (with default settings)")
+            } else {
+                String::new()
+            },
+            maybe_plan_settings = if plan_settings.is_empty() {
+                String::new()
+            } else {
+                format!(
+                    "\
+                    Configured settings:\n\
+                    {plan_settings}\n\
+                    \n\
+                ",
+                    plan_settings = plan_settings.join("\n")
+                )
+            },
+            done = done.join("\n"),
+            undo = if undo.is_empty() {
+                String::from("* Nothing, no action was done")
+            } else {
+                undo.join("\n")
+            },
+        );
+        Ok(buf)
+    }
+
     #[tracing::instrument(level = "debug", skip_all)]
     pub async fn uninstall(
         &mut self,
@@ -340,7 +817,8 @@ impl InstallPlan {
         // This is **deliberately sequential**.
         // Actions which are parallelizable are represented by "group actions" like CreateUsers
         // The plan itself represents the concept of the sequence of stages.
-        for action in actions.iter_mut().rev() {
+        let total = actions.len();
+        for (reverted, action) in actions.iter_mut().rev().enumerate() {
             if let Some(ref mut cancel_channel) = cancel_channel {
                 if cancel_channel.try_recv()
                     != Err(tokio::sync::broadcast::error::TryRecvError::Empty)
@@ -364,12 +842,24 @@ impl InstallPlan {
             }
 
             tracing::info!("Revert: {}", action.tracing_synopsis());
+            progress::report(ProgressEvent::RevertStarted {
+                synopsis: action.tracing_synopsis(),
+            });
             if let Err(errs) = action.try_revert().await {
                 errors.push(errs);
             }
+            progress::report(ProgressEvent::RevertCompleted {
+                reverted: reverted + 1,
+                total,
+            });
         }
 
         if errors.is_empty() {
+            remove_journal().await;
+            remove_receipt_backup().await;
+            // Left behind when the store was kept
+            remove_receipt().await;
+
             #[cfg(feature = "diagnostics")]
             if let Some(diagnostic_data) = &self.diagnostic_data {
                 diagnostic_data
@@ -400,7 +890,37 @@ impl InstallPlan {
         }
     }
 
+    /// The indexes of the earlier actions each action must wait for
+    fn dependencies(&self) -> Vec<Vec<usize>> {
+        let (tags, dependencies): (Vec<_>, Vec<_>) = self
+            .actions
+            .iter()
+            .map(|action| (action.inner_typetag_name(), action.action.depends_on()))
+            .unzip();
+        resolve_dependencies(&tags, &dependencies)
+    }
+
+    /// Refuse to apply a plan which was made on a different kind of host
+    pub fn check_host(&self) -> Result<(), NixInstallerError> {
+        let Some(planned) = &self.host else {
+            return Ok(());
+        };
+        let current = HostFingerprint::current();
+        if *planned == current {
+            Ok(())
+        } else {
+            Err(NixInstallerError::HostMismatch {
+                plan: Box::new(planned.clone()),
+                host: Box::new(current),
+            })
+        }
+    }
+
     pub fn check_compatible(&self) -> Result<(), NixInstallerError> {
+        // A receipt in the current schema (or upgraded to it) can be used by any version which writes that schema
+        if self.schema_version == SCHEMA_VERSION {
+            return Ok(());
+        }
         let self_version_string = self.version.to_string();
         let req = VersionReq::parse(&self_version_string)
             .map_err(|e| NixInstallerError::InvalidVersionRequirement(self_version_string, e))?;
@@ -416,31 +936,277 @@ impl InstallPlan {
     }
 
     pub(crate) async fn write_receipt(&self) -> Result<(), NixInstallerError> {
-        let install_receipt_path = PathBuf::from(RECEIPT_LOCATION);
-        let install_receipt_path_tmp = {
-            let mut install_receipt_path_tmp = install_receipt_path.clone();
-            install_receipt_path_tmp.set_extension("tmp");
-            install_receipt_path_tmp
+        tokio::fs::create_dir_all("/nix")
+            .await
+            .map_err(|e| NixInstallerError::RecordingReceipt(PathBuf::from("/nix"), e))?;
+        self.write_to(RECEIPT_LOCATION).await?;
+        receipt_signature::sign(Path::new(RECEIPT_LOCATION)).await?;
+        if let Err(err) = self.write_receipt_backup().await {
+            tracing::warn!(
+                "Error saving a copy of the receipt to `{RECEIPT_BACKUP_LOCATION}`: {:?}",
+                err
+            );
+        }
+        Ok(())
+    }
+
+    async fn write_receipt_backup(&self) -> Result<(), NixInstallerError> {
+        let backup = Path::new(RECEIPT_BACKUP_LOCATION);
+        create_state_dir().await?;
+        self.write_to(backup).await?;
+        receipt_signature::sign(backup).await?;
+        Ok(())
+    }
+
+    /// Record the progress of the install in the [`JOURNAL_LOCATION`], failing to is only logged as
+    /// the install itself can continue
+    async fn write_journal(&self) {
+        let written = async {
+            create_state_dir().await?;
+            self.write_to(JOURNAL_LOCATION).await
+        };
+        if let Err(err) = written.await {
+            tracing::warn!("Error saving journal: {:?}", err);
+        }
+    }
+
+    /// Atomically write the plan to `path`, through a temporary file beside it
+    ///
+    /// The temporary file is created afresh, never through a symlink, so whoever can write beside
+    /// `path` can't redirect the write elsewhere.
+    pub(crate) async fn write_to(&self, path: impl AsRef<Path>) -> Result<(), NixInstallerError> {
+        let path = path.as_ref().to_path_buf();
+        let path_tmp = {
+            let mut path_tmp = path.clone();
+            path_tmp.set_extension("tmp");
+            path_tmp
         };
         let self_json =
             serde_json::to_string_pretty(&self).map_err(NixInstallerError::SerializingReceipt)?;
 
-        tokio::fs::create_dir_all("/nix")
+        let contents = format!("{self_json}\n");
+        // Left behind if writing was interrupted, removing a symlink doesn't follow it
+        match tokio::fs::remove_file(&path_tmp).await {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                return Err(NixInstallerError::RecordingReceipt(path_tmp.clone(), e))
+            },
+            _ => (),
+        }
+        let mut file = tokio::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o644)
+            .custom_flags(nix::libc::O_NOFOLLOW)
+            .open(&path_tmp)
             .await
-            .map_err(|e| NixInstallerError::RecordingReceipt(PathBuf::from("/nix"), e))?;
-        tokio::fs::write(&install_receipt_path_tmp, format!("{self_json}\n"))
+            .map_err(|e| NixInstallerError::RecordingReceipt(path_tmp.clone(), e))?;
+        file.write_all(contents.as_bytes())
+            .await
+            .map_err(|e| NixInstallerError::RecordingReceipt(path_tmp.clone(), e))?;
+        file.sync_all()
             .await
-            .map_err(|e| {
-                NixInstallerError::RecordingReceipt(install_receipt_path_tmp.clone(), e)
-            })?;
-        tokio::fs::rename(&install_receipt_path_tmp, &install_receipt_path)
+            .map_err(|e| NixInstallerError::RecordingReceipt(path_tmp.clone(), e))?;
+        tokio::fs::rename(&path_tmp, &path)
             .await
-            .map_err(|e| NixInstallerError::RecordingReceipt(install_receipt_path.clone(), e))?;
+            .map_err(|e| NixInstallerError::RecordingReceipt(path.clone(), e))?;
+        audit::file_written(&path, contents.as_bytes());
 
         Ok(())
     }
 }
 
+/// Create the [`STATE_DIR`], making sure only root may read or write in it
+async fn create_state_dir() -> Result<(), NixInstallerError> {
+    let dir = Path::new(STATE_DIR);
+    match tokio::fs::DirBuilder::new()
+        .recursive(true)
+        .mode(0o700)
+        .create(dir)
+        .await
+    {
+        Ok(()) => (),
+        Err(e) => return Err(NixInstallerError::RecordingReceipt(dir.to_path_buf(), e)),
+    }
+    let metadata = tokio::fs::symlink_metadata(dir)
+        .await
+        .map_err(|e| NixInstallerError::RecordingReceipt(dir.to_path_buf(), e))?;
+    if !metadata.is_dir() || metadata.uid() != 0 {
+        return Err(NixInstallerError::UntrustedState(dir.to_path_buf()));
+    }
+    if metadata.mode() & 0o077 != 0 {
+        tokio::fs::set_permissions(dir, PermissionsExt::from_mode(0o700))
+            .await
+            .map_err(|e| NixInstallerError::RecordingReceipt(dir.to_path_buf(), e))?;
+    }
+    Ok(())
+}
+
+/// The journal of an interrupted install in [`JOURNAL_LOCATION`], if there is one
+///
+/// `nix-installer resume` carries out the journal as root, so one which root didn't write, or which
+/// someone else could have changed since, is refused.
+pub fn journal() -> Result<Option<PathBuf>, NixInstallerError> {
+    let path = Path::new(JOURNAL_LOCATION);
+    let metadata = match std::fs::symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(_) => return Err(NixInstallerError::UntrustedState(path.to_path_buf())),
+    };
+    let trusted =
+        |metadata: &std::fs::Metadata| metadata.uid() == 0 && metadata.mode() & 0o022 == 0;
+    let dir_trusted =
+        std::fs::symlink_metadata(STATE_DIR).is_ok_and(|dir| dir.is_dir() && trusted(&dir));
+    if !metadata.is_file() || !trusted(&metadata) || !dir_trusted {
+        return Err(NixInstallerError::UntrustedState(path.to_path_buf()));
+    }
+    Ok(Some(path.to_path_buf()))
+}
+
+/// Resolve the [`ActionDependencies`] of each of a sequence of actions (with `tags`) to the indexes of earlier actions
+fn resolve_dependencies(tags: &[&str], dependencies: &[ActionDependencies]) -> Vec<Vec<usize>> {
+    dependencies
+        .iter()
+        .enumerate()
+        .map(|(index, dependencies)| match dependencies {
+            ActionDependencies::All => (0..index).collect(),
+            ActionDependencies::AllExcept(excluded_tags) => tags[..index]
+                .iter()
+                .enumerate()
+                .filter(|(_, tag)| !excluded_tags.iter().any(|excluded| excluded.0 == **tag))
+                .map(|(earlier, _)| earlier)
+                .collect(),
+            ActionDependencies::Tags(dependency_tags) => tags[..index]
+                .iter()
+                .enumerate()
+                .filter(|(_, tag)| {
+                    dependency_tags
+                        .iter()
+                        .any(|dependency| dependency.0 == **tag)
+                })
+                .map(|(earlier, _)| earlier)
+                .collect(),
+        })
+        .collect()
+}
+
+/// The JSON pointers of the (serialized) actions with one of `tags` which aren't completed, outermost first
+fn find_tagged(
+    value: &serde_json::Value,
+    tags: &[ActionTag],
+    pointer: String,
+    found: &mut Vec<String>,
+) {
+    match value {
+        serde_json::Value::Array(values) => {
+            for (index, value) in values.iter().enumerate() {
+                find_tagged(value, tags, format!("{pointer}/{index}"), found);
+            }
+        },
+        serde_json::Value::Object(object) => {
+            let action_name = object
+                .get("action")
+                .and_then(|action| action.get("action_name"))
+                .and_then(serde_json::Value::as_str);
+            let completed =
+                object.get("state").and_then(serde_json::Value::as_str) == Some("Completed");
+            if action_name.is_some_and(|name| tags.iter().any(|tag| tag.0 == name)) {
+                if !completed {
+                    found.push(pointer);
+                }
+                return;
+            }
+            for (key, value) in object {
+                let key = key.replace('~', "~0").replace('/', "~1");
+                find_tagged(value, tags, format!("{pointer}/{key}"), found);
+            }
+        },
+        _ => (),
+    }
+}
+
+/// Mark each (serialized) action completed if any of the actions within it are, returning if any are
+///
+/// A composite action only reverts its completed actions, so one found to be partly in place
+/// must be completed for what was found to be reverted.
+fn mark_found_completed(value: &mut serde_json::Value) -> bool {
+    match value {
+        serde_json::Value::Array(values) => values
+            .iter_mut()
+            .fold(false, |found, value| mark_found_completed(value) | found),
+        serde_json::Value::Object(object) => {
+            let found = object
+                .values_mut()
+                .fold(false, |found, value| mark_found_completed(value) | found);
+            let is_action = object.contains_key("action");
+            match object.get_mut("state") {
+                Some(state) if is_action => {
+                    if found && state == "Uncompleted" {
+                        *state = "Completed".into();
+                    }
+                    found || state == "Completed"
+                },
+                _ => found,
+            }
+        },
+        _ => false,
+    }
+}
+
+/// The journal is only needed until the receipt is written, or the install is reverted
+async fn remove_journal() {
+    remove_record(Path::new(JOURNAL_LOCATION), "journal").await;
+}
+
+/// Remove the receipt (and its signature), if the Nix store it is in was kept
+async fn remove_receipt() {
+    let receipt = Path::new(RECEIPT_LOCATION);
+    remove_record(receipt, "receipt").await;
+    remove_record(
+        &receipt_signature::signature_path(receipt),
+        "receipt signature",
+    )
+    .await;
+}
+
+/// Remove the copy of the receipt (and its signature, and the key it was signed with) left outside of `/nix`, once everything it records is undone
+async fn remove_receipt_backup() {
+    let backup = Path::new(RECEIPT_BACKUP_LOCATION);
+    remove_record(backup, "receipt copy").await;
+    remove_record(
+        &receipt_signature::signature_path(backup),
+        "receipt signature",
+    )
+    .await;
+    remove_record(
+        Path::new(receipt_signature::MACHINE_KEY_LOCATION),
+        "receipt signing key",
+    )
+    .await;
+    remove_record(
+        Path::new(receipt_signature::SIGNED_MARKER_LOCATION),
+        "receipt signing record",
+    )
+    .await;
+    if let Some(parent) = backup.parent() {
+        // Only if nothing else was put there
+        if tokio::fs::remove_dir(parent).await.is_ok() {
+            audit::record(AuditEvent::DirectoryRemoved {
+                path: parent.to_path_buf(),
+            });
+        }
+    }
+}
+
+async fn remove_record(path: &Path, what: &str) {
+    match tokio::fs::remove_file(path).await {
+        Ok(()) => audit::record(AuditEvent::FileRemoved {
+            path: path.to_path_buf(),
+        }),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => (),
+        Err(err) => tracing::warn!("Error removing {what} `{}`: {:?}", path.display(), err),
+    }
+}
+
 pub fn current_version() -> Result<Version, NixInstallerError> {
     let nix_installer_version_str = env!("CARGO_PKG_VERSION");
     Version::from_str(nix_installer_version_str).map_err(|e| {
@@ -448,11 +1214,69 @@ pub fn current_version() -> Result<Version, NixInstallerError> {
     })
 }
 
+/**
+The properties of a host which a plan depends on
+
+Deliberately excludes anything unique to a machine (like the hostname) so one plan can be applied across a fleet.
+*/
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+pub struct HostFingerprint {
+    pub system: String,
+    pub os_name: String,
+    pub os_version: String,
+}
+
+impl HostFingerprint {
+    pub fn current() -> Self {
+        let (os_name, os_version) = os_name_and_version();
+        Self {
+            system: SYSTEM.to_string(),
+            os_name,
+            os_version,
+        }
+    }
+}
+
+impl std::fmt::Display for HostFingerprint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} ({} {})", self.system, self.os_name, self.os_version)
+    }
+}
+
+#[cfg(target_os = "macos")]
+fn os_name_and_version() -> (String, String) {
+    let version = plist::Value::from_file("/System/Library/CoreServices/SystemVersion.plist")
+        .ok()
+        .and_then(|value| value.into_dictionary());
+    let get = |key: &str| {
+        version
+            .as_ref()
+            .and_then(|version| version.get(key))
+            .and_then(|value| value.as_string())
+            .unwrap_or("unknown")
+            .to_string()
+    };
+    (get("ProductName"), get("ProductVersion"))
+}
+
+#[cfg(not(target_os = "macos"))]
+fn os_name_and_version() -> (String, String) {
+    match os_release::OsRelease::new() {
+        Ok(os_release) => (os_release.name, os_release.version),
+        Err(_) => ("unknown".into(), "unknown".into()),
+    }
+}
+
 #[cfg(test)]
 mod test {
     use semver::Version;
 
-    use crate::{planner::BuiltinPlanner, InstallPlan, NixInstallerError};
+    use super::{find_tagged, mark_found_completed, resolve_dependencies};
+    use crate::{
+        action::{ActionDependencies, ActionTag},
+        planner::BuiltinPlanner,
+        HostFingerprint, InstallPlan, NixInstallerError,
+    };
 
     #[tokio::test]
     async fn ensure_version_allows_compatible() -> Result<(), NixInstallerError> {
@@ -481,4 +1305,183 @@ mod test {
         assert!(maybe_plan.check_compatible().is_err());
         Ok(())
     }
+
+    #[tokio::test]
+    async fn ensure_host_denies_mismatch() -> Result<(), NixInstallerError> {
+        let planner = BuiltinPlanner::default().await?;
+        let value = serde_json::json!({
+            "planner": planner.clone().boxed(),
+            "version": Version::parse(env!("CARGO_PKG_VERSION"))?,
+            "host": HostFingerprint {
+                system: "riscv64-linux".into(),
+                ..HostFingerprint::current()
+            },
+            "actions": [],
+        });
+        let maybe_plan: InstallPlan = serde_json::from_value(value)?;
+        assert!(matches!(
+            maybe_plan.check_host(),
+            Err(NixInstallerError::HostMismatch { .. })
+        ));
+
+        let value = serde_json::json!({
+            "planner": planner.boxed(),
+            "version": Version::parse(env!("CARGO_PKG_VERSION"))?,
+            "host": HostFingerprint::current(),
+            "actions": [],
+        });
+        let maybe_plan: InstallPlan = serde_json::from_value(value)?;
+        maybe_plan.check_host()?;
+        Ok(())
+    }
+
+    #[test]
+    fn resolves_dependencies() {
+        let tags = [
+            "create_directory",
+            "provision_nix",
+            "create_users_and_group",
+            "configure_nix",
+        ];
+        let dependencies = [
+            ActionDependencies::All,
+            ActionDependencies::All,
+            ActionDependencies::Tags(vec![]),
+            ActionDependencies::Tags(vec![
+                ActionTag("provision_nix"),
+                ActionTag("create_users_and_group"),
+            ]),
+        ];
+        assert_eq!(
+            resolve_dependencies(&tags, &dependencies),
+            vec![vec![], vec![0], vec![], vec![1, 2]]
+        );
+
+        let tags = [
+            "create_directory",
+            "provision_determinate_nixd",
+            "provision_nix",
+            "create_users_and_group",
+        ];
+        let dependencies = [
+            ActionDependencies::All,
+            ActionDependencies::Tags(vec![]),
+            ActionDependencies::AllExcept(vec![ActionTag("provision_determinate_nixd")]),
+            ActionDependencies::Tags(vec![]),
+        ];
+        assert_eq!(
+            resolve_dependencies(&tags, &dependencies),
+            vec![vec![], vec![], vec![0], vec![]]
+        );
+
+        // As the planners order them, users and groups are created while Nix is provisioned
+        let tags = [
+            "create_snapshots",
+            "create_directory",
+            "provision_nix",
+            "create_users_and_group",
+        ];
+        let dependencies = [
+            ActionDependencies::All,
+            ActionDependencies::All,
+            ActionDependencies::AllExcept(vec![ActionTag("provision_determinate_nixd")]),
+            ActionDependencies::Tags(vec![ActionTag("create_snapshots")]),
+        ];
+        assert_eq!(
+            resolve_dependencies(&tags, &dependencies),
+            vec![vec![], vec![0], vec![0, 1], vec![0]]
+        );
+    }
+
+    #[tokio::test]
+    async fn written_plan_round_trips() -> eyre::Result<()> {
+        let planner = BuiltinPlanner::default().await?;
+        let value = serde_json::json!({
+            "planner": planner.boxed(),
+            "version": Version::parse(env!("CARGO_PKG_VERSION"))?,
+            "host": HostFingerprint::current(),
+            "actions": [],
+        });
+        let plan: InstallPlan = serde_json::from_value(value)?;
+
+        let temp_dir = tempfile::tempdir()?;
+        let journal = temp_dir.path().join("journal.json");
+        // A symlink in place of the temporary file isn't followed
+        let elsewhere = temp_dir.path().join("elsewhere");
+        tokio::fs::write(&elsewhere, "untouched").await?;
+        tokio::fs::symlink(&elsewhere, journal.with_extension("tmp")).await?;
+        plan.write_to(&journal).await?;
+        // The temporary file was renamed over the journal
+        assert!(!journal.with_extension("tmp").exists());
+        assert_eq!(tokio::fs::read_to_string(&elsewhere).await?, "untouched");
+
+        let read: InstallPlan = serde_json::from_str(&tokio::fs::read_to_string(&journal).await?)?;
+        assert_eq!(read.host, plan.host);
+        assert_eq!(read.version, plan.version);
+        Ok(())
+    }
+
+    #[test]
+    fn marks_partly_found_actions_completed() {
+        let mut actions = serde_json::json!([{
+            "action": {
+                "action_name": "configure_nix",
+                "configure_shell_profile": {
+                    "action": {
+                        "action_name": "configure_shell_profile",
+                        "create_or_insert_into_files": [{
+                            "action": { "action_name": "create_or_insert_into_file", "path": "/etc/zshrc" },
+                            "state": "Completed",
+                        }, {
+                            "action": { "action_name": "create_or_insert_into_file", "path": "/etc/bashrc" },
+                            "state": "Uncompleted",
+                        }],
+                    },
+                    "state": "Uncompleted",
+                },
+            },
+            "state": "Uncompleted",
+        }, {
+            "action": { "action_name": "remove_directory", "path": "/nix/temp-install-dir" },
+            "state": "Uncompleted",
+        }]);
+        assert!(mark_found_completed(&mut actions));
+
+        assert_eq!(actions[0]["state"], "Completed");
+        let shell_profile = &actions[0]["action"]["configure_shell_profile"];
+        assert_eq!(shell_profile["state"], "Completed");
+        assert_eq!(
+            shell_profile["action"]["create_or_insert_into_files"][1]["state"],
+            "Uncompleted"
+        );
+        assert_eq!(actions[1]["state"], "Uncompleted");
+    }
+
+    #[test]
+    fn finds_uncompleted_tagged_actions() {
+        let actions = serde_json::json!([{
+            "action": { "action_name": "create_users_and_group" },
+            "state": "Completed",
+        }, {
+            "action": {
+                "action_name": "configure_nix",
+                "configure_shell_profile": {
+                    "action": { "action_name": "configure_shell_profile" },
+                    "state": "Uncompleted",
+                },
+            },
+            "state": "Uncompleted",
+        }]);
+        let mut found = vec![];
+        find_tagged(
+            &actions,
+            &[
+                ActionTag("create_users_and_group"),
+                ActionTag("configure_shell_profile"),
+            ],
+            String::new(),
+            &mut found,
+        );
+        assert_eq!(found, vec!["/1/action/configure_shell_profile".to_string()]);
+    }
 }
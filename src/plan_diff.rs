@@ -0,0 +1,285 @@
+/*! What changed between two [`InstallPlan`]s (or receipts), for reviewing an upgrade of the installer
+
+Actions are matched by their tag, and how many actions with that tag came before, so a plan which
+gains an extra `create_user` shows it as added, rather than every user after it as changed. The
+state of each action is ignored, a receipt (with every action completed) can be compared with a
+fresh plan:
+
+```rust,no_run
+# async fn diff() -> color_eyre::Result<()> {
+use nix_installer::{plan_diff, InstallPlan};
+
+let receipt = InstallPlan::from_receipt(&std::fs::read_to_string("/nix/receipt.json")?)?;
+let fresh = receipt.replan().await?;
+println!("{}", plan_diff::diff(&receipt, &fresh)?);
+# Ok(())
+# }
+```
+*/
+
+use std::collections::{BTreeSet, HashMap};
+
+use serde_json::Value;
+
+use crate::{InstallPlan, NixInstallerError};
+
+/// The changes from one [`InstallPlan`] to another
+#[derive(Debug, Clone, PartialEq, Default, serde::Serialize)]
+pub struct PlanDiff {
+    /// The versions of `nix-installer` which made the plans, if they differ
+    pub version: Option<(String, String)>,
+    /// The planner's name (as `planner`) and settings which differ
+    pub planner: Vec<FieldChange>,
+    pub actions: Vec<ActionChange>,
+}
+
+impl PlanDiff {
+    pub fn is_empty(&self) -> bool {
+        self.version.is_none() && self.planner.is_empty() && self.actions.is_empty()
+    }
+}
+
+/// An action in only one of the plans, or in both but different
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+#[serde(tag = "change", rename_all = "snake_case")]
+pub enum ActionChange {
+    Added {
+        tag: String,
+        synopsis: String,
+    },
+    Removed {
+        tag: String,
+        synopsis: String,
+    },
+    Changed {
+        tag: String,
+        synopsis: String,
+        fields: Vec<FieldChange>,
+    },
+}
+
+/// A value at `path` (like `create_users.action.users[2].uid`) which is only in one plan, or differs
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct FieldChange {
+    pub path: String,
+    pub old: Option<Value>,
+    pub new: Option<Value>,
+}
+
+/// What changed from `old` to `new`
+pub fn diff(old: &InstallPlan, new: &InstallPlan) -> Result<PlanDiff, NixInstallerError> {
+    let version =
+        (old.version != new.version).then(|| (old.version.to_string(), new.version.to_string()));
+
+    let mut planner = vec![];
+    if old.planner.typetag_name() != new.planner.typetag_name() {
+        planner.push(FieldChange {
+            path: "planner".into(),
+            old: Some(old.planner.typetag_name().into()),
+            new: Some(new.planner.typetag_name().into()),
+        });
+    }
+    let old_settings = old.planner.settings()?;
+    let new_settings = new.planner.settings()?;
+    let keys = old_settings
+        .keys()
+        .chain(new_settings.keys())
+        .collect::<BTreeSet<_>>();
+    for key in keys {
+        changes(
+            key,
+            old_settings.get(key),
+            new_settings.get(key),
+            &mut planner,
+        );
+    }
+
+    let old_actions = keyed_actions(old)?;
+    let new_actions = keyed_actions(new)?;
+    let mut actions = vec![];
+    for (key, synopsis, _) in &old_actions {
+        if !new_actions.iter().any(|(new_key, _, _)| new_key == key) {
+            actions.push(ActionChange::Removed {
+                tag: key.0.clone(),
+                synopsis: synopsis.clone(),
+            });
+        }
+    }
+    for (key, synopsis, action) in &new_actions {
+        match old_actions.iter().find(|(old_key, _, _)| old_key == key) {
+            None => actions.push(ActionChange::Added {
+                tag: key.0.clone(),
+                synopsis: synopsis.clone(),
+            }),
+            Some((_, _, old_action)) => {
+                let mut fields = vec![];
+                changes(&key.0, Some(old_action), Some(action), &mut fields);
+                if !fields.is_empty() {
+                    actions.push(ActionChange::Changed {
+                        tag: key.0.clone(),
+                        synopsis: synopsis.clone(),
+                        fields,
+                    });
+                }
+            },
+        }
+    }
+
+    Ok(PlanDiff {
+        version,
+        planner,
+        actions,
+    })
+}
+
+/// An action keyed by its tag and how many actions with that tag came before, with its synopsis and (stateless) value
+type KeyedAction = ((String, usize), String, Value);
+
+/// Each action of `plan` as a [`KeyedAction`]
+fn keyed_actions(plan: &InstallPlan) -> Result<Vec<KeyedAction>, NixInstallerError> {
+    let mut seen: HashMap<&str, usize> = HashMap::new();
+    let mut keyed = vec![];
+    for action in &plan.actions {
+        let tag = action.inner_typetag_name();
+        let count = seen.entry(tag).or_default();
+        let mut value = serde_json::to_value(&action.action)?;
+        strip_states(&mut value);
+        keyed.push(((tag.to_string(), *count), action.tracing_synopsis(), value));
+        *count += 1;
+    }
+    Ok(keyed)
+}
+
+/// Remove the `state` of every [`StatefulAction`](crate::action::StatefulAction) within `value`
+fn strip_states(value: &mut Value) {
+    match value {
+        Value::Object(map) => {
+            if map.len() == 2 && map.contains_key("action") && map.contains_key("state") {
+                map.remove("state");
+            }
+            map.values_mut().for_each(strip_states);
+        },
+        Value::Array(values) => values.iter_mut().for_each(strip_states),
+        _ => (),
+    }
+}
+
+/// Push the differences between `old` and `new`, down to the values which differ, to `out`
+fn changes(path: &str, old: Option<&Value>, new: Option<&Value>, out: &mut Vec<FieldChange>) {
+    match (old, new) {
+        (Some(Value::Object(old)), Some(Value::Object(new))) => {
+            let keys = old.keys().chain(new.keys()).collect::<BTreeSet<_>>();
+            for key in keys {
+                changes(&format!("{path}.{key}"), old.get(key), new.get(key), out);
+            }
+        },
+        (Some(Value::Array(old)), Some(Value::Array(new))) if old.len() == new.len() => {
+            for (index, (old, new)) in old.iter().zip(new).enumerate() {
+                changes(&format!("{path}[{index}]"), Some(old), Some(new), out);
+            }
+        },
+        (old, new) if old != new => out.push(FieldChange {
+            path: path.to_string(),
+            old: old.cloned(),
+            new: new.cloned(),
+        }),
+        _ => (),
+    }
+}
+
+impl std::fmt::Display for PlanDiff {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.is_empty() {
+            return writeln!(f, "The plans are the same");
+        }
+        if let Some((old, new)) = &self.version {
+            writeln!(f, "Version: {old} -> {new}")?;
+        }
+        if !self.planner.is_empty() {
+            writeln!(f, "Planner:")?;
+            for change in &self.planner {
+                writeln!(f, "  {change}")?;
+            }
+        }
+        if !self.actions.is_empty() {
+            writeln!(f, "Actions:")?;
+        }
+        for change in &self.actions {
+            match change {
+                ActionChange::Added { tag, synopsis } => writeln!(f, "  + `{tag}`: {synopsis}")?,
+                ActionChange::Removed { tag, synopsis } => writeln!(f, "  - `{tag}`: {synopsis}")?,
+                ActionChange::Changed {
+                    tag,
+                    synopsis,
+                    fields,
+                } => {
+                    writeln!(f, "  ~ `{tag}`: {synopsis}")?;
+                    for field in fields {
+                        writeln!(f, "      {field}")?;
+                    }
+                },
+            }
+        }
+        Ok(())
+    }
+}
+
+impl std::fmt::Display for FieldChange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let show = |value: &Option<Value>| match value {
+            Some(value) => value.to_string(),
+            None => "(none)".to_string(),
+        };
+        write!(
+            f,
+            "{}: {} -> {}",
+            self.path,
+            show(&self.old),
+            show(&self.new)
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use super::{changes, strip_states, FieldChange};
+
+    #[test]
+    fn finds_changed_values() {
+        let mut old = json!({
+            "action": { "users": [{ "uid": 30001 }, { "uid": 30002 }], "group": "nixbld" },
+            "state": "Completed",
+        });
+        let mut new = json!({
+            "action": { "users": [{ "uid": 30001 }, { "uid": 30003 }], "shell": "/bin/false" },
+            "state": "Uncompleted",
+        });
+        strip_states(&mut old);
+        strip_states(&mut new);
+
+        let mut out = vec![];
+        changes("create_users", Some(&old), Some(&new), &mut out);
+        assert_eq!(
+            out,
+            vec![
+                FieldChange {
+                    path: "create_users.action.group".into(),
+                    old: Some(json!("nixbld")),
+                    new: None,
+                },
+                FieldChange {
+                    path: "create_users.action.shell".into(),
+                    old: None,
+                    new: Some(json!("/bin/false")),
+                },
+                FieldChange {
+                    path: "create_users.action.users[1].uid".into(),
+                    old: Some(json!(30002)),
+                    new: Some(json!(30003)),
+                },
+            ]
+        );
+    }
+}
@@ -1,4 +1,7 @@
-use std::{collections::HashMap, path::Path};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
 
 use tokio::process::Command;
 use which::which;
@@ -6,21 +9,30 @@ use which::which;
 use super::ShellProfileLocations;
 use crate::{
     action::{
-        base::{CreateDirectory, RemoveDirectory},
+        base::{
+            CreateDirectory, CreateFile, CreateSnapshots, InstallProfilePackages, RemoveDirectory,
+            RunPlugin, RunPostInstallHook,
+        },
         common::{
-            ConfigureDeterminateNixdInitService, ConfigureNix, ConfigureUpstreamInitService,
+            place_nix_configuration::NIX_SSL_CERT_FILE, ConfigureDeterminateNixdInitService,
+            ConfigureNix, ConfigureUpstreamInitService, CreateDaemonStartScript,
             CreateUsersAndGroups, ProvisionDeterminateNixd, ProvisionNix,
         },
         linux::{
-            provision_selinux::{DETERMINATE_SELINUX_POLICY_PP_CONTENT, SELINUX_POLICY_PP_CONTENT},
-            ProvisionSelinux,
+            provision_selinux::{
+                DETERMINATE_SELINUX_DAEMON_PATHS, DETERMINATE_SELINUX_POLICY_PP_CONTENT,
+                SELINUX_DAEMON_PATHS, SELINUX_POLICY_PP_CONTENT,
+            },
+            ApparmorMode, ConfigureCgroupDelegation, ProvisionApparmor, ProvisionSelinux,
+            RestoreSelinuxContexts, StartSystemdUnit,
         },
         StatefulAction,
     },
     error::HasExpectedErrors,
+    packaged_nix::PackagedNix,
     planner::{Planner, PlannerError},
     settings::{
-        determinate_nix_settings, CommonSettings, InitSettings, InitSystem, InstallSettingsError,
+        CommonSettings, Distribution, InitSettings, InitSystem, InstallSettingsError, RunningInit,
     },
     Action, BuiltinPlanner,
 };
@@ -33,8 +45,43 @@ pub struct Linux {
     pub settings: CommonSettings,
     #[cfg_attr(feature = "cli", clap(flatten))]
     pub init: InitSettings,
+    /// Confine the Nix daemon with an AppArmor profile, in this mode (requires systemd)
+    #[cfg_attr(
+        feature = "cli",
+        clap(long, env = "NIX_INSTALLER_APPARMOR", global = true)
+    )]
+    #[serde(default)]
+    pub apparmor: Option<ApparmorMode>,
+    /// Run each build in a cgroup of its own, for resource accounting and limits (requires systemd and cgroup v2)
+    #[cfg_attr(
+        feature = "cli",
+        clap(
+            long,
+            action(clap::ArgAction::SetTrue),
+            default_value = "false",
+            env = "NIX_INSTALLER_CGROUPS",
+            global = true
+        )
+    )]
+    #[serde(default)]
+    pub cgroups: bool,
+    /// Mount a tmpfs of this size (like `8G`, or `50%` of memory) on the build directory (requires systemd)
+    #[cfg_attr(
+        feature = "cli",
+        clap(
+            long,
+            env = "NIX_INSTALLER_BUILD_DIR_TMPFS",
+            value_parser = parse_tmpfs_size,
+            global = true
+        )
+    )]
+    #[serde(default)]
+    pub build_dir_tmpfs: Option<String>,
 }
 
+/// Where builds run if `--build-dir-tmpfs` is given without `--build-dir`
+const DEFAULT_BUILD_DIR: &str = "/nix/var/nix/builds";
+
 #[async_trait::async_trait]
 #[typetag::serde(name = "linux")]
 impl Planner for Linux {
@@ -42,14 +89,57 @@ impl Planner for Linux {
         Ok(Self {
             settings: CommonSettings::default().await?,
             init: InitSettings::default().await?,
+            apparmor: None,
+            cgroups: false,
+            build_dir_tmpfs: None,
         })
     }
 
     async fn plan(&self) -> Result<Vec<StatefulAction<Box<dyn Action>>>, PlannerError> {
         let has_selinux = detect_selinux().await?;
+        if self.apparmor.is_some() {
+            check_apparmor(self.init.init)?;
+        }
+        if self.cgroups {
+            check_cgroups(self.init.init)?;
+        }
+        if self.build_dir_tmpfs.is_some() && self.init.init != InitSystem::Systemd {
+            return Err(PlannerError::BuildDirTmpfsRequiresSystemd);
+        }
+
+        let mut nix_config = self.settings.distribution().nix_config();
+        if self.cgroups {
+            let settings = nix_config
+                .get_or_insert_with(nix_config_parser::NixConfig::new)
+                .settings_mut();
+            settings.insert("use-cgroups".into(), "true".into());
+            settings.insert("extra-experimental-features".into(), "cgroups".into());
+        }
+        // The tmpfs is mounted where builds run, so they have to run where it is
+        let build_dir = self
+            .settings
+            .build_dir
+            .clone()
+            .unwrap_or_else(|| DEFAULT_BUILD_DIR.into());
+        if self.build_dir_tmpfs.is_some() && self.settings.build_dir.is_none() {
+            nix_config
+                .get_or_insert_with(nix_config_parser::NixConfig::new)
+                .settings_mut()
+                .insert("build-dir".into(), DEFAULT_BUILD_DIR.into());
+        }
 
         let mut plan = vec![];
 
+        if self.settings.snapshot {
+            // Taken before anything is changed, and deleted after everything is reverted
+            plan.push(
+                CreateSnapshots::plan()
+                    .await
+                    .map_err(PlannerError::Action)?
+                    .boxed(),
+            );
+        }
+
         plan.push(
             CreateDirectory::plan("/nix", None, None, 0o0755, true)
                 .await
@@ -57,7 +147,7 @@ impl Planner for Linux {
                 .boxed(),
         );
 
-        if self.settings.determinate_nix {
+        if self.settings.distribution() == Distribution::Determinate {
             plan.push(
                 ProvisionDeterminateNixd::plan()
                     .await
@@ -79,21 +169,17 @@ impl Planner for Linux {
                 .boxed(),
         );
         plan.push(
-            ConfigureNix::plan(
-                ShellProfileLocations::default(),
-                &self.settings,
-                self.settings.determinate_nix.then(determinate_nix_settings),
-            )
-            .await
-            .map_err(PlannerError::Action)?
-            .boxed(),
+            ConfigureNix::plan(ShellProfileLocations::default(), &self.settings, nix_config)
+                .await
+                .map_err(PlannerError::Action)?
+                .boxed(),
         );
 
         if has_selinux {
             plan.push(
                 ProvisionSelinux::plan(
                     "/usr/share/selinux/packages/nix.pp".into(),
-                    if self.settings.determinate_nix {
+                    if self.settings.distribution() == Distribution::Determinate {
                         DETERMINATE_SELINUX_POLICY_PP_CONTENT
                     } else {
                         SELINUX_POLICY_PP_CONTENT
@@ -105,28 +191,120 @@ impl Planner for Linux {
             );
         }
 
-        plan.push(
-            CreateDirectory::plan("/etc/tmpfiles.d", None, None, 0o0755, false)
+        if let Some(mode) = self.apparmor {
+            plan.push(
+                ProvisionApparmor::plan(mode)
+                    .await
+                    .map_err(PlannerError::Action)?
+                    .boxed(),
+            );
+        }
+
+        if let Some(size) = &self.build_dir_tmpfs {
+            if !build_dir.is_absolute() {
+                return Err(PlannerError::BuildDirTmpfsRequiresAbsolutePath(build_dir));
+            }
+            let unit_name = format!("{}.mount", systemd_escape_path(&build_dir));
+            plan.push(
+                CreateFile::plan(
+                    Path::new("/etc/systemd/system").join(&unit_name),
+                    None,
+                    None,
+                    0o0644,
+                    build_dir_tmpfs_unit(&build_dir, size),
+                    false,
+                )
                 .await
                 .map_err(PlannerError::Action)?
                 .boxed(),
-        );
-
-        if self.settings.determinate_nix {
+            );
             plan.push(
-                ConfigureDeterminateNixdInitService::plan(self.init.init, self.init.start_daemon)
+                StartSystemdUnit::plan(unit_name, true)
                     .await
                     .map_err(PlannerError::Action)?
                     .boxed(),
             );
-        } else {
+        }
+
+        if self.cgroups {
             plan.push(
-                ConfigureUpstreamInitService::plan(self.init.init, self.init.start_daemon)
+                ConfigureCgroupDelegation::plan()
                     .await
                     .map_err(PlannerError::Action)?
                     .boxed(),
             );
         }
+
+        plan.push(
+            CreateDirectory::plan("/etc/tmpfiles.d", None, None, 0o0755, false)
+                .await
+                .map_err(PlannerError::Action)?
+                .boxed(),
+        );
+
+        if self.settings.distribution() == Distribution::Determinate {
+            plan.push(
+                ConfigureDeterminateNixdInitService::plan(
+                    self.init.init,
+                    self.init.start_daemon,
+                    self.settings
+                        .ssl_cert_file
+                        .as_ref()
+                        .map(|_| NIX_SSL_CERT_FILE.into()),
+                    self.settings.daemon_env.clone(),
+                )
+                .await
+                .map_err(PlannerError::Action)?
+                .boxed(),
+            );
+        } else {
+            plan.push(
+                ConfigureUpstreamInitService::plan(
+                    self.init.init,
+                    self.init.start_daemon,
+                    self.settings
+                        .ssl_cert_file
+                        .as_ref()
+                        .map(|_| NIX_SSL_CERT_FILE.into()),
+                    self.settings.daemon_env.clone(),
+                )
+                .await
+                .map_err(PlannerError::Action)?
+                .boxed(),
+            );
+        }
+        if has_selinux {
+            plan.push(
+                RestoreSelinuxContexts::plan(
+                    if self.settings.distribution() == Distribution::Determinate {
+                        DETERMINATE_SELINUX_DAEMON_PATHS
+                    } else {
+                        SELINUX_DAEMON_PATHS
+                    }
+                    .iter()
+                    .map(PathBuf::from)
+                    .collect(),
+                )
+                .await
+                .map_err(PlannerError::Action)?
+                .boxed(),
+            );
+        }
+        if self.init.init == InitSystem::None {
+            plan.push(
+                CreateDaemonStartScript::plan(
+                    self.settings.distribution(),
+                    self.settings
+                        .ssl_cert_file
+                        .as_ref()
+                        .map(|_| NIX_SSL_CERT_FILE.into()),
+                    self.settings.daemon_env.clone(),
+                )
+                .await
+                .map_err(PlannerError::Action)?
+                .boxed(),
+            );
+        }
         plan.push(
             RemoveDirectory::plan(crate::settings::SCRATCH_DIR)
                 .await
@@ -134,15 +312,54 @@ impl Planner for Linux {
                 .boxed(),
         );
 
+        if !self.settings.extra_profile_packages.is_empty() {
+            plan.push(
+                InstallProfilePackages::plan(self.settings.extra_profile_packages.clone())
+                    .await
+                    .map_err(PlannerError::Action)?
+                    .boxed(),
+            );
+        }
+
+        for plugin in &self.settings.plugins {
+            plan.push(
+                RunPlugin::plan(plugin)
+                    .await
+                    .map_err(PlannerError::Action)?
+                    .boxed(),
+            );
+        }
+
+        for hook in &self.settings.post_install_hooks {
+            plan.push(
+                RunPostInstallHook::plan(hook)
+                    .await
+                    .map_err(PlannerError::Action)?
+                    .boxed(),
+            );
+        }
+
         Ok(plan)
     }
 
     fn settings(&self) -> Result<HashMap<String, serde_json::Value>, InstallSettingsError> {
-        let Self { settings, init } = self;
+        let Self {
+            settings,
+            init,
+            apparmor,
+            cgroups,
+            build_dir_tmpfs,
+        } = self;
         let mut map = HashMap::default();
 
         map.extend(settings.settings()?);
         map.extend(init.settings()?);
+        map.insert("apparmor".into(), serde_json::to_value(apparmor)?);
+        map.insert("cgroups".into(), serde_json::to_value(cgroups)?);
+        map.insert(
+            "build_dir_tmpfs".into(),
+            serde_json::to_value(build_dir_tmpfs)?,
+        );
 
         Ok(map)
     }
@@ -174,7 +391,10 @@ impl Planner for Linux {
                 .into_keys()
                 .collect::<Vec<_>>(),
             self.settings.ssl_cert_file.clone(),
-        )?)
+        )?
+        .client_cert(self.settings.diagnostic_client_cert.clone())
+        .redact(self.settings.diagnostic_redact.clone())
+        .report_file(self.settings.diagnostic_report_file.clone()))
     }
 
     async fn platform_check(&self) -> Result<(), PlannerError> {
@@ -205,8 +425,11 @@ impl Planner for Linux {
 
         check_not_wsl1()?;
 
-        if self.init.init == InitSystem::Systemd && self.init.start_daemon {
-            check_systemd_active()?;
+        if self.init.init == InitSystem::Systemd {
+            check_init_supported()?;
+            if self.init.start_daemon {
+                check_systemd_active()?;
+            }
         }
 
         Ok(())
@@ -236,6 +459,101 @@ pub(crate) fn check_not_wsl1() -> Result<(), PlannerError> {
     Ok(())
 }
 
+fn check_apparmor(init: InitSystem) -> Result<(), PlannerError> {
+    if init != InitSystem::Systemd {
+        return Err(PlannerError::ApparmorRequirements(
+            "the profile is applied by the daemon's systemd unit, which `--init none` doesn't have",
+        ));
+    }
+    if !Path::new("/sys/kernel/security/apparmor").exists() {
+        return Err(PlannerError::ApparmorRequirements(
+            "AppArmor isn't enabled in the kernel",
+        ));
+    }
+    if which("apparmor_parser").is_err() {
+        return Err(PlannerError::ApparmorRequirements(
+            "`apparmor_parser` wasn't found, it is in the `apparmor` package",
+        ));
+    }
+    Ok(())
+}
+
+/// The size of a tmpfs, as `mount -t tmpfs -o size=` takes it
+fn parse_tmpfs_size(size: &str) -> Result<String, String> {
+    let digits = size.trim_end_matches(['k', 'm', 'g', 'K', 'M', 'G', '%']);
+    let valid = !digits.is_empty()
+        && digits.chars().all(|c| c.is_ascii_digit())
+        && size.len() - digits.len() <= 1;
+    if valid {
+        Ok(size.to_string())
+    } else {
+        Err(format!(
+            "`{size}` is not a tmpfs size, expected something like `8G` or `50%`"
+        ))
+    }
+}
+
+/// The name systemd gives the unit of `path`, like `systemd-escape --path`
+fn systemd_escape_path(path: &Path) -> String {
+    let path = path.to_string_lossy();
+    let components = path.split('/').filter(|component| !component.is_empty());
+    let mut escaped = String::new();
+    for (index, component) in components.enumerate() {
+        if index > 0 {
+            escaped.push('-');
+        }
+        for (position, byte) in component.bytes().enumerate() {
+            match byte {
+                b'.' if position == 0 && index == 0 => escaped.push_str("\\x2e"),
+                b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b':' | b'_' | b'.' => {
+                    escaped.push(byte as char)
+                },
+                _ => escaped.push_str(&format!("\\x{byte:02x}")),
+            }
+        }
+    }
+    if escaped.is_empty() {
+        "-".to_string()
+    } else {
+        escaped
+    }
+}
+
+fn build_dir_tmpfs_unit(build_dir: &Path, size: &str) -> String {
+    format!(
+        "\
+        [Unit]\n\
+        Description=tmpfs for Nix builds\n\
+        Before=nix-daemon.service\n\
+        \n\
+        [Mount]\n\
+        What=tmpfs\n\
+        Where={build_dir}\n\
+        Type=tmpfs\n\
+        Options=mode=0755,size={size},nosuid,nodev\n\
+        \n\
+        [Install]\n\
+        WantedBy=local-fs.target\n\
+        RequiredBy=nix-daemon.service\n\
+        ",
+        build_dir = build_dir.display(),
+    )
+}
+
+fn check_cgroups(init: InitSystem) -> Result<(), PlannerError> {
+    if init != InitSystem::Systemd {
+        return Err(PlannerError::CgroupsRequirements(
+            "the daemon's cgroup is delegated by its systemd unit, which `--init none` doesn't have",
+        ));
+    }
+    if !Path::new("/sys/fs/cgroup/cgroup.controllers").exists() {
+        return Err(PlannerError::CgroupsRequirements(
+            "the host doesn't use cgroup v2 (the unified hierarchy), try booting with `systemd.unified_cgroup_hierarchy=1`",
+        ));
+    }
+    Ok(())
+}
+
 pub(crate) async fn detect_selinux() -> Result<bool, PlannerError> {
     if Path::new("/sys/fs/selinux").exists() && which("sestatus").is_ok() {
         // We expect systems with SELinux to have the normal SELinux tools.
@@ -252,6 +570,14 @@ pub(crate) async fn detect_selinux() -> Result<bool, PlannerError> {
 }
 
 pub(crate) async fn check_nix_not_already_installed() -> Result<(), PlannerError> {
+    // Installing over a packaged Nix fails partway, name the package so it can be taken over or removed
+    if let Some(packaged) = PackagedNix::detect().await {
+        return Err(PlannerError::PackagedNix {
+            remove: packaged.manager.remove_command(&packaged.package),
+            package: packaged.package,
+        });
+    }
+
     // For now, we don't try to repair the user's Nix install or anything special.
     if Command::new("nix-env")
         .arg("--version")
@@ -266,6 +592,16 @@ pub(crate) async fn check_nix_not_already_installed() -> Result<(), PlannerError
     Ok(())
 }
 
+/// Configuring systemd where another init system runs fails at `systemctl`, refuse to plan it
+pub(crate) fn check_init_supported() -> Result<(), PlannerError> {
+    match RunningInit::detect() {
+        running @ (RunningInit::OpenRc | RunningInit::Runit | RunningInit::Other { .. }) => {
+            Err(LinuxErrorKind::UnsupportedInit(running).into())
+        },
+        _ => Ok(()),
+    }
+}
+
 pub(crate) fn check_systemd_active() -> Result<(), PlannerError> {
     if !Path::new("/run/systemd/system").exists() {
         if std::env::var("WSL_DISTRO_NAME").is_ok() {
@@ -301,6 +637,13 @@ pub enum LinuxErrorKind {
         To use a `root`-only Nix install, consider passing `--init none`."
     )]
     Wsl2SystemdNotActive,
+    #[error(
+        "\
+        The host's init system is {0}, not systemd, and `nix-installer` can't configure the Nix daemon for it.\n\
+        \n\
+        Pass `--init none`, and have the init system run `/usr/local/bin/nix-daemon-start` (as `root`) to start the daemon."
+    )]
+    UnsupportedInit(RunningInit),
 }
 
 impl HasExpectedErrors for LinuxErrorKind {
@@ -308,6 +651,7 @@ impl HasExpectedErrors for LinuxErrorKind {
         match self {
             LinuxErrorKind::SystemdNotActive => Some(Box::new(self)),
             LinuxErrorKind::Wsl2SystemdNotActive => Some(Box::new(self)),
+            LinuxErrorKind::UnsupportedInit(_) => Some(Box::new(self)),
         }
     }
 }
@@ -317,3 +661,34 @@ impl From<LinuxErrorKind> for PlannerError {
         PlannerError::Custom(Box::new(v))
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use super::{parse_tmpfs_size, systemd_escape_path};
+
+    #[test]
+    fn escapes_paths_like_systemd() {
+        assert_eq!(
+            systemd_escape_path(Path::new("/nix/var/nix/builds")),
+            "nix-var-nix-builds"
+        );
+        assert_eq!(
+            systemd_escape_path(Path::new("/mnt/nix-builds/")),
+            "mnt-nix\\x2dbuilds"
+        );
+        assert_eq!(systemd_escape_path(Path::new("/.builds")), "\\x2ebuilds");
+        assert_eq!(systemd_escape_path(Path::new("/")), "-");
+    }
+
+    #[test]
+    fn parses_tmpfs_sizes() {
+        assert_eq!(parse_tmpfs_size("8G"), Ok("8G".to_string()));
+        assert_eq!(parse_tmpfs_size("50%"), Ok("50%".to_string()));
+        assert_eq!(parse_tmpfs_size("1024"), Ok("1024".to_string()));
+        assert!(parse_tmpfs_size("8GB").is_err());
+        assert!(parse_tmpfs_size("G").is_err());
+        assert!(parse_tmpfs_size("").is_err());
+    }
+}
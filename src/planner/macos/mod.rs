@@ -15,10 +15,12 @@ use crate::action::common::ConfigureDeterminateNixdInitService;
 use crate::os::darwin::diskutil::DiskUtilList;
 use crate::{
     action::{
-        base::RemoveDirectory,
+        base::{
+            CreateSnapshots, InstallProfilePackages, RemoveDirectory, RunPlugin, RunPostInstallHook,
+        },
         common::{
-            ConfigureNix, ConfigureUpstreamInitService, CreateUsersAndGroups,
-            ProvisionDeterminateNixd, ProvisionNix,
+            place_nix_configuration::NIX_SSL_CERT_FILE, ConfigureNix, ConfigureUpstreamInitService,
+            CreateUsersAndGroups, ProvisionDeterminateNixd, ProvisionNix,
         },
         macos::{
             ConfigureRemoteBuilding, CreateDeterminateNixVolume, CreateNixHookService,
@@ -30,7 +32,7 @@ use crate::{
     os::darwin::DiskUtilInfoOutput,
     planner::{Planner, PlannerError},
     settings::InstallSettingsError,
-    settings::{determinate_nix_settings, CommonSettings, InitSystem},
+    settings::{CommonSettings, Distribution, InitSystem, ProfileStrategy},
     Action, BuiltinPlanner,
 };
 
@@ -83,12 +85,9 @@ pub struct Macos {
     /// Without this flag, installations on macOS on EC2 will require manual, graphical intervention when first installed to grant Full Disk Access.
     ///
     /// Setting this option:
-    ///  * Requires passing --determinate due to complications of AWS's deployment of macOS.
+    ///  * Requires passing --distribution determinate due to complications of AWS's deployment of macOS.
     ///  * Sets --root-disk to an auto-detected disk
-    #[cfg_attr(
-        feature = "cli",
-        clap(long, default_value = "false", requires = "determinate_nix")
-    )]
+    #[cfg_attr(feature = "cli", clap(long, default_value = "false"))]
     pub use_ec2_instance_store: bool,
 }
 
@@ -143,9 +142,15 @@ impl Planner for Macos {
     }
 
     async fn plan(&self) -> Result<Vec<StatefulAction<Box<dyn Action>>>, PlannerError> {
-        if self.use_ec2_instance_store && !self.settings.determinate_nix {
+        if self.use_ec2_instance_store && self.settings.distribution() != Distribution::Determinate
+        {
             return Err(PlannerError::Ec2InstanceStoreRequiresDeterminateNix);
         }
+        if self.settings.modify_profile
+            && self.settings.profile_strategy == ProfileStrategy::ProfileDOnly
+        {
+            return Err(PlannerError::ProfileDOnlyUnsupported);
+        }
 
         let root_disk = match &self.root_disk {
             root_disk @ Some(_) => root_disk.clone(),
@@ -161,7 +166,10 @@ impl Planner for Macos {
         // The encrypt variable isn't used in Determinate Nix since we have our own plan step for it,
         // however this match accounts for Determinate Nix so the receipt indicates encrypt: true.
         // This is a goofy thing to do, but it is in an attempt to make a more globally coherent plan / receipt.
-        let encrypt = match (self.settings.determinate_nix, self.encrypt) {
+        let encrypt = match (
+            self.settings.distribution() == Distribution::Determinate,
+            self.encrypt,
+        ) {
             (true, _) => true,
             (false, Some(choice)) => choice,
             (false, None) => {
@@ -183,7 +191,17 @@ impl Planner for Macos {
 
         let mut plan = vec![];
 
-        if self.settings.determinate_nix {
+        if self.settings.snapshot {
+            // Taken before anything is changed, and deleted after everything is reverted
+            plan.push(
+                CreateSnapshots::plan()
+                    .await
+                    .map_err(PlannerError::Action)?
+                    .boxed(),
+            );
+        }
+
+        if self.settings.distribution() == Distribution::Determinate {
             plan.push(
                 ProvisionDeterminateNixd::plan()
                     .await
@@ -192,7 +210,7 @@ impl Planner for Macos {
             );
         }
 
-        if self.settings.determinate_nix {
+        if self.settings.distribution() == Distribution::Determinate {
             plan.push(
                 CreateDeterminateNixVolume::plan(
                     root_disk.unwrap(), /* We just ensured it was populated */
@@ -243,7 +261,7 @@ impl Planner for Macos {
             ConfigureNix::plan(
                 ShellProfileLocations::default(),
                 &self.settings,
-                self.settings.determinate_nix.then(determinate_nix_settings),
+                self.settings.distribution().nix_config(),
             )
             .await
             .map_err(PlannerError::Action)?
@@ -265,19 +283,35 @@ impl Planner for Macos {
             );
         }
 
-        if self.settings.determinate_nix {
+        if self.settings.distribution() == Distribution::Determinate {
             plan.push(
-                ConfigureDeterminateNixdInitService::plan(InitSystem::Launchd, true)
-                    .await
-                    .map_err(PlannerError::Action)?
-                    .boxed(),
+                ConfigureDeterminateNixdInitService::plan(
+                    InitSystem::Launchd,
+                    true,
+                    self.settings
+                        .ssl_cert_file
+                        .as_ref()
+                        .map(|_| NIX_SSL_CERT_FILE.into()),
+                    self.settings.daemon_env.clone(),
+                )
+                .await
+                .map_err(PlannerError::Action)?
+                .boxed(),
             );
         } else {
             plan.push(
-                ConfigureUpstreamInitService::plan(InitSystem::Launchd, true)
-                    .await
-                    .map_err(PlannerError::Action)?
-                    .boxed(),
+                ConfigureUpstreamInitService::plan(
+                    InitSystem::Launchd,
+                    true,
+                    self.settings
+                        .ssl_cert_file
+                        .as_ref()
+                        .map(|_| NIX_SSL_CERT_FILE.into()),
+                    self.settings.daemon_env.clone(),
+                )
+                .await
+                .map_err(PlannerError::Action)?
+                .boxed(),
             );
         }
         plan.push(
@@ -287,6 +321,33 @@ impl Planner for Macos {
                 .boxed(),
         );
 
+        if !self.settings.extra_profile_packages.is_empty() {
+            plan.push(
+                InstallProfilePackages::plan(self.settings.extra_profile_packages.clone())
+                    .await
+                    .map_err(PlannerError::Action)?
+                    .boxed(),
+            );
+        }
+
+        for plugin in &self.settings.plugins {
+            plan.push(
+                RunPlugin::plan(plugin)
+                    .await
+                    .map_err(PlannerError::Action)?
+                    .boxed(),
+            );
+        }
+
+        for hook in &self.settings.post_install_hooks {
+            plan.push(
+                RunPostInstallHook::plan(hook)
+                    .await
+                    .map_err(PlannerError::Action)?
+                    .boxed(),
+            );
+        }
+
         Ok(plan)
     }
 
@@ -344,7 +405,10 @@ impl Planner for Macos {
                 .into_keys()
                 .collect::<Vec<_>>(),
             self.settings.ssl_cert_file.clone(),
-        )?)
+        )?
+        .client_cert(self.settings.diagnostic_client_cert.clone())
+        .redact(self.settings.diagnostic_redact.clone())
+        .report_file(self.settings.diagnostic_report_file.clone()))
     }
 
     async fn platform_check(&self) -> Result<(), PlannerError> {
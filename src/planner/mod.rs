@@ -119,14 +119,18 @@ pub mod macos;
 pub mod ostree;
 pub mod steam_deck;
 
-use std::{collections::HashMap, path::PathBuf, string::FromUtf8Error};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    string::FromUtf8Error,
+};
 
 use serde::{Deserialize, Serialize};
 
 use crate::{
     action::{ActionError, StatefulAction},
     error::HasExpectedErrors,
-    settings::{CommonSettings, InstallSettingsError},
+    settings::{CommonSettings, HomebrewPathOrder, InstallSettingsError, ProfileStrategy, ZshHook},
     Action, InstallPlan, NixInstallerError,
 };
 
@@ -306,6 +310,93 @@ pub struct ShellProfileLocations {
     pub fish: FishShellProfileLocations,
     pub bash: Vec<PathBuf>,
     pub zsh: Vec<PathBuf>,
+    /// Files in Nushell's vendor autoload directories, only written if `nu` is installed
+    #[serde(default = "ShellProfileLocations::default_nushell")]
+    pub nushell: Vec<PathBuf>,
+    /**
+    Elvish has no system-wide `rc.elv`, so these are relative to the home of root and each user,
+    and only written in homes where their directory (like `~/.config/elvish`) exists.
+    */
+    #[serde(default = "ShellProfileLocations::default_elvish")]
+    pub elvish: Vec<PathBuf>,
+    /// Files in Xonsh's system-wide `rc.d` directories, only written if `xonsh` is installed
+    #[serde(default = "ShellProfileLocations::default_xonsh")]
+    pub xonsh: Vec<PathBuf>,
+    /// Whether the sh, zsh, and fish snippets put Nix's profiles before or after Homebrew's prefix on `PATH`
+    #[serde(default)]
+    pub homebrew_path_order: Option<HomebrewPathOrder>,
+}
+
+impl ShellProfileLocations {
+    // https://www.nushell.sh/book/configuration.html#configuring-nu-as-a-login-shell
+    fn default_nushell() -> Vec<PathBuf> {
+        #[cfg(target_os = "macos")]
+        return vec!["/Library/Application Support/nushell/vendor/autoload/nix.nu".into()];
+        #[cfg(not(target_os = "macos"))]
+        return vec![
+            "/usr/share/nushell/vendor/autoload/nix.nu".into(),
+            "/usr/local/share/nushell/vendor/autoload/nix.nu".into(),
+        ];
+    }
+
+    /// Only the `/etc/profile.d` snippet and the fish vendor conf dir, see [`ProfileStrategy::ProfileDOnly`]
+    pub fn profile_d_only(self) -> Self {
+        Self {
+            fish: FishShellProfileLocations {
+                confd_prefixes: vec![],
+                ..self.fish
+            },
+            bash: self
+                .bash
+                .into_iter()
+                .filter(|profile| profile.starts_with("/etc/profile.d"))
+                .collect(),
+            zsh: vec![],
+            nushell: vec![],
+            elvish: vec![],
+            xonsh: vec![],
+            homebrew_path_order: self.homebrew_path_order,
+        }
+    }
+
+    /// The locations for `strategy`
+    pub fn for_strategy(self, strategy: ProfileStrategy) -> Self {
+        match strategy {
+            ProfileStrategy::Rc => self,
+            ProfileStrategy::ProfileDOnly => self.profile_d_only(),
+        }
+    }
+
+    /// Only the system-wide zsh startup file named by `hook`, in the directory this system's zsh reads them from
+    ///
+    /// Hooking a single file keeps Nix from being sourced twice.
+    pub fn with_zsh_hook(self, hook: ZshHook) -> Self {
+        let zsh_dir = if Path::new(ZSH_DEBIAN_DIR).is_dir() {
+            Path::new(ZSH_DEBIAN_DIR)
+        } else {
+            Path::new("/etc")
+        };
+        Self {
+            zsh: vec![zsh_hook_location(hook, zsh_dir)],
+            ..self
+        }
+    }
+
+    /// Order Nix's profiles and Homebrew's prefix on `PATH` (if Homebrew is installed), see [`HomebrewPathOrder`]
+    pub fn with_homebrew_path_order(self, order: Option<HomebrewPathOrder>) -> Self {
+        Self {
+            homebrew_path_order: order,
+            ..self
+        }
+    }
+
+    fn default_elvish() -> Vec<PathBuf> {
+        vec![".config/elvish/rc.elv".into()]
+    }
+
+    fn default_xonsh() -> Vec<PathBuf> {
+        vec!["/etc/xonsh/rc.d/nix.xsh".into()]
+    }
 }
 
 impl Default for ShellProfileLocations {
@@ -322,10 +413,82 @@ impl Default for ShellProfileLocations {
                 "/etc/zshrc".into(),
                 "/etc/zsh/zshrc".into(),
             ],
+            nushell: Self::default_nushell(),
+            elvish: Self::default_elvish(),
+            xonsh: Self::default_xonsh(),
+            homebrew_path_order: None,
         }
     }
 }
 
+/// Where Homebrew installs itself: on Apple silicon Macs, Intel Macs, and Linux
+pub(crate) const HOMEBREW_PREFIXES: &[&str] =
+    &["/opt/homebrew", "/usr/local", "/home/linuxbrew/.linuxbrew"];
+
+/// The prefix of the installed Homebrew, if there is one
+pub(crate) fn homebrew_prefix() -> Option<PathBuf> {
+    HOMEBREW_PREFIXES
+        .iter()
+        .map(PathBuf::from)
+        .find(|prefix| prefix.join("bin/brew").exists())
+}
+
+/// Debian, Ubuntu, and Arch build zsh to read its system-wide startup files from here, rather than `/etc`
+const ZSH_DEBIAN_DIR: &str = "/etc/zsh";
+
+/**
+Where the `hook` file in `zsh_dir` is, or its `zshenv` if that keeps zsh from reading the others
+
+The system-wide `zshenv` is read first, by every zsh. If it unsets `GLOBAL_RCS` the other system-wide
+files are skipped, and if it sets `ZDOTDIR` the users' own files are read from there instead of their
+homes (which doesn't change which system-wide files are read).
+*/
+fn zsh_hook_location(hook: ZshHook, zsh_dir: &Path) -> PathBuf {
+    let zshenv = zsh_dir.join("zshenv");
+    let contents = std::fs::read_to_string(&zshenv).unwrap_or_default();
+    let lines = contents
+        .lines()
+        .map(|line| line.split('#').next().unwrap_or_default().trim())
+        .filter(|line| !line.is_empty());
+
+    let mut global_rcs = true;
+    for line in lines {
+        let normalized = line.to_lowercase().replace('_', "");
+        if normalized.contains("noglobalrcs")
+            || (normalized.starts_with("unsetopt") && normalized.contains("globalrcs"))
+        {
+            global_rcs = false;
+        }
+        if let Some((_, zdotdir)) = line.split_once("ZDOTDIR=") {
+            tracing::info!(
+                "`{}` relocates users' zsh files to `{}`, the Nix hook is still read from the system-wide files",
+                zshenv.display(),
+                zdotdir.trim_matches(['"', '\''])
+            );
+        }
+    }
+
+    let hook = match hook {
+        ZshHook::Zshenv => ZshHook::Zshenv,
+        hook if !global_rcs => {
+            tracing::warn!(
+                "`{}` unsets `GLOBAL_RCS`, so zsh doesn't read `{hook}`, hooking Nix into `zshenv` instead",
+                zshenv.display()
+            );
+            ZshHook::Zshenv
+        },
+        hook => hook,
+    };
+    // On macOS, `/etc/zprofile` runs `path_helper`, which moves what `zshenv` added to `PATH` behind the system's directories
+    if hook == ZshHook::Zshenv && zsh_dir.join("zprofile").exists() && cfg!(target_os = "macos") {
+        tracing::warn!(
+            "`{}` may reorder `PATH` after `zshenv`, commands in `/usr/bin` will be found before Nix's",
+            zsh_dir.join("zprofile").display()
+        );
+    }
+    zsh_dir.join(hook.to_string())
+}
+
 #[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Clone)]
 pub struct FishShellProfileLocations {
     pub confd_suffix: PathBuf,
@@ -397,11 +560,24 @@ pub enum PlannerError {
     RosettaDetected,
     #[error("Determinate Nix is not available. See: https://determinate.systems/enterprise")]
     DeterminateNixUnavailable,
-    #[error("Running Nix on the EC2 instance store requires Determinate Nix to be enabled")]
+    #[error("Running Nix on the EC2 instance store requires Determinate Nix to be enabled with `--distribution determinate`")]
     Ec2InstanceStoreRequiresDeterminateNix,
     /// A Linux SELinux related error
     #[error("Unable to install on an SELinux system without common SELinux tooling, the binaries `restorecon`, and `semodule` are required")]
     SelinuxRequirements,
+    #[error("`/` is a read-only composefs, so `/nix` can't be created at boot. Add `RUN mkdir /nix` to the image's Containerfile (or set `transient = true` under `[root]` in `/usr/lib/ostree/prepare-root.conf`), switch to it, and try again")]
+    ComposefsWithoutNix,
+    /// An AppArmor profile was asked for on a system which can't load it
+    #[error("Unable to install an AppArmor profile for the Nix daemon: {0}")]
+    ApparmorRequirements(&'static str),
+    /// Builds in cgroups were asked for on a system which can't delegate them
+    #[error("Unable to run builds in cgroups: {0}")]
+    CgroupsRequirements(&'static str),
+    /// A tmpfs build directory was asked for without systemd to mount it
+    #[error("`--build-dir-tmpfs` is mounted by a systemd unit, which `--init none` doesn't have")]
+    BuildDirTmpfsRequiresSystemd,
+    #[error("`--build-dir-tmpfs` requires `--build-dir` to be an absolute path, not `{0}`")]
+    BuildDirTmpfsRequiresAbsolutePath(PathBuf),
     /// A UTF-8 related error
     #[error("UTF-8 error")]
     Utf8(#[from] FromUtf8Error),
@@ -412,6 +588,10 @@ pub enum PlannerError {
     NixOs,
     #[error("`nix` is already a valid command, so it is installed")]
     NixExists,
+    #[error("Nix is already installed by the `{package}` package, take it over with `nix-installer migrate`, or remove the package (like with `{remove}`) to install Nix afresh")]
+    PackagedNix { package: String, remove: String },
+    #[error("`--profile-strategy profile.d-only` is not supported on macOS, its shells don't load `/etc/profile.d`")]
+    ProfileDOnlyUnsupported,
     #[error("WSL1 is not supported, please upgrade to WSL2: https://learn.microsoft.com/en-us/windows/wsl/install#upgrade-version-from-wsl-1-to-wsl-2")]
     Wsl1,
     /// Failed to execute command
@@ -437,6 +617,11 @@ impl HasExpectedErrors for PlannerError {
             PlannerError::OsRelease(_) => None,
             PlannerError::Utf8(_) => None,
             PlannerError::SelinuxRequirements => Some(Box::new(self)),
+            this @ PlannerError::ApparmorRequirements(_) => Some(Box::new(this)),
+            this @ PlannerError::CgroupsRequirements(_) => Some(Box::new(this)),
+            this @ PlannerError::BuildDirTmpfsRequiresSystemd => Some(Box::new(this)),
+            this @ PlannerError::BuildDirTmpfsRequiresAbsolutePath(_) => Some(Box::new(this)),
+            this @ PlannerError::ComposefsWithoutNix => Some(Box::new(this)),
             PlannerError::Custom(_e) => {
                 #[cfg(target_os = "linux")]
                 if let Some(err) = _e.downcast_ref::<linux::LinuxErrorKind>() {
@@ -450,7 +635,9 @@ impl HasExpectedErrors for PlannerError {
             },
             this @ PlannerError::NixOs => Some(Box::new(this)),
             this @ PlannerError::NixExists => Some(Box::new(this)),
+            this @ PlannerError::PackagedNix { .. } => Some(Box::new(this)),
             this @ PlannerError::Wsl1 => Some(Box::new(this)),
+            this @ PlannerError::ProfileDOnlyUnsupported => Some(Box::new(this)),
             PlannerError::Command(_, _) => None,
             #[cfg(feature = "diagnostics")]
             PlannerError::Diagnostic(diagnostic_error) => Some(Box::new(diagnostic_error)),
@@ -465,3 +652,47 @@ impl crate::diagnostics::ErrorDiagnostic for PlannerError {
         static_str.to_string()
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::path::PathBuf;
+
+    use super::{zsh_hook_location, ShellProfileLocations};
+    use crate::settings::{ProfileStrategy, ZshHook};
+
+    #[test]
+    fn profile_d_only_leaves_rc_files_alone() {
+        let locations =
+            ShellProfileLocations::default().for_strategy(ProfileStrategy::ProfileDOnly);
+        assert_eq!(locations.bash, vec![PathBuf::from("/etc/profile.d/nix.sh")]);
+        assert!(locations.zsh.is_empty());
+        assert!(locations.fish.confd_prefixes.is_empty());
+        assert!(!locations.fish.vendor_confd_prefixes.is_empty());
+        assert!(locations.elvish.is_empty());
+    }
+
+    #[test]
+    fn hooks_zshenv_without_global_rcs() -> eyre::Result<()> {
+        let zsh_dir = tempfile::tempdir()?;
+        assert_eq!(
+            zsh_hook_location(ZshHook::Zprofile, zsh_dir.path()),
+            zsh_dir.path().join("zprofile")
+        );
+
+        std::fs::write(
+            zsh_dir.path().join("zshenv"),
+            "# unsetopt GLOBAL_RCS\nexport ZDOTDIR=\"$HOME/.config/zsh\"\n",
+        )?;
+        assert_eq!(
+            zsh_hook_location(ZshHook::Zshrc, zsh_dir.path()),
+            zsh_dir.path().join("zshrc")
+        );
+
+        std::fs::write(zsh_dir.path().join("zshenv"), "setopt no_global_rcs\n")?;
+        assert_eq!(
+            zsh_hook_location(ZshHook::Zshrc, zsh_dir.path()),
+            zsh_dir.path().join("zshenv")
+        );
+        Ok(())
+    }
+}
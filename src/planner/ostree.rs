@@ -1,22 +1,31 @@
 use crate::{
     action::{
-        base::{CreateDirectory, CreateFile, RemoveDirectory},
+        base::{
+            CreateDirectory, CreateFile, CreateSnapshots, InstallProfilePackages, RemoveDirectory,
+            RunPlugin, RunPostInstallHook,
+        },
         common::{
-            ConfigureNix, ConfigureUpstreamInitService, CreateUsersAndGroups,
-            ProvisionDeterminateNixd, ProvisionNix,
+            place_nix_configuration::NIX_SSL_CERT_FILE, ConfigureNix, ConfigureUpstreamInitService,
+            CreateUsersAndGroups, ProvisionDeterminateNixd, ProvisionNix,
         },
         linux::{
-            provision_selinux::{DETERMINATE_SELINUX_POLICY_PP_CONTENT, SELINUX_POLICY_PP_CONTENT},
-            ProvisionSelinux, StartSystemdUnit, SystemctlDaemonReload,
+            provision_selinux::{
+                DETERMINATE_SELINUX_DAEMON_PATHS, DETERMINATE_SELINUX_POLICY_PP_CONTENT,
+                SELINUX_DAEMON_PATHS, SELINUX_POLICY_PP_CONTENT,
+            },
+            ProvisionSelinux, RestoreSelinuxContexts, StartSystemdUnit, SystemctlDaemonReload,
         },
         StatefulAction,
     },
     error::HasExpectedErrors,
     planner::{Planner, PlannerError},
-    settings::{determinate_nix_settings, CommonSettings, InitSystem, InstallSettingsError},
+    settings::{CommonSettings, Distribution, InitSystem, InstallSettingsError},
     Action, BuiltinPlanner,
 };
-use std::{collections::HashMap, path::PathBuf};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
 
 use super::{
     linux::{
@@ -26,6 +35,14 @@ use super::{
     ShellProfileLocations,
 };
 
+const PERSISTENCE_TMPFILES: &str = "/etc/tmpfiles.d/nix-persistence.conf";
+const DEPLOYMENT_CHECK_UNIT: &str = "/etc/systemd/system/nix-installer-deployment-check.service";
+/// The deployment Nix was last checked in, in `/nix` so it goes with it
+const CHECKED_DEPLOYMENT: &str = "/nix/var/nix-installer-deployment";
+/// Greenboot reboots (and eventually rolls back) a deployment when a check in here fails
+const GREENBOOT_REQUIRED_CHECKS: &str = "/etc/greenboot/check/required.d";
+const GREENBOOT_CHECK: &str = "/etc/greenboot/check/required.d/50-nix.sh";
+
 /// A planner suitable for immutable systems using ostree, such as Fedora Silverblue
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 #[cfg_attr(feature = "cli", derive(clap::Parser))]
@@ -33,6 +50,18 @@ pub struct Ostree {
     /// Where `/nix` will be bind mounted to.
     #[cfg_attr(feature = "cli", clap(long, default_value = "/var/home/nix"))]
     persistence: PathBuf,
+    /// Add a greenboot health check, so upgrades which break `/nix` or the Nix daemon are rolled back
+    #[cfg_attr(
+        feature = "cli",
+        clap(
+            long,
+            action(clap::ArgAction::SetTrue),
+            default_value = "false",
+            env = "NIX_INSTALLER_GREENBOOT"
+        )
+    )]
+    #[serde(default)]
+    greenboot: bool,
     #[cfg_attr(feature = "cli", clap(flatten))]
     pub settings: CommonSettings,
 }
@@ -43,12 +72,20 @@ impl Planner for Ostree {
     async fn default() -> Result<Self, PlannerError> {
         Ok(Self {
             persistence: PathBuf::from("/var/home/nix"),
+            greenboot: false,
             settings: CommonSettings::default().await?,
         })
     }
 
     async fn plan(&self) -> Result<Vec<StatefulAction<Box<dyn Action>>>, PlannerError> {
         let has_selinux = detect_selinux().await?;
+        // `nix-directory.service` can't create `/nix` in a composefs `/`, the image must have it
+        if root_is_composefs().await && !Path::new("/nix").exists() {
+            return Err(PlannerError::ComposefsWithoutNix);
+        }
+        if self.greenboot && !Path::new(GREENBOOT_REQUIRED_CHECKS).is_dir() {
+            return Err(OstreeError::GreenbootNotInstalled.into());
+        }
         let mut plan = vec![
             // Primarily for uninstall
             SystemctlDaemonReload::plan()
@@ -57,6 +94,17 @@ impl Planner for Ostree {
                 .boxed(),
         ];
 
+        if self.settings.snapshot {
+            // Taken before anything is changed, and deleted after everything is reverted
+            plan.insert(
+                0,
+                CreateSnapshots::plan()
+                    .await
+                    .map_err(PlannerError::Action)?
+                    .boxed(),
+            );
+        }
+
         plan.push(
             CreateDirectory::plan(&self.persistence, None, None, 0o0755, true)
                 .await
@@ -64,6 +112,25 @@ impl Planner for Ostree {
                 .boxed(),
         );
 
+        // Image updates (like `bootc upgrade`) don't change `/var`, so systemd-tmpfiles recreates it if a fresh `/var` is deployed
+        let persistence_tmpfiles_buf = format!(
+            "d {persistence} 0755 root root -\n",
+            persistence = self.persistence.display(),
+        );
+        plan.push(
+            CreateFile::plan(
+                PERSISTENCE_TMPFILES,
+                None,
+                None,
+                0o0644,
+                persistence_tmpfiles_buf,
+                false,
+            )
+            .await
+            .map_err(PlannerError::Action)?
+            .boxed(),
+        );
+
         let nix_directory_buf = "\
                 [Unit]\n\
                 Description=Enable mount points in / for ostree\n\
@@ -161,7 +228,7 @@ impl Planner for Ostree {
             .fish
             .vendor_confd_prefixes
             .iter()
-            .position(|v| *v == PathBuf::from("/usr/share/fish/"))
+            .position(|v| *v == Path::new("/usr/share/fish/"))
         {
             shell_profile_locations
                 .fish
@@ -176,7 +243,7 @@ impl Planner for Ostree {
                 .boxed(),
         );
 
-        if self.settings.determinate_nix {
+        if self.settings.distribution() == Distribution::Determinate {
             plan.push(
                 ProvisionDeterminateNixd::plan()
                     .await
@@ -201,7 +268,7 @@ impl Planner for Ostree {
             ConfigureNix::plan(
                 shell_profile_locations,
                 &self.settings,
-                self.settings.determinate_nix.then(determinate_nix_settings),
+                self.settings.distribution().nix_config(),
             )
             .await
             .map_err(PlannerError::Action)?
@@ -212,7 +279,7 @@ impl Planner for Ostree {
             plan.push(
                 ProvisionSelinux::plan(
                     "/etc/nix-installer/selinux/packages/nix.pp".into(),
-                    if self.settings.determinate_nix {
+                    if self.settings.distribution() == Distribution::Determinate {
                         DETERMINATE_SELINUX_POLICY_PP_CONTENT
                     } else {
                         SELINUX_POLICY_PP_CONTENT
@@ -232,17 +299,78 @@ impl Planner for Ostree {
         );
 
         plan.push(
-            ConfigureUpstreamInitService::plan(InitSystem::Systemd, true)
+            ConfigureUpstreamInitService::plan(
+                InitSystem::Systemd,
+                true,
+                self.settings
+                    .ssl_cert_file
+                    .as_ref()
+                    .map(|_| NIX_SSL_CERT_FILE.into()),
+                self.settings.daemon_env.clone(),
+            )
+            .await
+            .map_err(PlannerError::Action)?
+            .boxed(),
+        );
+        if has_selinux {
+            plan.push(
+                RestoreSelinuxContexts::plan(
+                    if self.settings.distribution() == Distribution::Determinate {
+                        DETERMINATE_SELINUX_DAEMON_PATHS
+                    } else {
+                        SELINUX_DAEMON_PATHS
+                    }
+                    .iter()
+                    .map(PathBuf::from)
+                    .collect(),
+                )
                 .await
                 .map_err(PlannerError::Action)?
                 .boxed(),
-        );
+            );
+        }
         plan.push(
             StartSystemdUnit::plan("ensure-symlinked-units-resolve.service".to_string(), true)
                 .await
                 .map_err(PlannerError::Action)?
                 .boxed(),
         );
+        // Deployment switches (`rpm-ostree rebase`, image updates) replace `/` and merge `/etc`,
+        // check that Nix survived the first boot into each new one
+        plan.push(
+            CreateFile::plan(
+                DEPLOYMENT_CHECK_UNIT,
+                None,
+                None,
+                0o0644,
+                deployment_check_unit(),
+                false,
+            )
+            .await
+            .map_err(PlannerError::Action)?
+            .boxed(),
+        );
+        plan.push(
+            StartSystemdUnit::plan("nix-installer-deployment-check.service".to_string(), true)
+                .await
+                .map_err(PlannerError::Action)?
+                .boxed(),
+        );
+        if self.greenboot {
+            plan.push(
+                CreateFile::plan(
+                    GREENBOOT_CHECK,
+                    None,
+                    None,
+                    0o0755,
+                    greenboot_check(),
+                    false,
+                )
+                .await
+                .map_err(PlannerError::Action)?
+                .boxed(),
+            );
+        }
         plan.push(
             RemoveDirectory::plan(crate::settings::SCRATCH_DIR)
                 .await
@@ -256,12 +384,40 @@ impl Planner for Ostree {
                 .boxed(),
         );
 
+        if !self.settings.extra_profile_packages.is_empty() {
+            plan.push(
+                InstallProfilePackages::plan(self.settings.extra_profile_packages.clone())
+                    .await
+                    .map_err(PlannerError::Action)?
+                    .boxed(),
+            );
+        }
+
+        for plugin in &self.settings.plugins {
+            plan.push(
+                RunPlugin::plan(plugin)
+                    .await
+                    .map_err(PlannerError::Action)?
+                    .boxed(),
+            );
+        }
+
+        for hook in &self.settings.post_install_hooks {
+            plan.push(
+                RunPostInstallHook::plan(hook)
+                    .await
+                    .map_err(PlannerError::Action)?
+                    .boxed(),
+            );
+        }
+
         Ok(plan)
     }
 
     fn settings(&self) -> Result<HashMap<String, serde_json::Value>, InstallSettingsError> {
         let Self {
             persistence,
+            greenboot,
             settings,
         } = self;
         let mut map = HashMap::default();
@@ -271,6 +427,7 @@ impl Planner for Ostree {
             "persistence".to_string(),
             serde_json::to_value(persistence)?,
         );
+        map.insert("greenboot".to_string(), serde_json::to_value(greenboot)?);
 
         Ok(map)
     }
@@ -302,7 +459,10 @@ impl Planner for Ostree {
                 .into_keys()
                 .collect::<Vec<_>>(),
             self.settings.ssl_cert_file.clone(),
-        )?)
+        )?
+        .client_cert(self.settings.diagnostic_client_cert.clone())
+        .redact(self.settings.diagnostic_redact.clone())
+        .report_file(self.settings.diagnostic_report_file.clone()))
     }
 
     async fn platform_check(&self) -> Result<(), PlannerError> {
@@ -366,6 +526,8 @@ pub enum OstreeError {
         To use a `root`-only Nix install, consider passing `--init none`."
     )]
     Wsl2SystemdNotActive,
+    #[error("`--greenboot` was passed, but `/etc/greenboot/check/required.d` does not exist. Install greenboot first, or leave out `--greenboot`.")]
+    GreenbootNotInstalled,
 }
 
 impl HasExpectedErrors for OstreeError {
@@ -373,6 +535,7 @@ impl HasExpectedErrors for OstreeError {
         match self {
             OstreeError::SystemdNotActive => Some(Box::new(self)),
             OstreeError::Wsl2SystemdNotActive => Some(Box::new(self)),
+            OstreeError::GreenbootNotInstalled => Some(Box::new(self)),
         }
     }
 }
@@ -382,3 +545,82 @@ impl From<OstreeError> for PlannerError {
         PlannerError::Custom(Box::new(v))
     }
 }
+
+/// Runs `nix-installer verify` once after booting into a new deployment, the first run (during the install) only records it
+fn deployment_check_unit() -> String {
+    format!(
+        "\
+        [Unit]\n\
+        Description=Check that Nix survived the switch to a new ostree deployment\n\
+        After=nix.mount nix-daemon.socket ensure-symlinked-units-resolve.service\n\
+        Requires=nix.mount\n\
+        ConditionPathExists=/run/ostree-booted\n\
+        \n\
+        [Service]\n\
+        Type=oneshot\n\
+        RemainAfterExit=yes\n\
+        ExecStart=/bin/sh -c '\
+            deployment=\"$$(sed -n \"s/.*ostree=\\\\([^ ]*\\\\).*/\\\\1/p\" /proc/cmdline)\"; \
+            if [ -e {CHECKED_DEPLOYMENT} ] && [ \"$$(cat {CHECKED_DEPLOYMENT})\" != \"$$deployment\" ] && [ -x /nix/nix-installer ]; then \
+                /nix/nix-installer verify || exit 1; \
+            fi; \
+            echo \"$$deployment\" > {CHECKED_DEPLOYMENT}'\n\
+        \n\
+        [Install]\n\
+        WantedBy=multi-user.target\n\
+        "
+    )
+}
+
+/// The greenboot check, which fails when `/nix` isn't mounted or the daemon doesn't answer
+fn greenboot_check() -> String {
+    format!(
+        "\
+        #!/bin/bash\n\
+        # Check that Nix works in this deployment, written by nix-installer.\n\
+        # Greenboot reboots (and eventually rolls back) deployments where it fails.\n\
+        set -eu\n\
+        \n\
+        if ! mountpoint -q /nix; then\n\
+        {inde}echo \"nix: /nix is not mounted\" >&2\n\
+        {inde}exit 1\n\
+        fi\n\
+        \n\
+        /nix/var/nix/profiles/default/bin/nix --extra-experimental-features nix-command store ping --store daemon\n",
+        inde = "    ", // indent
+    )
+}
+
+/// On composefs (bootc images, and Fedora Atomic from 41), `/` can't be changed even after `chattr -i`
+async fn root_is_composefs() -> bool {
+    tokio::fs::read_to_string("/proc/mounts")
+        .await
+        .is_ok_and(|mounts| {
+            mounts.lines().any(|mount| {
+                let mut fields = mount.split_whitespace();
+                fields.next() == Some("composefs") && fields.next() == Some("/")
+            })
+        })
+}
+
+#[cfg(test)]
+mod test {
+    use super::{deployment_check_unit, greenboot_check};
+
+    #[test]
+    fn deployment_check_escapes_for_systemd() {
+        let unit = deployment_check_unit();
+        // systemd would expand `$deployment` itself
+        assert!(!unit.replace("$$", "").contains('$'));
+        assert!(unit.contains("/nix/nix-installer verify || exit 1;"));
+        assert!(unit.contains("echo \"$$deployment\" > /nix/var/nix-installer-deployment'\n"));
+    }
+
+    #[test]
+    fn greenboot_check_fails_without_nix() {
+        let check = greenboot_check();
+        assert!(check.starts_with("#!/bin/bash\n"));
+        assert!(check.contains("if ! mountpoint -q /nix; then\n    echo"));
+        assert!(check.ends_with("store ping --store daemon\n"));
+    }
+}
@@ -96,16 +96,23 @@ To test on a specific build id of the Steam Deck:
 6. Safely turn off the VM!
 
 */
-use std::{collections::HashMap, path::PathBuf, process::Output};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    process::Output,
+};
 
 use tokio::process::Command;
 
 use crate::{
     action::{
-        base::{CreateDirectory, CreateFile, RemoveDirectory},
+        base::{
+            CreateDirectory, CreateFile, CreateSnapshots, InstallProfilePackages, RemoveDirectory,
+            RunPlugin, RunPostInstallHook,
+        },
         common::{
-            ConfigureNix, ConfigureUpstreamInitService, CreateUsersAndGroups,
-            ProvisionDeterminateNixd, ProvisionNix,
+            place_nix_configuration::NIX_SSL_CERT_FILE, ConfigureNix, ConfigureUpstreamInitService,
+            CreateUsersAndGroups, ProvisionDeterminateNixd, ProvisionNix,
         },
         linux::{
             EnsureSteamosNixDirectory, RevertCleanSteamosNixOffload, StartSystemdUnit,
@@ -114,12 +121,16 @@ use crate::{
         Action, StatefulAction,
     },
     planner::{Planner, PlannerError},
-    settings::{determinate_nix_settings, CommonSettings, InitSystem, InstallSettingsError},
+    settings::{CommonSettings, Distribution, InitSystem, InstallSettingsError},
     BuiltinPlanner,
 };
 
 use super::ShellProfileLocations;
 
+const UPDATE_CHECK_UNIT: &str = "/etc/systemd/system/nix-installer-steamos-update.service";
+/// The SteamOS `BUILD_ID` the update check last saw, kept in `/nix` so it survives updates
+const CHECKED_BUILD: &str = "/nix/var/nix-installer-steamos-build";
+
 /// A planner for the Valve Steam Deck running SteamOS
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 #[cfg_attr(feature = "cli", derive(clap::Parser))]
@@ -160,6 +171,17 @@ impl Planner for SteamDeck {
                 .boxed(),
         ];
 
+        if self.settings.snapshot {
+            // Taken before anything is changed, and deleted after everything is reverted
+            actions.insert(
+                0,
+                CreateSnapshots::plan()
+                    .await
+                    .map_err(PlannerError::Action)?
+                    .boxed(),
+            );
+        }
+
         if let Ok(nix_mount_status) = systemctl_status("nix.mount").await {
             let nix_mount_status_stderr = String::from_utf8(nix_mount_status.stderr)?;
             if nix_mount_status_stderr.contains("Warning: The unit file, source configuration file or drop-ins of nix.mount changed on disk. Run 'systemctl daemon-reload' to reload units.") {
@@ -305,7 +327,7 @@ impl Planner for SteamDeck {
             .fish
             .vendor_confd_prefixes
             .iter()
-            .position(|v| *v == PathBuf::from("/usr/share/fish/"))
+            .position(|v| *v == Path::new("/usr/share/fish/"))
         {
             shell_profile_locations
                 .fish
@@ -322,7 +344,7 @@ impl Planner for SteamDeck {
             )
         }
 
-        if self.settings.determinate_nix {
+        if self.settings.distribution() == Distribution::Determinate {
             actions.push(
                 ProvisionDeterminateNixd::plan()
                     .await
@@ -331,6 +353,19 @@ impl Planner for SteamDeck {
             );
         }
 
+        // SteamOS updates replace the root filesystem, `/etc` and `/home` (where `/nix` lives) are kept.
+        // Repair what lived outside of them on the first boot into each new build.
+        let update_check_unit = CreateFile::plan(
+            UPDATE_CHECK_UNIT,
+            None,
+            None,
+            0o0644,
+            update_check_unit(),
+            false,
+        )
+        .await
+        .map_err(PlannerError::Action)?;
+
         actions.append(&mut vec![
             ProvisionNix::plan(&self.settings.clone())
                 .await
@@ -343,17 +378,30 @@ impl Planner for SteamDeck {
             ConfigureNix::plan(
                 shell_profile_locations,
                 &self.settings,
-                self.settings.determinate_nix.then(determinate_nix_settings),
+                self.settings.distribution().nix_config(),
             )
             .await
             .map_err(PlannerError::Action)?
             .boxed(),
             // Init is required for the steam-deck archetype to make the `/nix` mount
-            ConfigureUpstreamInitService::plan(InitSystem::Systemd, true)
+            ConfigureUpstreamInitService::plan(
+                InitSystem::Systemd,
+                true,
+                self.settings
+                    .ssl_cert_file
+                    .as_ref()
+                    .map(|_| NIX_SSL_CERT_FILE.into()),
+                self.settings.daemon_env.clone(),
+            )
+            .await
+            .map_err(PlannerError::Action)?
+            .boxed(),
+            StartSystemdUnit::plan("ensure-symlinked-units-resolve.service".to_string(), true)
                 .await
                 .map_err(PlannerError::Action)?
                 .boxed(),
-            StartSystemdUnit::plan("ensure-symlinked-units-resolve.service".to_string(), true)
+            update_check_unit.boxed(),
+            StartSystemdUnit::plan("nix-installer-steamos-update.service".to_string(), true)
                 .await
                 .map_err(PlannerError::Action)?
                 .boxed(),
@@ -366,6 +414,34 @@ impl Planner for SteamDeck {
                 .map_err(PlannerError::Action)?
                 .boxed(),
         ]);
+
+        if !self.settings.extra_profile_packages.is_empty() {
+            actions.push(
+                InstallProfilePackages::plan(self.settings.extra_profile_packages.clone())
+                    .await
+                    .map_err(PlannerError::Action)?
+                    .boxed(),
+            );
+        }
+
+        for plugin in &self.settings.plugins {
+            actions.push(
+                RunPlugin::plan(plugin)
+                    .await
+                    .map_err(PlannerError::Action)?
+                    .boxed(),
+            );
+        }
+
+        for hook in &self.settings.post_install_hooks {
+            actions.push(
+                RunPostInstallHook::plan(hook)
+                    .await
+                    .map_err(PlannerError::Action)?
+                    .boxed(),
+            );
+        }
+
         Ok(actions)
     }
 
@@ -412,7 +488,10 @@ impl Planner for SteamDeck {
                 .into_keys()
                 .collect::<Vec<_>>(),
             self.settings.ssl_cert_file.clone(),
-        )?)
+        )?
+        .client_cert(self.settings.diagnostic_client_cert.clone())
+        .redact(self.settings.diagnostic_redact.clone())
+        .report_file(self.settings.diagnostic_report_file.clone()))
     }
 
     async fn platform_check(&self) -> Result<(), PlannerError> {
@@ -468,6 +547,32 @@ pub enum SteamDeckError {
     NixMountSystemctlDaemonReloadRequired,
 }
 
+/// Repairs the install once after booting into a new SteamOS build, the first run (during the install) only records it
+fn update_check_unit() -> String {
+    format!(
+        "\
+        [Unit]\n\
+        Description=Repair Nix after a SteamOS update\n\
+        After=nix.mount nix-daemon.socket ensure-symlinked-units-resolve.service\n\
+        Requires=nix.mount\n\
+        \n\
+        [Service]\n\
+        Type=oneshot\n\
+        RemainAfterExit=yes\n\
+        ExecStart=/bin/sh -c '\
+            build=\"$$(sed -n \"s/^BUILD_ID=//p\" /etc/os-release)\"; \
+            if [ -e {CHECKED_BUILD} ] && [ \"$$(cat {CHECKED_BUILD})\" != \"$$build\" ] && [ -x /nix/nix-installer ]; then \
+                /nix/nix-installer repair hooks --no-confirm || exit 1; \
+                /nix/nix-installer verify || exit 1; \
+            fi; \
+            echo \"$$build\" > {CHECKED_BUILD}'\n\
+        \n\
+        [Install]\n\
+        WantedBy=multi-user.target\n\
+        "
+    )
+}
+
 pub(crate) async fn detect_requires_bind_mount() -> Result<bool, PlannerError> {
     let steamos_nix_mount_unit_path = "/usr/lib/systemd/system/nix.mount";
     let nix_mount_unit = tokio::fs::read_to_string(steamos_nix_mount_unit_path)
@@ -492,3 +597,17 @@ async fn systemctl_status(unit: &str) -> Result<Output, PlannerError> {
         .map_err(|e| PlannerError::Command(format!("{:?}", command.as_std()), e))?;
     Ok(output)
 }
+
+#[cfg(test)]
+mod test {
+    use super::update_check_unit;
+
+    #[test]
+    fn update_check_repairs_on_new_builds() {
+        let unit = update_check_unit();
+        // systemd would expand `$build` itself
+        assert!(!unit.replace("$$", "").contains('$'));
+        assert!(unit.contains("/nix/nix-installer repair hooks --no-confirm || exit 1;"));
+        assert!(unit.contains("echo \"$$build\" > /nix/var/nix-installer-steamos-build'\n"));
+    }
+}
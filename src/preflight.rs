@@ -0,0 +1,388 @@
+//! Non-mutating checks that a host can take an install, see `nix-installer check`
+//!
+//! Unlike the planners' `pre_install_check`, which stops at the first problem, every check is run
+//! so all the problems can be reported (and fixed) at once.
+
+use std::{
+    path::Path,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use url::Url;
+
+use crate::{
+    parse_ssl_cert,
+    settings::{CommonSettings, UrlOrPath},
+};
+
+const NIX_DIR: &str = "/nix";
+/// A fresh store, the unpacked package beside it during the install, and some first builds
+const MIN_FREE_BYTES: u64 = 2 * 1024 * 1024 * 1024;
+/// The daemon raises its soft limit of open files to the hard limit, builds of large closures need this many
+const MIN_OPEN_FILES: u64 = 4096;
+/// 2025-01-01, this `nix-installer` was released after, so a correct clock is too
+const EARLIEST_PLAUSIBLE_TIME: u64 = 1_735_689_600;
+/// How far the clock may be from the server's before TLS and substituters start misbehaving
+const MAX_CLOCK_SKEW: Duration = Duration::from_secs(5 * 60);
+const NETWORK_TIMEOUT: Duration = Duration::from_secs(10);
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/// The checks run by [`preflight`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Check {
+    DiskSpace,
+    KernelFeatures,
+    OpenFiles,
+    ExistingNix,
+    Clock,
+    Network,
+}
+
+impl std::fmt::Display for Check {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Check::DiskSpace => "Disk space",
+            Check::KernelFeatures => "Kernel features",
+            Check::OpenFiles => "Open file limit",
+            Check::ExistingNix => "Existing `/nix`",
+            Check::Clock => "Clock",
+            Check::Network => "Network",
+        };
+        write!(f, "{name}")
+    }
+}
+
+/// A problem found by [`preflight`], with how to fix it
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct Problem {
+    pub check: Check,
+    pub problem: String,
+    pub fix: String,
+}
+
+/// Run every check for an install with `settings`, returning the problems found
+#[tracing::instrument(level = "debug", skip_all)]
+pub async fn preflight(settings: &CommonSettings) -> Vec<Problem> {
+    let mut problems = vec![];
+    problems.extend(check_disk_space());
+    problems.extend(check_kernel_features().await);
+    problems.extend(check_open_files());
+    problems.extend(check_existing_nix().await);
+    let server_time = match settings.nix_package() {
+        Some(UrlOrPath::Url(url)) => match check_network(&url, settings).await {
+            Ok(server_time) => server_time,
+            Err(problem) => {
+                problems.push(problem);
+                None
+            },
+        },
+        // Installing the embedded (or a local) package doesn't need the network
+        Some(UrlOrPath::Path(_)) | None => None,
+    };
+    problems.extend(check_clock(SystemTime::now(), server_time));
+    problems
+}
+
+fn check_disk_space() -> Option<Problem> {
+    // `/nix` usually doesn't exist yet, it will be on the filesystem of its nearest ancestor
+    let target = Path::new(NIX_DIR)
+        .ancestors()
+        .find(|ancestor| ancestor.exists())?;
+    let stat = match nix::sys::statvfs::statvfs(target) {
+        Ok(stat) => stat,
+        Err(err) => {
+            tracing::debug!(%err, "Could not check the free space of `{}`", target.display());
+            return None;
+        },
+    };
+    let free = stat.blocks_available() as u64 * stat.fragment_size() as u64;
+    if free >= MIN_FREE_BYTES {
+        return None;
+    }
+    Some(Problem {
+        check: Check::DiskSpace,
+        problem: format!(
+            "Only {} MiB are free on the filesystem of `{}`, the install needs at least {} MiB",
+            free / 1024 / 1024,
+            target.display(),
+            MIN_FREE_BYTES / 1024 / 1024,
+        ),
+        fix: format!(
+            "Free up space on the filesystem of `{}`, or mount a larger one on `{NIX_DIR}`",
+            target.display()
+        ),
+    })
+}
+
+async fn check_kernel_features() -> Vec<Problem> {
+    if !cfg!(target_os = "linux") || !Path::new("/proc/self").exists() {
+        return vec![];
+    }
+    let mut problems = vec![];
+
+    if let Ok(status) = tokio::fs::read_to_string("/proc/self/status").await {
+        if !status.lines().any(|line| line.starts_with("Seccomp:")) {
+            problems.push(Problem {
+                check: Check::KernelFeatures,
+                problem: "The kernel does not support seccomp, which Nix uses to filter the system calls of builds".into(),
+                fix: "Use a kernel built with `CONFIG_SECCOMP`, or pass `--extra-conf 'filter-syscalls = false'`".into(),
+            });
+        }
+    }
+
+    problems
+}
+
+/// Why builds can't be sandboxed on this host, if they can't
+///
+/// Not a [`Problem`], where this is the case the install sets `sandbox = false` in `nix.conf`
+/// instead. Nix would otherwise fail each build with `cloning builder process: Operation not
+/// permitted`.
+pub(crate) async fn sandbox_unavailable() -> Option<String> {
+    if !cfg!(target_os = "linux") || !Path::new("/proc/self").exists() {
+        return None;
+    }
+    if !Path::new("/proc/self/ns/user").exists() {
+        return Some("the kernel does not support user namespaces".into());
+    }
+    // `kernel.unprivileged_userns_clone` (like on older Debian) doesn't limit root, which the daemon builds as
+    if let Ok(max) = tokio::fs::read_to_string("/proc/sys/user/max_user_namespaces").await {
+        if max.trim() == "0" {
+            return Some("`user.max_user_namespaces` is `0`".into());
+        }
+    }
+    // Catches what the files don't, like a container's seccomp filter, but only means something as root
+    if !nix::unistd::geteuid().is_root() {
+        return None;
+    }
+    let status = tokio::process::Command::new("unshare")
+        .args(["--user", "--mount", "--pid", "--fork", "true"])
+        .stdin(std::process::Stdio::null())
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .status()
+        .await;
+    match status {
+        Ok(status) if !status.success() => Some(
+            "creating user, mount, and PID namespaces failed (like in a container without `--privileged`)".into(),
+        ),
+        // Without `unshare` there is nothing more to check
+        Ok(_) | Err(_) => None,
+    }
+}
+
+fn check_open_files() -> Option<Problem> {
+    use nix::sys::resource::{getrlimit, Resource};
+
+    let (_soft, hard) = getrlimit(Resource::RLIMIT_NOFILE).ok()?;
+    if hard == nix::libc::RLIM_INFINITY || hard >= MIN_OPEN_FILES {
+        return None;
+    }
+    Some(Problem {
+        check: Check::OpenFiles,
+        problem: format!(
+            "The hard limit of open files is {hard}, builds of larger packages need at least {MIN_OPEN_FILES}"
+        ),
+        fix: format!("Raise the `nofile` limit (like in `/etc/security/limits.conf`) to at least {MIN_OPEN_FILES}"),
+    })
+}
+
+async fn check_existing_nix() -> Vec<Problem> {
+    let mut problems = vec![];
+
+    if let Ok(mut entries) = tokio::fs::read_dir(NIX_DIR).await {
+        let mut names = vec![];
+        while let Ok(Some(entry)) = entries.next_entry().await {
+            names.push(format!("`{}`", entry.file_name().to_string_lossy()));
+        }
+        if !names.is_empty() {
+            names.sort();
+            problems.push(Problem {
+                check: Check::ExistingNix,
+                problem: format!("`{NIX_DIR}` is not empty, it has {}", names.join(", ")),
+                fix: format!(
+                    "Uninstall the Nix it's from (`/nix/nix-installer uninstall` for installs by `nix-installer`), or move `{NIX_DIR}` aside"
+                ),
+            });
+        }
+    }
+
+    if let Ok(mounts) = tokio::fs::read_to_string("/proc/mounts").await {
+        for mount_point in mounts_inside_nix(&mounts) {
+            problems.push(Problem {
+                check: Check::ExistingNix,
+                problem: format!("`{mount_point}` is mounted inside `{NIX_DIR}`"),
+                fix: format!("Unmount `{mount_point}` (and remove it from `/etc/fstab`)"),
+            });
+        }
+    }
+
+    problems
+}
+
+/// The mount points in a `/proc/mounts` which are inside (not on) `/nix`
+fn mounts_inside_nix(mounts: &str) -> Vec<&str> {
+    mounts
+        .lines()
+        .filter_map(|mount| mount.split_whitespace().nth(1))
+        .filter(|mount_point| {
+            mount_point
+                .strip_prefix(NIX_DIR)
+                .is_some_and(|rest| rest.starts_with('/'))
+        })
+        .collect()
+}
+
+fn check_clock(now: SystemTime, server_time: Option<SystemTime>) -> Option<Problem> {
+    let fix = if cfg!(target_os = "macos") {
+        "Set the clock, like with `sudo sntp -sS time.apple.com`"
+    } else {
+        "Set the clock, like with `timedatectl set-ntp true`"
+    };
+    if now < UNIX_EPOCH + Duration::from_secs(EARLIEST_PLAUSIBLE_TIME) {
+        return Some(Problem {
+            check: Check::Clock,
+            problem: format!(
+                "The clock is set to {} seconds since the epoch, before this `nix-installer` was released",
+                now.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs()
+            ),
+            fix: fix.into(),
+        });
+    }
+    let server_time = server_time?;
+    let skew = match now.duration_since(server_time) {
+        Ok(skew) => skew,
+        Err(err) => err.duration(),
+    };
+    if skew <= MAX_CLOCK_SKEW {
+        return None;
+    }
+    Some(Problem {
+        check: Check::Clock,
+        problem: format!(
+            "The clock is {} seconds off from the package server's",
+            skew.as_secs()
+        ),
+        fix: fix.into(),
+    })
+}
+
+/// Check the Nix package can be fetched, returning the server's time if it said
+async fn check_network(
+    url: &Url,
+    settings: &CommonSettings,
+) -> Result<Option<SystemTime>, Problem> {
+    let unreachable = |err: String| {
+        Problem {
+        check: Check::Network,
+        problem: format!("Could not reach `{url}`: {err}"),
+        fix: "Check the network connection (and `--proxy` or `--ssl-cert-file`), or fetch the package elsewhere and pass it with `--nix-package-file`".into(),
+    }
+    };
+
+    let mut buildable_client = reqwest::Client::builder().timeout(NETWORK_TIMEOUT);
+    if let Some(proxy) = &settings.proxy {
+        if !matches!(proxy.scheme(), "https" | "http" | "socks5" | "socks5h") {
+            return Err(unreachable(format!(
+                "the proxy scheme `{}` is not supported",
+                proxy.scheme()
+            )));
+        }
+        let proxy = reqwest::Proxy::all(proxy.clone())
+            .map_err(|err| unreachable(err.to_string()))?
+            .no_proxy(reqwest::NoProxy::from_env());
+        buildable_client = buildable_client.proxy(proxy);
+    }
+    if let Some(ssl_cert_file) = &settings.ssl_cert_file {
+        let ssl_cert = parse_ssl_cert(ssl_cert_file)
+            .await
+            .map_err(|err| unreachable(err.to_string()))?;
+        buildable_client = buildable_client.add_root_certificate(ssl_cert);
+    }
+    let client = buildable_client
+        .build()
+        .map_err(|err| unreachable(err.to_string()))?;
+
+    let response = client
+        .head(url.clone())
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+        .map_err(|err| unreachable(err.to_string()))?;
+    Ok(response
+        .headers()
+        .get(reqwest::header::DATE)
+        .and_then(|date| date.to_str().ok())
+        .and_then(parse_http_date))
+}
+
+/// Parse an HTTP `Date` header, like `Sun, 06 Nov 1994 08:49:37 GMT` (the only format servers may send)
+fn parse_http_date(date: &str) -> Option<SystemTime> {
+    let mut fields = date.split_whitespace().skip(1);
+    let day: u64 = fields.next()?.parse().ok()?;
+    let month = fields.next()?;
+    let month = MONTHS.iter().position(|name| *name == month)? as u64 + 1;
+    let year: u64 = fields.next()?.parse().ok()?;
+    let mut time = fields.next()?.split(':').map(|part| part.parse::<u64>());
+    let (hours, minutes, seconds) = (time.next()?.ok()?, time.next()?.ok()?, time.next()?.ok()?);
+    if fields.next()? != "GMT" || year < 1970 || !(1..=31).contains(&day) {
+        return None;
+    }
+
+    // Days since the epoch of the civil date, from Howard Hinnant's `days_from_civil`
+    let (year, month) = if month <= 2 {
+        (year - 1, month + 9)
+    } else {
+        (year, month - 3)
+    };
+    let era = year / 400;
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * month + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = (era * 146097 + day_of_era).checked_sub(719468)?;
+
+    Some(UNIX_EPOCH + Duration::from_secs(days * 86400 + hours * 3600 + minutes * 60 + seconds))
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use super::{check_clock, mounts_inside_nix, parse_http_date, Check};
+
+    #[test]
+    fn parses_http_dates() {
+        assert_eq!(
+            parse_http_date("Sun, 06 Nov 1994 08:49:37 GMT"),
+            Some(UNIX_EPOCH + Duration::from_secs(784111777))
+        );
+        assert_eq!(
+            parse_http_date("Thu, 29 Feb 2024 00:00:00 GMT"),
+            Some(UNIX_EPOCH + Duration::from_secs(1709164800))
+        );
+        assert_eq!(parse_http_date("Sunday, 06-Nov-94 08:49:37 GMT"), None);
+        assert_eq!(parse_http_date("Sun, 06 Nov 1994 08:49:37 PST"), None);
+    }
+
+    #[test]
+    fn finds_a_skewed_clock() {
+        let server = UNIX_EPOCH + Duration::from_secs(1_750_000_000);
+        assert!(check_clock(server + Duration::from_secs(60), Some(server)).is_none());
+        let behind = check_clock(server - Duration::from_secs(3600), Some(server));
+        assert_eq!(behind.map(|problem| problem.check), Some(Check::Clock));
+        assert!(check_clock(UNIX_EPOCH + Duration::from_secs(86400), None).is_some());
+    }
+
+    #[test]
+    fn finds_mounts_inside_nix() {
+        let mounts = "\
+            /dev/sda1 / ext4 rw 0 0\n\
+            /dev/sda2 /nix ext4 rw 0 0\n\
+            /dev/sda3 /nix/store ext4 ro 0 0\n\
+            tmpfs /nixos tmpfs rw 0 0\n";
+        assert_eq!(mounts_inside_nix(mounts), vec!["/nix/store"]);
+    }
+}
@@ -0,0 +1,190 @@
+/*! Structured progress of an install, reported by the actions as they run
+
+Nothing is reported until a receiver is registered with [`set_progress_sender`], or a callback
+with [`set_progress_callback`]:
+
+```rust
+use nix_installer::progress::{set_progress_sender, ProgressEvent};
+
+let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+set_progress_sender(tx);
+// ... `InstallPlan::install` ...
+while let Ok(event) = rx.try_recv() {
+    if let ProgressEvent::ActionCompleted { completed, total } = event {
+        println!("{completed}/{total} actions complete");
+    }
+}
+```
+
+A callback suits GUIs and daemons without a Tokio task to spare for the receiver:
+
+```rust
+use nix_installer::progress::{set_progress_callback, ProgressEvent};
+
+set_progress_callback(|event| {
+    if let ProgressEvent::ActionStarted { synopsis } = event {
+        eprintln!("{synopsis}");
+    }
+});
+```
+*/
+
+use std::sync::{Arc, RwLock};
+
+use tokio::sync::mpsc::UnboundedSender;
+
+static PROGRESS_SENDER: RwLock<Option<Sink>> = RwLock::new(None);
+
+/// Where the [`ProgressEvent`]s go
+enum Sink {
+    Channel(UnboundedSender<ProgressEvent>),
+    Callback(Arc<dyn Fn(ProgressEvent) + Send + Sync>),
+}
+
+#[non_exhaustive]
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum ProgressEvent {
+    /// An action of the plan started
+    ActionStarted { synopsis: String },
+    /// `completed` of the `total` actions of the plan have finished
+    ActionCompleted { completed: usize, total: usize },
+    /// An action of the plan started being reverted
+    RevertStarted { synopsis: String },
+    /// `reverted` of the `total` actions of the plan have been reverted (or failed to)
+    RevertCompleted { reverted: usize, total: usize },
+    /// `bytes` of a download have been received, of `total` if the server gave a length
+    Downloaded { bytes: u64, total: Option<u64> },
+    /// `bytes` of the `total` of a (compressed) archive have been unpacked
+    Unpacked { bytes: u64, total: u64 },
+}
+
+/// Send the [`ProgressEvent`]s of every install (or uninstall) after this to `sender`
+pub fn set_progress_sender(sender: UnboundedSender<ProgressEvent>) {
+    let mut current = PROGRESS_SENDER
+        .write()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    *current = Some(Sink::Channel(sender));
+}
+
+/// Call `callback` with the [`ProgressEvent`]s of every install (or uninstall) after this
+///
+/// It is called on whichever thread the action reporting runs on, so should return quickly.
+/// Replaces any sender given to [`set_progress_sender`], and the other way around.
+pub fn set_progress_callback(callback: impl Fn(ProgressEvent) + Send + Sync + 'static) {
+    let mut current = PROGRESS_SENDER
+        .write()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    *current = Some(Sink::Callback(Arc::new(callback)));
+}
+
+/// Stop reporting progress, closing the channel given to [`set_progress_sender`] (or dropping
+/// the callback given to [`set_progress_callback`])
+pub fn clear_progress_sender() {
+    let mut current = PROGRESS_SENDER
+        .write()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    *current = None;
+}
+
+pub(crate) fn report(event: ProgressEvent) {
+    let current = PROGRESS_SENDER
+        .read()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    match current.as_ref() {
+        // The receiver going away only means nobody is watching
+        Some(Sink::Channel(sender)) => {
+            sender.send(event).ok();
+        },
+        Some(Sink::Callback(callback)) => {
+            // Not under the lock, so the callback may itself clear or replace the sink
+            let callback = callback.clone();
+            drop(current);
+            callback(event)
+        },
+        None => (),
+    }
+}
+
+/// A reader which reports [`ProgressEvent::Unpacked`] as the archive it wraps is read
+pub(crate) struct UnpackProgress<R> {
+    inner: R,
+    read: u64,
+    total: u64,
+    /// The `read` last reported, so every small read isn't
+    reported: u64,
+}
+
+impl<R> UnpackProgress<R> {
+    /// Report every 1/256th of the archive
+    const STEPS: u64 = 256;
+
+    pub(crate) fn new(inner: R, total: u64) -> Self {
+        Self {
+            inner,
+            read: 0,
+            total,
+            reported: 0,
+        }
+    }
+}
+
+impl<R: std::io::Read> std::io::Read for UnpackProgress<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.read += read as u64;
+        if read == 0 || self.read - self.reported >= (self.total / Self::STEPS).max(1) {
+            self.reported = self.read;
+            report(ProgressEvent::Unpacked {
+                bytes: self.read,
+                total: self.total,
+            });
+        }
+        Ok(read)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Read;
+    use std::sync::{Arc, Mutex};
+
+    use super::{
+        clear_progress_sender, report, set_progress_callback, ProgressEvent, UnpackProgress,
+    };
+
+    #[test]
+    fn unpack_progress_reads_through() -> std::io::Result<()> {
+        let data = vec![7u8; 4096];
+        let mut reader = UnpackProgress::new(data.as_slice(), data.len() as u64);
+        let mut buf = vec![];
+        reader.read_to_end(&mut buf)?;
+        assert_eq!(buf, data);
+        assert_eq!(reader.read, 4096);
+        assert_eq!(reader.reported, 4096);
+        Ok(())
+    }
+
+    #[test]
+    fn progress_callback_is_called() {
+        let seen = Arc::new(Mutex::new(vec![]));
+        let seen_by_callback = seen.clone();
+        set_progress_callback(move |event| {
+            // Other tests may report progress at the same time
+            if let ProgressEvent::ActionStarted { synopsis } = event {
+                seen_by_callback.lock().unwrap().push(synopsis);
+            }
+        });
+        report(ProgressEvent::ActionStarted {
+            synopsis: "progress_callback_is_called".into(),
+        });
+        clear_progress_sender();
+        report(ProgressEvent::ActionStarted {
+            synopsis: "after clearing".into(),
+        });
+        assert_eq!(
+            *seen.lock().unwrap(),
+            vec!["progress_callback_is_called".to_string()]
+        );
+    }
+}
@@ -0,0 +1,203 @@
+/*! Removing what Nix left in users' homes, see `nix-installer uninstall --purge`
+
+Uninstalling reverts what `nix-installer` did, but Nix itself creates things in the home of each user
+who ran it: the `~/.nix-profile` link to their profile, their channels, and caches. [`purge`] removes
+those from the home of every user in the user database, as that user, so nothing of Nix is left behind.
+*/
+
+use std::{
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+    process::Stdio,
+};
+
+use nix::unistd::{Gid, Uid, User};
+use tokio::process::Command;
+
+use crate::{
+    audit::{self, AuditEvent},
+    execute_command,
+};
+
+/// What Nix creates in a user's home
+pub const USER_STATE: &[&str] = &[
+    ".nix-profile",
+    ".nix-defexpr",
+    ".nix-channels",
+    ".cache/nix",
+    ".local/state/nix",
+];
+
+/// Remove the [`USER_STATE`] from the home of every user, returning what was removed
+///
+/// Failing to remove something is only logged, so the rest is still removed.
+#[tracing::instrument(level = "debug")]
+pub async fn purge() -> Vec<PathBuf> {
+    let mut removed = vec![];
+    for user in users().await {
+        removed.extend(purge_home(&user.dir, user.uid, user.gid).await);
+    }
+    removed
+}
+
+/// What [`purge`] would remove, without removing it
+pub async fn purgeable() -> Vec<PathBuf> {
+    let mut found = vec![];
+    for home in homes().await {
+        for state in USER_STATE {
+            let path = home.join(state);
+            if tokio::fs::symlink_metadata(&path).await.is_ok() {
+                found.push(path);
+            }
+        }
+    }
+    found
+}
+
+/// The home of every user with one of their own, see [`users`]
+pub(crate) async fn homes() -> Vec<PathBuf> {
+    users().await.into_iter().map(|user| user.dir).collect()
+}
+
+/// Every user (root included) in the user database whose home is a directory they own
+///
+/// Homes which are links, or shared by system users (like `/var/empty`), are left out.
+async fn users() -> Vec<User> {
+    let mut users: Vec<User> = vec![];
+    for name in user_names().await {
+        let user = match User::from_name(&name) {
+            Ok(Some(user)) => user,
+            Ok(None) => continue,
+            Err(err) => {
+                tracing::warn!("Error getting the user `{name}`: {err}");
+                continue;
+            },
+        };
+        let owned = tokio::fs::symlink_metadata(&user.dir)
+            .await
+            .is_ok_and(|metadata| metadata.is_dir() && metadata.uid() == user.uid.as_raw());
+        if !owned || user.dir == Path::new("/") || users.iter().any(|other| other.dir == user.dir) {
+            continue;
+        }
+        users.push(user);
+    }
+    users
+}
+
+/// The name of every user in the user database, from `dscl` on macOS, or `getent passwd` (falling back to `/etc/passwd`)
+async fn user_names() -> Vec<String> {
+    let mut command = if cfg!(target_os = "macos") {
+        let mut command = Command::new("/usr/bin/dscl");
+        command.args([".", "-list", "/Users"]);
+        command
+    } else {
+        let mut command = Command::new("getent");
+        command.arg("passwd");
+        command
+    };
+    let listing = match command.stdin(Stdio::null()).output().await {
+        Ok(output) if output.status.success() => {
+            String::from_utf8_lossy(&output.stdout).into_owned()
+        },
+        _ => tokio::fs::read_to_string("/etc/passwd")
+            .await
+            .unwrap_or_default(),
+    };
+    parse_user_names(&listing)
+}
+
+/// The names in `passwd` lines (or the plain names `dscl` lists)
+fn parse_user_names(listing: &str) -> Vec<String> {
+    listing
+        .lines()
+        .filter_map(|line| line.split(':').next())
+        .map(str::trim)
+        .filter(|name| !name.is_empty() && !name.starts_with('#'))
+        .map(String::from)
+        .collect()
+}
+
+/// Remove the [`USER_STATE`] from `home`, as the user `uid` (in the group `gid`) who owns it
+///
+/// Removing as its owner means a link placed in `home` (like `~/.cache` to elsewhere) only leads
+/// to what they could have removed themselves.
+async fn purge_home(home: &Path, uid: Uid, gid: Gid) -> Vec<PathBuf> {
+    let mut removed = vec![];
+    for state in USER_STATE {
+        let path = home.join(state);
+        // Not following links, `~/.nix-profile` is one into the (removed) store
+        let Ok(metadata) = tokio::fs::symlink_metadata(&path).await else {
+            continue;
+        };
+        let res = execute_command(
+            Command::new("rm")
+                .process_group(0)
+                .args(["-rf", "--"])
+                .arg(&path)
+                .uid(uid.as_raw())
+                .gid(gid.as_raw())
+                .stdin(Stdio::null()),
+        )
+        .await;
+        match res {
+            Ok(_) => {
+                audit::record(if metadata.is_dir() {
+                    AuditEvent::DirectoryRemoved { path: path.clone() }
+                } else {
+                    AuditEvent::FileRemoved { path: path.clone() }
+                });
+                removed.push(path);
+            },
+            Err(err) => tracing::warn!("Error removing `{}`: {:?}", path.display(), err),
+        }
+    }
+    removed
+}
+
+#[cfg(test)]
+mod test {
+    use nix::unistd::{Gid, Uid};
+
+    use super::{parse_user_names, purge_home};
+
+    #[tokio::test]
+    async fn purges_a_home() -> eyre::Result<()> {
+        let home = tempfile::tempdir()?;
+        tokio::fs::create_dir_all(home.path().join(".cache/nix/eval-cache-v5")).await?;
+        tokio::fs::write(home.path().join(".nix-channels"), "").await?;
+        tokio::fs::symlink(
+            "/nix/var/nix/profiles/per-user/ana/profile",
+            home.path().join(".nix-profile"),
+        )
+        .await?;
+        tokio::fs::write(home.path().join(".profile"), "").await?;
+
+        let mut removed = purge_home(home.path(), Uid::current(), Gid::current()).await;
+        removed.sort();
+        assert_eq!(
+            removed,
+            vec![
+                home.path().join(".cache/nix"),
+                home.path().join(".nix-channels"),
+                home.path().join(".nix-profile"),
+            ]
+        );
+        assert!(home.path().join(".cache").exists());
+        assert!(home.path().join(".profile").exists());
+        Ok(())
+    }
+
+    #[test]
+    fn parses_user_names() {
+        assert_eq!(
+            parse_user_names(
+                "root:x:0:0:root:/root:/bin/bash\nana:x:1000:1000::/home/ana:/bin/zsh\n"
+            ),
+            vec!["root", "ana"]
+        );
+        assert_eq!(
+            parse_user_names("# A comment\n_www\nana\n"),
+            vec!["_www", "ana"]
+        );
+    }
+}
@@ -0,0 +1,383 @@
+/*! Signing the receipt, so a tampered one isn't trusted to say what to undo as root
+
+Receipts are only signed when asked to, with `--sign-receipt` (which generates a key for this
+machine at [`MACHINE_KEY_LOCATION`], readable only by root, and kept outside of `/nix` beside the
+copy of the receipt so the copy can still be checked once `/nix` is lost) or with `--receipt-key` (a key kept
+elsewhere, like a secrets manager, given again whenever the receipt is read or rewritten). Each time
+the receipt is written, an HMAC-SHA256 of it is written beside it, like `/nix/receipt.json.sig`:
+
+```text
+hmac-sha256 4f1d...
+```
+
+Before an uninstall (or repair, or rollback) acts on a receipt, the signature is checked. A receipt
+which doesn't match its signature, or which has none while a key is known, is refused. Once a
+receipt was signed, [`SIGNED_MARKER_LOCATION`] records it, so removing both the signature and the
+key doesn't make the receipt pass as one which was never signed.
+*/
+
+use std::{
+    io::Write,
+    os::unix::fs::{DirBuilderExt, OpenOptionsExt},
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use sha2::Sha256;
+
+/// Where the key generated by `--sign-receipt` is kept, it is removed once the install is reverted
+pub const MACHINE_KEY_LOCATION: &str = "/var/lib/nix-installer/receipt.key";
+/// Written once a receipt is signed, after which a receipt without a signature (or a key to check it) is refused
+pub const SIGNED_MARKER_LOCATION: &str = "/var/lib/nix-installer/receipt.signed";
+/// Where the key generated by `--sign-receipt` was kept by earlier versions
+const LEGACY_MACHINE_KEY_LOCATION: &str = "/nix/receipt.key";
+const ALGORITHM: &str = "hmac-sha256";
+const MACHINE_KEY_LEN: usize = 32;
+const MIN_KEY_LEN: usize = 16;
+
+static SETTINGS: Mutex<Settings> = Mutex::new(Settings {
+    key: None,
+    skip_verification: false,
+});
+
+#[derive(Debug)]
+struct Settings {
+    key: Option<SigningKey>,
+    skip_verification: bool,
+}
+
+/// The key receipts are signed with, see [`set_receipt_signing`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SigningKey {
+    /// The key at [`MACHINE_KEY_LOCATION`], generated the first time a receipt is signed
+    Machine,
+    /// A key provided by the user
+    Provided(Vec<u8>),
+}
+
+/// What checking a receipt's signature found
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verification {
+    Verified,
+    /// The receipt has no signature, and it was never signed nor asked to be
+    Unsigned,
+    /// Checking was skipped with `--skip-receipt-verification`
+    Skipped,
+}
+
+/// Sign each receipt written after this with `key` (if any), and unless `skip_verification` check the signature of each receipt read
+pub fn set_receipt_signing(key: Option<SigningKey>, skip_verification: bool) {
+    let mut current = SETTINGS
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    *current = Settings {
+        key,
+        skip_verification,
+    };
+}
+
+/// Read a key provided with `--receipt-key`
+pub fn read_key(path: &Path) -> Result<SigningKey, ReceiptSignatureError> {
+    let key =
+        std::fs::read(path).map_err(|e| ReceiptSignatureError::ReadKey(path.to_path_buf(), e))?;
+    if key.len() < MIN_KEY_LEN {
+        return Err(ReceiptSignatureError::KeyTooShort(path.to_path_buf()));
+    }
+    Ok(SigningKey::Provided(key))
+}
+
+/// Where the signature of the receipt at `receipt` is kept
+pub fn signature_path(receipt: &Path) -> PathBuf {
+    let mut path = receipt.as_os_str().to_owned();
+    path.push(".sig");
+    PathBuf::from(path)
+}
+
+fn configured() -> (Option<SigningKey>, bool) {
+    let current = SETTINGS
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    (current.key.clone(), current.skip_verification)
+}
+
+/// Where the machine key is, installs signed before it moved out of `/nix` still have it there
+fn machine_key_location() -> &'static Path {
+    let legacy = Path::new(LEGACY_MACHINE_KEY_LOCATION);
+    if !Path::new(MACHINE_KEY_LOCATION).exists() && legacy.exists() {
+        legacy
+    } else {
+        Path::new(MACHINE_KEY_LOCATION)
+    }
+}
+
+/// The key to sign (or check) with, a receipt signed with the machine key at `machine_key` before stays signed with it
+async fn key_at(
+    machine_key: &Path,
+    generate: bool,
+) -> Result<Option<Vec<u8>>, ReceiptSignatureError> {
+    let (configured, _) = configured();
+    if let Some(SigningKey::Provided(key)) = configured {
+        return Ok(Some(key));
+    }
+    match tokio::fs::read(machine_key).await {
+        Ok(key) => Ok(Some(key)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            if generate && configured == Some(SigningKey::Machine) {
+                generate_machine_key(machine_key).map(Some)
+            } else {
+                Ok(None)
+            }
+        },
+        Err(e) => Err(ReceiptSignatureError::ReadKey(machine_key.to_path_buf(), e)),
+    }
+}
+
+fn generate_machine_key(machine_key: &Path) -> Result<Vec<u8>, ReceiptSignatureError> {
+    let mut key = vec![0; MACHINE_KEY_LEN];
+    rand::thread_rng().fill_bytes(&mut key);
+    if let Some(parent) = machine_key.parent() {
+        std::fs::DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(parent)
+            .map_err(|e| ReceiptSignatureError::WriteKey(machine_key.to_path_buf(), e))?;
+    }
+    std::fs::OpenOptions::new()
+        .create_new(true)
+        .write(true)
+        .mode(0o600)
+        .open(machine_key)
+        .and_then(|mut file| file.write_all(&key))
+        .map_err(|e| ReceiptSignatureError::WriteKey(machine_key.to_path_buf(), e))?;
+    tracing::debug!(
+        "Generated a receipt signing key at `{}`",
+        machine_key.display()
+    );
+    Ok(key)
+}
+
+fn mac(key: &[u8], contents: &[u8]) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(contents);
+    mac
+}
+
+pub(crate) fn signature(key: &[u8], contents: &[u8]) -> String {
+    let tag = mac(key, contents).finalize().into_bytes();
+    format!("{ALGORITHM} {tag:x}\n")
+}
+
+/// Sign the receipt just written to `receipt`, if there is a key to sign it with
+#[tracing::instrument(level = "debug")]
+pub(crate) async fn sign(receipt: &Path) -> Result<(), ReceiptSignatureError> {
+    let signature_path = signature_path(receipt);
+    let Some(key) = key_at(machine_key_location(), true).await? else {
+        if Path::new(SIGNED_MARKER_LOCATION).exists() {
+            return Err(ReceiptSignatureError::NoKey(receipt.to_path_buf()));
+        }
+        // A signature of what the receipt used to be would only make it look tampered
+        if tokio::fs::remove_file(&signature_path).await.is_ok() {
+            tracing::warn!(
+                "No key to sign `{}` with was given, it is no longer signed",
+                receipt.display()
+            );
+        }
+        return Ok(());
+    };
+    let contents = tokio::fs::read(receipt)
+        .await
+        .map_err(|e| ReceiptSignatureError::Read(receipt.to_path_buf(), e))?;
+    let signature = signature(&key, &contents);
+    tokio::fs::write(&signature_path, &signature)
+        .await
+        .map_err(|e| ReceiptSignatureError::Write(signature_path.clone(), e))?;
+    crate::audit::file_written(&signature_path, signature.as_bytes());
+    mark_signed(Path::new(SIGNED_MARKER_LOCATION))?;
+    Ok(())
+}
+
+fn mark_signed(signed_marker: &Path) -> Result<(), ReceiptSignatureError> {
+    if signed_marker.exists() {
+        return Ok(());
+    }
+    if let Some(parent) = signed_marker.parent() {
+        std::fs::DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(parent)
+            .map_err(|e| ReceiptSignatureError::Write(signed_marker.to_path_buf(), e))?;
+    }
+    std::fs::OpenOptions::new()
+        .create(true)
+        .truncate(true)
+        .write(true)
+        .mode(0o600)
+        .open(signed_marker)
+        .map_err(|e| ReceiptSignatureError::Write(signed_marker.to_path_buf(), e))?;
+    crate::audit::file_written(signed_marker, b"");
+    Ok(())
+}
+
+/// Check the signature of `contents`, read from `receipt`, before acting on it
+#[tracing::instrument(level = "debug", skip(contents))]
+pub async fn verify(
+    receipt: &Path,
+    contents: &[u8],
+) -> Result<Verification, ReceiptSignatureError> {
+    verify_at(
+        receipt,
+        contents,
+        machine_key_location(),
+        Path::new(SIGNED_MARKER_LOCATION),
+    )
+    .await
+}
+
+/// Check the signature of `contents`, read from `receipt`, with the machine key at `machine_key` (unless one was provided)
+///
+/// A receipt is expected to be signed if a key was configured, or `signed_marker` records one was signed.
+pub(crate) async fn verify_at(
+    receipt: &Path,
+    contents: &[u8],
+    machine_key: &Path,
+    signed_marker: &Path,
+) -> Result<Verification, ReceiptSignatureError> {
+    let (configured, skip_verification) = configured();
+    if skip_verification {
+        tracing::warn!(
+            "Not checking the signature of `{}`, it is trusted as is",
+            receipt.display()
+        );
+        return Ok(Verification::Skipped);
+    }
+
+    let signature_path = signature_path(receipt);
+    let signature = match tokio::fs::read_to_string(&signature_path).await {
+        Ok(signature) => Some(signature),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+        Err(e) => return Err(ReceiptSignatureError::Read(signature_path, e)),
+    };
+    let key = key_at(machine_key, false).await?;
+
+    match (signature, key) {
+        (None, None) if configured.is_some() || signed_marker.exists() => {
+            Err(ReceiptSignatureError::Expected(receipt.to_path_buf()))
+        },
+        (None, None) => {
+            tracing::debug!("`{}` is not signed", receipt.display());
+            Ok(Verification::Unsigned)
+        },
+        (None, Some(_)) => Err(ReceiptSignatureError::Missing(receipt.to_path_buf())),
+        (Some(_), None) => Err(ReceiptSignatureError::NoKey(receipt.to_path_buf())),
+        (Some(signature), Some(key)) => {
+            let tag = signature
+                .trim()
+                .strip_prefix(ALGORITHM)
+                .map(str::trim)
+                .and_then(decode_hex)
+                .ok_or_else(|| ReceiptSignatureError::Malformed(signature_path.clone()))?;
+            mac(&key, contents)
+                .verify_slice(&tag)
+                .map_err(|_| ReceiptSignatureError::Mismatch(receipt.to_path_buf()))?;
+            tracing::debug!("Verified the signature of `{}`", receipt.display());
+            Ok(Verification::Verified)
+        },
+    }
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|index| u8::from_str_radix(hex.get(index..index + 2)?, 16).ok())
+        .collect()
+}
+
+#[non_exhaustive]
+#[derive(Debug, thiserror::Error)]
+pub enum ReceiptSignatureError {
+    #[error("The receipt `{0}` does not match its signature, it may have been tampered with. If it was changed on purpose, pass `--skip-receipt-verification` to trust it anyway")]
+    Mismatch(PathBuf),
+    #[error("The receipt `{0}` is not signed, but a key to sign it with exists, it may have been tampered with. If it was changed on purpose, pass `--skip-receipt-verification` to trust it anyway")]
+    Missing(PathBuf),
+    #[error("The receipt `{0}` was signed, but its signature and the key to check it with are gone, it may have been tampered with. If it was changed on purpose, pass `--skip-receipt-verification` to trust it anyway")]
+    Expected(PathBuf),
+    #[error("The receipt `{0}` is signed, pass the key it was signed with as `--receipt-key`")]
+    NoKey(PathBuf),
+    #[error("The receipt signature `{0}` could not be read, it should be `{ALGORITHM}` and a hex encoded signature")]
+    Malformed(PathBuf),
+    #[error(
+        "The receipt signing key `{0}` is too short, it should be at least {MIN_KEY_LEN} bytes"
+    )]
+    KeyTooShort(PathBuf),
+    #[error("Reading the receipt signing key `{0}`")]
+    ReadKey(PathBuf, #[source] std::io::Error),
+    #[error("Writing the receipt signing key `{0}`")]
+    WriteKey(PathBuf, #[source] std::io::Error),
+    #[error("Reading `{0}`")]
+    Read(PathBuf, #[source] std::io::Error),
+    #[error("Writing `{0}`")]
+    Write(PathBuf, #[source] std::io::Error),
+}
+
+#[cfg(test)]
+mod test {
+    use hmac::Mac;
+
+    use super::{
+        decode_hex, mac, signature, signature_path, verify_at, ReceiptSignatureError, Verification,
+    };
+
+    #[test]
+    fn signatures_only_match_their_receipt() {
+        let key = b"a key which is long enough";
+        let receipt = br#"{"version":"0.27.0","actions":[]}"#;
+        let signature = signature(key, receipt);
+        let tag = decode_hex(
+            signature
+                .trim()
+                .strip_prefix("hmac-sha256 ")
+                .expect("has the algorithm"),
+        )
+        .expect("is hex");
+
+        assert!(mac(key, receipt).verify_slice(&tag).is_ok());
+        assert!(mac(key, br#"{"version":"0.27.0","actions":[{}]}"#)
+            .verify_slice(&tag)
+            .is_err());
+        assert!(mac(b"a different key entirely", receipt)
+            .verify_slice(&tag)
+            .is_err());
+        assert_eq!(
+            signature_path("/nix/receipt.json".as_ref()),
+            std::path::PathBuf::from("/nix/receipt.json.sig")
+        );
+    }
+
+    #[tokio::test]
+    async fn refuses_a_signed_receipt_stripped_of_its_signature_and_key() -> eyre::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let receipt = temp_dir.path().join("receipt.json");
+        let machine_key = temp_dir.path().join("receipt.key");
+        let signed_marker = temp_dir.path().join("receipt.signed");
+        let contents = br#"{"version":"0.27.0","actions":[]}"#;
+        std::fs::write(&receipt, contents)?;
+
+        assert_eq!(
+            verify_at(&receipt, contents, &machine_key, &signed_marker).await?,
+            Verification::Unsigned
+        );
+
+        // Signed once, then both the signature and the key were removed
+        std::fs::write(&signed_marker, "")?;
+        match verify_at(&receipt, contents, &machine_key, &signed_marker).await {
+            Err(ReceiptSignatureError::Expected(path)) => assert_eq!(path, receipt),
+            res => panic!("Expected the receipt to be refused, got {res:?}"),
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,108 @@
+//! Discovering the released versions of a [`Distribution`]
+
+use std::path::Path;
+
+use url::Url;
+
+use crate::{
+    parse_ssl_cert,
+    settings::{parse_nix_version, Distribution},
+    CertificateError,
+};
+
+#[non_exhaustive]
+#[derive(thiserror::Error, Debug)]
+pub enum ReleasesError {
+    #[error("Request error")]
+    Reqwest(#[from] reqwest::Error),
+    #[error(transparent)]
+    Certificate(#[from] CertificateError),
+    #[error(
+        "Unknown proxy scheme, `https://`, `socks5://`, `socks5h://`, and `http://` supported"
+    )]
+    UnknownProxyScheme,
+}
+
+/// Fetch the versions of `distribution` which have been released, oldest first
+#[tracing::instrument(level = "debug", skip_all, fields(%distribution))]
+pub async fn list_versions(
+    distribution: Distribution,
+    proxy: Option<&Url>,
+    ssl_cert_file: Option<&Path>,
+) -> Result<Vec<String>, ReleasesError> {
+    let mut buildable_client = reqwest::Client::builder();
+    if let Some(proxy) = proxy {
+        match proxy.scheme() {
+            "https" | "http" | "socks5" | "socks5h" => {},
+            _ => return Err(ReleasesError::UnknownProxyScheme),
+        };
+        let proxy = reqwest::Proxy::all(proxy.clone())?.no_proxy(reqwest::NoProxy::from_env());
+        buildable_client = buildable_client.proxy(proxy)
+    }
+    if let Some(ssl_cert_file) = ssl_cert_file {
+        let ssl_cert = parse_ssl_cert(ssl_cert_file).await?;
+        buildable_client = buildable_client.add_root_certificate(ssl_cert);
+    }
+    let client = buildable_client.build()?;
+
+    let listing = client
+        .get(distribution.releases_url())
+        .send()
+        .await?
+        .error_for_status()?
+        .text()
+        .await?;
+
+    Ok(parse_versions(distribution.package_name(), &listing))
+}
+
+/// Find every `$PACKAGE_NAME-$VERSION/` entry in a release listing, oldest first
+///
+/// Both the S3 bucket listing (XML) and the Lix index page (HTML) name each release this way.
+pub(crate) fn parse_versions(package_name: &str, listing: &str) -> Vec<String> {
+    let prefix = format!("{package_name}-");
+    let mut versions = listing
+        .match_indices(&prefix)
+        .filter_map(|(index, _)| {
+            let rest = &listing[index + prefix.len()..];
+            let (version, _) = rest.split_once('/')?;
+            parse_nix_version(version).ok()
+        })
+        .collect::<Vec<_>>();
+
+    versions.sort_by_key(|version| {
+        version
+            .split('.')
+            .map(|part| part.parse::<u64>().unwrap_or_default())
+            .collect::<Vec<_>>()
+    });
+    versions.dedup();
+    versions
+}
+
+#[cfg(test)]
+mod test {
+    use super::parse_versions;
+
+    #[test]
+    fn parses_s3_listing() {
+        let listing =
+            "<ListBucketResult><CommonPrefixes><Prefix>nix/nix-2.9.1/</Prefix></CommonPrefixes>\
+            <CommonPrefixes><Prefix>nix/nix-2.24.9/</Prefix></CommonPrefixes>\
+            <CommonPrefixes><Prefix>nix/nix-2.24.9/</Prefix></CommonPrefixes>\
+            <CommonPrefixes><Prefix>nix/nix-2.25pre20241001_abcdef/</Prefix></CommonPrefixes>\
+            <CommonPrefixes><Prefix>nix/nix-1.11/</Prefix></CommonPrefixes></ListBucketResult>";
+        assert_eq!(
+            parse_versions("nix", listing),
+            vec!["1.11", "2.9.1", "2.24.9"]
+        );
+    }
+
+    #[test]
+    fn parses_lix_index() {
+        let listing = r#"<a href="lix-2.91.1/">lix-2.91.1/</a>
+<a href="lix-2.90.0/">lix-2.90.0/</a>
+<a href="lix-2.91.1-rc1/">lix-2.91.1-rc1/</a>"#;
+        assert_eq!(parse_versions("lix", listing), vec!["2.90.0", "2.91.1"]);
+    }
+}
@@ -1,8 +1,43 @@
-use std::{process::Output, time::SystemTime};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    process::Output,
+    sync::Mutex,
+    time::{Duration, SystemTime},
+};
 
 use tokio::process::Command;
 use which::which;
 
+use crate::{
+    action::common::provision_determinate_nixd::DETERMINATE_NIXD_BINARY_PATH,
+    manifest::NIX_BIN,
+    settings::{Distribution, InitSystem, SYSTEM},
+};
+
+static SHELLS: Mutex<ShellSelection> = Mutex::new(ShellSelection {
+    only: None,
+    skip: vec![],
+});
+
+/// Which shells are tested, see [`set_self_test_shells`]
+#[derive(Debug)]
+struct ShellSelection {
+    only: Option<Vec<Shell>>,
+    skip: Vec<Shell>,
+}
+
+/// Only test the `only` shells (if given, otherwise each one found), except those in `skip`, in each self-test after this
+pub fn set_self_test_shells(only: Option<Vec<Shell>>, skip: Vec<Shell>) {
+    let mut current = SHELLS
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    *current = ShellSelection { only, skip };
+}
+
+/// How long a substituter may take to answer, and the daemon to come back after it is killed
+const TIMEOUT: Duration = Duration::from_secs(10);
+
 #[non_exhaustive]
 #[derive(thiserror::Error, Debug, strum::IntoStaticStr)]
 pub enum SelfTestError {
@@ -24,6 +59,69 @@ pub enum SelfTestError {
     },
     #[error(transparent)]
     SystemTime(#[from] std::time::SystemTimeError),
+    #[error("Expected {expected} Nix to be installed, but `nix --version` reported `{found}`")]
+    WrongDistribution {
+        expected: Distribution,
+        found: String,
+    },
+    #[error("Failed to execute `nix --version`")]
+    Version(#[source] std::io::Error),
+    #[error("Expected Determinate Nix to be installed, but `{0}` is missing")]
+    DeterminateNixdMissing(&'static str),
+    #[error("The Nix daemon didn't answer `nix store ping`, check it is running (`systemctl status nix-daemon.service`, or `launchctl print system/org.nixos.nix-daemon` on macOS), stderr:\n{}", String::from_utf8_lossy(&.0.stderr))]
+    StorePing(Output),
+    #[error("A trivial sandboxed build failed, check `sandbox` in `/etc/nix/nix.conf` (and that user namespaces are enabled, with `sysctl kernel.unprivileged_userns_clone`), stderr:\n{}", String::from_utf8_lossy(&.0.stderr))]
+    SandboxedBuild(Output),
+    #[error("Evaluating a trivial flake failed, check `experimental-features` in `/etc/nix/nix.conf` includes `nix-command flakes`, stderr:\n{}", String::from_utf8_lossy(&.0.stderr))]
+    FlakeEval(Output),
+    #[error("Writing the flake to evaluate to `{0}`")]
+    WriteFlake(std::path::PathBuf, #[source] std::io::Error),
+    #[error("Could not list the configured substituters with `nix config show substituters`, stderr:\n{}", String::from_utf8_lossy(&.0.stderr))]
+    Substituters(Output),
+    #[error("The substituter `{url}` is unreachable, check network access to it (and any proxy), or remove it from `substituters` in `/etc/nix/nix.conf`")]
+    SubstituterUnreachable {
+        url: String,
+        #[source]
+        error: reqwest::Error,
+    },
+    #[error("Killing the Nix daemon failed, stderr:\n{}", String::from_utf8_lossy(&.0.stderr))]
+    KillDaemon(Output),
+    #[error("The Nix daemon didn't come back within {}s of being killed, check its service restarts it (or is socket activated), like with `nix-installer repair`", TIMEOUT.as_secs())]
+    DaemonNotRestarted,
+    #[error("In `{shell}`, `nix` is `{}`, which comes before Nix's own on `PATH`, check what puts `{}` first in the shell's startup files (or `--homebrew-path-order`)", .by.join("nix").display(), .by.display())]
+    NixShadowed { shell: Shell, by: PathBuf },
+    #[error(
+        "The shell `{0}` was asked to be self-tested, but isn't installed (or isn't on `PATH`)"
+    )]
+    ShellMissing(Shell),
+    #[error("Failed to execute `{command}`")]
+    Execute {
+        command: String,
+        #[source]
+        error: std::io::Error,
+    },
+}
+
+impl SelfTestError {
+    /// The part of the install this failure is in, like `daemon` or `substituter`
+    pub fn subsystem(&self) -> &'static str {
+        match self {
+            Self::ShellFailed { .. }
+            | Self::Command { .. }
+            | Self::SystemTime(_)
+            | Self::ShellMissing(_) => "shell",
+            Self::NixShadowed { .. } => "path",
+            Self::WrongDistribution { .. } | Self::Version(_) | Self::DeterminateNixdMissing(_) => {
+                "distribution"
+            },
+            Self::StorePing(_) => "daemon",
+            Self::SandboxedBuild(_) => "sandbox",
+            Self::FlakeEval(_) | Self::WriteFlake(..) => "flakes",
+            Self::Substituters(_) | Self::SubstituterUnreachable { .. } => "substituter",
+            Self::KillDaemon(_) | Self::DaemonNotRestarted => "daemon-restart",
+            Self::Execute { .. } => "nix",
+        }
+    }
 }
 
 #[cfg(feature = "diagnostics")]
@@ -33,7 +131,14 @@ impl crate::diagnostics::ErrorDiagnostic for SelfTestError {
         let context = match self {
             Self::ShellFailed { shell, .. } => vec![shell.to_string()],
             Self::Command { shell, .. } => vec![shell.to_string()],
+            Self::ShellMissing(shell) => vec![shell.to_string()],
+            Self::NixShadowed { shell, .. } => vec![shell.to_string()],
             Self::SystemTime(_) => vec![],
+            Self::WrongDistribution { expected, .. } => vec![expected.to_string()],
+            Self::Version(_) => vec![],
+            Self::DeterminateNixdMissing(_) => vec![],
+            Self::SubstituterUnreachable { url, .. } => vec![url.clone()],
+            _ => vec![],
         };
         format!(
             "{}({})",
@@ -47,7 +152,8 @@ impl crate::diagnostics::ErrorDiagnostic for SelfTestError {
     }
 }
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
 pub enum Shell {
     Sh,
     Bash,
@@ -74,31 +180,21 @@ impl Shell {
         }
     }
 
-    #[tracing::instrument(skip_all)]
-    pub async fn self_test(&self) -> Result<(), SelfTestError> {
-        let executable = self.executable();
-        let mut command = match &self {
+    /// This shell, started the way a user's would be so it reads the Nix hook, to run a command given next
+    fn command(&self) -> Command {
+        let mut command = Command::new(self.executable());
+        match &self {
             // On Mac, `bash -ic nix` won't work, but `bash -lc nix` will.
-            Shell::Sh | Shell::Bash => {
-                let mut command = Command::new(executable);
-                command.arg("-lc");
-                command
-            },
-            Shell::Zsh | Shell::Fish => {
-                let mut command = Command::new(executable);
-                command.arg("-ic");
-                command
-            },
+            Shell::Sh | Shell::Bash => command.arg("-lc"),
+            Shell::Zsh | Shell::Fish => command.arg("-ic"),
         };
+        command
+    }
 
-        #[cfg(all(target_os = "linux", target_arch = "x86_64"))]
-        const SYSTEM: &str = "x86_64-linux";
-        #[cfg(all(target_os = "linux", target_arch = "aarch64"))]
-        const SYSTEM: &str = "aarch64-linux";
-        #[cfg(all(target_os = "macos", target_arch = "x86_64"))]
-        const SYSTEM: &str = "x86_64-darwin";
-        #[cfg(all(target_os = "macos", target_arch = "aarch64"))]
-        const SYSTEM: &str = "aarch64-darwin";
+    #[tracing::instrument(skip_all)]
+    pub async fn self_test(&self) -> Result<(), SelfTestError> {
+        let executable = self.executable();
+        let mut command = self.command();
 
         let timestamp_millis = SystemTime::now()
             .duration_since(SystemTime::UNIX_EPOCH)?
@@ -133,28 +229,129 @@ impl Shell {
         }
     }
 
+    /// The directories on `PATH` in this shell, in order
+    async fn path(&self) -> Result<Vec<PathBuf>, SelfTestError> {
+        let mut command = self.command();
+        command.arg(match &self {
+            Shell::Fish => "string join : $PATH",
+            Shell::Sh | Shell::Bash | Shell::Zsh => "printf %s \"$PATH\"",
+        });
+        let command_str = format!("{:?}", command.as_std());
+        let output = command
+            .stdin(std::process::Stdio::null())
+            .output()
+            .await
+            .map_err(|error| SelfTestError::Command {
+                shell: *self,
+                command: command_str.clone(),
+                error,
+            })?;
+        if !output.status.success() {
+            return Err(SelfTestError::ShellFailed {
+                shell: *self,
+                command: command_str,
+                output,
+            });
+        }
+        // Interactive shells may print more than asked (like a greeting), `PATH` is the last line
+        Ok(String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .last()
+            .unwrap_or_default()
+            .split(':')
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .collect())
+    }
+
+    /// The shells to self-test, each one unless only some are asked for (less those asked to be skipped)
+    pub fn selected() -> Vec<Shell> {
+        let current = SHELLS
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        current
+            .only
+            .as_deref()
+            .unwrap_or(Self::all())
+            .iter()
+            .filter(|shell| !current.skip.contains(shell))
+            .copied()
+            .collect()
+    }
+
+    /// The [`selected`](Self::selected) shells which are installed
     #[tracing::instrument(skip_all)]
     pub fn discover() -> Vec<Shell> {
         let mut found_shells = vec![];
-        for shell in Self::all() {
+        for shell in Self::selected() {
             if which(shell.executable()).is_ok() {
                 tracing::debug!("Discovered `{shell}`");
-                found_shells.push(*shell)
+                found_shells.push(shell)
             }
         }
         found_shells
     }
 }
 
+/// Check the installed `nix` is from the expected [`Distribution`]
 #[tracing::instrument(skip_all)]
-pub async fn self_test() -> Result<(), Vec<SelfTestError>> {
+pub async fn check_distribution(distribution: Distribution) -> Result<(), SelfTestError> {
+    if distribution == Distribution::Determinate
+        && !std::path::Path::new(DETERMINATE_NIXD_BINARY_PATH).exists()
+    {
+        return Err(SelfTestError::DeterminateNixdMissing(
+            DETERMINATE_NIXD_BINARY_PATH,
+        ));
+    }
+
+    let output = Command::new(NIX_BIN)
+        .arg("--version")
+        .output()
+        .await
+        .map_err(SelfTestError::Version)?;
+    let found = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    tracing::debug!(version = found, "Checking Nix distribution");
+    if found.contains(distribution.version_marker()) {
+        Ok(())
+    } else {
+        Err(SelfTestError::WrongDistribution {
+            expected: distribution,
+            found,
+        })
+    }
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn self_test(distribution: Distribution) -> Result<(), Vec<SelfTestError>> {
     let shells = Shell::discover();
 
     let mut failures = vec![];
 
+    let explicit = SHELLS
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .only
+        .is_some();
+    if explicit {
+        for shell in Shell::selected() {
+            if !shells.contains(&shell) {
+                failures.push(SelfTestError::ShellMissing(shell));
+            }
+        }
+    }
+
+    if let Err(err) = check_distribution(distribution).await {
+        failures.push(err);
+    }
+
     for shell in shells {
         match shell.self_test().await {
-            Ok(()) => (),
+            // Once `nix` is found at all, check it is Nix's
+            Ok(()) => {
+                if let Err(err) = check_shadowed_commands(shell).await {
+                    failures.push(err);
+                }
+            },
             Err(err) => failures.push(err),
         }
     }
@@ -165,3 +362,336 @@ pub async fn self_test() -> Result<(), Vec<SelfTestError>> {
         Err(failures)
     }
 }
+
+/// Check `shell` finds Nix's `nix` first on `PATH`, and report which other commands of Nix's profiles are shadowed (like by Homebrew's)
+#[tracing::instrument(skip_all)]
+pub async fn check_shadowed_commands(shell: Shell) -> Result<(), SelfTestError> {
+    let path = shell.path().await?;
+    let listed = path
+        .into_iter()
+        .map(|dir| {
+            let commands = std::fs::read_dir(&dir)
+                .into_iter()
+                .flatten()
+                .flatten()
+                .map(|entry| entry.file_name().to_string_lossy().into_owned())
+                .collect();
+            (dir, commands)
+        })
+        .collect::<Vec<_>>();
+
+    let shadowed = shadowed_commands(&listed);
+    if let Some((_, by)) = shadowed.iter().find(|(command, _)| command == "nix") {
+        return Err(SelfTestError::NixShadowed {
+            shell,
+            by: by.clone(),
+        });
+    }
+    if !shadowed.is_empty() {
+        tracing::warn!(
+            "In `{shell}`, these commands of Nix's profiles are shadowed by others earlier on `PATH` (see `--homebrew-path-order`): {}",
+            shadowed
+                .iter()
+                .map(|(command, by)| format!("`{command}` (by `{}`)", by.display()))
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+    }
+    Ok(())
+}
+
+/// A directory of a Nix profile, like `/nix/var/nix/profiles/default/bin` or `~/.nix-profile/bin`
+fn is_nix_dir(dir: &Path) -> bool {
+    dir.starts_with("/nix") || dir.ends_with(".nix-profile/bin")
+}
+
+/// The commands in Nix's directories of `path` which a directory before them (not Nix's) also has, with that directory
+fn shadowed_commands(path: &[(PathBuf, Vec<String>)]) -> Vec<(String, PathBuf)> {
+    let mut found: HashMap<&str, &Path> = HashMap::new();
+    let mut shadowed = vec![];
+    for (dir, commands) in path {
+        for command in commands {
+            match found.get(command.as_str()) {
+                Some(first) if is_nix_dir(dir) && !is_nix_dir(first) => {
+                    shadowed.push((command.clone(), first.to_path_buf()))
+                },
+                Some(_) => (),
+                None => {
+                    found.insert(command, dir);
+                },
+            }
+        }
+    }
+    shadowed
+}
+
+/// Run `command`, returning its output whether or not it succeeded
+async fn output(command: &mut Command) -> Result<Output, SelfTestError> {
+    let command_str = format!("{:?}", command.as_std());
+    tracing::debug!(command = command_str, "Running self-test command");
+    command
+        .stdin(std::process::Stdio::null())
+        .output()
+        .await
+        .map_err(|error| SelfTestError::Execute {
+            command: command_str,
+            error,
+        })
+}
+
+/// Check the Nix daemon answers `nix store ping`
+#[tracing::instrument(skip_all)]
+pub async fn check_store_ping() -> Result<(), SelfTestError> {
+    let output = output(Command::new(NIX_BIN).args(["store", "ping", "--store", "daemon"])).await?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(SelfTestError::StorePing(output))
+    }
+}
+
+/// Check a trivial derivation builds in the sandbox (where the platform sandboxes by default)
+#[tracing::instrument(skip_all)]
+pub async fn check_sandboxed_build() -> Result<(), SelfTestError> {
+    let timestamp_millis = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)?
+        .as_millis();
+    // macOS doesn't sandbox builds unless configured to
+    let sandbox = if cfg!(target_os = "macos") {
+        "false"
+    } else {
+        "true"
+    };
+    let output = output(Command::new(NIX_BIN).args([
+        "build",
+        "--option",
+        "sandbox",
+        sandbox,
+        "--option",
+        "substitute",
+        "false",
+        "--no-link",
+        "--expr",
+        &format!(
+            r#"derivation {{ name = "self-test-sandbox-{timestamp_millis}"; system = "{SYSTEM}"; builder = "/bin/sh"; args = ["-c" "echo hello > $out"]; }}"#
+        ),
+    ]))
+    .await?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(SelfTestError::SandboxedBuild(output))
+    }
+}
+
+/// Check a trivial flake (without inputs, so nothing is fetched) evaluates
+#[tracing::instrument(skip_all)]
+pub async fn check_flake_eval() -> Result<(), SelfTestError> {
+    // Removed again when dropped, whichever way this returns
+    let flake_dir = tempfile::Builder::new()
+        .prefix("nix-installer-self-test-")
+        .tempdir()
+        .map_err(|e| SelfTestError::WriteFlake(std::env::temp_dir(), e))?;
+    let flake = flake_dir.path().join("flake.nix");
+    tokio::fs::write(&flake, r#"{ outputs = _: { selfTest = "hello"; }; }"#)
+        .await
+        .map_err(|e| SelfTestError::WriteFlake(flake.clone(), e))?;
+
+    let res = output(Command::new(NIX_BIN).args([
+        "eval",
+        "--raw",
+        &format!("path:{}#selfTest", flake_dir.path().display()),
+    ]))
+    .await;
+    if let Err(err) = flake_dir.close() {
+        tracing::debug!(%err, "Could not remove the self test's flake");
+    }
+    let output = res?;
+    if output.status.success() && output.stdout == b"hello" {
+        Ok(())
+    } else {
+        Err(SelfTestError::FlakeEval(output))
+    }
+}
+
+/// Check each configured HTTP(S) substituter answers for its `nix-cache-info`
+#[tracing::instrument(skip_all)]
+pub async fn check_substituters() -> Vec<SelfTestError> {
+    let output = match output(Command::new(NIX_BIN).args(["config", "show", "substituters"])).await
+    {
+        Ok(output) if output.status.success() => output,
+        Ok(output) => return vec![SelfTestError::Substituters(output)],
+        Err(err) => return vec![err],
+    };
+    let client = match reqwest::Client::builder().timeout(TIMEOUT).build() {
+        Ok(client) => client,
+        Err(error) => {
+            return vec![SelfTestError::SubstituterUnreachable {
+                url: "any substituter".into(),
+                error,
+            }]
+        },
+    };
+
+    let mut failures = vec![];
+    for url in substituters(&String::from_utf8_lossy(&output.stdout)) {
+        let cache_info = format!("{}/nix-cache-info", url.trim_end_matches('/'));
+        tracing::debug!("Checking substituter `{url}`");
+        if let Err(error) = client
+            .get(&cache_info)
+            .send()
+            .await
+            .and_then(|res| res.error_for_status())
+        {
+            failures.push(SelfTestError::SubstituterUnreachable {
+                url: url.to_string(),
+                error,
+            });
+        }
+    }
+    failures
+}
+
+/// The HTTP(S) substituters in the output of `nix config show substituters`, others (like `file://`) are local
+fn substituters(config: &str) -> Vec<&str> {
+    config
+        .split_whitespace()
+        .filter(|url| url.starts_with("https://") || url.starts_with("http://"))
+        .collect()
+}
+
+/// Kill the Nix daemon, and check its service brings it back
+///
+/// Builds running in the daemon fail, so this is only run when asked for.
+#[tracing::instrument(skip_all)]
+pub async fn check_daemon_restart(
+    init: InitSystem,
+    distribution: Distribution,
+) -> Result<(), SelfTestError> {
+    // Started first (if socket activated), so there is a daemon to kill
+    check_store_ping().await?;
+
+    let mut command = match init {
+        InitSystem::Systemd => {
+            let mut command = Command::new("systemctl");
+            command.args(["kill", "--signal=SIGKILL", "nix-daemon.service"]);
+            command
+        },
+        InitSystem::Launchd => {
+            let service = if distribution == Distribution::Determinate {
+                "system/systems.determinate.nix-daemon"
+            } else {
+                "system/org.nixos.nix-daemon"
+            };
+            let mut command = Command::new("launchctl");
+            command.args(["kill", "SIGKILL", service]);
+            command
+        },
+        InitSystem::None => {
+            tracing::debug!("No init system runs the Nix daemon, not checking it restarts");
+            return Ok(());
+        },
+    };
+    let output = output(&mut command).await?;
+    if !output.status.success() {
+        return Err(SelfTestError::KillDaemon(output));
+    }
+
+    let deadline = tokio::time::Instant::now() + TIMEOUT;
+    while tokio::time::Instant::now() < deadline {
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        if check_store_ping().await.is_ok() {
+            return Ok(());
+        }
+    }
+    Err(SelfTestError::DaemonNotRestarted)
+}
+
+/// The checks of `nix-installer self-test` beyond the shells: the daemon, a sandboxed build, flakes, and the substituters
+///
+/// With `daemon_restart`, the daemon is also killed to check it is brought back.
+#[tracing::instrument(skip_all)]
+pub async fn self_test_subsystems(
+    distribution: Distribution,
+    daemon_restart: Option<InitSystem>,
+) -> Result<(), Vec<SelfTestError>> {
+    let mut failures = vec![];
+
+    // Without a daemon, the rest would only fail the same way
+    if let Err(err) = check_store_ping().await {
+        return Err(vec![err]);
+    }
+    if let Err(err) = check_sandboxed_build().await {
+        failures.push(err);
+    }
+    if let Err(err) = check_flake_eval().await {
+        failures.push(err);
+    }
+    failures.extend(check_substituters().await);
+    if let Some(init) = daemon_restart {
+        if let Err(err) = check_daemon_restart(init, distribution).await {
+            failures.push(err);
+        }
+    }
+
+    if failures.is_empty() {
+        Ok(())
+    } else {
+        Err(failures)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::PathBuf;
+
+    use super::{set_self_test_shells, shadowed_commands, substituters, Shell};
+
+    #[test]
+    fn selects_shells() {
+        set_self_test_shells(
+            Some(vec![Shell::Bash, Shell::Zsh, Shell::Fish]),
+            vec![Shell::Fish],
+        );
+        assert_eq!(Shell::selected(), vec![Shell::Bash, Shell::Zsh]);
+        set_self_test_shells(None, vec![Shell::Fish]);
+        assert_eq!(Shell::selected(), vec![Shell::Sh, Shell::Bash, Shell::Zsh]);
+        set_self_test_shells(None, vec![]);
+        assert_eq!(Shell::selected(), Shell::all());
+    }
+
+    #[test]
+    fn lists_http_substituters() {
+        assert_eq!(
+            substituters("https://cache.nixos.org/ file:///var/cache/nix http://10.0.0.2:5000\n"),
+            vec!["https://cache.nixos.org/", "http://10.0.0.2:5000"]
+        );
+        assert!(substituters("\n").is_empty());
+    }
+
+    #[test]
+    fn finds_shadowed_commands() {
+        let path = [
+            (
+                PathBuf::from("/opt/homebrew/bin"),
+                vec!["git".to_string(), "nix".to_string()],
+            ),
+            (
+                PathBuf::from("/Users/ana/.nix-profile/bin"),
+                vec!["git".to_string(), "jq".to_string()],
+            ),
+            (
+                PathBuf::from("/nix/var/nix/profiles/default/bin"),
+                vec!["jq".to_string(), "nix".to_string()],
+            ),
+            (PathBuf::from("/usr/bin"), vec!["git".to_string()]),
+        ];
+        assert_eq!(
+            shadowed_commands(&path),
+            vec![
+                ("git".to_string(), PathBuf::from("/opt/homebrew/bin")),
+                ("nix".to_string(), PathBuf::from("/opt/homebrew/bin")),
+            ]
+        );
+    }
+}
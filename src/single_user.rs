@@ -0,0 +1,252 @@
+/*! Converting a single-user Nix install into a multi-user one, see `nix-installer migrate-single-user`
+
+A single-user install (like one made with the upstream install script's `--no-daemon`) has `/nix`
+owned by the user who installed it, Nix only in that user's `~/.nix-profile`, no build users and no
+daemon, and a line in the user's shell profile (like `~/.profile`) which sources
+`~/.nix-profile/etc/profile.d/nix.sh`.
+
+[`SingleUserInstall::detect`] finds such an install, [`SingleUserInstall::reown`] gives `/nix` to
+root (except the user's own profiles and GC roots), and [`SingleUserInstall::setup_default_profile`]
+installs the user's Nix into the default profile, where the daemon runs it from. The build users,
+`nix.conf`, shell profiles, and daemon are then set up by the actions of an
+[adopted](crate::adopt) install, and [`SingleUserInstall::unhook_shell_profiles`] removes the line
+which would load the single-user Nix.
+*/
+
+use std::{
+    os::unix::fs::{MetadataExt, PermissionsExt},
+    path::{Path, PathBuf},
+};
+
+use nix::unistd::{Group, Uid, User};
+use tokio::process::Command;
+
+use crate::{audit, execute_command, manifest::DEFAULT_PROFILE};
+
+const NIX_STORE: &str = "/nix/store";
+/// What the upstream install script adds to the line it appends to the user's shell profile
+const PROFILE_LINE_MARKER: &str = "# added by Nix installer";
+/// The shell profiles in the user's home the upstream install script may have appended to
+const PROFILES: &[&str] = &[".profile", ".bash_profile", ".bash_login", ".zshenv"];
+
+/// A single-user install of Nix, see [`SingleUserInstall::detect`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SingleUserInstall {
+    /// The user who owns `/nix`
+    pub user: String,
+    pub uid: u32,
+    pub home: PathBuf,
+    /// The store path of the Nix in the user's profile
+    pub nix: PathBuf,
+    /// The shell profiles in the user's home which load the single-user Nix
+    pub shell_profiles: Vec<PathBuf>,
+}
+
+impl SingleUserInstall {
+    /// The single-user install on this system, if `/nix/store` is owned by a user other than root
+    #[tracing::instrument(level = "debug")]
+    pub async fn detect() -> Result<Option<Self>, SingleUserError> {
+        let metadata = match tokio::fs::metadata(NIX_STORE).await {
+            Ok(metadata) => metadata,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(SingleUserError::Read(NIX_STORE.into(), e)),
+        };
+        if metadata.uid() == 0 {
+            tracing::debug!("`{NIX_STORE}` is owned by root, Nix is not a single-user install");
+            return Ok(None);
+        }
+        let uid = metadata.uid();
+        let user = User::from_uid(Uid::from_raw(uid))
+            .map_err(|e| SingleUserError::GettingUser(uid, e))?
+            .ok_or(SingleUserError::NoUser(uid))?;
+
+        let nix_profile = user.dir.join(".nix-profile/bin/nix");
+        let nix = tokio::fs::canonicalize(&nix_profile)
+            .await
+            .ok()
+            .and_then(|nix| store_path(&nix))
+            .ok_or(SingleUserError::NoNix(nix_profile))?;
+
+        let mut shell_profiles = vec![];
+        for profile in PROFILES {
+            let path = user.dir.join(profile);
+            if let Ok(contents) = tokio::fs::read_to_string(&path).await {
+                if contents.contains(PROFILE_LINE_MARKER) {
+                    shell_profiles.push(path);
+                }
+            }
+        }
+
+        Ok(Some(Self {
+            user: user.name,
+            uid,
+            home: user.dir,
+            nix,
+            shell_profiles,
+        }))
+    }
+
+    /// What the conversion will do outside of the adopted install's actions, for the user to review
+    pub fn describe(&self, nix_build_group_name: &str) -> Vec<String> {
+        let mut steps = vec![
+            format!(
+                "Change the owner of `/nix` from `{}` to root, except `{}`'s profiles and GC roots",
+                self.user, self.user
+            ),
+            format!("Set up the default profile with `{}`", self.nix.display()),
+            format!("Give `{NIX_STORE}` to the `{nix_build_group_name}` group"),
+        ];
+        for profile in &self.shell_profiles {
+            steps.push(format!(
+                "Remove the line loading the single-user Nix from `{}`",
+                profile.display()
+            ));
+        }
+        steps
+    }
+
+    /// Give `/nix` to root, except the user's own profiles and GC roots
+    #[tracing::instrument(level = "debug", skip_all, fields(user = %self.user))]
+    pub async fn reown(&self) -> Result<(), SingleUserError> {
+        chown_recursive("0:0", Path::new("/nix")).await?;
+        for per_user in ["profiles", "gcroots"] {
+            let path = PathBuf::from(format!("/nix/var/nix/{per_user}/per-user/{}", self.user));
+            if path.exists() {
+                chown_recursive(&self.uid.to_string(), &path).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Install the user's Nix into the default profile, if it isn't there yet
+    #[tracing::instrument(level = "debug", skip_all, fields(nix = %self.nix.display()))]
+    pub async fn setup_default_profile(&self) -> Result<(), SingleUserError> {
+        if Path::new(DEFAULT_PROFILE).join("bin/nix").exists() {
+            tracing::debug!("The default profile already has Nix");
+            return Ok(());
+        }
+        let mut command = Command::new(self.nix.join("bin/nix-env"));
+        command
+            .arg("--profile")
+            .arg(DEFAULT_PROFILE)
+            .arg("--install")
+            .arg(&self.nix)
+            .env_remove("NIX_REMOTE")
+            .stdin(std::process::Stdio::null());
+        execute_command(&mut command)
+            .await
+            .map_err(|e| SingleUserError::Command(Box::new(e)))?;
+        Ok(())
+    }
+
+    /// Let the build users of `group` create paths in the store, as in a multi-user install
+    #[tracing::instrument(level = "debug")]
+    pub async fn share_store(&self, group: &str) -> Result<(), SingleUserError> {
+        let gid = Group::from_name(group)
+            .map_err(|e| SingleUserError::GettingGroup(group.to_string(), e))?
+            .ok_or_else(|| SingleUserError::NoGroup(group.to_string()))?
+            .gid;
+        nix::unistd::chown(NIX_STORE, Some(Uid::from_raw(0)), Some(gid))
+            .map_err(|e| SingleUserError::Chown(NIX_STORE.into(), e))?;
+        tokio::fs::set_permissions(NIX_STORE, PermissionsExt::from_mode(0o1775))
+            .await
+            .map_err(|e| SingleUserError::Write(NIX_STORE.into(), e))?;
+        Ok(())
+    }
+
+    /// Remove the line loading the single-user Nix from the user's shell profiles, the
+    /// system-wide ones load Nix now
+    #[tracing::instrument(level = "debug", skip_all, fields(user = %self.user))]
+    pub async fn unhook_shell_profiles(&self) -> Result<(), SingleUserError> {
+        for path in &self.shell_profiles {
+            let contents = tokio::fs::read_to_string(path)
+                .await
+                .map_err(|e| SingleUserError::Read(path.clone(), e))?;
+            let unhooked = remove_profile_line(&contents);
+            tokio::fs::write(path, &unhooked)
+                .await
+                .map_err(|e| SingleUserError::Write(path.clone(), e))?;
+            audit::file_written(path, unhooked.as_bytes());
+        }
+        Ok(())
+    }
+}
+
+/// The store path `path` is in, like `/nix/store/<hash>-nix-2.24.10`
+fn store_path(path: &Path) -> Option<PathBuf> {
+    let relative = path.strip_prefix(NIX_STORE).ok()?;
+    let name = relative.components().next()?;
+    Some(Path::new(NIX_STORE).join(name))
+}
+
+fn remove_profile_line(contents: &str) -> String {
+    contents
+        .split_inclusive('\n')
+        .filter(|line| !line.contains(PROFILE_LINE_MARKER))
+        .collect()
+}
+
+async fn chown_recursive(owner: &str, path: &Path) -> Result<(), SingleUserError> {
+    let mut command = Command::new("chown");
+    command
+        .arg("-R")
+        .arg(owner)
+        .arg(path)
+        .stdin(std::process::Stdio::null());
+    execute_command(&mut command)
+        .await
+        .map_err(|e| SingleUserError::Command(Box::new(e)))?;
+    Ok(())
+}
+
+#[non_exhaustive]
+#[derive(Debug, thiserror::Error)]
+pub enum SingleUserError {
+    #[error("Nix is not a single-user install (`{NIX_STORE}` is missing, or owned by root)")]
+    NotFound,
+    #[error("`{NIX_STORE}` is owned by UID {0}, which is not a user")]
+    NoUser(u32),
+    #[error(
+        "`{0}` is not in the Nix store, the single-user Nix to run the daemon with wasn't found"
+    )]
+    NoNix(PathBuf),
+    #[error("The group `{0}` does not exist")]
+    NoGroup(String),
+    #[error("Getting the user with UID {0}")]
+    GettingUser(u32, #[source] nix::errno::Errno),
+    #[error("Getting the group `{0}`")]
+    GettingGroup(String, #[source] nix::errno::Errno),
+    #[error("Changing the owner of `{0}`")]
+    Chown(PathBuf, #[source] nix::errno::Errno),
+    #[error("Reading `{0}`")]
+    Read(PathBuf, #[source] std::io::Error),
+    #[error("Writing `{0}`")]
+    Write(PathBuf, #[source] std::io::Error),
+    #[error(transparent)]
+    Command(Box<crate::action::ActionErrorKind>),
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::{Path, PathBuf};
+
+    use super::{remove_profile_line, store_path};
+
+    #[test]
+    fn finds_the_store_path() {
+        assert_eq!(
+            store_path(Path::new("/nix/store/abc123-nix-2.24.10/bin/nix")),
+            Some(PathBuf::from("/nix/store/abc123-nix-2.24.10"))
+        );
+        assert_eq!(store_path(Path::new("/usr/bin/nix")), None);
+    }
+
+    #[test]
+    fn removes_the_single_user_line() {
+        let profile = "export EDITOR=vi\nif [ -e /home/ana/.nix-profile/etc/profile.d/nix.sh ]; then . /home/ana/.nix-profile/etc/profile.d/nix.sh; fi # added by Nix installer\nalias ll='ls -l'\n";
+        assert_eq!(
+            remove_profile_line(profile),
+            "export EDITOR=vi\nalias ll='ls -l'\n"
+        );
+    }
+}
@@ -0,0 +1,418 @@
+/*! Fetching and unpacking the Nix package as an unprivileged user
+
+Downloading the Nix package speaks TLS to a server, and unpacking it decompresses and parses an
+archive, both of which may be attacker influenced. Neither needs root, so once a helper is set with
+[`set_unprivileged_helper`] (the CLI sets itself, unless `--no-privilege-separation` is passed),
+[`FetchAndUnpackNix`](crate::action::base::FetchAndUnpackNix) run as root only hands the work to
+`<helper> fetch-unprivileged` run as [`UNPRIVILEGED_USER`]:
+
+1. Root creates a scratch directory beside the destination, which only [`UNPRIVILEGED_USER`] can
+   use, and copies the helper (and any local files it needs) into it.
+2. The helper is sent the action as JSON on stdin, fetches, verifies, and unpacks it into the scratch
+   directory, and answers with a [`HelperMessage`] per line on stdout.
+3. Once the helper exited, root takes ownership of what it unpacked, refusing anything a tarball of
+   Nix doesn't have (like device files, or hard links), and moves it to the destination.
+*/
+
+use std::{
+    os::unix::fs::{MetadataExt, PermissionsExt},
+    path::{Path, PathBuf},
+    process::Stdio,
+    sync::Mutex,
+};
+
+use nix::unistd::{Gid, Uid, User};
+use reqwest::Url;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    process::Command,
+};
+use walkdir::WalkDir;
+
+use crate::{
+    action::{base::FetchAndUnpackNix, ActionErrorKind},
+    progress::{self, ProgressEvent},
+};
+
+/// The user the helper is run as
+pub const UNPRIVILEGED_USER: &str = "nobody";
+/// The subcommand of the helper which serves a request, see [`serve`]
+pub const HELPER_SUBCOMMAND: &str = "fetch-unprivileged";
+/// The environment variables the helper is run with, others (like `NIX_INSTALLER_RECEIPT_KEY`) may point at what it can't read
+const HELPER_ENV: &[&str] = &[
+    "RUST_LOG",
+    "RUST_BACKTRACE",
+    "HTTP_PROXY",
+    "http_proxy",
+    "HTTPS_PROXY",
+    "https_proxy",
+    "ALL_PROXY",
+    "all_proxy",
+    "NO_PROXY",
+    "no_proxy",
+    "SSL_CERT_FILE",
+    "SSL_CERT_DIR",
+    "NIX_INSTALLER_VERBOSITY",
+    "NIX_INSTALLER_LOGGER",
+    "NIX_INSTALLER_LOG_DIRECTIVES",
+];
+
+static HELPER: Mutex<Option<PathBuf>> = Mutex::new(None);
+
+/// Fetch (and unpack) each Nix package after this with `helper`, if running as root, or in this process if `None`
+///
+/// `helper` must be a `nix-installer` (or a binary which runs [`serve`] as its [`HELPER_SUBCOMMAND`]).
+pub fn set_unprivileged_helper(helper: Option<PathBuf>) {
+    let mut current = HELPER
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    *current = helper;
+}
+
+/// The helper to fetch with, if one is set and this process has privileges to drop
+pub(crate) fn helper() -> Option<PathBuf> {
+    if !Uid::effective().is_root() {
+        return None;
+    }
+    HELPER
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .clone()
+}
+
+/// What the helper is sent on stdin
+#[derive(Debug, serde::Deserialize, serde::Serialize)]
+struct HelperRequest {
+    action: FetchAndUnpackNix,
+    /// The proxy of `action` with its password, which is left out when `action` is serialized
+    proxy: Option<Url>,
+}
+
+/// A line the helper writes to stdout
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+#[serde(tag = "message", rename_all = "snake_case")]
+pub enum HelperMessage {
+    /// Progress of the download or unpacking, reported again by the privileged process
+    Progress { event: ProgressEvent },
+    /// The package was unpacked, and had this SHA-256
+    Unpacked { sha256: String },
+    /// Fetching or unpacking failed
+    Failed { error: String },
+}
+
+/// Serve a `request` (a [`FetchAndUnpackNix`] as JSON) from a privileged `nix-installer`, writing [`HelperMessage`]s to stdout
+#[tracing::instrument(level = "debug", skip_all)]
+pub async fn serve(request: &str) -> Result<(), UnprivilegedError> {
+    let HelperRequest { mut action, proxy } =
+        serde_json::from_str(request).map_err(UnprivilegedError::Request)?;
+    action.proxy = proxy;
+
+    let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+    progress::set_progress_sender(tx);
+    let forward = tokio::spawn(async move {
+        while let Some(event) = rx.recv().await {
+            send(&HelperMessage::Progress { event });
+        }
+    });
+    let res = action.fetch_and_unpack().await;
+    progress::clear_progress_sender();
+    forward.await.ok();
+
+    match res {
+        Ok(sha256) => {
+            send(&HelperMessage::Unpacked { sha256 });
+            Ok(())
+        },
+        Err(err) => {
+            let mut error = err.to_string();
+            let mut source = std::error::Error::source(&err);
+            while let Some(err) = source {
+                error.push_str(&format!(": {err}"));
+                source = err.source();
+            }
+            send(&HelperMessage::Failed { error });
+            Err(UnprivilegedError::Failed)
+        },
+    }
+}
+
+fn send(message: &HelperMessage) {
+    // Serializing these can't fail, they hold only strings and numbers
+    if let Ok(line) = serde_json::to_string(message) {
+        println!("{line}");
+    }
+}
+
+/// The [`UNPRIVILEGED_USER`]
+pub(crate) fn user() -> Result<User, UnprivilegedError> {
+    User::from_name(UNPRIVILEGED_USER)
+        .map_err(|e| UnprivilegedError::GettingUser(UNPRIVILEGED_USER.into(), e))?
+        .ok_or_else(|| UnprivilegedError::NoUser(UNPRIVILEGED_USER.into()))
+}
+
+/// Create an empty directory beside `dest` which only `user` can use
+pub(crate) async fn scratch_dir(dest: &Path, user: &User) -> Result<PathBuf, UnprivilegedError> {
+    let mut scratch = dest.as_os_str().to_owned();
+    scratch.push(".unprivileged");
+    let scratch = PathBuf::from(scratch);
+
+    // Left behind by an interrupted fetch
+    if tokio::fs::symlink_metadata(&scratch).await.is_ok() {
+        tokio::fs::remove_dir_all(&scratch)
+            .await
+            .map_err(|e| UnprivilegedError::Remove(scratch.clone(), e))?;
+    }
+    if let Some(parent) = scratch.parent() {
+        tokio::fs::create_dir_all(parent)
+            .await
+            .map_err(|e| UnprivilegedError::Write(parent.into(), e))?;
+    }
+    tokio::fs::create_dir(&scratch)
+        .await
+        .map_err(|e| UnprivilegedError::Write(scratch.clone(), e))?;
+    tokio::fs::set_permissions(&scratch, std::fs::Permissions::from_mode(0o700))
+        .await
+        .map_err(|e| UnprivilegedError::Write(scratch.clone(), e))?;
+    chown(&scratch, user.uid, user.gid)?;
+    Ok(scratch)
+}
+
+/// Copy `path` into the `scratch` directory as `name`, owned by `user`
+pub(crate) async fn stage(
+    path: &Path,
+    scratch: &Path,
+    name: &str,
+    user: &User,
+) -> Result<PathBuf, UnprivilegedError> {
+    let staged = scratch.join(name);
+    tokio::fs::copy(path, &staged)
+        .await
+        .map_err(|e| UnprivilegedError::Copy(path.into(), staged.clone(), e))?;
+    chown(&staged, user.uid, user.gid)?;
+    Ok(staged)
+}
+
+/// Run `helper` as `user` to serve `request`, returning the SHA-256 of what it unpacked
+#[tracing::instrument(level = "debug", skip_all, fields(helper = %helper.display()))]
+pub(crate) async fn run(
+    helper: &Path,
+    request: &FetchAndUnpackNix,
+    scratch: &Path,
+    user: &User,
+) -> Result<String, UnprivilegedError> {
+    let request = serde_json::to_string(&HelperRequest {
+        action: request.clone(),
+        proxy: request
+            .proxy
+            .as_ref()
+            .map(crate::settings::proxy_with_password),
+    })
+    .map_err(UnprivilegedError::Request)?;
+
+    let mut command = Command::new(helper);
+    command
+        .arg(HELPER_SUBCOMMAND)
+        .env_clear()
+        .envs(
+            HELPER_ENV
+                .iter()
+                .filter_map(|key| std::env::var_os(key).map(|value| (key, value))),
+        )
+        .env("HOME", scratch)
+        .env("PATH", "/usr/bin:/bin")
+        .current_dir(scratch)
+        // Also drops the supplementary groups of root
+        .uid(user.uid.as_raw())
+        .gid(user.gid.as_raw())
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::inherit())
+        .kill_on_drop(true);
+    tracing::debug!("Fetching as `{}` with `{}`", user.name, helper.display());
+    let mut child = command
+        .spawn()
+        .map_err(|e| UnprivilegedError::Spawn(helper.into(), e))?;
+
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    stdin
+        .write_all(request.as_bytes())
+        .await
+        .map_err(|e| UnprivilegedError::Spawn(helper.into(), e))?;
+    drop(stdin);
+
+    let stdout = child.stdout.take().expect("stdout is piped");
+    let mut lines = BufReader::new(stdout).lines();
+    let mut unpacked = None;
+    let mut failed = None;
+    while let Some(line) = lines
+        .next_line()
+        .await
+        .map_err(|e| UnprivilegedError::Spawn(helper.into(), e))?
+    {
+        match serde_json::from_str(&line) {
+            Ok(HelperMessage::Progress { event }) => progress::report(event),
+            Ok(HelperMessage::Unpacked { sha256 }) => unpacked = Some(sha256),
+            Ok(HelperMessage::Failed { error }) => failed = Some(error),
+            Err(_) => tracing::debug!("Unexpected output from the helper: {line}"),
+        }
+    }
+
+    let status = child
+        .wait()
+        .await
+        .map_err(|e| UnprivilegedError::Spawn(helper.into(), e))?;
+    match (status.success(), unpacked, failed) {
+        (_, _, Some(error)) => Err(UnprivilegedError::Helper(error)),
+        (true, Some(sha256), None) => Ok(sha256),
+        _ => Err(UnprivilegedError::HelperExited(status)),
+    }
+}
+
+/// Make root the owner of everything the helper unpacked at `unpacked` (in `scratch`)
+///
+/// The helper may have been compromised by what it unpacked, so anything a tarball of Nix doesn't
+/// have is refused: device files, sockets, FIFOs, and hard links (which could point outside of it).
+/// Set-user-ID and set-group-ID bits are cleared.
+#[tracing::instrument(level = "debug", skip_all, fields(unpacked = %unpacked.display()))]
+pub(crate) async fn take_ownership(
+    scratch: &Path,
+    unpacked: &Path,
+) -> Result<(), UnprivilegedError> {
+    // First, so nothing else running as the user can change it while it is checked
+    chown(scratch, Uid::from_raw(0), Gid::from_raw(0))?;
+
+    let metadata = tokio::fs::symlink_metadata(unpacked)
+        .await
+        .map_err(|e| UnprivilegedError::Read(unpacked.into(), e))?;
+    if !metadata.is_dir() {
+        return Err(UnprivilegedError::Unexpected(unpacked.into()));
+    }
+
+    for entry in WalkDir::new(unpacked).follow_links(false) {
+        let entry = entry.map_err(|e| {
+            let path = e.path().unwrap_or(unpacked).to_path_buf();
+            UnprivilegedError::Read(path, e.into())
+        })?;
+        let path = entry.path();
+        let metadata = entry
+            .metadata()
+            .map_err(|e| UnprivilegedError::Read(path.into(), e.into()))?;
+        let file_type = metadata.file_type();
+        if !(file_type.is_dir() || file_type.is_file() || file_type.is_symlink())
+            || (file_type.is_file() && metadata.nlink() > 1)
+        {
+            return Err(UnprivilegedError::Unexpected(path.into()));
+        }
+
+        chown(path, Uid::from_raw(0), Gid::from_raw(0))?;
+        if !file_type.is_symlink() && metadata.mode() & 0o6000 != 0 {
+            let mode = metadata.mode() & 0o1777;
+            std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))
+                .map_err(|e| UnprivilegedError::Write(path.into(), e))?;
+        }
+    }
+    Ok(())
+}
+
+/// Move the contents of `unpacked` into `dest`
+pub(crate) async fn place(unpacked: &Path, dest: &Path) -> Result<(), UnprivilegedError> {
+    tokio::fs::create_dir_all(dest)
+        .await
+        .map_err(|e| UnprivilegedError::Write(dest.into(), e))?;
+    let mut entries = tokio::fs::read_dir(unpacked)
+        .await
+        .map_err(|e| UnprivilegedError::Read(unpacked.into(), e))?;
+    while let Some(entry) = entries
+        .next_entry()
+        .await
+        .map_err(|e| UnprivilegedError::Read(unpacked.into(), e))?
+    {
+        let to = dest.join(entry.file_name());
+        tokio::fs::rename(entry.path(), &to)
+            .await
+            .map_err(|e| UnprivilegedError::Move(entry.path(), to, e))?;
+    }
+    Ok(())
+}
+
+fn chown(path: &Path, uid: Uid, gid: Gid) -> Result<(), UnprivilegedError> {
+    std::os::unix::fs::lchown(path, Some(uid.as_raw()), Some(gid.as_raw()))
+        .map_err(|e| UnprivilegedError::Chown(path.into(), e))
+}
+
+#[non_exhaustive]
+#[derive(thiserror::Error, Debug)]
+pub enum UnprivilegedError {
+    #[error("There is no `{0}` user to fetch the Nix package as, pass `--no-privilege-separation` to fetch it as root")]
+    NoUser(String),
+    #[error("Getting the user `{0}`")]
+    GettingUser(String, #[source] nix::errno::Errno),
+    #[error("Serializing or deserializing the request to the unprivileged helper")]
+    Request(#[source] serde_json::Error),
+    #[error("Running the unprivileged helper `{0}`")]
+    Spawn(PathBuf, #[source] std::io::Error),
+    #[error("Fetching the Nix package as `{UNPRIVILEGED_USER}`: {0}")]
+    Helper(String),
+    #[error("The unprivileged helper exited with {0} without unpacking the Nix package")]
+    HelperExited(std::process::ExitStatus),
+    #[error("Fetching the Nix package failed")]
+    Failed,
+    #[error("`{0}` is not something a Nix package unpacks, refusing to install it")]
+    Unexpected(PathBuf),
+    #[error("Reading `{0}`")]
+    Read(PathBuf, #[source] std::io::Error),
+    #[error("Writing `{0}`")]
+    Write(PathBuf, #[source] std::io::Error),
+    #[error("Removing `{0}`")]
+    Remove(PathBuf, #[source] std::io::Error),
+    #[error("Copying `{0}` to `{1}`")]
+    Copy(PathBuf, PathBuf, #[source] std::io::Error),
+    #[error("Moving `{0}` to `{1}`")]
+    Move(PathBuf, PathBuf, #[source] std::io::Error),
+    #[error("Changing the owner of `{0}`")]
+    Chown(PathBuf, #[source] std::io::Error),
+}
+
+impl From<UnprivilegedError> for ActionErrorKind {
+    fn from(val: UnprivilegedError) -> Self {
+        ActionErrorKind::Custom(Box::new(val))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{place, HelperMessage};
+    use crate::progress::ProgressEvent;
+
+    #[test]
+    fn helper_messages_round_trip() -> eyre::Result<()> {
+        let message = HelperMessage::Progress {
+            event: ProgressEvent::Downloaded {
+                bytes: 1024,
+                total: Some(4096),
+            },
+        };
+        let line = serde_json::to_string(&message)?;
+        assert_eq!(
+            line,
+            r#"{"message":"progress","event":{"event":"downloaded","bytes":1024,"total":4096}}"#
+        );
+        assert_eq!(serde_json::from_str::<HelperMessage>(&line)?, message);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn places_into_an_existing_dest() -> eyre::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let unpacked = temp_dir.path().join("unpacked");
+        let dest = temp_dir.path().join("dest");
+        tokio::fs::create_dir_all(unpacked.join("nix-2.24.10-x86_64-linux/store")).await?;
+        tokio::fs::create_dir_all(&dest).await?;
+
+        place(&unpacked, &dest).await?;
+
+        assert!(dest.join("nix-2.24.10-x86_64-linux/store").is_dir());
+        assert!(!unpacked.join("nix-2.24.10-x86_64-linux").exists());
+        Ok(())
+    }
+}
@@ -0,0 +1,331 @@
+/*! Migrating Nix installed by the upstream install script (<https://nixos.org/nix/install>), see `nix-installer migrate`
+
+The upstream install script leaves its own layout behind: each shell profile it changed is backed
+up beside it (like `/etc/bashrc.backup-before-nix`) and has Nix appended to it, and it places the
+Nix daemon's services (possibly with a systemd drop-in, like for `NIX_SSL_CERT_FILE`) itself.
+[`UpstreamInstall::detect`] finds that layout, and [`UpstreamInstall::normalize`] undoes it: the
+shell profiles are restored from their backups, and the daemon is stopped and its services removed.
+Then `nix-installer` configures the shell profiles and services its own way, and
+[adopts](crate::adopt) the rest of the install.
+*/
+
+use std::path::{Path, PathBuf};
+
+use tokio::process::Command;
+
+use crate::{
+    audit::{self, AuditEvent},
+    execute_command,
+    planner::ShellProfileLocations,
+};
+
+const BACKUP_SUFFIX: &str = ".backup-before-nix";
+/// Where a drop-in moved aside by [`UpstreamInstall::normalize`] is kept
+const MOVED_SUFFIX: &str = ".backup-before-nix-installer";
+/// The services the upstream install script places, `nix-installer` places the same ones
+const SERVICES: &[&str] = &[
+    "/etc/systemd/system/nix-daemon.service",
+    "/etc/systemd/system/nix-daemon.socket",
+    "/etc/tmpfiles.d/nix-daemon.conf",
+    "/Library/LaunchDaemons/org.nixos.nix-daemon.plist",
+];
+const SYSTEMD_DROPIN_DIR: &str = "/etc/systemd/system/nix-daemon.service.d";
+const LAUNCHD_SERVICE: &str = "system/org.nixos.nix-daemon";
+
+/// What the upstream install script left on the system, see [`UpstreamInstall::detect`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UpstreamInstall {
+    pub profiles: Vec<UpstreamProfile>,
+    /// The daemon's services
+    pub services: Vec<PathBuf>,
+    /// A systemd drop-in for the daemon, which `nix-installer` wouldn't make
+    pub dropin: Option<PathBuf>,
+}
+
+/// A shell profile the upstream install script appended Nix to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UpstreamProfile {
+    pub path: PathBuf,
+    /// The profile as it was before, if it existed
+    pub backup: Option<PathBuf>,
+}
+
+impl UpstreamInstall {
+    /// The layout of the upstream install script, if it installed the Nix on this system
+    ///
+    /// It is told apart from other installs by the backups it leaves of the shell profiles.
+    #[tracing::instrument(level = "debug")]
+    pub async fn detect() -> Result<Option<Self>, UpstreamInstallError> {
+        let locations = ShellProfileLocations::default();
+        let mut profiles = vec![];
+        for path in locations.bash.iter().chain(locations.zsh.iter()) {
+            let backup = backup_path(path);
+            let backup = backup.exists().then_some(backup);
+            let has_nix = match tokio::fs::read_to_string(path).await {
+                Ok(contents) => remove_upstream_nix(&contents).is_some(),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => false,
+                Err(e) => return Err(UpstreamInstallError::Read(path.clone(), e)),
+            };
+            if has_nix || backup.is_some() {
+                profiles.push(UpstreamProfile {
+                    path: path.clone(),
+                    backup,
+                });
+            }
+        }
+        if profiles.iter().all(|profile| profile.backup.is_none()) {
+            tracing::debug!(
+                "No shell profile backups, Nix was not installed by the upstream install script"
+            );
+            return Ok(None);
+        }
+
+        let services = SERVICES
+            .iter()
+            .map(PathBuf::from)
+            .filter(|service| service.symlink_metadata().is_ok())
+            .collect();
+        let dropin = Some(PathBuf::from(SYSTEMD_DROPIN_DIR)).filter(|dropin| dropin.exists());
+        Ok(Some(Self {
+            profiles,
+            services,
+            dropin,
+        }))
+    }
+
+    /// What [`normalize`](Self::normalize) will do, for the user to review
+    pub fn describe_normalize(&self) -> Vec<String> {
+        let mut steps = vec![];
+        for UpstreamProfile { path, backup } in &self.profiles {
+            match backup {
+                Some(backup) => steps.push(format!(
+                    "Remove Nix from `{}`, and `{}` if it has nothing else",
+                    path.display(),
+                    backup.display()
+                )),
+                None => steps.push(format!("Remove Nix from `{}`", path.display())),
+            }
+        }
+        if !self.services.is_empty() {
+            steps.push(format!(
+                "Stop the Nix daemon, and remove {}",
+                self.services
+                    .iter()
+                    .map(|service| format!("`{}`", service.display()))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ));
+        }
+        if let Some(dropin) = &self.dropin {
+            steps.push(format!(
+                "Move `{}` aside to `{}{MOVED_SUFFIX}`",
+                dropin.display(),
+                dropin.display()
+            ));
+        }
+        steps
+    }
+
+    /// Undo what the upstream install script did to the shell profiles and services
+    #[tracing::instrument(level = "debug", skip_all)]
+    pub async fn normalize(&self) -> Result<(), UpstreamInstallError> {
+        for profile in &self.profiles {
+            restore_profile(profile).await?;
+        }
+
+        if !self.services.is_empty() {
+            stop_daemon().await;
+        }
+        for service in &self.services {
+            tokio::fs::remove_file(service)
+                .await
+                .map_err(|e| UpstreamInstallError::Remove(service.clone(), e))?;
+            audit::record(AuditEvent::FileRemoved {
+                path: service.clone(),
+            });
+        }
+
+        if let Some(dropin) = &self.dropin {
+            let mut moved = dropin.as_os_str().to_owned();
+            moved.push(MOVED_SUFFIX);
+            let moved = PathBuf::from(moved);
+            tokio::fs::rename(dropin, &moved)
+                .await
+                .map_err(|e| UpstreamInstallError::Move(dropin.clone(), moved.clone(), e))?;
+            audit::record(AuditEvent::Moved {
+                from: dropin.clone(),
+                to: moved.clone(),
+            });
+            tracing::warn!(
+                "Moved `{}` aside to `{}`, settings in it are no longer applied to the Nix daemon",
+                dropin.display(),
+                moved.display()
+            );
+        }
+        Ok(())
+    }
+}
+
+fn backup_path(path: &Path) -> PathBuf {
+    let mut backup = path.as_os_str().to_owned();
+    backup.push(BACKUP_SUFFIX);
+    PathBuf::from(backup)
+}
+
+/// Take Nix out of `profile`, removing its backup if they match after (the profile wasn't otherwise changed)
+async fn restore_profile(profile: &UpstreamProfile) -> Result<(), UpstreamInstallError> {
+    let UpstreamProfile { path, backup } = profile;
+    let contents = match tokio::fs::read_to_string(path).await {
+        Ok(contents) => Some(contents),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+        Err(e) => return Err(UpstreamInstallError::Read(path.clone(), e)),
+    };
+    let restored = contents
+        .as_deref()
+        .map(|contents| remove_upstream_nix(contents).unwrap_or_else(|| contents.to_string()));
+
+    match (&restored, backup) {
+        // The upstream install script created it just for Nix
+        (Some(restored), None) if restored.trim().is_empty() => {
+            tokio::fs::remove_file(path)
+                .await
+                .map_err(|e| UpstreamInstallError::Remove(path.clone(), e))?;
+            audit::record(AuditEvent::FileRemoved { path: path.clone() });
+        },
+        (Some(restored), _) if Some(restored) != contents.as_ref() => {
+            tokio::fs::write(path, restored)
+                .await
+                .map_err(|e| UpstreamInstallError::Write(path.clone(), e))?;
+            audit::file_written(path, restored.as_bytes());
+        },
+        _ => (),
+    }
+
+    let Some(backup) = backup else {
+        return Ok(());
+    };
+    let backed_up = tokio::fs::read_to_string(backup)
+        .await
+        .map_err(|e| UpstreamInstallError::Read(backup.clone(), e))?;
+    if restored.is_none() || restored.as_deref() == Some(backed_up.as_str()) {
+        tokio::fs::remove_file(backup)
+            .await
+            .map_err(|e| UpstreamInstallError::Remove(backup.clone(), e))?;
+        audit::record(AuditEvent::FileRemoved {
+            path: backup.clone(),
+        });
+    } else {
+        tracing::warn!(
+            "`{}` was changed since `{}` was made, both are kept",
+            path.display(),
+            backup.display()
+        );
+    }
+    Ok(())
+}
+
+/// `contents` without the Nix the upstream install script appended, if it has it
+///
+/// The script appends a blank line, `# Nix`, what sources Nix, `# End Nix`, and another blank line.
+fn remove_upstream_nix(contents: &str) -> Option<String> {
+    let start = if contents.starts_with("# Nix\n") {
+        0
+    } else {
+        contents.find("\n# Nix\n")? + 1
+    };
+    let end = start + contents[start..].find("\n# End Nix\n")? + "\n# End Nix\n".len();
+    let before = &contents[..start];
+    let before = before
+        .strip_suffix('\n')
+        .filter(|before| before.is_empty() || before.ends_with('\n'))
+        .unwrap_or(before);
+    let after = &contents[end..];
+    let after = after.strip_prefix('\n').unwrap_or(after);
+    Some(format!("{before}{after}"))
+}
+
+/// Stop the daemon the upstream install script started, failing to is only logged as the services are removed next
+async fn stop_daemon() {
+    let mut command = if Path::new("/Library/LaunchDaemons").exists() {
+        let mut command = Command::new("launchctl");
+        command.args(["bootout", LAUNCHD_SERVICE]);
+        command
+    } else {
+        let mut command = Command::new("systemctl");
+        command.args([
+            "disable",
+            "--now",
+            "nix-daemon.socket",
+            "nix-daemon.service",
+        ]);
+        command
+    };
+    command.stdin(std::process::Stdio::null());
+    if let Err(err) = execute_command(&mut command).await {
+        tracing::debug!(%err, "Could not stop the Nix daemon");
+    }
+}
+
+#[non_exhaustive]
+#[derive(Debug, thiserror::Error)]
+pub enum UpstreamInstallError {
+    #[error("Nix was not installed by the upstream install script (there are no `/etc/*.backup-before-nix` shell profile backups) or a distribution's package, use `nix-installer adopt` instead")]
+    NotFound,
+    #[error("Reading `{0}`")]
+    Read(PathBuf, #[source] std::io::Error),
+    #[error("Writing `{0}`")]
+    Write(PathBuf, #[source] std::io::Error),
+    #[error("Removing `{0}`")]
+    Remove(PathBuf, #[source] std::io::Error),
+    #[error("Moving `{0}` to `{1}`")]
+    Move(PathBuf, PathBuf, #[source] std::io::Error),
+}
+
+#[cfg(test)]
+mod test {
+    use super::{remove_upstream_nix, restore_profile, UpstreamProfile};
+
+    const UPSTREAM_NIX: &str = "\n# Nix\nif [ -e '/nix/var/nix/profiles/default/etc/profile.d/nix-daemon.sh' ]; then\n  . '/nix/var/nix/profiles/default/etc/profile.d/nix-daemon.sh'\nfi\n# End Nix\n\n";
+
+    #[test]
+    fn removes_what_the_upstream_script_appended() {
+        let original = "# System-wide .bashrc\nalias ll='ls -l'\n";
+        assert_eq!(
+            remove_upstream_nix(&format!("{original}{UPSTREAM_NIX}")).as_deref(),
+            Some(original)
+        );
+        assert_eq!(
+            remove_upstream_nix(&format!("{original}{UPSTREAM_NIX}export EDITOR=vi\n")).as_deref(),
+            Some("# System-wide .bashrc\nalias ll='ls -l'\nexport EDITOR=vi\n")
+        );
+        assert_eq!(remove_upstream_nix(UPSTREAM_NIX).as_deref(), Some(""));
+        assert_eq!(remove_upstream_nix(original), None);
+    }
+
+    #[tokio::test]
+    async fn restores_profiles_from_their_backups() -> eyre::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let unchanged = temp_dir.path().join("bashrc");
+        let unchanged_backup = temp_dir.path().join("bashrc.backup-before-nix");
+        std::fs::write(&unchanged, format!("alias ll='ls -l'\n{UPSTREAM_NIX}"))?;
+        std::fs::write(&unchanged_backup, "alias ll='ls -l'\n")?;
+        let changed = temp_dir.path().join("zshrc");
+        let changed_backup = temp_dir.path().join("zshrc.backup-before-nix");
+        std::fs::write(&changed, format!("{UPSTREAM_NIX}export EDITOR=vi\n"))?;
+        std::fs::write(&changed_backup, "")?;
+
+        for (path, backup) in [(&unchanged, &unchanged_backup), (&changed, &changed_backup)] {
+            restore_profile(&UpstreamProfile {
+                path: path.clone(),
+                backup: Some(backup.clone()),
+            })
+            .await?;
+        }
+
+        assert_eq!(std::fs::read_to_string(&unchanged)?, "alias ll='ls -l'\n");
+        assert!(!unchanged_backup.exists());
+        assert_eq!(std::fs::read_to_string(&changed)?, "export EDITOR=vi\n");
+        assert!(changed_backup.exists());
+        Ok(())
+    }
+}
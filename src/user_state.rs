@@ -0,0 +1,358 @@
+/*! Keeping users' Nix profiles across a reinstall, see `nix-installer uninstall --keep-user-profiles`
+
+The profiles of each user (and their channels, which are profiles too) are links to store paths in
+`/nix/var/nix/profiles/per-user` (or, with newer Nix, `~/.local/state/nix/profiles`), so they are
+lost with the store. [`save`] copies the links in `/nix/var/nix/{profiles,gcroots}/per-user` to
+[`USER_STATE_LOCATION`] before the uninstall. The profiles' own store paths (which were built
+locally, so can't be fetched) are exported there too, and the store paths they refer to (like the
+packages installed in them) are listed. After the next install, [`restore`] fetches the listed
+store paths again from the substituters, imports the exported ones, and puts the links back.
+
+Uninstalling never touches the users' homes (like `~/.config/nix` or `~/.nix-channels`) unless
+`--purge` is passed.
+*/
+
+use std::{
+    collections::BTreeSet,
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+};
+
+use tokio::process::Command;
+
+use crate::{
+    audit::{self, AuditEvent},
+    execute_command,
+    manifest::NIX_BIN,
+    purge::homes,
+};
+
+/// Where the users' profiles are kept while Nix is uninstalled
+pub const USER_STATE_LOCATION: &str = "/var/lib/nix-installer/user-state";
+/// The directories of links which belong to users
+const PER_USER: &[&str] = &[
+    "/nix/var/nix/profiles/per-user",
+    "/nix/var/nix/gcroots/per-user",
+];
+/// Where profiles are in users' homes, with newer Nix
+const HOME_PROFILES: &str = ".local/state/nix/profiles";
+/// The store paths the saved profiles refer to, one per line
+const STORE_PATHS: &str = "store-paths";
+/// The store paths of the saved profiles, in the format of `nix-store --export`
+const EXPORT: &str = "profiles.export";
+const NIX_STORE: &str = "/nix/store";
+
+/// What was saved, or restored
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct UserState {
+    /// How many links were copied
+    pub links: usize,
+    /// The store paths of the profiles
+    pub profiles: Vec<PathBuf>,
+    /// The store paths the profiles refer to
+    pub store_paths: Vec<PathBuf>,
+}
+
+/// Copy the users' profiles and GC roots to [`USER_STATE_LOCATION`], replacing any saved before
+#[tracing::instrument(level = "debug")]
+pub async fn save() -> Result<UserState, UserStateError> {
+    let location = Path::new(USER_STATE_LOCATION);
+    if location.exists() {
+        tokio::fs::remove_dir_all(location)
+            .await
+            .map_err(|e| UserStateError::Remove(location.to_path_buf(), e))?;
+    }
+
+    let mut links = 0;
+    let mut profiles = BTreeSet::new();
+    for per_user in PER_USER {
+        let per_user = Path::new(per_user);
+        if !per_user.exists() {
+            continue;
+        }
+        let copied = copy_links(per_user, &saved_path(per_user)).await?;
+        links += copied.len();
+        if per_user.starts_with("/nix/var/nix/profiles") {
+            profiles.extend(copied.iter().filter_map(|target| store_path(target)));
+        }
+    }
+    for home in homes().await {
+        let home_profiles = home.join(HOME_PROFILES);
+        profiles.extend(
+            profile_targets(&home_profiles)
+                .await?
+                .iter()
+                .filter_map(|target| store_path(target)),
+        );
+    }
+    let profiles = profiles.into_iter().collect::<Vec<_>>();
+    tokio::fs::create_dir_all(location)
+        .await
+        .map_err(|e| UserStateError::Write(location.to_path_buf(), e))?;
+
+    let mut store_paths = vec![];
+    if !profiles.is_empty() {
+        let requisites = nix_store(&["--query", "--requisites"], &profiles).await?;
+        store_paths = String::from_utf8_lossy(&requisites)
+            .lines()
+            .map(PathBuf::from)
+            .filter(|path| !profiles.contains(path))
+            .collect();
+
+        let export = nix_store(&["--export"], &profiles).await?;
+        let export_path = location.join(EXPORT);
+        tokio::fs::write(&export_path, &export)
+            .await
+            .map_err(|e| UserStateError::Write(export_path.clone(), e))?;
+        audit::file_written(&export_path, &export);
+    }
+
+    let list = location.join(STORE_PATHS);
+    let contents = store_paths
+        .iter()
+        .map(|path| format!("{}\n", path.display()))
+        .collect::<String>();
+    tokio::fs::write(&list, &contents)
+        .await
+        .map_err(|e| UserStateError::Write(list.clone(), e))?;
+    audit::file_written(&list, contents.as_bytes());
+
+    Ok(UserState {
+        links,
+        profiles,
+        store_paths,
+    })
+}
+
+/// Fetch and import the store paths of the [saved](save) profiles and put their links back, if any were saved
+///
+/// Store paths which can't be fetched (or imported) are logged, the links to them are put back anyway.
+#[tracing::instrument(level = "debug")]
+pub async fn restore() -> Result<Option<UserState>, UserStateError> {
+    let location = Path::new(USER_STATE_LOCATION);
+    if !location.exists() {
+        return Ok(None);
+    }
+
+    let list = location.join(STORE_PATHS);
+    let store_paths = match tokio::fs::read_to_string(&list).await {
+        Ok(contents) => contents.lines().map(PathBuf::from).collect::<Vec<_>>(),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => vec![],
+        Err(e) => return Err(UserStateError::Read(list, e)),
+    };
+    if !store_paths.is_empty() {
+        if let Err(err) = nix_store(&["--realise"], &store_paths).await {
+            tracing::warn!(
+                "Not all of the store paths of the users' profiles could be fetched again: {err}"
+            );
+        }
+    }
+    let export_path = location.join(EXPORT);
+    let mut profiles = vec![];
+    if export_path.exists() {
+        let export = std::fs::File::open(&export_path)
+            .map_err(|e| UserStateError::Read(export_path.clone(), e))?;
+        let mut command = Command::new(nix_store_bin());
+        command.arg("--import").stdin(export);
+        match execute_command(&mut command).await {
+            Ok(output) => {
+                profiles = String::from_utf8_lossy(&output.stdout)
+                    .lines()
+                    .map(PathBuf::from)
+                    .collect()
+            },
+            Err(err) => tracing::warn!("The users' profiles could not be imported: {err}"),
+        }
+    }
+
+    let mut links = 0;
+    for per_user in PER_USER {
+        let per_user = Path::new(per_user);
+        let saved = saved_path(per_user);
+        if saved.exists() {
+            links += copy_links(&saved, per_user).await?.len();
+        }
+    }
+
+    tokio::fs::remove_dir_all(location)
+        .await
+        .map_err(|e| UserStateError::Remove(location.to_path_buf(), e))?;
+    audit::record(AuditEvent::DirectoryRemoved {
+        path: location.to_path_buf(),
+    });
+
+    Ok(Some(UserState {
+        links,
+        profiles,
+        store_paths,
+    }))
+}
+
+fn nix_store_bin() -> PathBuf {
+    Path::new(NIX_BIN).with_file_name("nix-store")
+}
+
+/// Run `nix-store` with `args` on `paths`, returning what it printed
+async fn nix_store(args: &[&str], paths: &[PathBuf]) -> Result<Vec<u8>, UserStateError> {
+    let mut command = Command::new(nix_store_bin());
+    command
+        .args(args)
+        .args(paths)
+        .stdin(std::process::Stdio::null());
+    let output = execute_command(&mut command)
+        .await
+        .map_err(|e| UserStateError::Command(Box::new(e)))?;
+    Ok(output.stdout)
+}
+
+/// Where `per_user` is kept in [`USER_STATE_LOCATION`]
+fn saved_path(per_user: &Path) -> PathBuf {
+    Path::new(USER_STATE_LOCATION).join(per_user.strip_prefix("/").unwrap_or(per_user))
+}
+
+/// Copy the directories and links in `from` to `to` with their owners, returning the targets of the links
+async fn copy_links(from: &Path, to: &Path) -> Result<Vec<PathBuf>, UserStateError> {
+    let mut targets = vec![];
+    let mut directories = vec![(from.to_path_buf(), to.to_path_buf())];
+    while let Some((from, to)) = directories.pop() {
+        copy_owner_of(&from, &to, true).await?;
+        let mut entries = tokio::fs::read_dir(&from)
+            .await
+            .map_err(|e| UserStateError::Read(from.clone(), e))?;
+        while let Some(entry) = entries
+            .next_entry()
+            .await
+            .map_err(|e| UserStateError::Read(from.clone(), e))?
+        {
+            let entry_to = to.join(entry.file_name());
+            let kind = entry
+                .file_type()
+                .await
+                .map_err(|e| UserStateError::Read(entry.path(), e))?;
+            if kind.is_dir() {
+                directories.push((entry.path(), entry_to));
+            } else if kind.is_symlink() {
+                let target = tokio::fs::read_link(entry.path())
+                    .await
+                    .map_err(|e| UserStateError::Read(entry.path(), e))?;
+                if tokio::fs::symlink_metadata(&entry_to).await.is_ok() {
+                    tokio::fs::remove_file(&entry_to)
+                        .await
+                        .map_err(|e| UserStateError::Remove(entry_to.clone(), e))?;
+                }
+                tokio::fs::symlink(&target, &entry_to)
+                    .await
+                    .map_err(|e| UserStateError::Write(entry_to.clone(), e))?;
+                copy_owner_of(&entry.path(), &entry_to, false).await?;
+                audit::record(AuditEvent::SymlinkCreated {
+                    path: entry_to,
+                    target: target.clone(),
+                });
+                targets.push(target);
+            }
+        }
+    }
+    Ok(targets)
+}
+
+/// Give `to` the owner of `from`, creating it first if it is a `directory`
+async fn copy_owner_of(from: &Path, to: &Path, directory: bool) -> Result<(), UserStateError> {
+    let metadata = tokio::fs::symlink_metadata(from)
+        .await
+        .map_err(|e| UserStateError::Read(from.to_path_buf(), e))?;
+    if directory {
+        tokio::fs::create_dir_all(to)
+            .await
+            .map_err(|e| UserStateError::Write(to.to_path_buf(), e))?;
+    }
+    std::os::unix::fs::lchown(to, Some(metadata.uid()), Some(metadata.gid()))
+        .map_err(|e| UserStateError::Write(to.to_path_buf(), e))
+}
+
+/// The targets of the links directly in `profiles`
+async fn profile_targets(profiles: &Path) -> Result<Vec<PathBuf>, UserStateError> {
+    let mut targets = vec![];
+    let mut entries = match tokio::fs::read_dir(profiles).await {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(targets),
+        Err(e) => return Err(UserStateError::Read(profiles.to_path_buf(), e)),
+    };
+    while let Some(entry) = entries
+        .next_entry()
+        .await
+        .map_err(|e| UserStateError::Read(profiles.to_path_buf(), e))?
+    {
+        if let Ok(target) = tokio::fs::read_link(entry.path()).await {
+            targets.push(target);
+        }
+    }
+    Ok(targets)
+}
+
+/// The store path `target` is in, if it is an absolute path into the store
+fn store_path(target: &Path) -> Option<PathBuf> {
+    let name = target.strip_prefix(NIX_STORE).ok()?.components().next()?;
+    Some(Path::new(NIX_STORE).join(name))
+}
+
+#[non_exhaustive]
+#[derive(Debug, thiserror::Error)]
+pub enum UserStateError {
+    #[error("Reading `{0}`")]
+    Read(PathBuf, #[source] std::io::Error),
+    #[error("Writing `{0}`")]
+    Write(PathBuf, #[source] std::io::Error),
+    #[error("Removing `{0}`")]
+    Remove(PathBuf, #[source] std::io::Error),
+    #[error(transparent)]
+    Command(Box<crate::action::ActionErrorKind>),
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::{Path, PathBuf};
+
+    use super::{copy_links, store_path};
+
+    #[test]
+    fn only_absolute_links_into_the_store_have_store_paths() {
+        assert_eq!(
+            store_path(Path::new("/nix/store/abc123-user-environment")),
+            Some(PathBuf::from("/nix/store/abc123-user-environment"))
+        );
+        assert_eq!(
+            store_path(Path::new("/nix/store/abc123-hello/bin/hello")),
+            Some(PathBuf::from("/nix/store/abc123-hello"))
+        );
+        assert_eq!(store_path(Path::new("profile-3-link")), None);
+    }
+
+    #[tokio::test]
+    async fn copies_links() -> eyre::Result<()> {
+        let from = tempfile::tempdir()?;
+        let to = tempfile::tempdir()?;
+        let ana = from.path().join("ana");
+        tokio::fs::create_dir(&ana).await?;
+        tokio::fs::symlink(
+            "/nix/store/abc123-user-environment",
+            ana.join("profile-3-link"),
+        )
+        .await?;
+        tokio::fs::symlink("profile-3-link", ana.join("profile")).await?;
+
+        let mut targets = copy_links(from.path(), to.path()).await?;
+        targets.sort();
+        assert_eq!(
+            targets,
+            vec![
+                PathBuf::from("/nix/store/abc123-user-environment"),
+                PathBuf::from("profile-3-link"),
+            ]
+        );
+        assert_eq!(
+            tokio::fs::read_link(to.path().join("ana/profile")).await?,
+            PathBuf::from("profile-3-link")
+        );
+        Ok(())
+    }
+}
@@ -3,6 +3,8 @@ use nix_installer::InstallPlan;
 const LINUX: &str = include_str!("./fixtures/linux/linux.json");
 const STEAM_DECK: &str = include_str!("./fixtures/linux/steam-deck.json");
 const MACOS: &str = include_str!("./fixtures/macos/macos.json");
+// A receipt from before `create_directory` recorded `is_mountpoint` and `force_prune_on_revert`
+const LINUX_SCHEMA_0: &str = include_str!("./fixtures/linux/linux-schema-0.json");
 
 // Ensure existing plans still parse
 // If this breaks and you need to update the fixture, disable these tests, bump `nix_installer` to a new version, and update the plans.
@@ -27,3 +29,43 @@ fn plan_compat_macos() -> eyre::Result<()> {
     let _: InstallPlan = serde_json::from_str(MACOS)?;
     Ok(())
 }
+
+// Ensure receipts from before the schema was versioned are upgraded, and can be uninstalled
+#[test]
+fn receipt_migrations() -> eyre::Result<()> {
+    for receipt in [LINUX, STEAM_DECK, MACOS, LINUX_SCHEMA_0] {
+        let plan = InstallPlan::from_receipt(receipt)?;
+        plan.check_compatible()?;
+    }
+    assert!(serde_json::from_str::<InstallPlan>(LINUX_SCHEMA_0).is_err());
+    Ok(())
+}
+
+// Ensure receipts from newer versions, with actions this version doesn't know, can still be uninstalled
+#[test]
+fn receipt_with_unknown_actions() -> eyre::Result<()> {
+    let mut receipt: serde_json::Value = serde_json::from_str(LINUX)?;
+    receipt["schema_version"] = 999.into();
+    receipt["actions"]
+        .as_array_mut()
+        .ok_or_else(|| eyre::eyre!("Expected `actions` to be an array"))?
+        .push(serde_json::json!({
+            "action": {
+                "action_name": "provision_frobnicator",
+                "path": "/etc/frob.conf",
+            },
+            "state": "Completed",
+        }));
+    let receipt = receipt.to_string();
+
+    assert!(InstallPlan::from_receipt(&receipt).is_err());
+    let plan = InstallPlan::from_receipt_lossy(&receipt)?;
+    plan.check_compatible()?;
+    let manual_steps = plan.manual_steps();
+    assert_eq!(manual_steps.len(), 1);
+    assert_eq!(
+        manual_steps[0].explanation,
+        vec!["Undo `provision_frobnicator` at `/etc/frob.conf`".to_string()]
+    );
+    Ok(())
+}